@@ -21,9 +21,7 @@ use futures::stream::{StreamExt, TryStreamExt};
 use tokio::io::{stdin, stdout, AsyncWriteExt, Stdin};
 
 use file_store::utils::ReaderStream;
-use file_store::{
-    ConnectFuture, ObjectInfo, ObjectPath, StorageBackend, StorageError, TransferError,
-};
+use file_store::{ConnectFuture, ObjectInfo, ObjectPath, StorageError, TransferError};
 
 #[derive(Debug)]
 pub struct ErrorResult {
@@ -0,0 +1,32 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that B2's percent encoding of file names round trips.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use storage_types::b2::v2::{percent_decode, percent_encode};
+
+fuzz_target!(|data: &[u8]| {
+    let input = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+
+    let encoded = percent_encode(input);
+    assert!(encoded.is_ascii());
+    assert!(!encoded.contains(' ') && !encoded.contains('+'));
+    assert_eq!(percent_decode(&encoded).unwrap(), input);
+});
@@ -0,0 +1,72 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that parsing an `ObjectPath` and splitting it into parts is
+//! lossless.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use file_store::ObjectPath;
+
+fuzz_target!(|data: &[u8]| {
+    let input = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+
+    let path = match ObjectPath::new(input) {
+        Ok(p) => p,
+        Err(_) => {
+            assert!(input.starts_with('/'));
+            return;
+        }
+    };
+    assert!(!input.starts_with('/'));
+    assert_eq!(path.to_string(), input);
+    assert_eq!(path.is_empty(), input.is_empty());
+
+    let parts = path.parts();
+    assert_eq!(parts.join("/"), input);
+
+    // Rebuilding the path from its parts gives the same path.
+    let mut rebuilt = ObjectPath::empty();
+    for part in &parts {
+        rebuilt.push_part(part);
+    }
+    assert_eq!(rebuilt, path);
+
+    // Popping and shifting visit every part.
+    let mut popped = path.clone();
+    let mut count = 0;
+    while let Some(part) = popped.pop_part() {
+        assert_eq!(part, parts[parts.len() - count - 1]);
+        count += 1;
+    }
+    assert_eq!(count, parts.len());
+
+    let mut unshifted = path.clone();
+    let mut count = 0;
+    while let Some(part) = unshifted.unshift_part() {
+        assert_eq!(part, parts[count]);
+        count += 1;
+    }
+    // Unshifting the last part from "a/" leaves an empty path so a trailing
+    // empty part is never seen.
+    if input.ends_with('/') {
+        assert_eq!(count, parts.len() - 1);
+    } else {
+        assert_eq!(count, parts.len());
+    }
+});
@@ -0,0 +1,59 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that a path that passes a strict `PathPolicy` can never escape the
+//! prefix that it is joined to.
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use file_store::{ObjectPath, PathPolicy};
+
+fuzz_target!(|data: &[u8]| {
+    let input = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+
+    // Use the first line as the prefix and the rest as the path.
+    let mut lines = input.splitn(2, '\n');
+    let (prefix, path) = match (lines.next(), lines.next()) {
+        (Some(prefix), Some(path)) => (prefix, path),
+        _ => return,
+    };
+
+    let (prefix, path) = match (ObjectPath::new(prefix), ObjectPath::new(path)) {
+        (Ok(prefix), Ok(path)) => (prefix, path),
+        _ => return,
+    };
+
+    let policy = PathPolicy::new()
+        .disallow_empty_parts()
+        .disallow_control_characters()
+        .disallow_characters(&['\\'])
+        .reserve_part(".")
+        .reserve_part("..");
+    if policy.validate(&prefix).is_err() || policy.validate(&path).is_err() {
+        return;
+    }
+
+    let joined = prefix.join(&path);
+    assert!(joined.starts_with(&prefix));
+
+    let joined_parts = joined.parts();
+    let prefix_parts = prefix.parts();
+    assert_eq!(&joined_parts[..prefix_parts.len()], &prefix_parts[..]);
+    assert_eq!(&joined_parts[prefix_parts.len()..], &path.parts()[..]);
+    assert!(joined_parts.iter().all(|p| *p != ".." && *p != "."));
+});
@@ -0,0 +1,1173 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streams the files under a prefix as a tar or zip archive. Included with the
+//! feature "archive".
+//!
+//! [`FileStore::archive`](../enum.FileStore.html#method.archive) generates the
+//! archive as it is read, one file at a time, so nothing is staged locally.
+//! This makes it simple to offer a directory as a download from a web handler.
+//!
+//! Tar archives use the ustar format and can be gzip compressed as a whole
+//! using the [`compression`](../compression/index.html) adapters. Zip archives
+//! store each file uncompressed with a trailing data descriptor since the CRC
+//! is only known once the file has been read. Zip archives do not support the
+//! zip64 extensions so are limited to 65535 files and 4GB.
+//!
+//! [`FileStore::extract`](../enum.FileStore.html#method.extract) does the
+//! reverse, streaming the files in a tar, gzip compressed tar or zip archive
+//! into a store. Zip entries may be stored or deflated.
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{BufMut, BytesMut, IntoBuf};
+use futures::channel::{mpsc, oneshot};
+use futures::future::{ready, select, Either};
+use futures::sink::SinkExt;
+use futures::stream::{empty, once, FuturesUnordered, Stream, StreamExt, TryStreamExt};
+
+use crate::compression::{compress, decompress, Encoding};
+use crate::types::*;
+use crate::utils::into_data_stream;
+use crate::{FileStore, StorageBackend};
+
+/// The format of an archive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// A ustar format tar archive.
+    Tar,
+    /// A gzip compressed ustar format tar archive.
+    TarGz,
+    /// A zip archive with uncompressed files.
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The mime type of this format.
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+
+    /// The usual file extension of this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+}
+
+const TAR_BLOCK: usize = 512;
+const ZIP_LOCAL_HEADER: u32 = 0x0403_4b50;
+const ZIP_DATA_DESCRIPTOR: u32 = 0x0807_4b50;
+const ZIP_CENTRAL_HEADER: u32 = 0x0201_4b50;
+const ZIP_END_OF_DIRECTORY: u32 = 0x0605_4b50;
+// Data descriptor follows the data, names are UTF-8.
+const ZIP_FLAGS: u16 = 0x0808;
+const ZIP_VERSION: u16 = 20;
+// Made on unix so the external attributes hold the file mode.
+const ZIP_MADE_BY: u16 = (3 << 8) | ZIP_VERSION;
+const FILE_MODE: u32 = 0o644;
+
+fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        let mut crc = i as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+        }
+        *entry = crc;
+    }
+    table
+}
+
+/// Continues a CRC-32 calculation with more data.
+fn crc32(table: &[u32; 256], crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc = table[((crc ^ u32::from(*byte)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn too_large(path: &str) -> StorageError {
+    error::invalid_data(Some(&format!("{} is too large for a zip archive", path)))
+}
+
+/// Converts a time to the (time, date) pair used in zip archives.
+fn dos_time(time: Option<SystemTime>) -> (u16, u16) {
+    let secs = time
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let days = (secs / 86400) as i64;
+    let seconds = secs % 86400;
+
+    // Converts days since the epoch to a civil date.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    // Zip archives cannot represent times before 1980.
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+
+    let time = ((seconds / 3600) << 11) | (((seconds % 3600) / 60) << 5) | ((seconds % 60) / 2);
+    let date = (((year - 1980) as u64) << 9) | ((month as u64) << 5) | day as u64;
+    (time as u16, date as u16)
+}
+
+/// Converts the (time, date) pair used in zip archives to a time.
+fn from_dos_time(time: u16, date: u16) -> SystemTime {
+    let year = i64::from(date >> 9) + 1980;
+    let month = i64::from((date >> 5) & 0x0f).max(1);
+    let day = i64::from(date & 0x1f).max(1);
+
+    // Converts a civil date to days since the epoch.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let seconds = u64::from(time >> 11) * 3600
+        + u64::from((time >> 5) & 0x3f) * 60
+        + u64::from(time & 0x1f) * 2;
+    UNIX_EPOCH + Duration::from_secs(days as u64 * 86400 + seconds)
+}
+
+/// Writes a number as a null terminated octal field.
+fn put_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let text = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(text.as_bytes());
+    field[digits] = 0;
+}
+
+fn tar_header(path: &str, size: u64, modified: Option<SystemTime>) -> StorageResult<Data> {
+    let mut header = [0u8; TAR_BLOCK];
+
+    // Long paths are split between the name and prefix fields.
+    let (prefix, name) = if path.len() <= 100 {
+        ("", path)
+    } else {
+        match path[..path.len().min(156)].rfind('/') {
+            Some(pos) if path.len() - pos - 1 <= 100 => (&path[..pos], &path[pos + 1..]),
+            _ => {
+                return Err(error::invalid_data(Some(&format!(
+                    "{} is too long for a tar archive",
+                    path
+                ))))
+            }
+        }
+    };
+
+    let mtime = modified
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    header[0..name.len()].copy_from_slice(name.as_bytes());
+    put_octal(&mut header[100..108], u64::from(FILE_MODE));
+    put_octal(&mut header[108..116], 0);
+    put_octal(&mut header[116..124], 0);
+    if size < 0o777_7777_7777 {
+        put_octal(&mut header[124..136], size);
+    } else {
+        // Sizes that don't fit in octal use the base-256 extension.
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    put_octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    // The checksum is calculated as if the field were all spaces.
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|b| u32::from(*b)).sum();
+    put_octal(&mut header[148..155], u64::from(checksum));
+    header[155] = b' ';
+
+    Ok(Data::from(&header[..]))
+}
+
+fn tar_padding(size: u64) -> Data {
+    let remainder = (size % TAR_BLOCK as u64) as usize;
+    if remainder == 0 {
+        Data::new()
+    } else {
+        Data::from(vec![0; TAR_BLOCK - remainder])
+    }
+}
+
+struct ZipEntry {
+    name: String,
+    time: u16,
+    date: u16,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+fn zip_local_header(entry: &ZipEntry) -> Data {
+    let mut buffer = BytesMut::with_capacity(30 + entry.name.len());
+    buffer.put_u32_le(ZIP_LOCAL_HEADER);
+    buffer.put_u16_le(ZIP_VERSION);
+    buffer.put_u16_le(ZIP_FLAGS);
+    buffer.put_u16_le(0);
+    buffer.put_u16_le(entry.time);
+    buffer.put_u16_le(entry.date);
+    // The CRC and sizes are in the data descriptor.
+    buffer.put_u32_le(0);
+    buffer.put_u32_le(0);
+    buffer.put_u32_le(0);
+    buffer.put_u16_le(entry.name.len() as u16);
+    buffer.put_u16_le(0);
+    buffer.put_slice(entry.name.as_bytes());
+    buffer.freeze()
+}
+
+fn zip_data_descriptor(entry: &ZipEntry) -> Data {
+    let mut buffer = BytesMut::with_capacity(16);
+    buffer.put_u32_le(ZIP_DATA_DESCRIPTOR);
+    buffer.put_u32_le(entry.crc);
+    buffer.put_u32_le(entry.size);
+    buffer.put_u32_le(entry.size);
+    buffer.freeze()
+}
+
+fn zip_central_directory(entries: &[ZipEntry], offset: u64) -> StorageResult<Data> {
+    let mut buffer = BytesMut::new();
+    for entry in entries {
+        buffer.reserve(46 + entry.name.len());
+        buffer.put_u32_le(ZIP_CENTRAL_HEADER);
+        buffer.put_u16_le(ZIP_MADE_BY);
+        buffer.put_u16_le(ZIP_VERSION);
+        buffer.put_u16_le(ZIP_FLAGS);
+        buffer.put_u16_le(0);
+        buffer.put_u16_le(entry.time);
+        buffer.put_u16_le(entry.date);
+        buffer.put_u32_le(entry.crc);
+        buffer.put_u32_le(entry.size);
+        buffer.put_u32_le(entry.size);
+        buffer.put_u16_le(entry.name.len() as u16);
+        buffer.put_u16_le(0);
+        buffer.put_u16_le(0);
+        buffer.put_u16_le(0);
+        buffer.put_u16_le(0);
+        buffer.put_u32_le((0o100_000 | FILE_MODE) << 16);
+        buffer.put_u32_le(entry.offset);
+        buffer.put_slice(entry.name.as_bytes());
+    }
+
+    let size = buffer.len() as u64;
+    if entries.len() > usize::from(u16::max_value())
+        || offset > u64::from(u32::max_value())
+        || size > u64::from(u32::max_value())
+    {
+        return Err(error::invalid_data(Some(
+            "Too many files for a zip archive",
+        )));
+    }
+
+    buffer.reserve(22);
+    buffer.put_u32_le(ZIP_END_OF_DIRECTORY);
+    buffer.put_u16_le(0);
+    buffer.put_u16_le(0);
+    buffer.put_u16_le(entries.len() as u16);
+    buffer.put_u16_le(entries.len() as u16);
+    buffer.put_u32_le(size as u32);
+    buffer.put_u32_le(offset as u32);
+    buffer.put_u16_le(0);
+    Ok(buffer.freeze())
+}
+
+struct CurrentFile {
+    stream: DataStream,
+    entry: ZipEntry,
+    expected: u64,
+    written: u64,
+}
+
+struct ArchiveState {
+    store: FileStore,
+    prefix: ObjectPath,
+    format: ArchiveFormat,
+    files: Option<VecDeque<Object>>,
+    current: Option<CurrentFile>,
+    entries: Vec<ZipEntry>,
+    offset: u64,
+    crc_table: [u32; 256],
+    done: bool,
+}
+
+impl ArchiveState {
+    fn emit(mut self, data: Data) -> Option<(StorageResult<Data>, ArchiveState)> {
+        self.offset += data.len() as u64;
+        Some((Ok(data), self))
+    }
+
+    fn fail(mut self, error: StorageError) -> Option<(StorageResult<Data>, ArchiveState)> {
+        self.done = true;
+        self.current = None;
+        Some((Err(error), self))
+    }
+
+    fn update_crc(&mut self, data: &[u8]) {
+        if let Some(ref mut current) = self.current {
+            current.entry.crc = crc32(&self.crc_table, current.entry.crc, data);
+        }
+    }
+
+    async fn list(&self) -> StorageResult<VecDeque<Object>> {
+        let list_prefix = if self.prefix.is_empty() {
+            self.prefix.clone()
+        } else {
+            ObjectPath::new(format!("{}/", self.prefix))?
+        };
+
+        let mut files: Vec<Object> = self
+            .store
+            .list_objects(list_prefix)
+            .await?
+            .try_filter(|o| futures::future::ready(o.object_type() == ObjectType::File))
+            .try_collect()
+            .await?;
+        files.sort_by_key(|o| o.path().to_string());
+
+        Ok(files.into())
+    }
+
+    async fn open(&self, object: Object) -> StorageResult<CurrentFile> {
+        let path = object.path();
+        let mut relative = ObjectPath::empty();
+        for part in path.parts().iter().skip(self.prefix.parts().len()) {
+            relative.push_part(part);
+        }
+        let name = relative.to_string();
+
+        let (time, date) = dos_time(object.modified());
+        let entry = ZipEntry {
+            name,
+            time,
+            date,
+            crc: 0,
+            size: 0,
+            offset: 0,
+        };
+
+        if self.format == ArchiveFormat::Zip
+            && (object.len() > u64::from(u32::max_value())
+                || self.offset > u64::from(u32::max_value()))
+        {
+            return Err(too_large(&entry.name));
+        }
+
+        let stream = self.store.get_file_stream(path).await?;
+        Ok(CurrentFile {
+            stream,
+            entry,
+            expected: object.len(),
+            written: 0,
+        })
+    }
+}
+
+async fn next_piece(mut state: ArchiveState) -> Option<(StorageResult<Data>, ArchiveState)> {
+    if state.done {
+        return None;
+    }
+
+    if state.files.is_none() {
+        match state.list().await {
+            Ok(files) => state.files = Some(files),
+            Err(e) => return state.fail(e),
+        }
+    }
+
+    if let Some(mut current) = state.current.take() {
+        match current.stream.next().await {
+            Some(Ok(data)) => {
+                current.written += data.len() as u64;
+                state.current = Some(current);
+                state.update_crc(&data);
+                return state.emit(data);
+            }
+            Some(Err(e)) => return state.fail(e),
+            None => {
+                // The headers have already been sent so a file that changed
+                // size can only fail the archive.
+                if current.written != current.expected {
+                    let message = format!("{} changed while being archived", current.entry.name);
+                    return state.fail(error::invalid_data(Some(&message)));
+                }
+
+                let trailer = match state.format {
+                    ArchiveFormat::Tar | ArchiveFormat::TarGz => tar_padding(current.written),
+                    ArchiveFormat::Zip => {
+                        current.entry.size = current.written as u32;
+                        let descriptor = zip_data_descriptor(&current.entry);
+                        state.entries.push(current.entry);
+                        descriptor
+                    }
+                };
+                return state.emit(trailer);
+            }
+        }
+    }
+
+    let next = state.files.as_mut().and_then(VecDeque::pop_front);
+    match next {
+        Some(object) => {
+            let size = object.len();
+            let modified = object.modified();
+            let mut current = match state.open(object).await {
+                Ok(current) => current,
+                Err(e) => return state.fail(e),
+            };
+
+            let header = match state.format {
+                ArchiveFormat::Tar | ArchiveFormat::TarGz => {
+                    match tar_header(&current.entry.name, size, modified) {
+                        Ok(header) => header,
+                        Err(e) => return state.fail(e),
+                    }
+                }
+                ArchiveFormat::Zip => {
+                    current.entry.offset = state.offset as u32;
+                    zip_local_header(&current.entry)
+                }
+            };
+
+            state.current = Some(current);
+            state.emit(header)
+        }
+        None => {
+            state.done = true;
+            let end = match state.format {
+                ArchiveFormat::Tar | ArchiveFormat::TarGz => Ok(Data::from(vec![0; TAR_BLOCK * 2])),
+                ArchiveFormat::Zip => zip_central_directory(&state.entries, state.offset),
+            };
+
+            match end {
+                Ok(data) => state.emit(data),
+                Err(e) => state.fail(e),
+            }
+        }
+    }
+}
+
+/// Generates an archive of the files under a prefix.
+pub(crate) fn archive(store: FileStore, prefix: ObjectPath, format: ArchiveFormat) -> DataStream {
+    let state = ArchiveState {
+        store,
+        prefix,
+        format,
+        files: None,
+        current: None,
+        entries: Vec::new(),
+        offset: 0,
+        crc_table: crc_table(),
+        done: false,
+    };
+
+    let stream = DataStream::from_stream(futures::stream::unfold(state, next_piece));
+    match format {
+        ArchiveFormat::TarGz => compress(stream, Encoding::Gzip),
+        _ => stream,
+    }
+}
+
+/// A future that resolves to the number of files extracted from an archive.
+pub type ExtractFuture = WrappedFuture<Result<usize, TransferError>>;
+
+const ZIP_ZIP64_END_OF_DIRECTORY: u32 = 0x0606_4b50;
+const ZIP_STORED: u16 = 0;
+const ZIP_DEFLATED: u16 = 8;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFAULT_CONCURRENCY: usize = 4;
+// The number of chunks queued for each file being written.
+const ENTRY_BUFFER: usize = 4;
+// Extended tar headers are read into memory so their size is limited.
+const MAX_EXTENDED_HEADER: u64 = 1024 * 1024;
+
+fn invalid_archive(message: &str) -> TransferError {
+    TransferError::SourceError(error::invalid_data(Some(message)))
+}
+
+fn unexpected_end() -> TransferError {
+    invalid_archive("Unexpected end of archive")
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Reads the string in a null terminated field.
+fn field(data: &[u8]) -> String {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+/// Reads a number from an octal or base-256 field in a tar header.
+fn parse_number(data: &[u8]) -> Result<u64, TransferError> {
+    if data[0] & 0x80 != 0 {
+        let bytes = &data[data.len() - 8..];
+        return Ok(u64::from_be_bytes(bytes.try_into().unwrap()));
+    }
+
+    let text = field(data);
+    let text = text.trim_matches(|c| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+
+    u64::from_str_radix(text, 8).map_err(|_| invalid_archive("Invalid number in tar header"))
+}
+
+/// Reads the path and modification time from a pax extended header.
+fn parse_pax(data: &[u8], path: &mut Option<String>, modified: &mut Option<u64>) {
+    let mut pos = 0;
+    while pos < data.len() {
+        let space = match data[pos..].iter().position(|b| *b == b' ') {
+            Some(space) => pos + space,
+            None => return,
+        };
+        let length = match String::from_utf8_lossy(&data[pos..space]).parse::<usize>() {
+            Ok(length) if pos + length > space + 1 && pos + length <= data.len() => length,
+            _ => return,
+        };
+
+        let record = String::from_utf8_lossy(&data[space + 1..pos + length - 1]);
+        if let Some(equals) = record.find('=') {
+            let (key, value) = (&record[..equals], &record[equals + 1..]);
+            match key {
+                "path" => *path = Some(value.to_owned()),
+                "mtime" => {
+                    let seconds = value.split('.').next().unwrap_or("");
+                    *modified = seconds.parse().ok();
+                }
+                _ => (),
+            }
+        }
+
+        pos += length;
+    }
+}
+
+/// Converts a path in an archive to a path in the store. Returns `None` for
+/// paths that do not name a file.
+fn entry_path(prefix: &ObjectPath, name: &str) -> Result<Option<ObjectPath>, TransferError> {
+    let mut path = prefix.clone();
+    let mut found = false;
+    for part in name.split('/') {
+        match part {
+            "" | "." => continue,
+            ".." => {
+                let message = format!("{} is outside of the archive", name);
+                return Err(invalid_archive(&message));
+            }
+            part => {
+                path.push_part(part);
+                found = true;
+            }
+        }
+    }
+
+    if found && !name.ends_with('/') {
+        Ok(Some(path))
+    } else {
+        Ok(None)
+    }
+}
+
+/// What the data of an entry must match once it has all been read.
+struct Expected {
+    crc: Option<u32>,
+    size: u64,
+}
+
+/// Passes the data of an entry to the write storing it.
+struct EntryWriter {
+    data: mpsc::Sender<StorageResult<Data>>,
+    expected: oneshot::Sender<Expected>,
+}
+
+impl EntryWriter {
+    /// Ends the entry's data. The write fails if the data doesn't match.
+    fn finish(writer: Option<EntryWriter>, expected: Expected) {
+        if let Some(writer) = writer {
+            let _ = writer.expected.send(expected);
+        }
+    }
+}
+
+struct EntryState {
+    name: String,
+    data: DataStream,
+    expected: Option<oneshot::Receiver<Expected>>,
+    crc_table: [u32; 256],
+    crc: u32,
+    size: u64,
+}
+
+async fn next_entry_chunk(mut state: EntryState) -> Option<(StorageResult<Data>, EntryState)> {
+    let expected = state.expected.take()?;
+
+    match state.data.next().await {
+        Some(Ok(data)) => {
+            state.crc = crc32(&state.crc_table, state.crc, &data);
+            state.size += data.len() as u64;
+            state.expected = Some(expected);
+            Some((Ok(data), state))
+        }
+        Some(Err(e)) => Some((Err(e), state)),
+        None => {
+            let message = match expected.await {
+                Ok(ref expected) if expected.size != state.size => {
+                    format!("{} is not the expected size", state.name)
+                }
+                Ok(Expected { crc: Some(crc), .. }) if crc != state.crc => {
+                    format!("{} failed its CRC check", state.name)
+                }
+                Ok(_) => return None,
+                // The archive ended before all of the entry was read.
+                Err(_) => "Unexpected end of archive".to_owned(),
+            };
+            Some((Err(error::invalid_data(Some(&message))), state))
+        }
+    }
+}
+
+/// The stream written to the store for an entry, it fails if the archive ends
+/// early or the data doesn't match what the archive expects.
+fn entry_stream(
+    name: String,
+    data: mpsc::Receiver<StorageResult<Data>>,
+    expected: oneshot::Receiver<Expected>,
+    deflated: bool,
+    crc_table: [u32; 256],
+) -> DataStream {
+    let data = DataStream::from_stream(data);
+    let data = if deflated {
+        decompress(data, Encoding::Deflate)
+    } else {
+        data
+    };
+
+    let state = EntryState {
+        name,
+        data,
+        expected: Some(expected),
+        crc_table,
+        crc: 0,
+        size: 0,
+    };
+    DataStream::from_stream(futures::stream::unfold(state, next_entry_chunk))
+}
+
+struct Extraction {
+    store: FileStore,
+    prefix: ObjectPath,
+    concurrency: usize,
+    stream: DataStream,
+    buffer: BytesMut,
+    finished: bool,
+    uploads: FuturesUnordered<WriteCompleteFuture>,
+    crc_table: [u32; 256],
+    count: usize,
+}
+
+impl Extraction {
+    /// Reads more data from the archive while letting uploads progress.
+    async fn read_more(&mut self) -> Result<(), TransferError> {
+        loop {
+            let item = if self.uploads.is_empty() {
+                self.stream.next().await
+            } else {
+                match select(self.stream.next(), self.uploads.next()).await {
+                    Either::Left((item, _)) => item,
+                    Either::Right((result, _)) => {
+                        if let Some(result) = result {
+                            result?;
+                        }
+                        continue;
+                    }
+                }
+            };
+
+            match item {
+                Some(Ok(data)) => self.buffer.extend_from_slice(&data),
+                Some(Err(e)) => return Err(TransferError::SourceError(e)),
+                None => self.finished = true,
+            }
+            return Ok(());
+        }
+    }
+
+    /// Buffers at least `length` bytes. Returns false if the archive ends
+    /// first.
+    async fn fill(&mut self, length: usize) -> Result<bool, TransferError> {
+        while self.buffer.len() < length {
+            if self.finished {
+                return Ok(false);
+            }
+            self.read_more().await?;
+        }
+        Ok(true)
+    }
+
+    async fn read_exact(&mut self, length: usize) -> Result<Data, TransferError> {
+        if self.fill(length).await? {
+            Ok(self.buffer.split_to(length).freeze())
+        } else {
+            Err(unexpected_end())
+        }
+    }
+
+    /// Starts writing an entry, first waiting for an earlier write to complete
+    /// if too many are in progress. Returns `None` for entries that aren't
+    /// files.
+    async fn start_entry(
+        &mut self,
+        name: &str,
+        modified: Option<SystemTime>,
+        deflated: bool,
+    ) -> Result<Option<EntryWriter>, TransferError> {
+        let path = match entry_path(&self.prefix, name)? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        while self.uploads.len() >= self.concurrency {
+            if let Some(result) = self.uploads.next().await {
+                result?;
+            }
+        }
+
+        let (data_sender, data) = mpsc::channel(ENTRY_BUFFER);
+        let (expected_sender, expected) = oneshot::channel();
+        let info = UploadInfo {
+            path,
+            modified,
+            // A file cut short by a broken archive is useless.
+            cleanup_on_failure: Some(true),
+            ..Default::default()
+        };
+        let stream = entry_stream(name.to_owned(), data, expected, deflated, self.crc_table);
+        self.uploads.push(StorageBackend::write_file_from_stream(
+            &self.store,
+            info,
+            stream,
+        ));
+        self.count += 1;
+
+        Ok(Some(EntryWriter {
+            data: data_sender,
+            expected: expected_sender,
+        }))
+    }
+
+    /// Passes data to the entry being written while letting uploads progress.
+    async fn send(
+        &mut self,
+        writer: &mut Option<EntryWriter>,
+        data: Data,
+    ) -> Result<(), TransferError> {
+        let sender = match writer {
+            Some(writer) => &mut writer.data,
+            None => return Ok(()),
+        };
+
+        // Sending only fails once the write has stopped reading, its result
+        // says why.
+        let mut send = sender.send(Ok(data));
+        loop {
+            match select(&mut send, self.uploads.next()).await {
+                Either::Left(_) => return Ok(()),
+                Either::Right((Some(result), _)) => result?,
+                Either::Right((None, _)) => {
+                    let _ = send.await;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Passes the next `length` bytes of the archive to an entry, or skips
+    /// them if there is no entry.
+    async fn copy(
+        &mut self,
+        writer: &mut Option<EntryWriter>,
+        mut length: u64,
+    ) -> Result<(), TransferError> {
+        while length > 0 {
+            if self.buffer.is_empty() {
+                if self.finished {
+                    return Err(unexpected_end());
+                }
+                self.read_more().await?;
+                continue;
+            }
+
+            let available = (self.buffer.len() as u64).min(length) as usize;
+            length -= available as u64;
+            let data = self.buffer.split_to(available).freeze();
+            self.send(writer, data).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Passes the data of a zip entry whose sizes are only given in the data
+    /// descriptor that follows it to the entry.
+    async fn copy_until_descriptor(
+        &mut self,
+        writer: &mut Option<EntryWriter>,
+        stored: bool,
+    ) -> Result<Expected, TransferError> {
+        let signature = ZIP_DATA_DESCRIPTOR.to_le_bytes();
+        // The length and, for stored data, the CRC of what has been passed on.
+        let mut copied = 0;
+        let mut crc = 0;
+
+        loop {
+            let mut searched = 0;
+            while let Some(found) = self.buffer[searched..]
+                .windows(4)
+                .position(|w| w == signature)
+            {
+                let pos = searched + found;
+                if self.buffer.len() < pos + 16 {
+                    break;
+                }
+
+                // The signature could appear in the data so check that the
+                // descriptor matches the data before it.
+                let expected = Expected {
+                    crc: Some(u32_at(&self.buffer, pos + 4)),
+                    size: u64::from(u32_at(&self.buffer, pos + 12)),
+                };
+                let compressed = u64::from(u32_at(&self.buffer, pos + 8));
+                if compressed == copied + pos as u64
+                    && (!stored
+                        || (expected.size == compressed
+                            && Some(crc32(&self.crc_table, crc, &self.buffer[..pos]))
+                                == expected.crc))
+                {
+                    let data = self.buffer.split_to(pos).freeze();
+                    self.buffer.advance(16);
+                    if !data.is_empty() {
+                        self.send(writer, data).await?;
+                    }
+                    return Ok(expected);
+                }
+
+                searched = pos + 1;
+            }
+
+            // Anything before the last 15 bytes has been ruled out as the
+            // start of the descriptor.
+            if self.buffer.len() > 15 {
+                let data = self.buffer.split_to(self.buffer.len() - 15).freeze();
+                copied += data.len() as u64;
+                if stored {
+                    crc = crc32(&self.crc_table, crc, &data);
+                }
+                self.send(writer, data).await?;
+            }
+
+            if self.finished {
+                return Err(unexpected_end());
+            }
+            self.read_more().await?;
+        }
+    }
+
+    async fn finish(mut self) -> Result<usize, TransferError> {
+        while let Some(result) = self.uploads.next().await {
+            result?;
+        }
+        Ok(self.count)
+    }
+
+    async fn extract_tar(&mut self) -> Result<(), TransferError> {
+        let mut long_name: Option<String> = None;
+        let mut pax_path: Option<String> = None;
+        let mut pax_modified: Option<u64> = None;
+
+        loop {
+            if !self.fill(TAR_BLOCK).await? {
+                if self.buffer.is_empty() {
+                    return Ok(());
+                }
+                return Err(unexpected_end());
+            }
+
+            let header = self.buffer.split_to(TAR_BLOCK).freeze();
+            if header.iter().all(|b| *b == 0) {
+                return Ok(());
+            }
+
+            let checksum = parse_number(&header[148..156])?;
+            let sum: u64 = header
+                .iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    if i >= 148 && i < 156 {
+                        32
+                    } else {
+                        u64::from(*b)
+                    }
+                })
+                .sum();
+            if checksum != sum {
+                return Err(invalid_archive("Invalid tar header checksum"));
+            }
+
+            let size = parse_number(&header[124..136])?;
+            let padding = (TAR_BLOCK as u64 - size % TAR_BLOCK as u64) % TAR_BLOCK as u64;
+
+            match header[156] {
+                b'0' | b'7' | 0 => {
+                    let name = match (long_name.take(), pax_path.take()) {
+                        (_, Some(name)) | (Some(name), None) => name,
+                        (None, None) => {
+                            let name = field(&header[0..100]);
+                            let prefix = field(&header[345..500]);
+                            if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                                format!("{}/{}", prefix, name)
+                            } else {
+                                name
+                            }
+                        }
+                    };
+
+                    let mtime = match pax_modified.take() {
+                        Some(mtime) => mtime,
+                        None => parse_number(&header[136..148])?,
+                    };
+                    let modified = UNIX_EPOCH + Duration::from_secs(mtime);
+
+                    let mut writer = self.start_entry(&name, Some(modified), false).await?;
+                    self.copy(&mut writer, size).await?;
+                    EntryWriter::finish(writer, Expected { crc: None, size });
+                }
+                kind @ b'x' | kind @ b'L' => {
+                    if size > MAX_EXTENDED_HEADER {
+                        return Err(invalid_archive("Extended tar header is too large"));
+                    }
+
+                    let data = self.read_exact(size as usize).await?;
+                    if kind == b'x' {
+                        parse_pax(&data, &mut pax_path, &mut pax_modified);
+                    } else {
+                        long_name = Some(field(&data));
+                    }
+                }
+                _ => {
+                    // Directories, links and devices have no place in storage.
+                    self.copy(&mut None, size).await?;
+                    long_name = None;
+                    pax_path = None;
+                    pax_modified = None;
+                }
+            }
+
+            self.copy(&mut None, padding).await?;
+        }
+    }
+
+    async fn extract_zip(&mut self) -> Result<(), TransferError> {
+        loop {
+            if !self.fill(4).await? {
+                return Err(unexpected_end());
+            }
+
+            match u32_at(&self.buffer, 0) {
+                ZIP_LOCAL_HEADER => (),
+                ZIP_CENTRAL_HEADER | ZIP_END_OF_DIRECTORY | ZIP_ZIP64_END_OF_DIRECTORY => {
+                    // Everything needed has been read from the local headers.
+                    return Ok(());
+                }
+                _ => return Err(invalid_archive("Invalid zip header")),
+            }
+
+            let header = self.read_exact(30).await?;
+            let flags = u16_at(&header, 6);
+            let method = u16_at(&header, 8);
+            let modified = from_dos_time(u16_at(&header, 10), u16_at(&header, 12));
+            let name_length = u16_at(&header, 26) as usize;
+            let extra_length = u16_at(&header, 28) as usize;
+
+            let name = self.read_exact(name_length).await?;
+            let name = String::from_utf8_lossy(&name).into_owned();
+            self.read_exact(extra_length).await?;
+
+            if flags & 0x01 != 0 {
+                let message = format!("{} is encrypted", name);
+                return Err(invalid_archive(&message));
+            }
+            let deflated = match method {
+                ZIP_STORED => false,
+                ZIP_DEFLATED => true,
+                _ => {
+                    let message = format!("{} uses an unsupported compression method", name);
+                    return Err(invalid_archive(&message));
+                }
+            };
+
+            let mut writer = self.start_entry(&name, Some(modified), deflated).await?;
+            let expected = if flags & 0x08 != 0 {
+                self.copy_until_descriptor(&mut writer, !deflated).await?
+            } else {
+                self.copy(&mut writer, u64::from(u32_at(&header, 18)))
+                    .await?;
+                Expected {
+                    crc: Some(u32_at(&header, 14)),
+                    size: u64::from(u32_at(&header, 22)),
+                }
+            };
+            EntryWriter::finish(writer, expected);
+        }
+    }
+
+    /// Decompresses the rest of the archive as it is read.
+    fn decompress_gzip(&mut self) {
+        let buffered = self.buffer.take().freeze();
+        let rest = mem::replace(&mut self.stream, DataStream::from_stream(empty()));
+        let rest = if self.finished {
+            DataStream::from_stream(empty())
+        } else {
+            rest
+        };
+
+        let compressed = DataStream::from_stream(once(ready(Ok(buffered))).chain(rest));
+        self.stream = decompress(compressed, Encoding::Gzip);
+        self.finished = false;
+    }
+
+    async fn extract(mut self) -> Result<usize, TransferError> {
+        if self.fill(GZIP_MAGIC.len()).await? && self.buffer[..GZIP_MAGIC.len()] == GZIP_MAGIC[..] {
+            self.decompress_gzip();
+        }
+
+        // Zip archives start with a local header or are empty.
+        let is_zip = self.fill(4).await?
+            && (u32_at(&self.buffer, 0) == ZIP_LOCAL_HEADER
+                || u32_at(&self.buffer, 0) == ZIP_END_OF_DIRECTORY);
+
+        let result = if is_zip {
+            self.extract_zip().await
+        } else {
+            self.extract_tar().await
+        };
+
+        match result {
+            Ok(()) => self.finish().await,
+            Err(e) => {
+                // Let any writes already started complete, the file being
+                // extracted when the archive failed fails too.
+                let _ = self.finish().await;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Extracts tar, gzip compressed tar or zip archives into a
+/// [`FileStore`](../enum.FileStore.html).
+///
+/// The format of the archive is detected from its content. Each file is
+/// streamed into the store as it is read from the archive, up to
+/// [`concurrency`](#method.concurrency) files may still be finishing their
+/// writes at once.
+#[derive(Clone, Debug)]
+pub struct Extractor {
+    store: FileStore,
+    concurrency: usize,
+}
+
+impl Extractor {
+    /// Creates an `Extractor` that writes up to 4 files at a time.
+    pub fn new(store: FileStore) -> Extractor {
+        Extractor {
+            store,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Sets the maximum number of files to write at once.
+    pub fn concurrency(mut self, concurrency: usize) -> Extractor {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Writes the files in an archive under the given prefix. Resolves to the
+    /// number of files written.
+    pub fn extract<S, I, E, P>(&self, stream: S, prefix: P) -> ExtractFuture
+    where
+        S: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let prefix = match prefix.try_into() {
+            Ok(prefix) => prefix,
+            Err(e) => return ExtractFuture::from_value(Err(TransferError::TargetError(e.into()))),
+        };
+
+        let extraction = Extraction {
+            store: self.store.clone(),
+            prefix,
+            concurrency: self.concurrency,
+            stream: DataStream::from_stream(into_data_stream(stream)),
+            buffer: BytesMut::new(),
+            finished: false,
+            uploads: FuturesUnordered::new(),
+            crc_table: crc_table(),
+            count: 0,
+        };
+
+        ExtractFuture::from_future(extraction.extract())
+    }
+}
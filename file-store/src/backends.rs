@@ -20,29 +20,38 @@
 //! generally behave the same regardless of the backend.
 #[cfg(feature = "b2")]
 pub mod b2;
-#[cfg(feature = "file")]
+#[cfg(all(feature = "file", not(feature = "wasm")))]
 pub mod file;
+#[cfg(feature = "remote")]
+pub mod remote;
 
 use std::fmt;
 
 /// An enumeration of the available backends.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Backend {
-    #[cfg(feature = "file")]
-    /// The [file backend](file/index.html). Included with the "file" feature.
+    #[cfg(all(feature = "file", not(feature = "wasm")))]
+    /// The [file backend](file/index.html). Included with the "file" feature
+    /// unless the "wasm" feature is enabled.
     File,
     #[cfg(feature = "b2")]
     /// The [b2 backend](b2/index.html). Included with the "b2" feature.
     B2,
+    #[cfg(feature = "remote")]
+    /// The [remote backend](remote/index.html). Included with the "remote"
+    /// feature.
+    Remote,
 }
 
 impl fmt::Display for Backend {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            #[cfg(feature = "file")]
+            #[cfg(all(feature = "file", not(feature = "wasm")))]
             Backend::File => f.pad("file"),
             #[cfg(feature = "b2")]
             Backend::B2 => f.pad("b2"),
+            #[cfg(feature = "remote")]
+            Backend::Remote => f.pad("remote"),
         }
     }
 }
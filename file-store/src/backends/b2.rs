@@ -31,9 +31,14 @@
 //! In order to be compatible with other backends, but still include some useful
 //! functionality file versioning (if enabled for the bucket) is currently
 //! handled as follows:
-//! * Deleting a file will delete all of its versions.
+//! * Deleting a file will delete all of its versions, unless the backend was
+//!   built with [`soft_delete`](struct.B2BackendBuilder.html#method.soft_delete)
+//!   in which case the file is hidden instead.
 //! * Replacing a file will add a new version.
 //!
+//! Individual versions can be listed, read and deleted through
+//! [`VersionedStorage`](../../versions/trait.VersionedStorage.html).
+//!
 //! Setting a file's mimetype on upload is not currently supported. The backend
 //! will rely on B2's automatic mimetype detection to set the mimetype. This
 //! uses the file's extension to set a mimetype from a [list of mappings](https://www.backblaze.com/b2/docs/content-types.html)
@@ -41,49 +46,90 @@
 //!
 //! The last modified time of an uploaded file will be set to the time that the
 //! upload began.
+//!
+//! Copying a file, and so moving one, happens within B2 without downloading
+//! the file's data.
+//!
+//! B2 has no conditional uploads so a [`write_mode`](../../struct.UploadInfo.html#structfield.write_mode)
+//! other than `Overwrite` is applied by looking the target up first. This isn't
+//! atomic, a file written to the target between the lookup and the upload is
+//! replaced.
+//!
+//! [`presigned_url`](struct.B2Backend.html#method.presigned_url) and
+//! [`get_download_authorization`](struct.B2Backend.html#method.get_download_authorization)
+//! let other clients download files from private buckets directly.
+//!
+//! An upload's [`idempotency_key`](../../struct.UploadInfo.html#structfield.idempotency_key)
+//! is stored in the file's info. An upload is skipped if the current version of
+//! the file has the same key, and a failed attempt that may have reached B2 is
+//! only retried if no such version exists. Deleting a version that has already
+//! gone counts as success so retrying a delete is safe.
 
 mod client;
 
+use std::collections::{HashMap, VecDeque};
 use std::convert::{Infallible, TryInto};
 use std::future::Future;
+use std::mem;
 use std::pin::Pin;
 use std::slice::Iter;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use bytes::IntoBuf;
-use futures::channel::mpsc::{channel, Sender};
-use futures::future::{ready, TryFutureExt};
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::future::{poll_fn, ready, try_join, TryFutureExt};
 use futures::sink::SinkExt;
-use futures::stream::{Stream, StreamExt, TryStreamExt};
-use hyper::client::connect::HttpConnector;
-use hyper::client::Client as HyperClient;
-use hyper_tls::HttpsConnector;
-use log::{error, trace};
-use sha1::Sha1;
-use tokio_executor::spawn;
+use futures::stream::{empty, iter, Stream, StreamExt, TryStreamExt};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use log::{error, trace, warn};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 
 use storage_types::b2::v2::requests::*;
 use storage_types::b2::v2::responses::*;
-use storage_types::b2::v2::{FileAction, UserFileInfo, LAST_MODIFIED_KEY};
+use storage_types::b2::v2::{percent_encode, FileAction, UserFileInfo, LAST_MODIFIED_KEY};
 
 use super::Backend;
-use crate::types::stream::{MergedStreams, ResultStreamPoll};
+use crate::cache::ObjectCache;
+use crate::events::{now, EventLog};
+use crate::executor::spawn;
+use crate::hashing::to_hex;
+use crate::http_client::{
+    default_client, HeadersClient, HttpClient, Middleware, MiddlewareClient, PoolSettings, Proxy,
+    SharedHttpClient, TlsSettings,
+};
+use crate::metrics::Metrics;
+use crate::types::stream::ResultStreamPoll;
 use crate::types::*;
-use crate::utils::{into_data_stream, Acquired, CloningPool, Pool};
+use crate::utils::{Acquired, CloningPool, Pool};
+use crate::versions::{ObjectVersion, VersionListFuture, VersionedStorage};
 use crate::{FileStore, StorageBackend};
-use client::{B2APIState, B2Client, B2API};
+use client::{B2APIState, B2Client, UploadBody, B2API};
 
 const TOTAL_MAX_SMALL_FILE_SIZE: u64 = 5 * 1000 * 1000 * 1000;
 const DEFAULT_MAX_SMALL_FILE_SIZE: u64 = 200 * 1000 * 1000;
 const DEFAULT_REQUEST_LIMIT: usize = 20;
-
-type ClientPool = CloningPool<HyperClient<HttpsConnector<HttpConnector>>>;
-type Client = Acquired<
-    HyperClient<HttpsConnector<HttpConnector>>,
-    HyperClient<HttpsConnector<HttpConnector>>,
-    Infallible,
->;
+const DEFAULT_PARTS_IN_FLIGHT: usize = 4;
+const DEFAULT_LIST_PAGE_SIZE: usize = 1000;
+const MAX_FILE_NAME_LENGTH: usize = 1024;
+const DIRECTORY_MARKER: &str = ".bzEmpty";
+// Asks B2 to pick the content type from the file's extension.
+const AUTO_CONTENT_TYPE: &str = "b2/x-auto";
+// The file info B2 recommends for storing the SHA-1 of a large file.
+const LARGE_FILE_SHA1_KEY: &str = "large_file_sha1";
+// Marks a content SHA-1 that was supplied after the upload.
+const UNVERIFIED_PREFIX: &str = "unverified:";
+// Replaces the metadata of a copied file with the metadata in the request.
+const REPLACE_METADATA: &str = "REPLACE";
+// The largest page of files B2 returns in a single request.
+const MAX_LIST_COUNT: usize = 10000;
+const IDEMPOTENCY_KEY: &str = "idempotency_key";
+// The longest B2 allows a download authorization to last.
+const MAX_DOWNLOAD_AUTHORIZATION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+type ClientPool = CloningPool<SharedHttpClient>;
+type Client = Acquired<SharedHttpClient, SharedHttpClient, Infallible>;
 
 #[derive(Clone, Debug)]
 struct FileVersions {
@@ -106,6 +152,90 @@ impl FileVersions {
     }
 }
 
+/// A future that resolves to an [`UploadSession`](struct.UploadSession.html).
+pub type UploadSessionFuture = WrappedFuture<StorageResult<UploadSession>>;
+
+/// A large file upload that can be resumed, see
+/// [`FileStore::begin_upload`](../../enum.FileStore.html#method.begin_upload).
+///
+/// Sessions serialize so they can be stored and used to resume an upload from
+/// another process after a crash. The parts already uploaded are listed from
+/// B2 when resuming so nothing needs updating as the upload progresses.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadSession {
+    path: String,
+    file_id: String,
+    part_size: u64,
+}
+
+impl UploadSession {
+    /// The path being uploaded to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The B2 file id of the unfinished large file.
+    pub fn file_id(&self) -> &str {
+        &self.file_id
+    }
+}
+
+/// A future that resolves to a
+/// [`DownloadAuthorization`](struct.DownloadAuthorization.html).
+pub type DownloadAuthorizationFuture = WrappedFuture<StorageResult<DownloadAuthorization>>;
+
+/// Allows downloading the files whose names start with a prefix without any
+/// other credentials, see
+/// [`get_download_authorization`](struct.B2Backend.html#method.get_download_authorization).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DownloadAuthorization {
+    root: ObjectPath,
+    download_url: String,
+    bucket: String,
+    file_name_prefix: String,
+    token: String,
+    expires: SystemTime,
+}
+
+impl DownloadAuthorization {
+    /// The authorization token. It can be sent in the `Authorization` header
+    /// of a download request instead of using [`url`](#method.url).
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// When the authorization stops working.
+    pub fn expires(&self) -> SystemTime {
+        self.expires
+    }
+
+    /// A URL that downloads the file at `path` until the authorization
+    /// expires. Fails if the path isn't covered by the authorization.
+    pub fn url(&self, path: &ObjectPath) -> StorageResult<String> {
+        let mut file_name = self.root.join(path);
+        let bucket = file_name.unshift_part();
+        let file_name = file_name.to_string();
+
+        if bucket.as_ref() != Some(&self.bucket)
+            || file_name.is_empty()
+            || !file_name.starts_with(&self.file_name_prefix)
+        {
+            return Err(error::invalid_path(
+                path.clone(),
+                Some("The path is not covered by this authorization."),
+            ));
+        }
+
+        Ok(format!(
+            "{}/file/{}/{}?Authorization={}",
+            self.download_url,
+            percent_encode(&self.bucket),
+            percent_encode(&file_name),
+            percent_encode(&self.token)
+        ))
+    }
+}
+
 /// The B2 implementation for [`Object`](../../enum.Object.html).
 #[derive(Clone, Debug)]
 pub struct B2Object {
@@ -114,6 +244,13 @@ pub struct B2Object {
 }
 
 impl B2Object {
+    /// The B2 file id of the current version of the file. Every upload creates
+    /// a new version with a new id, see
+    /// [`get_object_by_id`](struct.B2Backend.html#method.get_object_by_id).
+    pub fn file_id(&self) -> Option<&str> {
+        self.versions.latest().file_id.as_ref().map(String::as_str)
+    }
+
     fn versions(&self) -> Iter<FileInfo> {
         self.versions.iter()
     }
@@ -137,32 +274,87 @@ impl ObjectInfo for B2Object {
     }
 
     fn modified(&self) -> Option<SystemTime> {
+        version_modified(self.versions.latest())
+    }
+
+    /// Every upload creates a new version with a new id.
+    fn etag(&self) -> Option<String> {
         let version = self.versions.latest();
         if version.action != FileAction::Upload {
             return None;
         }
 
-        version
-            .file_info
-            .get(LAST_MODIFIED_KEY)
-            .and_then(|s| {
-                let time = match s.parse::<u64>() {
-                    Ok(t) => t,
-                    Err(_) => return None,
-                };
+        version.file_id.clone()
+    }
 
-                Some(UNIX_EPOCH + Duration::from_millis(time))
-            })
-            .or_else(|| {
-                if version.upload_timestamp > 0 {
-                    Some(UNIX_EPOCH + Duration::from_millis(version.upload_timestamp))
-                } else {
-                    None
-                }
-            })
+    /// The file info stored with the file, except what this crate uses
+    /// internally. B2 returns the keys in lower case.
+    fn metadata(&self) -> Option<HashMap<String, String>> {
+        let version = self.versions.latest();
+        if version.action != FileAction::Upload {
+            return None;
+        }
+
+        Some(
+            version
+                .file_info
+                .iter()
+                .filter(|(key, _)| !is_internal_info(key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        )
+    }
+
+    fn content_type(&self) -> Option<String> {
+        self.versions.latest().content_type.clone()
+    }
+
+    /// B2's SHA-1 of the content. Large files only have a checksum if the
+    /// uploader stored one in the `large_file_sha1` file info.
+    fn checksum(&self) -> Option<Checksum> {
+        version_checksum(self.versions.latest())
     }
 }
 
+fn version_modified(version: &FileInfo) -> Option<SystemTime> {
+    if version.action != FileAction::Upload {
+        return None;
+    }
+
+    version
+        .file_info
+        .get(LAST_MODIFIED_KEY)
+        .and_then(|s| {
+            let time = match s.parse::<u64>() {
+                Ok(t) => t,
+                Err(_) => return None,
+            };
+
+            Some(UNIX_EPOCH + Duration::from_millis(time))
+        })
+        .or_else(|| {
+            if version.upload_timestamp > 0 {
+                Some(UNIX_EPOCH + Duration::from_millis(version.upload_timestamp))
+            } else {
+                None
+            }
+        })
+}
+
+fn version_checksum(version: &FileInfo) -> Option<Checksum> {
+    if version.action != FileAction::Upload {
+        return None;
+    }
+
+    let digest = match version.content_sha1.as_ref().map(String::as_str) {
+        Some("none") | None => version.file_info.get(LARGE_FILE_SHA1_KEY)?.as_str(),
+        Some(sha1) if sha1.starts_with(UNVERIFIED_PREFIX) => &sha1[UNVERIFIED_PREFIX.len()..],
+        Some(sha1) => sha1,
+    };
+
+    Some(Checksum::new(ChecksumAlgorithm::Sha1, digest))
+}
+
 fn new_object(bucket: &str, versions: FileVersions, prefix: &ObjectPath) -> StorageResult<Object> {
     let mut path = ObjectPath::new(&versions.latest().file_name)?;
     path.shift_part(bucket);
@@ -177,6 +369,52 @@ fn new_object(bucket: &str, versions: FileVersions, prefix: &ObjectPath) -> Stor
     Ok(Object::from(B2Object { path, versions }))
 }
 
+/// Finds the file with a B2 file id. Only uploaded files beneath the prefix
+/// are found.
+async fn find_by_id(client: B2API, prefix: ObjectPath, file_id: String) -> StorageResult<Object> {
+    let missing = || {
+        error::not_found(
+            ObjectPath::empty(),
+            Some(&format!("There is no file with the id '{}'.", file_id)),
+        )
+    };
+
+    let info = client
+        .b2_get_file_info(
+            ObjectPath::empty(),
+            GetFileInfoRequest {
+                file_id: file_id.clone(),
+            },
+        )
+        .await?;
+    if info.action != FileAction::Upload {
+        return Err(missing());
+    }
+
+    let request = ListBucketsRequest {
+        account_id: client.account_info().await?.account_id,
+        bucket_id: Some(info.bucket_id.clone()),
+        bucket_name: None,
+        bucket_types: Default::default(),
+    };
+    let mut buckets = client
+        .b2_list_buckets(ObjectPath::empty(), request)
+        .await?
+        .buckets;
+    if buckets.len() != 1 {
+        return Err(missing());
+    }
+    let bucket = buckets.remove(0);
+
+    let mut path = ObjectPath::new(&info.file_name)?;
+    path.shift_part(&bucket.bucket_name);
+    if !path.parts().starts_with(&prefix.parts()) {
+        return Err(missing());
+    }
+
+    new_object(&bucket.bucket_name, FileVersions::new(vec![info]), &prefix)
+}
+
 #[derive(Clone, Debug)]
 struct B2Settings {
     key_id: String,
@@ -184,28 +422,38 @@ struct B2Settings {
     host: String,
     prefix: ObjectPath,
     max_small_file_size: u64,
+    max_parts_in_flight: usize,
+    list_page_size: usize,
     user_agent: String,
+    directory_markers: bool,
+    soft_delete: bool,
+    retry: RetryPolicy,
 }
 
 struct PartData {
-    data: Vec<Data>,
+    body: UploadBody,
     length: u64,
     hash: String,
 }
 
+/// Where the data for an upload comes from.
+enum UploadData {
+    Stream(DataStream),
+    Source(Arc<dyn UploadSource>),
+}
+
 async fn part_upload(
     client: B2API,
     path: ObjectPath,
     file_id: String,
     part: usize,
     part_data: PartData,
-    mut sender: Sender<Result<(), (usize, StorageError)>>,
+    mut sender: Sender<Result<usize, (usize, StorageError)>>,
 ) {
     trace!(
-        "Starting large file part upload to {} with {} bytes in {} chunks.",
+        "Starting large file part upload to {} with {} bytes.",
         path,
-        part_data.length,
-        part_data.data.len()
+        part_data.length
     );
 
     let part_url = match client
@@ -214,7 +462,8 @@ async fn part_upload(
     {
         Ok(p) => p,
         Err(e) => {
-            return sender.send(Err((part, e))).await.unwrap();
+            let _ = sender.send(Err((part, e))).await;
+            return;
         }
     };
 
@@ -225,46 +474,252 @@ async fn part_upload(
             part,
             part_data.length,
             part_data.hash,
-            part_data.data,
+            part_data.body,
         )
         .await
     {
-        return sender.send(Err((part, e))).await.unwrap();
+        let _ = sender.send(Err((part, e))).await;
+        return;
     }
 
-    sender.send(Ok(())).await.unwrap();
+    // The upload may have been abandoned, in which case nobody is listening.
+    let _ = sender.send(Ok(part)).await;
+}
+
+/// Waits for a part upload to complete, returning its part number.
+async fn wait_for_part(
+    receiver: &mut Receiver<Result<usize, (usize, StorageError)>>,
+    path: &ObjectPath,
+) -> Result<Option<usize>, TransferError> {
+    match receiver.next().await {
+        Some(Err((part_number, e))) => {
+            error!(
+                "Part {} of large file upload to {} failed: {}",
+                part_number, path, e
+            );
+            Err(TransferError::TargetError(e))
+        }
+        Some(Ok(part_number)) => Ok(Some(part_number)),
+        None => Ok(None),
+    }
 }
 
-async fn large_upload<S>(
+/// Uploads the parts of a started large file, with at most
+/// `max_parts_in_flight` parts uploading at once.
+struct PartUploader {
     client: B2API,
-    recommended_part_size: u64,
-    info: UploadInfo,
-    bucket_id: String,
-    file_name: String,
-    first_part: PartData,
-    mut stream: Pin<Box<S>>,
-) -> Result<(), TransferError>
-where
-    S: Stream<Item = StorageResult<Data>> + Send + 'static,
-{
-    trace!("Starting large file upload to {}.", info.path);
-    let mut part_count: usize = 1;
-    let (sender, mut receiver) = channel::<Result<(), (usize, StorageError)>>(0);
+    path: ObjectPath,
+    file_id: String,
+    max_parts_in_flight: usize,
+    in_flight: usize,
+    hashes: Vec<String>,
+    lengths: Vec<u64>,
+    progress: Option<ProgressListener>,
+    byte_total: Option<u64>,
+    bytes_complete: u64,
+    sender: Sender<Result<usize, (usize, StorageError)>>,
+    receiver: Receiver<Result<usize, (usize, StorageError)>>,
+}
+
+impl PartUploader {
+    fn new(
+        client: B2API,
+        path: ObjectPath,
+        file_id: String,
+        max_parts_in_flight: usize,
+        progress: Option<ProgressListener>,
+    ) -> PartUploader {
+        let (sender, receiver) = channel::<Result<usize, (usize, StorageError)>>(0);
+        PartUploader {
+            client,
+            path,
+            file_id,
+            max_parts_in_flight: max_parts_in_flight.max(1),
+            in_flight: 0,
+            hashes: Default::default(),
+            lengths: Default::default(),
+            progress,
+            byte_total: None,
+            bytes_complete: 0,
+            sender,
+            receiver,
+        }
+    }
+
+    /// Also reports the bytes uploaded as parts complete. Stream uploads are
+    /// already reported as the stream is read.
+    fn report_bytes(mut self, total: u64) -> PartUploader {
+        self.byte_total = Some(total);
+        self
+    }
+
+    /// The number of parts started or skipped so far.
+    fn part_count(&self) -> usize {
+        self.hashes.len()
+    }
+
+    /// Records a part that was already uploaded.
+    fn skip(&mut self, hash: String, length: u64) {
+        self.hashes.push(hash);
+        self.lengths.push(length);
+        self.bytes_complete += length;
+    }
 
-    let mut file_info = UserFileInfo::new();
-    if let Some(time) = info.modified {
+    /// Waits for a part to complete and reports it.
+    async fn wait(&mut self) -> Result<(), TransferError> {
+        let part = wait_for_part(&mut self.receiver, &self.path).await?;
+        self.in_flight -= 1;
+
+        if let (Some(listener), Some(part)) = (self.progress.as_ref(), part) {
+            listener.report(ProgressEvent::PartComplete { part });
+
+            if let Some(total) = self.byte_total {
+                self.bytes_complete += self.lengths[part - 1];
+                listener.report(ProgressEvent::Transferred {
+                    bytes: self.bytes_complete,
+                    total: Some(total),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Waits until there is room for another part to start uploading.
+    async fn make_room(&mut self) -> Result<(), TransferError> {
+        if self.in_flight >= self.max_parts_in_flight {
+            self.wait().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts uploading the next part once there is room for it.
+    async fn start(&mut self, part_data: PartData) -> Result<(), TransferError> {
+        self.make_room().await?;
+
+        self.in_flight += 1;
+        self.hashes.push(part_data.hash.clone());
+        self.lengths.push(part_data.length);
+        spawn(part_upload(
+            self.client.clone(),
+            self.path.clone(),
+            self.file_id.clone(),
+            self.hashes.len(),
+            part_data,
+            self.sender.clone(),
+        ));
+
+        Ok(())
+    }
+
+    /// Waits for the started parts to complete and then finishes the file.
+    async fn finish(mut self) -> Result<FileInfo, TransferError> {
+        trace!(
+            "All parts ({}) started for large file upload to {}, waiting for completion.",
+            self.hashes.len(),
+            self.path
+        );
+        while self.in_flight > 0 {
+            self.wait().await?;
+        }
+
+        trace!(
+            "All parts ({}) for large file upload to {} are complete.",
+            self.hashes.len(),
+            self.path
+        );
+
+        self.client
+            .b2_finish_large_file(
+                self.path,
+                FinishLargeFileRequest {
+                    file_id: self.file_id,
+                    part_sha1_array: self.hashes,
+                },
+            )
+            .await
+            .map_err(TransferError::TargetError)
+    }
+}
+
+/// B2 file names are at most 1024 bytes, including the part of the prefix
+/// beneath the bucket, and cannot contain control characters, backslashes or
+/// `//`.
+fn path_policy(prefix: &ObjectPath) -> PathPolicy {
+    let mut name = prefix.clone();
+    let container_parts = match name.unshift_part() {
+        Some(_) => 0,
+        None => 1,
+    };
+    let used = match name.to_string().len() {
+        0 => 0,
+        length => length + 1,
+    };
+
+    let policy = PathPolicy::new()
+        .max_length(MAX_FILE_NAME_LENGTH.saturating_sub(used))
+        .container_parts(container_parts)
+        .disallow_characters(&['\\'])
+        .disallow_control_characters()
+        .disallow_empty_parts();
+    #[cfg(feature = "lock")]
+    let policy = crate::lock::reserve_lock_directory(policy);
+    policy
+}
+
+fn is_not_found(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => true,
+        _ => false,
+    }
+}
+
+/// Whether a file info key is one used by this crate rather than user metadata.
+fn is_internal_info(key: &str) -> bool {
+    key == LAST_MODIFIED_KEY || key == IDEMPOTENCY_KEY || key == LARGE_FILE_SHA1_KEY
+}
+
+/// The content type to send with an upload.
+fn content_type(info: &UploadInfo) -> String {
+    info.content_type
+        .clone()
+        .unwrap_or_else(|| AUTO_CONTENT_TYPE.to_owned())
+}
+
+/// The file info stored with an upload.
+fn user_file_info(info: &UploadInfo) -> UserFileInfo {
+    let mut user_info: UserFileInfo = info.metadata.clone();
+    if let Some(time) = info.modified.as_ref() {
         if let Ok(duration) = time.duration_since(UNIX_EPOCH) {
-            file_info.insert(
+            user_info.insert(
                 LAST_MODIFIED_KEY.to_owned(),
                 duration.as_millis().to_string(),
             );
         }
     }
 
+    if let Some(ref key) = info.idempotency_key {
+        user_info.insert(IDEMPOTENCY_KEY.to_owned(), key.to_owned());
+    }
+
+    user_info
+}
+
+/// Starts a large file upload, returning its file id.
+async fn start_large_file(
+    client: &B2API,
+    info: &UploadInfo,
+    bucket_id: String,
+    file_name: String,
+) -> Result<String, TransferError> {
+    trace!("Starting large file upload to {}.", info.path);
+    let file_info = user_file_info(info);
+
     let request = StartLargeFileRequest {
         bucket_id,
         file_name,
-        content_type: String::from("b2/x-auto"),
+        content_type: content_type(info),
         file_info: Some(file_info),
     };
 
@@ -273,126 +728,232 @@ where
         .await
         .map_err(TransferError::TargetError)?;
 
-    let file_id = match result.file_id {
-        Some(s) => s,
-        None => {
-            return Err(TransferError::TargetError(error::invalid_data(Some(
-                "Attempt to request large file upload failed.",
-            ))))
-        }
-    };
+    match result.file_id {
+        Some(s) => Ok(s),
+        None => Err(TransferError::TargetError(error::invalid_data(Some(
+            "Attempt to request large file upload failed.",
+        )))),
+    }
+}
 
-    let mut hashes = vec![first_part.hash.clone()];
+/// Starts a large file and uses `upload_parts` to upload its parts, cancelling
+/// the file if that fails and cleanup was requested.
+async fn large_upload<F, R>(
+    client: B2API,
+    info: UploadInfo,
+    bucket_id: String,
+    file_name: String,
+    upload_parts: F,
+) -> Result<FileInfo, TransferError>
+where
+    F: FnOnce(String) -> R,
+    R: Future<Output = Result<FileInfo, TransferError>>,
+{
+    let file_id = start_large_file(&client, &info, bucket_id, file_name).await?;
 
-    spawn(part_upload(
-        client.clone(),
-        info.path.clone(),
-        file_id.clone(),
-        part_count,
-        first_part,
-        sender.clone(),
-    ));
+    let result = upload_parts(file_id.clone()).await;
 
+    if result.is_err() && info.cleans_up() {
+        trace!("Cancelling failed large file upload to {}.", info.path);
+        if let Err(e) = client
+            .b2_cancel_large_file(info.path.clone(), CancelLargeFileRequest { file_id })
+            .await
+        {
+            warn!("Failed to cancel large file upload to {}: {}", info.path, e);
+        }
+    }
+
+    result
+}
+
+/// Reads the parts of a large file from a stream, hashing each part as it is
+/// read, and passes them to `parts` to be uploaded. Reading waits while a part
+/// is already waiting to start uploading.
+async fn read_stream_parts<S>(
+    part_size: u64,
+    mut stream: Pin<Box<S>>,
+    mut parts: Sender<PartData>,
+) -> Result<(), TransferError>
+where
+    S: Stream<Item = StorageResult<Data>> + Send + 'static,
+{
     let mut hasher = Sha1::new();
     let mut length: u64 = 0;
     let mut buffers: Vec<Data> = Default::default();
 
     loop {
-        match stream.next().await {
-            Some(Ok(data)) => {
-                length += data.len() as u64;
-                hasher.update(&data);
-                buffers.push(data);
+        let next = stream
+            .next()
+            .await
+            .transpose()
+            .map_err(TransferError::SourceError)?;
+        let finished = next.is_none();
+
+        if let Some(data) = next {
+            length += data.len() as u64;
+            hasher.input(&data);
+            buffers.push(data);
+        }
 
-                if length > recommended_part_size {
-                    // Start part upload.
-                    part_count += 1;
-
-                    let hash = hasher.hexdigest();
-                    hashes.push(hash.clone());
-                    spawn(part_upload(
-                        client.clone(),
-                        info.path.clone(),
-                        file_id.clone(),
-                        part_count,
-                        PartData {
-                            data: buffers.drain(..).collect(),
-                            length,
-                            hash,
-                        },
-                        sender.clone(),
-                    ));
-
-                    hasher = Sha1::new();
-                    length = 0;
-                }
-            }
-            Some(Err(e)) => return Err(TransferError::SourceError(e)),
-            None => {
-                // Got all data, finish uploads.
-                if length > 0 {
-                    // Start part upload.
-                    part_count += 1;
-
-                    let hash = hasher.hexdigest();
-                    hashes.push(hash.clone());
-                    spawn(part_upload(
-                        client.clone(),
-                        info.path.clone(),
-                        file_id.clone(),
-                        part_count,
-                        PartData {
-                            data: buffers.drain(..).collect(),
-                            length,
-                            hash,
-                        },
-                        sender.clone(),
-                    ));
-                }
+        if length > part_size || (finished && length > 0) {
+            let part = PartData {
+                body: UploadBody::Buffered(BufferedSource::new(mem::replace(
+                    &mut buffers,
+                    Vec::new(),
+                ))),
+                length,
+                hash: to_hex(&hasher.result_reset()),
+            };
+            length = 0;
 
-                break;
+            // Wait for the part to start uploading before reading the next.
+            let sent = match parts.send(part).await {
+                Ok(()) => poll_fn(|cx| parts.poll_ready(cx)).await,
+                Err(e) => Err(e),
+            };
+            if sent.is_err() {
+                // The uploads failed, the error is reported there.
+                return Ok(());
             }
         }
+
+        if finished {
+            return Ok(());
+        }
     }
+}
 
-    trace!(
-        "All parts ({}) started for large file upload to {}, waiting for completion.",
-        part_count,
-        info.path
-    );
-    // Wait for parts to finish uploading.
-    while part_count > 0 {
-        match receiver.next().await {
-            Some(Ok(())) => part_count -= 1,
-            Some(Err((part_number, e))) => {
-                error!(
-                    "Part {} of large file upload to {} failed: {}",
-                    part_number, info.path, e
-                );
-                return Err(TransferError::TargetError(e));
+/// Uploads the parts of a large file from a stream.
+///
+/// Reading and uploading are pipelined. The next part is read and hashed
+/// while earlier parts upload and once that part is ready reading waits until
+/// there is room for it to start uploading.
+async fn upload_stream_parts<S>(
+    mut uploader: PartUploader,
+    recommended_part_size: u64,
+    first_part: PartData,
+    stream: Pin<Box<S>>,
+) -> Result<FileInfo, TransferError>
+where
+    S: Stream<Item = StorageResult<Data>> + Send + 'static,
+{
+    let (sender, mut receiver) = channel::<PartData>(0);
+
+    let uploads = async move {
+        uploader.start(first_part).await?;
+
+        loop {
+            uploader.make_room().await?;
+            match receiver.next().await {
+                Some(part) => uploader.start(part).await?,
+                None => return Ok::<PartUploader, TransferError>(uploader),
             }
-            None => break,
         }
+    };
+
+    let (_, uploader) = try_join(
+        read_stream_parts(recommended_part_size, stream, sender),
+        uploads,
+    )
+    .await?;
+
+    uploader.finish().await
+}
+
+/// Uploads the parts of a large file from an upload source. Parts are read
+/// from the source once to hash them and again for each attempt at uploading
+/// them so nothing is held in memory. Parts in `existing` that were already
+/// uploaded with the same hash are skipped.
+async fn upload_source_parts(
+    mut uploader: PartUploader,
+    part_size: u64,
+    source: Arc<dyn UploadSource>,
+    size: u64,
+    existing: HashMap<usize, String>,
+) -> Result<FileInfo, TransferError> {
+    let mut offset: u64 = 0;
+    while offset < size {
+        let length = part_size.min(size - offset);
+        let hash = hash_range(&source, offset, length)
+            .await
+            .map_err(TransferError::SourceError)?;
+
+        if existing.get(&(uploader.part_count() + 1)) == Some(&hash) {
+            uploader.skip(hash, length);
+            offset += length;
+            continue;
+        }
+
+        uploader
+            .start(PartData {
+                body: UploadBody::Source(source.clone(), offset),
+                length,
+                hash,
+            })
+            .await?;
+
+        offset += length;
     }
 
-    trace!(
-        "All parts ({}) for large file upload to {} are complete.",
-        hashes.len(),
-        info.path
-    );
+    uploader.finish().await
+}
 
-    client
-        .b2_finish_large_file(
-            info.path,
-            FinishLargeFileRequest {
-                file_id,
-                part_sha1_array: hashes,
-            },
-        )
-        .await
-        .map_err(TransferError::TargetError)?;
+/// Lists the parts already uploaded for a large file, mapping part numbers to
+/// their hashes.
+async fn list_parts(
+    client: &B2API,
+    path: &ObjectPath,
+    file_id: &str,
+) -> StorageResult<HashMap<usize, String>> {
+    let mut parts = HashMap::new();
+    let mut start_part_number = None;
+
+    loop {
+        let response = client
+            .b2_list_parts(
+                path.clone(),
+                ListPartsRequest {
+                    file_id: file_id.to_owned(),
+                    start_part_number,
+                    max_part_count: None,
+                },
+            )
+            .await?;
 
-    Ok(())
+        for part in response.parts {
+            parts.insert(part.part_number, part.content_sha1);
+        }
+
+        match response.next_part_number {
+            Some(number) => start_part_number = Some(number),
+            None => return Ok(parts),
+        }
+    }
+}
+
+/// Reads a range of an upload source to find its SHA1 hash.
+async fn hash_range(
+    source: &Arc<dyn UploadSource>,
+    offset: u64,
+    length: u64,
+) -> StorageResult<String> {
+    let mut stream = source.read_range(offset, length).await?;
+    let mut hasher = Sha1::new();
+    let mut read: u64 = 0;
+
+    while let Some(data) = stream.next().await {
+        let data = data?;
+        read += data.len() as u64;
+        hasher.input(&data);
+    }
+
+    if read != length {
+        return Err(error::invalid_data(Some(
+            "The upload source returned less data than its size.",
+        )));
+    }
+
+    Ok(to_hex(&hasher.result()))
 }
 
 async fn small_upload(
@@ -401,57 +962,59 @@ async fn small_upload(
     bucket_id: String,
     file_name: String,
     part_data: PartData,
-) -> StorageResult<()> {
+) -> StorageResult<FileInfo> {
     trace!(
-        "Starting regular file upload to {} with {} bytes in {} chunks.",
+        "Starting regular file upload to {} with {} bytes.",
         info.path,
-        part_data.length,
-        part_data.data.len()
+        part_data.length
     );
     let response = client
         .clone()
         .b2_get_upload_url(info.path.clone(), GetUploadUrlRequest { bucket_id })
         .await?;
 
-    let mut user_info = UserFileInfo::new();
-    if let Some(time) = info.modified.as_ref() {
-        if let Ok(duration) = time.duration_since(UNIX_EPOCH) {
-            user_info.insert(
-                LAST_MODIFIED_KEY.to_owned(),
-                duration.as_millis().to_string(),
-            );
-        }
-    }
+    let user_info = user_file_info(&info);
+    let mime = content_type(&info);
 
     client
         .b2_upload_file(
             info.path,
-            response.upload_url,
-            response.authorization_token,
+            response,
             file_name,
-            String::from("b2/x-auto"),
+            mime,
             user_info,
             part_data.length,
             part_data.hash,
-            part_data.data,
+            part_data.body,
         )
-        .await?;
-
-    Ok(())
+        .await
 }
 
-async fn perform_upload<S>(
+async fn perform_upload(
     client: B2API,
     mut max_small_file_size: u64,
+    max_parts_in_flight: usize,
     info: UploadInfo,
     bucket_id: String,
     file_name: String,
-    stream: S,
-) -> Result<(), TransferError>
-where
-    S: Stream<Item = StorageResult<Data>> + Send + 'static,
-{
+    data: UploadData,
+) -> Result<FileInfo, TransferError> {
     trace!("Starting file upload to {}", info.path);
+    if let Some(ref key) = info.idempotency_key {
+        let existing = client
+            .clone()
+            .find_keyed_upload(info.path.clone(), bucket_id.clone(), file_name.clone(), key)
+            .await
+            .map_err(TransferError::TargetError)?;
+        if let Some(file) = existing {
+            trace!(
+                "Skipping upload to {}, the current version has the same key.",
+                info.path
+            );
+            return Ok(file);
+        }
+    }
+
     let session = client
         .account_info()
         .await
@@ -460,6 +1023,50 @@ where
         max_small_file_size = session.absolute_minimum_part_size
     }
 
+    match data {
+        UploadData::Stream(stream) => {
+            stream_upload(
+                client,
+                max_small_file_size,
+                session.recommended_part_size,
+                max_parts_in_flight,
+                info,
+                bucket_id,
+                file_name,
+                stream,
+            )
+            .await
+        }
+        UploadData::Source(source) => {
+            source_upload(
+                client,
+                max_small_file_size,
+                session.recommended_part_size,
+                max_parts_in_flight,
+                info,
+                bucket_id,
+                file_name,
+                source,
+            )
+            .await
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn stream_upload<S>(
+    client: B2API,
+    max_small_file_size: u64,
+    recommended_part_size: u64,
+    max_parts_in_flight: usize,
+    info: UploadInfo,
+    bucket_id: String,
+    file_name: String,
+    stream: S,
+) -> Result<FileInfo, TransferError>
+where
+    S: Stream<Item = StorageResult<Data>> + Send + 'static,
+{
     let mut hasher = Sha1::new();
     let mut length: u64 = 0;
     let mut buffers: Vec<Data> = Default::default();
@@ -469,24 +1076,34 @@ where
         match stream.next().await {
             Some(Ok(data)) => {
                 length += data.len() as u64;
-                hasher.update(&data);
+                hasher.input(&data);
                 buffers.push(data);
 
                 if length > max_small_file_size {
                     // Start large file upload.
-                    return large_upload(
-                        client,
-                        session.recommended_part_size,
-                        info,
-                        bucket_id,
-                        file_name,
-                        PartData {
-                            data: buffers,
-                            length,
-                            hash: hasher.hexdigest(),
-                        },
-                        stream,
-                    )
+                    let first_part = PartData {
+                        body: UploadBody::Buffered(BufferedSource::new(buffers)),
+                        length,
+                        hash: to_hex(&hasher.result_reset()),
+                    };
+                    let uploader_client = client.clone();
+                    let path = info.path.clone();
+                    let progress = info.progress.clone();
+
+                    return large_upload(client, info, bucket_id, file_name, move |file_id| {
+                        upload_stream_parts(
+                            PartUploader::new(
+                                uploader_client,
+                                path,
+                                file_id,
+                                max_parts_in_flight,
+                                progress,
+                            ),
+                            recommended_part_size,
+                            first_part,
+                            stream,
+                        )
+                    })
                     .await;
                 }
             }
@@ -499,9 +1116,9 @@ where
                     bucket_id,
                     file_name,
                     PartData {
-                        data: buffers,
+                        body: UploadBody::Buffered(BufferedSource::new(buffers)),
                         length,
-                        hash: hasher.hexdigest(),
+                        hash: to_hex(&hasher.result_reset()),
                     },
                 )
                 .await
@@ -511,6 +1128,213 @@ where
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn source_upload(
+    client: B2API,
+    max_small_file_size: u64,
+    recommended_part_size: u64,
+    max_parts_in_flight: usize,
+    info: UploadInfo,
+    bucket_id: String,
+    file_name: String,
+    source: Arc<dyn UploadSource>,
+) -> Result<FileInfo, TransferError> {
+    let size = source.size().await.map_err(TransferError::SourceError)?;
+
+    if size <= max_small_file_size {
+        let hash = hash_range(&source, 0, size)
+            .await
+            .map_err(TransferError::SourceError)?;
+
+        let progress = info.progress.clone();
+        let file_info = small_upload(
+            client,
+            info,
+            bucket_id,
+            file_name,
+            PartData {
+                body: UploadBody::Source(source, 0),
+                length: size,
+                hash,
+            },
+        )
+        .await
+        .map_err(TransferError::TargetError)?;
+
+        if let Some(listener) = progress {
+            listener.report(ProgressEvent::Transferred {
+                bytes: size,
+                total: Some(size),
+            });
+        }
+
+        return Ok(file_info);
+    }
+
+    let uploader_client = client.clone();
+    let path = info.path.clone();
+    let progress = info.progress.clone();
+    large_upload(client, info, bucket_id, file_name, move |file_id| {
+        upload_source_parts(
+            PartUploader::new(
+                uploader_client,
+                path,
+                file_id,
+                max_parts_in_flight,
+                progress,
+            )
+            .report_bytes(size),
+            recommended_part_size,
+            source,
+            size,
+            HashMap::new(),
+        )
+    })
+    .await
+}
+
+/// Copies a file within B2 without transferring its data. Files up to the
+/// small file size are copied with a single request, larger files are copied
+/// in parts of that size.
+async fn server_copy(
+    client: B2API,
+    mut max_small_file_size: u64,
+    source: B2Object,
+    info: UploadInfo,
+    bucket_id: String,
+    file_name: String,
+) -> Result<FileInfo, TransferError> {
+    let version = source.versions.latest();
+    let source_id = match version.file_id {
+        Some(ref id) => id.clone(),
+        None => {
+            return Err(TransferError::SourceError(error::internal_error(Some(
+                "Expected object to have a file id.",
+            ))));
+        }
+    };
+    let size = version.content_length;
+
+    trace!("Copying {} to {}.", source.path, info.path);
+    if let Some(ref key) = info.idempotency_key {
+        let existing = client
+            .clone()
+            .find_keyed_upload(info.path.clone(), bucket_id.clone(), file_name.clone(), key)
+            .await
+            .map_err(TransferError::TargetError)?;
+        if let Some(file) = existing {
+            trace!(
+                "Skipping copy to {}, the current version has the same key.",
+                info.path
+            );
+            return Ok(file);
+        }
+    }
+
+    let session = client
+        .account_info()
+        .await
+        .map_err(TransferError::TargetError)?;
+    if session.absolute_minimum_part_size > max_small_file_size {
+        max_small_file_size = session.absolute_minimum_part_size
+    }
+
+    if size <= max_small_file_size {
+        let progress = info.progress.clone();
+        let file_info = client
+            .b2_copy_file(
+                info.path.clone(),
+                CopyFileRequest {
+                    source_file_id: source_id,
+                    destination_bucket_id: Some(bucket_id),
+                    file_name,
+                    range: None,
+                    metadata_directive: Some(REPLACE_METADATA.to_owned()),
+                    content_type: Some(content_type(&info)),
+                    file_info: Some(user_file_info(&info)),
+                },
+            )
+            .await
+            .map_err(TransferError::TargetError)?;
+
+        if let Some(listener) = progress {
+            listener.report(ProgressEvent::Transferred {
+                bytes: size,
+                total: Some(size),
+            });
+        }
+
+        return Ok(file_info);
+    }
+
+    let parts_client = client.clone();
+    let path = info.path.clone();
+    let progress = info.progress.clone();
+    large_upload(client, info, bucket_id, file_name, move |file_id| {
+        copy_parts(
+            parts_client,
+            path,
+            source_id,
+            file_id,
+            max_small_file_size,
+            size,
+            progress,
+        )
+    })
+    .await
+}
+
+/// Copies a file into a started large file one part at a time.
+async fn copy_parts(
+    client: B2API,
+    path: ObjectPath,
+    source_id: String,
+    file_id: String,
+    part_size: u64,
+    size: u64,
+    progress: Option<ProgressListener>,
+) -> Result<FileInfo, TransferError> {
+    let mut hashes: Vec<String> = Vec::new();
+    let mut offset: u64 = 0;
+
+    while offset < size {
+        let length = part_size.min(size - offset);
+        let part = client
+            .b2_copy_part(
+                path.clone(),
+                CopyPartRequest {
+                    source_file_id: source_id.clone(),
+                    large_file_id: file_id.clone(),
+                    part_number: hashes.len() + 1,
+                    range: Some(format!("bytes={}-{}", offset, offset + length - 1)),
+                },
+            )
+            .await
+            .map_err(TransferError::TargetError)?;
+
+        hashes.push(part.content_sha1);
+        offset += length;
+
+        if let Some(ref listener) = progress {
+            listener.report(ProgressEvent::Transferred {
+                bytes: offset,
+                total: Some(size),
+            });
+        }
+    }
+
+    client
+        .b2_finish_large_file(
+            path,
+            FinishLargeFileRequest {
+                file_id,
+                part_sha1_array: hashes,
+            },
+        )
+        .await
+        .map_err(TransferError::TargetError)
+}
+
 trait ListRequestor<S>
 where
     S: Send + 'static,
@@ -602,13 +1426,19 @@ impl ListRequestor<ListFileVersionsResponse> for FileVersionsRequestor {
 }
 
 /// A stream of objects from B2.
+///
+/// A page of results is only requested once the previous page has been
+/// consumed so at most one page is held in memory. Listings only keep the
+/// latest version of each file, otherwise every version of the current file is
+/// held too.
 struct ListStream<R, S>
 where
     R: ListRequestor<S> + Unpin + Send + 'static,
     S: Send + 'static,
 {
+    all_versions: bool,
     current: Vec<FileInfo>,
-    results: Vec<FileInfo>,
+    results: VecDeque<FileInfo>,
     requestor: R,
     future: Option<Pin<Box<WrappedFuture<StorageResult<S>>>>>,
 }
@@ -618,24 +1448,34 @@ where
     R: ListRequestor<S> + Send + Unpin + 'static,
     S: Send + 'static,
 {
+    /// Lists every version of each file.
     fn new(requestor: R) -> ListStream<R, S> {
         ListStream {
             requestor,
+            all_versions: true,
             current: Vec::new(),
-            results: Vec::new(),
+            results: VecDeque::new(),
             future: None,
         }
     }
 
+    /// Lists only the latest version of each file.
+    fn latest(requestor: R) -> ListStream<R, S> {
+        ListStream {
+            all_versions: false,
+            ..ListStream::new(requestor)
+        }
+    }
+
     fn poll_next_info(&mut self, cx: &mut Context) -> ResultStreamPoll<FileInfo> {
         loop {
-            if !self.results.is_empty() {
-                return Poll::Ready(Some(Ok(self.results.remove(0))));
+            if let Some(info) = self.results.pop_front() {
+                return Poll::Ready(Some(Ok(info)));
             } else if let Some(ref mut fut) = self.future {
                 match fut.as_mut().poll(cx) {
                     Poll::Ready(Ok(response)) => {
                         self.future = None;
-                        self.results = self.requestor.take_response(response);
+                        self.results = self.requestor.take_response(response).into();
                     }
                     Poll::Ready(Err(e)) => {
                         self.future = None;
@@ -660,27 +1500,34 @@ where
     type Item = StorageResult<FileVersions>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> ResultStreamPoll<FileVersions> {
-        match self.poll_next_info(cx) {
-            Poll::Ready(Some(Ok(info))) => {
-                if self.current.is_empty() || self.current[0].file_name == info.file_name {
-                    self.current.push(info);
-                    self.poll_next(cx)
-                } else {
-                    let versions = FileVersions::new(self.current.drain(..).collect());
-                    self.current.push(info);
-                    Poll::Ready(Some(Ok(versions)))
+        loop {
+            match self.poll_next_info(cx) {
+                Poll::Ready(Some(Ok(info))) => {
+                    if self.current.is_empty() {
+                        self.current.push(info);
+                    } else if self.current[0].file_name == info.file_name {
+                        if self.all_versions {
+                            self.current.push(info);
+                        } else if info.upload_timestamp >= self.current[0].upload_timestamp {
+                            self.current[0] = info;
+                        }
+                    } else {
+                        let versions = FileVersions::new(self.current.drain(..).collect());
+                        self.current.push(info);
+                        return Poll::Ready(Some(Ok(versions)));
+                    }
                 }
-            }
-            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
-            Poll::Ready(None) => {
-                if !self.current.is_empty() {
-                    let versions = FileVersions::new(self.current.drain(..).collect());
-                    Poll::Ready(Some(Ok(versions)))
-                } else {
-                    Poll::Ready(None)
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    if !self.current.is_empty() {
+                        let versions = FileVersions::new(self.current.drain(..).collect());
+                        return Poll::Ready(Some(Ok(versions)));
+                    } else {
+                        return Poll::Ready(None);
+                    }
                 }
+                Poll::Pending => return Poll::Pending,
             }
-            Poll::Pending => Poll::Pending,
         }
     }
 }
@@ -689,6 +1536,13 @@ where
 #[derive(Debug, Clone)]
 pub struct B2Backend {
     state: B2APIState,
+    events: EventLog,
+    cache: ObjectCache,
+    directories: DirectorySemantics,
+    strict_overwrites: bool,
+    cleanup_on_failure: bool,
+    limiter: Option<ConcurrencyLimiter>,
+    paths: PathPolicy,
 }
 
 impl B2Backend {
@@ -710,14 +1564,26 @@ impl B2Backend {
                 host: B2_API_HOST.to_owned(),
                 prefix: ObjectPath::empty(),
                 max_small_file_size: DEFAULT_MAX_SMALL_FILE_SIZE,
+                max_parts_in_flight: DEFAULT_PARTS_IN_FLIGHT,
+                list_page_size: DEFAULT_LIST_PAGE_SIZE,
                 user_agent: format!(
                     "{}/{} ({})",
                     env!("CARGO_PKG_NAME"),
                     env!("CARGO_PKG_VERSION"),
                     env!("CARGO_PKG_REPOSITORY")
                 ),
+                directory_markers: false,
+                soft_delete: false,
+                retry: Default::default(),
             },
             max_requests: DEFAULT_REQUEST_LIMIT,
+            client: None,
+            tls: Default::default(),
+            proxy: Proxy::from_env(),
+            pool: Default::default(),
+            user_agent_suffix: None,
+            headers: HeaderMap::new(),
+            middleware: Vec::new(),
         }
     }
 
@@ -727,6 +1593,354 @@ impl B2Backend {
         B2API::new(&self.state)
     }
 
+    /// Starts a large file upload that can be resumed with
+    /// [`resume_upload`](#method.resume_upload).
+    pub(crate) fn begin_upload(&self, info: UploadInfo) -> UploadSessionFuture {
+        async fn begin(
+            client: B2API,
+            prefix: ObjectPath,
+            info: UploadInfo,
+        ) -> StorageResult<UploadSession> {
+            let (bucket, file) =
+                B2Backend::expand_path(client.clone(), prefix, info.path.clone()).await?;
+            let session = client.account_info().await?;
+
+            let file_id = start_large_file(&client, &info, bucket.bucket_id, file).await?;
+            Ok(UploadSession {
+                path: info.path.to_string(),
+                file_id,
+                part_size: session.recommended_part_size,
+            })
+        }
+
+        if info.path.is_dir_prefix() {
+            return UploadSessionFuture::from_value(Err(error::invalid_path(
+                info.path,
+                Some("Object paths cannot be empty or end with a '/' character."),
+            )));
+        }
+
+        UploadSessionFuture::from_future(begin(
+            self.client(),
+            self.state.settings.prefix.clone(),
+            info,
+        ))
+    }
+
+    /// Uploads the parts of a session's file that are missing or differ from
+    /// the source and then finishes the file.
+    pub(crate) fn resume_upload(
+        &self,
+        session: UploadSession,
+        source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        async fn resume(
+            client: B2API,
+            max_parts_in_flight: usize,
+            session: UploadSession,
+            source: Arc<dyn UploadSource>,
+        ) -> Result<(), TransferError> {
+            let path = ObjectPath::new(&session.path).map_err(TransferError::TargetError)?;
+            let size = source.size().await.map_err(TransferError::SourceError)?;
+            let existing = list_parts(&client, &path, &session.file_id)
+                .await
+                .map_err(TransferError::TargetError)?;
+            trace!(
+                "Resuming large file upload to {} with {} parts already uploaded.",
+                path,
+                existing.len()
+            );
+
+            let uploader =
+                PartUploader::new(client, path, session.file_id, max_parts_in_flight, None);
+            upload_source_parts(uploader, session.part_size, source, size, existing).await?;
+            Ok(())
+        }
+
+        WriteCompleteFuture::from_future(resume(
+            self.client(),
+            self.state.settings.max_parts_in_flight,
+            session,
+            source,
+        ))
+    }
+
+    /// Uploads a file, resolving to the object for the new version.
+    fn upload(&self, info: UploadInfo, data: UploadData) -> WrittenObjectFuture {
+        async fn upload(
+            client: B2API,
+            max_small_file_size: u64,
+            max_parts_in_flight: usize,
+            prefix: ObjectPath,
+            info: UploadInfo,
+            data: UploadData,
+        ) -> Result<Object, TransferError> {
+            let (bucket, file) =
+                B2Backend::expand_path(client.clone(), prefix.clone(), info.path.clone())
+                    .await
+                    .map_err(TransferError::SourceError)?;
+
+            let path = info.path.clone();
+            let file_info = perform_upload(
+                client,
+                max_small_file_size,
+                max_parts_in_flight,
+                info,
+                bucket.bucket_id,
+                file,
+                data,
+            )
+            .await?;
+
+            Ok(Object::from(B2Object {
+                path,
+                versions: FileVersions::new(vec![file_info]),
+            }))
+        }
+
+        let path = info.path.clone();
+        if path.is_dir_prefix() {
+            return WrittenObjectFuture::from_value(Err(TransferError::TargetError(
+                error::invalid_path(
+                    path,
+                    Some("Object paths cannot be empty or end with a '/' character."),
+                ),
+            )));
+        }
+
+        let backend = self.clone();
+        WrittenObjectFuture::from_future(async move {
+            if let Some(existing) = backend.clone().check_write_mode(&info).await? {
+                return Ok(existing);
+            }
+
+            upload(
+                backend.client(),
+                backend.state.settings.max_small_file_size,
+                backend.state.settings.max_parts_in_flight,
+                backend.state.settings.prefix.clone(),
+                info,
+                data,
+            )
+            .await
+        })
+    }
+
+    pub(crate) fn event_log(&self) -> &EventLog {
+        &self.events
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.state.metrics
+    }
+
+    pub(crate) fn object_cache(&self) -> &ObjectCache {
+        &self.cache
+    }
+
+    pub(crate) fn directory_semantics(&self) -> DirectorySemantics {
+        self.directories
+    }
+
+    pub(crate) fn set_directory_semantics(&mut self, semantics: DirectorySemantics) {
+        self.directories = semantics;
+    }
+
+    pub(crate) fn strict_overwrites(&self) -> bool {
+        self.strict_overwrites
+    }
+
+    pub(crate) fn set_strict_overwrites(&mut self, strict: bool) {
+        self.strict_overwrites = strict;
+    }
+
+    pub(crate) fn cleanup_on_failure(&self) -> bool {
+        self.cleanup_on_failure
+    }
+
+    pub(crate) fn set_cleanup_on_failure(&mut self, cleanup: bool) {
+        self.cleanup_on_failure = cleanup;
+    }
+
+    pub(crate) fn concurrency_limiter(&self) -> Option<&ConcurrencyLimiter> {
+        self.limiter.as_ref()
+    }
+
+    pub(crate) fn set_concurrency_limiter(&mut self, limiter: Option<ConcurrencyLimiter>) {
+        self.limiter = limiter;
+    }
+
+    pub(crate) fn path_policy(&self) -> &PathPolicy {
+        &self.paths
+    }
+
+    pub(crate) fn set_path_policy(&mut self, policy: PathPolicy) {
+        self.paths = policy;
+    }
+
+    /// Cancels large file uploads that were started longer ago than
+    /// `older_than` but never finished. Resolves to the paths of the files
+    /// whose uploads were cancelled.
+    ///
+    /// B2 keeps, and charges for, the parts of an unfinished upload until it
+    /// is cancelled. Uploads can be left unfinished when a write fails part
+    /// way through or the process writing is killed.
+    pub fn cleanup_incomplete_uploads(&self, older_than: Duration) -> PathListFuture {
+        PathListFuture::from_future(cleanup_incomplete_uploads(
+            self.client(),
+            self.state.settings.prefix.clone(),
+            older_than,
+        ))
+    }
+
+    /// Looks up a file by its B2 file id, see
+    /// [`B2Object::file_id`](struct.B2Object.html#method.file_id). The id
+    /// may belong to an older version of a file in which case that version is
+    /// returned. Files outside of this backend's prefix are not found.
+    pub fn get_object_by_id(&self, file_id: &str) -> ObjectFuture {
+        ObjectFuture::from_future(find_by_id(
+            self.client(),
+            self.state.settings.prefix.clone(),
+            file_id.to_owned(),
+        ))
+    }
+
+    /// Streams the content of the file with a B2 file id, even if the file
+    /// has since been replaced.
+    pub fn get_file_stream_by_id(&self, file_id: &str) -> DataStreamFuture {
+        let client = self.client();
+        let prefix = self.state.settings.prefix.clone();
+        let file_id = file_id.to_owned();
+
+        DataStreamFuture::from_future(async move {
+            let object = find_by_id(client.clone(), prefix, file_id.clone()).await?;
+            client
+                .b2_download_file_by_id(object.path(), file_id, None)
+                .await
+        })
+    }
+
+    /// Creates an authorization that allows anyone holding it to download the
+    /// files whose names start with `prefix` for `valid_duration`, which can
+    /// be at most a week. This lets web clients download files directly from
+    /// B2 for buckets that aren't public.
+    ///
+    /// The prefix is compared to file names as a string so a prefix of `dir`
+    /// also covers `directory/file`.
+    pub fn get_download_authorization(
+        &self,
+        prefix: ObjectPath,
+        valid_duration: Duration,
+    ) -> DownloadAuthorizationFuture {
+        async fn authorize(
+            client: B2API,
+            root: ObjectPath,
+            prefix: ObjectPath,
+            valid_duration: Duration,
+        ) -> StorageResult<DownloadAuthorization> {
+            let mut file_name_prefix = root.join(&prefix);
+            let bucket = match file_name_prefix.unshift_part() {
+                Some(b) => b,
+                None => {
+                    return Err(error::invalid_path(
+                        prefix,
+                        Some("Download authorizations must be within a bucket."),
+                    ))
+                }
+            };
+
+            let request = ListBucketsRequest {
+                account_id: client.account_info().await?.account_id,
+                bucket_id: None,
+                bucket_name: Some(bucket.clone()),
+                bucket_types: Default::default(),
+            };
+            let buckets = client
+                .b2_list_buckets(prefix.clone(), request)
+                .await?
+                .buckets;
+            if buckets.len() != 1 {
+                return Err(error::not_found(prefix, None));
+            }
+
+            let expires = SystemTime::now() + valid_duration;
+            let response = client
+                .b2_get_download_authorization(
+                    prefix,
+                    GetDownloadAuthorizationRequest {
+                        bucket_id: buckets[0].bucket_id.clone(),
+                        file_name_prefix: file_name_prefix.to_string(),
+                        valid_duration_in_seconds: valid_duration.as_secs(),
+                    },
+                )
+                .await?;
+
+            Ok(DownloadAuthorization {
+                root,
+                download_url: client.account_info().await?.download_url,
+                bucket,
+                file_name_prefix: response.file_name_prefix,
+                token: response.authorization_token,
+                expires,
+            })
+        }
+
+        if valid_duration.as_secs() < 1 || valid_duration > MAX_DOWNLOAD_AUTHORIZATION {
+            return DownloadAuthorizationFuture::from_value(Err(error::invalid_settings(Some(
+                "Download authorizations must last between a second and a week.",
+            ))));
+        }
+
+        DownloadAuthorizationFuture::from_future(authorize(
+            self.client(),
+            self.state.settings.prefix.clone(),
+            prefix,
+            valid_duration,
+        ))
+    }
+
+    /// Creates a URL that downloads the file at `path` until `expiry` has
+    /// passed, see
+    /// [`get_download_authorization`](#method.get_download_authorization).
+    pub fn presigned_url(&self, path: ObjectPath, expiry: Duration) -> StringFuture {
+        let authorization = self.get_download_authorization(path.clone(), expiry);
+
+        StringFuture::from_future(async move { authorization.await?.url(&path) })
+    }
+
+    /// Hides a file. A hidden file no longer appears in listings and cannot be
+    /// read but its earlier versions are kept, B2 then removes them according
+    /// to the bucket's lifecycle rules.
+    pub fn hide_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        async fn hide(backend: B2Backend, path: ObjectPath) -> StorageResult<()> {
+            let object = backend.clone().get_object(path.clone()).await?;
+            if object.object_type() != ObjectType::File {
+                return Err(error::invalid_path(path, Some("Only files can be hidden.")));
+            }
+
+            let client = backend.client();
+            let (bucket, file_name) = B2Backend::expand_path(
+                client.clone(),
+                backend.state.settings.prefix.clone(),
+                path.clone(),
+            )
+            .await?;
+
+            client
+                .b2_hide_file(
+                    path,
+                    HideFileRequest {
+                        bucket_id: bucket.bucket_id,
+                        file_name,
+                    },
+                )
+                .await?;
+            Ok(())
+        }
+
+        OperationCompleteFuture::from_future(hide(self.clone(), path))
+    }
+
     async fn expand_path(
         client: B2API,
         prefix: ObjectPath,
@@ -764,61 +1978,196 @@ impl B2Backend {
 pub struct B2BackendBuilder {
     settings: B2Settings,
     max_requests: usize,
+    client: Option<SharedHttpClient>,
+    tls: TlsSettings,
+    proxy: Option<Proxy>,
+    pool: PoolSettings,
+    user_agent_suffix: Option<String>,
+    headers: HeaderMap,
+    middleware: Vec<Arc<dyn Middleware>>,
 }
 
 impl B2BackendBuilder {
     /// Sets the API host for B2.
     ///
-    /// This is generally only used for testing purposes.
-    pub fn host(mut self, host: &str) -> B2BackendBuilder {
-        self.settings.host = host.to_owned();
+    /// This is generally only used for testing purposes.
+    pub fn host(mut self, host: &str) -> B2BackendBuilder {
+        self.settings.host = host.to_owned();
+        self
+    }
+
+    /// Sets a path prefix for this storage.
+    ///
+    /// Essentially sets the 'root directory' for this storage, any paths
+    /// requested will be joined with this with a `/` character in between, so
+    /// this can be either the name of a bucket or a bucket followed by some
+    /// directory parts within that bucket.
+    pub fn prefix(mut self, prefix: ObjectPath) -> B2BackendBuilder {
+        self.settings.prefix = prefix;
+        self
+    }
+
+    /// Sets the cutoff between normal file uploads and large file uploads.
+    ///
+    /// B2 allows for two different upload mechanisms, normal files and large
+    /// files. Normal files are uploaded in a single http request while
+    /// large files are uploading in multiple parts which can be uploaded in
+    /// parallel.
+    ///
+    /// This sets the desired cut-off between the different upload methods.
+    /// Copies within B2 use the same cut-off, larger files are copied in parts
+    /// of this size.
+    /// Trying to set this larger than the maximum size of normal files will
+    /// just use the maximum size of normal files. Trying to set this smaller
+    /// than the minimum size of large file parts will just use the minimum
+    /// size of large file parts.
+    pub fn limit_small_file_size(mut self, size: u64) -> B2BackendBuilder {
+        if size > TOTAL_MAX_SMALL_FILE_SIZE {
+            self.settings.max_small_file_size = TOTAL_MAX_SMALL_FILE_SIZE;
+        } else {
+            self.settings.max_small_file_size = size;
+        }
+        self
+    }
+
+    /// Limits the number of API requests that can be called in parallel.
+    ///
+    /// This also limits the number of parallel threads for downloads and
+    /// uploads.
+    pub fn limit_requests(mut self, requests: usize) -> B2BackendBuilder {
+        self.max_requests = requests;
+        self
+    }
+
+    /// Limits the number of parts of a large file that upload at once.
+    ///
+    /// While parts upload the next part is read from the stream and hashed,
+    /// no more is read until that part can start uploading. Each part in
+    /// flight holds its data in memory so this bounds the memory used by an
+    /// upload to roughly one more than this many times the part size. Parts
+    /// still count towards the [request limit](#method.limit_requests).
+    /// Defaults to 4.
+    pub fn limit_parts_in_flight(mut self, parts: usize) -> B2BackendBuilder {
+        self.settings.max_parts_in_flight = parts;
+        self
+    }
+
+    /// Sets how many files are requested in each page of a listing.
+    ///
+    /// A listing only requests the next page once the previous page has been
+    /// consumed and holds no more than one page in memory, so this bounds the
+    /// memory a listing uses however many files there are. B2 returns at most
+    /// 10000 files in a page. Defaults to 1000.
+    pub fn limit_list_page_size(mut self, size: usize) -> B2BackendBuilder {
+        self.settings.list_page_size = size.max(1).min(MAX_LIST_COUNT);
+        self
+    }
+
+    /// Writes an empty `.bzEmpty` file inside directories that are
+    /// [created](../../trait.StorageBackend.html#method.create_directory), the
+    /// same marker the B2 web interface uses for folders. Without markers
+    /// creating a directory does nothing until a file is written inside it.
+    /// Defaults to false.
+    pub fn directory_markers(mut self, markers: bool) -> B2BackendBuilder {
+        self.settings.directory_markers = markers;
+        self
+    }
+
+    /// Makes [deleting](../../trait.StorageBackend.html#method.delete_object)
+    /// a file hide it rather than deleting all of its versions, see
+    /// [`hide_object`](struct.B2Backend.html#method.hide_object). Buckets
+    /// with lifecycle rules can then clean up hidden files on their own
+    /// schedule. Defaults to false.
+    pub fn soft_delete(mut self, soft_delete: bool) -> B2BackendBuilder {
+        self.settings.soft_delete = soft_delete;
+        self
+    }
+
+    /// Sets the [`RetryPolicy`](../../struct.RetryPolicy.html) for requests
+    /// to B2.
+    ///
+    /// By default failures caused by rate limiting, server errors, expired
+    /// authorization and dropped connections are retried with the
+    /// [default policy](../../struct.RetryPolicy.html#method.new).
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> B2BackendBuilder {
+        self.settings.retry = policy;
+        self
+    }
+
+    /// Sets the User-Agent for all requests to B2.
+    pub fn user_agent(mut self, user_agent: &str) -> B2BackendBuilder {
+        self.settings.user_agent = user_agent.to_owned();
+        self
+    }
+
+    /// Sets the [`HttpClient`](../../http_client/trait.HttpClient.html) used
+    /// to send requests to B2.
+    ///
+    /// If not set the [default client](../../http_client/fn.default_client.html)
+    /// is used.
+    pub fn http_client<C>(mut self, client: C) -> B2BackendBuilder
+    where
+        C: HttpClient,
+    {
+        self.client = Some(Arc::new(client));
+        self
+    }
+
+    /// Sets the [`TlsSettings`](../../http_client/struct.TlsSettings.html)
+    /// used for connections to B2.
+    ///
+    /// These are only used by the default client, they are ignored if a
+    /// custom client is set with [`http_client`](#method.http_client).
+    pub fn tls(mut self, tls: TlsSettings) -> B2BackendBuilder {
+        self.tls = tls;
         self
     }
 
-    /// Sets a path prefix for this storage.
+    /// Sets the [`Proxy`](../../http_client/struct.Proxy.html) used for
+    /// connections to B2, `None` connects directly.
     ///
-    /// Essentially sets the 'root directory' for this storage, any paths
-    /// requested will be joined with this with a `/` character in between, so
-    /// this can be either the name of a bucket or a bucket followed by some
-    /// directory parts within that bucket.
-    pub fn prefix(mut self, prefix: ObjectPath) -> B2BackendBuilder {
-        self.settings.prefix = prefix;
+    /// Defaults to the proxy configured in the environment, see
+    /// [`Proxy::from_env`](../../http_client/struct.Proxy.html#method.from_env).
+    /// Like the TLS settings this is ignored if a custom client is set.
+    pub fn proxy(mut self, proxy: Option<Proxy>) -> B2BackendBuilder {
+        self.proxy = proxy;
         self
     }
 
-    /// Sets the cutoff between normal file uploads and large file uploads.
+    /// Sets the [`PoolSettings`](../../http_client/struct.PoolSettings.html)
+    /// for connections to B2.
     ///
-    /// B2 allows for two different upload mechanisms, normal files and large
-    /// files. Normal files are uploaded in a single http request while
-    /// large files are uploading in multiple parts which can be uploaded in
-    /// parallel.
+    /// Like the TLS settings these are ignored if a custom client is set.
+    pub fn pool(mut self, pool: PoolSettings) -> B2BackendBuilder {
+        self.pool = pool;
+        self
+    }
+
+    /// Appends to the `User-Agent` sent with every request to B2.
     ///
-    /// This sets the desired cut-off between the different upload methods.
-    /// Trying to set this larger than the maximum size of normal files will
-    /// just use the maximum size of normal files. Trying to set this smaller
-    /// than the minimum size of large file parts will just use the minimum
-    /// size of large file parts.
-    pub fn limit_small_file_size(mut self, size: u64) -> B2BackendBuilder {
-        if size > TOTAL_MAX_SMALL_FILE_SIZE {
-            self.settings.max_small_file_size = TOTAL_MAX_SMALL_FILE_SIZE;
-        } else {
-            self.settings.max_small_file_size = size;
-        }
+    /// Unlike the TLS settings this also applies to a custom client.
+    pub fn user_agent_suffix(mut self, suffix: &str) -> B2BackendBuilder {
+        self.user_agent_suffix = Some(suffix.to_owned());
         self
     }
 
-    /// Limits the number of API requests that can be called in parallel.
+    /// Sets a header sent with every request to B2, for example a tracing
+    /// header.
     ///
-    /// This also limits the number of parallel threads for downloads and
-    /// uploads.
-    pub fn limit_requests(mut self, requests: usize) -> B2BackendBuilder {
-        self.max_requests = requests;
+    /// Unlike the TLS settings this also applies to a custom client.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> B2BackendBuilder {
+        self.headers.insert(name, value);
         self
     }
 
-    /// Sets the User-Agent for all requests to B2.
-    pub fn user_agent(mut self, user_agent: &str) -> B2BackendBuilder {
-        self.settings.user_agent = user_agent.to_owned();
+    /// Adds [`Middleware`](../../http_client/trait.Middleware.html) that sees
+    /// every request to B2 and its response.
+    ///
+    /// Middleware is called in the order it is added and sees requests after
+    /// any extra [headers](#method.header) have been added. Unlike the TLS
+    /// settings this also applies to a custom client.
+    pub fn middleware<M: Middleware>(mut self, middleware: M) -> B2BackendBuilder {
+        self.middleware.push(Arc::new(middleware));
         self
     }
 
@@ -827,36 +2176,46 @@ impl B2BackendBuilder {
     pub fn connect(self) -> ConnectFuture {
         ConnectFuture::from_future(async {
             trace!("Connecting to B2 with settings {:?}", self.settings);
-            let connector = match HttpsConnector::new() {
-                Ok(c) => c,
-                Err(e) => {
-                    return Err(error::connection_failed(Some(&format!(
-                        "Could not create http connection: {}.",
-                        e
-                    ))))
-                }
+            let client = match self.client {
+                Some(c) => c,
+                None => default_client(&self.tls, self.proxy.clone(), &self.pool)?,
             };
+            let client = MiddlewareClient::wrap(client, &self.middleware);
+            let client = HeadersClient::wrap(
+                client,
+                self.user_agent_suffix.as_ref().map(String::as_str),
+                &self.headers,
+            );
 
-            let client = HyperClient::builder().build(connector);
             let clients = ClientPool::new(client, Some(self.max_requests));
 
+            let metrics = Metrics::default();
             let auth_tokens = Pool::new(
-                (self.settings.clone(), clients.clone()),
+                (self.settings.clone(), clients.clone(), metrics.clone()),
                 Some(self.max_requests / 2),
-                |(settings, clients)| {
+                |(settings, clients, metrics)| {
                     WrappedFuture::<StorageResult<AuthorizeAccountResponse>>::from_future(
-                        B2Client::authorize(settings.clone(), clients.clone()),
+                        B2Client::authorize(settings.clone(), clients.clone(), metrics.clone()),
                     )
                 },
             );
 
+            let paths = path_policy(&self.settings.prefix);
             let backend = B2Backend {
                 state: B2APIState {
                     settings: self.settings,
                     next_id: Default::default(),
                     clients,
                     auth_tokens,
+                    metrics,
                 },
+                events: Default::default(),
+                cache: Default::default(),
+                directories: Default::default(),
+                strict_overwrites: false,
+                cleanup_on_failure: false,
+                limiter: None,
+                paths,
             };
 
             // Make sure we can connect.
@@ -868,15 +2227,108 @@ impl B2BackendBuilder {
     }
 }
 
+async fn cleanup_incomplete_uploads(
+    client: B2API,
+    backend_prefix: ObjectPath,
+    older_than: Duration,
+) -> StorageResult<Vec<ObjectPath>> {
+    let cutoff = match now().checked_sub(older_than) {
+        Some(time) => time,
+        None => return Ok(Vec::new()),
+    };
+    let cutoff = match cutoff.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as u64,
+        Err(_) => return Ok(Vec::new()),
+    };
+
+    let mut file_part = backend_prefix.clone();
+    let bucket = file_part.unshift_part();
+    let name_prefix = if file_part.is_empty() {
+        None
+    } else {
+        Some(format!("{}/", file_part))
+    };
+
+    let request = ListBucketsRequest {
+        account_id: client.account_info().await?.account_id,
+        bucket_id: None,
+        bucket_name: bucket.clone(),
+        bucket_types: Default::default(),
+    };
+    let path = ObjectPath::new(bucket.unwrap_or_else(String::new))?;
+    let buckets = client.b2_list_buckets(path, request).await?.buckets;
+
+    let mut cancelled = Vec::new();
+    for bucket in buckets {
+        let mut start_file_id = None;
+        loop {
+            let request = ListUnfinishedLargeFilesRequest {
+                bucket_id: bucket.bucket_id.clone(),
+                name_prefix: name_prefix.clone(),
+                start_file_id,
+                max_file_count: None,
+            };
+            let response = client
+                .b2_list_unfinished_large_files(backend_prefix.clone(), request)
+                .await?;
+
+            for file in response.files {
+                if file.upload_timestamp > cutoff {
+                    continue;
+                }
+
+                let file_id = match file.file_id {
+                    Some(id) => id,
+                    None => continue,
+                };
+
+                let mut path = ObjectPath::new(&file.file_name)?;
+                path.shift_part(&bucket.bucket_name);
+                for _ in backend_prefix.parts() {
+                    path.unshift_part();
+                }
+
+                trace!("Cancelling unfinished large file upload to {}.", path);
+                client
+                    .b2_cancel_large_file(path.clone(), CancelLargeFileRequest { file_id })
+                    .await?;
+                cancelled.push(path);
+            }
+
+            start_file_id = response.next_file_id;
+            if start_file_id.is_none() {
+                break;
+            }
+        }
+    }
+
+    Ok(cancelled)
+}
+
 async fn object_list(
     client: B2API,
     backend_prefix: ObjectPath,
     prefix: ObjectPath,
     delimiter: Option<String>,
+    page_size: usize,
+    options: ListOptions,
 ) -> StorageResult<ObjectStream> {
     let mut file_part = backend_prefix.join(&prefix);
     let bucket = file_part.unshift_part();
 
+    // B2 starts listing at `start_file_name` inclusively, the caller filters
+    // out the path itself.
+    let start = options.start_after_path().map(|after| {
+        let mut full = backend_prefix.join(after);
+        let bucket = full.unshift_part().unwrap_or_else(String::new);
+        (bucket, full.to_string())
+    });
+    let max_file_count = options
+        .max_result_count()
+        .map_or(page_size, |count| count.min(page_size))
+        .max(1)
+        .min(MAX_LIST_COUNT) as u64;
+
     let mut request = ListBucketsRequest {
         account_id: client.account_info().await?.account_id,
         bucket_id: None,
@@ -891,31 +2343,45 @@ async fn object_list(
 
     let bucket_name = bucket.unwrap_or_else(String::new);
     let path = ObjectPath::new(bucket_name.clone())?;
-    let listers = client
+    let mut buckets: Vec<Bucket> = client
         .b2_list_buckets(path, request)
         .await?
         .buckets
         .drain(..)
         .filter(|b| b.bucket_name.starts_with(&bucket_name))
+        .filter(|b| match start {
+            Some((ref start_bucket, _)) => b.bucket_name >= *start_bucket,
+            None => true,
+        })
+        .collect();
+    buckets.sort_by(|a, b| a.bucket_name.cmp(&b.bucket_name));
+
+    // Buckets are listed one after another and each bucket's lister is only
+    // created once the previous bucket has been exhausted.
+    let listers = iter(buckets)
         .map(move |b| {
+            let start_file_name = match start {
+                Some((ref start_bucket, ref file)) if *start_bucket == b.bucket_name => {
+                    Some(file.clone())
+                }
+                _ => None,
+            };
+
             let options = ListFileVersionsRequest {
                 bucket_id: b.bucket_id.clone(),
-                start_file_name: None,
+                start_file_name,
                 start_file_id: None,
-                max_file_count: None,
+                max_file_count: Some(max_file_count),
                 prefix: Some(file_part.to_string()),
                 delimiter: delimiter.clone(),
             };
 
             let requestor = FileVersionsRequestor::new(client.clone(), prefix.clone(), options);
             let temp_prefix = backend_prefix.clone();
-            ListStream::new(requestor)
+            ListStream::latest(requestor)
                 .and_then(move |i| ready(new_object(&b.bucket_name, i, &temp_prefix)))
         })
-        .fold(MergedStreams::new(), |mut m, s| {
-            m.push(s);
-            m
-        });
+        .flatten();
 
     Ok(ObjectStream::from_stream(listers))
 }
@@ -925,33 +2391,40 @@ impl StorageBackend for B2Backend {
         Backend::B2
     }
 
-    fn list_objects<P>(&self, prefix: P) -> ObjectStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
-        let prefix = match prefix.try_into() {
-            Ok(p) => p,
-            Err(e) => return ObjectStreamFuture::from_value(Err(e.into())),
-        };
-
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
         ObjectStreamFuture::from_future(object_list(
             self.client(),
             self.state.settings.prefix.clone(),
             prefix,
             None,
+            self.state.settings.list_page_size,
+            ListOptions::new(),
         ))
     }
 
-    fn list_directory<P>(&self, dir: P) -> ObjectStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
-        let mut path = match dir.try_into() {
-            Ok(p) => p,
-            Err(e) => return ObjectStreamFuture::from_value(Err(e.into())),
-        };
+    fn lists_in_order(&self) -> bool {
+        true
+    }
+
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        let listing = ObjectStreamFuture::from_future(object_list(
+            self.client(),
+            self.state.settings.prefix.clone(),
+            prefix.clone(),
+            None,
+            self.state.settings.list_page_size,
+            options.clone(),
+        ));
+
+        options.apply(prefix, listing)
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        let mut path = dir;
 
         if !path.is_empty() && !path.is_dir_prefix() {
             path.push_part("");
@@ -962,14 +2435,12 @@ impl StorageBackend for B2Backend {
             self.state.settings.prefix.clone(),
             path,
             Some(String::from("/")),
+            self.state.settings.list_page_size,
+            ListOptions::new(),
         ))
     }
 
-    fn get_object<P>(&self, path: P) -> ObjectFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
         async fn get(
             client: B2API,
             backend_prefix: ObjectPath,
@@ -1000,11 +2471,6 @@ impl StorageBackend for B2Backend {
             new_object(&bucket.bucket_name, files.remove(0), &backend_prefix)
         }
 
-        let path = match path.try_into() {
-            Ok(p) => p,
-            Err(e) => return ObjectFuture::from_value(Err(e.into())),
-        };
-
         if path.is_dir_prefix() {
             return ObjectFuture::from_value(Err(error::invalid_path(
                 path,
@@ -1017,16 +2483,38 @@ impl StorageBackend for B2Backend {
         ObjectFuture::from_future(get(client, prefix, path))
     }
 
-    fn get_file_stream<P>(&self, path: P) -> DataStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
-        let path = match path.try_into() {
-            Ok(p) => p,
-            Err(e) => return DataStreamFuture::from_value(Err(e.into())),
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        if path.is_dir_prefix() {
+            return DataStreamFuture::from_value(Err(error::invalid_path(
+                path,
+                Some("Object paths cannot be empty or end with a '/' character."),
+            )));
+        }
+
+        let mut file_name = self.state.settings.prefix.join(&path);
+        let bucket = match file_name.unshift_part() {
+            Some(b) => b,
+            _ => {
+                return DataStreamFuture::from_value(Err(error::invalid_path(
+                    path,
+                    Some("Object paths cannot be empty."),
+                )));
+            }
         };
 
+        let future =
+            self.client()
+                .b2_download_file_by_name(path, bucket, file_name.to_string(), None);
+
+        DataStreamFuture::from_future(future)
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
         if path.is_dir_prefix() {
             return DataStreamFuture::from_value(Err(error::invalid_path(
                 path,
@@ -1034,6 +2522,10 @@ impl StorageBackend for B2Backend {
             )));
         }
 
+        if length == Some(0) {
+            return DataStreamFuture::from_value(Ok(DataStream::from_stream(empty())));
+        }
+
         let mut file_name = self.state.settings.prefix.join(&path);
         let bucket = match file_name.unshift_part() {
             Some(b) => b,
@@ -1045,24 +2537,135 @@ impl StorageBackend for B2Backend {
             }
         };
 
-        let future = self
-            .client()
-            .b2_download_file_by_name(path, bucket, file_name.to_string())
-            .map_ok(|body| {
-                DataStream::from_stream(body.map(|result| match result {
-                    Ok(chunk) => Result::<Data, StorageError>::Ok(chunk.into_bytes()),
-                    Err(e) => Result::<Data, StorageError>::Err(e.into()),
-                }))
-            });
+        let future = self.client().b2_download_file_by_name(
+            path,
+            bucket,
+            file_name.to_string(),
+            Some((offset, length)),
+        );
 
         DataStreamFuture::from_future(future)
     }
 
-    fn delete_object<P>(&self, path: P) -> OperationCompleteFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        if !self.state.settings.directory_markers {
+            return OperationCompleteFuture::from_value(Ok(()));
+        }
+
+        let mut marker = path;
+        marker.push_part(DIRECTORY_MARKER);
+        let upload = self.write_file_from_stream(
+            UploadInfo {
+                path: marker,
+                ..Default::default()
+            },
+            DataStream::from_stream(empty()),
+        );
+
+        OperationCompleteFuture::from_future(upload.map_err(StorageError::from))
+    }
+
+    fn copy_file(&self, source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
+        async fn copy(
+            backend: B2Backend,
+            source: ObjectPath,
+            target: UploadInfo,
+        ) -> Result<(), TransferError> {
+            if backend.clone().check_write_mode(&target).await?.is_some() {
+                return Ok(());
+            }
+
+            let object = backend
+                .clone()
+                .get_object(source.clone())
+                .await
+                .map_err(TransferError::SourceError)?;
+            if object.object_type() != ObjectType::File {
+                return Err(TransferError::SourceError(error::not_found(
+                    source,
+                    Some("Only files can be copied."),
+                )));
+            }
+
+            let object: B2Object = match object.try_into() {
+                Ok(o) => o,
+                Err(_) => {
+                    return Err(TransferError::SourceError(error::internal_error(Some(
+                        "Failed to convert retrieved object to the expected type.",
+                    ))));
+                }
+            };
+
+            let client = backend.client();
+            let settings = &backend.state.settings;
+            let (bucket, file) = B2Backend::expand_path(
+                client.clone(),
+                settings.prefix.clone(),
+                target.path.clone(),
+            )
+            .await
+            .map_err(TransferError::TargetError)?;
+
+            server_copy(
+                client,
+                settings.max_small_file_size,
+                object,
+                target,
+                bucket.bucket_id,
+                file,
+            )
+            .await?;
+            Ok(())
+        }
+
+        if target.path.is_dir_prefix() {
+            return CopyCompleteFuture::from_value(Err(TransferError::TargetError(
+                error::invalid_path(
+                    target.path,
+                    Some("Object paths cannot be empty or end with a '/' character."),
+                ),
+            )));
+        }
+
+        CopyCompleteFuture::from_future(copy(self.clone(), source, target))
+    }
+
+    fn move_file(&self, source: ObjectPath, target: UploadInfo) -> MoveCompleteFuture {
+        async fn move_file(
+            backend: B2Backend,
+            source: ObjectPath,
+            target: UploadInfo,
+        ) -> Result<(), TransferError> {
+            // A skipped copy must leave the source in place too.
+            if backend.clone().check_write_mode(&target).await?.is_some() {
+                return Ok(());
+            }
+
+            if source == target.path {
+                return backend
+                    .get_object(source)
+                    .await
+                    .map(|_| ())
+                    .map_err(TransferError::SourceError);
+            }
+
+            backend
+                .copy_file(source.clone(), target.write_mode(WriteMode::Overwrite))
+                .await?;
+            backend
+                .delete_object(source)
+                .await
+                .map_err(TransferError::SourceError)
+        }
+
+        MoveCompleteFuture::from_future(move_file(self.clone(), source, target))
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        if self.state.settings.soft_delete {
+            return self.hide_object(path);
+        }
+
         async fn delete(backend: B2Backend, path: ObjectPath) -> StorageResult<()> {
             let object: B2Object = match backend.clone().get_object(path.clone()).await?.try_into()
             {
@@ -1077,7 +2680,7 @@ impl StorageBackend for B2Backend {
             for info in object.versions() {
                 match info.file_id {
                     Some(ref id) => {
-                        backend
+                        let result = backend
                             .client()
                             .b2_delete_file_version(
                                 path.clone(),
@@ -1086,7 +2689,15 @@ impl StorageBackend for B2Backend {
                                     file_id: id.to_owned(),
                                 },
                             )
-                            .await?;
+                            .await;
+
+                        // The version may already have been deleted by an
+                        // earlier attempt.
+                        match result {
+                            Ok(_) => (),
+                            Err(ref e) if is_not_found(e) => (),
+                            Err(e) => return Err(e),
+                        }
                     }
                     None => {
                         return Err(error::internal_error(Some(
@@ -1099,70 +2710,166 @@ impl StorageBackend for B2Backend {
             Ok(())
         }
 
-        let path = match path.try_into() {
-            Ok(p) => p,
-            Err(e) => return OperationCompleteFuture::from_value(Err(e.into())),
-        };
         OperationCompleteFuture::from_future(delete(self.clone(), path))
     }
 
-    fn write_file_from_stream<S, I, E, P>(&self, info: P, stream: S) -> WriteCompleteFuture
-    where
-        S: Stream<Item = Result<I, E>> + Send + 'static,
-        I: IntoBuf + 'static,
-        E: Into<StorageError> + 'static,
-        P: TryInto<UploadInfo>,
-        P::Error: Into<StorageError>,
-    {
-        async fn upload<S>(
-            client: B2API,
-            max_small_file_size: u64,
-            prefix: ObjectPath,
-            info: UploadInfo,
-            stream: S,
-        ) -> Result<(), TransferError>
-        where
-            S: Stream<Item = StorageResult<Data>> + Send + 'static,
-        {
-            let (bucket, file) =
-                B2Backend::expand_path(client.clone(), prefix.clone(), info.path.clone())
-                    .await
-                    .map_err(TransferError::SourceError)?;
+    fn write_file_from_stream(&self, info: UploadInfo, stream: DataStream) -> WriteCompleteFuture {
+        WriteCompleteFuture::from_future(
+            self.upload(info, UploadData::Stream(stream)).map_ok(|_| ()),
+        )
+    }
 
-            perform_upload(
-                client,
-                max_small_file_size,
-                info,
-                bucket.bucket_id,
-                file,
-                stream,
-            )
-            .await
+    fn write_file_from_stream_returning(
+        &self,
+        info: UploadInfo,
+        stream: DataStream,
+    ) -> WrittenObjectFuture {
+        self.upload(info, UploadData::Stream(stream))
+    }
+
+    fn write_file_from_source(
+        &self,
+        info: UploadInfo,
+        source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        WriteCompleteFuture::from_future(
+            self.upload(info, UploadData::Source(source)).map_ok(|_| ()),
+        )
+    }
+}
+
+fn to_version(path: &ObjectPath, info: &FileInfo, latest: bool) -> Option<ObjectVersion> {
+    Some(ObjectVersion {
+        id: info.file_id.clone()?,
+        path: path.clone(),
+        len: info.content_length,
+        modified: version_modified(info),
+        created: if info.upload_timestamp > 0 {
+            Some(UNIX_EPOCH + Duration::from_millis(info.upload_timestamp))
+        } else {
+            None
+        },
+        deleted: info.action == FileAction::Hide,
+        latest,
+        checksum: version_checksum(info),
+    })
+}
+
+impl B2Backend {
+    /// Applies an upload's write mode by looking up its target, resolving to
+    /// the object to leave in place if the write should be skipped.
+    ///
+    /// Something written to the target after the lookup isn't noticed.
+    async fn check_write_mode(self, info: &UploadInfo) -> Result<Option<Object>, TransferError> {
+        if info.write_mode == WriteMode::Overwrite {
+            return Ok(None);
         }
 
-        let info = match info.try_into() {
-            Ok(i) => i,
-            Err(e) => {
-                return WriteCompleteFuture::from_value(Err(TransferError::TargetError(e.into())))
+        match self.get_object(info.path.clone()).await {
+            Ok(_) if info.write_mode == WriteMode::FailIfExists => {
+                Err(TransferError::TargetError(error::already_exists(
+                    info.path.clone(),
+                    Some("Refusing to replace an existing object"),
+                )))
             }
-        };
+            Ok(object) => Ok(Some(object)),
+            Err(ref e) if is_not_found(e) => Ok(None),
+            Err(e) => Err(TransferError::TargetError(e)),
+        }
+    }
 
-        let path = info.path.clone();
-        if path.is_dir_prefix() {
-            return WriteCompleteFuture::from_value(Err(TransferError::TargetError(
-                error::invalid_path(
-                    path,
-                    Some("Object paths cannot be empty or end with a '/' character."),
-                ),
-            )));
+    async fn b2_object(self, path: ObjectPath) -> StorageResult<B2Object> {
+        match self.get_object(path).await?.try_into() {
+            Ok(o) => Ok(o),
+            Err(_) => Err(error::internal_error(Some(
+                "Failed to convert retrieved object to the expected type.",
+            ))),
         }
+    }
 
-        WriteCompleteFuture::from_future(upload(
-            self.client(),
-            self.state.settings.max_small_file_size,
-            self.state.settings.prefix.clone(),
-            info,
-            into_data_stream(stream),
-        ))
+    /// Finds a version of a file.
+    async fn version_info(self, path: ObjectPath, version_id: String) -> StorageResult<FileInfo> {
+        let object = self.b2_object(path.clone()).await?;
+        let found = object
+            .versions()
+            .find(|info| info.file_id.as_ref() == Some(&version_id))
+            .cloned();
+
+        match found {
+            Some(info) => Ok(info),
+            None => Err(error::not_found(
+                path,
+                Some(&format!("There is no version '{}'.", version_id)),
+            )),
+        }
+    }
+}
+
+impl VersionedStorage for B2Backend {
+    fn list_versions(&self, path: ObjectPath) -> VersionListFuture {
+        let backend = self.clone();
+
+        VersionListFuture::from_future(async move {
+            let object = backend.b2_object(path.clone()).await?;
+            let count = object.versions.versions.len();
+            Ok(object
+                .versions()
+                .enumerate()
+                .rev()
+                .filter_map(|(index, info)| to_version(&path, info, index + 1 == count))
+                .collect())
+        })
+    }
+
+    fn get_version_stream(&self, path: ObjectPath, version_id: &str) -> DataStreamFuture {
+        let backend = self.clone();
+        let version_id = version_id.to_owned();
+
+        DataStreamFuture::from_future(async move {
+            let info = backend
+                .clone()
+                .version_info(path.clone(), version_id.clone())
+                .await?;
+            if info.action != FileAction::Upload {
+                return Err(error::not_found(
+                    path,
+                    Some(&format!("Version '{}' has no content.", version_id)),
+                ));
+            }
+
+            backend
+                .client()
+                .b2_download_file_by_id(path, version_id, None)
+                .await
+        })
+    }
+
+    fn delete_version(&self, path: ObjectPath, version_id: &str) -> OperationCompleteFuture {
+        let backend = self.clone();
+        let version_id = version_id.to_owned();
+
+        OperationCompleteFuture::from_future(async move {
+            let info = backend
+                .clone()
+                .version_info(path.clone(), version_id.clone())
+                .await?;
+
+            let result = backend
+                .client()
+                .b2_delete_file_version(
+                    path,
+                    DeleteFileVersionRequest {
+                        file_name: info.file_name,
+                        file_id: version_id,
+                    },
+                )
+                .await;
+
+            match result {
+                Ok(_) => Ok(()),
+                Err(ref e) if is_not_found(e) => Ok(()),
+                Err(e) => Err(e),
+            }
+        })
     }
 }
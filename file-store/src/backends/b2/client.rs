@@ -22,17 +22,17 @@ use std::io::Read;
 use std::ops::Deref;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use base64::encode;
-use futures::stream::{iter, Stream, StreamExt};
+use futures::stream::empty;
 use http::header;
 use http::method::Method;
-use hyper::body::Body;
-use hyper::Chunk;
-use hyper::{Request, Response};
+use http::{Request, StatusCode};
 use log::{error, trace, warn};
 use serde::de::DeserializeOwned;
 use serde_json::{from_str, to_string};
+use tokio_timer::delay_for;
 
 use storage_types::b2::v2::requests::*;
 use storage_types::b2::v2::responses::*;
@@ -41,18 +41,43 @@ use storage_types::b2::v2::{
     B2_HEADER_FILE_NAME, B2_HEADER_PART_NUMBER,
 };
 
-use super::{B2Settings, Client, ClientPool};
+use super::{B2Settings, Client, ClientPool, IDEMPOTENCY_KEY};
+use crate::backends::Backend;
+use crate::events::now;
+use crate::http_client::{HttpRequest, HttpResponse, RequestBody};
+use crate::instrument::record_attempt;
+use crate::metrics::{
+    b2_transaction_class, Metrics, B2_TRANSACTIONS, REQUESTS, REQUEST_DURATION, RETRIES,
+};
 use crate::types::stream::AfterStream;
 use crate::types::*;
-use crate::utils::Pool;
+use crate::utils::{BlockingStreamReader, Pool};
+
+/// The data sent in an upload request. A fresh stream is opened for every
+/// attempt at the request.
+pub enum UploadBody {
+    /// Chunks of data held in memory, every attempt shares the same chunks.
+    Buffered(BufferedSource),
+    /// The range of an upload source starting at the given offset.
+    Source(Arc<dyn UploadSource>, u64),
+}
 
-const MAX_API_RETRIES: usize = 5;
+impl UploadBody {
+    async fn open(&self, length: u64) -> StorageResult<DataStream> {
+        match self {
+            UploadBody::Buffered(source) => Ok(source.stream()),
+            UploadBody::Source(source, offset) => source.read_range(*offset, length).await,
+        }
+    }
+}
 
 #[derive(Debug)]
 struct B2Error {
     error: StorageError,
     needs_auth: bool,
     can_retry: bool,
+    /// How long the server asked us to wait before retrying.
+    retry_after: Option<Duration>,
 }
 
 impl From<B2Error> for StorageError {
@@ -63,66 +88,51 @@ impl From<B2Error> for StorageError {
 
 type B2Result<T> = Result<T, B2Error>;
 
-impl From<hyper::error::Error> for B2Error {
-    fn from(hyper_error: hyper::error::Error) -> B2Error {
-        fn error(error: StorageError, can_retry: bool) -> B2Error {
-            B2Error {
-                error,
-                needs_auth: can_retry,
-                can_retry,
-            }
-        }
+impl From<StorageError> for B2Error {
+    fn from(error: StorageError) -> B2Error {
+        let can_retry = match error.kind() {
+            StorageErrorKind::Cancelled
+            | StorageErrorKind::ConnectionClosed
+            | StorageErrorKind::ConnectionFailed => true,
+            _ => false,
+        };
 
-        if hyper_error.is_parse() || hyper_error.is_user() {
-            error(error::invalid_data(Some(&hyper_error.to_string())), false)
-        } else if hyper_error.is_canceled() {
-            error(error::cancelled(Some(&hyper_error.to_string())), true)
-        } else if hyper_error.is_closed() {
-            error(
-                error::connection_closed(Some(&hyper_error.to_string())),
-                true,
-            )
-        } else if hyper_error.is_connect() {
-            error(
-                error::connection_failed(Some(&hyper_error.to_string())),
-                true,
-            )
-        } else if hyper_error.is_incomplete_message() {
-            error(
-                error::connection_closed(Some(&hyper_error.to_string())),
-                true,
-            )
-        } else {
-            error(error::invalid_data(Some(&hyper_error.to_string())), true)
+        B2Error {
+            error,
+            needs_auth: can_retry,
+            can_retry,
+            retry_after: None,
         }
     }
 }
 
-impl From<hyper::error::Error> for StorageError {
-    fn from(hyper_error: hyper::error::Error) -> StorageError {
-        let b2_error: B2Error = hyper_error.into();
-        b2_error.into()
-    }
-}
-
-impl From<http::Error> for StorageError {
-    fn from(error: http::Error) -> StorageError {
-        error::other_error(Some(&error.to_string()))
-    }
-}
-
 impl From<serde_json::error::Error> for StorageError {
     fn from(error: serde_json::error::Error) -> StorageError {
         error::internal_error(Some(&format!("Failed to encode request data: {}", error)))
     }
 }
 
+/// Reads the delay in seconds from a `Retry-After` header. B2 never sends the
+/// HTTP date form.
+fn parse_retry_after(response: &HttpResponse) -> Option<Duration> {
+    response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
 fn generate_error(method: &str, client_id: usize, path: &ObjectPath, response: &str) -> B2Error {
     fn error(error: StorageError) -> B2Error {
         B2Error {
             error,
             needs_auth: false,
             can_retry: false,
+            retry_after: None,
         }
     }
 
@@ -169,11 +179,13 @@ fn generate_error(method: &str, client_id: usize, path: &ObjectPath, response: &
             error: error::access_expired(Some(&error_info.message)),
             needs_auth: true,
             can_retry: true,
+            retry_after: None,
         },
         (401, "expired_auth_token") => B2Error {
             error: error::access_expired(Some(&error_info.message)),
             needs_auth: true,
             can_retry: true,
+            retry_after: None,
         },
 
         (403, "cap_exceeded") => error(error::over_quota(Some(&error_info.code))),
@@ -186,6 +198,7 @@ fn generate_error(method: &str, client_id: usize, path: &ObjectPath, response: &
             error: error::connection_closed(Some(&error_info.message)),
             needs_auth: true,
             can_retry: true,
+            retry_after: None,
         },
 
         (416, "range_not_satisfiable") => error(error::internal_error(Some(&error_info.message))),
@@ -194,17 +207,20 @@ fn generate_error(method: &str, client_id: usize, path: &ObjectPath, response: &
             error: error::over_quota(Some(&error_info.message)),
             needs_auth: true,
             can_retry: true,
+            retry_after: None,
         },
 
         (500, "internal_error") => B2Error {
             error: error::service_error(Some(&error_info.message)),
             needs_auth: true,
             can_retry: true,
+            retry_after: None,
         },
         (503, "bad_request") => B2Error {
             error: error::service_error(Some(&error_info.message)),
             needs_auth: true,
             can_retry: true,
+            retry_after: None,
         },
 
         (status, _) => {
@@ -215,18 +231,21 @@ fn generate_error(method: &str, client_id: usize, path: &ObjectPath, response: &
                     error: error::access_expired(Some(&error_info.message)),
                     needs_auth: true,
                     can_retry: true,
+                    retry_after: None,
                 }
             } else if status >= 500 && status < 600 {
                 B2Error {
                     error: error::service_error(Some(&error_info.message)),
                     needs_auth: true,
                     can_retry: true,
+                    retry_after: None,
                 }
             } else {
                 B2Error {
                     error: error::other_error(Some(&error_info.message)),
                     needs_auth: true,
                     can_retry: true,
+                    retry_after: None,
                 }
             }
         }
@@ -243,7 +262,7 @@ macro_rules! b2_api {
         ) -> impl Future<Output = StorageResult<$response>> {
             self.clone().b2_api_call(stringify!($method), path, request)
         }
-    }
+    };
 }
 
 #[derive(Debug, Clone)]
@@ -254,49 +273,105 @@ impl B2Client {
         format!("{}/b2api/{}/{}", host, B2_VERSION, method)
     }
 
-    async fn request(
+    async fn send(
         id: usize,
+        metrics: &Metrics,
         method: &str,
-        path: ObjectPath,
         client: &Client,
-        request: Request<Body>,
-    ) -> B2Result<Response<Body>> {
+        request: HttpRequest,
+    ) -> B2Result<HttpResponse> {
         trace!("Client {:04}: Requesting {}", id, request.uri());
-        let response = match client.request(request).await {
+        let started = now();
+        let result = client.request(request).await;
+
+        let outcome = match result {
+            Ok(ref r) if r.status().is_success() => "ok",
+            _ => "error",
+        };
+        metrics.counter(
+            REQUESTS,
+            Backend::B2,
+            1,
+            &[("method", method), ("result", outcome)],
+        );
+        metrics.duration(
+            REQUEST_DURATION,
+            Backend::B2,
+            started,
+            &[("method", method)],
+        );
+        if result.is_ok() {
+            metrics.counter(
+                B2_TRANSACTIONS,
+                Backend::B2,
+                1,
+                &[("method", method), ("class", b2_transaction_class(method))],
+            );
+        }
+
+        match result {
             Ok(r) => {
                 trace!("Client {:04}: {} b2 api call succeeded", id, method);
-                r
+                Ok(r)
             }
             Err(e) => {
                 error!("Client {:04}: {} b2 api call failed: {}", id, method, e);
-                return Err(e.into());
+                Err(e.into())
             }
-        };
+        }
+    }
 
+    fn check_response(
+        id: usize,
+        method: &str,
+        path: &ObjectPath,
+        response: HttpResponse,
+    ) -> B2Result<HttpResponse> {
         if response.status().is_success() {
             Ok(response)
         } else {
+            let retry_after = match response.status() {
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+                    parse_retry_after(&response)
+                }
+                _ => None,
+            };
             let (_, body) = response.into_parts();
 
             let mut data: String = String::new();
             BlockingStreamReader::from_stream(body)
                 .read_to_string(&mut data)
                 .unwrap();
-            Err(generate_error(method, id, &path, &data))
+            let mut error = generate_error(method, id, path, &data);
+            error.retry_after = retry_after;
+            Err(error)
         }
     }
 
+    async fn request(
+        id: usize,
+        metrics: &Metrics,
+        method: &str,
+        path: ObjectPath,
+        client: &Client,
+        request: HttpRequest,
+    ) -> B2Result<HttpResponse> {
+        let response = B2Client::send(id, metrics, method, client, request).await?;
+        B2Client::check_response(id, method, &path, response)
+    }
+
     async fn basic_request<R>(
         id: usize,
+        metrics: &Metrics,
         method: &str,
         path: ObjectPath,
         mut client: Client,
-        request: Request<Body>,
+        request: HttpRequest,
     ) -> B2Result<R>
     where
         R: DeserializeOwned + fmt::Debug,
     {
-        let response = B2Client::request(id, method, path, &client, request).await?;
+        let response = B2Client::request(id, metrics, method, path, &client, request).await?;
         let (_, body) = response.into_parts();
 
         let mut data: String = String::new();
@@ -321,6 +396,7 @@ impl B2Client {
                     ))),
                     needs_auth: false,
                     can_retry: true,
+                    retry_after: None,
                 })
             }
         }
@@ -329,6 +405,7 @@ impl B2Client {
     pub async fn authorize(
         settings: B2Settings,
         clients: ClientPool,
+        metrics: Metrics,
     ) -> StorageResult<AuthorizeAccountResponse> {
         let secret = format!(
             "Basic {}",
@@ -345,11 +422,14 @@ impl B2Client {
             .uri(B2Client::api_url(&settings.host, "b2_authorize_account"))
             .header(header::AUTHORIZATION, secret)
             .header(header::USER_AGENT, settings.user_agent)
-            .body(Body::empty())?;
+            .body(RequestBody::Empty)?;
 
         let empty = ObjectPath::empty();
         let client = clients.acquire().await;
-        Ok(B2Client::basic_request(0, "b2_authorize_account", empty, client, request).await?)
+        Ok(
+            B2Client::basic_request(0, &metrics, "b2_authorize_account", empty, client, request)
+                .await?,
+        )
     }
 }
 
@@ -358,7 +438,9 @@ pub(super) struct B2APIState {
     pub settings: B2Settings,
     pub clients: ClientPool,
     pub next_id: Arc<AtomicUsize>,
-    pub auth_tokens: Pool<(B2Settings, ClientPool), AuthorizeAccountResponse, StorageError>,
+    pub auth_tokens:
+        Pool<(B2Settings, ClientPool, Metrics), AuthorizeAccountResponse, StorageError>,
+    pub metrics: Metrics,
 }
 
 impl Clone for B2APIState {
@@ -368,6 +450,7 @@ impl Clone for B2APIState {
             clients: self.clients.clone(),
             next_id: self.next_id.clone(),
             auth_tokens: self.auth_tokens.clone(),
+            metrics: self.metrics.clone(),
         }
     }
 }
@@ -392,6 +475,26 @@ impl B2API {
         }
     }
 
+    /// Checks whether a failed request should be tried again after `tries`
+    /// attempts, waiting for the retry policy's delay if so.
+    async fn wait_to_retry(&self, tries: usize, error: &B2Error) -> bool {
+        let policy = &self.state.settings.retry;
+        if !policy.should_retry(tries, &error.error, error.can_retry, error.retry_after) {
+            return false;
+        }
+
+        self.state.metrics.counter(RETRIES, Backend::B2, 1, &[]);
+        let delay = policy.delay(tries, error.retry_after);
+        trace!(
+            "Client {:04}: Retrying after {}ms.",
+            self.id,
+            delay.as_millis()
+        );
+        delay_for(delay).await;
+        record_attempt(tries + 1);
+        true
+    }
+
     async fn b2_api_call<S, Q>(self, method: &str, path: ObjectPath, request: S) -> StorageResult<Q>
     where
         S: serde::ser::Serialize + Clone + fmt::Debug,
@@ -415,11 +518,21 @@ impl B2API {
                 .uri(B2Client::api_url(&auth_info.api_url, method))
                 .header(header::AUTHORIZATION, &auth_info.authorization_token)
                 .header(header::USER_AGENT, &self.state.settings.user_agent)
-                .body(data.into())?;
+                .header(header::CONTENT_LENGTH, data.len())
+                .body(RequestBody::Full(data.into()))?;
 
             let client = self.state.clients.acquire().await;
 
-            match B2Client::basic_request(self.id, method, path.clone(), client, request).await {
+            match B2Client::basic_request(
+                self.id,
+                &self.state.metrics,
+                method,
+                path.clone(),
+                client,
+                request,
+            )
+            .await
+            {
                 Ok(response) => return Ok(response),
                 Err(e) => {
                     if e.needs_auth {
@@ -428,7 +541,7 @@ impl B2API {
 
                     tries += 1;
 
-                    if !e.can_retry || tries >= MAX_API_RETRIES {
+                    if !self.wait_to_retry(tries, &e).await {
                         return Err(e.into());
                     }
                 }
@@ -448,7 +561,39 @@ impl B2API {
         path: ObjectPath,
         bucket: String,
         file: String,
-    ) -> StorageResult<impl Stream<Item = Result<Chunk, hyper::Error>>> {
+        range: Option<(u64, Option<u64>)>,
+    ) -> StorageResult<DataStream> {
+        let location = format!(
+            "/file/{}/{}",
+            percent_encode(&bucket),
+            percent_encode(&file)
+        );
+        self.download("b2_download_file_by_name", path, location, range)
+            .await
+    }
+
+    pub async fn b2_download_file_by_id(
+        self,
+        path: ObjectPath,
+        file_id: String,
+        range: Option<(u64, Option<u64>)>,
+    ) -> StorageResult<DataStream> {
+        let location = format!(
+            "/b2api/{}/b2_download_file_by_id?fileId={}",
+            B2_VERSION,
+            percent_encode(&file_id)
+        );
+        self.download("b2_download_file_by_id", path, location, range)
+            .await
+    }
+
+    async fn download(
+        self,
+        method: &'static str,
+        path: ObjectPath,
+        location: String,
+        range: Option<(u64, Option<u64>)>,
+    ) -> StorageResult<DataStream> {
         let mut tries: usize = 0;
         loop {
             let mut auth_info = self.state.auth_tokens.acquire().await?;
@@ -456,37 +601,54 @@ impl B2API {
             trace!(
                 "Client {:04}: Starting {} api call (attempt {})",
                 self.id,
-                "b2_download_file_by_name",
+                method,
                 tries + 1,
             );
 
-            let request = Request::builder()
+            let mut builder = Request::builder();
+            if let Some((offset, length)) = range {
+                let end = match length {
+                    Some(length) => (offset + length - 1).to_string(),
+                    None => String::new(),
+                };
+                builder.header(header::RANGE, format!("bytes={}-{}", offset, end));
+            }
+
+            let request = builder
                 .method(Method::GET)
                 .header(header::AUTHORIZATION, &auth_info.authorization_token)
                 .header(header::USER_AGENT, &self.state.settings.user_agent)
-                .uri(format!(
-                    "{}/file/{}/{}",
-                    auth_info.download_url,
-                    percent_encode(&bucket),
-                    percent_encode(&file)
-                ))
-                .body(Body::empty())?;
+                .uri(format!("{}{}", auth_info.download_url, location))
+                .body(RequestBody::Empty)?;
 
             let mut client = self.state.clients.acquire().await;
-            match B2Client::request(
+            let result = match B2Client::send(
                 self.id,
-                "b2_download_file_by_name",
-                path.clone(),
+                &self.state.metrics,
+                method,
                 &client,
                 request,
             )
             .await
             {
+                // B2 refuses ranges that start beyond the end of the file.
+                Ok(ref response)
+                    if range.is_some()
+                        && response.status() == StatusCode::RANGE_NOT_SATISFIABLE =>
+                {
+                    client.release();
+                    return Ok(DataStream::from_stream(empty()));
+                }
+                Ok(response) => B2Client::check_response(self.id, method, &path, response),
+                Err(e) => Err(e),
+            };
+
+            match result {
                 Ok(response) => {
                     let (_, body) = response.into_parts();
                     let stream = AfterStream::after(body, move || client.release());
 
-                    return Ok(stream);
+                    return Ok(DataStream::from_stream(stream));
                 }
                 Err(e) => {
                     client.release();
@@ -496,7 +658,7 @@ impl B2API {
 
                     tries += 1;
 
-                    if !e.can_retry || tries >= MAX_API_RETRIES {
+                    if !self.wait_to_retry(tries, &e).await {
                         return Err(e.into());
                     }
                 }
@@ -504,27 +666,51 @@ impl B2API {
         }
     }
 
+    /// Finds the current version of a file if it was uploaded with the given
+    /// idempotency key.
+    pub async fn find_keyed_upload(
+        self,
+        path: ObjectPath,
+        bucket_id: String,
+        file_name: String,
+        key: &str,
+    ) -> StorageResult<Option<FileInfo>> {
+        let request = ListFileNamesRequest {
+            bucket_id,
+            start_file_name: Some(file_name.clone()),
+            max_file_count: Some(1),
+            prefix: None,
+            delimiter: None,
+        };
+
+        let response = self.b2_list_file_names(path, request).await?;
+        Ok(response.files.into_iter().next().filter(|file| {
+            file.file_name == file_name
+                && file.file_info.get(IDEMPOTENCY_KEY).map(String::as_str) == Some(key)
+        }))
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub async fn b2_upload_file(
         self,
         path: ObjectPath,
-        url: String,
-        auth: String,
+        upload_url: GetUploadUrlResponse,
         file_name: String,
         content_type: String,
         info: UserFileInfo,
         length: u64,
         hash: String,
-        data: Vec<Data>,
+        body: UploadBody,
     ) -> StorageResult<UploadFileResponse> {
         let mut tries: usize = 0;
 
         loop {
+            let stream = body.open(length).await?;
             let mut builder = Request::builder();
             builder
                 .method(Method::POST)
-                .uri(&url)
-                .header(header::AUTHORIZATION, &auth)
+                .uri(&upload_url.upload_url)
+                .header(header::AUTHORIZATION, &upload_url.authorization_token)
                 .header(header::USER_AGENT, &self.state.settings.user_agent)
                 .header(B2_HEADER_FILE_NAME, percent_encode(&file_name))
                 .header(header::CONTENT_TYPE, &content_type)
@@ -532,24 +718,48 @@ impl B2API {
                 .header(B2_HEADER_CONTENT_SHA1, &hash);
 
             for (key, value) in info.iter() {
-                builder.header(&format!("{}{}", B2_HEADER_FILE_INFO_PREFIX, key), value);
+                builder.header(
+                    &format!("{}{}", B2_HEADER_FILE_INFO_PREFIX, key),
+                    percent_encode(value),
+                );
             }
 
-            let request = builder.body(Body::wrap_stream(
-                iter(data.clone()).map(Ok::<_, StorageError>),
-            ))?;
+            let request = builder.body(RequestBody::Stream(stream))?;
 
             let client = self.state.clients.acquire().await;
-            match B2Client::basic_request(self.id, "b2_upload_file", path.clone(), client, request)
-                .await
+            match B2Client::basic_request(
+                self.id,
+                &self.state.metrics,
+                "b2_upload_file",
+                path.clone(),
+                client,
+                request,
+            )
+            .await
             {
                 Ok(response) => return Ok(response),
                 Err(e) => {
                     tries += 1;
 
-                    if !e.can_retry || tries >= MAX_API_RETRIES {
+                    if !self.wait_to_retry(tries, &e).await {
                         return Err(e.into());
                     }
+
+                    // The failed attempt may still have created the file.
+                    if let Some(key) = info.get(IDEMPOTENCY_KEY) {
+                        let existing = self
+                            .clone()
+                            .find_keyed_upload(
+                                path.clone(),
+                                upload_url.bucket_id.clone(),
+                                file_name.clone(),
+                                key,
+                            )
+                            .await?;
+                        if let Some(file) = existing {
+                            return Ok(file);
+                        }
+                    }
                 }
             }
         }
@@ -562,11 +772,12 @@ impl B2API {
         part: usize,
         length: u64,
         hash: String,
-        data: Vec<Data>,
+        body: UploadBody,
     ) -> StorageResult<UploadPartResponse> {
         let mut tries: usize = 0;
 
         loop {
+            let stream = body.open(length).await?;
             let request = Request::builder()
                 .method(Method::POST)
                 .uri(&upload_url.upload_url)
@@ -575,19 +786,24 @@ impl B2API {
                 .header(B2_HEADER_PART_NUMBER, part)
                 .header(header::CONTENT_LENGTH, length)
                 .header(B2_HEADER_CONTENT_SHA1, &hash)
-                .body(Body::wrap_stream(
-                    iter(data.clone()).map(Ok::<_, StorageError>),
-                ))?;
+                .body(RequestBody::Stream(stream))?;
 
             let client = self.state.clients.acquire().await;
-            match B2Client::basic_request(self.id, "b2_upload_part", path.clone(), client, request)
-                .await
+            match B2Client::basic_request(
+                self.id,
+                &self.state.metrics,
+                "b2_upload_part",
+                path.clone(),
+                client,
+                request,
+            )
+            .await
             {
                 Ok(response) => return Ok(response),
                 Err(e) => {
                     tries += 1;
 
-                    if !e.can_retry || tries >= MAX_API_RETRIES {
+                    if !self.wait_to_retry(tries, &e).await {
                         return Err(e.into());
                     }
                 }
@@ -612,6 +828,12 @@ impl B2API {
         DeleteFileVersionRequest,
         DeleteFileVersionResponse
     );
+    b2_api!(b2_hide_file, HideFileRequest, HideFileResponse);
+    b2_api!(
+        b2_get_download_authorization,
+        GetDownloadAuthorizationRequest,
+        GetDownloadAuthorizationResponse
+    );
     b2_api!(b2_get_upload_url, GetUploadUrlRequest, GetUploadUrlResponse);
     b2_api!(
         b2_start_large_file,
@@ -628,4 +850,17 @@ impl B2API {
         FinishLargeFileRequest,
         FinishLargeFileResponse
     );
+    b2_api!(b2_list_parts, ListPartsRequest, ListPartsResponse);
+    b2_api!(b2_copy_file, CopyFileRequest, CopyFileResponse);
+    b2_api!(b2_copy_part, CopyPartRequest, CopyPartResponse);
+    b2_api!(
+        b2_list_unfinished_large_files,
+        ListUnfinishedLargeFilesRequest,
+        ListUnfinishedLargeFilesResponse
+    );
+    b2_api!(
+        b2_cancel_large_file,
+        CancelLargeFileRequest,
+        CancelLargeFileResponse
+    );
 }
@@ -25,27 +25,61 @@
 //! [`delete_object`](../../enum.FileStore.html#method.delete_object) and
 //! [`write_file_from_stream`](../../enum.FileStore.html#method.write_file_from_stream)
 //! will remove these (in the directory case recursively).
-use std::convert::TryInto;
+//!
+//! [`move_file`](../../enum.FileStore.html#method.move_file) renames the file
+//! rather than copying its data unless the source and target are on different
+//! devices. Either way the moved file only keeps the metadata and content type
+//! given in the move's [`UploadInfo`](../../struct.UploadInfo.html). Moving
+//! across devices copies the file and then deletes the source, if the delete
+//! fails the move fails with a
+//! [`SourceError`](../../enum.TransferError.html#variant.SourceError) even
+//! though the target has been written.
+//!
+//! A [`write_mode`](../../struct.UploadInfo.html#structfield.write_mode) other
+//! than `Overwrite` is applied as the file is created, and moves link the file
+//! to its new path rather than renaming it, so they never replace something
+//! created at the target at the same time.
+//!
+//! [`write_file_if_match`](../../enum.FileStore.html#method.write_file_if_match)
+//! writes to a temporary file in a `.partial` directory beside the target and
+//! renames it into place once the target's etag has been checked. That
+//! directory name is reserved and left out of listings so files left behind by
+//! a crash aren't seen. The check and rename happen while holding a lock on
+//! the path shared by every clone of the backend so two conditional writes
+//! through it can't both succeed. Other processes and unconditional writes
+//! don't take the lock.
+//!
+//! Etags are built from a file's size and modification time rather than its
+//! contents, so a change that keeps the size and lands within the
+//! filesystem's timestamp resolution isn't noticed.
+use std::collections::HashMap;
 use std::fs::Metadata;
 use std::io;
+use std::io::SeekFrom;
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::task::{Context, Poll};
-use std::time::SystemTime;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use bytes::IntoBuf;
 use filetime::{set_file_mtime, FileTime};
 use futures::future::{ready, Future, FutureExt, TryFutureExt};
 use futures::stream::{empty, once, Stream, StreamExt, TryStreamExt};
 use log::{trace, warn};
-use tokio_fs::DirEntry;
+use tokio_fs::{DirEntry, OpenOptions};
 use tokio_io::AsyncWriteExt;
+use tokio_timer::delay_for;
 
 use super::Backend;
+use crate::cache::ObjectCache;
+use crate::events::EventLog;
+use crate::instrument::record_attempt;
+use crate::metrics::Metrics;
 use crate::types::error;
-use crate::types::stream::{MergedStreams, ResultStreamPoll};
+use crate::types::stream::{MergedStreams, RangeStream, ResultStreamPoll};
 use crate::types::*;
-use crate::utils::{into_data_stream, ReaderStream};
+use crate::utils::{PathLocks, ReaderStream};
 use crate::{FileStore, Object, ObjectInfo, StorageBackend};
 
 // When reading from a file we start requesting INITIAL_BUFFER_SIZE bytes. As
@@ -54,13 +88,50 @@ use crate::{FileStore, Object, ObjectInfo, StorageBackend};
 const MB: usize = 1024 * 1024;
 const INITIAL_BUFFER_SIZE: usize = 20 * MB;
 const MIN_BUFFER_SIZE: usize = MB;
+const MAX_PART_LENGTH: usize = 255;
+
+/// The directory part that conditional writes keep their temporary files in.
+const PARTIAL_DIRECTORY: &str = ".partial";
+
+static TEMPORARY_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs a filesystem operation, trying again while it fails with errors that
+/// the retry policy allows.
+async fn retry_io<F, R, T>(retry: RetryPolicy, operation: F) -> io::Result<T>
+where
+    F: Fn() -> R,
+    R: Future<Output = io::Result<T>>,
+{
+    let mut attempts: usize = 0;
+    loop {
+        attempts += 1;
+        let error = match operation().await {
+            Ok(result) => return Ok(result),
+            Err(e) => e,
+        };
+
+        let transient = match error.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock => true,
+            _ => false,
+        };
+
+        let storage_error = StorageError::from(io::Error::new(error.kind(), error.to_string()));
+        if !retry.should_retry(attempts, &storage_error, transient, None) {
+            return Err(error);
+        }
+
+        trace!("Retrying filesystem operation after: {}", error);
+        delay_for(retry.delay(attempts, None)).await;
+        record_attempt(attempts + 1);
+    }
+}
 
-async fn read_dir<P>(path: P) -> io::Result<tokio_fs::ReadDir>
+async fn read_dir<P>(retry: RetryPolicy, path: P) -> io::Result<tokio_fs::ReadDir>
 where
     P: AsRef<Path> + Send + 'static,
 {
     let path = path.as_ref().to_owned();
-    let result = tokio_fs::read_dir(path.clone()).await;
+    let result = retry_io(retry, || tokio_fs::read_dir(path.clone())).await;
     match result {
         Ok(_) => trace!("tokio_fs::read_dir {} success", path.display()),
         Err(ref e) => trace!("tokio_fs::read_dir {} failed: {}", path.display(), e),
@@ -69,12 +140,26 @@ where
     result
 }
 
-async fn remove_dir<P>(path: P) -> io::Result<()>
+async fn create_dir_all<P>(retry: RetryPolicy, path: P) -> io::Result<()>
 where
     P: AsRef<Path> + Send + 'static,
 {
     let path = path.as_ref().to_owned();
-    let result = tokio_fs::remove_dir(path.clone()).await;
+    let result = retry_io(retry, || tokio_fs::create_dir_all(path.clone())).await;
+    match result {
+        Ok(_) => trace!("tokio_fs::create_dir_all {} success", path.display()),
+        Err(ref e) => trace!("tokio_fs::create_dir_all {} failed: {}", path.display(), e),
+    }
+
+    result
+}
+
+async fn remove_dir<P>(retry: RetryPolicy, path: P) -> io::Result<()>
+where
+    P: AsRef<Path> + Send + 'static,
+{
+    let path = path.as_ref().to_owned();
+    let result = retry_io(retry, || tokio_fs::remove_dir(path.clone())).await;
     match result {
         Ok(_) => trace!("tokio_fs::remove_dir {} success", path.display()),
         Err(ref e) => trace!("tokio_fs::remove_dir {} failed: {}", path.display(), e),
@@ -83,12 +168,12 @@ where
     result
 }
 
-async fn remove_file<P>(path: P) -> io::Result<()>
+async fn remove_file<P>(retry: RetryPolicy, path: P) -> io::Result<()>
 where
     P: AsRef<Path> + Send + 'static,
 {
     let path = path.as_ref().to_owned();
-    let result = tokio_fs::remove_file(path.clone()).await;
+    let result = retry_io(retry, || tokio_fs::remove_file(path.clone())).await;
     match result {
         Ok(_) => trace!("tokio_fs::remove_file {} success", path.display()),
         Err(ref e) => trace!("tokio_fs::remove_file {} failed: {}", path.display(), e),
@@ -97,12 +182,73 @@ where
     result
 }
 
-async fn symlink_metadata<P>(path: P) -> io::Result<Metadata>
+async fn rename<P, Q>(retry: RetryPolicy, from: P, to: Q) -> io::Result<()>
+where
+    P: AsRef<Path> + Send + 'static,
+    Q: AsRef<Path> + Send + 'static,
+{
+    let from = from.as_ref().to_owned();
+    let to = to.as_ref().to_owned();
+    let result = retry_io(retry, || tokio_fs::rename(from.clone(), to.clone())).await;
+    match result {
+        Ok(_) => trace!(
+            "tokio_fs::rename {} {} success",
+            from.display(),
+            to.display()
+        ),
+        Err(ref e) => trace!(
+            "tokio_fs::rename {} {} failed: {}",
+            from.display(),
+            to.display(),
+            e
+        ),
+    }
+
+    result
+}
+
+async fn hard_link<P, Q>(retry: RetryPolicy, from: P, to: Q) -> io::Result<()>
+where
+    P: AsRef<Path> + Send + 'static,
+    Q: AsRef<Path> + Send + 'static,
+{
+    let from = from.as_ref().to_owned();
+    let to = to.as_ref().to_owned();
+    let result = retry_io(retry, || tokio_fs::hard_link(from.clone(), to.clone())).await;
+    match result {
+        Ok(_) => trace!(
+            "tokio_fs::hard_link {} {} success",
+            from.display(),
+            to.display()
+        ),
+        Err(ref e) => trace!(
+            "tokio_fs::hard_link {} {} failed: {}",
+            from.display(),
+            to.display(),
+            e
+        ),
+    }
+
+    result
+}
+
+/// Whether a rename failed because the source and target are on different
+/// devices.
+fn is_cross_device(error: &io::Error) -> bool {
+    // ERROR_NOT_SAME_DEVICE on windows, EXDEV elsewhere.
+    if cfg!(windows) {
+        error.raw_os_error() == Some(17)
+    } else {
+        error.raw_os_error() == Some(18)
+    }
+}
+
+async fn symlink_metadata<P>(retry: RetryPolicy, path: P) -> io::Result<Metadata>
 where
     P: AsRef<Path> + Send + 'static,
 {
     let path = path.as_ref().to_owned();
-    let result = tokio_fs::symlink_metadata(path.clone()).await;
+    let result = retry_io(retry, || tokio_fs::symlink_metadata(path.clone())).await;
     match result {
         Ok(_) => trace!("tokio_fs::symlink_metadata {} success", path.display()),
         Err(ref e) => trace!(
@@ -118,12 +264,12 @@ where
 struct File {}
 
 impl File {
-    pub async fn open<P>(path: P) -> io::Result<tokio_fs::File>
+    pub async fn open<P>(retry: RetryPolicy, path: P) -> io::Result<tokio_fs::File>
     where
         P: AsRef<Path> + 'static,
     {
         let path = path.as_ref().to_owned();
-        let result = tokio_fs::File::open(path.clone()).await;
+        let result = retry_io(retry, || tokio_fs::File::open(path.clone())).await;
         match result {
             Ok(_) => trace!("tokio_fs::File::open {} success", path.display()),
             Err(ref e) => trace!("tokio_fs::File::open {} failed: {}", path.display(), e),
@@ -132,12 +278,12 @@ impl File {
         result
     }
 
-    pub async fn create<P>(path: P) -> io::Result<tokio_fs::File>
+    pub async fn create<P>(retry: RetryPolicy, path: P) -> io::Result<tokio_fs::File>
     where
         P: AsRef<Path> + 'static,
     {
         let path = path.as_ref().to_owned();
-        let result = tokio_fs::File::create(path.clone()).await;
+        let result = retry_io(retry, || tokio_fs::File::create(path.clone())).await;
         match result {
             Ok(_) => trace!("tokio_fs::File::create {} success", path.display()),
             Err(ref e) => trace!("tokio_fs::File::create {} failed: {}", path.display(), e),
@@ -145,6 +291,27 @@ impl File {
 
         result
     }
+
+    /// Creates a file, failing if anything already exists at the path.
+    pub async fn create_new<P>(retry: RetryPolicy, path: P) -> io::Result<tokio_fs::File>
+    where
+        P: AsRef<Path> + 'static,
+    {
+        let path = path.as_ref().to_owned();
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        let result = retry_io(retry, || options.open(path.clone())).await;
+        match result {
+            Ok(_) => trace!("tokio_fs::OpenOptions::open {} success", path.display()),
+            Err(ref e) => trace!(
+                "tokio_fs::OpenOptions::open {} failed: {}",
+                path.display(),
+                e
+            ),
+        }
+
+        result
+    }
 }
 
 fn get_storage_error(error: io::Error, path: ObjectPath) -> StorageError {
@@ -173,6 +340,8 @@ where
 pub struct FileObject {
     path: ObjectPath,
     metadata: Option<Metadata>,
+    user_metadata: Option<HashMap<String, String>>,
+    content_type: Option<String>,
 }
 
 impl ObjectInfo for FileObject {
@@ -210,15 +379,157 @@ impl ObjectInfo for FileObject {
             .as_ref()
             .and_then(|m| if m.is_file() { m.modified().ok() } else { None })
     }
+
+    /// Built from the size and modification time of the file, not its
+    /// contents.
+    fn etag(&self) -> Option<String> {
+        let modified = self.modified()?.duration_since(UNIX_EPOCH).ok()?;
+        Some(format!(
+            "{:x}-{:x}.{:x}",
+            self.len(),
+            modified.as_secs(),
+            modified.subsec_nanos()
+        ))
+    }
+
+    /// Stored in extended attributes, only loaded by `get_object`.
+    fn metadata(&self) -> Option<HashMap<String, String>> {
+        self.user_metadata.clone()
+    }
+
+    /// Stored in the `user.mime_type` extended attribute, only loaded by
+    /// `get_object`.
+    fn content_type(&self) -> Option<String> {
+        self.content_type.clone()
+    }
 }
 
 fn get_object(path: ObjectPath, metadata: Option<Metadata>) -> Object {
-    Object::from(FileObject { path, metadata })
+    Object::from(FileObject {
+        path,
+        metadata,
+        user_metadata: None,
+        content_type: None,
+    })
+}
+
+/// User metadata is stored in extended attributes under this namespace.
+#[cfg(all(unix, feature = "xattr"))]
+const XATTR_PREFIX: &str = "user.file-store.";
+
+/// The shared extended attribute for a file's MIME type.
+#[cfg(all(unix, feature = "xattr"))]
+const XATTR_MIME_TYPE: &str = "user.mime_type";
+
+/// Reads the user metadata stored with a file.
+#[cfg(all(unix, feature = "xattr"))]
+fn read_user_metadata(target: &Path) -> Option<HashMap<String, String>> {
+    let names = match xattr::list(target) {
+        Ok(names) => names,
+        Err(e) => {
+            trace!("Failed to list extended attributes: {}", e);
+            return None;
+        }
+    };
+
+    let mut result = HashMap::new();
+    for name in names {
+        let key = match name.to_str() {
+            Some(name) if name.starts_with(XATTR_PREFIX) => &name[XATTR_PREFIX.len()..],
+            _ => continue,
+        };
+
+        if let Ok(Some(value)) = xattr::get(target, &name) {
+            if let Ok(value) = String::from_utf8(value) {
+                result.insert(key.to_owned(), value);
+            }
+        }
+    }
+
+    Some(result)
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn read_user_metadata(_target: &Path) -> Option<HashMap<String, String>> {
+    None
+}
+
+/// Reads the content type stored with a file.
+#[cfg(all(unix, feature = "xattr"))]
+fn read_content_type(target: &Path) -> Option<String> {
+    match xattr::get(target, XATTR_MIME_TYPE) {
+        Ok(Some(value)) => String::from_utf8(value).ok(),
+        _ => None,
+    }
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn read_content_type(_target: &Path) -> Option<String> {
+    None
+}
+
+/// Stores the content type with a file.
+#[cfg(all(unix, feature = "xattr"))]
+fn write_content_type(target: &Path, content_type: &str) -> io::Result<()> {
+    xattr::set(target, XATTR_MIME_TYPE, content_type.as_bytes())
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn write_content_type(_target: &Path, _content_type: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Extended attributes are not supported on this platform.",
+    ))
+}
+
+/// Stores user metadata with a file.
+#[cfg(all(unix, feature = "xattr"))]
+fn write_user_metadata(target: &Path, metadata: &HashMap<String, String>) -> io::Result<()> {
+    for (key, value) in metadata {
+        xattr::set(target, format!("{}{}", XATTR_PREFIX, key), value.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn write_user_metadata(_target: &Path, metadata: &HashMap<String, String>) -> io::Result<()> {
+    if metadata.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "Extended attributes are not supported on this platform.",
+        ))
+    }
+}
+
+/// Removes the metadata and content type stored with a file.
+#[cfg(all(unix, feature = "xattr"))]
+fn clear_stored_metadata(target: &Path) -> io::Result<()> {
+    for name in xattr::list(target)? {
+        let stored = match name.to_str() {
+            Some(name) => name.starts_with(XATTR_PREFIX) || name == XATTR_MIME_TYPE,
+            None => false,
+        };
+
+        if stored {
+            xattr::remove(target, &name)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(all(unix, feature = "xattr")))]
+fn clear_stored_metadata(_target: &Path) -> io::Result<()> {
+    Ok(())
 }
 
 #[derive(Clone, Debug)]
 struct FileSpace {
     base: PathBuf,
+    retry: RetryPolicy,
 }
 
 impl FileSpace {
@@ -232,6 +543,48 @@ impl FileSpace {
     }
 }
 
+/// Applies the modification time, metadata and content type of an upload to a
+/// written file.
+fn apply_upload_info(target: &Path, info: &UploadInfo) {
+    if let Some(time) = info.modified {
+        if let Err(e) = set_file_mtime(target, FileTime::from_system_time(time)) {
+            warn!("Failed to set file modification time: {}", e);
+        }
+    }
+
+    if let Err(e) = write_user_metadata(target, &info.metadata) {
+        warn!("Failed to store file metadata: {}", e);
+    }
+
+    if let Some(ref content_type) = info.content_type {
+        if let Err(e) = write_content_type(target, content_type) {
+            warn!("Failed to store file content type: {}", e);
+        }
+    }
+}
+
+/// Directory parts become file names so they must be valid for the OS and can't
+/// step outside of the root.
+fn path_policy() -> PathPolicy {
+    let policy = PathPolicy::new()
+        .max_part_length(MAX_PART_LENGTH)
+        .disallow_characters(&['\0'])
+        .disallow_empty_parts()
+        .reserve_part(".")
+        .reserve_part("..")
+        .reserve_part(PARTIAL_DIRECTORY);
+    #[cfg(feature = "lock")]
+    let policy = crate::lock::reserve_lock_directory(policy);
+
+    if cfg!(windows) {
+        policy
+            .disallow_characters(&['<', '>', ':', '"', '\\', '|', '?', '*'])
+            .disallow_control_characters()
+    } else {
+        policy
+    }
+}
+
 fn directory_stream(
     space: &FileSpace,
     path: ObjectPath,
@@ -243,7 +596,7 @@ fn directory_stream(
     ) -> StorageResult<impl Stream<Item = StorageResult<DirEntry>>> {
         let target = space.get_std_path(&path)?;
         Ok(wrap_stream(
-            wrap_future(read_dir(target.clone()), path.clone()).await?,
+            wrap_future(read_dir(space.retry.clone(), target.clone()), path.clone()).await?,
             path,
         ))
     }
@@ -263,10 +616,15 @@ fn directory_stream(
         };
 
         stream
+            .try_filter(|direntry| ready(direntry.file_name() != *PARTIAL_DIRECTORY))
             .and_then(move |direntry| {
                 let fname = direntry.file_name();
                 let mut path = path.clone();
-                wrap_future(symlink_metadata(direntry.path()), path.clone()).map(move |result| {
+                wrap_future(
+                    symlink_metadata(space.retry.clone(), direntry.path()),
+                    path.clone(),
+                )
+                .map(move |result| {
                     let filename = match fname.into_string() {
                         Ok(f) => f,
                         Err(_) => {
@@ -340,33 +698,58 @@ impl Stream for FileLister {
     }
 }
 
+/// Removes a directory and everything in it depth first. Only the directories
+/// currently being removed are held open so memory use depends on the depth of
+/// the tree rather than the number of entries.
 #[allow(clippy::needless_lifetimes)]
 async fn delete_directory(space: FileSpace, path: ObjectPath) -> StorageResult<()> {
-    let mut dir_path = path.clone();
-    dir_path.push_part("");
-
-    let allfiles = FileLister::list(space.clone(), dir_path)
-        .try_collect::<Vec<Object>>()
-        .await?;
-    let nondirectories = allfiles
-        .iter()
-        .filter(|file| file.object_type() != ObjectType::Directory);
-    let directories = allfiles
-        .iter()
-        .filter(|file| file.object_type() == ObjectType::Directory);
+    let target = space.get_std_path(&path)?;
+    let entries = wrap_stream(
+        wrap_future(read_dir(space.retry.clone(), target), path.clone()).await?,
+        path.clone(),
+    );
+    let mut stack = vec![(path, Box::pin(entries))];
+
+    loop {
+        let (dir, next) = match stack.last_mut() {
+            Some((dir, entries)) => (dir.clone(), entries.next().await),
+            None => return Ok(()),
+        };
 
-    for file in nondirectories {
-        let target = space.get_std_path(&file.path())?;
-        wrap_future(remove_file(target), file.path()).await?;
-    }
+        let direntry = match next {
+            Some(result) => result?,
+            None => {
+                // Everything inside has been removed.
+                stack.pop();
+                let target = space.get_std_path(&dir)?;
+                wrap_future(remove_dir(space.retry.clone(), target), dir).await?;
+                continue;
+            }
+        };
 
-    for dir in directories {
-        let target = space.get_std_path(&dir.path())?;
-        wrap_future(remove_dir(target), dir.path()).await?;
+        let filename = match direntry.file_name().into_string() {
+            Ok(f) => f,
+            Err(_) => return Err(error::invalid_data(Some("Unable to convert OSString."))),
+        };
+        let mut child = dir;
+        child.push_part(&filename);
+
+        let target = direntry.path();
+        let metadata = wrap_future(
+            symlink_metadata(space.retry.clone(), target.clone()),
+            child.clone(),
+        )
+        .await?;
+        if metadata.is_dir() {
+            let entries = wrap_stream(
+                wrap_future(read_dir(space.retry.clone(), target), child.clone()).await?,
+                child.clone(),
+            );
+            stack.push((child, Box::pin(entries)));
+        } else {
+            wrap_future(remove_file(space.retry.clone(), target), child).await?;
+        }
     }
-
-    let target = space.get_std_path(&path)?;
-    wrap_future(remove_dir(target), path).await
 }
 
 /// The backend implementation for local file storage. Only included when the
@@ -374,6 +757,15 @@ async fn delete_directory(space: FileSpace, path: ObjectPath) -> StorageResult<(
 #[derive(Clone, Debug)]
 pub struct FileBackend {
     space: FileSpace,
+    events: EventLog,
+    metrics: Metrics,
+    cache: ObjectCache,
+    directories: DirectorySemantics,
+    strict_overwrites: bool,
+    cleanup_on_failure: bool,
+    limiter: Option<ConcurrencyLimiter>,
+    paths: PathPolicy,
+    replacing: PathLocks<PathBuf>,
 }
 
 impl FileBackend {
@@ -383,17 +775,234 @@ impl FileBackend {
     /// The root path provided must be a directory and is used as the base of
     /// the visible storage.
     pub fn connect(root: &Path) -> ConnectFuture {
-        let target = root.to_owned();
+        FileBackend::builder(root).connect()
+    }
+
+    /// Creates a new [`FileBackendBuilder`](struct.FileBackendBuilder.html)
+    /// for the given root path.
+    pub fn builder(root: &Path) -> FileBackendBuilder {
+        FileBackendBuilder {
+            root: root.to_owned(),
+            retry: Default::default(),
+        }
+    }
+
+    pub(crate) fn event_log(&self) -> &EventLog {
+        &self.events
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub(crate) fn object_cache(&self) -> &ObjectCache {
+        &self.cache
+    }
+
+    pub(crate) fn directory_semantics(&self) -> DirectorySemantics {
+        self.directories
+    }
+
+    pub(crate) fn set_directory_semantics(&mut self, semantics: DirectorySemantics) {
+        self.directories = semantics;
+    }
+
+    pub(crate) fn strict_overwrites(&self) -> bool {
+        self.strict_overwrites
+    }
+
+    pub(crate) fn set_strict_overwrites(&mut self, strict: bool) {
+        self.strict_overwrites = strict;
+    }
+
+    pub(crate) fn cleanup_on_failure(&self) -> bool {
+        self.cleanup_on_failure
+    }
+
+    pub(crate) fn set_cleanup_on_failure(&mut self, cleanup: bool) {
+        self.cleanup_on_failure = cleanup;
+    }
+
+    pub(crate) fn concurrency_limiter(&self) -> Option<&ConcurrencyLimiter> {
+        self.limiter.as_ref()
+    }
+
+    pub(crate) fn set_concurrency_limiter(&mut self, limiter: Option<ConcurrencyLimiter>) {
+        self.limiter = limiter;
+    }
+
+    pub(crate) fn path_policy(&self) -> &PathPolicy {
+        &self.paths
+    }
+
+    pub(crate) fn set_path_policy(&mut self, policy: PathPolicy) {
+        self.paths = policy;
+    }
+
+    /// Writes a file as long as the file already at its path has the given
+    /// etag.
+    pub(crate) fn write_file_if_match(
+        &self,
+        info: UploadInfo,
+        etag: String,
+        stream: DataStream,
+    ) -> WriteCompleteFuture {
+        async fn write(
+            backend: FileBackend,
+            info: UploadInfo,
+            etag: String,
+            stream: DataStream,
+        ) -> Result<(), TransferError> {
+            let space = backend.space.clone();
+            let target = space
+                .get_std_path(&info.path)
+                .map_err(TransferError::TargetError)?;
+            let temporary_info = UploadInfo {
+                path: temporary_path(&info.path).map_err(TransferError::TargetError)?,
+                write_mode: WriteMode::FailIfExists,
+                cleanup_on_failure: Some(true),
+                ..info.clone()
+            };
+            let temporary = space
+                .get_std_path(&temporary_info.path)
+                .map_err(TransferError::TargetError)?;
+            let partial = match temporary.parent() {
+                Some(parent) => parent.to_owned(),
+                None => {
+                    return Err(TransferError::TargetError(error::internal_error(Some(
+                        "Temporary files must be in a directory.",
+                    ))))
+                }
+            };
+
+            create_dir_all(space.retry.clone(), partial)
+                .await
+                .map_err(|e| TransferError::TargetError(get_storage_error(e, info.path.clone())))?;
+            StorageBackend::write_file_from_stream(&backend, temporary_info, stream).await?;
+
+            let result = {
+                let _guard = backend.replacing.lock(target.clone()).await;
+                replace_if_match(&space, &info.path, &target, &temporary, &etag).await
+            };
+
+            if result.is_err() {
+                trace!("Removing unused file {}.", temporary.display());
+                if let Err(e) = remove_file(space.retry.clone(), temporary).await {
+                    warn!("Failed to remove unused file: {}", e);
+                }
+            }
+
+            // The directory is left for other writes that may be starting.
+            result
+        }
+
+        WriteCompleteFuture::from_future(write(self.clone(), info, etag, stream))
+    }
+}
+
+/// A path in the reserved directory beside `path` to write a file to before
+/// renaming it into place.
+fn temporary_path(path: &ObjectPath) -> StorageResult<ObjectPath> {
+    let mut temporary = path.clone();
+    match temporary.pop_part() {
+        Some(name) => {
+            temporary.push_part(PARTIAL_DIRECTORY);
+            temporary.push_part(&format!(
+                "{}.{}-{}",
+                name,
+                process::id(),
+                TEMPORARY_COUNT.fetch_add(1, Ordering::SeqCst)
+            ));
+            Ok(temporary)
+        }
+        None => Err(error::invalid_path(
+            path.clone(),
+            Some("The root of a store cannot be written"),
+        )),
+    }
+}
+
+/// Renames a file over the target if the target's etag matches. The caller
+/// must hold the lock on the target.
+async fn replace_if_match(
+    space: &FileSpace,
+    path: &ObjectPath,
+    target: &Path,
+    temporary: &Path,
+    etag: &str,
+) -> Result<(), TransferError> {
+    let current = match symlink_metadata(space.retry.clone(), target.to_owned()).await {
+        Ok(metadata) => get_object(path.clone(), Some(metadata)).etag(),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(TransferError::TargetError(get_storage_error(
+                e,
+                path.clone(),
+            )))
+        }
+    };
+
+    if current.as_ref().map(String::as_str) != Some(etag) {
+        return Err(TransferError::TargetError(error::precondition_failed(
+            path.clone(),
+            Some("The object has changed since it was read"),
+        )));
+    }
+
+    rename(space.retry.clone(), temporary.to_owned(), target.to_owned())
+        .await
+        .map_err(|e| TransferError::TargetError(get_storage_error(e, path.clone())))
+}
+
+/// Used to build a [`FileBackend`](struct.FileBackend.html) with some custom
+/// settings.
+#[derive(Clone, Debug)]
+pub struct FileBackendBuilder {
+    root: PathBuf,
+    retry: RetryPolicy,
+}
+
+impl FileBackendBuilder {
+    /// Sets the [`RetryPolicy`](../../struct.RetryPolicy.html) for filesystem
+    /// operations.
+    ///
+    /// By default operations that are interrupted or would block are retried
+    /// with the [default policy](../../struct.RetryPolicy.html#method.new).
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> FileBackendBuilder {
+        self.retry = policy;
+        self
+    }
+
+    /// Creates a new file based [`FileStore`](../../enum.FileStore.html) using
+    /// this builder's settings.
+    ///
+    /// The root path must be a directory.
+    pub fn connect(self) -> ConnectFuture {
         ConnectFuture::from_future(async move {
-            let metadata =
-                wrap_future(symlink_metadata(target.clone()), ObjectPath::empty()).await?;
+            let metadata = wrap_future(
+                symlink_metadata(self.retry.clone(), self.root.clone()),
+                ObjectPath::empty(),
+            )
+            .await?;
             if !metadata.is_dir() {
                 Err(error::invalid_settings(Some(
                     "Root path is not a directory.",
                 )))
             } else {
                 Ok(FileStore::from(FileBackend {
-                    space: FileSpace { base: target },
+                    space: FileSpace {
+                        base: self.root,
+                        retry: self.retry,
+                    },
+                    events: Default::default(),
+                    metrics: Default::default(),
+                    cache: Default::default(),
+                    directories: Default::default(),
+                    strict_overwrites: false,
+                    cleanup_on_failure: false,
+                    limiter: None,
+                    paths: path_policy(),
+                    replacing: Default::default(),
                 }))
             }
         })
@@ -405,31 +1014,22 @@ impl StorageBackend for FileBackend {
         Backend::File
     }
 
-    fn list_objects<P>(&self, prefix: P) -> ObjectStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
         async fn list(space: FileSpace, prefix: ObjectPath) -> StorageResult<ObjectStream> {
             Ok(ObjectStream::from_stream(FileLister::list(space, prefix)))
         }
 
-        let path = match prefix.try_into() {
-            Ok(p) => p,
-            Err(e) => return ObjectStreamFuture::from_value(Err(e.into())),
-        };
-
-        ObjectStreamFuture::from_future(list(self.space.clone(), path))
+        ObjectStreamFuture::from_future(list(self.space.clone(), prefix))
     }
 
-    fn list_directory<P>(&self, dir: P) -> ObjectStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
         async fn list(space: FileSpace, directory: ObjectPath) -> StorageResult<ObjectStream> {
             let path = space.get_std_path(&directory)?;
-            let metadata = wrap_future(symlink_metadata(path.clone()), directory.clone()).await?;
+            let metadata = wrap_future(
+                symlink_metadata(space.retry.clone(), path.clone()),
+                directory.clone(),
+            )
+            .await?;
             if !metadata.is_dir() {
                 let stream = ObjectStream::from_stream(empty());
                 return Ok(stream);
@@ -437,38 +1037,41 @@ impl StorageBackend for FileBackend {
 
             Ok(ObjectStream::from_stream(
                 wrap_stream(
-                    wrap_future(read_dir(path.clone()), directory.clone()).await?,
+                    wrap_future(
+                        read_dir(space.retry.clone(), path.clone()),
+                        directory.clone(),
+                    )
+                    .await?,
                     directory.clone(),
                 )
                 .and_then(move |entry| {
                     let path_base = directory.clone();
-                    wrap_future(symlink_metadata(entry.path()), directory.clone()).map(
-                        move |result| match result {
-                            Ok(metadata) => {
-                                let file_name = match entry.file_name().into_string() {
-                                    Ok(s) => s,
-                                    Err(_) => {
-                                        return Err(error::invalid_data(Some(
-                                            "Unable to convert OSString.",
-                                        )))
-                                    }
-                                };
-
-                                let mut path = path_base.clone();
-                                path.push_part(&file_name);
-                                Ok(get_object(path, Some(metadata)))
-                            }
-                            Err(e) => Err(e),
-                        },
+                    wrap_future(
+                        symlink_metadata(space.retry.clone(), entry.path()),
+                        directory.clone(),
                     )
+                    .map(move |result| match result {
+                        Ok(metadata) => {
+                            let file_name = match entry.file_name().into_string() {
+                                Ok(s) => s,
+                                Err(_) => {
+                                    return Err(error::invalid_data(Some(
+                                        "Unable to convert OSString.",
+                                    )))
+                                }
+                            };
+
+                            let mut path = path_base.clone();
+                            path.push_part(&file_name);
+                            Ok(get_object(path, Some(metadata)))
+                        }
+                        Err(e) => Err(e),
+                    })
                 }),
             ))
         }
 
-        let mut path = match dir.try_into() {
-            Ok(p) => p,
-            Err(e) => return ObjectStreamFuture::from_value(Err(e.into())),
-        };
+        let mut path = dir;
 
         if !path.is_empty() && path.is_dir_prefix() {
             path.pop_part();
@@ -477,16 +1080,25 @@ impl StorageBackend for FileBackend {
         ObjectStreamFuture::from_future(list(self.space.clone(), path))
     }
 
-    fn get_object<P>(&self, path: P) -> ObjectFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
         async fn get(space: FileSpace, path: ObjectPath) -> StorageResult<Object> {
             let target = space.get_std_path(&path)?;
 
-            match symlink_metadata(target.clone()).await {
-                Ok(m) => Ok(get_object(path, Some(m))),
+            match symlink_metadata(space.retry.clone(), target.clone()).await {
+                Ok(m) => {
+                    let (user_metadata, content_type) = if m.is_file() {
+                        (read_user_metadata(&target), read_content_type(&target))
+                    } else {
+                        (None, None)
+                    };
+
+                    Ok(Object::from(FileObject {
+                        path,
+                        metadata: Some(m),
+                        user_metadata,
+                        content_type,
+                    }))
+                }
                 Err(e) => {
                     if e.kind() == io::ErrorKind::NotFound {
                         Err(error::not_found(path, Some(&e.to_string())))
@@ -497,11 +1109,6 @@ impl StorageBackend for FileBackend {
             }
         }
 
-        let path = match path.try_into() {
-            Ok(p) => p,
-            Err(e) => return ObjectFuture::from_value(Err(e.into())),
-        };
-
         if path.is_dir_prefix() {
             return ObjectFuture::from_value(Err(error::invalid_path(
                 path,
@@ -512,82 +1119,146 @@ impl StorageBackend for FileBackend {
         ObjectFuture::from_future(get(self.space.clone(), path))
     }
 
-    fn get_file_stream<P>(&self, path: P) -> DataStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
         async fn read(space: FileSpace, path: ObjectPath) -> StorageResult<DataStream> {
             let target = space.get_std_path(&path)?;
 
-            let metadata = wrap_future(symlink_metadata(target.clone()), path.clone()).await?;
+            let metadata = wrap_future(
+                symlink_metadata(space.retry.clone(), target.clone()),
+                path.clone(),
+            )
+            .await?;
             if !metadata.is_file() {
                 return Err(error::not_found(path, None));
             }
 
-            let file = wrap_future(File::open(target), path.clone()).await?;
+            let file = wrap_future(File::open(space.retry.clone(), target), path.clone()).await?;
             Ok(DataStream::from_stream(
                 ReaderStream::<tokio_fs::File>::stream(file, INITIAL_BUFFER_SIZE, MIN_BUFFER_SIZE)
                     .map_err(move |e| get_storage_error(e, path.clone())),
             ))
         }
 
-        match path.try_into() {
-            Ok(p) => DataStreamFuture::from_future(read(self.space.clone(), p)),
-            Err(e) => DataStreamFuture::from_value(Err(e.into())),
+        DataStreamFuture::from_future(read(self.space.clone(), path))
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        async fn read(
+            space: FileSpace,
+            path: ObjectPath,
+            offset: u64,
+            length: Option<u64>,
+        ) -> StorageResult<DataStream> {
+            let target = space.get_std_path(&path)?;
+
+            let metadata = wrap_future(
+                symlink_metadata(space.retry.clone(), target.clone()),
+                path.clone(),
+            )
+            .await?;
+            if !metadata.is_file() {
+                return Err(error::not_found(path, None));
+            }
+
+            let mut file =
+                wrap_future(File::open(space.retry.clone(), target), path.clone()).await?;
+            if offset > 0 {
+                wrap_future(file.seek(SeekFrom::Start(offset)), path.clone()).await?;
+            }
+
+            let stream = DataStream::from_stream(
+                ReaderStream::<tokio_fs::File>::stream(file, INITIAL_BUFFER_SIZE, MIN_BUFFER_SIZE)
+                    .map_err(move |e| get_storage_error(e, path.clone())),
+            );
+            Ok(DataStream::from_stream(RangeStream::new(stream, 0, length)))
         }
+
+        DataStreamFuture::from_future(read(self.space.clone(), path, offset, length))
     }
 
-    fn delete_object<P>(&self, path: P) -> OperationCompleteFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-    {
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
         async fn delete(space: FileSpace, path: ObjectPath) -> StorageResult<()> {
             let target = space.get_std_path(&path)?;
-            let metadata = wrap_future(symlink_metadata(target.clone()), path.clone()).await?;
+            let metadata = wrap_future(
+                symlink_metadata(space.retry.clone(), target.clone()),
+                path.clone(),
+            )
+            .await?;
 
             if !metadata.is_dir() {
-                wrap_future(remove_file(target.clone()), path.clone()).await
+                wrap_future(
+                    remove_file(space.retry.clone(), target.clone()),
+                    path.clone(),
+                )
+                .await
             } else {
                 delete_directory(space, path).await
             }
         }
 
-        match path.try_into() {
-            Ok(p) => OperationCompleteFuture::from_future(delete(self.space.clone(), p)),
-            Err(e) => OperationCompleteFuture::from_value(Err(e.into())),
-        }
+        OperationCompleteFuture::from_future(delete(self.space.clone(), path))
     }
 
-    fn write_file_from_stream<S, I, E, P>(&self, info: P, stream: S) -> WriteCompleteFuture
-    where
-        S: Stream<Item = Result<I, E>> + Send + 'static,
-        I: IntoBuf + 'static,
-        E: Into<StorageError> + 'static,
-        P: TryInto<UploadInfo>,
-        P::Error: Into<StorageError>,
-    {
-        async fn write<S>(
-            space: FileSpace,
+    fn move_file(&self, source: ObjectPath, info: UploadInfo) -> MoveCompleteFuture {
+        async fn move_file(
+            backend: FileBackend,
+            source: ObjectPath,
             info: UploadInfo,
-            mut stream: S,
-        ) -> Result<(), TransferError>
-        where
-            S: Stream<Item = StorageResult<Data>> + Send + Unpin + 'static,
-        {
+        ) -> Result<(), TransferError> {
+            let space = backend.space.clone();
+            let from = space
+                .get_std_path(&source)
+                .map_err(TransferError::SourceError)?;
             let target = space
                 .get_std_path(&info.path)
                 .map_err(TransferError::TargetError)?;
 
-            match symlink_metadata(target.clone()).await {
+            let metadata = wrap_future(
+                symlink_metadata(space.retry.clone(), from.clone()),
+                source.clone(),
+            )
+            .await
+            .map_err(TransferError::SourceError)?;
+            if metadata.is_dir() {
+                return Err(TransferError::SourceError(error::not_found(
+                    source,
+                    Some("Only files can be moved."),
+                )));
+            }
+
+            if from == target {
+                // Moving a file onto itself leaves it and its metadata alone.
+                return match info.write_mode {
+                    WriteMode::FailIfExists => Err(TransferError::TargetError(
+                        error::already_exists(info.path, None),
+                    )),
+                    _ => Ok(()),
+                };
+            }
+
+            match symlink_metadata(space.retry.clone(), target.clone()).await {
+                Ok(_) if info.write_mode == WriteMode::FailIfExists => {
+                    return Err(TransferError::TargetError(error::already_exists(
+                        info.path, None,
+                    )));
+                }
+                Ok(_) if info.write_mode == WriteMode::IgnoreIfExists => return Ok(()),
                 Ok(m) => {
+                    // Renaming replaces files but not directories.
                     if m.is_dir() {
-                        delete_directory(space, info.path.clone())
-                            .await
-                            .map_err(TransferError::TargetError)?;
-                    } else {
-                        wrap_future(remove_file(target.clone()), info.path.clone())
+                        if from.starts_with(&target) {
+                            return Err(TransferError::TargetError(error::invalid_path(
+                                info.path,
+                                Some("Cannot replace a directory containing the source."),
+                            )));
+                        }
+
+                        delete_directory(space.clone(), info.path.clone())
                             .await
                             .map_err(TransferError::TargetError)?;
                     }
@@ -599,9 +1270,91 @@ impl StorageBackend for FileBackend {
                 }
             };
 
-            let mut file = wrap_future(File::create(target.clone()), info.path.clone())
-                .await
-                .map_err(TransferError::TargetError)?;
+            // Something may have been created at the target since the check
+            // above. Linking fails rather than replacing it, filesystems
+            // without links fall back to a rename which doesn't.
+            let moved = match info.write_mode {
+                WriteMode::Overwrite => rename(space.retry.clone(), from.clone(), target.clone())
+                    .await
+                    .map(|()| false),
+                _ => match hard_link(space.retry.clone(), from.clone(), target.clone()).await {
+                    Err(ref e)
+                        if e.kind() != io::ErrorKind::AlreadyExists && !is_cross_device(e) =>
+                    {
+                        rename(space.retry.clone(), from.clone(), target.clone())
+                            .await
+                            .map(|()| false)
+                    }
+                    r => r.map(|()| true),
+                },
+            };
+
+            match moved {
+                Ok(false) => (),
+                Ok(true) => {
+                    wrap_future(remove_file(space.retry.clone(), from), source)
+                        .await
+                        .map_err(|e| {
+                            warn!("Failed to remove a linked file: {}", e);
+                            TransferError::SourceError(e)
+                        })?;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    return match info.write_mode {
+                        WriteMode::IgnoreIfExists => Ok(()),
+                        _ => Err(TransferError::TargetError(error::already_exists(
+                            info.path, None,
+                        ))),
+                    };
+                }
+                Err(ref e) if is_cross_device(e) => {
+                    trace!("Copying {} to {} across devices.", source, info.path);
+                    backend.copy_file(source.clone(), info).await?;
+                    // The target is complete even if the source can't be
+                    // removed, the error says which side failed.
+                    return backend.delete_object(source).await.map_err(|e| {
+                        warn!("Failed to remove a file moved across devices: {}", e);
+                        TransferError::SourceError(e)
+                    });
+                }
+                Err(e) => return Err(TransferError::TargetError(get_storage_error(e, info.path))),
+            }
+
+            // The renamed file still has the source's metadata.
+            if let Err(e) = clear_stored_metadata(&target) {
+                warn!("Failed to clear file metadata: {}", e);
+            }
+            apply_upload_info(&target, &info);
+            Ok(())
+        }
+
+        MoveCompleteFuture::from_future(move_file(self.clone(), source, info))
+    }
+
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        async fn create(space: FileSpace, path: ObjectPath) -> StorageResult<()> {
+            let target = space.get_std_path(&path)?;
+            wrap_future(create_dir_all(space.retry.clone(), target), path).await
+        }
+
+        OperationCompleteFuture::from_future(create(self.space.clone(), path))
+    }
+
+    fn write_file_from_stream(&self, info: UploadInfo, stream: DataStream) -> WriteCompleteFuture {
+        async fn write_data<S>(
+            mut file: File,
+            first: Option<Data>,
+            mut stream: S,
+            path: ObjectPath,
+        ) -> Result<(), TransferError>
+        where
+            S: Stream<Item = StorageResult<Data>> + Send + Unpin + 'static,
+        {
+            if let Some(data) = first {
+                if let Err(e) = file.write_all(&data).await {
+                    return Err(TransferError::TargetError(get_storage_error(e, path)));
+                }
+            }
 
             loop {
                 let option = stream.next().await;
@@ -610,7 +1363,7 @@ impl StorageBackend for FileBackend {
                     match file.write_all(&data).await {
                         Ok(()) => (),
                         Err(e) => {
-                            return Err(TransferError::TargetError(get_storage_error(e, info.path)))
+                            return Err(TransferError::TargetError(get_storage_error(e, path)))
                         }
                     };
                 } else {
@@ -620,34 +1373,98 @@ impl StorageBackend for FileBackend {
 
             match file.flush().await {
                 Ok(()) => (),
-                Err(e) => return Err(TransferError::TargetError(get_storage_error(e, info.path))),
+                Err(e) => return Err(TransferError::TargetError(get_storage_error(e, path))),
             }
 
             match file.shutdown().await {
                 Ok(()) => (),
-                Err(e) => return Err(TransferError::TargetError(get_storage_error(e, info.path))),
+                Err(e) => return Err(TransferError::TargetError(get_storage_error(e, path))),
             }
 
-            if let Some(time) = info.modified {
-                if let Err(e) = set_file_mtime(&target, FileTime::from_system_time(time)) {
-                    warn!("Failed to set file modification time: {}", e);
+            Ok(())
+        }
+
+        async fn write<S>(
+            space: FileSpace,
+            info: UploadInfo,
+            mut stream: S,
+        ) -> Result<(), TransferError>
+        where
+            S: Stream<Item = StorageResult<Data>> + Send + Unpin + 'static,
+        {
+            let target = space
+                .get_std_path(&info.path)
+                .map_err(TransferError::TargetError)?;
+
+            // Wait for the first chunk before touching the target so that a
+            // missing or broken source doesn't destroy existing data.
+            let first = match stream.next().await {
+                Some(result) => Some(result.map_err(TransferError::SourceError)?),
+                None => None,
+            };
+
+            match symlink_metadata(space.retry.clone(), target.clone()).await {
+                Ok(_) if info.write_mode == WriteMode::FailIfExists => {
+                    return Err(TransferError::TargetError(error::already_exists(
+                        info.path, None,
+                    )));
+                }
+                Ok(_) if info.write_mode == WriteMode::IgnoreIfExists => return Ok(()),
+                Ok(m) => {
+                    if m.is_dir() {
+                        delete_directory(space.clone(), info.path.clone())
+                            .await
+                            .map_err(TransferError::TargetError)?;
+                    } else {
+                        wrap_future(
+                            remove_file(space.retry.clone(), target.clone()),
+                            info.path.clone(),
+                        )
+                        .await
+                        .map_err(TransferError::TargetError)?;
+                    }
+                }
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        return Err(TransferError::TargetError(get_storage_error(e, info.path)));
+                    }
+                }
+            };
+
+            let file = match info.write_mode {
+                WriteMode::Overwrite => File::create(space.retry.clone(), target.clone()).await,
+                // Something may have been created since the check above.
+                _ => File::create_new(space.retry.clone(), target.clone()).await,
+            };
+            let file = match file {
+                Ok(file) => file,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::AlreadyExists
+                        && info.write_mode == WriteMode::IgnoreIfExists =>
+                {
+                    return Ok(())
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    return Err(TransferError::TargetError(error::already_exists(
+                        info.path, None,
+                    )))
+                }
+                Err(e) => return Err(TransferError::TargetError(get_storage_error(e, info.path))),
+            };
+
+            let result = write_data(file, first, stream, info.path.clone()).await;
+            if result.is_err() && info.cleans_up() {
+                trace!("Removing partially written file {}.", target.display());
+                if let Err(e) = remove_file(space.retry.clone(), target.clone()).await {
+                    warn!("Failed to remove partially written file: {}", e);
                 }
             }
+            result?;
 
+            apply_upload_info(&target, &info);
             Ok(())
         }
 
-        let info = match info.try_into() {
-            Ok(i) => i,
-            Err(e) => {
-                return WriteCompleteFuture::from_value(Err(TransferError::TargetError(e.into())))
-            }
-        };
-
-        WriteCompleteFuture::from_future(write(
-            self.space.clone(),
-            info,
-            Box::pin(into_data_stream(stream)),
-        ))
+        WriteCompleteFuture::from_future(write(self.space.clone(), info, stream))
     }
 }
@@ -0,0 +1,579 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Accesses files held by another process over gRPC. Included with the
+//! feature "remote".
+//!
+//! The [`RemoteBackend`](struct.RemoteBackend.html) talks to a server running
+//! the [`GrpcHandler`](../../serve/grpc/struct.GrpcHandler.html) which wraps
+//! any other `FileStore`. This lets thin clients access storage through a
+//! central broker that holds the real credentials.
+//!
+//! The protocol is defined in `proto/file_store.proto` in this crate's source
+//! so clients can be written in other languages. Each operation is a gRPC
+//! method and errors are returned inside the response messages rather than as
+//! gRPC statuses so they survive HTTP clients that can't read trailers. Any
+//! [`HttpClient`](../../http_client/trait.HttpClient.html) can be used, HTTP/2
+//! is not required.
+//!
+//! The remote store decides what the objects look like, for example listing
+//! a remote file backend will include directories. It also applies uploads'
+//! [`write_mode`](../../struct.UploadInfo.html#structfield.write_mode) so they
+//! are as atomic as its backend makes them.
+pub(crate) mod protocol;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use futures::future::ready;
+use futures::stream::{iter, once, StreamExt, TryStreamExt};
+use http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, TE};
+use http::{Method, Request, StatusCode};
+use log::trace;
+use prost::Message;
+
+use super::Backend;
+use crate::cache::ObjectCache;
+use crate::events::EventLog;
+use crate::http_client::{
+    default_client, HeadersClient, HttpClient, PoolSettings, Proxy, RequestBody, SharedHttpClient,
+    TlsSettings,
+};
+use crate::metrics::Metrics;
+use crate::types::*;
+use crate::{FileStore, StorageBackend};
+use protocol::*;
+
+/// The remote implementation for [`Object`](../../enum.Object.html).
+#[derive(Clone, Debug)]
+pub struct RemoteObject {
+    path: ObjectPath,
+    size: u64,
+    object_type: ObjectType,
+    modified: Option<SystemTime>,
+    etag: Option<String>,
+    metadata: Option<HashMap<String, String>>,
+    content_type: Option<String>,
+    checksum: Option<Checksum>,
+}
+
+impl ObjectInfo for RemoteObject {
+    fn path(&self) -> ObjectPath {
+        self.path.clone()
+    }
+
+    fn len(&self) -> u64 {
+        self.size
+    }
+
+    fn object_type(&self) -> ObjectType {
+        self.object_type
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    fn etag(&self) -> Option<String> {
+        self.etag.clone()
+    }
+
+    fn metadata(&self) -> Option<HashMap<String, String>> {
+        self.metadata.clone()
+    }
+
+    fn content_type(&self) -> Option<String> {
+        self.content_type.clone()
+    }
+
+    fn checksum(&self) -> Option<Checksum> {
+        self.checksum.clone()
+    }
+}
+
+/// Parses a checksum sent by the server, ignoring unknown algorithms.
+fn parse_checksum(checksum: &str) -> Option<Checksum> {
+    let mut parts = checksum.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("sha1"), Some(digest)) => Some(Checksum::new(ChecksumAlgorithm::Sha1, digest)),
+        _ => None,
+    }
+}
+
+fn new_object(message: ObjectMessage) -> StorageResult<Object> {
+    let kind = ObjectKind::from_i32(message.kind).unwrap_or(ObjectKind::Unknown);
+
+    Ok(Object::from(RemoteObject {
+        path: ObjectPath::new(message.path)?,
+        size: message.size,
+        object_type: kind.into(),
+        modified: message.modified.map(SystemTime::from),
+        etag: message.etag,
+        metadata: if message.has_metadata {
+            Some(message.metadata)
+        } else {
+            None
+        },
+        content_type: message.content_type,
+        checksum: message
+            .checksum
+            .as_ref()
+            .map(String::as_str)
+            .and_then(parse_checksum),
+    }))
+}
+
+fn object_result(response: ObjectResponse) -> StorageResult<Object> {
+    match response.result {
+        Some(ObjectResult::Object(object)) => new_object(object),
+        Some(ObjectResult::Error(error)) => Err(error.into_storage_error()),
+        None => Err(error::invalid_data(Some(
+            "Server returned an empty response",
+        ))),
+    }
+}
+
+fn data_result(response: DataResponse) -> StorageResult<Data> {
+    match response.result {
+        Some(DataResult::Data(data)) => Ok(Data::from(data)),
+        Some(DataResult::Error(error)) => Err(error.into_storage_error()),
+        None => Err(error::invalid_data(Some(
+            "Server returned an empty response",
+        ))),
+    }
+}
+
+#[derive(Debug)]
+struct RemoteSettings {
+    url: String,
+}
+
+/// The backend implementation for a remote store.
+#[derive(Debug, Clone)]
+pub struct RemoteBackend {
+    settings: Arc<RemoteSettings>,
+    client: SharedHttpClient,
+    events: EventLog,
+    metrics: Metrics,
+    cache: ObjectCache,
+    directories: DirectorySemantics,
+    strict_overwrites: bool,
+    cleanup_on_failure: bool,
+    limiter: Option<ConcurrencyLimiter>,
+    paths: PathPolicy,
+}
+
+impl RemoteBackend {
+    /// Creates a new [`FileStore`](../../enum.FileStore.html) instance using the
+    /// remote backend to connect to the server at the given url.
+    pub fn connect(url: &str) -> ConnectFuture {
+        RemoteBackend::builder(url).connect()
+    }
+
+    /// Creates a new [`RemoteBackendBuilder`](struct.RemoteBackendBuilder.html).
+    pub fn builder(url: &str) -> RemoteBackendBuilder {
+        RemoteBackendBuilder {
+            url: url.trim_end_matches('/').to_owned(),
+            client: None,
+            tls: Default::default(),
+            proxy: Proxy::from_env(),
+            pool: Default::default(),
+            user_agent_suffix: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    pub(crate) fn event_log(&self) -> &EventLog {
+        &self.events
+    }
+
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    pub(crate) fn object_cache(&self) -> &ObjectCache {
+        &self.cache
+    }
+
+    pub(crate) fn directory_semantics(&self) -> DirectorySemantics {
+        self.directories
+    }
+
+    pub(crate) fn set_directory_semantics(&mut self, semantics: DirectorySemantics) {
+        self.directories = semantics;
+    }
+
+    pub(crate) fn strict_overwrites(&self) -> bool {
+        self.strict_overwrites
+    }
+
+    pub(crate) fn set_strict_overwrites(&mut self, strict: bool) {
+        self.strict_overwrites = strict;
+    }
+
+    pub(crate) fn cleanup_on_failure(&self) -> bool {
+        self.cleanup_on_failure
+    }
+
+    pub(crate) fn set_cleanup_on_failure(&mut self, cleanup: bool) {
+        self.cleanup_on_failure = cleanup;
+    }
+
+    pub(crate) fn concurrency_limiter(&self) -> Option<&ConcurrencyLimiter> {
+        self.limiter.as_ref()
+    }
+
+    pub(crate) fn set_concurrency_limiter(&mut self, limiter: Option<ConcurrencyLimiter>) {
+        self.limiter = limiter;
+    }
+
+    pub(crate) fn path_policy(&self) -> &PathPolicy {
+        &self.paths
+    }
+
+    pub(crate) fn set_path_policy(&mut self, policy: PathPolicy) {
+        self.paths = policy;
+    }
+
+    /// Calls a method on the server and returns the response body.
+    async fn call(
+        client: SharedHttpClient,
+        settings: Arc<RemoteSettings>,
+        method: &'static str,
+        body: RequestBody,
+    ) -> StorageResult<DataStream> {
+        let url = format!("{}{}{}", settings.url, SERVICE_PATH, method);
+        trace!("Calling {}", url);
+
+        let mut request = Request::new(body);
+        *request.method_mut() = Method::POST;
+        *request.uri_mut() = match url.parse() {
+            Ok(uri) => uri,
+            Err(_) => {
+                return Err(error::invalid_settings(Some(&format!(
+                    "Invalid url {}",
+                    url
+                ))))
+            }
+        };
+        let headers = request.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_CONTENT_TYPE));
+        headers.insert(TE, HeaderValue::from_static("trailers"));
+
+        let response = client.request(request).await?;
+        if response.status() != StatusCode::OK {
+            return Err(error::service_error(Some(&format!(
+                "Server returned status {}",
+                response.status()
+            ))));
+        }
+
+        match response.headers().get(STATUS_HEADER) {
+            Some(status) if status != "0" => {
+                let message = response
+                    .headers()
+                    .get(MESSAGE_HEADER)
+                    .and_then(|m| m.to_str().ok())
+                    .unwrap_or("");
+                return Err(error::service_error(Some(&format!(
+                    "Server returned gRPC status {}: {}",
+                    status.to_str().unwrap_or(""),
+                    message
+                ))));
+            }
+            _ => (),
+        }
+
+        Ok(response.into_body())
+    }
+
+    /// Calls a method that takes a single message.
+    fn call_with<M>(&self, method: &'static str, message: &M) -> DataStreamFuture
+    where
+        M: Message,
+    {
+        DataStreamFuture::from_future(RemoteBackend::call(
+            self.client.clone(),
+            self.settings.clone(),
+            method,
+            RequestBody::Full(encode_frame(message)),
+        ))
+    }
+
+    fn list(&self, method: &'static str, path: ObjectPath) -> ObjectStreamFuture {
+        let response = self.call_with(
+            method,
+            &PathRequest {
+                path: path.to_string(),
+            },
+        );
+
+        ObjectStreamFuture::from_future(async move {
+            let stream = decode_messages::<ObjectResponse>(response.await?)
+                .and_then(|response| ready(object_result(response)));
+            Ok(ObjectStream::from_stream(stream))
+        })
+    }
+
+    fn status(&self, method: &'static str, response: DataStreamFuture) -> TransferFuture {
+        TransferFuture::from_future(async move {
+            let response = match response.await {
+                Ok(r) => r,
+                Err(e) => return Err(TransferError::SourceError(e)),
+            };
+
+            match first_message::<StatusResponse>(response).await {
+                Ok(StatusResponse { error: None }) => Ok(()),
+                Ok(StatusResponse { error: Some(e) }) => Err(e.into_transfer_error()),
+                Err(e) => {
+                    trace!("Invalid response to {}", method);
+                    Err(TransferError::SourceError(e))
+                }
+            }
+        })
+    }
+}
+
+/// A future that resolves once a transfer has completed.
+type TransferFuture = WrappedFuture<Result<(), TransferError>>;
+
+/// Used to build a [`RemoteBackend`](struct.RemoteBackend.html) with some
+/// custom settings.
+#[derive(Debug, Clone)]
+pub struct RemoteBackendBuilder {
+    url: String,
+    client: Option<SharedHttpClient>,
+    tls: TlsSettings,
+    proxy: Option<Proxy>,
+    pool: PoolSettings,
+    user_agent_suffix: Option<String>,
+    headers: HeaderMap,
+}
+
+impl RemoteBackendBuilder {
+    /// Sets the [`HttpClient`](../../http_client/trait.HttpClient.html) used
+    /// to send requests to the server.
+    ///
+    /// If not set the [default client](../../http_client/fn.default_client.html)
+    /// is used.
+    pub fn http_client<C>(mut self, client: C) -> RemoteBackendBuilder
+    where
+        C: HttpClient,
+    {
+        self.client = Some(Arc::new(client));
+        self
+    }
+
+    /// Sets the [`TlsSettings`](../../http_client/struct.TlsSettings.html)
+    /// used for connections to the server.
+    ///
+    /// These are only used by the default client, they are ignored if a
+    /// custom client is set with [`http_client`](#method.http_client).
+    pub fn tls(mut self, tls: TlsSettings) -> RemoteBackendBuilder {
+        self.tls = tls;
+        self
+    }
+
+    /// Sets the [`Proxy`](../../http_client/struct.Proxy.html) used for
+    /// connections to the server, `None` connects directly.
+    ///
+    /// Defaults to the proxy configured in the environment, see
+    /// [`Proxy::from_env`](../../http_client/struct.Proxy.html#method.from_env).
+    /// Like the TLS settings this is ignored if a custom client is set.
+    pub fn proxy(mut self, proxy: Option<Proxy>) -> RemoteBackendBuilder {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Sets the [`PoolSettings`](../../http_client/struct.PoolSettings.html)
+    /// for connections to the server.
+    ///
+    /// Like the TLS settings these are ignored if a custom client is set.
+    pub fn pool(mut self, pool: PoolSettings) -> RemoteBackendBuilder {
+        self.pool = pool;
+        self
+    }
+
+    /// Appends to the `User-Agent` sent with every request to the server.
+    ///
+    /// Unlike the TLS settings this also applies to a custom client.
+    pub fn user_agent_suffix(mut self, suffix: &str) -> RemoteBackendBuilder {
+        self.user_agent_suffix = Some(suffix.to_owned());
+        self
+    }
+
+    /// Sets a header sent with every request to the server, for example a tracing
+    /// header.
+    ///
+    /// Unlike the TLS settings this also applies to a custom client.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> RemoteBackendBuilder {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Creates a new remote [`FileStore`](../../enum.FileStore.html) using
+    /// this builder's settings.
+    pub fn connect(self) -> ConnectFuture {
+        ConnectFuture::from_future(async {
+            trace!("Connecting to remote store at {}", self.url);
+            let client = match self.client {
+                Some(c) => c,
+                None => default_client(&self.tls, self.proxy.clone(), &self.pool)?,
+            };
+            let client = HeadersClient::wrap(
+                client,
+                self.user_agent_suffix.as_ref().map(String::as_str),
+                &self.headers,
+            );
+
+            let backend = RemoteBackend {
+                settings: Arc::new(RemoteSettings { url: self.url }),
+                client,
+                events: Default::default(),
+                metrics: Default::default(),
+                cache: Default::default(),
+                directories: Default::default(),
+                strict_overwrites: false,
+                cleanup_on_failure: false,
+                limiter: None,
+                paths: Default::default(),
+            };
+
+            // Make sure we can connect.
+            backend
+                .status("Ping", backend.call_with("Ping", &Empty {}))
+                .await?;
+
+            Ok(FileStore::from(backend))
+        })
+    }
+}
+
+impl StorageBackend for RemoteBackend {
+    fn backend_type(&self) -> Backend {
+        Backend::Remote
+    }
+
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
+        self.list("ListObjects", prefix)
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        self.list("ListDirectory", dir)
+    }
+
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
+        let response = self.call_with(
+            "GetObject",
+            &PathRequest {
+                path: path.to_string(),
+            },
+        );
+
+        ObjectFuture::from_future(async move {
+            object_result(first_message::<ObjectResponse>(response.await?).await?)
+        })
+    }
+
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        let response = self.call_with(
+            "GetFileStream",
+            &PathRequest {
+                path: path.to_string(),
+            },
+        );
+
+        DataStreamFuture::from_future(async move {
+            let mut messages = Box::pin(decode_messages::<DataResponse>(response.await?));
+
+            // Errors finding the file are returned as the first message, fail
+            // early for those.
+            let first = match messages.next().await {
+                Some(message) => Some(data_result(message?)?),
+                None => None,
+            };
+
+            let rest = messages.and_then(|response| ready(data_result(response)));
+            Ok(DataStream::from_stream(iter(first.map(Ok)).chain(rest)))
+        })
+    }
+
+    fn copy_file(&self, source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
+        let response = self.call_with(
+            "CopyFile",
+            &TransferRequest {
+                source: source.to_string(),
+                target: Some(UploadMessage::new(&target)),
+            },
+        );
+
+        self.status("CopyFile", response)
+    }
+
+    fn move_file(&self, source: ObjectPath, target: UploadInfo) -> MoveCompleteFuture {
+        let response = self.call_with(
+            "MoveFile",
+            &TransferRequest {
+                source: source.to_string(),
+                target: Some(UploadMessage::new(&target)),
+            },
+        );
+
+        self.status("MoveFile", response)
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        let response = self.call_with(
+            "DeleteObject",
+            &PathRequest {
+                path: path.to_string(),
+            },
+        );
+
+        let status = self.status("DeleteObject", response);
+        OperationCompleteFuture::from_future(
+            async move { status.await.map_err(StorageError::from) },
+        )
+    }
+
+    fn write_file_from_stream(&self, info: UploadInfo, stream: DataStream) -> WriteCompleteFuture {
+        let header = WriteRequest {
+            part: Some(WritePart::Info(UploadMessage::new(&info))),
+        };
+
+        let chunks = stream.map_ok(|data| {
+            let messages: Vec<StorageResult<Data>> = data
+                .chunks(MAX_CHUNK_SIZE)
+                .map(|chunk| {
+                    Ok(encode_frame(&WriteRequest {
+                        part: Some(WritePart::Data(chunk.to_vec())),
+                    }))
+                })
+                .collect();
+            iter(messages)
+        });
+
+        let body = once(ready(Ok(encode_frame(&header)))).chain(chunks.try_flatten());
+
+        let response = DataStreamFuture::from_future(RemoteBackend::call(
+            self.client.clone(),
+            self.settings.clone(),
+            "WriteFile",
+            RequestBody::Stream(DataStream::from_stream(body)),
+        ));
+
+        self.status("WriteFile", response)
+    }
+}
@@ -0,0 +1,505 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The messages and framing shared by the remote backend and the
+//! [gRPC handler](../../../serve/grpc/index.html).
+//!
+//! The messages mirror `proto/file_store.proto`, keep the two in sync.
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{BufMut, BytesMut};
+use futures::future::ready;
+use futures::stream::{unfold, Stream, StreamExt, TryStreamExt};
+use prost::{Enumeration, Message, Oneof};
+
+use crate::types::*;
+
+/// The path prefix for every method of the service.
+pub(crate) const SERVICE_PATH: &str = "/file_store.v1.Storage/";
+
+/// The content type of requests and responses.
+pub(crate) const GRPC_CONTENT_TYPE: &str = "application/grpc+proto";
+
+/// The header holding the gRPC status code.
+pub(crate) const STATUS_HEADER: &str = "grpc-status";
+
+/// The header holding the gRPC status message.
+pub(crate) const MESSAGE_HEADER: &str = "grpc-message";
+
+/// The gRPC status for a method that the server doesn't know.
+pub(crate) const STATUS_UNIMPLEMENTED: u32 = 12;
+
+/// The largest chunk of file data sent in a single message.
+pub(crate) const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// The largest message that will be accepted.
+const MAX_MESSAGE_SIZE: usize = 4 * MAX_CHUNK_SIZE;
+
+/// The length of the prefix before every message.
+const FRAME_HEADER_SIZE: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub(crate) enum ErrorKind {
+    Other = 0,
+    ObjectPathParse = 1,
+    InvalidPath = 2,
+    NotFound = 3,
+    AlreadyExists = 4,
+    Cancelled = 5,
+    ConnectionFailed = 6,
+    ConnectionClosed = 7,
+    ServiceError = 8,
+    InvalidData = 9,
+    AccessDenied = 10,
+    AccessExpired = 11,
+    InvalidSettings = 12,
+    OverQuota = 13,
+    InternalError = 14,
+    PreconditionFailed = 15,
+    IntegrityError = 16,
+    ReadOnly = 17,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub(crate) enum ObjectKind {
+    File = 0,
+    Directory = 1,
+    Symlink = 2,
+    Unknown = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Enumeration)]
+pub(crate) enum WriteModeKind {
+    Overwrite = 0,
+    FailIfExists = 1,
+    IgnoreIfExists = 2,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct ErrorMessage {
+    #[prost(enumeration = "ErrorKind", tag = "1")]
+    pub kind: i32,
+    #[prost(string, tag = "2")]
+    pub path: String,
+    #[prost(string, tag = "3")]
+    pub detail: String,
+    /// For transfers, whether the error came from the target.
+    #[prost(bool, tag = "4")]
+    pub target: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(uint32, tag = "2")]
+    pub nanos: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Empty {}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct PathRequest {
+    #[prost(string, tag = "1")]
+    pub path: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct UploadMessage {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(message, optional, tag = "2")]
+    pub modified: Option<Timestamp>,
+    #[prost(bool, tag = "3")]
+    pub replace_directory: bool,
+    #[prost(bool, optional, tag = "4")]
+    pub cleanup_on_failure: Option<bool>,
+    #[prost(string, optional, tag = "5")]
+    pub idempotency_key: Option<String>,
+    #[prost(map = "string, string", tag = "6")]
+    pub metadata: HashMap<String, String>,
+    #[prost(string, optional, tag = "7")]
+    pub content_type: Option<String>,
+    #[prost(enumeration = "WriteModeKind", tag = "8")]
+    pub write_mode: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct TransferRequest {
+    #[prost(string, tag = "1")]
+    pub source: String,
+    #[prost(message, optional, tag = "2")]
+    pub target: Option<UploadMessage>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub(crate) enum WritePart {
+    /// Always the first message.
+    #[prost(message, tag = "1")]
+    Info(UploadMessage),
+    #[prost(bytes, tag = "2")]
+    Data(Vec<u8>),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct WriteRequest {
+    #[prost(oneof = "WritePart", tags = "1, 2")]
+    pub part: Option<WritePart>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct ObjectMessage {
+    #[prost(string, tag = "1")]
+    pub path: String,
+    #[prost(uint64, tag = "2")]
+    pub size: u64,
+    #[prost(enumeration = "ObjectKind", tag = "3")]
+    pub kind: i32,
+    #[prost(message, optional, tag = "4")]
+    pub modified: Option<Timestamp>,
+    #[prost(string, optional, tag = "5")]
+    pub etag: Option<String>,
+    /// Whether the object's metadata was loaded, an empty map is otherwise
+    /// indistinguishable from no metadata.
+    #[prost(bool, tag = "6")]
+    pub has_metadata: bool,
+    #[prost(map = "string, string", tag = "7")]
+    pub metadata: HashMap<String, String>,
+    #[prost(string, optional, tag = "8")]
+    pub content_type: Option<String>,
+    /// Formatted as `algorithm:digest`.
+    #[prost(string, optional, tag = "9")]
+    pub checksum: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub(crate) enum ObjectResult {
+    #[prost(message, tag = "1")]
+    Object(ObjectMessage),
+    #[prost(message, tag = "2")]
+    Error(ErrorMessage),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct ObjectResponse {
+    #[prost(oneof = "ObjectResult", tags = "1, 2")]
+    pub result: Option<ObjectResult>,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub(crate) enum DataResult {
+    #[prost(bytes, tag = "1")]
+    Data(Vec<u8>),
+    #[prost(message, tag = "2")]
+    Error(ErrorMessage),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct DataResponse {
+    #[prost(oneof = "DataResult", tags = "1, 2")]
+    pub result: Option<DataResult>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct StatusResponse {
+    #[prost(message, optional, tag = "1")]
+    pub error: Option<ErrorMessage>,
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Timestamp {
+        let duration = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        Timestamp {
+            seconds: duration.as_secs() as i64,
+            nanos: duration.subsec_nanos(),
+        }
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(timestamp: Timestamp) -> SystemTime {
+        UNIX_EPOCH + Duration::new(timestamp.seconds.max(0) as u64, timestamp.nanos)
+    }
+}
+
+impl From<ObjectType> for ObjectKind {
+    fn from(object_type: ObjectType) -> ObjectKind {
+        match object_type {
+            ObjectType::File => ObjectKind::File,
+            ObjectType::Directory => ObjectKind::Directory,
+            ObjectType::Symlink => ObjectKind::Symlink,
+            ObjectType::Unknown => ObjectKind::Unknown,
+        }
+    }
+}
+
+impl From<WriteMode> for WriteModeKind {
+    fn from(mode: WriteMode) -> WriteModeKind {
+        match mode {
+            WriteMode::Overwrite => WriteModeKind::Overwrite,
+            WriteMode::FailIfExists => WriteModeKind::FailIfExists,
+            WriteMode::IgnoreIfExists => WriteModeKind::IgnoreIfExists,
+        }
+    }
+}
+
+impl From<WriteModeKind> for WriteMode {
+    fn from(kind: WriteModeKind) -> WriteMode {
+        match kind {
+            WriteModeKind::Overwrite => WriteMode::Overwrite,
+            WriteModeKind::FailIfExists => WriteMode::FailIfExists,
+            WriteModeKind::IgnoreIfExists => WriteMode::IgnoreIfExists,
+        }
+    }
+}
+
+impl From<ObjectKind> for ObjectType {
+    fn from(kind: ObjectKind) -> ObjectType {
+        match kind {
+            ObjectKind::File => ObjectType::File,
+            ObjectKind::Directory => ObjectType::Directory,
+            ObjectKind::Symlink => ObjectType::Symlink,
+            ObjectKind::Unknown => ObjectType::Unknown,
+        }
+    }
+}
+
+impl UploadMessage {
+    pub fn new(info: &UploadInfo) -> UploadMessage {
+        UploadMessage {
+            path: info.path.to_string(),
+            modified: info.modified.map(Timestamp::from),
+            replace_directory: info.replace_directory,
+            cleanup_on_failure: info.cleanup_on_failure,
+            idempotency_key: info.idempotency_key.clone(),
+            metadata: info.metadata.clone(),
+            content_type: info.content_type.clone(),
+            write_mode: WriteModeKind::from(info.write_mode) as i32,
+        }
+    }
+
+    pub fn into_upload_info(self) -> StorageResult<UploadInfo> {
+        Ok(UploadInfo {
+            path: ObjectPath::new(self.path)?,
+            modified: self.modified.map(SystemTime::from),
+            replace_directory: self.replace_directory,
+            cleanup_on_failure: self.cleanup_on_failure,
+            idempotency_key: self.idempotency_key,
+            metadata: self.metadata,
+            content_type: self.content_type,
+            write_mode: WriteModeKind::from_i32(self.write_mode)
+                .map(WriteMode::from)
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl ErrorMessage {
+    pub fn new(error: &StorageError, target: bool) -> ErrorMessage {
+        let (kind, path) = match error.kind() {
+            StorageErrorKind::ObjectPathParse(s) => (ErrorKind::ObjectPathParse, s),
+            StorageErrorKind::InvalidPath(p) => (ErrorKind::InvalidPath, p.to_string()),
+            StorageErrorKind::NotFound(p) => (ErrorKind::NotFound, p.to_string()),
+            StorageErrorKind::AlreadyExists(p) => (ErrorKind::AlreadyExists, p.to_string()),
+            StorageErrorKind::PreconditionFailed(p) => {
+                (ErrorKind::PreconditionFailed, p.to_string())
+            }
+            StorageErrorKind::IntegrityError(p) => (ErrorKind::IntegrityError, p.to_string()),
+            StorageErrorKind::Cancelled => (ErrorKind::Cancelled, String::new()),
+            StorageErrorKind::ConnectionFailed => (ErrorKind::ConnectionFailed, String::new()),
+            StorageErrorKind::ConnectionClosed => (ErrorKind::ConnectionClosed, String::new()),
+            StorageErrorKind::ServiceError => (ErrorKind::ServiceError, String::new()),
+            StorageErrorKind::InvalidData => (ErrorKind::InvalidData, String::new()),
+            StorageErrorKind::AccessDenied => (ErrorKind::AccessDenied, String::new()),
+            StorageErrorKind::AccessExpired => (ErrorKind::AccessExpired, String::new()),
+            StorageErrorKind::InvalidSettings => (ErrorKind::InvalidSettings, String::new()),
+            StorageErrorKind::OverQuota => (ErrorKind::OverQuota, String::new()),
+            StorageErrorKind::ReadOnly => (ErrorKind::ReadOnly, String::new()),
+            StorageErrorKind::InternalError => (ErrorKind::InternalError, String::new()),
+            StorageErrorKind::Other => (ErrorKind::Other, String::new()),
+        };
+
+        ErrorMessage {
+            kind: kind as i32,
+            path,
+            detail: error.detail().unwrap_or_default().to_owned(),
+            target,
+        }
+    }
+
+    pub fn into_storage_error(self) -> StorageError {
+        let path = || ObjectPath::new(&self.path).unwrap_or_default();
+
+        let kind = match ErrorKind::from_i32(self.kind).unwrap_or(ErrorKind::Other) {
+            ErrorKind::ObjectPathParse => StorageErrorKind::ObjectPathParse(self.path.clone()),
+            ErrorKind::InvalidPath => StorageErrorKind::InvalidPath(path()),
+            ErrorKind::NotFound => StorageErrorKind::NotFound(path()),
+            ErrorKind::AlreadyExists => StorageErrorKind::AlreadyExists(path()),
+            ErrorKind::PreconditionFailed => StorageErrorKind::PreconditionFailed(path()),
+            ErrorKind::IntegrityError => StorageErrorKind::IntegrityError(path()),
+            ErrorKind::Cancelled => StorageErrorKind::Cancelled,
+            ErrorKind::ConnectionFailed => StorageErrorKind::ConnectionFailed,
+            ErrorKind::ConnectionClosed => StorageErrorKind::ConnectionClosed,
+            ErrorKind::ServiceError => StorageErrorKind::ServiceError,
+            ErrorKind::InvalidData => StorageErrorKind::InvalidData,
+            ErrorKind::AccessDenied => StorageErrorKind::AccessDenied,
+            ErrorKind::AccessExpired => StorageErrorKind::AccessExpired,
+            ErrorKind::InvalidSettings => StorageErrorKind::InvalidSettings,
+            ErrorKind::OverQuota => StorageErrorKind::OverQuota,
+            ErrorKind::ReadOnly => StorageErrorKind::ReadOnly,
+            ErrorKind::InternalError => StorageErrorKind::InternalError,
+            ErrorKind::Other => StorageErrorKind::Other,
+        };
+
+        if self.detail.is_empty() {
+            StorageError::new(kind, None)
+        } else {
+            StorageError::new(kind, Some(&self.detail))
+        }
+    }
+
+    pub fn into_transfer_error(self) -> TransferError {
+        if self.target {
+            TransferError::TargetError(self.into_storage_error())
+        } else {
+            TransferError::SourceError(self.into_storage_error())
+        }
+    }
+}
+
+impl StatusResponse {
+    pub fn from_result(result: StorageResult<()>) -> StatusResponse {
+        StatusResponse {
+            error: result.err().map(|e| ErrorMessage::new(&e, false)),
+        }
+    }
+
+    pub fn from_transfer_result(result: Result<(), TransferError>) -> StatusResponse {
+        StatusResponse {
+            error: match result {
+                Ok(()) => None,
+                Err(TransferError::SourceError(e)) => Some(ErrorMessage::new(&e, false)),
+                Err(TransferError::TargetError(e)) => Some(ErrorMessage::new(&e, true)),
+            },
+        }
+    }
+}
+
+/// Encodes a message with the gRPC length prefix.
+pub(crate) fn encode_frame<M: Message>(message: &M) -> Data {
+    let length = message.encoded_len();
+    let mut buffer = BytesMut::with_capacity(FRAME_HEADER_SIZE + length);
+    buffer.put_u8(0);
+    buffer.put_u32_be(length as u32);
+    // The buffer always has enough capacity.
+    let _ = message.encode(&mut buffer);
+    buffer.freeze()
+}
+
+struct FrameState {
+    stream: DataStream,
+    buffer: BytesMut,
+    done: bool,
+}
+
+async fn next_frame(mut state: FrameState) -> Option<(StorageResult<Data>, FrameState)> {
+    if state.done {
+        return None;
+    }
+
+    loop {
+        if state.buffer.len() >= FRAME_HEADER_SIZE {
+            if state.buffer[0] != 0 {
+                state.done = true;
+                return Some((
+                    Err(error::invalid_data(Some(
+                        "Compressed messages are not supported",
+                    ))),
+                    state,
+                ));
+            }
+
+            let length = ((state.buffer[1] as usize) << 24)
+                | ((state.buffer[2] as usize) << 16)
+                | ((state.buffer[3] as usize) << 8)
+                | (state.buffer[4] as usize);
+
+            if length > MAX_MESSAGE_SIZE {
+                state.done = true;
+                return Some((
+                    Err(error::invalid_data(Some("Message was too large"))),
+                    state,
+                ));
+            }
+
+            if state.buffer.len() >= FRAME_HEADER_SIZE + length {
+                state.buffer.advance(FRAME_HEADER_SIZE);
+                let frame = state.buffer.split_to(length).freeze();
+                return Some((Ok(frame), state));
+            }
+        }
+
+        match state.stream.next().await {
+            Some(Ok(data)) => state.buffer.extend_from_slice(&data),
+            Some(Err(e)) => {
+                state.done = true;
+                return Some((Err(e), state));
+            }
+            None => {
+                state.done = true;
+                if state.buffer.is_empty() {
+                    return None;
+                }
+                return Some((
+                    Err(error::invalid_data(Some(
+                        "Unexpected end of message stream",
+                    ))),
+                    state,
+                ));
+            }
+        }
+    }
+}
+
+/// Decodes a stream of length prefixed messages.
+pub(crate) fn decode_messages<M>(
+    stream: DataStream,
+) -> impl Stream<Item = StorageResult<M>> + Send + 'static
+where
+    M: Message + Default + Send + 'static,
+{
+    let state = FrameState {
+        stream,
+        buffer: BytesMut::new(),
+        done: false,
+    };
+
+    unfold(state, next_frame).and_then(|frame| {
+        ready(M::decode(frame).map_err(|e| error::invalid_data(Some(&e.to_string()))))
+    })
+}
+
+/// Reads the first message from a stream.
+pub(crate) async fn first_message<M>(stream: DataStream) -> StorageResult<M>
+where
+    M: Message + Default + Send + 'static,
+{
+    let mut messages = Box::pin(decode_messages::<M>(stream));
+    match messages.next().await {
+        Some(result) => result,
+        None => Err(error::invalid_data(Some("Expected a message"))),
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A synchronous API for accessing storage. Included with the feature
+//! "blocking".
+//!
+//! [`FileStoreSync`](struct.FileStoreSync.html) wraps a
+//! [`FileStore`](../enum.FileStore.html) along with its own runtime and
+//! exposes the most common operations as plain blocking methods. This is
+//! useful for command line tools and tests that do not want to deal with
+//! futures at all.
+//!
+//! The methods here must not be called from inside an existing runtime.
+use std::convert::TryInto;
+use std::fmt;
+
+use bytes::IntoBuf;
+use futures::future::ready;
+use futures::stream::{once, TryStreamExt};
+use tokio::runtime::Runtime;
+
+use crate::types::*;
+use crate::FileStore;
+
+/// A blocking wrapper around a [`FileStore`](../enum.FileStore.html).
+pub struct FileStoreSync {
+    store: FileStore,
+    runtime: Runtime,
+}
+
+impl fmt::Debug for FileStoreSync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FileStoreSync {{ store: {:?} }}", self.store)
+    }
+}
+
+impl FileStoreSync {
+    /// Wraps an already connected [`FileStore`](../enum.FileStore.html).
+    pub fn new(store: FileStore) -> StorageResult<FileStoreSync> {
+        Ok(FileStoreSync {
+            store,
+            runtime: Runtime::new()?,
+        })
+    }
+
+    /// Drives a [`ConnectFuture`](../type.ConnectFuture.html) to completion
+    /// and wraps the resulting [`FileStore`](../enum.FileStore.html).
+    pub fn connect(future: ConnectFuture) -> StorageResult<FileStoreSync> {
+        let runtime = Runtime::new()?;
+        let store = runtime.block_on(future)?;
+
+        Ok(FileStoreSync { store, runtime })
+    }
+
+    /// Gets the underlying [`FileStore`](../enum.FileStore.html).
+    pub fn store(&self) -> &FileStore {
+        &self.store
+    }
+
+    /// Lists the objects that are prefixed by the given prefix.
+    ///
+    /// See [`list_objects`](../enum.FileStore.html#method.list_objects)
+    /// for more details.
+    pub fn list<P>(&self, prefix: P) -> StorageResult<Vec<Object>>
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let prefix = match prefix.try_into() {
+            Ok(p) => p,
+            Err(e) => return Err(e.into()),
+        };
+        let store = &self.store;
+
+        self.runtime.block_on(async move {
+            store
+                .list_objects(prefix)
+                .await?
+                .try_collect::<Vec<Object>>()
+                .await
+        })
+    }
+
+    /// Retrieves the entire contents of the file at the given path.
+    ///
+    /// See [`get_file_stream`](../enum.FileStore.html#method.get_file_stream)
+    /// for more details.
+    pub fn get<P>(&self, path: P) -> StorageResult<Data>
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        self.runtime.block_on(self.store.read_to_bytes(path))
+    }
+
+    /// Writes the given data to the file at the given path, replacing anything
+    /// already there.
+    ///
+    /// See [`write_file_from_stream`](../enum.FileStore.html#method.write_file_from_stream)
+    /// for more details.
+    pub fn put<P, D>(&self, info: P, data: D) -> Result<(), TransferError>
+    where
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+        D: IntoBuf + Send + 'static,
+    {
+        let stream = once(ready(Ok::<D, StorageError>(data)));
+        self.runtime
+            .block_on(self.store.write_file_from_stream(info, stream))
+    }
+
+    /// Deletes the object at the given path.
+    ///
+    /// See [`delete_object`](../enum.FileStore.html#method.delete_object)
+    /// for more details.
+    pub fn delete<P>(&self, path: P) -> StorageResult<()>
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        self.runtime.block_on(self.store.delete_object(path))
+    }
+}
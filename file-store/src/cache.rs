@@ -0,0 +1,302 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Caching of object metadata.
+//!
+//! Looking up an object normally asks the backend every time, for B2 that
+//! means a listing request. Workloads like syncing or serving files look up
+//! the same paths over and over so a [`FileStore`](../enum.FileStore.html)
+//! can remember the objects it has found for a while, see
+//! [`FileStore::cache_objects`](../enum.FileStore.html#method.cache_objects).
+//!
+//! Lookups for paths that don't exist can also be remembered, usually for a
+//! shorter time, so that repeatedly checking for an optional file doesn't
+//! reach the backend each time.
+//!
+//! The cache is shared by all clones of a `FileStore`. Changes made through
+//! the `FileStore` remove the affected entries but changes made in any other
+//! way are only noticed once an entry expires.
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::events::now;
+use crate::types::*;
+
+/// The number of objects cached unless configured otherwise.
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+/// Configures the object cache of a [`FileStore`](../enum.FileStore.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheSettings {
+    ttl: Duration,
+    not_found_ttl: Option<Duration>,
+    capacity: usize,
+}
+
+impl CacheSettings {
+    /// Caches objects for `ttl` after they are looked up. Paths that aren't
+    /// found are not cached.
+    pub fn new(ttl: Duration) -> CacheSettings {
+        CacheSettings {
+            ttl,
+            not_found_ttl: None,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+
+    /// Remembers that a path was not found for `ttl`, `None` doesn't cache
+    /// missing paths.
+    pub fn not_found_ttl(mut self, ttl: Option<Duration>) -> CacheSettings {
+        self.not_found_ttl = ttl;
+        self
+    }
+
+    /// Sets the maximum number of objects cached. Once full the oldest
+    /// entries are dropped first.
+    pub fn capacity(mut self, capacity: usize) -> CacheSettings {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Gets how long objects are cached for.
+    pub fn time_to_live(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Gets how long missing paths are remembered for.
+    pub fn not_found_time_to_live(&self) -> Option<Duration> {
+        self.not_found_ttl
+    }
+
+    /// Gets the maximum number of objects cached.
+    pub fn max_entries(&self) -> usize {
+        self.capacity
+    }
+}
+
+enum Lookup {
+    Found(Object),
+    NotFound(StorageErrorKind, Option<String>),
+}
+
+impl Lookup {
+    fn result(&self) -> StorageResult<Object> {
+        match self {
+            Lookup::Found(object) => Ok(object.clone()),
+            Lookup::NotFound(kind, detail) => Err(StorageError::new(
+                kind.clone(),
+                detail.as_ref().map(String::as_str),
+            )),
+        }
+    }
+}
+
+struct Entry {
+    lookup: Lookup,
+    expires: SystemTime,
+}
+
+struct CacheState {
+    settings: CacheSettings,
+    entries: HashMap<ObjectPath, Entry>,
+    // Incremented whenever entries are invalidated so lookups that started
+    // before a change don't cache what they found.
+    generation: u64,
+}
+
+impl CacheState {
+    fn insert(&mut self, path: ObjectPath, lookup: Lookup) {
+        let ttl = match lookup {
+            Lookup::Found(_) => self.settings.ttl,
+            Lookup::NotFound(..) => match self.settings.not_found_ttl {
+                Some(ttl) => ttl,
+                None => return,
+            },
+        };
+
+        if self.settings.capacity == 0 {
+            return;
+        }
+
+        let now = now();
+        if self.entries.len() >= self.settings.capacity {
+            self.entries.retain(|_, entry| entry.expires > now);
+        }
+
+        if self.entries.len() >= self.settings.capacity {
+            // Drop whichever entry would expire first.
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.entries.insert(
+            path,
+            Entry {
+                lookup,
+                expires: now + ttl,
+            },
+        );
+    }
+}
+
+fn is_beneath(path: &ObjectPath, dir: &ObjectPath) -> bool {
+    path.parts().starts_with(&dir.parts())
+}
+
+/// The object cache of a backend. Disabled until configured, clones share
+/// the same entries.
+#[derive(Clone, Default)]
+pub(crate) struct ObjectCache {
+    state: Arc<Mutex<Option<CacheState>>>,
+}
+
+impl fmt::Debug for ObjectCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.state.lock() {
+            Ok(ref s) => match s.as_ref() {
+                Some(state) => write!(f, "ObjectCache({} entries)", state.entries.len()),
+                None => f.pad("ObjectCache(disabled)"),
+            },
+            Err(_) => f.pad("ObjectCache"),
+        }
+    }
+}
+
+impl ObjectCache {
+    /// Enables the cache with the given settings or disables it. Any cached
+    /// entries are dropped.
+    pub fn configure(&self, settings: Option<CacheSettings>) {
+        if let Ok(mut state) = self.state.lock() {
+            let generation = state.as_ref().map(|s| s.generation + 1).unwrap_or(0);
+            *state = settings.map(|settings| CacheState {
+                settings,
+                entries: HashMap::new(),
+                generation,
+            });
+        }
+    }
+
+    /// Drops all cached entries.
+    pub fn clear(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(ref mut state) = *state {
+                state.entries.clear();
+                state.generation += 1;
+            }
+        }
+    }
+
+    /// Drops the entries for a path and anything beneath it.
+    pub fn invalidate(&self, path: &ObjectPath) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(ref mut state) = *state {
+                state.entries.retain(|p, _| !is_beneath(p, path));
+                state.generation += 1;
+            }
+        }
+    }
+
+    fn get(&self, path: &ObjectPath) -> Option<StorageResult<Object>> {
+        let mut state = self.state.lock().ok()?;
+        let state = state.as_mut()?;
+
+        let expired = match state.entries.get(path) {
+            Some(entry) if entry.expires > now() => return Some(entry.lookup.result()),
+            Some(_) => true,
+            None => false,
+        };
+
+        if expired {
+            state.entries.remove(path);
+        }
+        None
+    }
+
+    fn generation(&self) -> Option<u64> {
+        match self.state.lock() {
+            Ok(state) => state.as_ref().map(|s| s.generation),
+            Err(_) => None,
+        }
+    }
+
+    fn insert(&self, generation: u64, path: ObjectPath, lookup: Lookup) {
+        if let Ok(mut state) = self.state.lock() {
+            if let Some(ref mut state) = *state {
+                if state.generation == generation {
+                    state.insert(path, lookup);
+                }
+            }
+        }
+    }
+
+    /// Returns the cached object for a path if there is one, otherwise waits
+    /// for the lookup and caches its result.
+    pub fn lookup<F>(&self, path: ObjectPath, lookup: F) -> ObjectFuture
+    where
+        F: Future<Output = StorageResult<Object>> + Send + 'static,
+    {
+        if let Some(result) = self.get(&path) {
+            return ObjectFuture::from_value(result);
+        }
+
+        let generation = match self.generation() {
+            Some(generation) => generation,
+            None => return ObjectFuture::from_future(lookup),
+        };
+
+        let cache = self.clone();
+        ObjectFuture::from_future(async move {
+            match lookup.await {
+                Ok(object) => {
+                    cache.insert(generation, path, Lookup::Found(object.clone()));
+                    Ok(object)
+                }
+                Err(e) => {
+                    if let StorageErrorKind::NotFound(_) = e.kind() {
+                        let detail = e.detail().map(ToOwned::to_owned);
+                        cache.insert(generation, path, Lookup::NotFound(e.kind(), detail));
+                    }
+                    Err(e)
+                }
+            }
+        })
+    }
+
+    /// Wraps an operation's future so the entries for the paths it changes
+    /// are dropped when it completes.
+    pub fn invalidate_after<F>(&self, paths: Vec<ObjectPath>, future: F) -> WrappedFuture<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let cache = self.clone();
+
+        WrappedFuture::<F::Output>::from_future(async move {
+            let result = future.await;
+            for path in &paths {
+                cache.invalidate(path);
+            }
+            result
+        })
+    }
+}
@@ -0,0 +1,500 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Content-addressed, deduplicated storage. Included with the feature "cas".
+//!
+//! A [`ChunkStore`](struct.ChunkStore.html) splits the files written to it
+//! into variable sized chunks using content defined chunking
+//! ([FastCDC](https://www.usenix.org/conference/atc16/technical-sessions/presentation/xia)).
+//! Each chunk is stored once, named by its SHA-256 hash, and every logical
+//! path gets a [`Manifest`](struct.Manifest.html) listing its chunks. Because
+//! chunk boundaries depend on the content rather than offsets, inserting data
+//! in a file only changes the chunks around the insertion so writing similar
+//! data again, for example repeated backups, only uploads the new chunks.
+//!
+//! Chunks and manifests are stored in any `FileStore` under two prefixes,
+//! `chunks` and `manifests` by default.
+//!
+//! Deleting a file only removes its manifest, call
+//! [`collect_garbage`](struct.ChunkStore.html#method.collect_garbage) to
+//! remove the chunks that are no longer used. Collecting garbage while files
+//! are being written may delete chunks that the new files need.
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+use bytes::{BytesMut, IntoBuf};
+use futures::stream::{iter, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::hashing::to_hex;
+use crate::types::*;
+use crate::utils::into_data_stream;
+use crate::{FileStore, StorageBackend};
+
+const KB: usize = 1024;
+
+/// A chunk of a file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    /// The hex encoded SHA-256 hash of the chunk.
+    pub hash: String,
+    /// The size of the chunk in bytes.
+    pub size: u64,
+}
+
+/// The list of chunks that make up a file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The chunks in order.
+    pub chunks: Vec<ChunkRef>,
+}
+
+/// The results of writing a file to a [`ChunkStore`](struct.ChunkStore.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteSummary {
+    /// The manifest written for the file.
+    pub manifest: Manifest,
+    /// The number of chunks that had to be uploaded.
+    pub new_chunks: usize,
+    /// The number of bytes that had to be uploaded.
+    pub new_bytes: u64,
+}
+
+/// A future that resolves to a [`Manifest`](struct.Manifest.html).
+pub type ManifestFuture = WrappedFuture<StorageResult<Manifest>>;
+
+/// A future that resolves to a [`WriteSummary`](struct.WriteSummary.html).
+pub type WriteSummaryFuture = WrappedFuture<StorageResult<WriteSummary>>;
+
+/// A future that resolves to the number of chunks removed.
+pub type GarbageFuture = WrappedFuture<StorageResult<usize>>;
+
+/// Finds the chunk boundaries in data.
+#[derive(Clone, Debug)]
+struct Chunker {
+    gear: Vec<u64>,
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+    /// Used before the average size, harder to match.
+    mask_small: u64,
+    /// Used after the average size, easier to match.
+    mask_large: u64,
+}
+
+impl Chunker {
+    fn new(min_size: usize, avg_size: usize, max_size: usize) -> Chunker {
+        // The gear table only needs to be random looking and identical every
+        // time, splitmix64 generates it from a fixed seed.
+        let mut seed: u64 = 0;
+        let gear = (0..256)
+            .map(|_| {
+                seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = seed;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                z ^ (z >> 31)
+            })
+            .collect();
+
+        // The hash is shifted left for every byte so the top bits depend on
+        // the most bytes.
+        let bits = (avg_size as f64).log2().round() as u32;
+        let mask = |bits: u32| ((1u64 << bits) - 1) << (64 - bits);
+
+        Chunker {
+            gear,
+            min_size,
+            avg_size,
+            max_size,
+            mask_small: mask(bits + 1),
+            mask_large: mask(bits - 1),
+        }
+    }
+
+    /// Finds the length of the first chunk in the data. If the data is shorter
+    /// than the maximum chunk size and no boundary is found returns `None`.
+    fn boundary(&self, data: &[u8]) -> Option<usize> {
+        if data.len() <= self.min_size {
+            return None;
+        }
+
+        let end = data.len().min(self.max_size);
+        let normal = self.avg_size.min(end);
+        let mut hash: u64 = 0;
+
+        for (i, byte) in data.iter().enumerate().take(normal).skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(self.gear[*byte as usize]);
+            if hash & self.mask_small == 0 {
+                return Some(i + 1);
+            }
+        }
+
+        for (i, byte) in data.iter().enumerate().take(end).skip(normal) {
+            hash = (hash << 1).wrapping_add(self.gear[*byte as usize]);
+            if hash & self.mask_large == 0 {
+                return Some(i + 1);
+            }
+        }
+
+        if end == self.max_size {
+            Some(end)
+        } else {
+            None
+        }
+    }
+}
+
+struct ChunkState {
+    stream: DataStream,
+    buffer: BytesMut,
+    chunker: Chunker,
+    done: bool,
+}
+
+async fn next_chunk(mut state: ChunkState) -> Option<(StorageResult<Data>, ChunkState)> {
+    loop {
+        // Boundaries are only searched for once a full chunk is buffered so
+        // the buffer is not rescanned as every piece of data arrives.
+        let buffered = state.buffer.len();
+        if buffered >= state.chunker.max_size || (state.done && buffered > 0) {
+            let length = state.chunker.boundary(&state.buffer).unwrap_or(buffered);
+            let chunk = state.buffer.split_to(length).freeze();
+            return Some((Ok(chunk), state));
+        }
+
+        if state.done {
+            return None;
+        }
+
+        match state.stream.next().await {
+            Some(Ok(data)) => state.buffer.extend_from_slice(&data),
+            Some(Err(e)) => {
+                state.done = true;
+                state.buffer.clear();
+                return Some((Err(e), state));
+            }
+            None => state.done = true,
+        }
+    }
+}
+
+fn chunk_stream(
+    stream: DataStream,
+    chunker: Chunker,
+) -> impl Stream<Item = StorageResult<Data>> + Send {
+    let state = ChunkState {
+        stream,
+        buffer: BytesMut::new(),
+        chunker,
+        done: false,
+    };
+
+    futures::stream::unfold(state, next_chunk)
+}
+
+fn is_not_found(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => true,
+        _ => false,
+    }
+}
+
+async fn read_all(store: &FileStore, path: ObjectPath) -> StorageResult<Vec<u8>> {
+    let chunks: Vec<Data> = store.get_file_stream(path).await?.try_collect().await?;
+    Ok(chunks.concat())
+}
+
+/// Stores files as deduplicated chunks in a [`FileStore`](../enum.FileStore.html).
+#[derive(Clone, Debug)]
+pub struct ChunkStore {
+    store: FileStore,
+    chunk_prefix: ObjectPath,
+    manifest_prefix: ObjectPath,
+    chunker: Chunker,
+}
+
+impl ChunkStore {
+    /// Creates a new `ChunkStore` in the given `FileStore` with chunks of
+    /// 256KB to 4MB, 1MB on average.
+    pub fn new(store: FileStore) -> ChunkStore {
+        ChunkStore {
+            store,
+            chunk_prefix: ObjectPath::new("chunks").unwrap(),
+            manifest_prefix: ObjectPath::new("manifests").unwrap(),
+            chunker: Chunker::new(256 * KB, 1024 * KB, 4096 * KB),
+        }
+    }
+
+    /// Sets the prefix that chunks are stored under.
+    pub fn chunk_prefix(mut self, prefix: ObjectPath) -> ChunkStore {
+        self.chunk_prefix = prefix;
+        self
+    }
+
+    /// Sets the prefix that manifests are stored under.
+    pub fn manifest_prefix(mut self, prefix: ObjectPath) -> ChunkStore {
+        self.manifest_prefix = prefix;
+        self
+    }
+
+    /// Sets the minimum, average and maximum chunk sizes.
+    ///
+    /// Smaller chunks find more duplicate data but need more objects and
+    /// requests. Changing the sizes of an existing store changes where chunk
+    /// boundaries fall so existing data will not be deduplicated against.
+    pub fn chunk_sizes(mut self, min: usize, avg: usize, max: usize) -> StorageResult<ChunkStore> {
+        if min < 64 || !(min < avg && avg < max) {
+            return Err(error::invalid_settings(Some(
+                "Chunk sizes must satisfy 64 <= min < avg < max.",
+            )));
+        }
+
+        self.chunker = Chunker::new(min, avg, max);
+        Ok(self)
+    }
+
+    fn chunk_path(&self, hash: &str) -> ObjectPath {
+        let mut path = self.chunk_prefix.clone();
+        path.push_part(&hash[..2]);
+        path.push_part(hash);
+        path
+    }
+
+    fn manifest_path(&self, path: &ObjectPath) -> ObjectPath {
+        self.manifest_prefix.join(path)
+    }
+
+    /// Writes a file, uploading only the chunks that aren't already stored.
+    pub fn write<P, S, I, E>(&self, path: P, stream: S) -> WriteSummaryFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+        S: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+    {
+        async fn write(
+            cas: ChunkStore,
+            path: ObjectPath,
+            stream: DataStream,
+        ) -> StorageResult<WriteSummary> {
+            let mut chunks = Box::pin(chunk_stream(stream, cas.chunker.clone()));
+            let mut summary = WriteSummary {
+                manifest: Manifest {
+                    size: 0,
+                    chunks: Vec::new(),
+                },
+                new_chunks: 0,
+                new_bytes: 0,
+            };
+
+            while let Some(chunk) = chunks.next().await {
+                let chunk = chunk?;
+                let hash = to_hex(&Sha256::digest(&chunk));
+                let size = chunk.len() as u64;
+                let chunk_path = cas.chunk_path(&hash);
+
+                match cas.store.get_object(chunk_path.clone()).await {
+                    Ok(_) => (),
+                    Err(ref e) if is_not_found(e) => {
+                        cas.store
+                            .write_file_from_stream(
+                                chunk_path,
+                                iter(vec![Ok::<_, StorageError>(chunk)]),
+                            )
+                            .await?;
+                        summary.new_chunks += 1;
+                        summary.new_bytes += size;
+                    }
+                    Err(e) => return Err(e),
+                }
+
+                summary.manifest.size += size;
+                summary.manifest.chunks.push(ChunkRef { hash, size });
+            }
+
+            let json = match serde_json::to_vec(&summary.manifest) {
+                Ok(j) => j,
+                Err(e) => return Err(error::internal_error(Some(&e.to_string()))),
+            };
+            cas.store
+                .write_file_from_stream(
+                    cas.manifest_path(&path),
+                    iter(vec![Ok::<_, StorageError>(Data::from(json))]),
+                )
+                .await?;
+
+            Ok(summary)
+        }
+
+        let path = match path.try_into() {
+            Ok(p) => p,
+            Err(e) => return WriteSummaryFuture::from_value(Err(e.into())),
+        };
+
+        WriteSummaryFuture::from_future(write(
+            self.clone(),
+            path,
+            DataStream::from_stream(into_data_stream(stream)),
+        ))
+    }
+
+    /// Reads the manifest for a file.
+    pub fn manifest<P>(&self, path: P) -> ManifestFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let path = match path.try_into() {
+            Ok(p) => p,
+            Err(e) => return ManifestFuture::from_value(Err(e.into())),
+        };
+
+        let store = self.store.clone();
+        let manifest_path = self.manifest_path(&path);
+        ManifestFuture::from_future(async move {
+            let json = match read_all(&store, manifest_path).await {
+                Ok(j) => j,
+                Err(ref e) if is_not_found(e) => return Err(error::not_found(path, None)),
+                Err(e) => return Err(e),
+            };
+
+            match serde_json::from_slice(&json) {
+                Ok(m) => Ok(m),
+                Err(e) => Err(error::invalid_data(Some(&format!(
+                    "Invalid manifest for {}: {}",
+                    path, e
+                )))),
+            }
+        })
+    }
+
+    /// Reads a file, the chunks are requested one at a time as the stream is
+    /// read.
+    ///
+    /// Chunks are checked against their hashes, corrupted chunks result in an
+    /// [`InvalidData`](../enum.StorageErrorKind.html#variant.InvalidData)
+    /// error.
+    pub fn read<P>(&self, path: P) -> DataStreamFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let manifest = self.manifest(path);
+        let cas = self.clone();
+
+        DataStreamFuture::from_future(async move {
+            let manifest = manifest.await?;
+            let chunks = iter(manifest.chunks).then(move |chunk| {
+                let store = cas.store.clone();
+                let chunk_path = cas.chunk_path(&chunk.hash);
+                async move {
+                    let data = Data::from(read_all(&store, chunk_path).await?);
+                    if to_hex(&Sha256::digest(&data)) != chunk.hash {
+                        return Err(error::invalid_data(Some(&format!(
+                            "Chunk {} is corrupt",
+                            chunk.hash
+                        ))));
+                    }
+                    Ok(data)
+                }
+            });
+
+            Ok(DataStream::from_stream(chunks))
+        })
+    }
+
+    /// Deletes a file's manifest. The chunks are left for
+    /// [`collect_garbage`](#method.collect_garbage).
+    pub fn delete<P>(&self, path: P) -> OperationCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => self.store.delete_object(self.manifest_path(&p)),
+            Err(e) => OperationCompleteFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Lists the files in the store.
+    pub fn list(&self) -> WrappedFuture<StorageResult<Vec<ObjectPath>>> {
+        let cas = self.clone();
+
+        WrappedFuture::<StorageResult<Vec<ObjectPath>>>::from_future(async move {
+            let objects: Vec<Object> = cas
+                .store
+                .list_objects(ObjectPath::new(format!("{}/", cas.manifest_prefix))?)
+                .await?
+                .try_collect()
+                .await?;
+
+            let parts = cas.manifest_prefix.parts().len();
+            Ok(objects
+                .into_iter()
+                .filter(|o| o.object_type() == ObjectType::File)
+                .map(|o| {
+                    let mut path = ObjectPath::empty();
+                    for part in o.path().parts().iter().skip(parts) {
+                        path.push_part(part);
+                    }
+                    path
+                })
+                .collect())
+        })
+    }
+
+    /// Deletes the chunks that no manifest refers to.
+    pub fn collect_garbage(&self) -> GarbageFuture {
+        let cas = self.clone();
+
+        GarbageFuture::from_future(async move {
+            let mut used = HashSet::new();
+            for path in cas.list().await? {
+                for chunk in cas.manifest(path).await?.chunks {
+                    used.insert(chunk.hash);
+                }
+            }
+
+            let chunks: Vec<Object> = cas
+                .store
+                .list_objects(ObjectPath::new(format!("{}/", cas.chunk_prefix))?)
+                .await?
+                .try_collect()
+                .await?;
+
+            let mut removed = 0;
+            for chunk in chunks {
+                if chunk.object_type() != ObjectType::File {
+                    continue;
+                }
+
+                let path = chunk.path();
+                let hash = path.parts().last().map(|s| (*s).to_owned());
+                if let Some(hash) = hash {
+                    if !used.contains(&hash) {
+                        StorageBackend::delete_object(&cas.store, path).await?;
+                        removed += 1;
+                    }
+                }
+            }
+
+            Ok(removed)
+        })
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compression of file data using
+//! [async-compression](https://docs.rs/async-compression). Included with the
+//! feature "compression".
+//!
+//! [`compress`](fn.compress.html) and [`decompress`](fn.decompress.html) wrap
+//! a [`DataStream`](../type.DataStream.html) so compressing a file on upload is
+//! just:
+//!
+//! ```ignore
+//! store.write_file_from_stream(path, compress(data, Encoding::Gzip)).await?;
+//! ```
+//!
+//! The [`archive`](../archive/index.html) module uses these for gzip compressed
+//! tar archives and deflated zip entries.
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use async_compression::stream::{
+    BrotliDecoder, BrotliEncoder, DeflateDecoder, DeflateEncoder, GzipDecoder, GzipEncoder,
+    ZlibDecoder, ZlibEncoder,
+};
+use flate2::Compression;
+use futures::stream::{Stream, TryStreamExt};
+
+use crate::types::*;
+
+/// The default brotli quality, a balance between speed and size.
+const BROTLI_QUALITY: u32 = 6;
+
+/// A compression format.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// The gzip format.
+    Gzip,
+    /// A raw deflate stream.
+    Deflate,
+    /// The zlib format.
+    Zlib,
+    /// The brotli format.
+    Brotli,
+}
+
+impl Encoding {
+    /// The name of the encoding as used in the HTTP `Content-Encoding` header.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Zlib => "zlib",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    /// The file extension conventionally used for files in this encoding.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gz",
+            Encoding::Deflate => "deflate",
+            Encoding::Zlib => "zz",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    /// Finds the encoding conventionally used for a file extension.
+    pub fn from_extension(extension: &str) -> Option<Encoding> {
+        match extension {
+            "gz" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            "zz" => Some(Encoding::Zlib),
+            "br" => Some(Encoding::Brotli),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = StorageError;
+
+    fn from_str(name: &str) -> StorageResult<Encoding> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(Encoding::Gzip),
+            "deflate" => Ok(Encoding::Deflate),
+            "zlib" => Ok(Encoding::Zlib),
+            "br" | "brotli" => Ok(Encoding::Brotli),
+            _ => Err(error::invalid_settings(Some(&format!(
+                "Unknown compression format '{}'",
+                name
+            )))),
+        }
+    }
+}
+
+fn into_io_stream(stream: DataStream) -> impl Stream<Item = io::Result<Data>> + Send + 'static {
+    stream.map_err(io::Error::from)
+}
+
+fn from_io_stream<S>(stream: S) -> DataStream
+where
+    S: Stream<Item = io::Result<Data>> + Send + 'static,
+{
+    DataStream::from_stream(stream.map_err(StorageError::from))
+}
+
+/// Compresses a stream of data.
+pub fn compress(stream: DataStream, encoding: Encoding) -> DataStream {
+    let stream = into_io_stream(stream);
+
+    match encoding {
+        Encoding::Gzip => from_io_stream(GzipEncoder::new(stream, Compression::default())),
+        Encoding::Deflate => from_io_stream(DeflateEncoder::new(stream, Compression::default())),
+        Encoding::Zlib => from_io_stream(ZlibEncoder::new(stream, Compression::default())),
+        Encoding::Brotli => from_io_stream(BrotliEncoder::new(stream, BROTLI_QUALITY)),
+    }
+}
+
+/// Decompresses a stream of data.
+///
+/// Invalid compressed data results in an
+/// [`InvalidData`](../enum.StorageErrorKind.html#variant.InvalidData) error.
+pub fn decompress(stream: DataStream, encoding: Encoding) -> DataStream {
+    let stream = into_io_stream(stream);
+
+    match encoding {
+        Encoding::Gzip => from_io_stream(GzipDecoder::new(stream)),
+        Encoding::Deflate => from_io_stream(DeflateDecoder::new(stream)),
+        Encoding::Zlib => from_io_stream(ZlibDecoder::new(stream)),
+        Encoding::Brotli => from_io_stream(BrotliDecoder::new(stream)),
+    }
+}
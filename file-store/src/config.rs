@@ -0,0 +1,174 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Declarative configuration of storage. Included with the feature "config".
+//!
+//! A [`StoreConfig`](enum.StoreConfig.html) describes a backend and its
+//! settings and can be deserialized from any format supported by
+//! [serde](https://serde.rs/), for example TOML, JSON or YAML. The backend is
+//! chosen by the `type` field:
+//!
+//! ```toml
+//! type = "b2"
+//! key_id = "<key id>"
+//! key = "<key>"
+//! prefix = "my-bucket/files"
+//! ```
+//!
+//! [`FileStore::from_config`](../enum.FileStore.html#method.from_config)
+//! connects to the described storage.
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "b2")]
+use crate::backends::b2::B2Backend;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+use crate::backends::file::FileBackend;
+use crate::types::*;
+
+/// Settings for the [file backend](../backends/file/index.html).
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// The directory to use as the root of the storage.
+    pub root: PathBuf,
+}
+
+/// Settings for the [b2 backend](../backends/b2/index.html).
+///
+/// Any optional settings that are not given use the same defaults as
+/// [`B2BackendBuilder`](../backends/b2/struct.B2BackendBuilder.html).
+#[cfg(feature = "b2")]
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct B2Config {
+    /// The application key id.
+    pub key_id: String,
+    /// The application key.
+    pub key: String,
+    /// A path prefix, generally a bucket name optionally followed by some
+    /// directories.
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// The API host.
+    #[serde(default)]
+    pub host: Option<String>,
+    /// The cut-off between normal and large file uploads.
+    #[serde(default)]
+    pub max_small_file_size: Option<u64>,
+    /// The maximum number of parallel API requests.
+    #[serde(default)]
+    pub max_requests: Option<usize>,
+    /// The User-Agent to send with requests.
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// A proxy url, see [`Proxy::parse`](../http_client/struct.Proxy.html#method.parse).
+    /// If not given the proxy is read from the environment.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Whether creating a directory writes a marker file.
+    #[serde(default)]
+    pub directory_markers: Option<bool>,
+}
+
+#[cfg(feature = "b2")]
+impl std::fmt::Debug for B2Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("B2Config")
+            .field("key_id", &self.key_id)
+            .field("prefix", &self.prefix)
+            .field("host", &self.host)
+            .field("max_small_file_size", &self.max_small_file_size)
+            .field("max_requests", &self.max_requests)
+            .field("user_agent", &self.user_agent)
+            .field("proxy", &self.proxy)
+            .field("directory_markers", &self.directory_markers)
+            .finish()
+    }
+}
+
+/// Describes a storage backend and its settings.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StoreConfig {
+    /// The [file backend](../backends/file/index.html).
+    #[cfg(all(feature = "file", not(feature = "wasm")))]
+    File(FileConfig),
+    /// The [b2 backend](../backends/b2/index.html).
+    #[cfg(feature = "b2")]
+    B2(B2Config),
+}
+
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+fn connect_file(config: FileConfig) -> ConnectFuture {
+    FileBackend::connect(&config.root)
+}
+
+#[cfg(feature = "b2")]
+fn connect_b2(config: B2Config) -> ConnectFuture {
+    use crate::http_client::Proxy;
+
+    let mut builder = B2Backend::builder(&config.key_id, &config.key);
+
+    if let Some(prefix) = config.prefix {
+        match ObjectPath::new(prefix) {
+            Ok(p) => builder = builder.prefix(p),
+            Err(e) => return ConnectFuture::from_value(Err(e)),
+        }
+    }
+
+    if let Some(host) = config.host {
+        builder = builder.host(&host);
+    }
+
+    if let Some(size) = config.max_small_file_size {
+        builder = builder.limit_small_file_size(size);
+    }
+
+    if let Some(requests) = config.max_requests {
+        builder = builder.limit_requests(requests);
+    }
+
+    if let Some(user_agent) = config.user_agent {
+        builder = builder.user_agent(&user_agent);
+    }
+
+    if let Some(markers) = config.directory_markers {
+        builder = builder.directory_markers(markers);
+    }
+
+    if let Some(proxy) = config.proxy {
+        match Proxy::parse(&proxy) {
+            Ok(p) => builder = builder.proxy(Some(p)),
+            Err(e) => return ConnectFuture::from_value(Err(e)),
+        }
+    }
+
+    builder.connect()
+}
+
+impl StoreConfig {
+    /// Connects to the storage described by this configuration.
+    pub fn connect(self) -> ConnectFuture {
+        match self {
+            #[cfg(all(feature = "file", not(feature = "wasm")))]
+            StoreConfig::File(config) => connect_file(config),
+            #[cfg(feature = "b2")]
+            StoreConfig::B2(config) => connect_b2(config),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Copying every file under a prefix.
+//!
+//! [`FileStore::copy_prefix`](../enum.FileStore.html#method.copy_prefix) lists
+//! the files under a prefix and copies each one to the same relative path under
+//! a target prefix. A failure to copy one file does not stop the others, the
+//! failures are collected in the returned
+//! [`CopySummary`](struct.CopySummary.html). [`CopyOptions`](struct.CopyOptions.html)
+//! control how many files are copied at once and can report progress as each
+//! file completes.
+use std::fmt;
+use std::sync::Arc;
+
+use futures::stream::{iter, StreamExt, TryStreamExt};
+
+use crate::types::*;
+use crate::FileStore;
+
+/// The number of files copied at once by default.
+pub const DEFAULT_CONCURRENCY: usize = 4;
+
+/// A future that resolves to a [`CopySummary`](struct.CopySummary.html).
+pub type CopyPrefixFuture = WrappedFuture<StorageResult<CopySummary>>;
+
+/// Progress through a prefix copy, reported after each file.
+#[derive(Clone, Debug)]
+pub struct CopyProgress {
+    /// The source path of the file that just finished.
+    pub path: ObjectPath,
+    /// Whether the file was copied successfully.
+    pub succeeded: bool,
+    /// The number of files finished so far, including failures.
+    pub completed: usize,
+    /// The total number of files being copied.
+    pub total: usize,
+    /// The number of bytes copied successfully so far.
+    pub bytes: u64,
+}
+
+type ProgressCallback = Arc<dyn Fn(&CopyProgress) + Send + Sync>;
+
+/// Options controlling a prefix copy.
+#[derive(Clone)]
+pub struct CopyOptions {
+    concurrency: usize,
+    progress: Option<ProgressCallback>,
+    limiter: Option<ConcurrencyLimiter>,
+}
+
+impl Default for CopyOptions {
+    fn default() -> CopyOptions {
+        CopyOptions::new()
+    }
+}
+
+impl fmt::Debug for CopyOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CopyOptions")
+            .field("concurrency", &self.concurrency)
+            .field("progress", &self.progress.is_some())
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
+
+impl CopyOptions {
+    /// Creates options that copy [`DEFAULT_CONCURRENCY`](constant.DEFAULT_CONCURRENCY.html)
+    /// files at once without reporting progress.
+    pub fn new() -> CopyOptions {
+        CopyOptions {
+            concurrency: DEFAULT_CONCURRENCY,
+            progress: None,
+            limiter: None,
+        }
+    }
+
+    /// Sets how many files are copied at once. At least one file is always
+    /// copied.
+    pub fn concurrency(mut self, concurrency: usize) -> CopyOptions {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Makes each file copy wait for a permit from a
+    /// [`ConcurrencyLimiter`](../struct.ConcurrencyLimiter.html), on top of
+    /// the [concurrency](#method.concurrency) of this copy. Share the limiter
+    /// with other copies and stores to bound the transfers made in total.
+    pub fn limiter(mut self, limiter: ConcurrencyLimiter) -> CopyOptions {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Calls `callback` each time a file finishes copying.
+    pub fn on_progress<F>(mut self, callback: F) -> CopyOptions
+    where
+        F: Fn(&CopyProgress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(callback));
+        self
+    }
+}
+
+/// The outcome of a prefix copy.
+#[derive(Debug, Default)]
+pub struct CopySummary {
+    /// The source paths of the files that were copied.
+    pub copied: Vec<ObjectPath>,
+    /// The source paths of the files that failed to copy along with the
+    /// error.
+    pub failed: Vec<(ObjectPath, TransferError)>,
+    /// The number of bytes copied.
+    pub bytes: u64,
+}
+
+impl CopySummary {
+    /// Returns whether every file was copied.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// Lists the files, but not directories, under a prefix.
+pub(crate) async fn list_files(
+    store: &FileStore,
+    prefix: &ObjectPath,
+) -> StorageResult<Vec<Object>> {
+    let list_prefix = if prefix.is_empty() {
+        prefix.clone()
+    } else {
+        ObjectPath::new(format!("{}/", prefix))?
+    };
+
+    let objects: Vec<Object> = store.list_objects(list_prefix).await?.try_collect().await?;
+    Ok(objects
+        .into_iter()
+        .filter(|o| o.object_type() == ObjectType::File)
+        .collect())
+}
+
+/// Moves a path from beneath the `source` prefix to beneath `target`.
+pub(crate) fn target_path(
+    source: &ObjectPath,
+    target: &ObjectPath,
+    path: &ObjectPath,
+) -> ObjectPath {
+    let mut relative = ObjectPath::empty();
+    for part in path.parts().iter().skip(source.parts().len()) {
+        relative.push_part(part);
+    }
+    target.join(&relative)
+}
+
+pub(crate) async fn copy_prefix(
+    store: FileStore,
+    source: ObjectPath,
+    target: ObjectPath,
+    options: CopyOptions,
+) -> StorageResult<CopySummary> {
+    // Everything is listed up front so files copied inside the source prefix
+    // are not copied again.
+    let files = list_files(&store, &source).await?;
+    let total = files.len();
+
+    let store = match options.limiter {
+        Some(ref limiter) => store.with_concurrency_limiter(limiter.clone()),
+        None => store,
+    };
+
+    let copies = files.into_iter().map(|object| {
+        let path = object.path();
+        let upload = object.as_upload(target_path(&source, &target, &path));
+        let store = store.clone();
+
+        async move {
+            let result = match upload {
+                Ok(info) => store.copy_file(path.clone(), info).await,
+                Err(e) => Err(TransferError::TargetError(e)),
+            };
+            (path, object.len(), result)
+        }
+    });
+
+    let mut summary = CopySummary::default();
+    let mut results = iter(copies).buffer_unordered(options.concurrency);
+    while let Some((path, len, result)) = results.next().await {
+        let succeeded = result.is_ok();
+        match result {
+            Ok(()) => {
+                summary.bytes += len;
+                summary.copied.push(path.clone());
+            }
+            Err(e) => summary.failed.push((path.clone(), e)),
+        }
+
+        if let Some(ref callback) = options.progress {
+            callback(&CopyProgress {
+                path,
+                succeeded,
+                completed: summary.copied.len() + summary.failed.len(),
+                total,
+                bytes: summary.bytes,
+            });
+        }
+    }
+
+    Ok(summary)
+}
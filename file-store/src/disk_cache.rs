@@ -0,0 +1,507 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Caching of file data on the local disk.
+//!
+//! A [`CachingBackend`](struct.CachingBackend.html) wraps another
+//! [`StorageBackend`](../trait.StorageBackend.html), normally a
+//! [`FileStore`](../enum.FileStore.html), and keeps a copy of the files it
+//! downloads in a local directory. Reading a file again is served from the
+//! local copy so long as the object's size, modification time, etag and
+//! checksum are unchanged, which only costs a lookup of the object rather
+//! than downloading it again.
+//!
+//! The cache is limited to a maximum size, once full the least recently used
+//! files are removed. Changes made through the `CachingBackend` remove the
+//! affected files from the cache straight away. Included with the
+//! "disk-cache" feature.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use futures::stream::StreamExt;
+use log::{trace, warn};
+use tokio_io::AsyncWriteExt;
+
+use crate::backends::Backend;
+use crate::types::*;
+use crate::StorageBackend;
+
+/// The maximum size of the cache unless configured otherwise, 1GB.
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+/// A cached copy of a file.
+#[derive(Debug)]
+struct Entry {
+    file: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    etag: Option<String>,
+    checksum: Option<Checksum>,
+    last_used: u64,
+}
+
+impl Entry {
+    /// Checks whether the cached copy is of the object's current content.
+    fn matches(&self, object: &Object) -> bool {
+        self.len == object.len()
+            && self.modified == object.modified()
+            && self.etag == object.etag()
+            && self.checksum == object.checksum()
+    }
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<ObjectPath, Entry>,
+    size: u64,
+    clock: u64,
+    next_file: u64,
+}
+
+/// The files held in the cache directory. Clones share the same files.
+#[derive(Clone)]
+struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl fmt::Debug for DiskCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.state.lock() {
+            Ok(s) => write!(
+                f,
+                "DiskCache({}, {} files, {} bytes)",
+                self.dir.display(),
+                s.entries.len(),
+                s.size
+            ),
+            Err(_) => f.pad("DiskCache"),
+        }
+    }
+}
+
+async fn remove_files(files: Vec<PathBuf>) {
+    for file in files {
+        trace!("Removing cached file {}.", file.display());
+        if let Err(e) = tokio_fs::remove_file(file.clone()).await {
+            warn!("Failed to remove cached file {}: {}", file.display(), e);
+        }
+    }
+}
+
+fn is_beneath(path: &ObjectPath, dir: &ObjectPath) -> bool {
+    path.parts().starts_with(&dir.parts())
+}
+
+impl DiskCache {
+    /// Finds the cached copy of an object, marking it as recently used.
+    fn lookup(&self, path: &ObjectPath, object: &Object) -> Option<(PathBuf, u64)> {
+        let mut state = self.state.lock().ok()?;
+        state.clock += 1;
+        let clock = state.clock;
+
+        let entry = state.entries.get_mut(path)?;
+        if entry.matches(object) {
+            entry.last_used = clock;
+            Some((entry.file.clone(), entry.len))
+        } else {
+            None
+        }
+    }
+
+    /// Forgets the cached copies of paths and anything beneath them,
+    /// returning their files.
+    fn forget(&self, paths: &[ObjectPath]) -> Vec<PathBuf> {
+        let mut state = match self.state.lock() {
+            Ok(s) => s,
+            Err(_) => return Vec::new(),
+        };
+
+        let forgotten: Vec<ObjectPath> = state
+            .entries
+            .keys()
+            .filter(|p| paths.iter().any(|dir| is_beneath(p, dir)))
+            .cloned()
+            .collect();
+
+        forgotten
+            .iter()
+            .filter_map(|path| {
+                let entry = state.entries.remove(path)?;
+                state.size -= entry.len;
+                Some(entry.file)
+            })
+            .collect()
+    }
+
+    async fn remove(self, paths: Vec<ObjectPath>) {
+        remove_files(self.forget(&paths)).await
+    }
+
+    fn contains(&self, path: &ObjectPath) -> bool {
+        match self.state.lock() {
+            Ok(s) => s.entries.contains_key(path),
+            Err(_) => false,
+        }
+    }
+
+    fn size(&self) -> u64 {
+        match self.state.lock() {
+            Ok(s) => s.size,
+            Err(_) => 0,
+        }
+    }
+
+    fn next_file(&self) -> StorageResult<PathBuf> {
+        match self.state.lock() {
+            Ok(mut s) => {
+                s.next_file += 1;
+                Ok(self.dir.join(format!("{}.cache", s.next_file)))
+            }
+            Err(_) => Err(error::other_error(Some("The disk cache is poisoned."))),
+        }
+    }
+
+    /// Adds a downloaded file to the cache, evicting the least recently used
+    /// files to make space for it.
+    async fn insert(&self, path: ObjectPath, object: &Object, file: PathBuf) {
+        let mut removed = self.forget(&[path.clone()]);
+
+        if let Ok(mut state) = self.state.lock() {
+            state.clock += 1;
+            let last_used = state.clock;
+            state.size += object.len();
+            state.entries.insert(
+                path.clone(),
+                Entry {
+                    file,
+                    len: object.len(),
+                    modified: object.modified(),
+                    etag: object.etag(),
+                    checksum: object.checksum(),
+                    last_used,
+                },
+            );
+
+            while state.size > self.max_size {
+                let oldest = state
+                    .entries
+                    .iter()
+                    .filter(|(p, _)| **p != path)
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(p, _)| p.clone());
+
+                match oldest.and_then(|p| state.entries.remove(&p)) {
+                    Some(entry) => {
+                        state.size -= entry.len;
+                        removed.push(entry.file);
+                    }
+                    None => break,
+                }
+            }
+        }
+
+        remove_files(removed).await
+    }
+
+    /// Writes a downloaded stream to a new file in the cache directory.
+    async fn download(&self, mut stream: DataStream) -> StorageResult<(PathBuf, u64)> {
+        tokio_fs::create_dir_all(self.dir.clone()).await?;
+        let target = self.next_file()?;
+
+        let result: StorageResult<u64> = async {
+            let mut file = tokio_fs::File::create(target.clone()).await?;
+            let mut written: u64 = 0;
+            while let Some(data) = stream.next().await {
+                let data = data?;
+                file.write_all(&data).await?;
+                written += data.len() as u64;
+            }
+            file.flush().await?;
+            Ok(written)
+        }
+        .await;
+
+        match result {
+            Ok(written) => Ok((target, written)),
+            Err(e) => {
+                remove_files(vec![target]).await;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Reads from a cached file.
+async fn read_cached(
+    file: PathBuf,
+    len: u64,
+    offset: u64,
+    length: Option<u64>,
+) -> StorageResult<DataStream> {
+    let available = len.saturating_sub(offset);
+    let length = match length {
+        Some(l) => l.min(available),
+        None => available,
+    };
+    PathSource::new(file).read_range(offset, length).await
+}
+
+/// Reads a file, from the cache if possible, otherwise downloading it into
+/// the cache first.
+async fn read<B>(
+    inner: Arc<B>,
+    cache: DiskCache,
+    path: ObjectPath,
+    offset: u64,
+    length: Option<u64>,
+) -> StorageResult<DataStream>
+where
+    B: StorageBackend,
+{
+    let object = inner.get_object(path.clone()).await?;
+    if object.object_type() != ObjectType::File {
+        return inner.get_file_stream_range(path, offset, length).await;
+    }
+
+    if let Some((file, len)) = cache.lookup(&path, &object) {
+        match read_cached(file, len, offset, length).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                warn!("Failed to read cached copy of {}: {}", path, e);
+                cache.clone().remove(vec![path.clone()]).await;
+            }
+        }
+    }
+
+    // Ranged reads of files that aren't cached and files that could never
+    // fit in the cache are passed straight through.
+    if offset > 0 || length.is_some() || object.len() > cache.max_size {
+        return inner.get_file_stream_range(path, offset, length).await;
+    }
+
+    let stream = inner.get_file_stream(path.clone()).await?;
+    let (file, written) = match cache.download(stream).await {
+        Ok(d) => d,
+        Err(e) => match e.kind() {
+            StorageErrorKind::NotFound(_) => return Err(e),
+            _ => {
+                warn!("Failed to cache {}: {}", path, e);
+                return inner.get_file_stream(path).await;
+            }
+        },
+    };
+
+    if written != object.len() {
+        // The file changed while it was being downloaded.
+        remove_files(vec![file]).await;
+        return inner.get_file_stream(path).await;
+    }
+
+    trace!("Cached {} bytes of {}.", written, path);
+    cache.insert(path, &object, file.clone()).await;
+    read_cached(file, written, 0, None).await
+}
+
+/// A [`StorageBackend`](../trait.StorageBackend.html) that caches the files
+/// read from another backend on the local disk.
+///
+/// Clones share the same cache.
+#[derive(Debug)]
+pub struct CachingBackend<B> {
+    inner: Arc<B>,
+    cache: DiskCache,
+}
+
+impl<B> Clone for CachingBackend<B> {
+    fn clone(&self) -> CachingBackend<B> {
+        CachingBackend {
+            inner: self.inner.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<B> CachingBackend<B>
+where
+    B: StorageBackend,
+{
+    /// Caches files read from `inner` in the directory `dir`, holding up to
+    /// [`DEFAULT_MAX_SIZE`](constant.DEFAULT_MAX_SIZE.html) bytes.
+    ///
+    /// The directory is created when needed and should not be used for
+    /// anything else. Files left in it by earlier caches are not reused.
+    pub fn new<P: Into<PathBuf>>(inner: B, dir: P) -> CachingBackend<B> {
+        CachingBackend {
+            inner: Arc::new(inner),
+            cache: DiskCache {
+                dir: dir.into(),
+                max_size: DEFAULT_MAX_SIZE,
+                state: Default::default(),
+            },
+        }
+    }
+
+    /// Sets the maximum number of bytes of files to keep in the cache.
+    ///
+    /// Files larger than this are never cached.
+    pub fn max_size(mut self, max_size: u64) -> CachingBackend<B> {
+        self.cache.max_size = max_size;
+        self
+    }
+
+    /// Gets the backend that files are read from.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Gets the total size of the files currently cached.
+    pub fn cached_size(&self) -> u64 {
+        self.cache.size()
+    }
+
+    /// Checks whether there is a cached copy of a path. The copy may no
+    /// longer match the object.
+    pub fn is_cached(&self, path: &ObjectPath) -> bool {
+        self.cache.contains(path)
+    }
+
+    /// Removes cached copies of the paths once an operation that changes them
+    /// completes.
+    fn invalidate_after<R>(
+        &self,
+        paths: Vec<ObjectPath>,
+        future: WrappedFuture<R>,
+    ) -> WrappedFuture<R>
+    where
+        R: Send + 'static,
+    {
+        let cache = self.cache.clone();
+        WrappedFuture::from_future(async move {
+            let result = future.await;
+            cache.remove(paths).await;
+            result
+        })
+    }
+}
+
+impl<B> StorageBackend for CachingBackend<B>
+where
+    B: StorageBackend,
+{
+    fn backend_type(&self) -> Backend {
+        self.inner.backend_type()
+    }
+
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
+        self.inner.list_objects(prefix)
+    }
+
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        self.inner.list_objects_with_options(prefix, options)
+    }
+
+    fn lists_in_order(&self) -> bool {
+        self.inner.lists_in_order()
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        self.inner.list_directory(dir)
+    }
+
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
+        self.inner.get_object(path)
+    }
+
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        DataStreamFuture::from_future(read(self.inner.clone(), self.cache.clone(), path, 0, None))
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        DataStreamFuture::from_future(read(
+            self.inner.clone(),
+            self.cache.clone(),
+            path,
+            offset,
+            length,
+        ))
+    }
+
+    fn copy_file(&self, source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
+        self.invalidate_after(
+            vec![target.path.clone()],
+            self.inner.copy_file(source, target),
+        )
+    }
+
+    fn move_file(&self, source: ObjectPath, target: UploadInfo) -> MoveCompleteFuture {
+        self.invalidate_after(
+            vec![source.clone(), target.path.clone()],
+            self.inner.move_file(source, target),
+        )
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        self.invalidate_after(vec![path.clone()], self.inner.delete_object(path))
+    }
+
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        self.inner.create_directory(path)
+    }
+
+    fn delete_objects(&self, paths: Vec<ObjectPath>) -> DeleteSummaryFuture {
+        self.invalidate_after(paths.clone(), self.inner.delete_objects(paths))
+    }
+
+    fn write_file_from_stream(&self, info: UploadInfo, stream: DataStream) -> WriteCompleteFuture {
+        self.invalidate_after(
+            vec![info.path.clone()],
+            self.inner.write_file_from_stream(info, stream),
+        )
+    }
+
+    fn write_file_from_stream_returning(
+        &self,
+        info: UploadInfo,
+        stream: DataStream,
+    ) -> WrittenObjectFuture {
+        self.invalidate_after(
+            vec![info.path.clone()],
+            self.inner.write_file_from_stream_returning(info, stream),
+        )
+    }
+
+    fn write_file_from_source(
+        &self,
+        info: UploadInfo,
+        source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        self.invalidate_after(
+            vec![info.path.clone()],
+            self.inner.write_file_from_source(info, source),
+        )
+    }
+}
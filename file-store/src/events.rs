@@ -0,0 +1,266 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Records of the operations that change storage.
+//!
+//! [`FileStore::events`](../enum.FileStore.html#method.events) returns a
+//! stream of [`OperationEvent`](struct.OperationEvent.html)s, one for every
+//! copy, move, delete or write made through the `FileStore` (or any of its
+//! clones) once the operation completes. Applications can use these to keep an
+//! audit trail or to invalidate caches.
+//!
+//! [`FileStore::subscribe_events`](../enum.FileStore.html#method.subscribe_events)
+//! returns a more detailed stream of [`StorageEvent`](struct.StorageEvent.html)s
+//! covering every operation, including reads and listings, as it starts and
+//! again once it finishes or fails.
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "wasm")]
+use std::time::UNIX_EPOCH;
+use std::time::{Duration, SystemTime};
+
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+
+use crate::backends::Backend;
+use crate::types::*;
+
+/// The kind of operation that an event records.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// A file was copied.
+    Copy,
+    /// A file was moved.
+    Move,
+    /// An object was deleted.
+    Delete,
+    /// A file was written.
+    Write,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Copy => f.pad("copy"),
+            Operation::Move => f.pad("move"),
+            Operation::Delete => f.pad("delete"),
+            Operation::Write => f.pad("write"),
+        }
+    }
+}
+
+/// A record of a completed operation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OperationEvent {
+    /// The operation.
+    pub operation: Operation,
+    /// The backend that performed the operation.
+    pub backend: Backend,
+    /// The path the operation acted on, for copies and moves the source.
+    pub path: ObjectPath,
+    /// For copies and moves the target path.
+    pub target: Option<ObjectPath>,
+    /// When the operation started.
+    pub started: SystemTime,
+    /// How long the operation took.
+    pub duration: Duration,
+    /// The outcome of the operation.
+    pub result: Result<(), StorageErrorKind>,
+}
+
+/// A stream of [`OperationEvent`](struct.OperationEvent.html)s.
+pub type EventStream = WrappedStream<OperationEvent>;
+
+/// The point in an operation that a [`StorageEvent`](struct.StorageEvent.html)
+/// describes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EventPhase {
+    /// The operation has started.
+    Started,
+    /// The operation completed successfully.
+    Finished,
+    /// The operation failed.
+    Failed(StorageErrorKind),
+}
+
+/// An event describing an operation starting or ending.
+///
+/// Reads of a file's data finish once the data has been fully read or the
+/// stream is dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StorageEvent {
+    /// Identifies the operation, the events for the start and end of an
+    /// operation share the same id.
+    pub id: usize,
+    /// What happened to the operation.
+    pub phase: EventPhase,
+    /// The name of the operation, the same as the `StorageBackend` method,
+    /// for example `get_object`.
+    pub operation: &'static str,
+    /// The backend that performed the operation.
+    pub backend: Backend,
+    /// The path the operation acted on, for copies and moves the source.
+    pub path: ObjectPath,
+    /// For copies and moves the target path.
+    pub target: Option<ObjectPath>,
+    /// When the event happened.
+    pub time: SystemTime,
+    /// How long the operation took, for the end of an operation.
+    pub duration: Option<Duration>,
+    /// The bytes transferred by reads and writes, for the end of an
+    /// operation.
+    pub bytes: Option<u64>,
+}
+
+/// A stream of [`StorageEvent`](struct.StorageEvent.html)s.
+pub type StorageEventStream = WrappedStream<StorageEvent>;
+
+static NEXT_OPERATION_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Gets a new id for an operation.
+pub(crate) fn next_operation_id() -> usize {
+    NEXT_OPERATION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[cfg(not(feature = "wasm"))]
+pub(crate) fn now() -> SystemTime {
+    SystemTime::now()
+}
+
+/// The system clock isn't available to WebAssembly so use JavaScript's.
+#[cfg(feature = "wasm")]
+pub(crate) fn now() -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(js_sys::Date::now() as u64)
+}
+
+/// The results of operations that can be recorded.
+pub(crate) trait OperationResult {
+    fn error_kind(&self) -> Option<StorageErrorKind>;
+}
+
+impl<T> OperationResult for StorageResult<T> {
+    fn error_kind(&self) -> Option<StorageErrorKind> {
+        self.as_ref().err().map(StorageError::kind)
+    }
+}
+
+impl<T> OperationResult for Result<T, TransferError> {
+    fn error_kind(&self) -> Option<StorageErrorKind> {
+        match self {
+            Ok(_) => None,
+            Err(TransferError::SourceError(e)) => Some(e.kind()),
+            Err(TransferError::TargetError(e)) => Some(e.kind()),
+        }
+    }
+}
+
+/// Sends events to any subscribers. Clones share the same subscribers.
+#[derive(Clone, Default)]
+pub(crate) struct EventLog {
+    subscribers: Arc<Mutex<Vec<UnboundedSender<OperationEvent>>>>,
+    storage_subscribers: Arc<Mutex<Vec<UnboundedSender<StorageEvent>>>>,
+}
+
+impl fmt::Debug for EventLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.subscribers.lock() {
+            Ok(s) => write!(f, "EventLog({} subscribers)", s.len()),
+            Err(_) => f.pad("EventLog"),
+        }
+    }
+}
+
+impl EventLog {
+    pub fn subscribe(&self) -> EventStream {
+        let (sender, receiver) = unbounded();
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(sender);
+        }
+        EventStream::from_stream(receiver)
+    }
+
+    fn has_subscribers(&self) -> bool {
+        match self.subscribers.lock() {
+            Ok(s) => !s.is_empty(),
+            Err(_) => false,
+        }
+    }
+
+    fn emit(&self, event: OperationEvent) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            // Subscribers that have dropped their stream are forgotten.
+            subscribers.retain(|s| s.unbounded_send(event.clone()).is_ok());
+        }
+    }
+
+    pub fn subscribe_storage(&self) -> StorageEventStream {
+        let (sender, receiver) = unbounded();
+        if let Ok(mut subscribers) = self.storage_subscribers.lock() {
+            subscribers.push(sender);
+        }
+        StorageEventStream::from_stream(receiver)
+    }
+
+    pub fn has_storage_subscribers(&self) -> bool {
+        match self.storage_subscribers.lock() {
+            Ok(s) => !s.is_empty(),
+            Err(_) => false,
+        }
+    }
+
+    pub fn emit_storage(&self, event: StorageEvent) {
+        if let Ok(mut subscribers) = self.storage_subscribers.lock() {
+            subscribers.retain(|s| s.unbounded_send(event.clone()).is_ok());
+        }
+    }
+
+    /// Wraps an operation's future so an event is sent when it completes.
+    pub fn record<F>(
+        &self,
+        operation: Operation,
+        backend: Backend,
+        path: ObjectPath,
+        target: Option<ObjectPath>,
+        future: F,
+    ) -> WrappedFuture<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: OperationResult + Send + 'static,
+    {
+        let log = self.clone();
+
+        WrappedFuture::<F::Output>::from_future(async move {
+            let started = now();
+            let result = future.await;
+
+            if log.has_subscribers() {
+                log.emit(OperationEvent {
+                    operation,
+                    backend,
+                    path,
+                    target,
+                    started,
+                    duration: now().duration_since(started).unwrap_or_default(),
+                    result: match result.error_kind() {
+                        Some(kind) => Err(kind),
+                        None => Ok(()),
+                    },
+                });
+            }
+
+            result
+        })
+    }
+}
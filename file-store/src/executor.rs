@@ -0,0 +1,222 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for running futures regardless of the surrounding runtime.
+//!
+//! Some operations, uploading large files to B2 or renewing a
+//! [`Lease`](../lock/struct.Lease.html) for example, need to spawn background
+//! tasks. [`spawn`](fn.spawn.html) uses the current executor when there is one
+//! and otherwise falls back to a runtime that is created the first time it is
+//! needed, so the crate can be used outside of a tokio context. The fallback
+//! runtime requires the "executor" feature, without it a future spawned
+//! outside of a tokio context runs on a thread of its own and so can't use
+//! tokio's timers or IO.
+//!
+//! [`run`](fn.run.html) blocks the current thread until a future completes and
+//! [`spawn_blocking`](fn.spawn_blocking.html) moves blocking work off of the
+//! executor.
+use std::future::Future;
+#[cfg(not(feature = "wasm"))]
+use std::panic::{catch_unwind, AssertUnwindSafe};
+#[cfg(all(not(feature = "executor"), not(feature = "wasm")))]
+use std::thread;
+
+#[cfg(not(feature = "wasm"))]
+use futures::channel::oneshot;
+#[cfg(all(feature = "tokio-executor", not(feature = "wasm")))]
+use futures::future::poll_fn;
+#[cfg(not(feature = "wasm"))]
+use futures::future::TryFutureExt;
+#[cfg(all(feature = "executor", not(feature = "wasm")))]
+use lazy_static::lazy_static;
+
+#[cfg(not(feature = "wasm"))]
+use crate::types::*;
+
+#[cfg(all(feature = "executor", not(feature = "wasm")))]
+lazy_static! {
+    static ref FALLBACK: tokio::runtime::Runtime =
+        tokio::runtime::Runtime::new().expect("Unable to create the fallback runtime.");
+}
+
+/// Spawns a future to run in the background.
+///
+/// Inside a tokio context the future is spawned on the current executor,
+/// otherwise it is spawned on a lazily created fallback runtime.
+#[cfg(all(feature = "executor", not(feature = "wasm")))]
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    use log::warn;
+    use tokio_executor::{DefaultExecutor, Executor};
+
+    let mut executor = DefaultExecutor::current();
+    if executor.status().is_ok() {
+        if executor.spawn(Box::pin(future)).is_err() {
+            warn!("Unable to spawn a background task.");
+        }
+    } else {
+        FALLBACK.spawn(future);
+    }
+}
+
+/// Spawns a future to run in the background.
+///
+/// Inside a tokio context the future is spawned on the current executor,
+/// otherwise it runs on a thread of its own. Enable the "executor" feature to
+/// fall back to a separate runtime instead.
+#[cfg(all(
+    feature = "tokio-executor",
+    not(feature = "executor"),
+    not(feature = "wasm")
+))]
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    use log::warn;
+    use tokio_executor::{DefaultExecutor, Executor};
+
+    let mut executor = DefaultExecutor::current();
+    if executor.status().is_ok() {
+        if executor.spawn(Box::pin(future)).is_err() {
+            warn!("Unable to spawn a background task.");
+        }
+        return;
+    }
+
+    let spawned = thread::Builder::new()
+        .name("file-store-task".to_owned())
+        .spawn(move || futures::executor::block_on(future));
+    if let Err(e) = spawned {
+        warn!("Unable to spawn a background task: {}", e);
+    }
+}
+
+/// Spawns a future to run in the background on the browser's event loop.
+#[cfg(feature = "wasm")]
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    wasm_bindgen_futures::futures_0_3::spawn_local(future);
+}
+
+/// Runs a future to completion, blocking the current thread.
+///
+/// This uses the fallback runtime and so must not be called from inside an
+/// existing runtime.
+#[cfg(all(feature = "executor", not(feature = "wasm")))]
+pub fn run<F>(future: F) -> F::Output
+where
+    F: Future,
+{
+    FALLBACK.block_on(future)
+}
+
+/// Calls a function, turning a panic into an error.
+#[cfg(not(feature = "wasm"))]
+fn call<F, R>(function: F) -> StorageResult<R>
+where
+    F: FnOnce() -> R,
+{
+    catch_unwind(AssertUnwindSafe(function))
+        .map_err(|_| error::cancelled(Some("The blocking function panicked.")))
+}
+
+/// Runs a blocking function on a tokio thread pool's blocking threads. The
+/// function is handed back when not called from one of the pool's workers.
+#[cfg(all(feature = "tokio-executor", not(feature = "wasm")))]
+async fn pool_blocking<F, R>(function: F) -> Result<StorageResult<R>, F>
+where
+    F: FnOnce() -> R,
+{
+    use tokio_executor::threadpool::blocking;
+
+    // The closure is only called once the pool has room for it.
+    let mut function = Some(function);
+    let result = poll_fn(|_| blocking(|| call(function.take().unwrap()))).await;
+    match result {
+        Ok(result) => Ok(result),
+        Err(_) => Err(function.take().unwrap()),
+    }
+}
+
+/// Runs a blocking function on the fallback runtime's blocking threads.
+#[cfg(all(feature = "executor", not(feature = "wasm")))]
+async fn fallback_blocking<F, R>(function: F) -> StorageResult<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    FALLBACK.spawn(async move {
+        let result = match pool_blocking(function).await {
+            Ok(result) => result,
+            Err(_) => Err(error::internal_error(Some(
+                "The fallback runtime has no blocking threads.",
+            ))),
+        };
+        let _ = sender.send(result);
+    });
+
+    receiver
+        .map_err(|_| error::cancelled(Some("The fallback runtime shut down.")))
+        .await?
+}
+
+/// Runs a blocking function on a thread of its own.
+#[cfg(all(not(feature = "executor"), not(feature = "wasm")))]
+async fn fallback_blocking<F, R>(function: F) -> StorageResult<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    thread::Builder::new()
+        .name("file-store-blocking".to_owned())
+        .spawn(move || {
+            let _ = sender.send(call(function));
+        })?;
+
+    receiver
+        .map_err(|_| error::cancelled(Some("The blocking function panicked.")))
+        .await?
+}
+
+/// Runs a blocking function, returning a future that resolves with the
+/// function's result.
+///
+/// From inside a tokio runtime the function runs on the runtime's blocking
+/// threads. Otherwise it runs on the fallback runtime's blocking threads with
+/// the "executor" feature, or on a thread of its own without it.
+///
+/// The future resolves to a `Cancelled` error if the function panics.
+#[cfg(not(feature = "wasm"))]
+pub fn spawn_blocking<F, R>(function: F) -> WrappedFuture<StorageResult<R>>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    WrappedFuture::from_future(async move {
+        #[cfg(feature = "tokio-executor")]
+        let function = match pool_blocking(function).await {
+            Ok(result) => return result,
+            Err(function) => function,
+        };
+
+        fallback_blocking(function).await
+    })
+}
@@ -0,0 +1,903 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mounts storage as a local filesystem using FUSE. Included with the feature
+//! "mount".
+//!
+//! [`mount`](fn.mount.html) exposes everything under a path in a
+//! [`FileStore`](../enum.FileStore.html) at a local directory and returns a
+//! [`MountHandle`](struct.MountHandle.html). The filesystem stays mounted
+//! until the handle is unmounted or dropped.
+//!
+//! Files are read into memory when first read and writes are buffered in
+//! memory until the file is closed, at which point the whole file is written
+//! to storage. Directories that are created but never have files written to
+//! them only exist for as long as the filesystem is mounted on backends that
+//! don't support real directories.
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ::fuse::{
+    BackgroundSession, FileAttr, FileType, Filesystem, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, Request,
+};
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::{once, TryStreamExt};
+use libc::{c_int, EEXIST, EINVAL, EIO, EISDIR, ENOENT, ENOSPC, ENOTDIR, ENOTEMPTY, EPERM, EROFS};
+use log::{error, trace};
+use time::Timespec;
+use tokio::runtime::Runtime;
+
+use crate::types::*;
+use crate::FileStore;
+
+const ROOT_INODE: u64 = 1;
+
+/// Options for mounting storage.
+#[derive(Clone, Debug)]
+pub struct MountOptions {
+    read_only: bool,
+    allow_other: bool,
+    fs_name: Option<String>,
+    ttl: Duration,
+}
+
+impl Default for MountOptions {
+    fn default() -> MountOptions {
+        MountOptions {
+            read_only: false,
+            allow_other: false,
+            fs_name: None,
+            ttl: Duration::from_secs(1),
+        }
+    }
+}
+
+impl MountOptions {
+    /// Creates the default options.
+    pub fn new() -> MountOptions {
+        Default::default()
+    }
+
+    /// Sets whether the filesystem rejects any changes.
+    pub fn read_only(mut self, read_only: bool) -> MountOptions {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Sets whether users other than the one that mounted the filesystem may
+    /// access it. Most systems only allow this if configured to.
+    pub fn allow_other(mut self, allow_other: bool) -> MountOptions {
+        self.allow_other = allow_other;
+        self
+    }
+
+    /// Sets the name of the filesystem shown by tools like `mount` and `df`.
+    pub fn fs_name(mut self, name: &str) -> MountOptions {
+        self.fs_name = Some(name.to_owned());
+        self
+    }
+
+    /// Sets how long the kernel may cache file attributes and directory
+    /// entries.
+    pub fn ttl(mut self, ttl: Duration) -> MountOptions {
+        self.ttl = ttl;
+        self
+    }
+
+    fn fuse_options(&self) -> Vec<OsString> {
+        let mut options: Vec<String> = vec![format!(
+            "fsname={}",
+            self.fs_name
+                .as_ref()
+                .map(String::as_str)
+                .unwrap_or("file-store")
+        )];
+
+        if self.read_only {
+            options.push(String::from("ro"));
+        }
+
+        if self.allow_other {
+            options.push(String::from("allow_other"));
+        }
+
+        options
+            .into_iter()
+            .flat_map(|o| vec![OsString::from("-o"), OsString::from(o)])
+            .collect()
+    }
+}
+
+/// A mounted filesystem. Dropping the handle unmounts the filesystem.
+pub struct MountHandle {
+    mountpoint: PathBuf,
+    session: BackgroundSession<'static>,
+}
+
+impl fmt::Debug for MountHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MountHandle({})", self.mountpoint.display())
+    }
+}
+
+impl MountHandle {
+    /// Gets the directory the filesystem is mounted at.
+    pub fn mountpoint(&self) -> &Path {
+        &self.mountpoint
+    }
+
+    /// Unmounts the filesystem.
+    pub fn unmount(self) {
+        trace!("Unmounting {}", self.mountpoint.display());
+        drop(self.session);
+    }
+}
+
+/// Mounts everything under `prefix` in the store at `mountpoint`.
+///
+/// The filesystem is served from a background thread. This must not be called
+/// from inside an existing runtime.
+pub fn mount<P>(
+    store: FileStore,
+    prefix: ObjectPath,
+    mountpoint: P,
+    options: MountOptions,
+) -> StorageResult<MountHandle>
+where
+    P: AsRef<Path>,
+{
+    let mountpoint = mountpoint.as_ref().to_owned();
+    let fuse_options = options.fuse_options();
+    let fuse_options: Vec<&OsStr> = fuse_options.iter().map(OsString::as_os_str).collect();
+
+    let filesystem = StoreFilesystem::new(store, prefix, options)?;
+
+    trace!("Mounting at {}", mountpoint.display());
+    // The filesystem is owned by the session so nothing it borrows can be
+    // dropped while it is mounted.
+    let session = unsafe { ::fuse::spawn_mount(filesystem, &mountpoint, &fuse_options)? };
+
+    Ok(MountHandle {
+        mountpoint,
+        session,
+    })
+}
+
+fn errno(error: &StorageError) -> c_int {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => ENOENT,
+        StorageErrorKind::AlreadyExists(_) => EEXIST,
+        StorageErrorKind::InvalidPath(_) | StorageErrorKind::ObjectPathParse(_) => EINVAL,
+        StorageErrorKind::AccessDenied | StorageErrorKind::AccessExpired => EPERM,
+        StorageErrorKind::OverQuota => ENOSPC,
+        StorageErrorKind::ReadOnly => EROFS,
+        _ => EIO,
+    }
+}
+
+fn timespec(time: SystemTime) -> Timespec {
+    let duration = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Timespec::new(duration.as_secs() as i64, duration.subsec_nanos() as i32)
+}
+
+fn ttl(options: &MountOptions) -> Timespec {
+    Timespec::new(
+        options.ttl.as_secs() as i64,
+        options.ttl.subsec_nanos() as i32,
+    )
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    path: ObjectPath,
+    kind: FileType,
+    size: u64,
+    modified: SystemTime,
+}
+
+#[derive(Debug)]
+struct Handle {
+    inode: u64,
+    /// The file's contents once they have been read.
+    data: Option<Vec<u8>>,
+    /// Whether the contents need to be written back to storage.
+    dirty: bool,
+}
+
+struct StoreFilesystem {
+    store: FileStore,
+    runtime: Runtime,
+    options: MountOptions,
+    nodes: HashMap<u64, Node>,
+    inodes: HashMap<ObjectPath, u64>,
+    next_inode: u64,
+    handles: HashMap<u64, Handle>,
+    next_handle: u64,
+    /// Directories created through the filesystem.
+    directories: HashSet<ObjectPath>,
+    uid: u32,
+    gid: u32,
+}
+
+impl StoreFilesystem {
+    fn new(
+        store: FileStore,
+        prefix: ObjectPath,
+        options: MountOptions,
+    ) -> StorageResult<StoreFilesystem> {
+        let mut filesystem = StoreFilesystem {
+            store,
+            runtime: Runtime::new()?,
+            options,
+            nodes: HashMap::new(),
+            inodes: HashMap::new(),
+            next_inode: ROOT_INODE,
+            handles: HashMap::new(),
+            next_handle: 1,
+            directories: HashSet::new(),
+            uid: unsafe { libc::getuid() },
+            gid: unsafe { libc::getgid() },
+        };
+
+        filesystem.insert_node(Node {
+            path: prefix,
+            kind: FileType::Directory,
+            size: 0,
+            modified: SystemTime::now(),
+        });
+
+        Ok(filesystem)
+    }
+
+    fn insert_node(&mut self, node: Node) -> u64 {
+        if let Some(inode) = self.inodes.get(&node.path) {
+            let inode = *inode;
+            self.nodes.insert(inode, node);
+            return inode;
+        }
+
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(node.path.clone(), inode);
+        self.nodes.insert(inode, node);
+        inode
+    }
+
+    fn remove_node(&mut self, path: &ObjectPath) {
+        if let Some(inode) = self.inodes.remove(path) {
+            self.nodes.remove(&inode);
+        }
+    }
+
+    fn attr(&self, inode: u64, node: &Node) -> FileAttr {
+        let time = timespec(node.modified);
+        let (perm, nlink) = match node.kind {
+            FileType::Directory => (0o755, 2),
+            _ => (0o644, 1),
+        };
+
+        FileAttr {
+            ino: inode,
+            size: node.size,
+            blocks: (node.size + 511) / 512,
+            atime: time,
+            mtime: time,
+            ctime: time,
+            crtime: time,
+            kind: node.kind,
+            perm: if self.options.read_only {
+                perm & 0o555
+            } else {
+                perm
+            },
+            nlink,
+            uid: self.uid,
+            gid: self.gid,
+            rdev: 0,
+            flags: 0,
+        }
+    }
+
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<ObjectPath, c_int> {
+        let parent = match self.nodes.get(&parent) {
+            Some(n) if n.kind == FileType::Directory => n,
+            Some(_) => return Err(ENOTDIR),
+            None => return Err(ENOENT),
+        };
+
+        match name.to_str() {
+            Some(name) if !name.contains('/') => {
+                let mut path = parent.path.clone();
+                path.push_part(name);
+                Ok(path)
+            }
+            _ => Err(EINVAL),
+        }
+    }
+
+    fn node_from_object(object: &Object) -> Node {
+        let kind = match object.object_type() {
+            ObjectType::Directory => FileType::Directory,
+            ObjectType::Symlink => FileType::Symlink,
+            _ => FileType::RegularFile,
+        };
+
+        let mut path = object.path();
+        if path.to_string().ends_with('/') {
+            path = ObjectPath::new(path.to_string().trim_end_matches('/'))
+                .unwrap_or_else(|_| object.path());
+        }
+
+        Node {
+            path,
+            kind,
+            size: object.len(),
+            modified: object.modified().unwrap_or(UNIX_EPOCH),
+        }
+    }
+
+    /// Lists the contents of a directory.
+    fn list(&self, path: &ObjectPath) -> StorageResult<Vec<Node>> {
+        let store = &self.store;
+        let objects = self.runtime.block_on(async move {
+            store
+                .list_directory(path.clone())
+                .await?
+                .try_collect::<Vec<Object>>()
+                .await
+        })?;
+
+        let mut nodes: Vec<Node> = objects
+            .iter()
+            .map(StoreFilesystem::node_from_object)
+            .filter(|n| &n.path != path)
+            .collect();
+
+        for directory in self.directories.iter() {
+            let mut parent = directory.clone();
+            parent.pop_part();
+            if &parent == path && !nodes.iter().any(|n| &n.path == directory) {
+                nodes.push(Node {
+                    path: directory.clone(),
+                    kind: FileType::Directory,
+                    size: 0,
+                    modified: SystemTime::now(),
+                });
+            }
+        }
+
+        Ok(nodes)
+    }
+
+    /// Finds what is at a path.
+    fn find(&self, path: &ObjectPath) -> StorageResult<Node> {
+        let store = &self.store;
+        let result = self
+            .runtime
+            .block_on(async move { store.get_object(path.clone()).await });
+
+        match result {
+            Ok(object) => return Ok(StoreFilesystem::node_from_object(&object)),
+            Err(ref e) if errno(e) == ENOENT => (),
+            Err(e) => return Err(e),
+        }
+
+        // Backends without real directories only have directories implied by
+        // the files inside them.
+        if self.directories.contains(path) || !self.list(path)?.is_empty() {
+            Ok(Node {
+                path: path.clone(),
+                kind: FileType::Directory,
+                size: 0,
+                modified: SystemTime::now(),
+            })
+        } else {
+            Err(error::not_found(path.clone(), None))
+        }
+    }
+
+    fn read_all(&self, path: &ObjectPath) -> StorageResult<Vec<u8>> {
+        let store = &self.store;
+        self.runtime.block_on(async move {
+            let chunks: Vec<Data> = store
+                .get_file_stream(path.clone())
+                .await?
+                .try_collect()
+                .await?;
+            Ok(chunks.concat())
+        })
+    }
+
+    fn write_all(&self, path: &ObjectPath, data: Vec<u8>) -> StorageResult<()> {
+        let store = &self.store;
+        let stream = once(ready(Ok::<Data, StorageError>(Bytes::from(data))));
+        self.runtime
+            .block_on(async move { store.write_file_from_stream(path.clone(), stream).await })
+            .map_err(StorageError::from)
+    }
+
+    fn open_handle(&mut self, inode: u64, data: Option<Vec<u8>>, dirty: bool) -> u64 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.handles.insert(handle, Handle { inode, data, dirty });
+        handle
+    }
+
+    /// Makes sure a handle's data has been read from storage.
+    fn load_handle(&mut self, handle: u64) -> Result<(), c_int> {
+        let inode = match self.handles.get(&handle) {
+            Some(h) if h.data.is_some() => return Ok(()),
+            Some(h) => h.inode,
+            None => return Err(EINVAL),
+        };
+
+        let path = match self.nodes.get(&inode) {
+            Some(n) => n.path.clone(),
+            None => return Err(ENOENT),
+        };
+
+        let data = self.read_all(&path).map_err(|e| errno(&e))?;
+        if let Some(h) = self.handles.get_mut(&handle) {
+            h.data = Some(data);
+        }
+        Ok(())
+    }
+
+    /// Writes a handle's data back to storage if it has changed.
+    fn flush_handle(&mut self, handle: u64) -> Result<(), c_int> {
+        let (inode, data) = match self.handles.get_mut(&handle) {
+            Some(h) if h.dirty => {
+                h.dirty = false;
+                (h.inode, h.data.clone().unwrap_or_default())
+            }
+            Some(_) => return Ok(()),
+            None => return Err(EINVAL),
+        };
+
+        let path = match self.nodes.get(&inode) {
+            Some(n) => n.path.clone(),
+            None => return Err(ENOENT),
+        };
+
+        let size = data.len() as u64;
+        trace!("Writing {} bytes to {}", size, path);
+        if let Err(e) = self.write_all(&path, data) {
+            error!("Failed to write {}: {}", path, e);
+            return Err(errno(&e));
+        }
+
+        if let Some(node) = self.nodes.get_mut(&inode) {
+            node.size = size;
+            node.modified = SystemTime::now();
+        }
+
+        Ok(())
+    }
+
+    fn check_writable(&self) -> Result<(), c_int> {
+        if self.options.read_only {
+            Err(EROFS)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Filesystem for StoreFilesystem {
+    fn lookup(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let path = match self.child_path(parent, name) {
+            Ok(p) => p,
+            Err(e) => return reply.error(e),
+        };
+
+        match self.find(&path) {
+            Ok(node) => {
+                let inode = self.insert_node(node.clone());
+                reply.entry(&ttl(&self.options), &self.attr(inode, &node), 0);
+            }
+            Err(e) => {
+                self.remove_node(&path);
+                reply.error(errno(&e));
+            }
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request, inode: u64, reply: ReplyAttr) {
+        match self.nodes.get(&inode) {
+            Some(node) => reply.attr(&ttl(&self.options), &self.attr(inode, node)),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn setattr(
+        &mut self,
+        _req: &Request,
+        inode: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<Timespec>,
+        _mtime: Option<Timespec>,
+        fh: Option<u64>,
+        _crtime: Option<Timespec>,
+        _chgtime: Option<Timespec>,
+        _bkuptime: Option<Timespec>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        // Only truncation is supported.
+        if let Some(size) = size {
+            if let Err(e) = self.check_writable() {
+                return reply.error(e);
+            }
+
+            let handle = match fh {
+                Some(h) => h,
+                None => self.open_handle(inode, None, false),
+            };
+
+            if let Err(e) = self.load_handle(handle) {
+                if fh.is_none() {
+                    self.handles.remove(&handle);
+                }
+                return reply.error(e);
+            }
+
+            if let Some(h) = self.handles.get_mut(&handle) {
+                if let Some(data) = h.data.as_mut() {
+                    data.resize(size as usize, 0);
+                }
+                h.dirty = true;
+            }
+
+            let result = if fh.is_none() {
+                let result = self.flush_handle(handle);
+                self.handles.remove(&handle);
+                result
+            } else {
+                Ok(())
+            };
+
+            if let Err(e) = result {
+                return reply.error(e);
+            }
+
+            if let Some(node) = self.nodes.get_mut(&inode) {
+                node.size = size;
+            }
+        }
+
+        match self.nodes.get(&inode) {
+            Some(node) => reply.attr(&ttl(&self.options), &self.attr(inode, node)),
+            None => reply.error(ENOENT),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request,
+        inode: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let path = match self.nodes.get(&inode) {
+            Some(n) if n.kind == FileType::Directory => n.path.clone(),
+            Some(_) => return reply.error(ENOTDIR),
+            None => return reply.error(ENOENT),
+        };
+
+        let nodes = match self.list(&path) {
+            Ok(n) => n,
+            Err(e) => return reply.error(errno(&e)),
+        };
+
+        let mut entries: Vec<(u64, FileType, String)> = vec![
+            (inode, FileType::Directory, String::from(".")),
+            (inode, FileType::Directory, String::from("..")),
+        ];
+
+        for node in nodes {
+            let name = node.path.parts().last().map(|s| (*s).to_owned());
+            let kind = node.kind;
+            let child = self.insert_node(node);
+            if let Some(name) = name {
+                entries.push((child, kind, name));
+            }
+        }
+
+        for (index, (child, kind, name)) in entries.into_iter().enumerate().skip(offset as usize) {
+            if reply.add(child, (index + 1) as i64, kind, name) {
+                break;
+            }
+        }
+
+        reply.ok();
+    }
+
+    fn open(&mut self, _req: &Request, inode: u64, flags: u32, reply: ReplyOpen) {
+        let writing = flags as c_int & libc::O_ACCMODE != libc::O_RDONLY;
+        if writing {
+            if let Err(e) = self.check_writable() {
+                return reply.error(e);
+            }
+        }
+
+        match self.nodes.get(&inode) {
+            Some(n) if n.kind == FileType::Directory => return reply.error(EISDIR),
+            Some(_) => (),
+            None => return reply.error(ENOENT),
+        }
+
+        let handle = if flags as c_int & libc::O_TRUNC != 0 && writing {
+            self.open_handle(inode, Some(Vec::new()), true)
+        } else {
+            self.open_handle(inode, None, false)
+        };
+
+        reply.opened(handle, flags);
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        offset: i64,
+        size: u32,
+        reply: ReplyData,
+    ) {
+        if let Err(e) = self.load_handle(fh) {
+            return reply.error(e);
+        }
+
+        match self.handles.get(&fh).and_then(|h| h.data.as_ref()) {
+            Some(data) => {
+                let start = (offset as usize).min(data.len());
+                let end = (start + size as usize).min(data.len());
+                reply.data(&data[start..end]);
+            }
+            None => reply.error(EIO),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        if let Err(e) = self.check_writable().and_then(|()| self.load_handle(fh)) {
+            return reply.error(e);
+        }
+
+        match self.handles.get_mut(&fh) {
+            Some(Handle {
+                data: Some(buffer),
+                dirty,
+                ..
+            }) => {
+                let offset = offset as usize;
+                if buffer.len() < offset + data.len() {
+                    buffer.resize(offset + data.len(), 0);
+                }
+                buffer[offset..offset + data.len()].copy_from_slice(data);
+                *dirty = true;
+                reply.written(data.len() as u32);
+            }
+            _ => reply.error(EIO),
+        }
+    }
+
+    fn flush(&mut self, _req: &Request, _inode: u64, fh: u64, _lock: u64, reply: ReplyEmpty) {
+        match self.flush_handle(fh) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        _inode: u64,
+        fh: u64,
+        _flags: u32,
+        _lock: u64,
+        _flush: bool,
+        reply: ReplyEmpty,
+    ) {
+        let result = self.flush_handle(fh);
+        self.handles.remove(&fh);
+
+        match result {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        flags: u32,
+        reply: ReplyCreate,
+    ) {
+        if let Err(e) = self.check_writable() {
+            return reply.error(e);
+        }
+
+        let path = match self.child_path(parent, name) {
+            Ok(p) => p,
+            Err(e) => return reply.error(e),
+        };
+
+        // Write the empty file straight away so it is visible.
+        if let Err(e) = self.write_all(&path, Vec::new()) {
+            return reply.error(errno(&e));
+        }
+
+        let node = Node {
+            path,
+            kind: FileType::RegularFile,
+            size: 0,
+            modified: SystemTime::now(),
+        };
+        let inode = self.insert_node(node.clone());
+        let handle = self.open_handle(inode, Some(Vec::new()), false);
+
+        reply.created(
+            &ttl(&self.options),
+            &self.attr(inode, &node),
+            0,
+            handle,
+            flags,
+        );
+    }
+
+    fn mkdir(&mut self, _req: &Request, parent: u64, name: &OsStr, _mode: u32, reply: ReplyEntry) {
+        if let Err(e) = self.check_writable() {
+            return reply.error(e);
+        }
+
+        let path = match self.child_path(parent, name) {
+            Ok(p) => p,
+            Err(e) => return reply.error(e),
+        };
+
+        if self.find(&path).is_ok() {
+            return reply.error(EEXIST);
+        }
+
+        self.directories.insert(path.clone());
+        let node = Node {
+            path,
+            kind: FileType::Directory,
+            size: 0,
+            modified: SystemTime::now(),
+        };
+        let inode = self.insert_node(node.clone());
+        reply.entry(&ttl(&self.options), &self.attr(inode, &node), 0);
+    }
+
+    fn unlink(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if let Err(e) = self.check_writable() {
+            return reply.error(e);
+        }
+
+        let path = match self.child_path(parent, name) {
+            Ok(p) => p,
+            Err(e) => return reply.error(e),
+        };
+
+        let store = &self.store;
+        let target = path.clone();
+        match self
+            .runtime
+            .block_on(async move { store.delete_object(target).await })
+        {
+            Ok(()) => {
+                self.remove_node(&path);
+                reply.ok();
+            }
+            Err(e) => reply.error(errno(&e)),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        if let Err(e) = self.check_writable() {
+            return reply.error(e);
+        }
+
+        let path = match self.child_path(parent, name) {
+            Ok(p) => p,
+            Err(e) => return reply.error(e),
+        };
+
+        match self.list(&path) {
+            Ok(ref nodes) if !nodes.is_empty() => return reply.error(ENOTEMPTY),
+            Ok(_) => (),
+            Err(e) => return reply.error(errno(&e)),
+        }
+
+        if !self.directories.remove(&path) {
+            // A physical directory.
+            let store = &self.store;
+            let target = path.clone();
+            if let Err(e) = self
+                .runtime
+                .block_on(async move { store.delete_object(target).await })
+            {
+                return reply.error(errno(&e));
+            }
+        }
+
+        self.remove_node(&path);
+        reply.ok();
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request,
+        parent: u64,
+        name: &OsStr,
+        new_parent: u64,
+        new_name: &OsStr,
+        reply: ReplyEmpty,
+    ) {
+        if let Err(e) = self.check_writable() {
+            return reply.error(e);
+        }
+
+        let (source, target) = match (
+            self.child_path(parent, name),
+            self.child_path(new_parent, new_name),
+        ) {
+            (Ok(s), Ok(t)) => (s, t),
+            (Err(e), _) | (_, Err(e)) => return reply.error(e),
+        };
+
+        match self.find(&source) {
+            Ok(ref node) if node.kind == FileType::Directory => return reply.error(EISDIR),
+            Ok(_) => (),
+            Err(e) => return reply.error(errno(&e)),
+        }
+
+        let store = &self.store;
+        let (from, to) = (source.clone(), target.clone());
+        match self
+            .runtime
+            .block_on(async move { store.move_file(from, to).await })
+        {
+            Ok(()) => {
+                self.remove_node(&source);
+                self.remove_node(&target);
+                reply.ok();
+            }
+            Err(e) => reply.error(errno(&StorageError::from(e))),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hashing of file data. Included with the feature "hashing".
+//!
+//! Everything here is generic over the
+//! [`Digest`](https://docs.rs/digest/0.8/digest/trait.Digest.html) trait so
+//! any of the RustCrypto hash implementations (SHA-1, SHA-256, BLAKE2, etc.)
+//! can be used:
+//!
+//! ```ignore
+//! let hash = digest_file::<Sha256>(&store, path).await?;
+//! println!("{}", to_hex(&hash));
+//! ```
+//!
+//! [`checksum_tree`](fn.checksum_tree.html) hashes every file under a prefix
+//! and combines the results into a single root hash, so two stores can be
+//! compared by exchanging hashes rather than data.
+use std::fmt::Write;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::digest::generic_array::GenericArray;
+use ::digest::Digest;
+use futures::channel::oneshot::{channel, Sender};
+use futures::future::{ready, TryFutureExt};
+use futures::stream::{iter, Stream, StreamExt, TryStreamExt};
+
+use crate::types::*;
+use crate::FileStore;
+
+/// The output of a digest.
+pub type DigestOutput<D> = GenericArray<u8, <D as Digest>::OutputSize>;
+
+/// A future that resolves to the output of a digest.
+pub type DigestFuture<D> = WrappedFuture<StorageResult<DigestOutput<D>>>;
+
+/// The hashes of the files under a prefix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TreeChecksum {
+    /// The root hash of the tree. Two trees with the same files and contents
+    /// have the same root.
+    pub root: Vec<u8>,
+    /// The hash of every file, keyed by path relative to the prefix and sorted
+    /// by path.
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+/// A future that resolves to a [`TreeChecksum`](struct.TreeChecksum.html).
+pub type TreeChecksumFuture = WrappedFuture<StorageResult<TreeChecksum>>;
+
+/// The number of files that are hashed at once by
+/// [`checksum_tree`](fn.checksum_tree.html).
+const TREE_CONCURRENCY: usize = 8;
+
+/// Formats a hash as a lowercase hexadecimal string.
+pub fn to_hex(hash: &[u8]) -> String {
+    let mut result = String::with_capacity(hash.len() * 2);
+    for byte in hash {
+        let _ = write!(result, "{:02x}", byte);
+    }
+    result
+}
+
+/// A stream that passes through data while hashing it.
+struct HashingStream<D>
+where
+    D: Digest,
+{
+    stream: DataStream,
+    hasher: D,
+    sender: Option<Sender<DigestOutput<D>>>,
+}
+
+impl<D> Stream for HashingStream<D>
+where
+    D: Digest + Unpin,
+{
+    type Item = StorageResult<Data>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let result = Pin::new(&mut self.stream).poll_next(cx);
+        match result {
+            Poll::Ready(Some(Ok(ref data))) => self.hasher.input(data),
+            Poll::Ready(Some(Err(_))) => {
+                // The hash can never be completed.
+                self.sender.take();
+            }
+            Poll::Ready(None) => {
+                if let Some(sender) = self.sender.take() {
+                    let _ = sender.send(self.hasher.result_reset());
+                }
+            }
+            Poll::Pending => (),
+        }
+
+        result
+    }
+}
+
+/// Hashes the data in a stream as it passes through.
+///
+/// Returns the stream to use in place of the original and a future that
+/// resolves to the hash once the stream has been read to the end. If the
+/// stream fails or is dropped early the future resolves to a
+/// [`Cancelled`](../enum.StorageErrorKind.html#variant.Cancelled) error.
+pub fn hash_stream<D>(stream: DataStream) -> (DataStream, DigestFuture<D>)
+where
+    D: Digest + Send + Unpin + 'static,
+{
+    let (sender, receiver) = channel();
+
+    let stream = HashingStream {
+        stream,
+        hasher: D::new(),
+        sender: Some(sender),
+    };
+
+    (
+        DataStream::from_stream(stream),
+        DigestFuture::<D>::from_future(
+            receiver.map_err(|_| error::cancelled(Some("The stream was not read to completion"))),
+        ),
+    )
+}
+
+/// Hashes the contents of a file.
+pub fn digest_file<D>(store: &FileStore, path: ObjectPath) -> DigestFuture<D>
+where
+    D: Digest + Send + 'static,
+{
+    let store = store.clone();
+
+    DigestFuture::<D>::from_future(async move {
+        let hasher = store
+            .get_file_stream(path)
+            .await?
+            .try_fold(D::new(), |mut hasher, data| {
+                hasher.input(&data);
+                ready(Ok(hasher))
+            })
+            .await?;
+
+        Ok(hasher.result())
+    })
+}
+
+/// Checks that the contents of a file match an expected hash.
+///
+/// Fails with an
+/// [`InvalidData`](../enum.StorageErrorKind.html#variant.InvalidData) error
+/// if the hash does not match.
+pub fn verify_file<D>(
+    store: &FileStore,
+    path: ObjectPath,
+    expected: &[u8],
+) -> OperationCompleteFuture
+where
+    D: Digest + Send + 'static,
+{
+    let expected = expected.to_owned();
+
+    OperationCompleteFuture::from_future(digest_file::<D>(store, path.clone()).and_then(
+        move |hash| {
+            let result = if hash.as_slice() == expected.as_slice() {
+                Ok(())
+            } else {
+                Err(error::invalid_data(Some(&format!(
+                    "Expected hash {} for {} but found {}",
+                    to_hex(&expected),
+                    path,
+                    to_hex(&hash)
+                ))))
+            };
+
+            ready(result)
+        },
+    ))
+}
+
+fn merkle_root<D>(files: &[(String, Vec<u8>)]) -> Vec<u8>
+where
+    D: Digest,
+{
+    // Leaves include the path so that renaming a file changes the root.
+    let mut level: Vec<Vec<u8>> = files
+        .iter()
+        .map(|(path, hash)| {
+            let mut hasher = D::new();
+            hasher.input(&[0]);
+            hasher.input(path.as_bytes());
+            hasher.input(&[0]);
+            hasher.input(hash);
+            hasher.result().to_vec()
+        })
+        .collect();
+
+    if level.is_empty() {
+        return D::new().result().to_vec();
+    }
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = D::new();
+                    hasher.input(&[1]);
+                    hasher.input(left);
+                    hasher.input(right);
+                    hasher.result().to_vec()
+                }
+                // An odd node is promoted unchanged.
+                _ => pair[0].clone(),
+            })
+            .collect();
+    }
+
+    level.remove(0)
+}
+
+async fn tree_checksum<D>(store: FileStore, prefix: ObjectPath) -> StorageResult<TreeChecksum>
+where
+    D: Digest + Send + 'static,
+{
+    let list_prefix = if prefix.is_empty() {
+        prefix.clone()
+    } else {
+        ObjectPath::new(format!("{}/", prefix))?
+    };
+
+    let objects: Vec<Object> = store.list_objects(list_prefix).await?.try_collect().await?;
+    let paths = objects
+        .into_iter()
+        .filter(|o| o.object_type() == ObjectType::File)
+        .map(|o| o.path());
+
+    let results: Vec<StorageResult<(ObjectPath, DigestOutput<D>)>> = iter(paths)
+        .map(|path| digest_file::<D>(&store, path.clone()).map_ok(move |hash| (path, hash)))
+        .buffer_unordered(TREE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut files = Vec::with_capacity(results.len());
+    for result in results {
+        let (path, hash) = result?;
+        let mut relative = ObjectPath::empty();
+        for part in path.parts().iter().skip(prefix.parts().len()) {
+            relative.push_part(part);
+        }
+        files.push((relative.to_string(), hash.to_vec()));
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(TreeChecksum {
+        root: merkle_root::<D>(&files),
+        files,
+    })
+}
+
+/// Hashes every file under a prefix, several at a time, and combines the
+/// hashes into a Merkle tree.
+///
+/// Paths are relative to the prefix so the same files under different
+/// prefixes or in different stores produce the same root.
+pub fn checksum_tree<D>(store: &FileStore, prefix: ObjectPath) -> TreeChecksumFuture
+where
+    D: Digest + Send + 'static,
+{
+    TreeChecksumFuture::from_future(tree_checksum::<D>(store.clone(), prefix))
+}
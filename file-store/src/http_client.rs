@@ -0,0 +1,187 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The HTTP client abstraction used by the network based backends.
+//!
+//! Backends that talk to a service over HTTP never use a particular HTTP
+//! library directly, instead they send requests through an implementation of
+//! [`HttpClient`](trait.HttpClient.html). This allows you to share a
+//! connection pool between backends or wrap the client to add your own
+//! behaviour to every request.
+//!
+//! A [hyper](https://hyper.rs/) based client is included with the
+//! "hyper-client" feature and is used by default. Any other HTTP library can
+//! be used by implementing [`HttpClient`](trait.HttpClient.html) and passing
+//! it to the backend's builder. When compiling for WebAssembly the "wasm"
+//! feature provides a client based on the browser's fetch API instead.
+//!
+//! The TLS connections made by the included client can be configured with
+//! [`TlsSettings`](struct.TlsSettings.html), for example to trust a private
+//! certificate authority or to pin the server's certificate. The TLS
+//! implementation is chosen with either the "tls-native" or "tls-rustls"
+//! feature.
+//!
+//! Connections can be made through an HTTP or SOCKS5
+//! [`Proxy`](struct.Proxy.html) with the hyper client. Unless told otherwise
+//! it uses the proxy configured by the `HTTPS_PROXY` environment variable.
+//!
+//! Extra headers, like tracing headers or an addition to the `User-Agent`,
+//! can be added to every request with a
+//! [`HeadersClient`](struct.HeadersClient.html).
+//!
+//! Requests can also be signed, audited or otherwise changed by
+//! [`Middleware`](trait.Middleware.html) run by a
+//! [`MiddlewareClient`](struct.MiddlewareClient.html).
+//!
+//! How the included client keeps connections open between requests is
+//! configured with [`PoolSettings`](struct.PoolSettings.html).
+//!
+//! The "recording" feature includes a
+//! [`RecordingClient`](struct.RecordingClient.html) that captures the requests
+//! made to a service in a [`Cassette`](struct.Cassette.html) and a
+//! [`ReplayClient`](struct.ReplayClient.html) that answers requests from a
+//! cassette. Together they allow testing backends without access to the real
+//! service.
+#[cfg(feature = "wasm")]
+mod fetch_client;
+mod headers;
+#[cfg(feature = "hyper-client")]
+mod hyper_client;
+mod middleware;
+mod pool;
+mod proxy;
+#[cfg(feature = "hyper-client")]
+mod proxy_connector;
+#[cfg(feature = "recording")]
+mod recording;
+mod tls;
+
+use std::fmt;
+use std::sync::Arc;
+
+use futures::future::ready;
+use futures::stream::once;
+use http::{Request, Response};
+
+use crate::types::*;
+
+#[cfg(feature = "wasm")]
+pub use self::fetch_client::FetchClient;
+pub use self::headers::HeadersClient;
+#[cfg(feature = "hyper-client")]
+pub use self::hyper_client::HyperClient;
+pub use self::middleware::*;
+pub use self::pool::*;
+pub use self::proxy::*;
+#[cfg(feature = "recording")]
+pub use self::recording::*;
+pub use self::tls::*;
+
+/// The body of a request sent through an [`HttpClient`](trait.HttpClient.html).
+pub enum RequestBody {
+    /// A request with no body.
+    Empty,
+    /// A body whose entire content is already known.
+    Full(Data),
+    /// A body streamed from a series of chunks. Normally the sender will also
+    /// set the `Content-Length` header.
+    Stream(DataStream),
+}
+
+impl RequestBody {
+    /// Converts this body into a [`DataStream`](../type.DataStream.html).
+    pub fn into_stream(self) -> DataStream {
+        match self {
+            RequestBody::Empty => DataStream::from_stream(futures::stream::empty()),
+            RequestBody::Full(data) => DataStream::from_stream(once(ready(Ok(data)))),
+            RequestBody::Stream(stream) => stream,
+        }
+    }
+}
+
+impl fmt::Debug for RequestBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestBody::Empty => f.pad("RequestBody::Empty"),
+            RequestBody::Full(data) => write!(f, "RequestBody::Full({} bytes)", data.len()),
+            RequestBody::Stream(_) => f.pad("RequestBody::Stream"),
+        }
+    }
+}
+
+/// An HTTP request.
+pub type HttpRequest = Request<RequestBody>;
+/// An HTTP response, the body is streamed as it arrives.
+pub type HttpResponse = Response<DataStream>;
+/// A future that resolves to an [`HttpResponse`](type.HttpResponse.html).
+pub type HttpResponseFuture = WrappedFuture<StorageResult<HttpResponse>>;
+
+/// A client capable of sending HTTP requests.
+///
+/// Implementations should only return an error for transport level problems.
+/// Any response from the server, regardless of its status code, should be
+/// returned as a successful response.
+pub trait HttpClient: fmt::Debug + Send + Sync + 'static {
+    /// Sends a request and resolves to the response.
+    fn request(&self, request: HttpRequest) -> HttpResponseFuture;
+}
+
+impl<C> HttpClient for Arc<C>
+where
+    C: HttpClient + ?Sized,
+{
+    fn request(&self, request: HttpRequest) -> HttpResponseFuture {
+        self.as_ref().request(request)
+    }
+}
+
+/// A reference counted [`HttpClient`](trait.HttpClient.html) that can be
+/// shared between backends.
+pub type SharedHttpClient = Arc<dyn HttpClient>;
+
+/// Creates the default [`HttpClient`](trait.HttpClient.html) for the enabled
+/// features using the given TLS settings, proxy and connection pool settings.
+///
+/// With the "wasm" feature this is a [`FetchClient`](struct.FetchClient.html)
+/// and the settings are ignored.
+pub fn default_client(
+    tls: &TlsSettings,
+    proxy: Option<Proxy>,
+    pool: &PoolSettings,
+) -> StorageResult<SharedHttpClient> {
+    #[cfg(feature = "wasm")]
+    {
+        let _ = (tls, proxy, pool);
+        Ok(Arc::new(FetchClient::new()))
+    }
+
+    #[cfg(all(feature = "hyper-client", not(feature = "wasm")))]
+    {
+        Ok(Arc::new(HyperClient::with_pool(tls, proxy, pool)?))
+    }
+
+    #[cfg(not(any(feature = "hyper-client", feature = "wasm")))]
+    {
+        let _ = (tls, proxy, pool);
+        Err(error::invalid_settings(Some(
+            "No HTTP client was provided and no default client is available.",
+        )))
+    }
+}
+
+impl From<http::Error> for StorageError {
+    fn from(error: http::Error) -> StorageError {
+        error::other_error(Some(&error.to_string()))
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use bytes::{Bytes, BytesMut};
+use futures::channel::oneshot;
+use futures::stream::StreamExt;
+use http::header::{HeaderName, HeaderValue, CONTENT_LENGTH};
+use js_sys::{Array, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::futures_0_3::{spawn_local, JsFuture};
+use web_sys::{Headers, Request as JsRequest, RequestInit, Response as JsResponse};
+use web_sys::{Window, WorkerGlobalScope};
+
+use super::*;
+
+fn js_error(value: JsValue) -> StorageError {
+    error::connection_failed(Some(&format!("Fetch failed: {:?}.", value)))
+}
+
+async fn read_body(body: RequestBody) -> StorageResult<Bytes> {
+    let mut stream = body.into_stream();
+    let mut buffer = BytesMut::new();
+    while let Some(data) = stream.next().await {
+        buffer.extend_from_slice(&data?);
+    }
+
+    Ok(buffer.freeze())
+}
+
+fn build_request(parts: http::request::Parts, body: Bytes) -> StorageResult<JsRequest> {
+    let headers = Headers::new().map_err(js_error)?;
+    for (name, value) in parts.headers.iter() {
+        // The browser calculates the length itself and refuses to let it be set.
+        if name == CONTENT_LENGTH {
+            continue;
+        }
+
+        let value = match value.to_str() {
+            Ok(v) => v,
+            Err(e) => return Err(error::invalid_data(Some(&e.to_string()))),
+        };
+        headers.append(name.as_str(), value).map_err(js_error)?;
+    }
+
+    let mut init = RequestInit::new();
+    init.method(parts.method.as_str());
+    init.headers(&headers);
+    if !body.is_empty() {
+        init.body(Some(&JsValue::from(Uint8Array::from(&body[..]))));
+    }
+
+    JsRequest::new_with_str_and_init(&parts.uri.to_string(), &init).map_err(js_error)
+}
+
+async fn fetch(request: HttpRequest) -> StorageResult<HttpResponse> {
+    let (parts, body) = request.into_parts();
+    let body = read_body(body).await?;
+    let request = build_request(parts, body)?;
+
+    let global = js_sys::global();
+    let promise = if let Some(window) = global.dyn_ref::<Window>() {
+        window.fetch_with_request(&request)
+    } else if let Some(worker) = global.dyn_ref::<WorkerGlobalScope>() {
+        worker.fetch_with_request(&request)
+    } else {
+        return Err(error::internal_error(Some(
+            "The fetch API is not available in this environment.",
+        )));
+    };
+
+    let response: JsResponse = JsFuture::from(promise)
+        .await
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(js_error)?;
+
+    let mut builder = Response::builder();
+    builder.status(response.status());
+
+    if let Some(entries) = js_sys::try_iter(response.headers().as_ref()).map_err(js_error)? {
+        for entry in entries {
+            let entry: Array = entry.map_err(js_error)?.unchecked_into();
+            if let (Some(name), Some(value)) = (entry.get(0).as_string(), entry.get(1).as_string())
+            {
+                match (
+                    HeaderName::from_bytes(name.as_bytes()),
+                    HeaderValue::from_str(&value),
+                ) {
+                    (Ok(name), Ok(value)) => {
+                        builder.header(name, value);
+                    }
+                    _ => return Err(error::invalid_data(Some("Invalid response header."))),
+                }
+            }
+        }
+    }
+
+    let buffer = JsFuture::from(response.array_buffer().map_err(js_error)?)
+        .await
+        .map_err(js_error)?;
+    let data = Bytes::from(Uint8Array::new(&buffer).to_vec());
+
+    Ok(builder.body(DataStream::from_stream(once(ready(Ok(data)))))?)
+}
+
+/// An [`HttpClient`](trait.HttpClient.html) that uses the browser's
+/// [fetch API](https://developer.mozilla.org/docs/Web/API/Fetch_API).
+/// Included with the feature "wasm".
+///
+/// TLS and proxies are handled by the browser so
+/// [`TlsSettings`](struct.TlsSettings.html) and
+/// [`Proxy`](struct.Proxy.html) are ignored. Request bodies are buffered
+/// before being sent and responses are read in their entirety before being
+/// returned.
+#[derive(Clone, Debug, Default)]
+pub struct FetchClient {}
+
+impl FetchClient {
+    /// Creates a new client.
+    pub fn new() -> FetchClient {
+        FetchClient {}
+    }
+}
+
+impl HttpClient for FetchClient {
+    fn request(&self, request: HttpRequest) -> HttpResponseFuture {
+        // JavaScript values cannot leave the thread they were created on so
+        // the request runs as a local task and only the result is sent back.
+        let (sender, receiver) = oneshot::channel();
+        spawn_local(async move {
+            let _ = sender.send(fetch(request).await);
+        });
+
+        HttpResponseFuture::from_future(async move {
+            match receiver.await {
+                Ok(result) => result,
+                Err(_) => Err(error::cancelled(Some("The fetch request was dropped."))),
+            }
+        })
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! A client that adds headers to every request.
+use std::sync::Arc;
+
+use http::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+
+use super::{HttpClient, HttpRequest, HttpResponseFuture, SharedHttpClient};
+use crate::types::*;
+
+/// An [`HttpClient`](trait.HttpClient.html) that adds headers to every
+/// request sent through another client.
+///
+/// This can be used to identify an integration to a service or to pass
+/// tracing headers along with each request. Backend builders use this for
+/// their `header` and `user_agent_suffix` settings.
+#[derive(Clone, Debug)]
+pub struct HeadersClient {
+    inner: SharedHttpClient,
+    user_agent_suffix: Option<String>,
+    headers: HeaderMap,
+}
+
+impl HeadersClient {
+    /// Adds headers to the requests sent through `inner`.
+    pub fn new<C: HttpClient>(inner: C) -> HeadersClient {
+        HeadersClient {
+            inner: Arc::new(inner),
+            user_agent_suffix: None,
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Appends to the `User-Agent` of each request, separated by a space.
+    ///
+    /// Requests without a `User-Agent` are sent with just the suffix.
+    pub fn user_agent_suffix(mut self, suffix: &str) -> HeadersClient {
+        self.user_agent_suffix = Some(suffix.to_owned());
+        self
+    }
+
+    /// Sets a header on each request, replacing any value the request
+    /// already had for the header.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> HeadersClient {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Wraps a client if there are any headers to add, otherwise returns the
+    /// client unchanged.
+    pub(crate) fn wrap(
+        client: SharedHttpClient,
+        user_agent_suffix: Option<&str>,
+        headers: &HeaderMap,
+    ) -> SharedHttpClient {
+        if user_agent_suffix.is_none() && headers.is_empty() {
+            return client;
+        }
+
+        Arc::new(HeadersClient {
+            inner: client,
+            user_agent_suffix: user_agent_suffix.map(ToOwned::to_owned),
+            headers: headers.clone(),
+        })
+    }
+
+    fn user_agent(&self, current: Option<&HeaderValue>) -> StorageResult<Option<HeaderValue>> {
+        let suffix = match &self.user_agent_suffix {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+
+        let user_agent = match current.map(HeaderValue::to_str) {
+            Some(Ok(agent)) => format!("{} {}", agent, suffix),
+            _ => suffix.to_owned(),
+        };
+
+        match HeaderValue::from_str(&user_agent) {
+            Ok(value) => Ok(Some(value)),
+            Err(_) => Err(error::invalid_settings(Some(&format!(
+                "Invalid User-Agent suffix '{}'.",
+                suffix
+            )))),
+        }
+    }
+}
+
+impl HttpClient for HeadersClient {
+    fn request(&self, mut request: HttpRequest) -> HttpResponseFuture {
+        let headers = request.headers_mut();
+        for (name, value) in &self.headers {
+            headers.insert(name.clone(), value.clone());
+        }
+
+        match self.user_agent(headers.get(USER_AGENT)) {
+            Ok(Some(user_agent)) => {
+                headers.insert(USER_AGENT, user_agent);
+            }
+            Ok(None) => (),
+            Err(e) => return HttpResponseFuture::from_value(Err(e)),
+        }
+
+        self.inner.request(request)
+    }
+}
@@ -0,0 +1,336 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use futures::stream::StreamExt;
+use hyper::client::connect::HttpConnector;
+use hyper::client::{Client, ResponseFuture};
+use hyper::Body;
+
+use super::proxy_connector::ProxyConnector;
+use super::*;
+
+#[cfg(not(any(feature = "tls-native", feature = "tls-rustls")))]
+compile_error!("The \"hyper-client\" feature requires either \"tls-native\" or \"tls-rustls\".");
+
+#[cfg(feature = "tls-rustls")]
+type Connector = hyper_rustls::HttpsConnector<ProxyConnector>;
+#[cfg(all(feature = "tls-native", not(feature = "tls-rustls")))]
+type Connector = hyper_tls::HttpsConnector<ProxyConnector>;
+
+impl From<hyper::error::Error> for StorageError {
+    fn from(hyper_error: hyper::error::Error) -> StorageError {
+        let detail = hyper_error.to_string();
+
+        if hyper_error.is_parse() || hyper_error.is_user() {
+            error::invalid_data(Some(&detail))
+        } else if hyper_error.is_canceled() {
+            error::cancelled(Some(&detail))
+        } else if hyper_error.is_connect() {
+            error::connection_failed(Some(&detail))
+        } else {
+            error::connection_closed(Some(&detail))
+        }
+    }
+}
+
+fn tls_error(detail: &str) -> StorageError {
+    error::invalid_settings(Some(&format!("Invalid TLS settings: {}.", detail)))
+}
+
+#[cfg(all(feature = "tls-native", not(feature = "tls-rustls")))]
+fn build_connector(http: ProxyConnector, tls: &TlsSettings) -> StorageResult<Connector> {
+    use native_tls::{Certificate as NativeCertificate, Identity as NativeIdentity};
+
+    let mut builder = native_tls::TlsConnector::builder();
+
+    for certificate in tls.root_certificates() {
+        let parsed = match certificate {
+            Certificate::Pem(pem) => NativeCertificate::from_pem(pem),
+            Certificate::Der(der) => NativeCertificate::from_der(der),
+        };
+
+        match parsed {
+            Ok(c) => builder.add_root_certificate(c),
+            Err(e) => return Err(tls_error(&e.to_string())),
+        };
+    }
+
+    match tls.client_identity() {
+        Some(Identity::Pkcs12 { der, password }) => {
+            match NativeIdentity::from_pkcs12(der, password) {
+                Ok(i) => builder.identity(i),
+                Err(e) => return Err(tls_error(&e.to_string())),
+            };
+        }
+        Some(Identity::Pem(_)) => {
+            return Err(tls_error(
+                "PEM client identities are not supported by the native TLS implementation",
+            ))
+        }
+        None => (),
+    }
+
+    if !tls.pinned_certificates().is_empty() {
+        return Err(tls_error(
+            "Certificate pinning is not supported by the native TLS implementation",
+        ));
+    }
+
+    builder.danger_accept_invalid_certs(tls.accepts_invalid_certs());
+
+    let connector = match builder.build() {
+        Ok(c) => c,
+        Err(e) => {
+            return Err(error::connection_failed(Some(&format!(
+                "Could not create http connection: {}.",
+                e
+            ))))
+        }
+    };
+
+    Ok(hyper_tls::HttpsConnector::from((
+        http,
+        tokio_tls::TlsConnector::from(connector),
+    )))
+}
+
+#[cfg(feature = "tls-rustls")]
+mod dangerous {
+    use std::sync::Arc;
+
+    use rustls::{Certificate, RootCertStore, ServerCertVerified, ServerCertVerifier, TLSError};
+    use sha2::{Digest, Sha256};
+    use webpki::DNSNameRef;
+
+    /// Accepts any certificate the server presents.
+    pub struct NoVerifier;
+
+    impl ServerCertVerifier for NoVerifier {
+        fn verify_server_cert(
+            &self,
+            _roots: &RootCertStore,
+            _presented_certs: &[Certificate],
+            _dns_name: DNSNameRef,
+            _ocsp_response: &[u8],
+        ) -> Result<ServerCertVerified, TLSError> {
+            Ok(ServerCertVerified::assertion())
+        }
+    }
+
+    /// Verifies the server's certificate with another verifier and then
+    /// requires that the chain includes one of the pinned certificates.
+    pub struct PinningVerifier {
+        pub inner: Arc<dyn ServerCertVerifier>,
+        pub pins: Vec<[u8; 32]>,
+    }
+
+    impl ServerCertVerifier for PinningVerifier {
+        fn verify_server_cert(
+            &self,
+            roots: &RootCertStore,
+            presented_certs: &[Certificate],
+            dns_name: DNSNameRef,
+            ocsp_response: &[u8],
+        ) -> Result<ServerCertVerified, TLSError> {
+            let verified =
+                self.inner
+                    .verify_server_cert(roots, presented_certs, dns_name, ocsp_response)?;
+
+            let pinned = presented_certs.iter().any(|certificate| {
+                let fingerprint = Sha256::digest(&certificate.0);
+                self.pins
+                    .iter()
+                    .any(|pin| pin[..] == fingerprint.as_slice()[..])
+            });
+
+            if pinned {
+                Ok(verified)
+            } else {
+                Err(TLSError::General(
+                    "The server did not present a pinned certificate.".to_owned(),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tls-rustls")]
+fn build_connector(http: ProxyConnector, tls: &TlsSettings) -> StorageResult<Connector> {
+    use std::io::Cursor;
+
+    use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+    use rustls::ClientConfig;
+
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+
+    for certificate in tls.root_certificates() {
+        match certificate {
+            Certificate::Pem(pem) => {
+                if config
+                    .root_store
+                    .add_pem_file(&mut Cursor::new(pem))
+                    .is_err()
+                {
+                    return Err(tls_error("Unable to parse PEM root certificate"));
+                }
+            }
+            Certificate::Der(der) => {
+                let certificate = rustls::Certificate(der.clone());
+                if let Err(e) = config.root_store.add(&certificate) {
+                    return Err(tls_error(&format!("{:?}", e)));
+                }
+            }
+        }
+    }
+
+    match tls.client_identity() {
+        Some(Identity::Pem(pem)) => {
+            let chain = match certs(&mut Cursor::new(pem)) {
+                Ok(c) => c,
+                Err(()) => return Err(tls_error("Unable to parse PEM client certificates")),
+            };
+
+            let mut keys = match pkcs8_private_keys(&mut Cursor::new(pem)) {
+                Ok(k) => k,
+                Err(()) => return Err(tls_error("Unable to parse PEM private key")),
+            };
+            if keys.is_empty() {
+                keys = match rsa_private_keys(&mut Cursor::new(pem)) {
+                    Ok(k) => k,
+                    Err(()) => return Err(tls_error("Unable to parse PEM private key")),
+                };
+            }
+
+            if chain.is_empty() || keys.is_empty() {
+                return Err(tls_error(
+                    "A PEM client identity must contain a certificate and a private key",
+                ));
+            }
+
+            config.set_single_client_cert(chain, keys.remove(0));
+        }
+        Some(Identity::Pkcs12 { .. }) => {
+            return Err(tls_error(
+                "PKCS #12 client identities are not supported by rustls",
+            ))
+        }
+        None => (),
+    }
+
+    let verifier: Arc<dyn rustls::ServerCertVerifier> = if tls.accepts_invalid_certs() {
+        Arc::new(dangerous::NoVerifier)
+    } else {
+        Arc::new(rustls::WebPKIVerifier::new())
+    };
+
+    if tls.pinned_certificates().is_empty() {
+        if tls.accepts_invalid_certs() {
+            config.dangerous().set_certificate_verifier(verifier);
+        }
+    } else {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(dangerous::PinningVerifier {
+                inner: verifier,
+                pins: tls.pinned_certificates().to_vec(),
+            }));
+    }
+
+    Ok(hyper_rustls::HttpsConnector::from((http, config)))
+}
+
+/// An [`HttpClient`](trait.HttpClient.html) implemented with
+/// [hyper](https://hyper.rs/). Included with the feature "hyper-client".
+///
+/// TLS is provided by the platform's implementation with the "tls-native"
+/// feature or by [rustls](https://github.com/ctz/rustls) with the
+/// "tls-rustls" feature. If both are enabled rustls is used.
+#[derive(Clone, Debug)]
+pub struct HyperClient {
+    client: Client<Connector>,
+}
+
+impl HyperClient {
+    /// Creates a new client with the default TLS settings and the proxy
+    /// configured in the environment, if any.
+    ///
+    /// See [`Proxy::from_env`](struct.Proxy.html#method.from_env).
+    pub fn new() -> StorageResult<HyperClient> {
+        HyperClient::with_settings(&Default::default(), Proxy::from_env())
+    }
+
+    /// Creates a new client using the given TLS settings and no proxy.
+    pub fn with_tls(tls: &TlsSettings) -> StorageResult<HyperClient> {
+        HyperClient::with_settings(tls, None)
+    }
+
+    /// Creates a new client using the given TLS settings that connects
+    /// through the given proxy.
+    pub fn with_settings(tls: &TlsSettings, proxy: Option<Proxy>) -> StorageResult<HyperClient> {
+        HyperClient::with_pool(tls, proxy, &Default::default())
+    }
+
+    /// Creates a new client using the given TLS settings, proxy and
+    /// connection pool settings.
+    pub fn with_pool(
+        tls: &TlsSettings,
+        proxy: Option<Proxy>,
+        pool: &PoolSettings,
+    ) -> StorageResult<HyperClient> {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        http.set_keepalive(pool.keepalive_interval());
+
+        let connector = ProxyConnector::new(http, proxy);
+
+        let client = Client::builder()
+            .max_idle_per_host(pool.max_idle_connections())
+            .keep_alive_timeout(pool.idle_connection_timeout())
+            .http2_only(pool.is_http2_only())
+            .build(build_connector(connector, tls)?);
+
+        Ok(HyperClient { client })
+    }
+}
+
+impl From<Client<Connector>> for HyperClient {
+    fn from(client: Client<Connector>) -> HyperClient {
+        HyperClient { client }
+    }
+}
+
+async fn send(future: ResponseFuture) -> StorageResult<HttpResponse> {
+    let (parts, body) = future.await?.into_parts();
+    let stream = body.map(|result| match result {
+        Ok(chunk) => Ok(chunk.into_bytes()),
+        Err(e) => Err(StorageError::from(e)),
+    });
+
+    Ok(Response::from_parts(parts, DataStream::from_stream(stream)))
+}
+
+impl HttpClient for HyperClient {
+    fn request(&self, request: HttpRequest) -> HttpResponseFuture {
+        let (parts, body) = request.into_parts();
+        let body = match body {
+            RequestBody::Empty => Body::empty(),
+            RequestBody::Full(data) => Body::from(data),
+            RequestBody::Stream(stream) => Body::wrap_stream(stream),
+        };
+
+        HttpResponseFuture::from_future(send(self.client.request(Request::from_parts(parts, body))))
+    }
+}
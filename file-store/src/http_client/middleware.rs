@@ -0,0 +1,113 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Hooks that can inspect or change requests and responses.
+use std::fmt;
+use std::sync::Arc;
+
+use super::{HttpClient, HttpRequest, HttpResponse, HttpResponseFuture, SharedHttpClient};
+use crate::types::*;
+
+/// Hooks called for every request sent through a
+/// [`MiddlewareClient`](struct.MiddlewareClient.html).
+///
+/// Middleware can be used to sign requests, add headers or audit the
+/// requests a backend makes without changing the backend itself. The same
+/// middleware is used for requests sent in parallel so any state must be
+/// shared safely.
+pub trait Middleware: fmt::Debug + Send + Sync + 'static {
+    /// Called before a request is sent. Returning an error fails the request
+    /// without sending it.
+    fn on_request(&self, _request: &mut HttpRequest) -> StorageResult<()> {
+        Ok(())
+    }
+
+    /// Called when the response's headers have been received.
+    fn on_response(&self, _response: &HttpResponse) {}
+}
+
+impl<M> Middleware for Arc<M>
+where
+    M: Middleware + ?Sized,
+{
+    fn on_request(&self, request: &mut HttpRequest) -> StorageResult<()> {
+        self.as_ref().on_request(request)
+    }
+
+    fn on_response(&self, response: &HttpResponse) {
+        self.as_ref().on_response(response)
+    }
+}
+
+/// An [`HttpClient`](trait.HttpClient.html) that passes every request and
+/// response through a series of [`Middleware`](trait.Middleware.html).
+///
+/// Requests are passed to the middleware in the order it was added,
+/// responses in the reverse order.
+#[derive(Clone, Debug)]
+pub struct MiddlewareClient {
+    inner: SharedHttpClient,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl MiddlewareClient {
+    /// Passes the requests sent through `inner` through middleware.
+    pub fn new<C: HttpClient>(inner: C) -> MiddlewareClient {
+        MiddlewareClient {
+            inner: Arc::new(inner),
+            middleware: Vec::new(),
+        }
+    }
+
+    /// Adds middleware, it sees requests after any middleware already added.
+    pub fn with<M: Middleware>(mut self, middleware: M) -> MiddlewareClient {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Wraps a client if there is any middleware, otherwise returns the
+    /// client unchanged.
+    pub(crate) fn wrap(
+        client: SharedHttpClient,
+        middleware: &[Arc<dyn Middleware>],
+    ) -> SharedHttpClient {
+        if middleware.is_empty() {
+            return client;
+        }
+
+        Arc::new(MiddlewareClient {
+            inner: client,
+            middleware: middleware.to_vec(),
+        })
+    }
+}
+
+impl HttpClient for MiddlewareClient {
+    fn request(&self, mut request: HttpRequest) -> HttpResponseFuture {
+        for middleware in &self.middleware {
+            if let Err(e) = middleware.on_request(&mut request) {
+                return HttpResponseFuture::from_value(Err(e));
+            }
+        }
+
+        let response = self.inner.request(request);
+        let middleware = self.middleware.clone();
+        HttpResponseFuture::from_future(async move {
+            let response = response.await?;
+            for middleware in middleware.iter().rev() {
+                middleware.on_response(&response);
+            }
+            Ok(response)
+        })
+    }
+}
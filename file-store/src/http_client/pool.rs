@@ -0,0 +1,103 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::Duration;
+
+/// How long idle connections are kept open by default.
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// The default interval between TCP keep-alive probes.
+pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// Settings for the connection pool of an
+/// [`HttpClient`](trait.HttpClient.html).
+///
+/// Backends that send many requests in parallel, like B2 uploading the parts
+/// of a large file, benefit from keeping enough idle connections open that
+/// each request doesn't need a new connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolSettings {
+    max_idle_per_host: usize,
+    idle_timeout: Option<Duration>,
+    tcp_keepalive: Option<Duration>,
+    http2_only: bool,
+}
+
+impl Default for PoolSettings {
+    fn default() -> PoolSettings {
+        PoolSettings {
+            max_idle_per_host: usize::max_value(),
+            idle_timeout: Some(DEFAULT_IDLE_TIMEOUT),
+            tcp_keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+            http2_only: false,
+        }
+    }
+}
+
+impl PoolSettings {
+    /// Creates the default settings. Any number of idle connections are kept
+    /// open for [`DEFAULT_IDLE_TIMEOUT`](constant.DEFAULT_IDLE_TIMEOUT.html)
+    /// and probed every
+    /// [`DEFAULT_TCP_KEEPALIVE`](constant.DEFAULT_TCP_KEEPALIVE.html) to stop
+    /// them being dropped by firewalls.
+    pub fn new() -> PoolSettings {
+        Default::default()
+    }
+
+    /// Sets the maximum number of idle connections kept open to each host.
+    pub fn max_idle_per_host(mut self, max: usize) -> PoolSettings {
+        self.max_idle_per_host = max;
+        self
+    }
+
+    /// Sets how long an idle connection is kept open, `None` keeps idle
+    /// connections open indefinitely.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> PoolSettings {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the interval between TCP keep-alive probes, `None` disables them.
+    pub fn tcp_keepalive(mut self, interval: Option<Duration>) -> PoolSettings {
+        self.tcp_keepalive = interval;
+        self
+    }
+
+    /// Only uses HTTP/2, multiplexing requests to the same host over a single
+    /// connection. Only use this if the server is known to support HTTP/2.
+    pub fn http2_only(mut self, http2_only: bool) -> PoolSettings {
+        self.http2_only = http2_only;
+        self
+    }
+
+    /// Gets the maximum number of idle connections kept open to each host.
+    pub fn max_idle_connections(&self) -> usize {
+        self.max_idle_per_host
+    }
+
+    /// Gets how long an idle connection is kept open.
+    pub fn idle_connection_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    /// Gets the interval between TCP keep-alive probes.
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        self.tcp_keepalive
+    }
+
+    /// Checks whether only HTTP/2 is used.
+    pub fn is_http2_only(&self) -> bool {
+        self.http2_only
+    }
+}
@@ -0,0 +1,228 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A hyper connector that tunnels connections through a proxy.
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use hyper::client::connect::{Connect, Connected, Destination, HttpConnector};
+use log::trace;
+use tokio_io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{Proxy, ProxyProtocol};
+
+type Transport = <HttpConnector as Connect>::Transport;
+type ConnectResult = Result<(Transport, Connected), io::Error>;
+
+const MAX_RESPONSE_HEADER: usize = 8 * 1024;
+
+fn proxy_error(detail: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, detail)
+}
+
+/// Connects either directly or, if a proxy is configured and applies to the
+/// destination, through a tunnel opened by the proxy.
+#[derive(Clone, Debug)]
+pub struct ProxyConnector {
+    http: HttpConnector,
+    proxy: Option<Arc<Proxy>>,
+}
+
+impl ProxyConnector {
+    pub fn new(http: HttpConnector, proxy: Option<Proxy>) -> ProxyConnector {
+        ProxyConnector {
+            http,
+            proxy: proxy.map(Arc::new),
+        }
+    }
+}
+
+impl Connect for ProxyConnector {
+    type Transport = Transport;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = ConnectResult> + Send>>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        match &self.proxy {
+            Some(proxy) if proxy.applies_to(dst.host()) => {
+                Box::pin(tunnel(self.http.clone(), proxy.clone(), dst))
+            }
+            _ => Box::pin(self.http.connect(dst)),
+        }
+    }
+}
+
+async fn tunnel(http: HttpConnector, proxy: Arc<Proxy>, dst: Destination) -> ConnectResult {
+    let host = dst.host().to_owned();
+    let port = match dst.port() {
+        Some(p) => p,
+        None if dst.scheme() == "https" => 443,
+        None => 80,
+    };
+
+    let mut proxy_dst = dst;
+    let result = proxy_dst
+        .set_scheme("http")
+        .and_then(|_| proxy_dst.set_host(proxy.host()));
+    if let Err(e) = result {
+        return Err(proxy_error(format!("Invalid proxy host: {}", e)));
+    }
+    proxy_dst.set_port(proxy.port());
+
+    trace!(
+        "Connecting to {}:{} through proxy {}:{}.",
+        host,
+        port,
+        proxy.host(),
+        proxy.port()
+    );
+    let (mut stream, connected) = http.connect(proxy_dst).await?;
+
+    match proxy.protocol() {
+        ProxyProtocol::Http => http_connect(&mut stream, &proxy, &host, port).await?,
+        ProxyProtocol::Socks5 => socks5_connect(&mut stream, &proxy, &host, port).await?,
+    }
+
+    Ok((stream, connected))
+}
+
+async fn http_connect(
+    stream: &mut Transport,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> io::Result<()> {
+    let mut request = format!("CONNECT {0}:{1} HTTP/1.1\r\nHost: {0}:{1}\r\n", host, port);
+    if let Some((username, password)) = proxy.credentials() {
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::encode(&format!("{}:{}", username, password))
+        ));
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    // Read byte by byte so nothing beyond the end of the proxy's response is
+    // consumed from the tunnel.
+    let mut response: Vec<u8> = Vec::new();
+    let mut byte = [0; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > MAX_RESPONSE_HEADER {
+            return Err(proxy_error("Proxy response was too large.".to_owned()));
+        }
+
+        if stream.read(&mut byte).await? == 0 {
+            return Err(proxy_error(
+                "Proxy closed the connection unexpectedly.".to_owned(),
+            ));
+        }
+        response.push(byte[0]);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        Some("407") => Err(proxy_error(
+            "Proxy authentication was required or failed.".to_owned(),
+        )),
+        _ => Err(proxy_error(format!(
+            "Proxy refused to open a tunnel: {}",
+            status
+        ))),
+    }
+}
+
+async fn socks5_connect(
+    stream: &mut Transport,
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+) -> io::Result<()> {
+    if host.len() > 255 {
+        return Err(proxy_error(format!("Host name {} is too long.", host)));
+    }
+
+    match proxy.credentials() {
+        Some(_) => stream.write_all(&[5, 2, 0, 2]).await?,
+        None => stream.write_all(&[5, 1, 0]).await?,
+    }
+
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 5 {
+        return Err(proxy_error("Proxy is not a SOCKS5 proxy.".to_owned()));
+    }
+
+    match (reply[1], proxy.credentials()) {
+        (0, _) => (),
+        (2, Some((username, password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                return Err(proxy_error(
+                    "Proxy username or password is too long.".to_owned(),
+                ));
+            }
+
+            let mut request = vec![1, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(proxy_error("Proxy authentication failed.".to_owned()));
+            }
+        }
+        _ => {
+            return Err(proxy_error(
+                "Proxy does not support any offered authentication method.".to_owned(),
+            ))
+        }
+    }
+
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut header = [0; 4];
+    stream.read_exact(&mut header).await?;
+    if header[1] != 0 {
+        return Err(proxy_error(format!(
+            "Proxy failed to connect with error code {}.",
+            header[1]
+        )));
+    }
+
+    // Skip over the bound address and port.
+    let remaining = match header[3] {
+        1 => 4 + 2,
+        4 => 16 + 2,
+        3 => {
+            let mut length = [0; 1];
+            stream.read_exact(&mut length).await?;
+            length[0] as usize + 2
+        }
+        _ => return Err(proxy_error("Proxy returned an invalid address.".to_owned())),
+    };
+    let mut address = vec![0; remaining];
+    stream.read_exact(&mut address).await?;
+
+    Ok(())
+}
@@ -0,0 +1,271 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use futures::future::ready;
+use futures::stream::{once, TryStreamExt};
+use http::{Method, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use super::{HttpClient, HttpRequest, HttpResponseFuture, RequestBody};
+use crate::types::*;
+
+fn encode_body(data: &[u8]) -> String {
+    base64::encode(data)
+}
+
+fn decode_body(data: &str) -> StorageResult<Data> {
+    base64::decode(data)
+        .map(Data::from)
+        .map_err(|e| error::invalid_data(Some(&format!("Invalid recorded body: {}", e))))
+}
+
+/// A single request and the response that it received.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interaction {
+    method: String,
+    uri: String,
+    request_body: String,
+    status: u16,
+    response_headers: Vec<(String, String)>,
+    response_body: String,
+}
+
+impl Interaction {
+    /// The request's method.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// The request's URI.
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// The response's status code.
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    fn matches(&self, method: &Method, uri: &str, body: &str) -> bool {
+        self.method == method.as_str() && self.uri == uri && self.request_body == body
+    }
+
+    fn response(&self) -> StorageResult<HttpResponseFuture> {
+        let status = StatusCode::from_u16(self.status)
+            .map_err(|e| error::invalid_data(Some(&e.to_string())))?;
+
+        let mut builder = Response::builder();
+        builder.status(status);
+        for (name, value) in &self.response_headers {
+            builder.header(name.as_str(), value.as_str());
+        }
+
+        let body = decode_body(&self.response_body)?;
+        let response = builder.body(DataStream::from_stream(once(ready(Ok(body)))))?;
+        Ok(HttpResponseFuture::from_value(Ok(response)))
+    }
+}
+
+/// A recorded set of [`Interaction`](struct.Interaction.html)s.
+///
+/// Cassettes are stored as JSON. Request headers are not recorded but
+/// response bodies are, so a cassette recorded against a real service will
+/// include any credentials that the service returned, for example
+/// authorization tokens. Use keys that are revoked after recording or remove
+/// the secrets before sharing a cassette.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cassette {
+    interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Creates an empty cassette.
+    pub fn new() -> Cassette {
+        Default::default()
+    }
+
+    /// The recorded interactions in the order they completed.
+    pub fn interactions(&self) -> &[Interaction] {
+        &self.interactions
+    }
+
+    /// Parses a cassette from JSON.
+    pub fn from_json(json: &[u8]) -> StorageResult<Cassette> {
+        serde_json::from_slice(json).map_err(|e| error::invalid_data(Some(&e.to_string())))
+    }
+
+    /// Serializes the cassette to JSON.
+    pub fn to_json(&self) -> StorageResult<Vec<u8>> {
+        serde_json::to_vec_pretty(self).map_err(|e| error::internal_error(Some(&e.to_string())))
+    }
+
+    /// Loads a cassette from a file.
+    #[cfg(not(feature = "wasm"))]
+    pub fn load<P: AsRef<std::path::Path>>(path: P) -> StorageResult<Cassette> {
+        Cassette::from_json(&std::fs::read(path)?)
+    }
+
+    /// Saves the cassette to a file.
+    #[cfg(not(feature = "wasm"))]
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> StorageResult<()> {
+        std::fs::write(path, self.to_json()?)?;
+        Ok(())
+    }
+}
+
+/// An [`HttpClient`](trait.HttpClient.html) that passes requests to another
+/// client and records every request and response into a
+/// [`Cassette`](struct.Cassette.html).
+///
+/// Request and response bodies are read entirely into memory so this is only
+/// suitable for tests.
+#[derive(Clone)]
+pub struct RecordingClient {
+    inner: Arc<dyn HttpClient>,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl fmt::Debug for RecordingClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RecordingClient")
+            .field("inner", &self.inner)
+            .field(
+                "interactions",
+                &self.cassette.lock().unwrap().interactions.len(),
+            )
+            .finish()
+    }
+}
+
+impl RecordingClient {
+    /// Records the requests sent through `inner`.
+    pub fn new<C: HttpClient>(inner: C) -> RecordingClient {
+        RecordingClient {
+            inner: Arc::new(inner),
+            cassette: Default::default(),
+        }
+    }
+
+    /// Returns a copy of everything recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+}
+
+impl HttpClient for RecordingClient {
+    fn request(&self, request: HttpRequest) -> HttpResponseFuture {
+        let inner = self.inner.clone();
+        let cassette = self.cassette.clone();
+
+        HttpResponseFuture::from_future(async move {
+            let (head, body) = request.into_parts();
+            let request_body: Vec<Data> = body.into_stream().try_collect().await?;
+            let request_body = request_body.concat();
+
+            let method = head.method.to_string();
+            let uri = head.uri.to_string();
+            let request =
+                HttpRequest::from_parts(head, RequestBody::Full(request_body.clone().into()));
+
+            let response = inner.request(request).await?;
+            let (head, body) = response.into_parts();
+            let response_body: Vec<Data> = body.try_collect().await?;
+            let response_body = response_body.concat();
+
+            let response_headers = head
+                .headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    value
+                        .to_str()
+                        .ok()
+                        .map(|v| (name.as_str().to_owned(), v.to_owned()))
+                })
+                .collect();
+
+            cassette.lock().unwrap().interactions.push(Interaction {
+                method,
+                uri,
+                request_body: encode_body(&request_body),
+                status: head.status.as_u16(),
+                response_headers,
+                response_body: encode_body(&response_body),
+            });
+
+            Ok(Response::from_parts(
+                head,
+                DataStream::from_stream(once(ready(Ok(Data::from(response_body))))),
+            ))
+        })
+    }
+}
+
+/// An [`HttpClient`](trait.HttpClient.html) that answers requests from a
+/// [`Cassette`](struct.Cassette.html) without making any network requests.
+///
+/// Each request is answered by the first unused interaction with the same
+/// method, URI and body. Requests with no matching interaction fail with an
+/// [`Other`](../enum.StorageErrorKind.html#variant.Other) error.
+#[derive(Clone, Debug)]
+pub struct ReplayClient {
+    remaining: Arc<Mutex<Vec<Interaction>>>,
+}
+
+impl ReplayClient {
+    /// Replays the interactions in a cassette.
+    pub fn new(cassette: Cassette) -> ReplayClient {
+        ReplayClient {
+            remaining: Arc::new(Mutex::new(cassette.interactions)),
+        }
+    }
+
+    /// The number of interactions that have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.remaining.lock().unwrap().len()
+    }
+}
+
+impl HttpClient for ReplayClient {
+    fn request(&self, request: HttpRequest) -> HttpResponseFuture {
+        let remaining = self.remaining.clone();
+
+        HttpResponseFuture::from_future(async move {
+            let (head, body) = request.into_parts();
+            let body: Vec<Data> = body.into_stream().try_collect().await?;
+            let body = encode_body(&body.concat());
+            let uri = head.uri.to_string();
+
+            let interaction = {
+                let mut remaining = remaining.lock().unwrap();
+                match remaining
+                    .iter()
+                    .position(|i| i.matches(&head.method, &uri, &body))
+                {
+                    Some(index) => remaining.remove(index),
+                    None => {
+                        return Err(error::other_error(Some(&format!(
+                            "No recorded interaction for {} {}",
+                            head.method, uri
+                        ))))
+                    }
+                }
+            };
+
+            interaction.response()?.await
+        })
+    }
+}
@@ -0,0 +1,129 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use std::fmt;
+
+/// A certificate to trust as a root certificate authority.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Certificate {
+    /// A PEM encoded certificate.
+    Pem(Vec<u8>),
+    /// A DER encoded certificate.
+    Der(Vec<u8>),
+}
+
+/// A client certificate and private key used to identify this client to the
+/// server.
+///
+/// Which formats are supported depends on the TLS implementation in use. The
+/// "tls-native" feature supports PKCS #12 archives, the "tls-rustls" feature
+/// supports PEM files.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Identity {
+    /// A DER encoded PKCS #12 archive and the password needed to decrypt it.
+    Pkcs12 {
+        /// The archive.
+        der: Vec<u8>,
+        /// The password for the archive.
+        password: String,
+    },
+    /// A PEM file containing a certificate chain and a private key.
+    Pem(Vec<u8>),
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Identity::Pkcs12 { der, .. } => {
+                write!(f, "Identity::Pkcs12 {{ {} bytes }}", der.len())
+            }
+            Identity::Pem(pem) => write!(f, "Identity::Pem {{ {} bytes }}", pem.len()),
+        }
+    }
+}
+
+/// Settings for the TLS connections made by an
+/// [`HttpClient`](trait.HttpClient.html).
+#[derive(Clone, Debug, Default)]
+pub struct TlsSettings {
+    root_certificates: Vec<Certificate>,
+    identity: Option<Identity>,
+    accept_invalid_certs: bool,
+    pinned_certificates: Vec<[u8; 32]>,
+}
+
+impl TlsSettings {
+    /// Creates the default settings, trusting only the standard certificate
+    /// authorities.
+    pub fn new() -> TlsSettings {
+        Default::default()
+    }
+
+    /// Adds a certificate authority to trust in addition to the standard
+    /// ones.
+    ///
+    /// This is needed to connect to servers using certificates signed by a
+    /// private certificate authority.
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> TlsSettings {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Sets the client certificate to present to the server.
+    pub fn identity(mut self, identity: Identity) -> TlsSettings {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Disables all validation of the server's certificate.
+    ///
+    /// This is dangerous and should only be used for testing against servers
+    /// using self-signed certificates. Any server will be trusted, including
+    /// one intercepting the connection.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> TlsSettings {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Pins a certificate that the server must present.
+    ///
+    /// The certificate is identified by the SHA-256 fingerprint of its DER
+    /// encoding. Once any certificate is pinned connections are only made to
+    /// servers whose certificate chain includes one of the pinned
+    /// certificates, in addition to the normal validation. Pinning is only
+    /// supported by the "tls-rustls" feature.
+    pub fn pin_certificate(mut self, sha256: [u8; 32]) -> TlsSettings {
+        self.pinned_certificates.push(sha256);
+        self
+    }
+
+    /// Gets the additional certificate authorities to trust.
+    pub fn root_certificates(&self) -> &[Certificate] {
+        &self.root_certificates
+    }
+
+    /// Gets the client certificate to present to the server.
+    pub fn client_identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    /// Checks whether validation of the server's certificate is disabled.
+    pub fn accepts_invalid_certs(&self) -> bool {
+        self.accept_invalid_certs
+    }
+
+    /// Gets the fingerprints of the pinned certificates.
+    pub fn pinned_certificates(&self) -> &[[u8; 32]] {
+        &self.pinned_certificates
+    }
+}
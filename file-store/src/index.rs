@@ -0,0 +1,545 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A local index of the objects in a store. Included with the feature "index".
+//!
+//! Searching a remote store means listing everything in it. A
+//! [`MetadataIndex`](struct.MetadataIndex.html) keeps the listing in a local
+//! SQLite database so searches by name, size, modification time or custom
+//! metadata don't have to touch the backend at all.
+//!
+//! The index is filled by [`refresh`](struct.MetadataIndex.html#method.refresh)
+//! and can be kept up to date with changes made through the `FileStore` by
+//! running the future returned from
+//! [`watch`](struct.MetadataIndex.html#method.watch). Changes made to the
+//! storage by anything else are only seen on the next refresh.
+//!
+//! Custom metadata is stored only in the index, it is not written to the
+//! storage.
+//!
+//! Queries against the index are synchronous since they only touch the local
+//! database.
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::stream::{StreamExt, TryStreamExt};
+use log::warn;
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection, Row, NO_PARAMS};
+
+use crate::events::{Operation, OperationEvent};
+use crate::types::*;
+use crate::FileStore;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS objects (
+        path TEXT PRIMARY KEY NOT NULL,
+        name TEXT NOT NULL,
+        kind INTEGER NOT NULL,
+        size INTEGER NOT NULL,
+        modified INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS objects_name ON objects (name);
+    CREATE TABLE IF NOT EXISTS metadata (
+        path TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (path, key)
+    );
+";
+
+/// A future that resolves to the number of objects indexed.
+pub type RefreshFuture = WrappedFuture<StorageResult<usize>>;
+
+/// A future that keeps an index up to date.
+pub type WatchFuture = WrappedFuture<()>;
+
+fn sql_error(error: rusqlite::Error) -> StorageError {
+    error::internal_error(Some(&error.to_string()))
+}
+
+fn kind_to_sql(kind: ObjectType) -> i64 {
+    match kind {
+        ObjectType::File => 0,
+        ObjectType::Directory => 1,
+        ObjectType::Symlink => 2,
+        ObjectType::Unknown => 3,
+    }
+}
+
+fn kind_from_sql(kind: i64) -> ObjectType {
+    match kind {
+        0 => ObjectType::File,
+        1 => ObjectType::Directory,
+        2 => ObjectType::Symlink,
+        _ => ObjectType::Unknown,
+    }
+}
+
+fn time_to_sql(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as i64,
+        Err(e) => -(e.duration().as_millis() as i64),
+    }
+}
+
+fn time_from_sql(millis: i64) -> SystemTime {
+    if millis >= 0 {
+        UNIX_EPOCH + Duration::from_millis(millis as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_millis(-millis as u64)
+    }
+}
+
+/// Matches everything beneath a path.
+const BENEATH_PATH: &str = "substr(path, 1, length(?1) + 1) = ?1 || '/'";
+/// Matches the path itself and everything beneath it.
+const UNDER_PATH: &str = "(path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/')";
+
+/// An object recorded in the index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexEntry {
+    /// The path of the object.
+    pub path: ObjectPath,
+    /// The type of the object.
+    pub object_type: ObjectType,
+    /// The size of the object.
+    pub size: u64,
+    /// The last modification time of the object if known.
+    pub modified: Option<SystemTime>,
+}
+
+impl IndexEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<IndexEntry> {
+        let path: String = row.get(0)?;
+        let kind: i64 = row.get(1)?;
+        let size: i64 = row.get(2)?;
+        let modified: Option<i64> = row.get(3)?;
+
+        Ok(IndexEntry {
+            path: ObjectPath::new(path).unwrap_or_else(|_| ObjectPath::empty()),
+            object_type: kind_from_sql(kind),
+            size: size as u64,
+            modified: modified.map(time_from_sql),
+        })
+    }
+}
+
+/// A search of the index. All of the conditions must match.
+#[derive(Clone, Debug, Default)]
+pub struct Query {
+    prefix: Option<ObjectPath>,
+    name: Option<String>,
+    object_type: Option<ObjectType>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+    metadata: Vec<(String, Option<String>)>,
+    limit: Option<usize>,
+}
+
+impl Query {
+    /// Creates a query that matches everything.
+    pub fn new() -> Query {
+        Default::default()
+    }
+
+    /// Only matches objects at or beneath the given path.
+    pub fn prefix(mut self, prefix: ObjectPath) -> Query {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    /// Only matches objects whose name, the last part of the path, matches a
+    /// glob pattern. `*` matches any characters, `?` matches a single
+    /// character and `[...]` matches any of the enclosed characters. Matching
+    /// is case sensitive.
+    pub fn name(mut self, pattern: &str) -> Query {
+        self.name = Some(pattern.to_owned());
+        self
+    }
+
+    /// Only matches objects of the given type.
+    pub fn object_type(mut self, object_type: ObjectType) -> Query {
+        self.object_type = Some(object_type);
+        self
+    }
+
+    /// Only matches objects at least this large.
+    pub fn min_size(mut self, size: u64) -> Query {
+        self.min_size = Some(size);
+        self
+    }
+
+    /// Only matches objects at most this large.
+    pub fn max_size(mut self, size: u64) -> Query {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Only matches objects modified at or after the given time.
+    pub fn modified_after(mut self, time: SystemTime) -> Query {
+        self.modified_after = Some(time);
+        self
+    }
+
+    /// Only matches objects modified before the given time.
+    pub fn modified_before(mut self, time: SystemTime) -> Query {
+        self.modified_before = Some(time);
+        self
+    }
+
+    /// Only matches objects with the given custom metadata. If `value` is
+    /// `None` any value matches.
+    pub fn metadata(mut self, key: &str, value: Option<&str>) -> Query {
+        self.metadata
+            .push((key.to_owned(), value.map(ToOwned::to_owned)));
+        self
+    }
+
+    /// Returns at most this many results.
+    pub fn limit(mut self, limit: usize) -> Query {
+        self.limit = Some(limit);
+        self
+    }
+
+    fn to_sql(&self) -> (String, Vec<Box<dyn ToSql>>) {
+        let mut conditions: Vec<String> = Vec::new();
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        fn param(value: Box<dyn ToSql>, params: &mut Vec<Box<dyn ToSql>>) -> String {
+            params.push(value);
+            format!("?{}", params.len())
+        }
+
+        if let Some(ref prefix) = self.prefix {
+            if !prefix.is_empty() {
+                let p = param(Box::new(prefix.to_string()), &mut params);
+                conditions.push(format!(
+                    "(path = {p} OR substr(path, 1, length({p}) + 1) = {p} || '/')",
+                    p = p
+                ));
+            }
+        }
+        if let Some(ref name) = self.name {
+            let p = param(Box::new(name.clone()), &mut params);
+            conditions.push(format!("name GLOB {}", p));
+        }
+        if let Some(kind) = self.object_type {
+            let p = param(Box::new(kind_to_sql(kind)), &mut params);
+            conditions.push(format!("kind = {}", p));
+        }
+        if let Some(size) = self.min_size {
+            let p = param(Box::new(size as i64), &mut params);
+            conditions.push(format!("size >= {}", p));
+        }
+        if let Some(size) = self.max_size {
+            let p = param(Box::new(size as i64), &mut params);
+            conditions.push(format!("size <= {}", p));
+        }
+        if let Some(time) = self.modified_after {
+            let p = param(Box::new(time_to_sql(time)), &mut params);
+            conditions.push(format!("modified >= {}", p));
+        }
+        if let Some(time) = self.modified_before {
+            let p = param(Box::new(time_to_sql(time)), &mut params);
+            conditions.push(format!("modified < {}", p));
+        }
+        for (key, value) in &self.metadata {
+            let k = param(Box::new(key.clone()), &mut params);
+            match value {
+                Some(value) => {
+                    let v = param(Box::new(value.clone()), &mut params);
+                    conditions.push(format!(
+                        "path IN (SELECT path FROM metadata WHERE key = {} AND value = {})",
+                        k, v
+                    ));
+                }
+                None => conditions.push(format!(
+                    "path IN (SELECT path FROM metadata WHERE key = {})",
+                    k
+                )),
+            }
+        }
+
+        let mut sql = String::from("SELECT path, kind, size, modified FROM objects");
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY path");
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        (sql, params)
+    }
+}
+
+/// A local index of the objects in a [`FileStore`](../enum.FileStore.html).
+#[derive(Clone)]
+pub struct MetadataIndex {
+    store: FileStore,
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl fmt::Debug for MetadataIndex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MetadataIndex")
+            .field("store", &self.store)
+            .finish()
+    }
+}
+
+impl MetadataIndex {
+    /// Opens or creates an index stored in the given database file.
+    pub fn open<P: AsRef<Path>>(store: FileStore, database: P) -> StorageResult<MetadataIndex> {
+        MetadataIndex::from_connection(store, Connection::open(database).map_err(sql_error)?)
+    }
+
+    /// Creates an index that is only held in memory.
+    pub fn in_memory(store: FileStore) -> StorageResult<MetadataIndex> {
+        MetadataIndex::from_connection(store, Connection::open_in_memory().map_err(sql_error)?)
+    }
+
+    fn from_connection(store: FileStore, connection: Connection) -> StorageResult<MetadataIndex> {
+        connection.execute_batch(SCHEMA).map_err(sql_error)?;
+
+        Ok(MetadataIndex {
+            store,
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn connection(&self) -> StorageResult<MutexGuard<Connection>> {
+        self.connection
+            .lock()
+            .map_err(|_| error::internal_error(Some("The index database is poisoned")))
+    }
+
+    /// Replaces everything in the index beneath the given prefix with a fresh
+    /// listing from the store. An empty prefix indexes the entire store.
+    ///
+    /// Custom metadata is kept for objects that still exist.
+    pub fn refresh<P>(&self, prefix: P) -> RefreshFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let prefix = match prefix.try_into() {
+            Ok(p) => p,
+            Err(e) => return RefreshFuture::from_value(Err(e.into())),
+        };
+
+        let index = self.clone();
+        RefreshFuture::from_future(async move {
+            let list_prefix = if prefix.is_empty() {
+                prefix.clone()
+            } else {
+                ObjectPath::new(format!("{}/", prefix))?
+            };
+
+            let objects: Vec<Object> = index
+                .store
+                .list_objects(list_prefix)
+                .await?
+                .try_collect()
+                .await?;
+
+            let mut connection = index.connection()?;
+            let transaction = connection.transaction().map_err(sql_error)?;
+            if prefix.is_empty() {
+                transaction
+                    .execute("DELETE FROM objects", NO_PARAMS)
+                    .map_err(sql_error)?;
+            } else {
+                transaction
+                    .execute(
+                        &format!("DELETE FROM objects WHERE {}", BENEATH_PATH),
+                        params![prefix.to_string()],
+                    )
+                    .map_err(sql_error)?;
+            }
+
+            for object in &objects {
+                insert(&transaction, object)?;
+            }
+
+            // Forget metadata for objects that no longer exist.
+            transaction
+                .execute(
+                    "DELETE FROM metadata WHERE path NOT IN (SELECT path FROM objects)",
+                    NO_PARAMS,
+                )
+                .map_err(sql_error)?;
+            transaction.commit().map_err(sql_error)?;
+
+            Ok(objects.len())
+        })
+    }
+
+    /// Returns a future that updates the index as changes are made through the
+    /// `FileStore` or any of its clones. The future never completes so it
+    /// should be spawned on an executor, dropping it stops the updates.
+    pub fn watch(&self) -> WatchFuture {
+        let index = self.clone();
+        let mut events = self.store.events();
+
+        WatchFuture::from_future(async move {
+            while let Some(event) = events.next().await {
+                if let Err(e) = index.apply(&event).await {
+                    warn!("Failed to update the index for {}: {}", event.path, e);
+                }
+            }
+        })
+    }
+
+    async fn apply(&self, event: &OperationEvent) -> StorageResult<()> {
+        if event.result.is_err() {
+            return Ok(());
+        }
+
+        match event.operation {
+            Operation::Delete => self.remove(&event.path),
+            Operation::Write => self.update(&event.path).await,
+            Operation::Copy => match event.target {
+                Some(ref target) => self.update(target).await,
+                None => Ok(()),
+            },
+            Operation::Move => {
+                self.remove(&event.path)?;
+                match event.target {
+                    Some(ref target) => self.update(target).await,
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+
+    async fn update(&self, path: &ObjectPath) -> StorageResult<()> {
+        let object = self.store.get_object(path.clone()).await?;
+        insert(&self.connection()?, &object)
+    }
+
+    fn remove(&self, path: &ObjectPath) -> StorageResult<()> {
+        let connection = self.connection()?;
+        for table in &["objects", "metadata"] {
+            connection
+                .execute(
+                    &format!("DELETE FROM {} WHERE {}", table, UNDER_PATH),
+                    params![path.to_string()],
+                )
+                .map_err(sql_error)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the indexed object at a path.
+    pub fn get(&self, path: &ObjectPath) -> StorageResult<Option<IndexEntry>> {
+        let entries = self.search(&Query::new().prefix(path.clone()))?;
+        Ok(entries.into_iter().find(|e| &e.path == path))
+    }
+
+    /// Finds the objects in the index that match a query, sorted by path.
+    pub fn search(&self, query: &Query) -> StorageResult<Vec<IndexEntry>> {
+        let (sql, params) = query.to_sql();
+        let connection = self.connection()?;
+        let mut statement = connection.prepare(&sql).map_err(sql_error)?;
+        let rows = statement
+            .query_map(params.iter().map(|p| p.as_ref()), IndexEntry::from_row)
+            .map_err(sql_error)?;
+
+        rows.collect::<rusqlite::Result<Vec<IndexEntry>>>()
+            .map_err(sql_error)
+    }
+
+    /// Sets a custom metadata value for an indexed object.
+    pub fn set_metadata(&self, path: &ObjectPath, key: &str, value: &str) -> StorageResult<()> {
+        let connection = self.connection()?;
+        let exists: i64 = connection
+            .query_row(
+                "SELECT COUNT(*) FROM objects WHERE path = ?1",
+                params![path.to_string()],
+                |row| row.get(0),
+            )
+            .map_err(sql_error)?;
+        if exists == 0 {
+            return Err(error::not_found(path.clone(), Some("Not in the index")));
+        }
+
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO metadata (path, key, value) VALUES (?1, ?2, ?3)",
+                params![path.to_string(), key, value],
+            )
+            .map_err(sql_error)?;
+        Ok(())
+    }
+
+    /// Removes a custom metadata value from an object.
+    pub fn remove_metadata(&self, path: &ObjectPath, key: &str) -> StorageResult<()> {
+        self.connection()?
+            .execute(
+                "DELETE FROM metadata WHERE path = ?1 AND key = ?2",
+                params![path.to_string(), key],
+            )
+            .map_err(sql_error)?;
+        Ok(())
+    }
+
+    /// Returns the custom metadata for an object.
+    pub fn metadata(&self, path: &ObjectPath) -> StorageResult<HashMap<String, String>> {
+        let connection = self.connection()?;
+        let mut statement = connection
+            .prepare("SELECT key, value FROM metadata WHERE path = ?1")
+            .map_err(sql_error)?;
+        let rows = statement
+            .query_map(params![path.to_string()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .map_err(sql_error)?;
+
+        rows.collect::<rusqlite::Result<HashMap<String, String>>>()
+            .map_err(sql_error)
+    }
+}
+
+fn insert(connection: &Connection, object: &Object) -> StorageResult<()> {
+    let path = object.path();
+    let name = path
+        .parts()
+        .last()
+        .map(|s| (*s).to_owned())
+        .unwrap_or_default();
+
+    connection
+        .execute(
+            "INSERT OR REPLACE INTO objects (path, name, kind, size, modified)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                path.to_string(),
+                name,
+                kind_to_sql(object.object_type()),
+                object.len() as i64,
+                object.modified().map(time_to_sql),
+            ],
+        )
+        .map_err(sql_error)?;
+    Ok(())
+}
@@ -0,0 +1,344 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Tracing spans, metrics and events for the operations made through a
+//! `FileStore`.
+//!
+//! Every operation reports its outcome, duration and the bytes transferred to
+//! the store's [metrics sink](../metrics/index.html), if any, and to any
+//! subscribers to its [storage events](../events/struct.StorageEvent.html).
+//! With the "tracing" feature every operation also runs inside a `storage`
+//! span recording the operation, backend and path. Downloads and uploads also
+//! record the bytes transferred and backends record how many attempts a
+//! request took.
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "tracing")]
+use futures::future::{Future, FutureExt, TryFutureExt};
+use futures::stream::{Stream, StreamExt};
+#[cfg(feature = "tracing")]
+use tracing::{field, info_span, Span};
+
+use crate::backends::Backend;
+use crate::events::{next_operation_id, now, EventLog, EventPhase, OperationResult, StorageEvent};
+use crate::metrics::{self, Metrics};
+use crate::types::*;
+
+/// Enters a span every time the inner future or stream is polled.
+#[cfg(feature = "tracing")]
+struct Instrumented<T> {
+    span: Span,
+    inner: T,
+}
+
+#[cfg(feature = "tracing")]
+impl<F> Future for Instrumented<F>
+where
+    F: Future + Unpin,
+{
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+        let this = &mut *self;
+        let _entered = this.span.enter();
+        this.inner.poll_unpin(cx)
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl<S> Stream for Instrumented<S>
+where
+    S: Stream + Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<S::Item>> {
+        let this = &mut *self;
+        let _entered = this.span.enter();
+        this.inner.poll_next_unpin(cx)
+    }
+}
+
+/// Counts the bytes passing through a data stream and records them once the
+/// stream ends or is dropped.
+struct Counted {
+    operation: OperationSpan,
+    metric: &'static str,
+    inner: DataStream,
+    bytes: u64,
+    /// For downloads, when the operation started. The operation only ends
+    /// once the stream does.
+    finish: Option<SystemTime>,
+    error: Option<StorageErrorKind>,
+}
+
+impl Stream for Counted {
+    type Item = StorageResult<Data>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        #[cfg(feature = "tracing")]
+        let _entered = this.operation.span.enter();
+        let result = this.inner.poll_next_unpin(cx);
+        match &result {
+            Poll::Ready(Some(Ok(data))) => this.bytes += data.len() as u64,
+            Poll::Ready(Some(Err(e))) => this.error = Some(e.kind()),
+            _ => (),
+        }
+        result
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        #[cfg(feature = "tracing")]
+        self.operation.span.record("bytes", &self.bytes);
+        if self.bytes > 0 {
+            self.operation
+                .metrics
+                .counter(self.metric, self.operation.backend, self.bytes, &[]);
+        }
+
+        self.operation.add_bytes(self.bytes);
+        if let Some(started) = self.finish {
+            self.operation.end(started, self.error.take());
+        }
+    }
+}
+
+/// The span covering a single operation.
+#[derive(Clone, Debug)]
+pub(crate) struct OperationSpan {
+    id: usize,
+    operation: &'static str,
+    backend: Backend,
+    path: ObjectPath,
+    target: Option<ObjectPath>,
+    metrics: Metrics,
+    events: EventLog,
+    bytes: Arc<Mutex<Option<u64>>>,
+    #[cfg(feature = "tracing")]
+    span: Span,
+}
+
+impl OperationSpan {
+    pub fn new(
+        operation: &'static str,
+        backend: Backend,
+        path: &ObjectPath,
+        metrics: &Metrics,
+        events: &EventLog,
+    ) -> OperationSpan {
+        OperationSpan {
+            id: next_operation_id(),
+            operation,
+            backend,
+            path: path.clone(),
+            target: None,
+            metrics: metrics.clone(),
+            events: events.clone(),
+            bytes: Default::default(),
+            #[cfg(feature = "tracing")]
+            span: info_span!(
+                "storage",
+                operation = operation,
+                backend = %backend,
+                path = %path,
+                target = field::Empty,
+                attempts = field::Empty,
+                bytes = field::Empty
+            ),
+        }
+    }
+
+    /// Records the target of a copy or move.
+    pub fn target(mut self, target: &ObjectPath) -> OperationSpan {
+        #[cfg(feature = "tracing")]
+        self.span.record("target", &field::display(target));
+        self.target = Some(target.clone());
+        self
+    }
+
+    fn add_bytes(&self, bytes: u64) {
+        if let Ok(mut total) = self.bytes.lock() {
+            *total = Some(total.unwrap_or_default() + bytes);
+        }
+    }
+
+    fn emit(&self, phase: EventPhase, duration: Option<Duration>) {
+        if !self.events.has_storage_subscribers() {
+            return;
+        }
+
+        let bytes = match duration {
+            Some(_) => self.bytes.lock().ok().and_then(|b| *b),
+            None => None,
+        };
+
+        self.events.emit_storage(StorageEvent {
+            id: self.id,
+            phase,
+            operation: self.operation,
+            backend: self.backend,
+            path: self.path.clone(),
+            target: self.target.clone(),
+            time: now(),
+            duration,
+            bytes,
+        });
+    }
+
+    /// Marks the start of the operation, returning the time it started.
+    fn start(&self) -> SystemTime {
+        self.emit(EventPhase::Started, None);
+        now()
+    }
+
+    /// Reports the outcome of the operation to the metrics sink.
+    fn report(&self, started: SystemTime, error: Option<&StorageErrorKind>) {
+        let outcome = match error {
+            Some(_) => "error",
+            None => "ok",
+        };
+        self.metrics.counter(
+            metrics::OPERATIONS,
+            self.backend,
+            1,
+            &[("operation", self.operation), ("result", outcome)],
+        );
+        self.metrics.duration(
+            metrics::OPERATION_DURATION,
+            self.backend,
+            started,
+            &[("operation", self.operation)],
+        );
+    }
+
+    /// Marks the end of the operation.
+    fn end(&self, started: SystemTime, error: Option<StorageErrorKind>) {
+        let phase = match error {
+            Some(kind) => EventPhase::Failed(kind),
+            None => EventPhase::Finished,
+        };
+        self.emit(
+            phase,
+            Some(now().duration_since(started).unwrap_or_default()),
+        );
+    }
+
+    fn instrument<R>(&self, future: WrappedFuture<R>) -> WrappedFuture<R>
+    where
+        R: Send + 'static,
+    {
+        #[cfg(feature = "tracing")]
+        {
+            WrappedFuture::from_future(Instrumented {
+                span: self.span.clone(),
+                inner: future,
+            })
+        }
+
+        #[cfg(not(feature = "tracing"))]
+        {
+            future
+        }
+    }
+
+    /// Runs a future as the operation.
+    pub fn future<R>(&self, future: WrappedFuture<R>) -> WrappedFuture<R>
+    where
+        R: OperationResult + Send + 'static,
+    {
+        let operation = self.clone();
+        self.instrument(WrappedFuture::from_future(async move {
+            let started = operation.start();
+            let result = future.await;
+
+            let error = result.error_kind();
+            operation.report(started, error.as_ref());
+            operation.end(started, error);
+            result
+        }))
+    }
+
+    /// Runs a listing as the operation, when tracing the listed objects are
+    /// also polled inside the span.
+    pub fn list(&self, future: ObjectStreamFuture) -> ObjectStreamFuture {
+        #[cfg(feature = "tracing")]
+        let future = {
+            let span = self.span.clone();
+            ObjectStreamFuture::from_future(future.map_ok(move |stream| {
+                ObjectStream::from_stream(Instrumented {
+                    span,
+                    inner: stream,
+                })
+            }))
+        };
+
+        self.future(future)
+    }
+
+    fn counted(
+        &self,
+        metric: &'static str,
+        stream: DataStream,
+        finish: Option<SystemTime>,
+    ) -> DataStream {
+        self.add_bytes(0);
+        DataStream::from_stream(Counted {
+            operation: self.clone(),
+            metric,
+            inner: stream,
+            bytes: 0,
+            finish,
+            error: None,
+        })
+    }
+
+    /// Runs a download as the operation. The operation ends once the
+    /// downloaded data has been read.
+    pub fn download(&self, future: DataStreamFuture) -> DataStreamFuture {
+        let operation = self.clone();
+        self.instrument(DataStreamFuture::from_future(async move {
+            let started = operation.start();
+            match future.await {
+                Ok(stream) => {
+                    operation.report(started, None);
+                    Ok(operation.counted(metrics::BYTES_DOWNLOADED, stream, Some(started)))
+                }
+                Err(e) => {
+                    let kind = e.kind();
+                    operation.report(started, Some(&kind));
+                    operation.end(started, Some(kind));
+                    Err(e)
+                }
+            }
+        }))
+    }
+
+    /// Records the bytes read from the stream being uploaded.
+    pub fn upload(&self, stream: DataStream) -> DataStream {
+        self.counted(metrics::BYTES_UPLOADED, stream, None)
+    }
+}
+
+/// Records the attempt a backend is making in the current operation's span.
+pub(crate) fn record_attempt(attempt: usize) {
+    #[cfg(feature = "tracing")]
+    Span::current().record("attempts", &(attempt as u64));
+    #[cfg(not(feature = "tracing"))]
+    let _ = attempt;
+}
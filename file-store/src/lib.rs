@@ -31,28 +31,126 @@
 //!
 //! The [`FileStore`](enum.FileStore.html) is the main way to access storage. A
 //! [`FileStore`](enum.FileStore.html) is created from one of the backends.
+//! Backends can be combined with the [`mirror`](mirror/index.html),
+//! [`shard`](shard/index.html), [`quota`](quota/index.html) and
+//! [`read_only`](read_only/index.html) wrappers.
+//!
+//! Other functionality is enabled with these features:
+//!
+//! | Feature | Provides |
+//! | --- | --- |
+//! | "file", "b2", "remote" | The [backends](backends/index.html), "remote" also includes the [gRPC server](serve/grpc/index.html) |
+//! | "hyper-client", "tls-native", "tls-rustls" | [HTTP clients](http_client/index.html) for the network backends |
+//! | "wasm" | An [HTTP client](http_client/index.html) using the browser's fetch API |
+//! | "recording" | [Recording and replaying](http_client/struct.RecordingClient.html) HTTP requests |
+//! | "executor" | A fallback [runtime](executor/index.html) for spawning tasks outside of tokio |
+//! | "blocking" | A [synchronous wrapper](blocking/index.html) |
+//! | "config" | Stores described in [configuration](config/index.html) |
+//! | "hashing", "compression" | [Hashing](hashing/index.html) and [compression](compression/index.html) of streams |
+//! | "cas", "snapshot", "archive" | [Chunk stores](cas/index.html), [snapshots](snapshot/index.html) and [archives](archive/index.html) |
+//! | "index", "tags", "lifecycle" | [Indexes](index/index.html), [tags](tags/index.html) and [lifecycle rules](lifecycle/index.html) |
+//! | "sync", "lock" | [Synchronisation](sync/index.html) and [leases](lock/index.html) |
+//! | "disk-cache" | A [local cache](disk_cache/index.html) of files read |
+//! | "tower", "responder", "upload" | [Services](service/index.html), [responses](responder/index.html) and [uploads](upload/index.html) over HTTP |
+//! | "mount", "webdav", "s3-gateway" | Serving storage through [FUSE](fuse/index.html) or over the [network](serve/index.html) |
+//! | "tracing" | Operations run inside [tracing](https://docs.rs/tracing) spans |
+//! | "testing" | The backends' [conformance tests](testing/index.html) |
 #![warn(missing_docs)]
 
+#[cfg(feature = "archive")]
+pub mod archive;
 #[macro_use]
 pub mod backends;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+pub mod cache;
+#[cfg(feature = "cas")]
+pub mod cas;
+#[cfg(feature = "compression")]
+pub mod compression;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod copy;
+#[cfg(all(feature = "disk-cache", not(feature = "wasm")))]
+pub mod disk_cache;
+pub mod events;
+pub mod executor;
+#[cfg(feature = "mount")]
+pub mod fuse;
+#[cfg(feature = "hashing")]
+pub mod hashing;
+#[cfg(feature = "http")]
+pub mod http_client;
+#[cfg(feature = "index")]
+pub mod index;
+mod instrument;
+#[cfg(feature = "lifecycle")]
+pub mod lifecycle;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+mod local;
+#[cfg(feature = "lock")]
+pub mod lock;
+pub mod metrics;
+pub mod mirror;
+pub mod quota;
+pub mod read_only;
+#[cfg(feature = "responder")]
+pub mod responder;
+#[cfg(any(feature = "webdav", feature = "s3-gateway", feature = "remote"))]
+pub mod serve;
+#[cfg(feature = "tower")]
+pub mod service;
+pub mod shard;
+#[cfg(feature = "snapshot")]
+pub mod snapshot;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "tags")]
+pub mod tags;
+#[cfg(all(feature = "testing", not(feature = "wasm")))]
+pub mod testing;
 mod types;
+#[cfg(feature = "upload")]
+pub mod upload;
 pub mod utils;
+pub mod versions;
 
 pub use types::*;
 
+use std::collections::BinaryHeap;
 use std::convert::TryInto;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 
-use bytes::IntoBuf;
-use enum_dispatch::enum_dispatch;
-use futures::future::TryFutureExt;
-use futures::stream::Stream;
+use bytes::{BytesMut, IntoBuf};
+use futures::future::{ready, TryFutureExt};
+use futures::stream::{empty, iter, once, Stream, StreamExt, TryStreamExt};
 
+use instrument::OperationSpan;
+use types::stream::RangeStream;
+
+#[cfg(feature = "b2")]
 use backends::b2::B2Backend;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
 use backends::file::FileBackend;
+#[cfg(feature = "remote")]
+use backends::remote::RemoteBackend;
+
+/// The number of objects that [`delete_objects`](trait.StorageBackend.html#method.delete_objects)
+/// deletes at once by default.
+pub const DELETE_CONCURRENCY: usize = 16;
 
 /// The trait that every storage backend must implement at a minimum.
-#[enum_dispatch]
-pub trait StorageBackend: Clone + Send + 'static {
+///
+/// This trait is object safe so backends can be used as
+/// `Box<dyn StorageBackend>` or `Arc<dyn StorageBackend>`. Its methods take
+/// already converted paths, [`FileStore`](enum.FileStore.html) provides
+/// the same methods accepting anything that can be converted to a path.
+///
+/// The futures returned should not start any work until they are first polled.
+pub trait StorageBackend: Send + Sync + 'static {
     /// Retrieves the type of this backend.
     fn backend_type(&self) -> backends::Backend;
 
@@ -62,10 +160,30 @@ pub trait StorageBackend: Clone + Send + 'static {
     /// Be sure to include a trailing `/` if you only want to include objects
     /// inside that (possibly virtual) directory. This will only include
     /// directory objects if those actually exists in the underlying storage.
-    fn list_objects<P>(&self, prefix: P) -> ObjectStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>;
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture;
+
+    /// Lists the objects that are prefixed by the given prefix, limited by the
+    /// given [options](struct.ListOptions.html).
+    ///
+    /// By default this filters the stream returned by
+    /// [`list_objects`](#tymethod.list_objects), backends that can limit a
+    /// listing directly should override it.
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        options.apply(prefix.clone(), self.list_objects(prefix))
+    }
+
+    /// Returns whether [`list_objects`](#tymethod.list_objects) always returns
+    /// objects in path order.
+    ///
+    /// Paged listings can stop as soon as a page is filled when this is true,
+    /// otherwise they must look at every remaining object.
+    fn lists_in_order(&self) -> bool {
+        false
+    }
 
     /// Lists the objects that exist in the given (possibly virtual) directory.
     ///
@@ -74,19 +192,13 @@ pub trait StorageBackend: Clone + Send + 'static {
     /// additional `/` character are returned. This will include directory
     /// objects even if the underlying storage doesn't actually support
     /// directories to indicate that there may be deeper objects not included.
-    fn list_directory<P>(&self, dir: P) -> ObjectStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>;
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture;
 
     /// Gets info about the object at the given path.
     ///
     /// This will return a [`NotFound`](enum.StorageErrorKind.html#variant.NotFound)
     /// error if no object exists at the fiven path.
-    fn get_object<P>(&self, path: P) -> ObjectFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>;
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture;
 
     /// Gets a stream of data for the file at the given path.
     ///
@@ -96,10 +208,28 @@ pub trait StorageBackend: Clone + Send + 'static {
     ///
     /// This will return a [`NotFound`](enum.StorageErrorKind.html#variant.NotFound)
     /// error if the object at the path does not exist or is not a file.
-    fn get_file_stream<P>(&self, path: P) -> DataStreamFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>;
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture;
+
+    /// Gets a stream of part of the data for the file at the given path.
+    ///
+    /// The stream starts `offset` bytes into the file and contains at most
+    /// `length` bytes, or everything up to the end of the file if `length` is
+    /// `None`. Reading from an offset beyond the end of the file returns an
+    /// empty stream.
+    ///
+    /// By default this skips through the stream returned by
+    /// [`get_file_stream`](#tymethod.get_file_stream), backends that can fetch
+    /// part of a file directly should override it.
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        DataStreamFuture::from_future(self.get_file_stream(path).map_ok(move |stream| {
+            DataStream::from_stream(RangeStream::new(stream, offset, length))
+        }))
+    }
 
     /// Copies a file from one path to another within this `Backend`.
     ///
@@ -109,13 +239,7 @@ pub trait StorageBackend: Clone + Send + 'static {
     ///
     /// Various properties of the file such as last modification time may not be
     /// copied to the new file.
-    fn copy_file<P, I>(&self, source: P, target: I) -> CopyCompleteFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-        I: TryInto<UploadInfo>,
-        I::Error: Into<StorageError>,
-    {
+    fn copy_file(&self, source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
         let source = DataStream::from_stream(self.get_file_stream(source).try_flatten_stream());
         self.write_file_from_stream(target, source)
     }
@@ -127,27 +251,29 @@ pub trait StorageBackend: Clone + Send + 'static {
     /// location.
     ///
     /// Various properties of the file such as last modification time may not be
-    /// copied to the new file.
-    fn move_file<P, I>(&self, source: P, target: I) -> MoveCompleteFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>,
-        I: TryInto<UploadInfo>,
-        I::Error: Into<StorageError>,
-    {
-        let path = match source.try_into() {
-            Ok(p) => p,
-            Err(e) => {
-                return MoveCompleteFuture::from_value(Err(TransferError::SourceError(e.into())));
-            }
-        };
+    /// copied to the new file. Moving a file onto itself leaves it unchanged.
+    fn move_file(&self, source: ObjectPath, target: UploadInfo) -> MoveCompleteFuture {
+        async fn move_file(
+            copy: CopyCompleteFuture,
+            delete: OperationCompleteFuture,
+        ) -> Result<(), TransferError> {
+            copy.await?;
+            delete.await.map_err(TransferError::SourceError)
+        }
 
-        let deleter = self.clone();
-        MoveCompleteFuture::from_future(self.copy_file(path.clone(), target).and_then(move |()| {
-            deleter
-                .delete_object(path)
-                .map_err(TransferError::SourceError)
-        }))
+        // Copying a file onto itself then deleting it would lose it.
+        if source == target.path {
+            let exists = self.get_object(source);
+            return MoveCompleteFuture::from_future(async move {
+                exists.await.map(|_| ()).map_err(TransferError::SourceError)
+            });
+        }
+
+        // The delete isn't polled until the copy has completed.
+        MoveCompleteFuture::from_future(move_file(
+            self.copy_file(source.clone(), target),
+            self.delete_object(source),
+        ))
     }
 
     /// Deletes the object at the given path.
@@ -157,23 +283,65 @@ pub trait StorageBackend: Clone + Send + 'static {
     ///
     /// This will return a [`NotFound`](enum.StorageErrorKind.html#variant.NotFound)
     /// error if the object does not exist.
-    fn delete_object<P>(&self, path: P) -> OperationCompleteFuture
-    where
-        P: TryInto<ObjectPath>,
-        P::Error: Into<StorageError>;
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture;
+
+    /// Creates a directory at the given path along with any missing parents.
+    ///
+    /// Creating a directory that already exists succeeds. Backends without
+    /// real directories treat this as a no-op by default since a directory
+    /// appears as soon as a file is written inside it.
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        let _ = path;
+        OperationCompleteFuture::from_value(Ok(()))
+    }
+
+    /// Deletes many objects.
+    ///
+    /// Failing to delete one object does not stop the others, the failures are
+    /// collected in the returned [`DeleteSummary`](struct.DeleteSummary.html).
+    /// By default this runs up to [`DELETE_CONCURRENCY`](constant.DELETE_CONCURRENCY.html)
+    /// calls to [`delete_object`](#tymethod.delete_object) at once, backends
+    /// that support deleting many objects in one request should override it.
+    fn delete_objects(&self, paths: Vec<ObjectPath>) -> DeleteSummaryFuture {
+        let deletes: Vec<_> = paths
+            .into_iter()
+            .map(|path| {
+                let delete = self.delete_object(path.clone());
+                async move { (path, delete.await) }
+            })
+            .collect();
+
+        DeleteSummaryFuture::from_future(async move {
+            let mut summary = DeleteSummary::default();
+            let mut results = iter(deletes).buffer_unordered(DELETE_CONCURRENCY);
+            while let Some((path, result)) = results.next().await {
+                match result {
+                    Ok(()) => summary.deleted.push(path),
+                    Err(e) => summary.failed.push((path, e)),
+                }
+            }
+
+            summary
+        })
+    }
 
     /// Writes a stream of data to the file at the given path.
     ///
-    /// Calling this will overwrite anything at the given path (notably on
-    /// backends that support symlinks or directories those will be deleted
-    /// along with their contents and replaced with a file). The rationale for
-    /// this is that for network based backends not overwriting generally
-    /// involves more API calls to check if something is there first. If you
-    /// care about overwriting, call [`get_object`](trait.StorageBackend.html#method.get_file)
-    /// first and check the result.
+    /// By default calling this will overwrite anything at the given path
+    /// (notably on backends that support symlinks or directories those will be
+    /// deleted along with their contents and replaced with a file). The
+    /// rationale for this is that for network based backends not overwriting
+    /// generally involves more API calls to check if something is there first.
+    /// If you care about overwriting, set the upload's
+    /// [`write_mode`](struct.UploadInfo.html#structfield.write_mode). A
+    /// `FileStore` can also be set to refuse to replace directories with
+    /// [`with_strict_overwrites`](enum.FileStore.html#method.with_strict_overwrites).
     ///
     /// If this operation fails there are no guarantees about the state of the
-    /// file. If that is an issue then you should consider always calling
+    /// file unless the [`UploadInfo`](struct.UploadInfo.html) asks for
+    /// [cleanup on failure](struct.UploadInfo.html#method.cleanup_on_failure),
+    /// in which case the backend removes anything it partially wrote.
+    /// Otherwise you should consider always calling
     /// [`delete_object`](trait.StorageBackend.html#method.delete_object) after a
     /// failure.
     ///
@@ -183,16 +351,57 @@ pub trait StorageBackend: Clone + Send + 'static {
     /// written.
     ///
     /// Any error emitted by the stream will cause this operation to fail.
-    fn write_file_from_stream<S, I, E, P>(&self, info: P, stream: S) -> WriteCompleteFuture
-    where
-        S: Stream<Item = Result<I, E>> + Send + 'static,
-        I: IntoBuf + 'static,
-        E: Into<StorageError> + 'static,
-        P: TryInto<UploadInfo>,
-        P::Error: Into<StorageError>;
+    fn write_file_from_stream(&self, info: UploadInfo, stream: DataStream) -> WriteCompleteFuture;
+
+    /// Writes a stream of data to the file at the given path and resolves to
+    /// the [`Object`](enum.Object.html) that was written.
+    ///
+    /// This behaves the same as [`write_file_from_stream`](#tymethod.write_file_from_stream).
+    /// By default the object is looked up once the write completes, backends
+    /// that learn about the new file from the upload itself should override
+    /// this to avoid the extra request.
+    fn write_file_from_stream_returning(
+        &self,
+        info: UploadInfo,
+        stream: DataStream,
+    ) -> WrittenObjectFuture {
+        let lookup = self.get_object(info.path.clone());
+        let write = self.write_file_from_stream(info, stream);
+
+        WrittenObjectFuture::from_future(async move {
+            write.await?;
+            lookup.await.map_err(TransferError::TargetError)
+        })
+    }
+
+    /// Writes the data from an [`UploadSource`](trait.UploadSource.html) to the
+    /// file at the given path.
+    ///
+    /// This behaves the same as [`write_file_from_stream`](#tymethod.write_file_from_stream)
+    /// but backends that retry failed requests can read the data they need
+    /// again from the source rather than holding on to everything they have
+    /// sent. By default the whole source is read as a single stream.
+    fn write_file_from_source(
+        &self,
+        info: UploadInfo,
+        source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        let progress = info.progress.clone();
+        let size = source.size();
+        let stream = size
+            .and_then(move |size| {
+                source
+                    .read_range(0, size)
+                    .map_ok(move |stream| match progress {
+                        Some(listener) => listener.track(stream, Some(size)),
+                        None => stream,
+                    })
+            })
+            .try_flatten_stream();
+        self.write_file_from_stream(info, DataStream::from_stream(stream))
+    }
 }
 
-#[enum_dispatch(StorageBackend)]
 /// Provides access to a storage backend.
 ///
 /// `FileStore` exposes all of the functionality guaranteed to be implemented by
@@ -205,9 +414,1651 @@ pub trait StorageBackend: Clone + Send + 'static {
 #[derive(Clone, Debug)]
 pub enum FileStore {
     #[doc(hidden)]
-    #[cfg(feature = "file")]
+    #[cfg(all(feature = "file", not(feature = "wasm")))]
     File(FileBackend),
     #[doc(hidden)]
     #[cfg(feature = "b2")]
     B2(B2Backend),
+    #[doc(hidden)]
+    #[cfg(feature = "remote")]
+    Remote(RemoteBackend),
+}
+
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+impl From<FileBackend> for FileStore {
+    fn from(backend: FileBackend) -> FileStore {
+        FileStore::File(backend)
+    }
+}
+
+#[cfg(feature = "b2")]
+impl From<B2Backend> for FileStore {
+    fn from(backend: B2Backend) -> FileStore {
+        FileStore::B2(backend)
+    }
+}
+
+#[cfg(feature = "remote")]
+impl From<RemoteBackend> for FileStore {
+    fn from(backend: RemoteBackend) -> FileStore {
+        FileStore::Remote(backend)
+    }
+}
+
+/// Calls a method on whichever backend a `FileStore` contains.
+macro_rules! dispatch {
+    ($store:expr, $backend:ident => $call:expr) => {
+        match $store {
+            #[cfg(all(feature = "file", not(feature = "wasm")))]
+            FileStore::File($backend) => $call,
+            #[cfg(feature = "b2")]
+            FileStore::B2($backend) => $call,
+            #[cfg(feature = "remote")]
+            FileStore::Remote($backend) => $call,
+        }
+    };
+}
+
+/// Filters a listing down to just files. A prefix that doesn't exist lists
+/// nothing.
+async fn list_files(listing: ObjectStreamFuture) -> StorageResult<ObjectStream> {
+    match listing.await {
+        // Backends may only discover a missing prefix once listing starts.
+        Ok(stream) => Ok(ObjectStream::from_stream(stream.filter_map(|result| {
+            ready(match result {
+                Ok(object) => match object.object_type() {
+                    ObjectType::Directory => None,
+                    _ => Some(Ok(object)),
+                },
+                Err(e) => match e.kind() {
+                    StorageErrorKind::NotFound(_) => None,
+                    _ => Some(Err(e)),
+                },
+            })
+        }))),
+        Err(e) => match e.kind() {
+            StorageErrorKind::NotFound(_) => Ok(ObjectStream::from_stream(empty())),
+            _ => Err(e),
+        },
+    }
+}
+
+/// Collects the first `size` objects of a listing in path order.
+async fn list_page(
+    listing: ObjectStreamFuture,
+    in_order: bool,
+    size: usize,
+) -> StorageResult<ListPage> {
+    let mut stream = listing.await?;
+    // Keeps the smallest paths seen, one more than needed to know whether
+    // there is another page.
+    let mut heap: BinaryHeap<Object> = BinaryHeap::new();
+    while let Some(object) = stream.next().await {
+        heap.push(object?);
+        if heap.len() > size + 1 {
+            heap.pop();
+        }
+
+        if in_order && heap.len() > size {
+            break;
+        }
+    }
+
+    let mut objects = heap.into_sorted_vec();
+    let next = if objects.len() > size {
+        objects.truncate(size);
+        objects.last().map(|o| ListToken::after(o.path()))
+    } else {
+        None
+    };
+
+    Ok(ListPage { objects, next })
+}
+
+/// Uses the object to check a file's data against its checksum and to report
+/// progress against its size.
+async fn checked_stream(
+    lookup: ObjectFuture,
+    stream: DataStreamFuture,
+    path: ObjectPath,
+    options: StreamOptions,
+) -> StorageResult<DataStream> {
+    let object = lookup.await?;
+    let stream = stream.await?;
+
+    let stream = match object.checksum() {
+        Some(checksum) if options.verifies() => {
+            types::checksum::verify_stream(stream, path, checksum)?
+        }
+        _ => stream,
+    };
+
+    Ok(options.apply(stream, Some(object.len())))
+}
+
+/// Reports the data read from an upload's stream to its progress listener and
+/// fails the stream if the upload is cancelled.
+fn prepare_upload(info: &UploadInfo, stream: DataStream) -> DataStream {
+    let stream = match info.progress {
+        Some(ref listener) => listener.track(stream, None),
+        None => stream,
+    };
+
+    match info.cancellation {
+        Some(ref token) => token.wrap_stream(stream),
+        None => stream,
+    }
+}
+
+/// Abandons a copy or move if it is cancelled.
+fn cancellable(info: &UploadInfo, operation: WriteCompleteFuture) -> WriteCompleteFuture {
+    match info.cancellation {
+        Some(ref token) => token.wrap_operation(operation),
+        None => operation,
+    }
+}
+
+/// Refuses to replace a directory at the target before starting an operation.
+///
+/// Write modes are left to the backends, which can apply them as part of the
+/// write where the storage allows it.
+async fn check_overwrite<R>(
+    lookup: Option<ObjectFuture>,
+    target: ObjectPath,
+    operation: WrappedFuture<Result<R, TransferError>>,
+) -> Result<R, TransferError>
+where
+    R: Send + 'static,
+{
+    if let Some(lookup) = lookup {
+        match lookup.await {
+            Ok(ref object) if object.object_type() == ObjectType::Directory => {
+                return Err(TransferError::TargetError(error::already_exists(
+                    target,
+                    Some("Refusing to replace a directory"),
+                )))
+            }
+            Ok(_) => (),
+            Err(e) => match e.kind() {
+                StorageErrorKind::NotFound(_) => (),
+                _ => return Err(TransferError::TargetError(e)),
+            },
+        }
+    }
+
+    operation.await
+}
+
+/// Reads an entire file into memory.
+async fn read_all(stream: DataStreamFuture) -> StorageResult<Data> {
+    let mut stream = stream.await?;
+    let mut buffer = BytesMut::new();
+    while let Some(data) = stream.next().await {
+        buffer.extend_from_slice(&data?);
+    }
+
+    Ok(buffer.freeze())
+}
+
+impl StorageBackend for FileStore {
+    fn backend_type(&self) -> backends::Backend {
+        dispatch!(self, b => b.backend_type())
+    }
+
+    fn list_objects(&self, mut prefix: ObjectPath) -> ObjectStreamFuture {
+        if let Err(e) = self.path_policy().validate(&prefix) {
+            return ObjectStreamFuture::from_value(Err(e));
+        }
+
+        let span = self.operation_span("list_objects", &prefix);
+        span.list(match self.directory_semantics() {
+            DirectorySemantics::Native => {
+                dispatch!(self, b => StorageBackend::list_objects(b, prefix))
+            }
+            DirectorySemantics::Prefix => ObjectStreamFuture::from_future(list_files(
+                dispatch!(self, b => StorageBackend::list_objects(b, prefix)),
+            )),
+            DirectorySemantics::StrictSlash => {
+                if !prefix.is_dir_prefix() {
+                    prefix.push_part("");
+                }
+
+                ObjectStreamFuture::from_future(list_files(
+                    dispatch!(self, b => StorageBackend::list_objects(b, prefix)),
+                ))
+            }
+        })
+    }
+
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        if let Err(e) = self.path_policy().validate(&prefix) {
+            return ObjectStreamFuture::from_value(Err(e));
+        }
+
+        let span = self.operation_span("list_objects", &prefix);
+        span.list(match self.directory_semantics() {
+            DirectorySemantics::Native => {
+                dispatch!(self, b => StorageBackend::list_objects_with_options(b, prefix, options))
+            }
+            DirectorySemantics::Prefix => {
+                options.apply(prefix.clone(), StorageBackend::list_objects(self, prefix))
+            }
+            DirectorySemantics::StrictSlash => {
+                // Depth is measured from the directory that is actually listed.
+                let mut base = prefix.clone();
+                if !base.is_dir_prefix() {
+                    base.push_part("");
+                }
+
+                options.apply(base, StorageBackend::list_objects(self, prefix))
+            }
+        })
+    }
+
+    fn lists_in_order(&self) -> bool {
+        dispatch!(self, b => b.lists_in_order())
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        if let Err(e) = self.path_policy().validate(&dir) {
+            return ObjectStreamFuture::from_value(Err(e));
+        }
+
+        let span = self.operation_span("list_directory", &dir);
+        span.list(dispatch!(self, b => StorageBackend::list_directory(b, dir)))
+    }
+
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
+        if let Err(e) = self.path_policy().validate(&path) {
+            return ObjectFuture::from_value(Err(e));
+        }
+
+        let span = self.operation_span("get_object", &path);
+        span.future(self.object_cache().lookup(
+            path.clone(),
+            dispatch!(self, b => StorageBackend::get_object(b, path)),
+        ))
+    }
+
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        if let Err(e) = self.path_policy().validate(&path) {
+            return DataStreamFuture::from_value(Err(e));
+        }
+
+        let span = self.operation_span("get_file_stream", &path);
+        span.download(dispatch!(self, b => StorageBackend::get_file_stream(b, path)))
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        if let Err(e) = self.path_policy().validate(&path) {
+            return DataStreamFuture::from_value(Err(e));
+        }
+
+        let span = self.operation_span("get_file_stream", &path);
+        span.download(
+            dispatch!(self, b => StorageBackend::get_file_stream_range(b, path, offset, length)),
+        )
+    }
+
+    fn copy_file(&self, source: ObjectPath, mut target: UploadInfo) -> CopyCompleteFuture {
+        if let Err(e) = self.path_policy().validate(&source) {
+            return CopyCompleteFuture::from_value(Err(TransferError::SourceError(e)));
+        }
+        if let Err(e) = self.path_policy().validate(&target.path) {
+            return CopyCompleteFuture::from_value(Err(TransferError::TargetError(e)));
+        }
+
+        target
+            .cleanup_on_failure
+            .get_or_insert(self.cleanup_on_failure());
+        let span = self
+            .operation_span("copy_file", &source)
+            .target(&target.path);
+        span.future(self.event_log().record(
+            events::Operation::Copy,
+            self.backend_type(),
+            source.clone(),
+            Some(target.path.clone()),
+            self.object_cache().invalidate_after(
+                vec![target.path.clone()],
+                self.limited(check_overwrite(
+                    self.overwrite_lookup(&target),
+                    target.path.clone(),
+                    cancellable(
+                        &target,
+                        dispatch!(self, b => StorageBackend::copy_file(b, source, target.clone())),
+                    ),
+                )),
+            ),
+        ))
+    }
+
+    fn move_file(&self, source: ObjectPath, mut target: UploadInfo) -> MoveCompleteFuture {
+        if let Err(e) = self.path_policy().validate(&source) {
+            return MoveCompleteFuture::from_value(Err(TransferError::SourceError(e)));
+        }
+        if let Err(e) = self.path_policy().validate(&target.path) {
+            return MoveCompleteFuture::from_value(Err(TransferError::TargetError(e)));
+        }
+
+        target
+            .cleanup_on_failure
+            .get_or_insert(self.cleanup_on_failure());
+        let span = self
+            .operation_span("move_file", &source)
+            .target(&target.path);
+        span.future(self.event_log().record(
+            events::Operation::Move,
+            self.backend_type(),
+            source.clone(),
+            Some(target.path.clone()),
+            self.object_cache().invalidate_after(
+                vec![source.clone(), target.path.clone()],
+                self.limited(check_overwrite(
+                    self.overwrite_lookup(&target),
+                    target.path.clone(),
+                    cancellable(
+                        &target,
+                        dispatch!(self, b => StorageBackend::move_file(b, source, target.clone())),
+                    ),
+                )),
+            ),
+        ))
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        if let Err(e) = self.path_policy().validate(&path) {
+            return OperationCompleteFuture::from_value(Err(e));
+        }
+
+        let span = self.operation_span("delete_object", &path);
+        span.future(self.event_log().record(
+            events::Operation::Delete,
+            self.backend_type(),
+            path.clone(),
+            None,
+            self.object_cache().invalidate_after(
+                vec![path.clone()],
+                dispatch!(self, b => StorageBackend::delete_object(b, path)),
+            ),
+        ))
+    }
+
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        if let Err(e) = self.path_policy().validate(&path) {
+            return OperationCompleteFuture::from_value(Err(e));
+        }
+
+        let span = self.operation_span("create_directory", &path);
+        span.future(self.object_cache().invalidate_after(
+            vec![path.clone()],
+            dispatch!(self, b => StorageBackend::create_directory(b, path)),
+        ))
+    }
+
+    fn write_file_from_stream(
+        &self,
+        mut info: UploadInfo,
+        stream: DataStream,
+    ) -> WriteCompleteFuture {
+        if let Err(e) = self.path_policy().validate(&info.path) {
+            return WriteCompleteFuture::from_value(Err(TransferError::TargetError(e)));
+        }
+
+        info.cleanup_on_failure
+            .get_or_insert(self.cleanup_on_failure());
+        let span = self.operation_span("write_file", &info.path);
+        let stream = span.upload(prepare_upload(&info, stream));
+        span.future(self.event_log().record(
+            events::Operation::Write,
+            self.backend_type(),
+            info.path.clone(),
+            None,
+            self.object_cache().invalidate_after(
+                vec![info.path.clone()],
+                self.limited(check_overwrite(
+                    self.overwrite_lookup(&info),
+                    info.path.clone(),
+                    dispatch!(self, b => StorageBackend::write_file_from_stream(b, info, stream)),
+                )),
+            ),
+        ))
+    }
+
+    fn write_file_from_stream_returning(
+        &self,
+        mut info: UploadInfo,
+        stream: DataStream,
+    ) -> WrittenObjectFuture {
+        if let Err(e) = self.path_policy().validate(&info.path) {
+            return WrittenObjectFuture::from_value(Err(TransferError::TargetError(e)));
+        }
+
+        info.cleanup_on_failure
+            .get_or_insert(self.cleanup_on_failure());
+        let span = self.operation_span("write_file", &info.path);
+        let stream = span.upload(prepare_upload(&info, stream));
+        span.future(self.event_log().record(
+            events::Operation::Write,
+            self.backend_type(),
+            info.path.clone(),
+            None,
+            self.object_cache().invalidate_after(
+                vec![info.path.clone()],
+                self.limited(check_overwrite(
+                    self.overwrite_lookup(&info),
+                    info.path.clone(),
+                    dispatch!(self, b => StorageBackend::write_file_from_stream_returning(b, info, stream)),
+                )),
+            ),
+        ))
+    }
+
+    fn write_file_from_source(
+        &self,
+        mut info: UploadInfo,
+        source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        if let Err(e) = self.path_policy().validate(&info.path) {
+            return WriteCompleteFuture::from_value(Err(TransferError::TargetError(e)));
+        }
+
+        info.cleanup_on_failure
+            .get_or_insert(self.cleanup_on_failure());
+        let source = match info.cancellation {
+            Some(ref token) => token.wrap_source(source),
+            None => source,
+        };
+        let span = self.operation_span("write_file", &info.path);
+        span.future(self.event_log().record(
+            events::Operation::Write,
+            self.backend_type(),
+            info.path.clone(),
+            None,
+            self.object_cache().invalidate_after(
+                vec![info.path.clone()],
+                self.limited(check_overwrite(
+                    self.overwrite_lookup(&info),
+                    info.path.clone(),
+                    dispatch!(self, b => StorageBackend::write_file_from_source(b, info, source)),
+                )),
+            ),
+        ))
+    }
+}
+
+impl FileStore {
+    /// Connects to the storage described by a
+    /// [`StoreConfig`](config/enum.StoreConfig.html). Included with the
+    /// feature "config".
+    #[cfg(feature = "config")]
+    pub fn from_config(config: config::StoreConfig) -> ConnectFuture {
+        config.connect()
+    }
+
+    /// Retrieves the type of this backend.
+    pub fn backend_type(&self) -> backends::Backend {
+        StorageBackend::backend_type(self)
+    }
+
+    fn event_log(&self) -> &events::EventLog {
+        dispatch!(self, b => b.event_log())
+    }
+
+    /// Subscribes to the [events](events/index.html) recorded for every copy,
+    /// move, delete and write made through this `FileStore` or any of its
+    /// clones.
+    ///
+    /// Only operations that start after subscribing are included. Dropping the
+    /// stream ends the subscription.
+    pub fn events(&self) -> events::EventStream {
+        self.event_log().subscribe()
+    }
+
+    /// Subscribes to [`StorageEvent`](events/struct.StorageEvent.html)s
+    /// for every operation made through this `FileStore` or any of its
+    /// clones, sent as each operation starts and again once it finishes or
+    /// fails.
+    ///
+    /// Only operations that start after subscribing are included. Dropping the
+    /// stream ends the subscription.
+    pub fn subscribe_events(&self) -> events::StorageEventStream {
+        self.event_log().subscribe_storage()
+    }
+
+    fn metrics(&self) -> &metrics::Metrics {
+        dispatch!(self, b => b.metrics())
+    }
+
+    /// Sends [metrics](metrics/index.html) for every operation made through
+    /// this `FileStore` or any of its clones, and the requests the backend
+    /// makes, to a sink.
+    ///
+    /// This replaces any sink set previously.
+    pub fn set_metrics_sink<S: metrics::MetricsSink>(&self, sink: S) {
+        self.metrics().set_sink(Some(Arc::new(sink)));
+    }
+
+    /// Stops sending metrics to the sink set with
+    /// [`set_metrics_sink`](#method.set_metrics_sink).
+    pub fn remove_metrics_sink(&self) {
+        self.metrics().set_sink(None);
+    }
+
+    fn operation_span(&self, operation: &'static str, path: &ObjectPath) -> OperationSpan {
+        OperationSpan::new(
+            operation,
+            self.backend_type(),
+            path,
+            self.metrics(),
+            self.event_log(),
+        )
+    }
+
+    fn object_cache(&self) -> &cache::ObjectCache {
+        dispatch!(self, b => b.object_cache())
+    }
+
+    /// Gets how this `FileStore` interprets listing prefixes.
+    pub fn directory_semantics(&self) -> DirectorySemantics {
+        dispatch!(self, b => b.directory_semantics())
+    }
+
+    /// Returns a `FileStore` that interprets listing prefixes as described by
+    /// [`DirectorySemantics`](enum.DirectorySemantics.html).
+    ///
+    /// Only this `FileStore` and clones made from it afterwards are affected.
+    pub fn with_directory_semantics(mut self, semantics: DirectorySemantics) -> FileStore {
+        dispatch!(&mut self, b => b.set_directory_semantics(semantics));
+        self
+    }
+
+    /// Gets whether this `FileStore` refuses to replace directories with files.
+    pub fn strict_overwrites(&self) -> bool {
+        dispatch!(self, b => b.strict_overwrites())
+    }
+
+    /// Returns a `FileStore` that refuses to replace a directory with a file
+    /// when copying, moving or writing unless the
+    /// [`UploadInfo`](struct.UploadInfo.html) sets `replace_directory`. The
+    /// operation fails with an
+    /// [`AlreadyExists`](enum.StorageErrorKind.html#variant.AlreadyExists)
+    /// error instead.
+    ///
+    /// Only this `FileStore` and clones made from it afterwards are affected.
+    pub fn with_strict_overwrites(mut self, strict: bool) -> FileStore {
+        dispatch!(&mut self, b => b.set_strict_overwrites(strict));
+        self
+    }
+
+    /// Gets whether uploads that don't say otherwise remove whatever was
+    /// partially written when they fail.
+    pub fn cleanup_on_failure(&self) -> bool {
+        dispatch!(self, b => b.cleanup_on_failure())
+    }
+
+    /// Returns a `FileStore` that by default removes whatever was partially
+    /// written when a write fails. Individual uploads can override this with
+    /// [`UploadInfo::cleanup_on_failure`](struct.UploadInfo.html#method.cleanup_on_failure).
+    ///
+    /// Only this `FileStore` and clones made from it afterwards are affected.
+    pub fn with_cleanup_on_failure(mut self, cleanup: bool) -> FileStore {
+        dispatch!(&mut self, b => b.set_cleanup_on_failure(cleanup));
+        self
+    }
+
+    /// Gets the rules that paths must follow to be used with this `FileStore`.
+    /// Paths are checked against these before any request is made.
+    pub fn path_policy(&self) -> &PathPolicy {
+        dispatch!(self, b => b.path_policy())
+    }
+
+    /// Returns a `FileStore` that checks paths against the given
+    /// [`PathPolicy`](struct.PathPolicy.html) instead of the backend's own.
+    /// With the feature "lock" the
+    /// [lock directory](lock/constant.LOCK_DIRECTORY.html) is always reserved.
+    ///
+    /// Only this `FileStore` and clones made from it afterwards are affected.
+    pub fn with_path_policy(self, policy: PathPolicy) -> FileStore {
+        #[cfg(feature = "lock")]
+        let policy = lock::reserve_lock_directory(policy);
+        self.with_internal_path_policy(policy)
+    }
+
+    /// Like [`with_path_policy`](#method.with_path_policy) but the policy is
+    /// used as is.
+    pub(crate) fn with_internal_path_policy(mut self, policy: PathPolicy) -> FileStore {
+        dispatch!(&mut self, b => b.set_path_policy(policy));
+        self
+    }
+
+    /// Gets the [`ConcurrencyLimiter`](struct.ConcurrencyLimiter.html) that
+    /// transfers through this `FileStore` wait for, if any.
+    pub fn concurrency_limiter(&self) -> Option<&ConcurrencyLimiter> {
+        dispatch!(self, b => b.concurrency_limiter())
+    }
+
+    /// Returns a `FileStore` whose writes, copies and moves wait for a permit
+    /// from the given [`ConcurrencyLimiter`](struct.ConcurrencyLimiter.html).
+    /// Share the limiter between stores to bound the transfers they make in
+    /// total.
+    ///
+    /// Only this `FileStore` and clones made from it afterwards are affected.
+    pub fn with_concurrency_limiter(mut self, limiter: ConcurrencyLimiter) -> FileStore {
+        dispatch!(&mut self, b => b.set_concurrency_limiter(Some(limiter)));
+        self
+    }
+
+    /// Runs a transfer once the concurrency limiter, if any, allows it.
+    fn limited<F>(&self, operation: F) -> WrappedFuture<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send,
+    {
+        match self.concurrency_limiter() {
+            Some(limiter) => limiter.limit_operation(operation),
+            None => WrappedFuture::from_future(operation),
+        }
+    }
+
+    /// Looks up the target of an upload if it must be checked before
+    /// replacing it.
+    fn overwrite_lookup(&self, target: &UploadInfo) -> Option<ObjectFuture> {
+        if self.strict_overwrites() && !target.replace_directory {
+            Some(dispatch!(self, b => StorageBackend::get_object(b, target.path.clone())))
+        } else {
+            None
+        }
+    }
+
+    /// Caches the objects returned by
+    /// [`get_object`](#method.get_object) with the given
+    /// [settings](cache/struct.CacheSettings.html), `None` disables the cache.
+    ///
+    /// The cache is shared with all clones of this `FileStore` and starts out
+    /// empty whenever it is configured.
+    pub fn cache_objects(&self, settings: Option<cache::CacheSettings>) {
+        self.object_cache().configure(settings)
+    }
+
+    /// Drops every object in the [cache](cache/index.html), for example after
+    /// the storage has been changed by something other than this `FileStore`.
+    pub fn clear_object_cache(&self) {
+        self.object_cache().clear()
+    }
+
+    /// Streams the files under a prefix as an [archive](archive/index.html).
+    /// Included with the feature "archive".
+    ///
+    /// Paths in the archive are relative to the prefix.
+    #[cfg(feature = "archive")]
+    pub fn archive<P>(&self, prefix: P, format: archive::ArchiveFormat) -> DataStream
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match prefix.try_into() {
+            Ok(p) => archive::archive(self.clone(), p, format),
+            Err(e) => DataStream::from_stream(futures::stream::once(futures::future::ready(Err(
+                e.into(),
+            )))),
+        }
+    }
+
+    /// Writes the files in a tar, gzip compressed tar or zip
+    /// [archive](archive/index.html) under a prefix. Included with the feature "archive".
+    ///
+    /// Resolves to the number of files written. Use an
+    /// [`Extractor`](archive/struct.Extractor.html) to control how many files
+    /// are written at once.
+    #[cfg(feature = "archive")]
+    pub fn extract<S, I, E, P>(&self, stream: S, prefix: P) -> archive::ExtractFuture
+    where
+        S: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        archive::Extractor::new(self.clone()).extract(stream, prefix)
+    }
+
+    /// Cancels uploads that were started longer ago than `older_than` but
+    /// never finished. Resolves to the paths of the files whose uploads were
+    /// cancelled.
+    ///
+    /// Backends that upload large files in parts, like B2, keep the parts of
+    /// unfinished uploads around until they are cleaned up. For other backends
+    /// this does nothing.
+    pub fn cleanup_incomplete_uploads(&self, older_than: Duration) -> PathListFuture {
+        match self {
+            #[cfg(feature = "b2")]
+            FileStore::B2(b) => b.cleanup_incomplete_uploads(older_than),
+            #[allow(unreachable_patterns)]
+            _ => PathListFuture::from_value(Ok(Vec::new())),
+        }
+    }
+
+    /// Hides a file, keeping its earlier versions. Included with the feature
+    /// "b2".
+    ///
+    /// See [`B2Backend::hide_object`](backends/b2/struct.B2Backend.html#method.hide_object).
+    /// Other backends cannot hide files.
+    #[cfg(feature = "b2")]
+    pub fn hide_object<P>(&self, path: P) -> OperationCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let path = match path.try_into() {
+            Ok(p) => p,
+            Err(e) => return OperationCompleteFuture::from_value(Err(e.into())),
+        };
+
+        match self {
+            FileStore::B2(b) => self
+                .object_cache()
+                .invalidate_after(vec![path.clone()], b.hide_object(path)),
+            #[allow(unreachable_patterns)]
+            _ => OperationCompleteFuture::from_value(Err(error::invalid_settings(Some(
+                "This backend does not support hiding files.",
+            )))),
+        }
+    }
+
+    /// Creates a URL that downloads the file at `path` without any other
+    /// credentials until `expiry` has passed. Web clients can use this to
+    /// fetch files directly from the storage rather than through a server.
+    ///
+    /// Only the B2 backend can create these URLs, see
+    /// [`B2Backend::presigned_url`](backends/b2/struct.B2Backend.html#method.presigned_url).
+    pub fn presigned_url<P>(&self, path: P, expiry: Duration) -> StringFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let path = match path.try_into() {
+            Ok(p) => p,
+            Err(e) => return StringFuture::from_value(Err(e.into())),
+        };
+
+        match self {
+            #[cfg(feature = "b2")]
+            FileStore::B2(b) => b.presigned_url(path, expiry),
+            #[allow(unreachable_patterns)]
+            _ => StringFuture::from_value(Err(error::invalid_settings(Some(
+                "This backend cannot create presigned URLs.",
+            )))),
+        }
+    }
+
+    /// Creates an authorization to download the files beneath a prefix.
+    /// Included with the feature "b2".
+    ///
+    /// See [`B2Backend::get_download_authorization`](backends/b2/struct.B2Backend.html#method.get_download_authorization).
+    #[cfg(feature = "b2")]
+    pub fn get_download_authorization<P>(
+        &self,
+        prefix: P,
+        valid_duration: Duration,
+    ) -> backends::b2::DownloadAuthorizationFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let prefix = match prefix.try_into() {
+            Ok(p) => p,
+            Err(e) => return backends::b2::DownloadAuthorizationFuture::from_value(Err(e.into())),
+        };
+
+        match self {
+            FileStore::B2(b) => b.get_download_authorization(prefix, valid_duration),
+            #[allow(unreachable_patterns)]
+            _ => {
+                backends::b2::DownloadAuthorizationFuture::from_value(Err(error::invalid_settings(
+                    Some("This backend does not support download authorizations."),
+                )))
+            }
+        }
+    }
+
+    /// Looks up a file by its B2 file id. Included with the feature "b2".
+    ///
+    /// See [`B2Backend::get_object_by_id`](backends/b2/struct.B2Backend.html#method.get_object_by_id).
+    /// Other backends have no file ids.
+    #[cfg(feature = "b2")]
+    pub fn get_object_by_id(&self, file_id: &str) -> ObjectFuture {
+        match self {
+            FileStore::B2(b) => b.get_object_by_id(file_id),
+            #[allow(unreachable_patterns)]
+            _ => ObjectFuture::from_value(Err(error::invalid_settings(Some(
+                "This backend does not support file ids.",
+            )))),
+        }
+    }
+
+    /// Streams the content of the file with a B2 file id. Included with the
+    /// feature "b2".
+    ///
+    /// See [`B2Backend::get_file_stream_by_id`](backends/b2/struct.B2Backend.html#method.get_file_stream_by_id).
+    #[cfg(feature = "b2")]
+    pub fn get_file_stream_by_id(&self, file_id: &str) -> DataStreamFuture {
+        match self {
+            FileStore::B2(b) => b.get_file_stream_by_id(file_id),
+            #[allow(unreachable_patterns)]
+            _ => DataStreamFuture::from_value(Err(error::invalid_settings(Some(
+                "This backend does not support file ids.",
+            )))),
+        }
+    }
+
+    /// Starts a large file upload that can be resumed after a crash. Included
+    /// with the feature "b2".
+    ///
+    /// The returned [`UploadSession`](backends/b2/struct.UploadSession.html)
+    /// can be serialized and passed to
+    /// [`resume_upload`](#method.resume_upload) along with the data to upload,
+    /// even from a different process. Only the B2 backend supports resumable
+    /// uploads.
+    #[cfg(feature = "b2")]
+    pub fn begin_upload<P>(&self, info: P) -> backends::b2::UploadSessionFuture
+    where
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+    {
+        let info = match info.try_into() {
+            Ok(i) => i,
+            Err(e) => return backends::b2::UploadSessionFuture::from_value(Err(e.into())),
+        };
+
+        match self {
+            FileStore::B2(b) => b.begin_upload(info),
+            #[allow(unreachable_patterns)]
+            _ => backends::b2::UploadSessionFuture::from_value(Err(error::invalid_settings(Some(
+                "This backend does not support resumable uploads.",
+            )))),
+        }
+    }
+
+    /// Uploads the data for a session started with
+    /// [`begin_upload`](#method.begin_upload). Included with the feature "b2".
+    ///
+    /// Parts that were already uploaded with matching hashes are skipped, so
+    /// the source must provide the same data as the original attempt.
+    #[cfg(feature = "b2")]
+    pub fn resume_upload<S>(
+        &self,
+        session: backends::b2::UploadSession,
+        source: S,
+    ) -> WriteCompleteFuture
+    where
+        S: UploadSource,
+    {
+        let path = match ObjectPath::new(session.path()) {
+            Ok(p) => p,
+            Err(e) => return WriteCompleteFuture::from_value(Err(TransferError::TargetError(e))),
+        };
+
+        match self {
+            FileStore::B2(b) => self
+                .object_cache()
+                .invalidate_after(vec![path], b.resume_upload(session, Arc::new(source))),
+            #[allow(unreachable_patterns)]
+            _ => WriteCompleteFuture::from_value(Err(TransferError::TargetError(
+                error::invalid_settings(Some("This backend does not support resumable uploads.")),
+            ))),
+        }
+    }
+
+    /// Returns the tags on an object. Included with the feature "tags".
+    ///
+    /// See [`TaggingBackend::get_tags`](tags/trait.TaggingBackend.html#tymethod.get_tags).
+    #[cfg(feature = "tags")]
+    pub fn get_tags<P>(&self, path: P) -> tags::TagsFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => tags::TaggingBackend::get_tags(self, p),
+            Err(e) => tags::TagsFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Replaces the tags on an object. Included with the feature "tags".
+    ///
+    /// See [`TaggingBackend::set_tags`](tags/trait.TaggingBackend.html#tymethod.set_tags).
+    #[cfg(feature = "tags")]
+    pub fn set_tags<P>(&self, path: P, tags: tags::Tags) -> OperationCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => tags::TaggingBackend::set_tags(self, p, tags),
+            Err(e) => OperationCompleteFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Takes out an exclusive [`Lease`](lock/struct.Lease.html) on a path that
+    /// lasts for `ttl` unless renewed. Included with the feature "lock".
+    ///
+    /// Fails with an
+    /// [`AlreadyExists`](enum.StorageErrorKind.html#variant.AlreadyExists)
+    /// error for the lock file if the lock file already exists, even if the
+    /// lease it records has expired.
+    #[cfg(feature = "lock")]
+    pub fn lock<P>(&self, path: P, ttl: Duration) -> lock::LeaseFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => lock::Lease::acquire(self.clone(), p, ttl),
+            Err(e) => lock::LeaseFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Removes the lock file for a path whoever holds the lease. Included with
+    /// the feature "lock".
+    ///
+    /// Use this to clear a lease left behind by a holder that crashed. Nothing
+    /// checks that the lease has expired or that its holder is gone.
+    #[cfg(feature = "lock")]
+    pub fn break_lock<P>(&self, path: P) -> OperationCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => lock::break_lock(self.clone(), p),
+            Err(e) => OperationCompleteFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Takes a [snapshot](snapshot/index.html) of the files under a prefix.
+    /// Included with the feature "snapshot".
+    ///
+    /// Every file is read in order to hash it.
+    #[cfg(feature = "snapshot")]
+    pub fn snapshot<P>(&self, prefix: P) -> snapshot::SnapshotFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match prefix.try_into() {
+            Ok(p) => snapshot::SnapshotFuture::from_future(snapshot::snapshot(self.clone(), p)),
+            Err(e) => snapshot::SnapshotFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Copies the files in a [snapshot](snapshot/index.html) of this store to
+    /// the same paths in the target store. Included with the feature
+    /// "snapshot".
+    ///
+    /// Fails with an
+    /// [`InvalidData`](enum.StorageErrorKind.html#variant.InvalidData) source
+    /// error if a file has changed since the snapshot was taken.
+    #[cfg(feature = "snapshot")]
+    pub fn restore(
+        &self,
+        manifest: &snapshot::SnapshotManifest,
+        target: &FileStore,
+    ) -> CopyCompleteFuture {
+        CopyCompleteFuture::from_future(snapshot::restore(
+            self.clone(),
+            manifest.clone(),
+            target.clone(),
+        ))
+    }
+
+    /// Compares the files under a prefix against a
+    /// [snapshot](snapshot/index.html) manifest. Included with the feature
+    /// "snapshot".
+    ///
+    /// The prefix need not match the manifest's so a restored copy can be
+    /// verified wherever it lives. Files are only read when their size matches
+    /// the manifest.
+    #[cfg(feature = "snapshot")]
+    pub fn verify<P>(
+        &self,
+        prefix: P,
+        manifest: &snapshot::SnapshotManifest,
+    ) -> snapshot::VerifyFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match prefix.try_into() {
+            Ok(p) => snapshot::VerifyFuture::from_future(snapshot::verify(
+                self.clone(),
+                p,
+                manifest.clone(),
+            )),
+            Err(e) => snapshot::VerifyFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Lists the objects that are prefixed by the given prefix.
+    ///
+    /// See [`StorageBackend::list_objects`](trait.StorageBackend.html#tymethod.list_objects).
+    pub fn list_objects<P>(&self, prefix: P) -> ObjectStreamFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match prefix.try_into() {
+            Ok(p) => StorageBackend::list_objects(self, p),
+            Err(e) => ObjectStreamFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Lists the objects that are prefixed by the given prefix, limited by the
+    /// given options.
+    ///
+    /// See [`StorageBackend::list_objects_with_options`](trait.StorageBackend.html#method.list_objects_with_options).
+    pub fn list_objects_with_options<P>(
+        &self,
+        prefix: P,
+        options: ListOptions,
+    ) -> ObjectStreamFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match prefix.try_into() {
+            Ok(p) => StorageBackend::list_objects_with_options(self, p, options),
+            Err(e) => ObjectStreamFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Lists a single page of at most `size` objects prefixed by the given
+    /// prefix, starting from where `token` marks.
+    ///
+    /// The page includes a token for the next page which can be persisted and
+    /// used to resume the listing later, even from another process. Objects
+    /// created or deleted between pages may or may not be seen.
+    pub fn list_objects_page<P>(&self, prefix: P, token: ListToken, size: usize) -> ListPageFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        if size == 0 {
+            return ListPageFuture::from_value(Err(error::invalid_settings(Some(
+                "A page must contain at least one object.",
+            ))));
+        }
+
+        let mut options = token.options();
+        if self.lists_in_order() {
+            options = options.max_results(size + 1);
+        }
+
+        ListPageFuture::from_future(list_page(
+            self.list_objects_with_options(prefix, options),
+            self.lists_in_order(),
+            size,
+        ))
+    }
+
+    /// Lists the objects whose paths match a [glob pattern](struct.Glob.html).
+    ///
+    /// Only objects beneath the literal start of the pattern are listed from
+    /// the backend so `a/b/*.txt` never looks outside of `a/b/`.
+    pub fn list_glob(&self, pattern: &str) -> ObjectStreamFuture {
+        let glob = match Glob::new(pattern) {
+            Ok(g) => g,
+            Err(e) => return ObjectStreamFuture::from_value(Err(e)),
+        };
+
+        let listing = StorageBackend::list_objects(self, glob.prefix());
+        ObjectStreamFuture::from_future(listing.map_ok(move |stream| {
+            ObjectStream::from_stream(stream.try_filter(move |o| ready(glob.matches(&o.path()))))
+        }))
+    }
+
+    /// Lists the objects that exist in the given (possibly virtual) directory.
+    ///
+    /// See [`StorageBackend::list_directory`](trait.StorageBackend.html#tymethod.list_directory).
+    pub fn list_directory<P>(&self, dir: P) -> ObjectStreamFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match dir.try_into() {
+            Ok(p) => StorageBackend::list_directory(self, p),
+            Err(e) => ObjectStreamFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Gets info about the object at the given path.
+    ///
+    /// See [`StorageBackend::get_object`](trait.StorageBackend.html#tymethod.get_object).
+    pub fn get_object<P>(&self, path: P) -> ObjectFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => StorageBackend::get_object(self, p),
+            Err(e) => ObjectFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Gets a stream of data for the file at the given path.
+    ///
+    /// See [`StorageBackend::get_file_stream`](trait.StorageBackend.html#tymethod.get_file_stream).
+    pub fn get_file_stream<P>(&self, path: P) -> DataStreamFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => StorageBackend::get_file_stream(self, p),
+            Err(e) => DataStreamFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Reads the entire contents of the file at the given path.
+    ///
+    /// This is intended for small files, larger files are better handled as a
+    /// stream. See [`get_file_stream`](#method.get_file_stream).
+    pub fn read_to_bytes<P>(&self, path: P) -> DataFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        DataFuture::from_future(read_all(self.get_file_stream(path)))
+    }
+
+    /// Reads the entire contents of the file at the given path as UTF-8 text.
+    ///
+    /// This will return an [`InvalidData`](enum.StorageErrorKind.html#variant.InvalidData)
+    /// error if the file is not valid UTF-8.
+    pub fn read_to_string<P>(&self, path: P) -> StringFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let read = read_all(self.get_file_stream(path));
+        StringFuture::from_future(async move {
+            String::from_utf8(read.await?.to_vec())
+                .map_err(|e| error::invalid_data(Some(&e.to_string())))
+        })
+    }
+
+    /// Opens the file at the given path for reading at arbitrary offsets.
+    ///
+    /// This will return a [`NotFound`](enum.StorageErrorKind.html#variant.NotFound)
+    /// error if the object at the path does not exist or is not a file.
+    pub fn open_file<P>(&self, path: P) -> OpenFileFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let path = match path.try_into() {
+            Ok(p) => p,
+            Err(e) => return OpenFileFuture::from_value(Err(e.into())),
+        };
+
+        let store = self.clone();
+        let lookup = StorageBackend::get_object(self, path.clone());
+        OpenFileFuture::from_future(async move {
+            let object = lookup.await?;
+            if object.object_type() != ObjectType::File {
+                return Err(error::not_found(path, None));
+            }
+
+            Ok(OpenFile::new(store, object))
+        })
+    }
+
+    /// Gets a stream of part of the data for the file at the given path.
+    ///
+    /// See [`StorageBackend::get_file_stream_range`](trait.StorageBackend.html#method.get_file_stream_range).
+    pub fn get_file_stream_range<P>(
+        &self,
+        path: P,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => StorageBackend::get_file_stream_range(self, p, offset, length),
+            Err(e) => DataStreamFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Gets a stream of data for the file at the given path, read according
+    /// to the given [`StreamOptions`](struct.StreamOptions.html).
+    pub fn get_file_stream_with_options<P>(
+        &self,
+        path: P,
+        options: StreamOptions,
+    ) -> DataStreamFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let path = match path.try_into() {
+            Ok(p) => p,
+            Err(e) => return DataStreamFuture::from_value(Err(e.into())),
+        };
+
+        if !options.verifies() && options.progress_listener().is_none() {
+            return DataStreamFuture::from_future(
+                self.get_file_stream(path)
+                    .map_ok(move |stream| options.apply(stream, None)),
+            );
+        }
+
+        DataStreamFuture::from_future(checked_stream(
+            StorageBackend::get_object(self, path.clone()),
+            StorageBackend::get_file_stream(self, path.clone()),
+            path,
+            options,
+        ))
+    }
+
+    /// Copies a file from one path to another.
+    ///
+    /// See [`StorageBackend::copy_file`](trait.StorageBackend.html#method.copy_file).
+    pub fn copy_file<P, I>(&self, source: P, target: I) -> CopyCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+        I: TryInto<UploadInfo>,
+        I::Error: Into<StorageError>,
+    {
+        let source = match source.try_into() {
+            Ok(p) => p,
+            Err(e) => {
+                return CopyCompleteFuture::from_value(Err(TransferError::SourceError(e.into())))
+            }
+        };
+
+        match target.try_into() {
+            Ok(i) => StorageBackend::copy_file(self, source, i),
+            Err(e) => CopyCompleteFuture::from_value(Err(TransferError::TargetError(e.into()))),
+        }
+    }
+
+    /// Copies every file under `source` to the same relative path under
+    /// `target`, see the [`copy`](copy/index.html) module.
+    ///
+    /// Failing to copy individual files does not stop the copy, check the
+    /// returned [`CopySummary`](copy/struct.CopySummary.html) for failures.
+    pub fn copy_prefix<S, T>(
+        &self,
+        source: S,
+        target: T,
+        options: copy::CopyOptions,
+    ) -> copy::CopyPrefixFuture
+    where
+        S: TryInto<ObjectPath>,
+        S::Error: Into<StorageError>,
+        T: TryInto<ObjectPath>,
+        T::Error: Into<StorageError>,
+    {
+        let source = match source.try_into() {
+            Ok(p) => p,
+            Err(e) => return copy::CopyPrefixFuture::from_value(Err(e.into())),
+        };
+        let target = match target.try_into() {
+            Ok(p) => p,
+            Err(e) => return copy::CopyPrefixFuture::from_value(Err(e.into())),
+        };
+
+        copy::CopyPrefixFuture::from_future(copy::copy_prefix(
+            self.clone(),
+            source,
+            target,
+            options,
+        ))
+    }
+
+    /// Moves a file from one path to another.
+    ///
+    /// See [`StorageBackend::move_file`](trait.StorageBackend.html#method.move_file).
+    pub fn move_file<P, I>(&self, source: P, target: I) -> MoveCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+        I: TryInto<UploadInfo>,
+        I::Error: Into<StorageError>,
+    {
+        let source = match source.try_into() {
+            Ok(p) => p,
+            Err(e) => {
+                return MoveCompleteFuture::from_value(Err(TransferError::SourceError(e.into())))
+            }
+        };
+
+        match target.try_into() {
+            Ok(i) => StorageBackend::move_file(self, source, i),
+            Err(e) => MoveCompleteFuture::from_value(Err(TransferError::TargetError(e.into()))),
+        }
+    }
+
+    /// Creates a directory at the given path along with any missing parents.
+    ///
+    /// See [`StorageBackend::create_directory`](trait.StorageBackend.html#method.create_directory).
+    pub fn create_directory<P>(&self, path: P) -> OperationCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => StorageBackend::create_directory(self, p),
+            Err(e) => OperationCompleteFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Deletes the object at the given path.
+    ///
+    /// See [`StorageBackend::delete_object`](trait.StorageBackend.html#tymethod.delete_object).
+    pub fn delete_object<P>(&self, path: P) -> OperationCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        match path.try_into() {
+            Ok(p) => StorageBackend::delete_object(self, p),
+            Err(e) => OperationCompleteFuture::from_value(Err(e.into())),
+        }
+    }
+
+    /// Deletes many objects.
+    ///
+    /// Paths that can't be parsed are listed in the summary's
+    /// [`invalid`](struct.DeleteSummary.html#structfield.invalid) field, the
+    /// rest are passed to
+    /// [`StorageBackend::delete_objects`](trait.StorageBackend.html#method.delete_objects).
+    pub fn delete_objects<I, P>(&self, paths: I) -> DeleteSummaryFuture
+    where
+        I: IntoIterator<Item = P>,
+        P: TryInto<ObjectPath> + fmt::Display,
+        P::Error: Into<StorageError>,
+    {
+        let mut invalid = Vec::new();
+        let mut valid = Vec::new();
+        for path in paths {
+            let input = path.to_string();
+            match path.try_into() {
+                Ok(p) => valid.push(p),
+                Err(e) => invalid.push((input, e.into())),
+            }
+        }
+
+        let deletes = StorageBackend::delete_objects(self, valid);
+        DeleteSummaryFuture::from_future(async move {
+            let mut summary = deletes.await;
+            summary.invalid.extend(invalid);
+            summary
+        })
+    }
+
+    /// Deletes every file under a prefix.
+    ///
+    /// Include a trailing `/` to only delete files inside that (possibly
+    /// virtual) directory. Directories themselves are left in place on
+    /// backends that have them.
+    pub fn delete_prefix<P>(&self, prefix: P) -> WrappedFuture<StorageResult<DeleteSummary>>
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let listing = self.list_objects(prefix);
+        let store = self.clone();
+        WrappedFuture::from_future(async move {
+            let paths: Vec<ObjectPath> = listing
+                .await?
+                .try_filter_map(|o| {
+                    ready(Ok(if o.object_type() == ObjectType::File {
+                        Some(o.path())
+                    } else {
+                        None
+                    }))
+                })
+                .try_collect()
+                .await?;
+
+            Ok(StorageBackend::delete_objects(&store, paths).await)
+        })
+    }
+
+    /// Writes a stream of data to the file at the given path.
+    ///
+    /// Each chunk is copied into [`Data`](type.Data.html) as it is read. To
+    /// share `Data` chunks with the backend without copying them pass a
+    /// [`DataStream`](type.DataStream.html) to
+    /// [`StorageBackend::write_file_from_stream`](trait.StorageBackend.html#tymethod.write_file_from_stream)
+    /// instead.
+    pub fn write_file_from_stream<S, I, E, P>(&self, info: P, stream: S) -> WriteCompleteFuture
+    where
+        S: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+    {
+        match info.try_into() {
+            Ok(i) => StorageBackend::write_file_from_stream(
+                self,
+                i,
+                DataStream::from_stream(utils::into_data_stream(stream)),
+            ),
+            Err(e) => WriteCompleteFuture::from_value(Err(TransferError::TargetError(e.into()))),
+        }
+    }
+
+    /// Writes a stream of data to the file at the given path and resolves to
+    /// the object that was written.
+    ///
+    /// See [`StorageBackend::write_file_from_stream_returning`](trait.StorageBackend.html#method.write_file_from_stream_returning).
+    pub fn write_file_from_stream_returning<S, I, E, P>(
+        &self,
+        info: P,
+        stream: S,
+    ) -> WrittenObjectFuture
+    where
+        S: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+    {
+        match info.try_into() {
+            Ok(i) => StorageBackend::write_file_from_stream_returning(
+                self,
+                i,
+                DataStream::from_stream(utils::into_data_stream(stream)),
+            ),
+            Err(e) => WrittenObjectFuture::from_value(Err(TransferError::TargetError(e.into()))),
+        }
+    }
+
+    /// Writes the data from an upload source to the file at the given path.
+    ///
+    /// See [`StorageBackend::write_file_from_source`](trait.StorageBackend.html#method.write_file_from_source).
+    pub fn write_file_from_source<P, S>(&self, info: P, source: S) -> WriteCompleteFuture
+    where
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+        S: UploadSource,
+    {
+        match info.try_into() {
+            Ok(i) => StorageBackend::write_file_from_source(self, i, Arc::new(source)),
+            Err(e) => WriteCompleteFuture::from_value(Err(TransferError::TargetError(e.into()))),
+        }
+    }
+
+    /// Writes the given data to the file at the given path, replacing anything
+    /// already there. The data is passed to the backend without being copied.
+    ///
+    /// See [`StorageBackend::write_file_from_stream`](trait.StorageBackend.html#tymethod.write_file_from_stream).
+    pub fn write_bytes<P, D>(&self, info: P, data: D) -> WriteCompleteFuture
+    where
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+        D: Into<Data>,
+    {
+        match info.try_into() {
+            Ok(i) => StorageBackend::write_file_from_stream(
+                self,
+                i,
+                DataStream::from_stream(once(ready(Ok(data.into())))),
+            ),
+            Err(e) => WriteCompleteFuture::from_value(Err(TransferError::TargetError(e.into()))),
+        }
+    }
+
+    /// Downloads the file at the given path to a file on the local disk.
+    ///
+    /// The local file is replaced if it exists and is given the same
+    /// modification time as the object. Nothing is left behind if the download
+    /// fails part way through. Only included with the "file" feature.
+    #[cfg(all(feature = "file", not(feature = "wasm")))]
+    pub fn download_to_path<P, T>(&self, path: P, target: T) -> WriteCompleteFuture
+    where
+        P: TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+        T: AsRef<std::path::Path>,
+    {
+        let path = match path.try_into() {
+            Ok(p) => p,
+            Err(e) => {
+                return WriteCompleteFuture::from_value(Err(TransferError::SourceError(e.into())))
+            }
+        };
+
+        WriteCompleteFuture::from_future(local::download_to_path(
+            self.clone(),
+            path,
+            target.as_ref().to_owned(),
+        ))
+    }
+
+    /// Uploads a file from the local disk.
+    ///
+    /// Unless `info` includes a modification time the local file's is used.
+    /// Only included with the "file" feature.
+    #[cfg(all(feature = "file", not(feature = "wasm")))]
+    pub fn upload_from_path<P, S>(&self, info: P, source: S) -> WriteCompleteFuture
+    where
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+        S: AsRef<std::path::Path>,
+    {
+        let info = match info.try_into() {
+            Ok(i) => i,
+            Err(e) => {
+                return WriteCompleteFuture::from_value(Err(TransferError::TargetError(e.into())))
+            }
+        };
+
+        WriteCompleteFuture::from_future(local::upload_from_path(
+            self.clone(),
+            info,
+            source.as_ref().to_owned(),
+        ))
+    }
+
+    /// Creates a [`FileWriter`](struct.FileWriter.html) that writes data to
+    /// the file as it is produced.
+    ///
+    /// See [`StorageBackend::write_file_from_stream`](trait.StorageBackend.html#tymethod.write_file_from_stream).
+    pub fn write_file<P>(&self, info: P) -> StorageResult<FileWriter>
+    where
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+    {
+        let info = info.try_into().map_err(Into::into)?;
+        self.path_policy().validate(&info.path)?;
+        Ok(FileWriter::new(self, info))
+    }
+
+    /// Writes a stream of data to the file at the given path as long as the
+    /// file's [`etag`](trait.ObjectInfo.html#method.etag) still matches
+    /// `etag`.
+    ///
+    /// This allows safely replacing a file that was read earlier. If the file
+    /// has changed or no longer exists this fails with a
+    /// [`PreconditionFailed`](enum.StorageErrorKind.html#variant.PreconditionFailed)
+    /// error without replacing it.
+    ///
+    /// Only the file backend supports this, it writes the data to a temporary
+    /// file and checks the etag and renames the file into place together. Of
+    /// two conditional writes racing each other through the same backend at
+    /// most one succeeds. Other backends fail with an
+    /// [`InvalidSettings`](enum.StorageErrorKind.html#variant.InvalidSettings)
+    /// error.
+    pub fn write_file_if_match<S, I, E, P>(
+        &self,
+        info: P,
+        etag: &str,
+        stream: S,
+    ) -> WriteCompleteFuture
+    where
+        S: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+        P: TryInto<UploadInfo>,
+        P::Error: Into<StorageError>,
+    {
+        let mut info = match info.try_into() {
+            Ok(i) => i,
+            Err(e) => {
+                return WriteCompleteFuture::from_value(Err(TransferError::TargetError(e.into())))
+            }
+        };
+
+        if let Err(e) = self.path_policy().validate(&info.path) {
+            return WriteCompleteFuture::from_value(Err(TransferError::TargetError(e)));
+        }
+
+        info.cleanup_on_failure
+            .get_or_insert(self.cleanup_on_failure());
+        let span = self.operation_span("write_file", &info.path);
+        let stream = span.upload(prepare_upload(
+            &info,
+            DataStream::from_stream(utils::into_data_stream(stream)),
+        ));
+        let path = info.path.clone();
+        let write = match self {
+            #[cfg(all(feature = "file", not(feature = "wasm")))]
+            FileStore::File(b) => b.write_file_if_match(info, etag.to_owned(), stream),
+            #[allow(unreachable_patterns)]
+            _ => {
+                return WriteCompleteFuture::from_value(Err(TransferError::TargetError(
+                    error::invalid_settings(Some(
+                        "This backend cannot replace files conditionally.",
+                    )),
+                )))
+            }
+        };
+
+        span.future(
+            self.event_log().record(
+                events::Operation::Write,
+                self.backend_type(),
+                path.clone(),
+                None,
+                self.object_cache()
+                    .invalidate_after(vec![path], self.limited(write)),
+            ),
+        )
+    }
 }
@@ -0,0 +1,300 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Client-side lifecycle rules. Included with the feature "lifecycle".
+//!
+//! A [`LifecyclePolicy`](struct.LifecyclePolicy.html) holds
+//! [`Rule`](struct.Rule.html)s that either expire (delete) or transition
+//! (move to an archive location) files once they reach a certain age. Rules
+//! work with any backend since they are applied by listing the store, so
+//! applying a policy to a large store can take some time.
+//!
+//! Policies are applied on demand with
+//! [`run`](struct.LifecyclePolicy.html#method.run) or repeatedly with
+//! [`schedule`](struct.LifecyclePolicy.html#method.schedule). Files without a
+//! known modification time are never affected.
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime};
+
+use futures::stream::TryStreamExt;
+
+use crate::types::*;
+use crate::FileStore;
+
+/// A future that resolves to a [`LifecycleReport`](struct.LifecycleReport.html).
+pub type LifecycleFuture = WrappedFuture<StorageResult<LifecycleReport>>;
+
+/// A stream of [`LifecycleReport`](struct.LifecycleReport.html)s.
+pub type LifecycleStream = WrappedStream<StorageResult<LifecycleReport>>;
+
+/// What happens to files that match a rule.
+#[derive(Clone, Debug)]
+pub enum Action {
+    /// The file is deleted.
+    Expire,
+    /// The file is moved beneath the prefix, either in the same store or in
+    /// another store.
+    Transition {
+        /// The store to move the file to, `None` for the same store.
+        store: Option<FileStore>,
+        /// The prefix to move the file beneath.
+        prefix: ObjectPath,
+    },
+}
+
+/// A lifecycle rule.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    prefix: ObjectPath,
+    age: Duration,
+    action: Action,
+}
+
+impl Rule {
+    /// Deletes files beneath the prefix that were last modified longer ago
+    /// than `age`.
+    pub fn expire(prefix: ObjectPath, age: Duration) -> Rule {
+        Rule {
+            prefix,
+            age,
+            action: Action::Expire,
+        }
+    }
+
+    /// Moves files beneath the prefix that were last modified longer ago than
+    /// `age` to the same path beneath `archive` in the same store.
+    pub fn transition(prefix: ObjectPath, age: Duration, archive: ObjectPath) -> Rule {
+        Rule {
+            prefix,
+            age,
+            action: Action::Transition {
+                store: None,
+                prefix: archive,
+            },
+        }
+    }
+
+    /// Makes a transition rule move files to another store. Has no effect on
+    /// expiration rules.
+    pub fn to_store(mut self, target: FileStore) -> Rule {
+        if let Action::Transition { ref mut store, .. } = self.action {
+            *store = Some(target);
+        }
+        self
+    }
+
+    /// The prefix this rule applies to.
+    pub fn prefix(&self) -> &ObjectPath {
+        &self.prefix
+    }
+
+    /// The age at which files are affected.
+    pub fn age(&self) -> Duration {
+        self.age
+    }
+
+    /// What happens to affected files.
+    pub fn action(&self) -> &Action {
+        &self.action
+    }
+}
+
+/// What applying a policy did.
+#[derive(Debug, Default)]
+pub struct LifecycleReport {
+    /// Files that were deleted.
+    pub expired: Vec<ObjectPath>,
+    /// Files that were moved, with their new paths.
+    pub transitioned: Vec<(ObjectPath, ObjectPath)>,
+    /// Files that could not be deleted or moved.
+    pub failed: Vec<(ObjectPath, StorageError)>,
+}
+
+/// A set of lifecycle rules for a [`FileStore`](../enum.FileStore.html).
+#[derive(Clone, Debug)]
+pub struct LifecyclePolicy {
+    store: FileStore,
+    rules: Vec<Rule>,
+    dry_run: bool,
+}
+
+fn is_not_found(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => true,
+        _ => false,
+    }
+}
+
+async fn transition(
+    source: &FileStore,
+    path: &ObjectPath,
+    modified: Option<SystemTime>,
+    target: &FileStore,
+    target_path: ObjectPath,
+) -> StorageResult<()> {
+    let stream = source.get_file_stream(path.clone()).await?;
+    let info = UploadInfo {
+        path: target_path,
+        modified,
+        ..Default::default()
+    };
+    target.write_file_from_stream(info, stream).await?;
+    source.delete_object(path.clone()).await
+}
+
+impl LifecyclePolicy {
+    /// Creates a policy with no rules.
+    pub fn new(store: FileStore) -> LifecyclePolicy {
+        LifecyclePolicy {
+            store,
+            rules: Vec::new(),
+            dry_run: false,
+        }
+    }
+
+    /// Adds a rule. When more than one rule matches a file only the first
+    /// added is applied.
+    pub fn rule(mut self, rule: Rule) -> LifecyclePolicy {
+        self.rules.push(rule);
+        self
+    }
+
+    /// When true only reports what would be done without changing anything.
+    pub fn dry_run(mut self, dry_run: bool) -> LifecyclePolicy {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Applies the rules now.
+    pub fn run(&self) -> LifecycleFuture {
+        self.run_at(SystemTime::now())
+    }
+
+    /// Applies the rules as if the current time were `now`.
+    pub fn run_at(&self, now: SystemTime) -> LifecycleFuture {
+        LifecycleFuture::from_future(self.clone().apply(now))
+    }
+
+    async fn apply(self, now: SystemTime) -> StorageResult<LifecycleReport> {
+        let mut report = LifecycleReport::default();
+        let mut seen: HashSet<ObjectPath> = HashSet::new();
+
+        for rule in &self.rules {
+            let list_prefix = if rule.prefix.is_empty() {
+                rule.prefix.clone()
+            } else {
+                ObjectPath::new(format!("{}/", rule.prefix))?
+            };
+
+            let objects: StorageResult<Vec<Object>> =
+                match self.store.list_objects(list_prefix).await {
+                    Ok(stream) => stream.try_collect().await,
+                    Err(e) => Err(e),
+                };
+            let objects = match objects {
+                Ok(objects) => objects,
+                // Nothing exists beneath the prefix.
+                Err(ref e) if is_not_found(e) => continue,
+                Err(e) => return Err(e),
+            };
+
+            for object in objects {
+                if object.object_type() != ObjectType::File {
+                    continue;
+                }
+
+                let modified = match object.modified() {
+                    Some(modified) => modified,
+                    None => continue,
+                };
+                match now.duration_since(modified) {
+                    Ok(age) if age > rule.age => (),
+                    _ => continue,
+                }
+
+                // Skip files handled by an earlier rule, including any moved
+                // to where this rule applies.
+                let path = object.path();
+                if !seen.insert(path.clone()) {
+                    continue;
+                }
+
+                match rule.action {
+                    Action::Expire => {
+                        if !self.dry_run {
+                            if let Err(e) = self.store.delete_object(path.clone()).await {
+                                report.failed.push((path, e));
+                                continue;
+                            }
+                        }
+                        report.expired.push(path);
+                    }
+                    Action::Transition {
+                        ref store,
+                        ref prefix,
+                    } => {
+                        let target_path = prefix.join(&path);
+                        seen.insert(target_path.clone());
+
+                        if !self.dry_run {
+                            let result = match store {
+                                Some(target) => {
+                                    transition(
+                                        &self.store,
+                                        &path,
+                                        Some(modified),
+                                        target,
+                                        target_path.clone(),
+                                    )
+                                    .await
+                                }
+                                None => self
+                                    .store
+                                    .move_file(path.clone(), target_path.clone())
+                                    .await
+                                    .map_err(StorageError::from),
+                            };
+
+                            if let Err(e) = result {
+                                report.failed.push((path, e));
+                                continue;
+                            }
+                        }
+                        report.transitioned.push((path, target_path));
+                    }
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Applies the rules now and then repeatedly, waiting `interval` between
+    /// each run. The stream never ends.
+    #[cfg(not(feature = "wasm"))]
+    pub fn schedule(&self, interval: Duration) -> LifecycleStream {
+        let policy = self.clone();
+        let stream = futures::stream::unfold(true, move |first| {
+            let policy = policy.clone();
+            async move {
+                if !first {
+                    tokio_timer::delay_for(interval).await;
+                }
+                Some((policy.apply(SystemTime::now()).await, false))
+            }
+        });
+
+        LifecycleStream::from_stream(stream)
+    }
+}
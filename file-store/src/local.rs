@@ -0,0 +1,104 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transfers between files on the local disk and a store.
+use std::path::PathBuf;
+
+use filetime::{set_file_mtime, FileTime};
+use futures::stream::StreamExt;
+use log::{trace, warn};
+use tokio_io::AsyncWriteExt;
+
+use crate::types::*;
+use crate::FileStore;
+
+/// Streams a file from the store to the local disk, giving the local file the
+/// same modification time.
+pub(crate) async fn download_to_path(
+    store: FileStore,
+    path: ObjectPath,
+    target: PathBuf,
+) -> Result<(), TransferError> {
+    async fn write(mut stream: DataStream, target: &PathBuf) -> Result<(), TransferError> {
+        let mut file = tokio_fs::File::create(target.clone())
+            .await
+            .map_err(|e| TransferError::TargetError(e.into()))?;
+
+        while let Some(data) = stream.next().await {
+            let data = data.map_err(TransferError::SourceError)?;
+            file.write_all(&data)
+                .await
+                .map_err(|e| TransferError::TargetError(e.into()))?;
+        }
+
+        file.flush()
+            .await
+            .map_err(|e| TransferError::TargetError(e.into()))
+    }
+
+    let object = store
+        .get_object(path.clone())
+        .await
+        .map_err(TransferError::SourceError)?;
+    if object.object_type() != ObjectType::File {
+        return Err(TransferError::SourceError(error::not_found(path, None)));
+    }
+
+    let stream = store
+        .get_file_stream(path)
+        .await
+        .map_err(TransferError::SourceError)?;
+
+    if let Err(e) = write(stream, &target).await {
+        trace!("Removing partially downloaded file {}.", target.display());
+        if let Err(e) = tokio_fs::remove_file(target.clone()).await {
+            warn!("Failed to remove partially downloaded file: {}", e);
+        }
+        return Err(e);
+    }
+
+    if let Some(time) = object.modified() {
+        if let Err(e) = set_file_mtime(&target, FileTime::from_system_time(time)) {
+            warn!("Failed to set file modification time: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Streams a file from the local disk to the store. The upload takes the local
+/// file's modification time unless one was already given.
+pub(crate) async fn upload_from_path(
+    store: FileStore,
+    mut info: UploadInfo,
+    source: PathBuf,
+) -> Result<(), TransferError> {
+    let metadata = tokio_fs::metadata(source.clone())
+        .await
+        .map_err(|e| TransferError::SourceError(e.into()))?;
+    if !metadata.is_file() {
+        return Err(TransferError::SourceError(error::invalid_data(Some(
+            &format!("{} is not a file.", source.display()),
+        ))));
+    }
+
+    if info.modified.is_none() {
+        info.modified = metadata.modified().ok();
+    }
+
+    // Reading from a source lets backends re-read parts that need retrying.
+    store
+        .write_file_from_source(info, PathSource::new(source))
+        .await
+}
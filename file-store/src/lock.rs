@@ -0,0 +1,422 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Cooperative locking. Included with the feature "lock".
+//!
+//! [`FileStore::lock`](../enum.FileStore.html#method.lock) takes out a
+//! [`Lease`](struct.Lease.html) on a path by creating a lock file for it. The
+//! lock file for `dir/file` is `dir/.lock/file` and records who holds the
+//! lease and when it expires. While the `Lease` is alive it is renewed in the
+//! background and it is released when dropped.
+//!
+//! Paths with a [`.lock`](constant.LOCK_DIRECTORY.html) directory part are
+//! reserved for lock files, every `FileStore` rejects them so lock files can't
+//! collide with other objects. The remote backend leaves this to the server's
+//! store so leases can't be taken through it.
+//!
+//! A lease is only taken if its lock file doesn't exist yet. The file backend
+//! creates the lock file atomically, the B2 backend has to check first so two
+//! holders racing each other may both succeed. An expired lease is never taken
+//! over automatically, a lease left behind by a holder that crashed has to be
+//! removed with [`FileStore::break_lock`](../enum.FileStore.html#method.break_lock).
+//!
+//! A lease is lost if its lock file names a different holder when it is
+//! renewed. The file backend only renews a lock file that hasn't changed since
+//! it was checked, other backends may overwrite a holder that took the lock in
+//! between.
+//!
+//! Locks are only advisory, nothing stops other code from changing a locked
+//! path.
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::stream::{iter, TryStreamExt};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::executor::spawn;
+use crate::types::*;
+use crate::FileStore;
+
+/// A future that resolves to a [`Lease`](struct.Lease.html).
+pub type LeaseFuture = WrappedFuture<StorageResult<Lease>>;
+
+/// The directory part that lock files are kept in.
+pub const LOCK_DIRECTORY: &str = ".lock";
+
+static HOLDER_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn is_not_found(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => true,
+        _ => false,
+    }
+}
+
+fn is_already_exists(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::AlreadyExists(_) => true,
+        _ => false,
+    }
+}
+
+fn is_precondition_failed(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::PreconditionFailed(_) => true,
+        _ => false,
+    }
+}
+
+fn is_invalid_settings(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::InvalidSettings => true,
+        _ => false,
+    }
+}
+
+fn as_millis(time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_millis() as u64,
+        Err(_) => 0,
+    }
+}
+
+fn new_holder() -> String {
+    format!(
+        "{}-{:x}-{}",
+        process::id(),
+        as_millis(SystemTime::now()),
+        HOLDER_COUNT.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+/// Adds the lock directory to a policy's reserved parts.
+pub(crate) fn reserve_lock_directory(policy: PathPolicy) -> PathPolicy {
+    policy.reserve_part(LOCK_DIRECTORY)
+}
+
+/// A store that accepts paths in the lock directory.
+fn lock_store(store: &FileStore) -> FileStore {
+    let policy = store.path_policy().clone().allow_part(LOCK_DIRECTORY);
+    store.clone().with_internal_path_policy(policy)
+}
+
+fn lock_path(path: &ObjectPath) -> StorageResult<ObjectPath> {
+    let mut lock = path.clone();
+    match lock.pop_part() {
+        Some(name) => {
+            lock.push_part(LOCK_DIRECTORY);
+            lock.push_part(&name);
+            Ok(lock)
+        }
+        None => Err(error::invalid_path(
+            path.clone(),
+            Some("The root of a store cannot be locked"),
+        )),
+    }
+}
+
+/// The contents of a lock file.
+#[derive(Serialize, Deserialize)]
+struct LockInfo {
+    holder: String,
+    expires: u64,
+}
+
+async fn read_lock(store: &FileStore, path: &ObjectPath) -> StorageResult<LockInfo> {
+    let data: Vec<Data> = store
+        .get_file_stream(path.clone())
+        .await?
+        .try_collect()
+        .await?;
+    let info = serde_json::from_slice(&data.concat())
+        .map_err(|e| error::invalid_data(Some(&e.to_string())))?;
+    Ok(info)
+}
+
+fn lock_data(holder: &str, expires: SystemTime) -> StorageResult<Data> {
+    let info = LockInfo {
+        holder: holder.to_owned(),
+        expires: as_millis(expires),
+    };
+    match serde_json::to_vec(&info) {
+        Ok(j) => Ok(Data::from(j)),
+        Err(e) => Err(error::internal_error(Some(&e.to_string()))),
+    }
+}
+
+/// Writes the lock file and then checks that the write won.
+async fn write_lock(
+    store: &FileStore,
+    path: &ObjectPath,
+    holder: &str,
+    expires: SystemTime,
+    mode: WriteMode,
+) -> StorageResult<()> {
+    let data = iter(vec![Ok::<_, StorageError>(lock_data(holder, expires)?)]);
+
+    let info = UploadInfo::from(path.clone()).write_mode(mode);
+    match store.write_file_from_stream(info, data).await {
+        Ok(()) => (),
+        Err(TransferError::TargetError(ref e)) if is_already_exists(e) => {
+            return Err(error::already_exists(
+                path.clone(),
+                Some("The lock is held by another holder"),
+            ))
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    // Backends that can't create files atomically may have let someone else
+    // write the lock file at the same time.
+    let current = read_lock(store, path).await?;
+    if current.holder != holder {
+        return Err(error::already_exists(
+            path.clone(),
+            Some("The lock was taken by another holder"),
+        ));
+    }
+    Ok(())
+}
+
+/// Extends a lease as long as its lock file still names the holder.
+///
+/// The lock file is only replaced if it hasn't changed since it was checked
+/// on backends that support conditional writes. Elsewhere another holder
+/// taking the lock between the check and the write is overwritten.
+async fn renew_lock(
+    store: &FileStore,
+    path: &ObjectPath,
+    holder: &str,
+    expires: SystemTime,
+) -> StorageResult<()> {
+    // The etag is read first so any change after it fails the write.
+    let etag = store.get_object(path.clone()).await?.etag();
+    let current = read_lock(store, path).await?;
+    if current.holder != holder {
+        return Err(error::already_exists(
+            path.clone(),
+            Some("The lock was taken by another holder"),
+        ));
+    }
+
+    let etag = match etag {
+        Some(etag) => etag,
+        None => return write_lock(store, path, holder, expires, WriteMode::Overwrite).await,
+    };
+
+    let data = iter(vec![Ok::<_, StorageError>(lock_data(holder, expires)?)]);
+    match store
+        .write_file_if_match(UploadInfo::from(path.clone()), &etag, data)
+        .await
+    {
+        Ok(()) => Ok(()),
+        Err(TransferError::TargetError(ref e)) if is_precondition_failed(e) => Err(
+            error::already_exists(path.clone(), Some("The lock was taken by another holder")),
+        ),
+        Err(TransferError::TargetError(ref e)) if is_invalid_settings(e) => {
+            write_lock(store, path, holder, expires, WriteMode::Overwrite).await
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn remove_lock(store: FileStore, path: ObjectPath, holder: String) -> StorageResult<()> {
+    match read_lock(&store, &path).await {
+        Ok(ref info) if info.holder == holder => (),
+        Ok(_) => return Ok(()),
+        Err(ref e) if is_not_found(e) => return Ok(()),
+        Err(e) => return Err(e),
+    }
+
+    match store.delete_object(path).await {
+        Err(ref e) if is_not_found(e) => Ok(()),
+        result => result,
+    }
+}
+
+#[derive(Debug)]
+struct LeaseState {
+    expires: SystemTime,
+    released: bool,
+    lost: bool,
+}
+
+async fn renew(
+    store: FileStore,
+    path: ObjectPath,
+    holder: String,
+    ttl: Duration,
+    state: Arc<Mutex<LeaseState>>,
+) {
+    loop {
+        tokio_timer::delay_for(ttl / 2).await;
+
+        if state.lock().unwrap().released {
+            return;
+        }
+
+        let expires = SystemTime::now() + ttl;
+        let result = renew_lock(&store, &path, &holder, expires).await;
+
+        let mut state = state.lock().unwrap();
+        match result {
+            Ok(()) => state.expires = expires,
+            Err(e) => {
+                warn!("Failed to renew the lease on {}: {}", path, e);
+                state.lost = true;
+                return;
+            }
+        }
+    }
+}
+
+/// An exclusive lease on a path.
+///
+/// The lease is renewed in the background every half `ttl` for as long as it
+/// is alive and is released when dropped. Use
+/// [`release`](#method.release) to wait for the lock file to be removed.
+#[derive(Debug)]
+pub struct Lease {
+    store: FileStore,
+    path: ObjectPath,
+    lock_path: ObjectPath,
+    holder: String,
+    ttl: Duration,
+    state: Arc<Mutex<LeaseState>>,
+}
+
+impl Lease {
+    pub(crate) fn acquire(store: FileStore, path: ObjectPath, ttl: Duration) -> LeaseFuture {
+        LeaseFuture::from_future(async move {
+            store.path_policy().validate(&path)?;
+            let lock_path = lock_path(&path)?;
+            let store = lock_store(&store);
+
+            let holder = new_holder();
+            let expires = SystemTime::now() + ttl;
+            write_lock(
+                &store,
+                &lock_path,
+                &holder,
+                expires,
+                WriteMode::FailIfExists,
+            )
+            .await?;
+
+            let state = Arc::new(Mutex::new(LeaseState {
+                expires,
+                released: false,
+                lost: false,
+            }));
+
+            spawn(renew(
+                store.clone(),
+                lock_path.clone(),
+                holder.clone(),
+                ttl,
+                state.clone(),
+            ));
+
+            Ok(Lease {
+                store,
+                path,
+                lock_path,
+                holder,
+                ttl,
+                state,
+            })
+        })
+    }
+
+    /// The path that is locked.
+    pub fn path(&self) -> &ObjectPath {
+        &self.path
+    }
+
+    /// The path of the lock file.
+    pub fn lock_path(&self) -> &ObjectPath {
+        &self.lock_path
+    }
+
+    /// A unique identifier for this lease, recorded in the lock file.
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    /// How long the lease lasts without being renewed.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// When the lease expires unless it is renewed.
+    pub fn expires(&self) -> SystemTime {
+        self.state.lock().unwrap().expires
+    }
+
+    /// Whether the lease is still held. This is false once renewing it has
+    /// failed or it has expired.
+    pub fn is_held(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        !state.released && !state.lost && state.expires > SystemTime::now()
+    }
+
+    fn mark_released(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let was_released = state.released;
+        state.released = true;
+        !was_released
+    }
+
+    /// Stops renewing the lease and removes the lock file. The lock file is
+    /// left alone if another holder has since replaced it.
+    pub fn release(self) -> OperationCompleteFuture {
+        self.mark_released();
+        OperationCompleteFuture::from_future(remove_lock(
+            self.store.clone(),
+            self.lock_path.clone(),
+            self.holder.clone(),
+        ))
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        if !self.mark_released() {
+            return;
+        }
+
+        let path = self.lock_path.clone();
+        let removal = remove_lock(self.store.clone(), path.clone(), self.holder.clone());
+        spawn(async move {
+            if let Err(e) = removal.await {
+                warn!("Failed to release the lease on {}: {}", path, e);
+            }
+        });
+    }
+}
+
+pub(crate) fn break_lock(store: FileStore, path: ObjectPath) -> OperationCompleteFuture {
+    OperationCompleteFuture::from_future(async move {
+        store.path_policy().validate(&path)?;
+        let lock_path = lock_path(&path)?;
+
+        match lock_store(&store).delete_object(lock_path).await {
+            Err(ref e) if is_not_found(e) => Ok(()),
+            result => result,
+        }
+    })
+}
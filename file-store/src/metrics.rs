@@ -0,0 +1,158 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Counters and histograms describing the work done by storage.
+//!
+//! Metrics are sent to a [`MetricsSink`](trait.MetricsSink.html) set with
+//! [`FileStore::set_metrics_sink`](../enum.FileStore.html#method.set_metrics_sink).
+//! The sink receives every operation made through the `FileStore` (or any of
+//! its clones) along with the requests that backends make to services, so
+//! applications can forward them to whatever metrics system they use.
+//!
+//! Every metric is labelled with the `backend` that produced it. The names
+//! and other labels of the metrics are described by the constants in this
+//! module.
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use crate::backends::Backend;
+use crate::events::now;
+
+/// A counter of operations, labelled with the `operation` and its `result`,
+/// either `ok` or `error`.
+pub const OPERATIONS: &str = "file_store.operations";
+/// A histogram of how long operations took in seconds, labelled with the
+/// `operation`.
+pub const OPERATION_DURATION: &str = "file_store.operation.duration";
+/// A counter of the bytes read from downloaded files.
+pub const BYTES_DOWNLOADED: &str = "file_store.bytes.downloaded";
+/// A counter of the bytes written to uploaded files.
+pub const BYTES_UPLOADED: &str = "file_store.bytes.uploaded";
+/// A counter of the requests made to a service, labelled with the API
+/// `method` and the `result`, either `ok` or `error`.
+pub const REQUESTS: &str = "file_store.requests";
+/// A histogram of how long requests to a service took to respond in seconds,
+/// labelled with the API `method`.
+pub const REQUEST_DURATION: &str = "file_store.request.duration";
+/// A counter of the requests that were retried after failing.
+pub const RETRIES: &str = "file_store.retries";
+/// A counter of the B2 transactions made, labelled with the API `method` and
+/// the transaction `class` that B2 bills it as, `A`, `B` or `C`.
+pub const B2_TRANSACTIONS: &str = "file_store.b2.transactions";
+
+/// Receives metrics.
+///
+/// Labels are given as name and value pairs. Sinks are called from whatever
+/// thread is driving the operation so should avoid blocking.
+pub trait MetricsSink: fmt::Debug + Send + Sync + 'static {
+    /// Increments a counter.
+    fn increment_counter(&self, name: &'static str, value: u64, labels: &[(&'static str, &str)]);
+
+    /// Records a value in a histogram.
+    fn record_histogram(&self, name: &'static str, value: f64, labels: &[(&'static str, &str)]);
+}
+
+impl<S> MetricsSink for Arc<S>
+where
+    S: MetricsSink + ?Sized,
+{
+    fn increment_counter(&self, name: &'static str, value: u64, labels: &[(&'static str, &str)]) {
+        self.as_ref().increment_counter(name, value, labels)
+    }
+
+    fn record_histogram(&self, name: &'static str, value: f64, labels: &[(&'static str, &str)]) {
+        self.as_ref().record_histogram(name, value, labels)
+    }
+}
+
+/// Sends metrics to a sink. Clones share the same sink.
+#[derive(Clone, Default)]
+pub(crate) struct Metrics {
+    sink: Arc<RwLock<Option<Arc<dyn MetricsSink>>>>,
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.sink.read() {
+            Ok(s) => f.debug_tuple("Metrics").field(&*s).finish(),
+            Err(_) => f.pad("Metrics"),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn set_sink(&self, sink: Option<Arc<dyn MetricsSink>>) {
+        if let Ok(mut current) = self.sink.write() {
+            *current = sink;
+        }
+    }
+
+    fn sink(&self) -> Option<Arc<dyn MetricsSink>> {
+        match self.sink.read() {
+            Ok(s) => s.clone(),
+            Err(_) => None,
+        }
+    }
+
+    pub fn counter(
+        &self,
+        name: &'static str,
+        backend: Backend,
+        value: u64,
+        labels: &[(&'static str, &str)],
+    ) {
+        if let Some(sink) = self.sink() {
+            let backend = backend.to_string();
+            let mut all = vec![("backend", backend.as_str())];
+            all.extend_from_slice(labels);
+            sink.increment_counter(name, value, &all);
+        }
+    }
+
+    /// Records the seconds since `started` in a histogram.
+    pub fn duration(
+        &self,
+        name: &'static str,
+        backend: Backend,
+        started: SystemTime,
+        labels: &[(&'static str, &str)],
+    ) {
+        if let Some(sink) = self.sink() {
+            let duration = now().duration_since(started).unwrap_or_default();
+            let seconds =
+                duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0;
+            let backend = backend.to_string();
+            let mut all = vec![("backend", backend.as_str())];
+            all.extend_from_slice(labels);
+            sink.record_histogram(name, seconds, &all);
+        }
+    }
+}
+
+/// Gets the class that B2 bills an API method as.
+#[cfg(feature = "b2")]
+pub(crate) fn b2_transaction_class(method: &str) -> &'static str {
+    match method {
+        "b2_cancel_large_file"
+        | "b2_delete_file_version"
+        | "b2_finish_large_file"
+        | "b2_get_upload_part_url"
+        | "b2_get_upload_url"
+        | "b2_start_large_file"
+        | "b2_upload_file"
+        | "b2_upload_part" => "A",
+        "b2_download_file_by_id" | "b2_download_file_by_name" | "b2_get_file_info" => "B",
+        _ => "C",
+    }
+}
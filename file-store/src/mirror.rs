@@ -0,0 +1,360 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Mirroring files across several stores.
+//!
+//! A [`MirrorBackend`](struct.MirrorBackend.html) holds a list of member
+//! [`FileStore`](../enum.FileStore.html)s and makes every change to all of
+//! them. By default a change only succeeds if it succeeds on every member,
+//! [`quorum`](struct.MirrorBackend.html#method.quorum) allows changes to
+//! succeed once enough members have made them.
+//!
+//! Reads and listings go to the first member that answers successfully, a
+//! member that fails or is missing the object is passed over for the next.
+//!
+//! Members that missed changes, because they were unavailable or were added
+//! later, can be brought back in line with
+//! [`repair`](struct.MirrorBackend.html#method.repair) which copies the files
+//! missing from each member from another member that has them.
+//!
+//! Writing a stream to every member buffers the whole stream in memory first,
+//! writing from an [`UploadSource`](../trait.UploadSource.html) reads the
+//! source separately for each member instead.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use futures::future::join_all;
+use futures::stream::{iter, StreamExt};
+
+use crate::backends::Backend;
+use crate::copy::list_files;
+use crate::types::*;
+use crate::{FileStore, StorageBackend};
+
+/// A future that resolves to a [`RepairSummary`](struct.RepairSummary.html).
+pub type RepairFuture = WrappedFuture<StorageResult<RepairSummary>>;
+
+/// The outcome of [repairing](struct.MirrorBackend.html#method.repair) a
+/// mirror.
+#[derive(Debug, Default)]
+pub struct RepairSummary {
+    /// The files that were copied along with the index of the member they
+    /// were copied to.
+    pub copied: Vec<(usize, ObjectPath)>,
+    /// The files that failed to copy along with the index of the member they
+    /// were being copied to and the error.
+    pub failed: Vec<(usize, ObjectPath, TransferError)>,
+    /// The number of bytes copied.
+    pub bytes: u64,
+}
+
+impl RepairSummary {
+    /// Returns whether every missing file was copied.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+/// A [`StorageBackend`](../trait.StorageBackend.html) that mirrors its files
+/// across several stores.
+#[derive(Clone)]
+pub struct MirrorBackend {
+    members: Arc<Vec<FileStore>>,
+    quorum: Option<usize>,
+}
+
+impl fmt::Debug for MirrorBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MirrorBackend")
+            .field("members", &self.members.len())
+            .field("quorum", &self.required())
+            .finish()
+    }
+}
+
+impl MirrorBackend {
+    /// Mirrors files across the given stores. Reads prefer the members in the
+    /// order given.
+    ///
+    /// Every member must succeed for a change to succeed.
+    ///
+    /// Panics if `members` is empty.
+    pub fn new(members: Vec<FileStore>) -> MirrorBackend {
+        assert!(
+            !members.is_empty(),
+            "A mirror must have at least one member."
+        );
+
+        MirrorBackend {
+            members: Arc::new(members),
+            quorum: None,
+        }
+    }
+
+    /// Sets the number of members that must succeed for a change to succeed.
+    ///
+    /// Changes are still attempted on every member. The quorum is limited to
+    /// the number of members.
+    pub fn quorum(mut self, quorum: usize) -> MirrorBackend {
+        self.quorum = Some(quorum);
+        self
+    }
+
+    /// Gets the member stores.
+    pub fn members(&self) -> &[FileStore] {
+        &self.members
+    }
+
+    fn required(&self) -> usize {
+        match self.quorum {
+            Some(quorum) => quorum.min(self.members.len()),
+            None => self.members.len(),
+        }
+    }
+
+    /// Tries each member in turn until one succeeds, returning the first
+    /// error if none do.
+    async fn first<T, F>(members: Arc<Vec<FileStore>>, operation: F) -> StorageResult<T>
+    where
+        F: Fn(&FileStore) -> WrappedFuture<StorageResult<T>>,
+    {
+        let mut first_error = None;
+        for member in members.iter() {
+            match operation(member).await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    if first_error.is_none() {
+                        first_error = Some(e);
+                    }
+                }
+            }
+        }
+
+        Err(first_error.unwrap_or_else(|| error::internal_error(Some("A mirror has no members."))))
+    }
+
+    /// Runs an operation on every member, succeeding if enough of them do.
+    fn all<E, F>(&self, operation: F) -> WrappedFuture<Result<(), E>>
+    where
+        E: Send + 'static,
+        F: Fn(&FileStore) -> WrappedFuture<Result<(), E>>,
+    {
+        let required = self.required();
+        let operations: Vec<_> = self.members.iter().map(operation).collect();
+
+        WrappedFuture::from_future(async move {
+            let mut succeeded = 0;
+            let mut first_error = None;
+            for result in join_all(operations).await {
+                match result {
+                    Ok(()) => succeeded += 1,
+                    Err(e) => {
+                        if first_error.is_none() {
+                            first_error = Some(e);
+                        }
+                    }
+                }
+            }
+
+            match first_error {
+                Some(e) if succeeded < required => Err(e),
+                _ => Ok(()),
+            }
+        })
+    }
+
+    /// Copies the files under `prefix` that are missing from any member from
+    /// the first member that has them.
+    ///
+    /// Only missing files are copied, files that differ between members are
+    /// left alone. Failing to copy individual files does not stop the repair,
+    /// check the returned summary for failures.
+    pub fn repair<P>(&self, prefix: P) -> RepairFuture
+    where
+        P: std::convert::TryInto<ObjectPath>,
+        P::Error: Into<StorageError>,
+    {
+        let prefix = match prefix.try_into() {
+            Ok(p) => p,
+            Err(e) => return RepairFuture::from_value(Err(e.into())),
+        };
+
+        RepairFuture::from_future(repair(self.members.clone(), prefix))
+    }
+}
+
+async fn repair(members: Arc<Vec<FileStore>>, prefix: ObjectPath) -> StorageResult<RepairSummary> {
+    let mut listings: Vec<HashMap<ObjectPath, Object>> = Vec::new();
+    for member in members.iter() {
+        let files = list_files(member, &prefix).await?;
+        listings.push(files.into_iter().map(|o| (o.path(), o)).collect());
+    }
+
+    let mut copies: Vec<(usize, usize, Object)> = Vec::new();
+    for (source, listing) in listings.iter().enumerate() {
+        for (path, object) in listing {
+            // Only the first member holding the file is used as the source.
+            if listings[..source].iter().any(|l| l.contains_key(path)) {
+                continue;
+            }
+
+            for (target, other) in listings.iter().enumerate() {
+                if !other.contains_key(path) {
+                    copies.push((source, target, object.clone()));
+                }
+            }
+        }
+    }
+
+    let mut summary = RepairSummary::default();
+    let mut results = iter(copies.into_iter().map(|(source, target, object)| {
+        let members = members.clone();
+        async move {
+            let result = copy(&members[source], &object, &members[target]).await;
+            (target, object, result)
+        }
+    }))
+    .buffer_unordered(crate::copy::DEFAULT_CONCURRENCY);
+
+    while let Some((target, object, result)) = results.next().await {
+        match result {
+            Ok(()) => {
+                summary.bytes += object.len();
+                summary.copied.push((target, object.path()));
+            }
+            Err(e) => summary.failed.push((target, object.path(), e)),
+        }
+    }
+
+    Ok(summary)
+}
+
+async fn copy(
+    source: &FileStore,
+    object: &Object,
+    target: &FileStore,
+) -> Result<(), TransferError> {
+    let info = object
+        .as_upload(object.path())
+        .map_err(TransferError::TargetError)?;
+    let stream = StorageBackend::get_file_stream(source, object.path())
+        .await
+        .map_err(TransferError::SourceError)?;
+    StorageBackend::write_file_from_stream(target, info, stream).await
+}
+
+impl StorageBackend for MirrorBackend {
+    fn backend_type(&self) -> Backend {
+        self.members[0].backend_type()
+    }
+
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
+        ObjectStreamFuture::from_future(MirrorBackend::first(self.members.clone(), move |m| {
+            StorageBackend::list_objects(m, prefix.clone())
+        }))
+    }
+
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        ObjectStreamFuture::from_future(MirrorBackend::first(self.members.clone(), move |m| {
+            StorageBackend::list_objects_with_options(m, prefix.clone(), options.clone())
+        }))
+    }
+
+    fn lists_in_order(&self) -> bool {
+        self.members.iter().all(|m| m.lists_in_order())
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        ObjectStreamFuture::from_future(MirrorBackend::first(self.members.clone(), move |m| {
+            StorageBackend::list_directory(m, dir.clone())
+        }))
+    }
+
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
+        ObjectFuture::from_future(MirrorBackend::first(self.members.clone(), move |m| {
+            StorageBackend::get_object(m, path.clone())
+        }))
+    }
+
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        DataStreamFuture::from_future(MirrorBackend::first(self.members.clone(), move |m| {
+            StorageBackend::get_file_stream(m, path.clone())
+        }))
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        DataStreamFuture::from_future(MirrorBackend::first(self.members.clone(), move |m| {
+            StorageBackend::get_file_stream_range(m, path.clone(), offset, length)
+        }))
+    }
+
+    fn copy_file(&self, source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
+        self.all(|m| StorageBackend::copy_file(m, source.clone(), target.clone()))
+    }
+
+    fn move_file(&self, source: ObjectPath, target: UploadInfo) -> MoveCompleteFuture {
+        self.all(|m| StorageBackend::move_file(m, source.clone(), target.clone()))
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        self.all(|m| StorageBackend::delete_object(m, path.clone()))
+    }
+
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        self.all(|m| StorageBackend::create_directory(m, path.clone()))
+    }
+
+    fn write_file_from_stream(
+        &self,
+        info: UploadInfo,
+        mut stream: DataStream,
+    ) -> WriteCompleteFuture {
+        let mirror = self.clone();
+
+        WriteCompleteFuture::from_future(async move {
+            let mut chunks: Vec<Data> = Vec::new();
+            while let Some(data) = stream.next().await {
+                chunks.push(data.map_err(TransferError::SourceError)?);
+            }
+
+            mirror
+                .all(|m| {
+                    let stream = iter(chunks.clone().into_iter().map(Ok));
+                    StorageBackend::write_file_from_stream(
+                        m,
+                        info.clone(),
+                        DataStream::from_stream(stream),
+                    )
+                })
+                .await
+        })
+    }
+
+    fn write_file_from_source(
+        &self,
+        info: UploadInfo,
+        source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        self.all(|m| StorageBackend::write_file_from_source(m, info.clone(), source.clone()))
+    }
+}
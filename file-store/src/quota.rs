@@ -0,0 +1,712 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Limiting the data stored under prefixes.
+//!
+//! A [`QuotaBackend`](struct.QuotaBackend.html) wraps another
+//! [`StorageBackend`](../trait.StorageBackend.html) and enforces
+//! [limits](struct.QuotaLimits.html) on the number of bytes and files stored
+//! beneath prefixes, for example one prefix per customer. Writes, copies and
+//! moves that would take a prefix over its limits fail with an
+//! [`OverQuota`](../enum.StorageErrorKind.html#variant.OverQuota) error.
+//!
+//! The current [usage](struct.Usage.html) of each prefix is kept in a
+//! [`UsageStore`](trait.UsageStore.html), by default in memory. Usage that the
+//! store doesn't know yet is calculated by listing the prefix the first time
+//! it is needed. Only changes made through the `QuotaBackend` are tracked, use
+//! [`recalculate`](struct.QuotaBackend.html#method.recalculate) after changing
+//! files some other way. Operations on the same path wait for each other so
+//! concurrent writes of a new file only count it once.
+//!
+//! The size of a streamed write isn't known in advance so space is claimed as
+//! the data is written and the write fails as soon as it passes the limit.
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use futures::future::ready;
+use futures::stream::{StreamExt, TryStreamExt};
+
+use crate::backends::Backend;
+use crate::types::*;
+use crate::utils::PathLocks;
+use crate::StorageBackend;
+
+/// A future that resolves to the [`Usage`](struct.Usage.html) of a prefix.
+pub type UsageFuture = WrappedFuture<StorageResult<Usage>>;
+
+/// The data stored beneath a prefix.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// The total size of the files.
+    pub bytes: u64,
+    /// The number of files.
+    pub objects: u64,
+}
+
+/// The limits applied to a prefix. Unset limits are not enforced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QuotaLimits {
+    max_bytes: Option<u64>,
+    max_objects: Option<u64>,
+}
+
+impl QuotaLimits {
+    /// Creates limits that allow anything.
+    pub fn new() -> QuotaLimits {
+        Default::default()
+    }
+
+    /// Limits the total size of the files.
+    pub fn max_bytes(mut self, bytes: u64) -> QuotaLimits {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Limits the number of files.
+    pub fn max_objects(mut self, objects: u64) -> QuotaLimits {
+        self.max_objects = Some(objects);
+        self
+    }
+
+    fn check(&self, prefix: &ObjectPath, old: Usage, new: Usage) -> StorageResult<()> {
+        if let Some(max) = self.max_bytes {
+            if new.bytes > old.bytes && new.bytes > max {
+                return Err(error::over_quota(Some(&format!(
+                    "The files under '{}' would exceed the limit of {} bytes.",
+                    prefix, max
+                ))));
+            }
+        }
+
+        if let Some(max) = self.max_objects {
+            if new.objects > old.objects && new.objects > max {
+                return Err(error::over_quota(Some(&format!(
+                    "The files under '{}' would exceed the limit of {} files.",
+                    prefix, max
+                ))));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Keeps the usage of each prefix with a quota.
+///
+/// Implementations are called while holding the `QuotaBackend`'s lock so
+/// should return quickly.
+pub trait UsageStore: Send + Sync + 'static {
+    /// Gets the usage of a prefix, `None` if it isn't known.
+    fn usage(&self, prefix: &ObjectPath) -> Option<Usage>;
+
+    /// Records the usage of a prefix.
+    fn set_usage(&self, prefix: &ObjectPath, usage: Usage);
+}
+
+/// A [`UsageStore`](trait.UsageStore.html) that keeps usage in memory.
+#[derive(Debug, Default)]
+pub struct MemoryUsageStore {
+    usage: Mutex<HashMap<ObjectPath, Usage>>,
+}
+
+impl UsageStore for MemoryUsageStore {
+    fn usage(&self, prefix: &ObjectPath) -> Option<Usage> {
+        self.usage.lock().ok()?.get(prefix).cloned()
+    }
+
+    fn set_usage(&self, prefix: &ObjectPath, usage: Usage) {
+        if let Ok(mut map) = self.usage.lock() {
+            map.insert(prefix.clone(), usage);
+        }
+    }
+}
+
+fn is_beneath(path: &ObjectPath, dir: &ObjectPath) -> bool {
+    path.parts().starts_with(&dir.parts())
+}
+
+fn is_not_found(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => true,
+        _ => false,
+    }
+}
+
+fn adjust(value: u64, delta: i64) -> u64 {
+    if delta < 0 {
+        value.saturating_sub(delta.wrapping_neg() as u64)
+    } else {
+        value.saturating_add(delta as u64)
+    }
+}
+
+/// A change in the data stored at a path.
+#[derive(Clone, Debug)]
+struct Change {
+    path: ObjectPath,
+    bytes: i64,
+    objects: i64,
+}
+
+impl Change {
+    fn new(path: &ObjectPath, bytes: i64, objects: i64) -> Change {
+        Change {
+            path: path.clone(),
+            bytes,
+            objects,
+        }
+    }
+
+    fn reverse(changes: &[Change]) -> Vec<Change> {
+        changes
+            .iter()
+            .map(|c| Change::new(&c.path, -c.bytes, -c.objects))
+            .collect()
+    }
+}
+
+struct QuotaState {
+    limits: Vec<(ObjectPath, QuotaLimits)>,
+    store: Arc<dyn UsageStore>,
+}
+
+struct Quotas {
+    state: Mutex<QuotaState>,
+    /// Held while looking at a path, changing it and recording the change so
+    /// concurrent operations on the same path see each other's results.
+    paths: PathLocks<ObjectPath>,
+    /// Held while calculating the usage of a prefix so it only happens once.
+    calculating: PathLocks<ObjectPath>,
+}
+
+impl Quotas {
+    fn state(&self) -> MutexGuard<'_, QuotaState> {
+        // Usage is only recorded once every limit has been checked so a panic
+        // can't leave it half updated.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn usage(&self, prefix: &ObjectPath) -> Option<Usage> {
+        self.state().store.usage(prefix)
+    }
+
+    fn set_usage(&self, prefix: &ObjectPath, usage: Usage) {
+        self.state().store.set_usage(prefix, usage)
+    }
+
+    /// The prefixes with limits matching a filter.
+    fn prefixes<F>(&self, filter: F) -> Vec<ObjectPath>
+    where
+        F: Fn(&ObjectPath) -> bool,
+    {
+        self.state()
+            .limits
+            .iter()
+            .map(|(prefix, _)| prefix)
+            .filter(|prefix| filter(prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Applies changes to the usage of every prefix they fall under, failing
+    /// without changing anything if any limit would be passed and `check` is
+    /// set.
+    ///
+    /// Checking a prefix whose usage isn't known fails if the changes add to
+    /// it. Otherwise such prefixes are left alone to be calculated from their
+    /// files later.
+    fn apply(&self, changes: &[Change], check: bool) -> StorageResult<()> {
+        let state = self.state();
+
+        let mut updates = Vec::new();
+        for (prefix, limits) in &state.limits {
+            let mut bytes = 0;
+            let mut objects = 0;
+            for change in changes.iter().filter(|c| is_beneath(&c.path, prefix)) {
+                bytes += change.bytes;
+                objects += change.objects;
+            }
+
+            if bytes == 0 && objects == 0 {
+                continue;
+            }
+
+            let old = match state.store.usage(prefix) {
+                Some(usage) => usage,
+                None if check && (bytes > 0 || objects > 0) => {
+                    return Err(error::internal_error(Some(&format!(
+                        "The usage of '{}' is not known.",
+                        prefix
+                    ))))
+                }
+                None => continue,
+            };
+            let new = Usage {
+                bytes: adjust(old.bytes, bytes),
+                objects: adjust(old.objects, objects),
+            };
+
+            if check {
+                limits.check(prefix, old, new)?;
+            }
+            updates.push((prefix, new));
+        }
+
+        for (prefix, usage) in updates {
+            state.store.set_usage(prefix, usage);
+        }
+
+        Ok(())
+    }
+}
+
+/// Lists the files under a prefix and totals them up.
+async fn calculate<B>(inner: &B, prefix: &ObjectPath) -> StorageResult<Usage>
+where
+    B: StorageBackend,
+{
+    let list_prefix = if prefix.is_empty() {
+        prefix.clone()
+    } else {
+        ObjectPath::new(format!("{}/", prefix))?
+    };
+
+    let result = async {
+        inner
+            .list_objects(list_prefix)
+            .await?
+            .try_filter(|o| ready(o.object_type() == ObjectType::File))
+            .try_fold(Usage::default(), |usage, o| {
+                ready(Ok(Usage {
+                    bytes: usage.bytes + o.len(),
+                    objects: usage.objects + 1,
+                }))
+            })
+            .await
+    }
+    .await;
+
+    // Some backends fail to list a prefix that doesn't exist.
+    match result {
+        Err(ref e) if is_not_found(e) => Ok(Usage::default()),
+        r => r,
+    }
+}
+
+/// A [`StorageBackend`](../trait.StorageBackend.html) that limits the data
+/// stored beneath prefixes.
+///
+/// Clones share the same limits and usage.
+pub struct QuotaBackend<B> {
+    inner: Arc<B>,
+    quotas: Arc<Quotas>,
+}
+
+impl<B> Clone for QuotaBackend<B> {
+    fn clone(&self) -> QuotaBackend<B> {
+        QuotaBackend {
+            inner: self.inner.clone(),
+            quotas: self.quotas.clone(),
+        }
+    }
+}
+
+impl<B> fmt::Debug for QuotaBackend<B>
+where
+    B: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("QuotaBackend")
+            .field("inner", &self.inner)
+            .field("limits", &self.quotas.state().limits)
+            .finish()
+    }
+}
+
+impl<B> QuotaBackend<B>
+where
+    B: StorageBackend,
+{
+    /// Wraps a backend with no limits, keeping usage in a
+    /// [`MemoryUsageStore`](struct.MemoryUsageStore.html).
+    pub fn new(inner: B) -> QuotaBackend<B> {
+        QuotaBackend {
+            inner: Arc::new(inner),
+            quotas: Arc::new(Quotas {
+                state: Mutex::new(QuotaState {
+                    limits: Vec::new(),
+                    store: Arc::new(MemoryUsageStore::default()),
+                }),
+                paths: Default::default(),
+                calculating: Default::default(),
+            }),
+        }
+    }
+
+    /// Sets where the usage of each prefix is kept.
+    ///
+    /// This changes the store used by every clone of this backend.
+    pub fn usage_store<S>(self, store: S) -> QuotaBackend<B>
+    where
+        S: UsageStore,
+    {
+        self.quotas.state().store = Arc::new(store);
+        self
+    }
+
+    /// Applies limits to the files beneath `prefix`, replacing any limits
+    /// already set for it. An empty prefix limits the whole store.
+    ///
+    /// A file beneath several prefixes with limits counts towards them all.
+    /// This changes the limits of every clone of this backend.
+    pub fn limit(self, prefix: ObjectPath, limits: QuotaLimits) -> QuotaBackend<B> {
+        {
+            let mut state = self.quotas.state();
+            state.limits.retain(|(p, _)| *p != prefix);
+            state.limits.push((prefix, limits));
+        }
+        self
+    }
+
+    /// Gets the wrapped backend.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Gets the usage of a prefix with limits, calculating it if it isn't
+    /// known yet.
+    pub fn usage(&self, prefix: ObjectPath) -> UsageFuture {
+        let quota = self.clone();
+
+        UsageFuture::from_future(async move {
+            if let Some(usage) = quota.quotas.usage(&prefix) {
+                return Ok(usage);
+            }
+            quota.recalculate(prefix).await
+        })
+    }
+
+    /// Calculates the usage of a prefix from the files stored beneath it and
+    /// records it in the usage store.
+    pub fn recalculate(&self, prefix: ObjectPath) -> UsageFuture {
+        let quota = self.clone();
+
+        UsageFuture::from_future(async move {
+            let usage = calculate(&*quota.inner, &prefix).await?;
+            quota.quotas.set_usage(&prefix, usage);
+            Ok(usage)
+        })
+    }
+
+    /// Makes sure the usage of every prefix with limits covering `path` is
+    /// known.
+    async fn prepare(&self, path: &ObjectPath) -> StorageResult<()> {
+        for prefix in self.quotas.prefixes(|prefix| is_beneath(path, prefix)) {
+            let _calculating = self.quotas.calculating.lock(prefix.clone()).await;
+            if self.quotas.usage(&prefix).is_none() {
+                self.recalculate(prefix).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets whatever is at a path.
+    async fn existing(&self, path: &ObjectPath) -> StorageResult<Option<Object>> {
+        match self.inner.get_object(path.clone()).await {
+            Ok(o) => Ok(Some(o)),
+            Err(ref e) if is_not_found(e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Lists the files beneath a directory as changes that remove them.
+    async fn removing(&self, dir: &ObjectPath) -> StorageResult<Vec<Change>> {
+        let result = async {
+            self.inner
+                .list_objects(ObjectPath::new(format!("{}/", dir))?)
+                .await?
+                .try_filter(|o| ready(o.object_type() == ObjectType::File))
+                .map_ok(|o| Change::new(&o.path(), -(o.len() as i64), -1))
+                .try_collect()
+                .await
+        }
+        .await;
+
+        match result {
+            Err(ref e) if is_not_found(e) => Ok(Vec::new()),
+            r => r,
+        }
+    }
+
+    /// The changes from writing a file of `size` bytes. `None` if the write
+    /// leaves the object already at the path alone.
+    ///
+    /// Writing over a directory removes everything in it.
+    ///
+    /// The caller must hold the lock on the path until the changes are applied.
+    async fn replacing(&self, info: &UploadInfo, size: u64) -> StorageResult<Option<Vec<Change>>> {
+        self.prepare(&info.path).await?;
+        let existing = match self.existing(&info.path).await? {
+            Some(_) if info.write_mode == WriteMode::IgnoreIfExists => return Ok(None),
+            existing => existing,
+        };
+
+        Ok(Some(match existing {
+            Some(ref old) if old.object_type() == ObjectType::File => {
+                vec![Change::new(&info.path, size as i64 - old.len() as i64, 0)]
+            }
+            Some(ref old) if old.object_type() == ObjectType::Directory => {
+                let mut changes = self.removing(&info.path).await?;
+                changes.push(Change::new(&info.path, size as i64, 1));
+                changes
+            }
+            _ => vec![Change::new(&info.path, size as i64, 1)],
+        }))
+    }
+
+    /// Claims space for changes, runs an operation and gives the space back
+    /// if it fails.
+    async fn claimed<F>(&self, changes: Vec<Change>, operation: F) -> Result<(), TransferError>
+    where
+        F: std::future::Future<Output = Result<(), TransferError>>,
+    {
+        self.quotas
+            .apply(&changes, true)
+            .map_err(TransferError::TargetError)?;
+
+        let result = operation.await;
+        if result.is_err() {
+            let _ = self.quotas.apply(&Change::reverse(&changes), false);
+        }
+        result
+    }
+
+    async fn write_stream(self, info: UploadInfo, stream: DataStream) -> Result<(), TransferError> {
+        let path = info.path.clone();
+        let _guard = self.quotas.paths.lock(path.clone()).await;
+        let start = match self
+            .replacing(&info, 0)
+            .await
+            .map_err(TransferError::TargetError)?
+        {
+            Some(changes) => changes,
+            None => return self.inner.write_file_from_stream(info, stream).await,
+        };
+
+        let written = Arc::new(AtomicU64::new(0));
+        let exceeded = Arc::new(AtomicBool::new(false));
+        let counted = {
+            let quotas = self.quotas.clone();
+            let written = written.clone();
+            let exceeded = exceeded.clone();
+            let path = path.clone();
+            stream.map(move |result| {
+                let data = result?;
+                let change = Change::new(&path, data.len() as i64, 0);
+                match quotas.apply(&[change], true) {
+                    Ok(()) => {
+                        written.fetch_add(data.len() as u64, Ordering::SeqCst);
+                        Ok(data)
+                    }
+                    Err(e) => {
+                        exceeded.store(true, Ordering::SeqCst);
+                        Err(e)
+                    }
+                }
+            })
+        };
+
+        let write = self
+            .inner
+            .write_file_from_stream(info, DataStream::from_stream(counted));
+        let result = self.claimed(start, write).await;
+
+        if result.is_err() {
+            let change = Change::new(&path, -(written.load(Ordering::SeqCst) as i64), 0);
+            let _ = self.quotas.apply(&[change], false);
+        }
+
+        match result {
+            Err(_) if exceeded.load(Ordering::SeqCst) => Err(TransferError::TargetError(
+                error::over_quota(Some(&format!("Writing '{}' exceeded a quota.", path))),
+            )),
+            r => r,
+        }
+    }
+
+    async fn write_source(
+        self,
+        info: UploadInfo,
+        source: Arc<dyn UploadSource>,
+    ) -> Result<(), TransferError> {
+        let size = source.size().await.map_err(TransferError::SourceError)?;
+        let _guard = self.quotas.paths.lock(info.path.clone()).await;
+        let changes = self
+            .replacing(&info, size)
+            .await
+            .map_err(TransferError::TargetError)?;
+
+        let write = self.inner.write_file_from_source(info, source);
+        match changes {
+            Some(changes) => self.claimed(changes, write).await,
+            None => write.await,
+        }
+    }
+
+    async fn transfer(
+        self,
+        source: ObjectPath,
+        target: UploadInfo,
+        is_move: bool,
+    ) -> Result<(), TransferError> {
+        let _guards = self
+            .quotas
+            .paths
+            .lock_all(vec![source.clone(), target.path.clone()])
+            .await;
+
+        let size = self
+            .inner
+            .get_object(source.clone())
+            .await
+            .map_err(TransferError::SourceError)?
+            .len();
+        let mut changes = match self
+            .replacing(&target, size)
+            .await
+            .map_err(TransferError::TargetError)?
+        {
+            Some(changes) => changes,
+            None if is_move => return self.inner.move_file(source, target).await,
+            None => return self.inner.copy_file(source, target).await,
+        };
+
+        if is_move {
+            self.prepare(&source)
+                .await
+                .map_err(TransferError::SourceError)?;
+            changes.push(Change::new(&source, -(size as i64), -1));
+            let operation = self.inner.move_file(source, target);
+            self.claimed(changes, operation).await
+        } else {
+            let operation = self.inner.copy_file(source, target);
+            self.claimed(changes, operation).await
+        }
+    }
+
+    async fn delete(self, path: ObjectPath) -> StorageResult<()> {
+        let _guard = self.quotas.paths.lock(path.clone()).await;
+        let existing = self
+            .existing(&path)
+            .await?
+            .filter(|o| o.object_type() == ObjectType::File);
+        self.inner.delete_object(path.clone()).await?;
+
+        match existing {
+            Some(file) => self
+                .quotas
+                .apply(&[Change::new(&path, -(file.len() as i64), -1)], false),
+            None => {
+                // Deleting a directory can change the usage of any prefix
+                // beneath it or above it.
+                let affected = self
+                    .quotas
+                    .prefixes(|prefix| is_beneath(prefix, &path) || is_beneath(&path, prefix));
+
+                for prefix in affected {
+                    if self.quotas.usage(&prefix).is_some() {
+                        self.recalculate(prefix).await?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<B> StorageBackend for QuotaBackend<B>
+where
+    B: StorageBackend,
+{
+    fn backend_type(&self) -> Backend {
+        self.inner.backend_type()
+    }
+
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
+        self.inner.list_objects(prefix)
+    }
+
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        self.inner.list_objects_with_options(prefix, options)
+    }
+
+    fn lists_in_order(&self) -> bool {
+        self.inner.lists_in_order()
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        self.inner.list_directory(dir)
+    }
+
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
+        self.inner.get_object(path)
+    }
+
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        self.inner.get_file_stream(path)
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        self.inner.get_file_stream_range(path, offset, length)
+    }
+
+    fn copy_file(&self, source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
+        CopyCompleteFuture::from_future(self.clone().transfer(source, target, false))
+    }
+
+    fn move_file(&self, source: ObjectPath, target: UploadInfo) -> MoveCompleteFuture {
+        MoveCompleteFuture::from_future(self.clone().transfer(source, target, true))
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        OperationCompleteFuture::from_future(self.clone().delete(path))
+    }
+
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        self.inner.create_directory(path)
+    }
+
+    fn write_file_from_stream(&self, info: UploadInfo, stream: DataStream) -> WriteCompleteFuture {
+        WriteCompleteFuture::from_future(self.clone().write_stream(info, stream))
+    }
+
+    fn write_file_from_source(
+        &self,
+        info: UploadInfo,
+        source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        WriteCompleteFuture::from_future(self.clone().write_source(info, source))
+    }
+}
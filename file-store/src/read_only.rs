@@ -0,0 +1,149 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Preventing changes to a store.
+//!
+//! [`ReadOnly`](struct.ReadOnly.html) wraps another
+//! [`StorageBackend`](../trait.StorageBackend.html) passing reads and listings
+//! through while rejecting every write, copy, move, delete and directory
+//! creation with a
+//! [`ReadOnly`](../enum.StorageErrorKind.html#variant.ReadOnly) error. Nothing is
+//! sent to the wrapped backend for a rejected operation.
+//!
+//! Code that should only be able to read from a store can be given a
+//! `ReadOnly` wrapper, for example as an `Arc<dyn StorageBackend>`.
+use std::sync::Arc;
+
+use crate::backends::Backend;
+use crate::types::*;
+use crate::StorageBackend;
+
+fn rejected(path: &ObjectPath) -> StorageError {
+    error::read_only(Some(&format!(
+        "Cannot change '{}' in a read-only store.",
+        path
+    )))
+}
+
+/// A [`StorageBackend`](../trait.StorageBackend.html) that only allows reads.
+#[derive(Clone, Debug)]
+pub struct ReadOnly<B> {
+    inner: B,
+}
+
+impl<B> ReadOnly<B>
+where
+    B: StorageBackend,
+{
+    /// Wraps a backend, preventing any changes through the wrapper.
+    pub fn new(inner: B) -> ReadOnly<B> {
+        ReadOnly { inner }
+    }
+}
+
+impl<B> StorageBackend for ReadOnly<B>
+where
+    B: StorageBackend,
+{
+    fn backend_type(&self) -> Backend {
+        self.inner.backend_type()
+    }
+
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
+        self.inner.list_objects(prefix)
+    }
+
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        self.inner.list_objects_with_options(prefix, options)
+    }
+
+    fn lists_in_order(&self) -> bool {
+        self.inner.lists_in_order()
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        self.inner.list_directory(dir)
+    }
+
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
+        self.inner.get_object(path)
+    }
+
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        self.inner.get_file_stream(path)
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        self.inner.get_file_stream_range(path, offset, length)
+    }
+
+    fn copy_file(&self, _source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
+        CopyCompleteFuture::from_value(Err(TransferError::TargetError(rejected(&target.path))))
+    }
+
+    fn move_file(&self, source: ObjectPath, _target: UploadInfo) -> MoveCompleteFuture {
+        MoveCompleteFuture::from_value(Err(TransferError::SourceError(rejected(&source))))
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        OperationCompleteFuture::from_value(Err(rejected(&path)))
+    }
+
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        OperationCompleteFuture::from_value(Err(rejected(&path)))
+    }
+
+    fn delete_objects(&self, paths: Vec<ObjectPath>) -> DeleteSummaryFuture {
+        let failed = paths
+            .into_iter()
+            .map(|path| {
+                let error = rejected(&path);
+                (path, error)
+            })
+            .collect();
+
+        DeleteSummaryFuture::from_value(DeleteSummary {
+            failed,
+            ..Default::default()
+        })
+    }
+
+    fn write_file_from_stream(&self, info: UploadInfo, _stream: DataStream) -> WriteCompleteFuture {
+        WriteCompleteFuture::from_value(Err(TransferError::TargetError(rejected(&info.path))))
+    }
+
+    fn write_file_from_stream_returning(
+        &self,
+        info: UploadInfo,
+        _stream: DataStream,
+    ) -> WrittenObjectFuture {
+        WrittenObjectFuture::from_value(Err(TransferError::TargetError(rejected(&info.path))))
+    }
+
+    fn write_file_from_source(
+        &self,
+        info: UploadInfo,
+        _source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        WriteCompleteFuture::from_value(Err(TransferError::TargetError(rejected(&info.path))))
+    }
+}
@@ -0,0 +1,351 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for serving stored files over HTTP. Included with the feature
+//! "responder".
+//!
+//! [`serve_object`](fn.serve_object.html) builds a complete
+//! [`http::Response`](https://docs.rs/http/0.1/http/response/struct.Response.html)
+//! for a request, setting `Content-Length`, `Content-Type`, `ETag` and
+//! `Last-Modified` and honouring conditional (`If-None-Match`,
+//! `If-Modified-Since`) and single range (`Range`, `If-Range`) requests.
+//!
+//! The response body is a [`DataStream`](../type.DataStream.html) which any
+//! framework built on the `http` crate can stream, with hyper for example:
+//!
+//! ```ignore
+//! let response = serve_object(&store, path, request.method(), request.headers()).await?;
+//! Ok(response.map(Body::wrap_stream))
+//! ```
+use std::cmp::min;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::ready;
+use futures::stream::{empty, StreamExt};
+use http::header::{
+    HeaderMap, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, IF_RANGE, LAST_MODIFIED, RANGE,
+};
+use http::{Method, Response, StatusCode};
+use httpdate::{fmt_http_date, parse_http_date};
+
+use crate::types::*;
+use crate::FileStore;
+
+/// A future that resolves to an HTTP response.
+pub type ResponseFuture = WrappedFuture<StorageResult<Response<DataStream>>>;
+
+/// The validators used for conditional requests.
+struct Validators {
+    etag: String,
+    modified: Option<SystemTime>,
+}
+
+impl Validators {
+    fn new(object: &Object) -> Validators {
+        let modified = object.modified().map(|time| {
+            // HTTP dates only have second precision.
+            let seconds = time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            UNIX_EPOCH + Duration::from_secs(seconds)
+        });
+
+        let seconds = modified
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        Validators {
+            etag: format!("\"{:x}-{:x}\"", object.len(), seconds),
+            modified,
+        }
+    }
+
+    fn matches_etag(&self, header: &str) -> bool {
+        header.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == self.etag
+        })
+    }
+
+    /// Checks whether the client's cached copy is still valid.
+    fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(value) = headers.get(IF_NONE_MATCH) {
+            return value
+                .to_str()
+                .map(|v| self.matches_etag(v))
+                .unwrap_or(false);
+        }
+
+        match (self.modified, headers.get(IF_MODIFIED_SINCE)) {
+            (Some(modified), Some(value)) => match value.to_str().map(parse_http_date) {
+                Ok(Ok(since)) => modified <= since,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Checks whether a `Range` header should be honoured.
+    fn range_applies(&self, headers: &HeaderMap) -> bool {
+        match headers.get(IF_RANGE).map(|v| v.to_str()) {
+            None => true,
+            Some(Ok(value)) => {
+                if value.starts_with('"') {
+                    value == self.etag
+                } else {
+                    match (self.modified, parse_http_date(value)) {
+                        (Some(modified), Ok(date)) => modified == date,
+                        _ => false,
+                    }
+                }
+            }
+            Some(Err(_)) => false,
+        }
+    }
+}
+
+/// Gets the entity tag used for an object.
+pub(crate) fn entity_tag(object: &Object) -> String {
+    Validators::new(object).etag
+}
+
+/// The part of the file that was requested.
+#[derive(Debug, PartialEq)]
+enum RequestedRange {
+    /// The entire file.
+    All,
+    /// An inclusive byte range.
+    Bytes(u64, u64),
+    /// A range that cannot be satisfied.
+    Unsatisfiable,
+}
+
+fn parse_range(header: &str, len: u64) -> RequestedRange {
+    let header = header.trim();
+    if !header.starts_with("bytes=") {
+        return RequestedRange::All;
+    }
+    let spec = &header["bytes=".len()..];
+
+    // Multiple ranges are allowed to be ignored.
+    if spec.contains(',') {
+        return RequestedRange::All;
+    }
+
+    let (start, end) = match spec.find('-') {
+        Some(pos) => (spec[..pos].trim(), spec[pos + 1..].trim()),
+        None => return RequestedRange::All,
+    };
+
+    let range = if start.is_empty() {
+        // A suffix range.
+        match end.parse::<u64>() {
+            Ok(0) => return RequestedRange::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            Err(_) => return RequestedRange::All,
+        }
+    } else {
+        let start = match start.parse::<u64>() {
+            Ok(s) => s,
+            Err(_) => return RequestedRange::All,
+        };
+
+        let end = if end.is_empty() {
+            len.saturating_sub(1)
+        } else {
+            match end.parse::<u64>() {
+                Ok(e) if e >= start => min(e, len.saturating_sub(1)),
+                _ => return RequestedRange::All,
+            }
+        };
+
+        (start, end)
+    };
+
+    if range.0 >= len {
+        RequestedRange::Unsatisfiable
+    } else {
+        RequestedRange::Bytes(range.0, range.1)
+    }
+}
+
+/// Limits a stream of data to the bytes from `start` to `end` inclusive.
+fn slice_stream(stream: DataStream, start: u64, end: u64) -> DataStream {
+    DataStream::from_stream(
+        stream
+            .scan(0, move |position: &mut u64, result: StorageResult<Data>| {
+                let data = match result {
+                    Ok(d) => d,
+                    Err(e) => return ready(Some(Some(Err(e)))),
+                };
+
+                let chunk_start = *position;
+                let chunk_end = chunk_start + data.len() as u64;
+                *position = chunk_end;
+
+                if chunk_start > end {
+                    ready(None)
+                } else if chunk_end <= start {
+                    ready(Some(None))
+                } else {
+                    let from = start.saturating_sub(chunk_start) as usize;
+                    let to = (min(chunk_end, end + 1) - chunk_start) as usize;
+                    ready(Some(Some(Ok(data.slice(from, to)))))
+                }
+            })
+            .filter_map(ready),
+    )
+}
+
+fn empty_body() -> DataStream {
+    DataStream::from_stream(empty())
+}
+
+/// What will be sent in response to a request.
+enum Plan {
+    NotModified,
+    Unsatisfiable,
+    Full,
+    Partial(u64, u64),
+}
+
+fn plan(object: &Object, validators: &Validators, headers: &HeaderMap) -> Plan {
+    if validators.not_modified(headers) {
+        return Plan::NotModified;
+    }
+
+    if !validators.range_applies(headers) {
+        return Plan::Full;
+    }
+
+    match headers
+        .get(RANGE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| parse_range(v, object.len()))
+    {
+        Some(RequestedRange::Bytes(start, end)) => Plan::Partial(start, end),
+        Some(RequestedRange::Unsatisfiable) => Plan::Unsatisfiable,
+        _ => Plan::Full,
+    }
+}
+
+/// Builds a response for a request for the given object.
+///
+/// `data` must be the full contents of the object, it is dropped without being
+/// read if the request does not need a body.
+pub fn object_response(
+    object: &Object,
+    data: DataStream,
+    method: &Method,
+    headers: &HeaderMap,
+) -> StorageResult<Response<DataStream>> {
+    let validators = Validators::new(object);
+    let plan = plan(object, &validators, headers);
+
+    let mut builder = Response::builder();
+    builder.header(ETAG, validators.etag.as_str());
+    if let Some(modified) = validators.modified {
+        builder.header(LAST_MODIFIED, fmt_http_date(modified));
+    }
+
+    let (status, body, length) = match plan {
+        Plan::NotModified => {
+            return Ok(builder
+                .status(StatusCode::NOT_MODIFIED)
+                .body(empty_body())?);
+        }
+        Plan::Unsatisfiable => {
+            return Ok(builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(CONTENT_RANGE, format!("bytes */{}", object.len()))
+                .body(empty_body())?);
+        }
+        Plan::Full => (StatusCode::OK, data, object.len()),
+        Plan::Partial(start, end) => {
+            builder.header(
+                CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, object.len()),
+            );
+            (
+                StatusCode::PARTIAL_CONTENT,
+                slice_stream(data, start, end),
+                end - start + 1,
+            )
+        }
+    };
+
+    // Prefer the type stored with the object over guessing from its name.
+    let content_type = object.content_type().unwrap_or_else(|| {
+        mime_guess::from_path(object.path().to_string())
+            .first_or_octet_stream()
+            .to_string()
+    });
+
+    builder
+        .status(status)
+        .header(ACCEPT_RANGES, "bytes")
+        .header(CONTENT_TYPE, content_type.as_str())
+        .header(CONTENT_LENGTH, length);
+
+    if method == Method::HEAD {
+        Ok(builder.body(empty_body())?)
+    } else {
+        Ok(builder.body(body)?)
+    }
+}
+
+/// Looks up the object at the given path and builds a response for a request
+/// for it.
+///
+/// The file's data is only requested from the store if the response needs it.
+/// A missing object is returned as a
+/// [`NotFound`](../enum.StorageErrorKind.html#variant.NotFound) error so the
+/// caller can choose how to respond.
+pub fn serve_object(
+    store: &FileStore,
+    path: ObjectPath,
+    method: &Method,
+    headers: &HeaderMap,
+) -> ResponseFuture {
+    async fn serve(
+        store: FileStore,
+        path: ObjectPath,
+        method: Method,
+        headers: HeaderMap,
+    ) -> StorageResult<Response<DataStream>> {
+        let object = store.get_object(path.clone()).await?;
+        if object.object_type() != ObjectType::File {
+            return Err(error::not_found(path, None));
+        }
+
+        let needs_body = method != Method::HEAD
+            && match plan(&object, &Validators::new(&object), &headers) {
+                Plan::Full | Plan::Partial(_, _) => true,
+                Plan::NotModified | Plan::Unsatisfiable => false,
+            };
+
+        let data = if needs_body {
+            store.get_file_stream(path).await?
+        } else {
+            empty_body()
+        };
+
+        object_response(&object, data, &method, &headers)
+    }
+
+    ResponseFuture::from_future(serve(store.clone(), path, method.clone(), headers.clone()))
+}
@@ -0,0 +1,169 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Exposes a [`FileStore`](../enum.FileStore.html) through standard network
+//! protocols.
+//!
+//! Each protocol is implemented as a handler that turns an
+//! [`http::Request`](https://docs.rs/http/0.1/http/request/struct.Request.html)
+//! into an
+//! [`http::Response`](https://docs.rs/http/0.1/http/response/struct.Response.html)
+//! so it can be plugged into any server built on the `http` crate. Handlers
+//! never fail, storage errors are converted to the appropriate HTTP status.
+//!
+//! * [`webdav`](webdav/index.html) is included with the feature "webdav".
+//! * [`s3`](s3/index.html) is included with the feature "s3-gateway".
+//! * [`grpc`](grpc/index.html), the server for the
+//!   [remote backend](../backends/remote/index.html), is included with the
+//!   feature "remote".
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::{empty, once};
+use http::header::{HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Response, StatusCode};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::types::*;
+
+#[cfg(feature = "remote")]
+pub mod grpc;
+#[cfg(feature = "s3-gateway")]
+pub mod s3;
+#[cfg(feature = "webdav")]
+pub mod webdav;
+
+/// Characters that are left unencoded in the paths of urls.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// A future that resolves to the response to a request.
+pub type ServeFuture = WrappedFuture<Response<DataStream>>;
+
+/// Gets the HTTP status that best represents a storage error.
+pub(crate) fn error_status(error: &StorageError) -> StatusCode {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => StatusCode::NOT_FOUND,
+        StorageErrorKind::AlreadyExists(_) => StatusCode::CONFLICT,
+        StorageErrorKind::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+        StorageErrorKind::ObjectPathParse(_)
+        | StorageErrorKind::InvalidPath(_)
+        | StorageErrorKind::InvalidData => StatusCode::BAD_REQUEST,
+        StorageErrorKind::AccessDenied
+        | StorageErrorKind::AccessExpired
+        | StorageErrorKind::ReadOnly => StatusCode::FORBIDDEN,
+        StorageErrorKind::OverQuota => StatusCode::INSUFFICIENT_STORAGE,
+        StorageErrorKind::ConnectionFailed | StorageErrorKind::ConnectionClosed => {
+            StatusCode::BAD_GATEWAY
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Builds a response with no body.
+pub(crate) fn empty_response(status: StatusCode) -> Response<DataStream> {
+    let mut response = Response::new(DataStream::from_stream(empty()));
+    *response.status_mut() = status;
+    response
+}
+
+/// Builds a response with a body.
+pub(crate) fn full_response(
+    status: StatusCode,
+    content_type: &'static str,
+    body: String,
+) -> Response<DataStream> {
+    let body = Bytes::from(body);
+    let length = body.len();
+
+    let mut response = Response::new(DataStream::from_stream(once(ready(Ok(body)))));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(length));
+    response
+}
+
+/// Converts the path of a request url to a path in storage.
+///
+/// `base` is the url path that the handler is served from and is removed from
+/// the start of the path. Any trailing `/` is ignored.
+pub(crate) fn decode_path(base: &str, path: &str) -> StorageResult<ObjectPath> {
+    let base = base.trim_end_matches('/');
+    if !path.starts_with(base) {
+        return Err(error::not_found(ObjectPath::empty(), Some(path)));
+    }
+
+    let mut result = ObjectPath::empty();
+    for part in path[base.len()..].split('/').filter(|p| !p.is_empty()) {
+        let part = match percent_decode_str(part).decode_utf8() {
+            Ok(p) => p,
+            Err(_) => return Err(error::parse_error(path, Some("Path was not valid UTF-8."))),
+        };
+
+        if part == "." || part == ".." || part.contains('/') {
+            return Err(error::parse_error(
+                path,
+                Some("Path contained an invalid part."),
+            ));
+        }
+
+        result.push_part(&part);
+    }
+
+    Ok(result)
+}
+
+/// Converts a path in storage to the path of a url.
+pub(crate) fn encode_path(base: &str, path: &ObjectPath) -> String {
+    let mut result = base.trim_end_matches('/').to_owned();
+    for part in path.parts() {
+        result.push('/');
+        result.extend(utf8_percent_encode(part, PATH_SEGMENT));
+    }
+
+    if result.is_empty() {
+        result.push('/');
+    }
+    result
+}
+
+/// Removes a prefix from the start of a path.
+pub(crate) fn strip_prefix(prefix: &ObjectPath, path: &ObjectPath) -> ObjectPath {
+    let mut result = ObjectPath::empty();
+    for part in path.parts().iter().skip(prefix.parts().len()) {
+        if !part.is_empty() {
+            result.push_part(part);
+        }
+    }
+    result
+}
+
+/// Escapes text for inclusion in XML.
+pub(crate) fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
@@ -0,0 +1,335 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A gRPC server for the [remote backend](../../backends/remote/index.html).
+//! Included with the feature "remote".
+//!
+//! [`GrpcHandler`](struct.GrpcHandler.html) exposes a store through the
+//! protocol defined in `proto/file_store.proto` so it can act as a broker,
+//! holding the credentials for the real storage while clients connect with a
+//! [`RemoteBackend`](../../backends/remote/struct.RemoteBackend.html).
+//!
+//! Clients are not authenticated, the handler should only be exposed to
+//! trusted clients or behind something that does authentication.
+use bytes::IntoBuf;
+use futures::future::ready;
+use futures::stream::{empty, iter, once, Stream, StreamExt, TryStreamExt};
+use http::header::{HeaderValue, CONTENT_TYPE};
+use http::{Method, Request, Response, StatusCode};
+use prost::Message;
+
+use super::*;
+use crate::backends::remote::protocol::*;
+use crate::utils::into_data_stream;
+use crate::{FileStore, StorageBackend};
+
+/// Handles gRPC requests from a
+/// [`RemoteBackend`](../../backends/remote/struct.RemoteBackend.html).
+#[derive(Clone, Debug)]
+pub struct GrpcHandler {
+    store: FileStore,
+    prefix: ObjectPath,
+}
+
+/// Builds a successful response from a stream of encoded messages.
+fn grpc_response<S>(messages: S) -> Response<DataStream>
+where
+    S: Stream<Item = StorageResult<Data>> + Send + 'static,
+{
+    let mut response = Response::new(DataStream::from_stream(messages));
+    let headers = response.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_CONTENT_TYPE));
+    headers.insert(STATUS_HEADER, HeaderValue::from(0));
+    response
+}
+
+/// Builds a response holding a single message.
+fn message_response<M: Message>(message: &M) -> Response<DataStream> {
+    grpc_response(once(ready(Ok(encode_frame(message)))))
+}
+
+impl GrpcHandler {
+    /// Creates a handler that serves the entire store.
+    pub fn new(store: FileStore) -> GrpcHandler {
+        GrpcHandler {
+            store,
+            prefix: ObjectPath::empty(),
+        }
+    }
+
+    /// Only serves the objects under the given path.
+    pub fn prefix(mut self, prefix: ObjectPath) -> GrpcHandler {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Handles a request.
+    pub fn handle<B, I, E>(&self, request: Request<B>) -> ServeFuture
+    where
+        B: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+    {
+        let handler = self.clone();
+        ServeFuture::from_future(async move {
+            match handler.dispatch(request).await {
+                Ok(response) => response,
+                Err(e) => empty_response(error_status(&e)),
+            }
+        })
+    }
+
+    async fn dispatch<B, I, E>(self, request: Request<B>) -> StorageResult<Response<DataStream>>
+    where
+        B: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+    {
+        if request.method() != Method::POST {
+            return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        let (parts, body) = request.into_parts();
+        let path = parts.uri.path();
+        if !path.starts_with(SERVICE_PATH) {
+            return Ok(empty_response(StatusCode::NOT_FOUND));
+        }
+        let method = &path[SERVICE_PATH.len()..];
+        let body = DataStream::from_stream(into_data_stream(body));
+
+        match method {
+            "Ping" => Ok(message_response(&StatusResponse { error: None })),
+            "ListObjects" => self.list(body, false).await,
+            "ListDirectory" => self.list(body, true).await,
+            "GetObject" => self.get_object(body).await,
+            "GetFileStream" => self.get_file_stream(body).await,
+            "CopyFile" => self.transfer(body, false).await,
+            "MoveFile" => self.transfer(body, true).await,
+            "DeleteObject" => self.delete_object(body).await,
+            "WriteFile" => self.write_file(body).await,
+            _ => {
+                let mut response = grpc_response(empty());
+                response
+                    .headers_mut()
+                    .insert(STATUS_HEADER, HeaderValue::from(STATUS_UNIMPLEMENTED));
+                Ok(response)
+            }
+        }
+    }
+
+    /// Converts a path from a client to a path in the store.
+    fn store_path(&self, path: String) -> StorageResult<ObjectPath> {
+        Ok(self.prefix.join(&ObjectPath::new(path)?))
+    }
+
+    /// Converts an object in the store to a message for a client.
+    fn object_message(prefix: &ObjectPath, object: &Object) -> ObjectMessage {
+        let metadata = object.metadata();
+        ObjectMessage {
+            path: strip_prefix(prefix, &object.path()).to_string(),
+            size: object.len(),
+            kind: ObjectKind::from(object.object_type()) as i32,
+            modified: object.modified().map(Timestamp::from),
+            etag: object.etag(),
+            has_metadata: metadata.is_some(),
+            metadata: metadata.unwrap_or_default(),
+            content_type: object.content_type(),
+            checksum: object.checksum().map(|c| c.to_string()),
+        }
+    }
+
+    async fn list(&self, body: DataStream, directory: bool) -> StorageResult<Response<DataStream>> {
+        let request = first_message::<PathRequest>(body).await?;
+
+        let result = match self.store_path(request.path) {
+            Ok(path) if directory => self.store.list_directory(path).await,
+            Ok(path) => self.store.list_objects(path).await,
+            Err(e) => Err(e),
+        };
+
+        let objects = match result {
+            Ok(objects) => objects,
+            Err(e) => {
+                return Ok(message_response(&ObjectResponse {
+                    result: Some(ObjectResult::Error(ErrorMessage::new(&e, false))),
+                }))
+            }
+        };
+
+        let prefix = self.prefix.clone();
+        let messages = objects.map(move |result| {
+            let result = match result {
+                Ok(object) => ObjectResult::Object(GrpcHandler::object_message(&prefix, &object)),
+                Err(e) => ObjectResult::Error(ErrorMessage::new(&e, false)),
+            };
+
+            Ok(encode_frame(&ObjectResponse {
+                result: Some(result),
+            }))
+        });
+
+        Ok(grpc_response(messages))
+    }
+
+    async fn get_object(&self, body: DataStream) -> StorageResult<Response<DataStream>> {
+        let request = first_message::<PathRequest>(body).await?;
+
+        let result = match self.store_path(request.path) {
+            Ok(path) => self.store.get_object(path).await,
+            Err(e) => Err(e),
+        };
+
+        let result = match result {
+            Ok(object) => ObjectResult::Object(GrpcHandler::object_message(&self.prefix, &object)),
+            Err(e) => ObjectResult::Error(ErrorMessage::new(&e, false)),
+        };
+
+        Ok(message_response(&ObjectResponse {
+            result: Some(result),
+        }))
+    }
+
+    async fn get_file_stream(&self, body: DataStream) -> StorageResult<Response<DataStream>> {
+        let request = first_message::<PathRequest>(body).await?;
+
+        let result = match self.store_path(request.path) {
+            Ok(path) => self.store.get_file_stream(path).await,
+            Err(e) => Err(e),
+        };
+
+        let stream = match result {
+            Ok(stream) => stream,
+            Err(e) => {
+                return Ok(message_response(&DataResponse {
+                    result: Some(DataResult::Error(ErrorMessage::new(&e, false))),
+                }))
+            }
+        };
+
+        let messages = stream.map(|result| {
+            let messages: Vec<StorageResult<Data>> = match result {
+                Ok(data) => data
+                    .chunks(MAX_CHUNK_SIZE)
+                    .map(|chunk| {
+                        Ok(encode_frame(&DataResponse {
+                            result: Some(DataResult::Data(chunk.to_vec())),
+                        }))
+                    })
+                    .collect(),
+                Err(e) => vec![Ok(encode_frame(&DataResponse {
+                    result: Some(DataResult::Error(ErrorMessage::new(&e, false))),
+                }))],
+            };
+            iter(messages)
+        });
+
+        Ok(grpc_response(messages.flatten()))
+    }
+
+    async fn transfer(
+        &self,
+        body: DataStream,
+        is_move: bool,
+    ) -> StorageResult<Response<DataStream>> {
+        let request = first_message::<TransferRequest>(body).await?;
+
+        let source = match self.store_path(request.source) {
+            Ok(path) => path,
+            Err(e) => {
+                let result = Err(TransferError::SourceError(e));
+                return Ok(message_response(&StatusResponse::from_transfer_result(
+                    result,
+                )));
+            }
+        };
+
+        let target = match request.target.map(UploadMessage::into_upload_info) {
+            Some(Ok(mut info)) => {
+                info.path = self.prefix.join(&info.path);
+                info
+            }
+            Some(Err(e)) => {
+                let result = Err(TransferError::TargetError(e));
+                return Ok(message_response(&StatusResponse::from_transfer_result(
+                    result,
+                )));
+            }
+            None => return Err(error::invalid_data(Some("Missing transfer target"))),
+        };
+
+        let result = if is_move {
+            StorageBackend::move_file(&self.store, source, target).await
+        } else {
+            StorageBackend::copy_file(&self.store, source, target).await
+        };
+
+        Ok(message_response(&StatusResponse::from_transfer_result(
+            result,
+        )))
+    }
+
+    async fn delete_object(&self, body: DataStream) -> StorageResult<Response<DataStream>> {
+        let request = first_message::<PathRequest>(body).await?;
+
+        let result = match self.store_path(request.path) {
+            Ok(path) => self.store.delete_object(path).await,
+            Err(e) => Err(e),
+        };
+
+        Ok(message_response(&StatusResponse::from_result(result)))
+    }
+
+    async fn write_file(&self, body: DataStream) -> StorageResult<Response<DataStream>> {
+        let mut messages = Box::pin(decode_messages::<WriteRequest>(body));
+
+        let info = match messages.next().await {
+            Some(Ok(WriteRequest {
+                part: Some(WritePart::Info(info)),
+            })) => info,
+            Some(Err(e)) => return Err(e),
+            _ => return Err(error::invalid_data(Some("Expected the upload info"))),
+        };
+
+        let info = match info.into_upload_info() {
+            Ok(mut info) => {
+                info.path = self.prefix.join(&info.path);
+                info
+            }
+            Err(e) => {
+                let result = Err(TransferError::TargetError(e));
+                return Ok(message_response(&StatusResponse::from_transfer_result(
+                    result,
+                )));
+            }
+        };
+
+        let data = messages.and_then(|request| {
+            ready(match request.part {
+                Some(WritePart::Data(data)) => Ok(Data::from(data)),
+                _ => Err(error::invalid_data(Some("Expected file data"))),
+            })
+        });
+
+        let result = StorageBackend::write_file_from_stream(
+            &self.store,
+            info,
+            DataStream::from_stream(data),
+        )
+        .await;
+
+        Ok(message_response(&StatusResponse::from_transfer_result(
+            result,
+        )))
+    }
+}
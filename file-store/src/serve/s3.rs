@@ -0,0 +1,423 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An S3 compatible server. Included with the feature "s3-gateway".
+//!
+//! [`S3Handler`](struct.S3Handler.html) exposes a store as a single bucket
+//! using path style urls (`/bucket/key`) and implements the parts of the S3 API
+//! that most tools need: `ListObjectsV2`, `GetObject`, `HeadObject`,
+//! `PutObject`, `DeleteObject` and `HeadBucket`.
+//!
+//! Request signatures are not checked, the handler should only be exposed to
+//! trusted clients or behind something that does authentication. Clients
+//! should be configured to use path style urls.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::IntoBuf;
+use futures::stream::{Stream, TryStreamExt};
+use http::header::{HeaderValue, ETAG};
+use http::{Method, Request, Response, StatusCode};
+use percent_encoding::percent_decode_str;
+use time::{at_utc, Timespec};
+
+use super::*;
+use crate::responder::{entity_tag, serve_object};
+use crate::upload::upload_body;
+use crate::FileStore;
+
+/// The most keys returned in a single listing.
+const MAX_KEYS: usize = 1000;
+
+/// Handles S3 requests for a [`FileStore`](../../enum.FileStore.html).
+#[derive(Clone, Debug)]
+pub struct S3Handler {
+    store: FileStore,
+    bucket: String,
+    prefix: ObjectPath,
+    base_path: String,
+    max_upload_size: Option<u64>,
+}
+
+/// The parameters of a `ListObjectsV2` request.
+#[derive(Default)]
+struct ListParams {
+    prefix: String,
+    delimiter: Option<String>,
+    max_keys: usize,
+    start_after: Option<String>,
+    continuation_token: Option<String>,
+}
+
+impl ListParams {
+    fn parse(query: Option<&str>) -> StorageResult<ListParams> {
+        let mut params = ListParams {
+            max_keys: MAX_KEYS,
+            ..Default::default()
+        };
+
+        for (name, value) in query_pairs(query.unwrap_or(""))? {
+            match name.as_str() {
+                "prefix" => params.prefix = value,
+                "delimiter" if !value.is_empty() => params.delimiter = Some(value),
+                "start-after" => params.start_after = Some(value),
+                "continuation-token" => params.continuation_token = Some(value),
+                "max-keys" => match value.parse::<usize>() {
+                    Ok(max) => params.max_keys = max.min(MAX_KEYS),
+                    Err(_) => {
+                        return Err(error::invalid_data(Some(&format!(
+                            "Invalid max-keys '{}'",
+                            value
+                        ))))
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        Ok(params)
+    }
+}
+
+/// Splits a url query string into decoded name and value pairs.
+fn query_pairs(query: &str) -> StorageResult<Vec<(String, String)>> {
+    let decode = |s: &str| -> StorageResult<String> {
+        match percent_decode_str(&s.replace('+', " ")).decode_utf8() {
+            Ok(s) => Ok(s.into_owned()),
+            Err(_) => Err(error::invalid_data(Some("Query was not valid UTF-8."))),
+        }
+    };
+
+    let mut pairs = Vec::new();
+    for pair in query.split('&').filter(|p| !p.is_empty()) {
+        let mut parts = pair.splitn(2, '=');
+        let name = decode(parts.next().unwrap_or(""))?;
+        let value = decode(parts.next().unwrap_or(""))?;
+        pairs.push((name, value));
+    }
+    Ok(pairs)
+}
+
+/// Formats a time in the ISO 8601 format that S3 uses.
+fn iso_date(time: SystemTime) -> String {
+    let duration = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let tm = at_utc(Timespec::new(duration.as_secs() as i64, 0));
+    format!(
+        "{}.{:03}Z",
+        tm.strftime("%Y-%m-%dT%H:%M:%S")
+            .map(|t| t.to_string())
+            .unwrap_or_default(),
+        duration.subsec_millis()
+    )
+}
+
+/// The S3 error code that best represents a storage error.
+fn error_code(error: &StorageError) -> &'static str {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => "NoSuchKey",
+        StorageErrorKind::AccessDenied | StorageErrorKind::ReadOnly => "AccessDenied",
+        StorageErrorKind::AccessExpired => "ExpiredToken",
+        StorageErrorKind::OverQuota => "EntityTooLarge",
+        StorageErrorKind::PreconditionFailed(_) => "PreconditionFailed",
+        StorageErrorKind::ObjectPathParse(_)
+        | StorageErrorKind::InvalidPath(_)
+        | StorageErrorKind::InvalidData => "InvalidArgument",
+        StorageErrorKind::ConnectionFailed | StorageErrorKind::ConnectionClosed => {
+            "ServiceUnavailable"
+        }
+        _ => "InternalError",
+    }
+}
+
+/// Builds an S3 error response.
+fn s3_error(status: StatusCode, code: &str, message: &str) -> Response<DataStream> {
+    full_response(
+        status,
+        "application/xml",
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Error><Code>{}</Code><Message>{}</Message></Error>\n",
+            code,
+            escape(message)
+        ),
+    )
+}
+
+impl S3Handler {
+    /// Creates a handler that serves the entire store as the named bucket.
+    pub fn new(store: FileStore, bucket: &str) -> S3Handler {
+        S3Handler {
+            store,
+            bucket: bucket.to_owned(),
+            prefix: ObjectPath::empty(),
+            base_path: String::new(),
+            max_upload_size: None,
+        }
+    }
+
+    /// Only serves the objects under the given path.
+    pub fn prefix(mut self, prefix: ObjectPath) -> S3Handler {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Sets the url path that the handler is served from, for example `/s3`.
+    pub fn base_path(mut self, base_path: &str) -> S3Handler {
+        self.base_path = base_path.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Limits the size of objects that can be uploaded.
+    pub fn max_upload_size(mut self, size: u64) -> S3Handler {
+        self.max_upload_size = Some(size);
+        self
+    }
+
+    /// Handles a request.
+    pub fn handle<B, I, E>(&self, request: Request<B>) -> ServeFuture
+    where
+        B: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+    {
+        let handler = self.clone();
+        ServeFuture::from_future(async move {
+            match handler.dispatch(request).await {
+                Ok(response) => response,
+                Err(e) => s3_error(error_status(&e), error_code(&e), &e.to_string()),
+            }
+        })
+    }
+
+    async fn dispatch<B, I, E>(self, request: Request<B>) -> StorageResult<Response<DataStream>>
+    where
+        B: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+    {
+        let mut key = decode_path(&self.base_path, request.uri().path())?;
+        match key.unshift_part() {
+            Some(ref bucket) if bucket == &self.bucket => (),
+            _ => {
+                return Ok(s3_error(
+                    StatusCode::NOT_FOUND,
+                    "NoSuchBucket",
+                    "The specified bucket does not exist.",
+                ))
+            }
+        }
+
+        let (parts, body) = request.into_parts();
+
+        if key.is_empty() {
+            return match parts.method {
+                Method::GET => self.list(parts.uri.query()).await,
+                Method::HEAD => Ok(empty_response(StatusCode::OK)),
+                _ => Ok(s3_error(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "MethodNotAllowed",
+                    "The method is not supported for buckets.",
+                )),
+            };
+        }
+
+        let path = self.prefix.join(&key);
+        match parts.method {
+            Method::GET | Method::HEAD => {
+                serve_object(&self.store, path, &parts.method, &parts.headers).await
+            }
+            Method::PUT => {
+                match upload_body(
+                    &self.store,
+                    path.clone().into(),
+                    &parts.headers,
+                    body,
+                    self.max_upload_size,
+                )
+                .await
+                {
+                    Ok(()) => (),
+                    Err(TransferError::SourceError(ref e))
+                        if e.kind() == StorageErrorKind::OverQuota =>
+                    {
+                        return Ok(s3_error(
+                            StatusCode::BAD_REQUEST,
+                            "EntityTooLarge",
+                            "The object exceeds the maximum allowed size.",
+                        ))
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+
+                let object = self.store.get_object(path).await?;
+                let mut response = empty_response(StatusCode::OK);
+                if let Ok(etag) = HeaderValue::from_str(&entity_tag(&object)) {
+                    response.headers_mut().insert(ETAG, etag);
+                }
+                Ok(response)
+            }
+            Method::DELETE => {
+                // S3 reports success when deleting objects that don't exist.
+                match self.store.delete_object(path).await {
+                    Ok(()) => Ok(empty_response(StatusCode::NO_CONTENT)),
+                    Err(ref e) if error_status(e) == StatusCode::NOT_FOUND => {
+                        Ok(empty_response(StatusCode::NO_CONTENT))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            _ => Ok(s3_error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "MethodNotAllowed",
+                "The method is not supported for objects.",
+            )),
+        }
+    }
+
+    /// The key for a path in storage.
+    fn key(&self, path: &ObjectPath) -> String {
+        strip_prefix(&self.prefix, path).to_string()
+    }
+
+    async fn list(&self, query: Option<&str>) -> StorageResult<Response<DataStream>> {
+        let params = ListParams::parse(query)?;
+
+        let storage_prefix = if self.prefix.is_empty() {
+            ObjectPath::new(&params.prefix)?
+        } else {
+            ObjectPath::new(format!("{}/{}", self.prefix, params.prefix))?
+        };
+
+        let objects: Vec<Object> = self
+            .store
+            .list_objects(storage_prefix)
+            .await?
+            .try_collect()
+            .await?;
+
+        let mut files: Vec<(String, Object)> = objects
+            .into_iter()
+            .filter(|o| o.object_type() == ObjectType::File)
+            .map(|o| (self.key(&o.path()), o))
+            .filter(|(key, _)| key.starts_with(&params.prefix))
+            .collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let after = params
+            .continuation_token
+            .as_ref()
+            .or_else(|| params.start_after.as_ref());
+
+        let mut contents = String::new();
+        let mut common_prefixes: Vec<String> = Vec::new();
+        let mut count = 0;
+        let mut last: Option<String> = None;
+        let mut truncated = false;
+
+        for (key, object) in files {
+            if let Some(after) = after {
+                // A token ending in the delimiter is a common prefix that was
+                // already returned so skip everything beneath it.
+                let skipped_prefix = params
+                    .delimiter
+                    .as_ref()
+                    .map(|d| after.ends_with(d.as_str()) && key.starts_with(after.as_str()))
+                    .unwrap_or(false);
+                if key.as_str() <= after.as_str() || skipped_prefix {
+                    continue;
+                }
+            }
+
+            // Keys that contain the delimiter after the prefix are rolled up.
+            let common = params.delimiter.as_ref().and_then(|delimiter| {
+                key[params.prefix.len()..]
+                    .find(delimiter.as_str())
+                    .map(|pos| key[..params.prefix.len() + pos + delimiter.len()].to_owned())
+            });
+
+            if let Some(ref common) = common {
+                if common_prefixes.last() == Some(common) {
+                    continue;
+                }
+            }
+
+            if count == params.max_keys {
+                truncated = true;
+                break;
+            }
+            count += 1;
+            last = Some(common.clone().unwrap_or_else(|| key.clone()));
+
+            match common {
+                Some(common) => common_prefixes.push(common),
+                None => {
+                    contents.push_str(&format!(
+                        "<Contents><Key>{}</Key><Size>{}</Size><ETag>{}</ETag>",
+                        escape(&key),
+                        object.len(),
+                        escape(&entity_tag(&object))
+                    ));
+                    if let Some(modified) = object.modified() {
+                        contents.push_str(&format!(
+                            "<LastModified>{}</LastModified>",
+                            iso_date(modified)
+                        ));
+                    }
+                    contents.push_str("<StorageClass>STANDARD</StorageClass></Contents>");
+                }
+            }
+        }
+
+        let mut body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">\
+             <Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount>\
+             <MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+            escape(&self.bucket),
+            escape(&params.prefix),
+            count,
+            params.max_keys,
+            truncated
+        );
+
+        if let Some(ref delimiter) = params.delimiter {
+            body.push_str(&format!("<Delimiter>{}</Delimiter>", escape(delimiter)));
+        }
+        if let Some(ref token) = params.continuation_token {
+            body.push_str(&format!(
+                "<ContinuationToken>{}</ContinuationToken>",
+                escape(&token)
+            ));
+        }
+        if let (true, Some(token)) = (truncated, last) {
+            body.push_str(&format!(
+                "<NextContinuationToken>{}</NextContinuationToken>",
+                escape(&token)
+            ));
+        }
+        if let Some(ref start_after) = params.start_after {
+            body.push_str(&format!("<StartAfter>{}</StartAfter>", escape(start_after)));
+        }
+
+        body.push_str(&contents);
+        for prefix in common_prefixes {
+            body.push_str(&format!(
+                "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                escape(&prefix)
+            ));
+        }
+        body.push_str("</ListBucketResult>\n");
+
+        Ok(full_response(StatusCode::OK, "application/xml", body))
+    }
+}
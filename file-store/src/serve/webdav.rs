@@ -0,0 +1,363 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A WebDAV server. Included with the feature "webdav".
+//!
+//! [`WebDavHandler`](struct.WebDavHandler.html) implements enough of
+//! [RFC 4918](https://tools.ietf.org/html/rfc4918) (`OPTIONS`, `PROPFIND`,
+//! `GET`, `HEAD`, `PUT`, `DELETE` and `MKCOL`) for the file managers built
+//! into most operating systems to browse and edit storage. Locking is not
+//! supported so clients will treat the server as class 1.
+//!
+//! With hyper for example:
+//!
+//! ```ignore
+//! let handler = Arc::new(WebDavHandler::new(store).base_path("/dav"));
+//! let service = make_service_fn(move |_| {
+//!     let handler = handler.clone();
+//!     async move {
+//!         Ok::<_, Infallible>(service_fn(move |request| {
+//!             handler
+//!                 .handle(request)
+//!                 .map(|response| Ok::<_, Infallible>(response.map(Body::wrap_stream)))
+//!         }))
+//!     }
+//! });
+//! ```
+//!
+//! Backends without real directories can't store empty directories so a
+//! collection created with `MKCOL` only persists once a file is written inside
+//! it.
+use bytes::IntoBuf;
+use futures::stream::{Stream, TryStreamExt};
+use http::header::{HeaderMap, HeaderValue, ALLOW};
+use http::{Request, Response, StatusCode};
+use httpdate::fmt_http_date;
+
+use super::*;
+use crate::responder::{entity_tag, serve_object};
+use crate::upload::upload_body;
+use crate::FileStore;
+
+const ALLOWED_METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, DELETE, MKCOL";
+
+/// Handles WebDAV requests for a [`FileStore`](../../enum.FileStore.html).
+#[derive(Clone, Debug)]
+pub struct WebDavHandler {
+    store: FileStore,
+    prefix: ObjectPath,
+    base_path: String,
+    max_upload_size: Option<u64>,
+}
+
+/// What exists at a path.
+enum Resource {
+    File(Object),
+    Collection(ObjectPath),
+}
+
+impl WebDavHandler {
+    /// Creates a handler that serves the entire store from the root of the
+    /// server.
+    pub fn new(store: FileStore) -> WebDavHandler {
+        WebDavHandler {
+            store,
+            prefix: ObjectPath::empty(),
+            base_path: String::new(),
+            max_upload_size: None,
+        }
+    }
+
+    /// Only serves the objects under the given path.
+    pub fn prefix(mut self, prefix: ObjectPath) -> WebDavHandler {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Sets the url path that the handler is served from, for example `/dav`.
+    pub fn base_path(mut self, base_path: &str) -> WebDavHandler {
+        self.base_path = base_path.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Limits the size of files that can be uploaded.
+    pub fn max_upload_size(mut self, size: u64) -> WebDavHandler {
+        self.max_upload_size = Some(size);
+        self
+    }
+
+    /// Handles a request.
+    pub fn handle<B, I, E>(&self, request: Request<B>) -> ServeFuture
+    where
+        B: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+    {
+        let handler = self.clone();
+        ServeFuture::from_future(async move {
+            match handler.dispatch(request).await {
+                Ok(response) => response,
+                Err(e) => empty_response(error_status(&e)),
+            }
+        })
+    }
+
+    async fn dispatch<B, I, E>(self, request: Request<B>) -> StorageResult<Response<DataStream>>
+    where
+        B: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+    {
+        let path = self
+            .prefix
+            .join(&decode_path(&self.base_path, request.uri().path())?);
+        let (parts, body) = request.into_parts();
+
+        match parts.method.as_str() {
+            "OPTIONS" => {
+                let mut response = empty_response(StatusCode::OK);
+                let headers = response.headers_mut();
+                headers.insert("DAV", HeaderValue::from_static("1"));
+                headers.insert(ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+                Ok(response)
+            }
+            "GET" | "HEAD" => serve_object(&self.store, path, &parts.method, &parts.headers).await,
+            "PUT" => self.put(path, &parts.headers, body).await,
+            "DELETE" => self.delete(path).await,
+            "MKCOL" => self.mkcol(path).await,
+            "PROPFIND" => self.propfind(path, &parts.headers).await,
+            _ => {
+                let mut response = empty_response(StatusCode::METHOD_NOT_ALLOWED);
+                response
+                    .headers_mut()
+                    .insert(ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+                Ok(response)
+            }
+        }
+    }
+
+    /// Finds what exists at a path.
+    async fn resource(&self, path: &ObjectPath) -> StorageResult<Option<Resource>> {
+        if path.is_empty() || path == &self.prefix {
+            return Ok(Some(Resource::Collection(path.clone())));
+        }
+
+        match self.store.get_object(path.clone()).await {
+            Ok(ref object) if object.object_type() == ObjectType::Directory => {
+                return Ok(Some(Resource::Collection(path.clone())))
+            }
+            Ok(object) => return Ok(Some(Resource::File(object))),
+            Err(ref e) if error_status(e) == StatusCode::NOT_FOUND => (),
+            Err(e) => return Err(e),
+        }
+
+        let children: Vec<Object> = self
+            .store
+            .list_directory(path.clone())
+            .await?
+            .try_collect()
+            .await?;
+        if children.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Resource::Collection(path.clone())))
+        }
+    }
+
+    async fn put<B, I, E>(
+        &self,
+        path: ObjectPath,
+        headers: &HeaderMap,
+        body: B,
+    ) -> StorageResult<Response<DataStream>>
+    where
+        B: Stream<Item = Result<I, E>> + Send + 'static,
+        I: IntoBuf + 'static,
+        E: Into<StorageError> + 'static,
+    {
+        if path.is_empty() {
+            return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        let status = match self.resource(&path).await? {
+            Some(Resource::Collection(_)) => return Ok(empty_response(StatusCode::CONFLICT)),
+            Some(Resource::File(_)) => StatusCode::NO_CONTENT,
+            None => StatusCode::CREATED,
+        };
+
+        match upload_body(
+            &self.store,
+            path.into(),
+            headers,
+            body,
+            self.max_upload_size,
+        )
+        .await
+        {
+            Ok(()) => Ok(empty_response(status)),
+            Err(TransferError::SourceError(ref e)) if e.kind() == StorageErrorKind::OverQuota => {
+                Ok(empty_response(StatusCode::PAYLOAD_TOO_LARGE))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, path: ObjectPath) -> StorageResult<Response<DataStream>> {
+        if path.is_empty() || path == self.prefix {
+            return Ok(empty_response(StatusCode::FORBIDDEN));
+        }
+
+        match self.store.get_object(path.clone()).await {
+            Ok(_) => {
+                // This also deletes the contents of physical directories.
+                self.store.delete_object(path).await?;
+                return Ok(empty_response(StatusCode::NO_CONTENT));
+            }
+            Err(ref e) if error_status(e) == StatusCode::NOT_FOUND => (),
+            Err(e) => return Err(e),
+        }
+
+        // A virtual directory, delete everything inside it.
+        let dir = ObjectPath::new(format!("{}/", path))?;
+        let objects: Vec<Object> = self.store.list_objects(dir).await?.try_collect().await?;
+        if objects.is_empty() {
+            return Ok(empty_response(StatusCode::NOT_FOUND));
+        }
+
+        for object in objects {
+            self.store.delete_object(object.path()).await?;
+        }
+
+        Ok(empty_response(StatusCode::NO_CONTENT))
+    }
+
+    async fn mkcol(&self, path: ObjectPath) -> StorageResult<Response<DataStream>> {
+        if self.resource(&path).await?.is_some() {
+            return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+        }
+
+        let mut parent = path.clone();
+        parent.pop_part();
+        match self.resource(&parent).await? {
+            Some(Resource::Collection(_)) => Ok(empty_response(StatusCode::CREATED)),
+            _ => Ok(empty_response(StatusCode::CONFLICT)),
+        }
+    }
+
+    async fn propfind(
+        &self,
+        path: ObjectPath,
+        headers: &HeaderMap,
+    ) -> StorageResult<Response<DataStream>> {
+        let depth_zero = headers
+            .get("Depth")
+            .map(|v| v.as_bytes() == b"0")
+            .unwrap_or(false);
+
+        let mut body = String::from(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+        );
+
+        match self.resource(&path).await? {
+            None => return Ok(empty_response(StatusCode::NOT_FOUND)),
+            Some(Resource::File(object)) => self.write_file(&mut body, &object),
+            Some(Resource::Collection(dir)) => {
+                self.write_collection(&mut body, &dir);
+
+                if !depth_zero {
+                    // Infinite depth is treated as a depth of one.
+                    let children: Vec<Object> = self
+                        .store
+                        .list_directory(dir.clone())
+                        .await?
+                        .try_collect()
+                        .await?;
+
+                    for child in children {
+                        match child.object_type() {
+                            ObjectType::Directory => {
+                                let path = child.path();
+                                if path != dir {
+                                    self.write_collection(&mut body, &path);
+                                }
+                            }
+                            _ => self.write_file(&mut body, &child),
+                        }
+                    }
+                }
+            }
+        }
+
+        body.push_str("</D:multistatus>\n");
+        Ok(full_response(
+            StatusCode::MULTI_STATUS,
+            "application/xml; charset=utf-8",
+            body,
+        ))
+    }
+
+    fn write_response(&self, body: &mut String, path: &ObjectPath, collection: bool, props: &str) {
+        let relative = strip_prefix(&self.prefix, path);
+        let mut href = encode_path(&self.base_path, &relative);
+        if collection && !href.ends_with('/') {
+            href.push('/');
+        }
+
+        let name = relative
+            .parts()
+            .last()
+            .map(|s| (*s).to_owned())
+            .unwrap_or_default();
+
+        body.push_str(&format!(
+            "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+             <D:displayname>{}</D:displayname>{}</D:prop>\
+             <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+            escape(&href),
+            escape(&name),
+            props
+        ));
+    }
+
+    fn write_collection(&self, body: &mut String, path: &ObjectPath) {
+        self.write_response(
+            body,
+            path,
+            true,
+            "<D:resourcetype><D:collection/></D:resourcetype>",
+        );
+    }
+
+    fn write_file(&self, body: &mut String, object: &Object) {
+        let path = object.path();
+        let content_type = mime_guess::from_path(path.to_string()).first_or_octet_stream();
+
+        let mut props = format!(
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>{}</D:getcontenttype><D:getetag>{}</D:getetag>",
+            object.len(),
+            escape(content_type.as_ref()),
+            escape(&entity_tag(object)),
+        );
+
+        if let Some(modified) = object.modified() {
+            props.push_str(&format!(
+                "<D:getlastmodified>{}</D:getlastmodified>",
+                fmt_http_date(modified)
+            ));
+        }
+
+        self.write_response(body, &path, false, &props);
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Access to storage as a [tower](https://github.com/tower-rs/tower)
+//! service. Included with the feature "tower".
+//!
+//! [`StorageService`](struct.StorageService.html) wraps any
+//! [`StorageBackend`](../trait.StorageBackend.html) and implements
+//! `Service<StorageRequest>` so the standard tower middlewares (timeouts,
+//! rate limits, load shedding, tracing, etc.) can be layered around it.
+//!
+//! The service's error type is [`StorageError`](../struct.StorageError.html),
+//! errors from copies, moves and writes lose the information about which side
+//! of the transfer failed.
+use std::fmt;
+use std::task::{Context, Poll};
+
+use futures::future::{FutureExt, TryFutureExt};
+use tower_service::Service;
+
+use crate::types::*;
+use crate::{FileStore, StorageBackend};
+
+/// A request to a [`StorageService`](struct.StorageService.html). Each variant
+/// corresponds to a method of [`StorageBackend`](../trait.StorageBackend.html).
+pub enum StorageRequest {
+    /// See [`list_objects`](../trait.StorageBackend.html#tymethod.list_objects).
+    ListObjects(ObjectPath),
+    /// See [`list_directory`](../trait.StorageBackend.html#tymethod.list_directory).
+    ListDirectory(ObjectPath),
+    /// See [`get_object`](../trait.StorageBackend.html#tymethod.get_object).
+    GetObject(ObjectPath),
+    /// See [`get_file_stream`](../trait.StorageBackend.html#tymethod.get_file_stream).
+    GetFileStream(ObjectPath),
+    /// See [`copy_file`](../trait.StorageBackend.html#method.copy_file).
+    CopyFile {
+        /// The file to copy.
+        source: ObjectPath,
+        /// Where to copy it to.
+        target: UploadInfo,
+    },
+    /// See [`move_file`](../trait.StorageBackend.html#method.move_file).
+    MoveFile {
+        /// The file to move.
+        source: ObjectPath,
+        /// Where to move it to.
+        target: UploadInfo,
+    },
+    /// See [`delete_object`](../trait.StorageBackend.html#tymethod.delete_object).
+    DeleteObject(ObjectPath),
+    /// See [`write_file_from_stream`](../trait.StorageBackend.html#tymethod.write_file_from_stream).
+    WriteFileFromStream {
+        /// The file to write.
+        info: UploadInfo,
+        /// The data to write.
+        data: DataStream,
+    },
+}
+
+impl fmt::Debug for StorageRequest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageRequest::ListObjects(path) => write!(f, "ListObjects({})", path),
+            StorageRequest::ListDirectory(path) => write!(f, "ListDirectory({})", path),
+            StorageRequest::GetObject(path) => write!(f, "GetObject({})", path),
+            StorageRequest::GetFileStream(path) => write!(f, "GetFileStream({})", path),
+            StorageRequest::CopyFile { source, target } => {
+                write!(f, "CopyFile({} -> {})", source, target.path)
+            }
+            StorageRequest::MoveFile { source, target } => {
+                write!(f, "MoveFile({} -> {})", source, target.path)
+            }
+            StorageRequest::DeleteObject(path) => write!(f, "DeleteObject({})", path),
+            StorageRequest::WriteFileFromStream { info, .. } => {
+                write!(f, "WriteFileFromStream({})", info.path)
+            }
+        }
+    }
+}
+
+/// The response to a [`StorageRequest`](enum.StorageRequest.html).
+pub enum StorageResponse {
+    /// The objects from a `ListObjects` or `ListDirectory` request.
+    Objects(ObjectStream),
+    /// The object from a `GetObject` request.
+    Object(Object),
+    /// The data from a `GetFileStream` request.
+    Data(DataStream),
+    /// The operation completed successfully.
+    Complete,
+}
+
+impl fmt::Debug for StorageResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageResponse::Objects(_) => f.pad("Objects"),
+            StorageResponse::Object(object) => write!(f, "Object({:?})", object),
+            StorageResponse::Data(_) => f.pad("Data"),
+            StorageResponse::Complete => f.pad("Complete"),
+        }
+    }
+}
+
+/// A future that resolves to a [`StorageResponse`](enum.StorageResponse.html).
+pub type StorageResponseFuture = WrappedFuture<StorageResult<StorageResponse>>;
+
+/// A tower `Service` that sends requests to a backend.
+///
+/// Backends have no limit on the number of requests in flight so the service
+/// is always ready.
+#[derive(Clone, Debug)]
+pub struct StorageService<B = FileStore>
+where
+    B: StorageBackend,
+{
+    backend: B,
+}
+
+impl<B> StorageService<B>
+where
+    B: StorageBackend,
+{
+    /// Creates a new service for the given backend.
+    pub fn new(backend: B) -> StorageService<B> {
+        StorageService { backend }
+    }
+
+    /// Gets the backend that this service sends requests to.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+}
+
+impl<B> Service<StorageRequest> for StorageService<B>
+where
+    B: StorageBackend,
+{
+    type Response = StorageResponse;
+    type Error = StorageError;
+    type Future = StorageResponseFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context) -> Poll<StorageResult<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: StorageRequest) -> StorageResponseFuture {
+        let backend = &self.backend;
+
+        match request {
+            StorageRequest::ListObjects(prefix) => StorageResponseFuture::from_future(
+                backend
+                    .list_objects(prefix)
+                    .map_ok(StorageResponse::Objects),
+            ),
+            StorageRequest::ListDirectory(dir) => StorageResponseFuture::from_future(
+                backend.list_directory(dir).map_ok(StorageResponse::Objects),
+            ),
+            StorageRequest::GetObject(path) => StorageResponseFuture::from_future(
+                backend.get_object(path).map_ok(StorageResponse::Object),
+            ),
+            StorageRequest::GetFileStream(path) => StorageResponseFuture::from_future(
+                backend.get_file_stream(path).map_ok(StorageResponse::Data),
+            ),
+            StorageRequest::CopyFile { source, target } => {
+                StorageResponseFuture::from_future(backend.copy_file(source, target).map(complete))
+            }
+            StorageRequest::MoveFile { source, target } => {
+                StorageResponseFuture::from_future(backend.move_file(source, target).map(complete))
+            }
+            StorageRequest::DeleteObject(path) => StorageResponseFuture::from_future(
+                backend
+                    .delete_object(path)
+                    .map_ok(|()| StorageResponse::Complete),
+            ),
+            StorageRequest::WriteFileFromStream { info, data } => {
+                StorageResponseFuture::from_future(
+                    backend.write_file_from_stream(info, data).map(complete),
+                )
+            }
+        }
+    }
+}
+
+fn complete(result: Result<(), TransferError>) -> StorageResult<StorageResponse> {
+    match result {
+        Ok(()) => Ok(StorageResponse::Complete),
+        Err(e) => Err(e.into()),
+    }
+}
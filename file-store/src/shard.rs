@@ -0,0 +1,375 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Spreading files across several stores.
+//!
+//! A [`ShardedBackend`](struct.ShardedBackend.html) holds a list of shard
+//! [`FileStore`](../enum.FileStore.html)s and stores each file in exactly one
+//! of them, chosen by a [`ShardRouter`](trait.ShardRouter.html). The default
+//! [`HashRouter`](struct.HashRouter.html) picks the shard from a hash of the
+//! file's path which spreads files evenly across the shards.
+//!
+//! Listings are made from every shard at once and merged. When every shard
+//! lists in path order the merged listing is in path order too. Directories
+//! can exist in more than one shard but are only listed once.
+//!
+//! Changing the number of shards or the router moves where files are
+//! expected to be, files already stored are not moved.
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::Arc;
+
+use futures::future::{join_all, FutureExt};
+use futures::stream::{Stream, StreamExt};
+
+use crate::backends::Backend;
+use crate::types::*;
+use crate::{FileStore, StorageBackend};
+
+/// Picks the shard that stores a path.
+pub trait ShardRouter: Send + Sync + 'static {
+    /// Returns the index of the shard, less than `shards`, that stores
+    /// `path`.
+    fn route(&self, path: &ObjectPath, shards: usize) -> usize;
+}
+
+impl<F> ShardRouter for F
+where
+    F: Fn(&ObjectPath, usize) -> usize + Send + Sync + 'static,
+{
+    fn route(&self, path: &ObjectPath, shards: usize) -> usize {
+        self(path, shards)
+    }
+}
+
+/// Routes paths by a hash of the full path.
+///
+/// The hash (FNV-1a) is stable so paths are routed to the same shard across
+/// releases and platforms.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HashRouter;
+
+impl ShardRouter for HashRouter {
+    fn route(&self, path: &ObjectPath, shards: usize) -> usize {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in path.to_string().bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+
+        (hash % shards as u64) as usize
+    }
+}
+
+/// A [`StorageBackend`](../trait.StorageBackend.html) that spreads its files
+/// across several stores.
+#[derive(Clone)]
+pub struct ShardedBackend {
+    shards: Arc<Vec<FileStore>>,
+    router: Arc<dyn ShardRouter>,
+}
+
+impl fmt::Debug for ShardedBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShardedBackend")
+            .field("shards", &self.shards.len())
+            .finish()
+    }
+}
+
+impl ShardedBackend {
+    /// Spreads files across the given stores using a
+    /// [`HashRouter`](struct.HashRouter.html).
+    ///
+    /// Panics if `shards` is empty.
+    pub fn new(shards: Vec<FileStore>) -> ShardedBackend {
+        assert!(!shards.is_empty(), "There must be at least one shard.");
+
+        ShardedBackend {
+            shards: Arc::new(shards),
+            router: Arc::new(HashRouter),
+        }
+    }
+
+    /// Sets the router that picks the shard for each path.
+    pub fn router<R>(mut self, router: R) -> ShardedBackend
+    where
+        R: ShardRouter,
+    {
+        self.router = Arc::new(router);
+        self
+    }
+
+    /// Gets the shard stores.
+    pub fn shards(&self) -> &[FileStore] {
+        &self.shards
+    }
+
+    /// Gets the index of the shard that stores `path`.
+    pub fn shard_index(&self, path: &ObjectPath) -> usize {
+        self.router.route(path, self.shards.len()) % self.shards.len()
+    }
+
+    fn shard(&self, path: &ObjectPath) -> &FileStore {
+        &self.shards[self.shard_index(path)]
+    }
+
+    /// Lists from every shard and merges the results.
+    fn merge<F>(&self, list: F) -> ObjectStreamFuture
+    where
+        F: Fn(&FileStore) -> ObjectStreamFuture,
+    {
+        let listings = join_all(self.shards.iter().map(list).collect::<Vec<_>>());
+
+        ObjectStreamFuture::from_future(async move {
+            let mut streams = Vec::new();
+            for listing in listings.await {
+                streams.push(listing?);
+            }
+
+            Ok(ObjectStream::from_stream(merge_streams(streams)))
+        })
+    }
+}
+
+struct MergeState {
+    streams: Vec<Option<ObjectStream>>,
+    heads: Vec<Option<Object>>,
+    directories: HashSet<ObjectPath>,
+}
+
+async fn next_object(mut state: MergeState) -> Option<(StorageResult<Object>, MergeState)> {
+    loop {
+        for index in 0..state.streams.len() {
+            if state.heads[index].is_some() {
+                continue;
+            }
+
+            let next = match state.streams[index] {
+                Some(ref mut stream) => stream.next().await,
+                None => continue,
+            };
+
+            match next {
+                Some(Ok(object)) => state.heads[index] = Some(object),
+                Some(Err(e)) => {
+                    state.streams.iter_mut().for_each(|s| *s = None);
+                    state.heads.iter_mut().for_each(|h| *h = None);
+                    return Some((Err(e), state));
+                }
+                None => state.streams[index] = None,
+            }
+        }
+
+        let index = state
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| head.as_ref().map(|o| (i, o.path())))
+            .min_by(|a, b| a.1.cmp(&b.1))?
+            .0;
+
+        let object = state.heads[index].take()?;
+        if object.object_type() == ObjectType::Directory && !state.directories.insert(object.path())
+        {
+            continue;
+        }
+
+        return Some((Ok(object), state));
+    }
+}
+
+/// Merges listings, taking the object with the lowest path from the front of
+/// each listing in turn.
+fn merge_streams(streams: Vec<ObjectStream>) -> impl Stream<Item = StorageResult<Object>> + Send {
+    let state = MergeState {
+        heads: streams.iter().map(|_| None).collect(),
+        streams: streams.into_iter().map(Some).collect(),
+        directories: HashSet::new(),
+    };
+
+    futures::stream::unfold(state, next_object)
+}
+
+fn is_not_found(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => true,
+        _ => false,
+    }
+}
+
+/// Moves a file between two shards.
+async fn transfer(
+    source: FileStore,
+    path: ObjectPath,
+    target: FileStore,
+    info: UploadInfo,
+    delete: bool,
+) -> Result<(), TransferError> {
+    let stream = StorageBackend::get_file_stream(&source, path.clone())
+        .await
+        .map_err(TransferError::SourceError)?;
+    StorageBackend::write_file_from_stream(&target, info, stream).await?;
+
+    if delete {
+        StorageBackend::delete_object(&source, path)
+            .await
+            .map_err(TransferError::SourceError)?;
+    }
+
+    Ok(())
+}
+
+impl StorageBackend for ShardedBackend {
+    fn backend_type(&self) -> Backend {
+        self.shards[0].backend_type()
+    }
+
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
+        self.merge(|s| StorageBackend::list_objects(s, prefix.clone()))
+    }
+
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        // Every shard may return up to the limit so it is applied again to
+        // the merged listing.
+        let merged = self.merge(|s| {
+            StorageBackend::list_objects_with_options(s, prefix.clone(), options.clone())
+        });
+        options.apply(prefix, merged)
+    }
+
+    fn lists_in_order(&self) -> bool {
+        self.shards.iter().all(|s| s.lists_in_order())
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        self.merge(|s| StorageBackend::list_directory(s, dir.clone()))
+    }
+
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
+        let shards = self.shards.clone();
+        let index = self.shard_index(&path);
+
+        ObjectFuture::from_future(async move {
+            let error = match StorageBackend::get_object(&shards[index], path.clone()).await {
+                Ok(object) => return Ok(object),
+                Err(e) if is_not_found(&e) => e,
+                Err(e) => return Err(e),
+            };
+
+            // Directories may only exist in other shards.
+            let others = shards
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != index)
+                .map(|(_, s)| StorageBackend::get_object(s, path.clone()));
+            for result in join_all(others.collect::<Vec<_>>()).await {
+                if let Ok(object) = result {
+                    if object.object_type() == ObjectType::Directory {
+                        return Ok(object);
+                    }
+                }
+            }
+
+            Err(error)
+        })
+    }
+
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        StorageBackend::get_file_stream(self.shard(&path), path)
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        StorageBackend::get_file_stream_range(self.shard(&path), path, offset, length)
+    }
+
+    fn copy_file(&self, source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
+        let from = self.shard(&source).clone();
+        let to = self.shard(&target.path).clone();
+        if self.shard_index(&source) == self.shard_index(&target.path) {
+            return StorageBackend::copy_file(&from, source, target);
+        }
+
+        CopyCompleteFuture::from_future(transfer(from, source, to, target, false))
+    }
+
+    fn move_file(&self, source: ObjectPath, target: UploadInfo) -> MoveCompleteFuture {
+        let from = self.shard(&source).clone();
+        let to = self.shard(&target.path).clone();
+        if self.shard_index(&source) == self.shard_index(&target.path) {
+            return StorageBackend::move_file(&from, source, target);
+        }
+
+        MoveCompleteFuture::from_future(transfer(from, source, to, target, true))
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        // The path may be a directory with files in every shard.
+        let deletes = join_all(
+            self.shards
+                .iter()
+                .map(|s| StorageBackend::delete_object(s, path.clone()))
+                .collect::<Vec<_>>(),
+        );
+
+        OperationCompleteFuture::from_future(deletes.map(|results| {
+            let mut not_found = None;
+            let mut deleted = false;
+            for result in results {
+                match result {
+                    Ok(()) => deleted = true,
+                    Err(e) if is_not_found(&e) => not_found = Some(e),
+                    Err(e) => return Err(e),
+                }
+            }
+
+            match not_found {
+                Some(e) if !deleted => Err(e),
+                _ => Ok(()),
+            }
+        }))
+    }
+
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        StorageBackend::create_directory(self.shard(&path), path)
+    }
+
+    fn write_file_from_stream(&self, info: UploadInfo, stream: DataStream) -> WriteCompleteFuture {
+        StorageBackend::write_file_from_stream(self.shard(&info.path), info, stream)
+    }
+
+    fn write_file_from_stream_returning(
+        &self,
+        info: UploadInfo,
+        stream: DataStream,
+    ) -> WrittenObjectFuture {
+        StorageBackend::write_file_from_stream_returning(self.shard(&info.path), info, stream)
+    }
+
+    fn write_file_from_source(
+        &self,
+        info: UploadInfo,
+        source: Arc<dyn UploadSource>,
+    ) -> WriteCompleteFuture {
+        StorageBackend::write_file_from_source(self.shard(&info.path), info, source)
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Point-in-time snapshots of the files under a prefix. Included with the
+//! feature "snapshot".
+//!
+//! [`FileStore::snapshot`](../enum.FileStore.html#method.snapshot) records the
+//! path, size, modification time and SHA-256 hash of every file under a prefix
+//! in a [`SnapshotManifest`](struct.SnapshotManifest.html). The manifest can be
+//! serialized with serde and later passed to
+//! [`FileStore::restore`](../enum.FileStore.html#method.restore) to copy the
+//! same files into another store.
+//!
+//! A snapshot does not copy any data so restoring reads the files from the
+//! original store. Files that have changed since the snapshot was taken are
+//! detected by their hash and fail the restore.
+//!
+//! [`FileStore::verify`](../enum.FileStore.html#method.verify) compares the
+//! files in a store against a manifest to validate a backup, producing a
+//! [`VerifyReport`](struct.VerifyReport.html) of missing, corrupted and
+//! unexpected files.
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use futures::stream::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::hashing::{digest_file, hash_stream, to_hex};
+use crate::types::*;
+use crate::{FileStore, StorageBackend};
+
+/// A file recorded in a snapshot.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// The path of the file relative to the snapshot's prefix.
+    pub path: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// The last modification time of the file if known.
+    pub modified: Option<SystemTime>,
+    /// The hex encoded SHA-256 hash of the file.
+    pub sha256: String,
+}
+
+/// A point-in-time listing of the files under a prefix.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotManifest {
+    /// The prefix that was captured.
+    pub prefix: String,
+    /// When the snapshot was taken.
+    pub created: SystemTime,
+    /// The files under the prefix, sorted by path.
+    pub entries: Vec<SnapshotEntry>,
+}
+
+impl SnapshotManifest {
+    /// The total size of the files in the snapshot.
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+}
+
+/// A future that resolves to a [`SnapshotManifest`](struct.SnapshotManifest.html).
+pub type SnapshotFuture = WrappedFuture<StorageResult<SnapshotManifest>>;
+
+/// A file whose content doesn't match its manifest entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Corruption {
+    /// The path of the file relative to the verified prefix.
+    pub path: String,
+    /// The size recorded in the manifest.
+    pub expected_size: u64,
+    /// The size of the file in the store.
+    pub actual_size: u64,
+    /// The hash recorded in the manifest.
+    pub expected_sha256: String,
+    /// The hash of the file in the store. `None` if the sizes differ since the
+    /// file is not read in that case.
+    pub actual_sha256: Option<String>,
+}
+
+/// The result of verifying a store against a manifest. All paths are relative
+/// to the verified prefix and sorted.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Files that match the manifest.
+    pub verified: Vec<String>,
+    /// Files in the manifest that don't exist in the store.
+    pub missing: Vec<String>,
+    /// Files whose size or hash doesn't match the manifest.
+    pub corrupted: Vec<Corruption>,
+    /// Files in the store that aren't in the manifest.
+    pub unexpected: Vec<String>,
+}
+
+impl VerifyReport {
+    /// True if no files are missing or corrupted. Unexpected files are
+    /// ignored.
+    pub fn is_valid(&self) -> bool {
+        self.missing.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+/// A future that resolves to a [`VerifyReport`](struct.VerifyReport.html).
+pub type VerifyFuture = WrappedFuture<StorageResult<VerifyReport>>;
+
+fn full_path(prefix: &ObjectPath, path: &str) -> StorageResult<ObjectPath> {
+    Ok(prefix.join(&ObjectPath::new(path)?))
+}
+
+fn is_not_found(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => true,
+        _ => false,
+    }
+}
+
+fn relative_path(prefix: &ObjectPath, path: &ObjectPath) -> String {
+    let mut relative = ObjectPath::empty();
+    for part in path.parts().iter().skip(prefix.parts().len()) {
+        relative.push_part(part);
+    }
+    relative.to_string()
+}
+
+async fn list_files(store: &FileStore, prefix: &ObjectPath) -> StorageResult<Vec<Object>> {
+    let list_prefix = if prefix.is_empty() {
+        prefix.clone()
+    } else {
+        ObjectPath::new(format!("{}/", prefix))?
+    };
+
+    let objects: Vec<Object> = store.list_objects(list_prefix).await?.try_collect().await?;
+    Ok(objects
+        .into_iter()
+        .filter(|o| o.object_type() == ObjectType::File)
+        .collect())
+}
+
+pub(crate) async fn snapshot(
+    store: FileStore,
+    prefix: ObjectPath,
+) -> StorageResult<SnapshotManifest> {
+    let created = SystemTime::now();
+
+    let mut entries = Vec::new();
+    for object in list_files(&store, &prefix).await? {
+        let path = object.path();
+        let relative = relative_path(&prefix, &path);
+
+        let hash = digest_file::<Sha256>(&store, path).await?;
+        entries.push(SnapshotEntry {
+            path: relative,
+            size: object.len(),
+            modified: object.modified(),
+            sha256: to_hex(&hash),
+        });
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(SnapshotManifest {
+        prefix: prefix.to_string(),
+        created,
+        entries,
+    })
+}
+
+pub(crate) async fn restore(
+    source: FileStore,
+    manifest: SnapshotManifest,
+    target: FileStore,
+) -> Result<(), TransferError> {
+    let prefix = ObjectPath::new(&manifest.prefix).map_err(TransferError::SourceError)?;
+
+    for entry in manifest.entries {
+        let path = full_path(&prefix, &entry.path).map_err(TransferError::SourceError)?;
+
+        let stream = source
+            .get_file_stream(path.clone())
+            .await
+            .map_err(TransferError::SourceError)?;
+        let (stream, hash) = hash_stream::<Sha256>(stream);
+
+        let info = UploadInfo {
+            path: path.clone(),
+            modified: entry.modified,
+            ..Default::default()
+        };
+        StorageBackend::write_file_from_stream(&target, info, stream).await?;
+
+        let hash = to_hex(&hash.await.map_err(TransferError::SourceError)?);
+        if hash != entry.sha256 {
+            // Don't leave the wrong data behind.
+            let _ = target.delete_object(path.clone()).await;
+            return Err(TransferError::SourceError(error::invalid_data(Some(
+                &format!("{} has changed since the snapshot was taken", path),
+            ))));
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn verify(
+    store: FileStore,
+    prefix: ObjectPath,
+    manifest: SnapshotManifest,
+) -> StorageResult<VerifyReport> {
+    let objects = match list_files(&store, &prefix).await {
+        Ok(objects) => objects,
+        // Nothing exists beneath the prefix.
+        Err(ref e) if is_not_found(e) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+
+    let mut found: HashMap<String, Object> = objects
+        .into_iter()
+        .map(|o| (relative_path(&prefix, &o.path()), o))
+        .collect();
+
+    let mut report = VerifyReport::default();
+    for entry in manifest.entries {
+        let object = match found.remove(&entry.path) {
+            Some(object) => object,
+            None => {
+                report.missing.push(entry.path);
+                continue;
+            }
+        };
+
+        // Only read the file if the size suggests it may be intact.
+        let actual_sha256 = if object.len() == entry.size {
+            let hash = digest_file::<Sha256>(&store, object.path()).await?;
+            Some(to_hex(&hash))
+        } else {
+            None
+        };
+
+        if actual_sha256.as_ref() == Some(&entry.sha256) {
+            report.verified.push(entry.path);
+        } else {
+            report.corrupted.push(Corruption {
+                path: entry.path,
+                expected_size: entry.size,
+                actual_size: object.len(),
+                expected_sha256: entry.sha256,
+                actual_sha256,
+            });
+        }
+    }
+
+    report.unexpected = found.into_iter().map(|(path, _)| path).collect();
+
+    report.verified.sort();
+    report.missing.sort();
+    report.corrupted.sort_by(|a, b| a.path.cmp(&b.path));
+    report.unexpected.sort();
+
+    Ok(report)
+}
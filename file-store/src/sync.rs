@@ -0,0 +1,611 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! One-way synchronisation between stores. Included with the feature "sync".
+//!
+//! Deciding whether a file needs copying compares sizes and modification
+//! times. Backends keep modification times at different resolutions (B2 stores
+//! milliseconds, some filesystems only whole seconds) and clocks drift, so
+//! times within a tolerance are treated as equal. When the times still differ
+//! the contents can be compared by checksum instead, which stops files that are
+//! already identical from being uploaded over and over.
+//!
+//! [`sync`](fn.sync.html) makes the files under a prefix in a target store
+//! match those under a prefix in a source store, copying new and changed files
+//! and optionally deleting files that are not in the source. To preview the
+//! changes first use [`plan`](fn.plan.html) and then [`apply`](fn.apply.html)
+//! the resulting plan.
+//!
+//! [`diff`](fn.diff.html) reports how two prefixes differ without changing
+//! anything, which is useful for verifying that a copy is complete.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{Duration, SystemTime};
+
+use futures::future::{join, FutureExt, TryFutureExt};
+use futures::stream::iter;
+use sha2::Sha256;
+
+use crate::copy::{list_files, target_path};
+use crate::hashing::digest_file;
+use crate::types::*;
+use crate::FileStore;
+
+/// The default tolerance used when comparing modification times.
+pub const DEFAULT_MTIME_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// A future that resolves to whether a file needs to be copied.
+pub type CompareFuture = WrappedFuture<StorageResult<bool>>;
+/// A stream of the [differences](enum.DiffEntry.html) between two prefixes.
+pub type DiffStream = WrappedStream<StorageResult<DiffEntry>>;
+/// A future that resolves to a [`DiffStream`](type.DiffStream.html).
+pub type DiffStreamFuture = WrappedFuture<StorageResult<DiffStream>>;
+/// A future that resolves to a [`SyncPlan`](struct.SyncPlan.html).
+pub type PlanFuture = WrappedFuture<StorageResult<SyncPlan>>;
+/// A future that resolves to a [`SyncReport`](struct.SyncReport.html).
+pub type SyncFuture = WrappedFuture<StorageResult<SyncReport>>;
+
+/// Options controlling how files are compared and synchronised.
+#[derive(Clone, Debug)]
+pub struct SyncOptions {
+    mtime_tolerance: Duration,
+    checksum_fallback: bool,
+    delete_extraneous: bool,
+    limiter: Option<ConcurrencyLimiter>,
+}
+
+impl Default for SyncOptions {
+    fn default() -> SyncOptions {
+        SyncOptions::new()
+    }
+}
+
+impl SyncOptions {
+    /// Creates options using the
+    /// [default tolerance](constant.DEFAULT_MTIME_TOLERANCE.html) and no
+    /// checksum fallback.
+    pub fn new() -> SyncOptions {
+        SyncOptions {
+            mtime_tolerance: DEFAULT_MTIME_TOLERANCE,
+            checksum_fallback: false,
+            delete_extraneous: false,
+            limiter: None,
+        }
+    }
+
+    /// Sets how far apart modification times may be while still being
+    /// considered equal.
+    pub fn mtime_tolerance(mut self, tolerance: Duration) -> SyncOptions {
+        self.mtime_tolerance = tolerance;
+        self
+    }
+
+    /// Compares checksums of files that are the same size but whose
+    /// modification times differ or are unknown rather than assuming they
+    /// changed. This requires reading both files.
+    pub fn checksum_fallback(mut self, fallback: bool) -> SyncOptions {
+        self.checksum_fallback = fallback;
+        self
+    }
+
+    /// Deletes files from the target that do not exist in the source when
+    /// synchronising.
+    pub fn delete_extraneous(mut self, delete: bool) -> SyncOptions {
+        self.delete_extraneous = delete;
+        self
+    }
+
+    /// Makes each file written to the target wait for a permit from a
+    /// [`ConcurrencyLimiter`](../struct.ConcurrencyLimiter.html) when
+    /// synchronising. Share the limiter with other operations to bound the
+    /// transfers made in total.
+    pub fn limiter(mut self, limiter: ConcurrencyLimiter) -> SyncOptions {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Gets the tolerance used when comparing modification times.
+    pub fn tolerance(&self) -> Duration {
+        self.mtime_tolerance
+    }
+
+    /// Gets whether checksums are compared when modification times differ.
+    pub fn uses_checksums(&self) -> bool {
+        self.checksum_fallback
+    }
+
+    /// Gets whether extraneous files are deleted from the target.
+    pub fn deletes_extraneous(&self) -> bool {
+        self.delete_extraneous
+    }
+
+    /// Checks whether two modification times are within the tolerance of each
+    /// other. Unknown times never match.
+    pub fn mtimes_match(&self, a: Option<SystemTime>, b: Option<SystemTime>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                let difference = match a.duration_since(b) {
+                    Ok(d) => d,
+                    Err(e) => e.duration(),
+                };
+                difference <= self.mtime_tolerance
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Checks whether the `source` file in the source store needs to be copied
+/// over the `target` file in the target store.
+///
+/// Files of different sizes always need copying. Files with matching
+/// modification times never do. Otherwise, if enabled, the contents are
+/// compared by checksum.
+pub fn needs_update(
+    source: &FileStore,
+    source_object: &Object,
+    target: &FileStore,
+    target_object: &Object,
+    options: &SyncOptions,
+) -> CompareFuture {
+    if source_object.len() != target_object.len() {
+        return CompareFuture::from_value(Ok(true));
+    }
+
+    if options.mtimes_match(source_object.modified(), target_object.modified()) {
+        return CompareFuture::from_value(Ok(false));
+    }
+
+    if !options.uses_checksums() {
+        return CompareFuture::from_value(Ok(true));
+    }
+
+    let source_hash = digest_file::<Sha256>(source, source_object.path());
+    let target_hash = digest_file::<Sha256>(target, target_object.path());
+
+    CompareFuture::from_future(async move { Ok(source_hash.await? != target_hash.await?) })
+}
+
+/// A difference between two prefixes found by [`diff`](fn.diff.html).
+#[derive(Clone, Debug)]
+pub enum DiffEntry {
+    /// A file that only exists under the source prefix.
+    OnlyInSource(Object),
+    /// A file that only exists under the target prefix.
+    OnlyInTarget(Object),
+    /// A file that exists under both prefixes but differs.
+    Different {
+        /// The file under the source prefix.
+        source: Object,
+        /// The file under the target prefix.
+        target: Object,
+        /// Whether the sizes differ.
+        size: bool,
+        /// Whether the modification times differ.
+        mtime: bool,
+    },
+}
+
+/// A change that [`sync`](fn.sync.html) needs to make to the target.
+#[derive(Clone, Debug)]
+pub enum SyncAction {
+    /// Copies a file that does not exist in the target yet.
+    Create {
+        /// The file to copy.
+        source: Object,
+        /// The path that the file will be copied to.
+        target: ObjectPath,
+    },
+    /// Replaces a file in the target that differs from the source.
+    Update {
+        /// The file to copy.
+        source: Object,
+        /// The path that the file will be copied to.
+        target: ObjectPath,
+    },
+    /// Deletes a file from the target that is not in the source.
+    Delete {
+        /// The file to delete.
+        target: Object,
+    },
+}
+
+impl SyncAction {
+    /// Gets the path in the target store that this action changes.
+    pub fn path(&self) -> ObjectPath {
+        match self {
+            SyncAction::Create { target, .. } => target.clone(),
+            SyncAction::Update { target, .. } => target.clone(),
+            SyncAction::Delete { target } => target.path(),
+        }
+    }
+
+    /// Gets the size of the file being copied or deleted.
+    pub fn size(&self) -> u64 {
+        match self {
+            SyncAction::Create { source, .. } => source.len(),
+            SyncAction::Update { source, .. } => source.len(),
+            SyncAction::Delete { target } => target.len(),
+        }
+    }
+}
+
+/// The changes that [`sync`](fn.sync.html) would make, see
+/// [`plan`](fn.plan.html).
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// The changes to make, in the order they will be made.
+    pub actions: Vec<SyncAction>,
+    /// The number of files that are already up to date.
+    pub unchanged: usize,
+    /// Files that could not be compared along with the error.
+    pub failed: Vec<(ObjectPath, TransferError)>,
+}
+
+impl SyncPlan {
+    /// Returns whether there is nothing to change.
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Gets the number of bytes that would be copied.
+    pub fn bytes(&self) -> u64 {
+        self.actions
+            .iter()
+            .filter(|a| match a {
+                SyncAction::Delete { .. } => false,
+                _ => true,
+            })
+            .map(SyncAction::size)
+            .sum()
+    }
+}
+
+/// The changes made by [`sync`](fn.sync.html). Paths are those in the target
+/// store.
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    /// Files that were copied to the target for the first time.
+    pub created: Vec<ObjectPath>,
+    /// Files in the target that were replaced.
+    pub updated: Vec<ObjectPath>,
+    /// Files deleted from the target.
+    pub deleted: Vec<ObjectPath>,
+    /// The number of files that were already up to date.
+    pub unchanged: usize,
+    /// The number of bytes copied.
+    pub bytes: u64,
+    /// Files that could not be synchronised along with the error.
+    pub failed: Vec<(ObjectPath, TransferError)>,
+}
+
+impl SyncReport {
+    /// Returns whether every file was synchronised.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    /// Gets the number of files that were created, updated or deleted.
+    pub fn changes(&self) -> usize {
+        self.created.len() + self.updated.len() + self.deleted.len()
+    }
+}
+
+async fn copy(
+    source: &FileStore,
+    object: &Object,
+    target: &FileStore,
+    path: ObjectPath,
+) -> Result<(), TransferError> {
+    let info = object.as_upload(path).map_err(TransferError::TargetError)?;
+    let stream = source
+        .get_file_stream(object.path())
+        .await
+        .map_err(TransferError::SourceError)?;
+    target.write_file_from_stream(info, stream).await
+}
+
+async fn plan_prefix(
+    source: FileStore,
+    source_prefix: ObjectPath,
+    target: FileStore,
+    target_prefix: ObjectPath,
+    options: SyncOptions,
+) -> StorageResult<SyncPlan> {
+    let mut existing: HashMap<ObjectPath, Object> = list_files(&target, &target_prefix)
+        .await?
+        .into_iter()
+        .map(|o| (o.path(), o))
+        .collect();
+
+    let mut plan = SyncPlan::default();
+    for object in list_files(&source, &source_prefix).await? {
+        let path = target_path(&source_prefix, &target_prefix, &object.path());
+
+        match existing.remove(&path) {
+            Some(current) => {
+                match needs_update(&source, &object, &target, &current, &options).await {
+                    Ok(true) => plan.actions.push(SyncAction::Update {
+                        source: object,
+                        target: path,
+                    }),
+                    Ok(false) => plan.unchanged += 1,
+                    Err(e) => plan.failed.push((path, TransferError::SourceError(e))),
+                }
+            }
+            None => plan.actions.push(SyncAction::Create {
+                source: object,
+                target: path,
+            }),
+        }
+    }
+
+    if options.deletes_extraneous() {
+        let mut extraneous: Vec<Object> = existing.into_iter().map(|(_, o)| o).collect();
+        extraneous.sort();
+
+        for object in extraneous {
+            plan.actions.push(SyncAction::Delete { target: object });
+        }
+    }
+
+    Ok(plan)
+}
+
+async fn apply_plan(source: FileStore, target: FileStore, plan: SyncPlan) -> SyncReport {
+    let mut report = SyncReport {
+        unchanged: plan.unchanged,
+        failed: plan.failed,
+        ..Default::default()
+    };
+
+    for action in plan.actions {
+        match action {
+            SyncAction::Create {
+                source: object,
+                target: path,
+            } => match copy(&source, &object, &target, path.clone()).await {
+                Ok(()) => {
+                    report.bytes += object.len();
+                    report.created.push(path);
+                }
+                Err(e) => report.failed.push((path, e)),
+            },
+            SyncAction::Update {
+                source: object,
+                target: path,
+            } => match copy(&source, &object, &target, path.clone()).await {
+                Ok(()) => {
+                    report.bytes += object.len();
+                    report.updated.push(path);
+                }
+                Err(e) => report.failed.push((path, e)),
+            },
+            SyncAction::Delete { target: object } => {
+                let path = object.path();
+                match target.delete_object(path.clone()).await {
+                    Ok(()) => report.deleted.push(path),
+                    Err(e) => report.failed.push((path, TransferError::TargetError(e))),
+                }
+            }
+        }
+    }
+
+    report
+}
+
+fn relative_path(prefix: &ObjectPath, path: &ObjectPath) -> ObjectPath {
+    target_path(prefix, &ObjectPath::empty(), path)
+}
+
+async fn diff_prefix(
+    source: FileStore,
+    source_prefix: ObjectPath,
+    target: FileStore,
+    target_prefix: ObjectPath,
+    options: SyncOptions,
+) -> StorageResult<Vec<StorageResult<DiffEntry>>> {
+    let (source_files, target_files) = join(
+        list_files(&source, &source_prefix),
+        list_files(&target, &target_prefix),
+    )
+    .await;
+
+    let mut source_files: Vec<(ObjectPath, Object)> = source_files?
+        .into_iter()
+        .map(|o| (relative_path(&source_prefix, &o.path()), o))
+        .collect();
+    let mut target_files: Vec<(ObjectPath, Object)> = target_files?
+        .into_iter()
+        .map(|o| (relative_path(&target_prefix, &o.path()), o))
+        .collect();
+    source_files.sort_by(|a, b| a.0.cmp(&b.0));
+    target_files.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut entries = Vec::new();
+    let mut source_files = source_files.into_iter().peekable();
+    let mut target_files = target_files.into_iter().peekable();
+    loop {
+        let order = match (source_files.peek(), target_files.peek()) {
+            (Some(s), Some(t)) => s.0.cmp(&t.0),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => break,
+        };
+
+        match order {
+            Ordering::Less => {
+                if let Some((_, o)) = source_files.next() {
+                    entries.push(Ok(DiffEntry::OnlyInSource(o)));
+                }
+            }
+            Ordering::Greater => {
+                if let Some((_, o)) = target_files.next() {
+                    entries.push(Ok(DiffEntry::OnlyInTarget(o)));
+                }
+            }
+            Ordering::Equal => {
+                if let (Some((_, s)), Some((_, t))) = (source_files.next(), target_files.next()) {
+                    let size = s.len() != t.len();
+                    let mtime = !options.mtimes_match(s.modified(), t.modified());
+                    if size || mtime {
+                        entries.push(Ok(DiffEntry::Different {
+                            source: s,
+                            target: t,
+                            size,
+                            mtime,
+                        }));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Compares the files under `source_prefix` in the `source` store with those
+/// under `target_prefix` in the `target` store.
+///
+/// Both prefixes are listed at the same time and only differences are
+/// returned, in order of their path relative to the prefixes. Files are
+/// compared by size and modification time, using the tolerance from the
+/// options. Checksums are never compared.
+pub fn diff<S, T>(
+    source: &FileStore,
+    source_prefix: S,
+    target: &FileStore,
+    target_prefix: T,
+    options: SyncOptions,
+) -> DiffStreamFuture
+where
+    S: TryInto<ObjectPath>,
+    S::Error: Into<StorageError>,
+    T: TryInto<ObjectPath>,
+    T::Error: Into<StorageError>,
+{
+    let source_prefix = match source_prefix.try_into() {
+        Ok(p) => p,
+        Err(e) => return DiffStreamFuture::from_value(Err(e.into())),
+    };
+    let target_prefix = match target_prefix.try_into() {
+        Ok(p) => p,
+        Err(e) => return DiffStreamFuture::from_value(Err(e.into())),
+    };
+
+    DiffStreamFuture::from_future(
+        diff_prefix(
+            source.clone(),
+            source_prefix,
+            target.clone(),
+            target_prefix,
+            options,
+        )
+        .map_ok(|entries| DiffStream::from_stream(iter(entries))),
+    )
+}
+
+/// Works out the changes that [`sync`](fn.sync.html) would make without
+/// making them.
+///
+/// The returned [`SyncPlan`](struct.SyncPlan.html) can be shown to a user and
+/// then carried out with [`apply`](fn.apply.html).
+pub fn plan<S, T>(
+    source: &FileStore,
+    source_prefix: S,
+    target: &FileStore,
+    target_prefix: T,
+    options: SyncOptions,
+) -> PlanFuture
+where
+    S: TryInto<ObjectPath>,
+    S::Error: Into<StorageError>,
+    T: TryInto<ObjectPath>,
+    T::Error: Into<StorageError>,
+{
+    let source_prefix = match source_prefix.try_into() {
+        Ok(p) => p,
+        Err(e) => return PlanFuture::from_value(Err(e.into())),
+    };
+    let target_prefix = match target_prefix.try_into() {
+        Ok(p) => p,
+        Err(e) => return PlanFuture::from_value(Err(e.into())),
+    };
+
+    PlanFuture::from_future(plan_prefix(
+        source.clone(),
+        source_prefix,
+        target.clone(),
+        target_prefix,
+        options,
+    ))
+}
+
+/// Carries out the actions in a [`SyncPlan`](struct.SyncPlan.html) created by
+/// [`plan`](fn.plan.html) for the same stores.
+///
+/// Files are copied as they were when the plan was made, anything that has
+/// changed since then is not noticed.
+pub fn apply(source: &FileStore, target: &FileStore, plan: SyncPlan) -> SyncFuture {
+    SyncFuture::from_future(apply_plan(source.clone(), target.clone(), plan).map(Ok))
+}
+
+/// Makes the files under `target_prefix` in the `target` store match those
+/// under `source_prefix` in the `source` store.
+///
+/// Files missing from the target or that [need updating](fn.needs_update.html)
+/// are copied, along with their modification times. Files only in the target
+/// are deleted if the options ask for it. Failing to synchronise a file does
+/// not stop the rest, check the returned [`SyncReport`](struct.SyncReport.html)
+/// for failures.
+pub fn sync<S, T>(
+    source: &FileStore,
+    source_prefix: S,
+    target: &FileStore,
+    target_prefix: T,
+    options: SyncOptions,
+) -> SyncFuture
+where
+    S: TryInto<ObjectPath>,
+    S::Error: Into<StorageError>,
+    T: TryInto<ObjectPath>,
+    T::Error: Into<StorageError>,
+{
+    let source_prefix = match source_prefix.try_into() {
+        Ok(p) => p,
+        Err(e) => return SyncFuture::from_value(Err(e.into())),
+    };
+    let target_prefix = match target_prefix.try_into() {
+        Ok(p) => p,
+        Err(e) => return SyncFuture::from_value(Err(e.into())),
+    };
+
+    let source = source.clone();
+    let target = match options.limiter {
+        Some(ref limiter) => target.clone().with_concurrency_limiter(limiter.clone()),
+        None => target.clone(),
+    };
+    SyncFuture::from_future(async move {
+        let plan = plan_prefix(
+            source.clone(),
+            source_prefix,
+            target.clone(),
+            target_prefix,
+            options,
+        )
+        .await?;
+        Ok(apply_plan(source, target, plan).await)
+    })
+}
@@ -0,0 +1,154 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Key-value tags on objects. Included with the feature "tags".
+//!
+//! Tags classify objects, for example by owner or retention period, for use by
+//! lifecycle rules or searches. Backends that support tagging natively
+//! implement [`TaggingBackend`](trait.TaggingBackend.html) directly. None of
+//! the current backends can change an object's metadata after it is written
+//! so [`FileStore`](../enum.FileStore.html) stores tags in
+//! [sidecar files](struct.SidecarTags.html).
+//!
+//! Tags are not copied, moved or deleted along with their object.
+use std::collections::HashMap;
+
+use futures::stream::{iter, TryStreamExt};
+
+use crate::types::*;
+use crate::FileStore;
+
+/// A set of tags.
+pub type Tags = HashMap<String, String>;
+
+/// A future that resolves to the tags on an object.
+pub type TagsFuture = WrappedFuture<StorageResult<Tags>>;
+
+/// The prefix that [`FileStore`](../enum.FileStore.html) stores sidecar files
+/// under.
+pub const DEFAULT_TAG_PREFIX: &str = ".tags";
+
+/// Storage that can tag objects.
+pub trait TaggingBackend {
+    /// Returns the tags on an object. Objects that have never been tagged have
+    /// no tags.
+    ///
+    /// Fails with a [`NotFound`](../enum.StorageErrorKind.html#variant.NotFound)
+    /// error if the object doesn't exist.
+    fn get_tags(&self, path: ObjectPath) -> TagsFuture;
+
+    /// Replaces all of the tags on an object. An empty set removes all tags.
+    ///
+    /// Fails with a [`NotFound`](../enum.StorageErrorKind.html#variant.NotFound)
+    /// error if the object doesn't exist.
+    fn set_tags(&self, path: ObjectPath, tags: Tags) -> OperationCompleteFuture;
+}
+
+fn is_not_found(error: &StorageError) -> bool {
+    match error.kind() {
+        StorageErrorKind::NotFound(_) => true,
+        _ => false,
+    }
+}
+
+/// Stores tags as JSON files in a separate part of a store.
+///
+/// The tags for `dir/file` are stored in `<prefix>/dir/file.json`. Sidecar
+/// files are ordinary files so they appear when listing the prefix.
+#[derive(Clone, Debug)]
+pub struct SidecarTags {
+    store: FileStore,
+    prefix: ObjectPath,
+}
+
+impl SidecarTags {
+    /// Stores tags for the objects in `store` under
+    /// [`DEFAULT_TAG_PREFIX`](constant.DEFAULT_TAG_PREFIX.html).
+    pub fn new(store: FileStore) -> SidecarTags {
+        SidecarTags {
+            store,
+            prefix: ObjectPath::new(DEFAULT_TAG_PREFIX).unwrap(),
+        }
+    }
+
+    /// Stores sidecar files under a different prefix.
+    pub fn prefix(mut self, prefix: ObjectPath) -> SidecarTags {
+        self.prefix = prefix;
+        self
+    }
+
+    fn sidecar_path(&self, path: &ObjectPath) -> ObjectPath {
+        let mut sidecar = self.prefix.join(path);
+        if let Some(name) = sidecar.pop_part() {
+            sidecar.push_part(&format!("{}.json", name));
+        }
+        sidecar
+    }
+}
+
+impl TaggingBackend for SidecarTags {
+    fn get_tags(&self, path: ObjectPath) -> TagsFuture {
+        let tags = self.clone();
+        TagsFuture::from_future(async move {
+            tags.store.get_object(path.clone()).await?;
+
+            let sidecar = tags.sidecar_path(&path);
+            let data: Vec<Data> = match tags.store.get_file_stream(sidecar).await {
+                Ok(stream) => stream.try_collect().await?,
+                Err(ref e) if is_not_found(e) => return Ok(Tags::new()),
+                Err(e) => return Err(e),
+            };
+
+            serde_json::from_slice(&data.concat())
+                .map_err(|e| error::invalid_data(Some(&e.to_string())))
+        })
+    }
+
+    fn set_tags(&self, path: ObjectPath, new_tags: Tags) -> OperationCompleteFuture {
+        let tags = self.clone();
+        OperationCompleteFuture::from_future(async move {
+            tags.store.get_object(path.clone()).await?;
+
+            let sidecar = tags.sidecar_path(&path);
+            if new_tags.is_empty() {
+                return match tags.store.delete_object(sidecar).await {
+                    Err(ref e) if is_not_found(e) => Ok(()),
+                    result => result,
+                };
+            }
+
+            let json = match serde_json::to_vec(&new_tags) {
+                Ok(j) => j,
+                Err(e) => return Err(error::internal_error(Some(&e.to_string()))),
+            };
+            tags.store
+                .write_file_from_stream(
+                    sidecar,
+                    iter(vec![Ok::<_, StorageError>(Data::from(json))]),
+                )
+                .await
+                .map_err(StorageError::from)
+        })
+    }
+}
+
+impl TaggingBackend for FileStore {
+    fn get_tags(&self, path: ObjectPath) -> TagsFuture {
+        SidecarTags::new(self.clone()).get_tags(path)
+    }
+
+    fn set_tags(&self, path: ObjectPath, tags: Tags) -> OperationCompleteFuture {
+        SidecarTags::new(self.clone()).set_tags(path, tags)
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A conformance test suite for backends. Included with the feature "testing".
+//!
+//! These are the same tests that the built-in backends are tested with and
+//! they can be used to check that a new backend behaves in the same way.
+//!
+//! Each test is run against a fresh fixture of files and directories created
+//! in a temporary directory by [`prepare_test`](fn.prepare_test.html). The
+//! store being tested must present the files beneath the
+//! [`TestContext`](struct.TestContext.html)'s
+//! [`get_fs_root`](struct.TestContext.html#method.get_fs_root) and changes
+//! made through the store must be visible in that directory since that is
+//! where the results are checked. The built-in backends do this by either
+//! accessing the directory directly or by talking to a mock server that
+//! serves it.
+//!
+//! [`build_tests!`](../macro.build_tests.html) generates a `#[test]` for every
+//! test in the suite:
+//!
+//! ```ignore
+//! use file_store::backends::Backend;
+//! use file_store::backends::file::FileBackend;
+//! use file_store::testing::{TestContext, TestResult};
+//! use file_store::{build_tests, FileStore};
+//!
+//! async fn build_fs(context: &TestContext) -> TestResult<(FileStore, ())> {
+//!     Ok((FileBackend::connect(&context.get_fs_root()).await?, ()))
+//! }
+//!
+//! async fn cleanup(_: ()) -> TestResult<()> {
+//!     Ok(())
+//! }
+//!
+//! build_tests!("test1", Backend::File, build_fs, cleanup);
+//! ```
+//!
+//! A [`FixtureBuilder`](struct.FixtureBuilder.html) declares a tree of files
+//! that can be created in any store and the [`strategies`](strategies/index.html)
+//! module generates random fixtures and changes for property based tests. The
+//! [`chaos`](chaos/index.html) module injects failures into any backend to
+//! test how code copes with them.
+#[macro_use]
+mod utils;
+pub mod chaos;
+pub mod events;
+mod fixture;
+pub mod read;
+pub mod settings;
+pub mod strategies;
+pub mod write;
+
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Once;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::FutureExt;
+use tempfile::{tempdir, TempDir};
+use tokio::executor::spawn as tokio_spawn;
+use tokio::runtime::current_thread::Runtime;
+use tokio::sync::oneshot;
+
+pub use self::fixture::*;
+pub use self::utils::{data_stream, stream_iterator, ContentIterator, IteratorStream, MB};
+
+use crate::backends::Backend;
+use crate::types::*;
+use crate::FileStore;
+
+static INIT: Once = Once::new();
+
+/// Initialises logging for tests, only the first call has any effect.
+pub fn init_logging() {
+    INIT.call_once(env_logger::init);
+}
+
+/// The modification time of the fixture's `largefile`.
+#[allow(non_snake_case)]
+pub fn LARGE_FILE_MODIFIED() -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(1_568_259_129)
+}
+
+/// The modification time of the fixture's `smallfile.txt`.
+#[allow(non_snake_case)]
+pub fn SMALL_FILE_MODIFIED() -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(1_603_257_714)
+}
+
+/// The result of a test.
+pub type TestResult<I> = Result<I, TestError>;
+
+/// Why a test failed.
+#[derive(Debug)]
+pub enum TestError {
+    /// An operation failed when it should have succeeded.
+    UnexpectedStorageError(StorageError),
+    /// A transfer failed when it should have succeeded.
+    UnexpectedTransferError(TransferError),
+    /// Setting up or cleaning up after the test failed.
+    HarnessFailure(String),
+    /// The store did not behave as expected.
+    TestFailure(String),
+}
+
+impl TestError {
+    /// Wraps any error as a harness failure.
+    pub fn from_error<E>(error: E) -> TestError
+    where
+        E: fmt::Display,
+    {
+        TestError::HarnessFailure(error.to_string())
+    }
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TestError::UnexpectedStorageError(error) => {
+                write!(f, "Unexpected storage error thrown: {}", error)
+            }
+            TestError::UnexpectedTransferError(error) => match error {
+                TransferError::SourceError(e) => write!(f, "Unexpected source error thrown: {}", e),
+                TransferError::TargetError(e) => write!(f, "Unexpected target error thrown: {}", e),
+            },
+            TestError::HarnessFailure(message) => f.pad(message),
+            TestError::TestFailure(message) => f.pad(message),
+        }
+    }
+}
+
+impl From<StorageError> for TestError {
+    fn from(error: StorageError) -> TestError {
+        TestError::UnexpectedStorageError(error)
+    }
+}
+
+impl From<TransferError> for TestError {
+    fn from(error: TransferError) -> TestError {
+        TestError::UnexpectedTransferError(error)
+    }
+}
+
+trait IntoTestResult<O> {
+    fn into_test_result(self) -> TestResult<O>;
+}
+
+impl<O, E> IntoTestResult<O> for Result<O, E>
+where
+    E: fmt::Display,
+{
+    fn into_test_result(self) -> TestResult<O> {
+        self.map_err(TestError::from_error)
+    }
+}
+
+/// Runs a future on the existing runtime blocking until it completes.
+pub fn run<F>(future: F) -> F::Output
+where
+    F: Future + Send + 'static,
+    F::Output: Send,
+{
+    let mut runtime = Runtime::new().unwrap();
+    runtime.block_on(future)
+}
+
+/// Spawns a future on the existing runtime returning a future that resolves to
+/// its result.
+#[allow(dead_code)]
+pub fn spawn<F>(future: F) -> impl Future<Output = Result<F::Output, oneshot::error::RecvError>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send,
+{
+    let (sender, receiver) = oneshot::channel::<F::Output>();
+    tokio_spawn(future.map(move |r| {
+        if sender.send(r).is_err() {
+            panic!("Failed to complete.")
+        }
+    }));
+    receiver
+}
+
+/// The fixture that a test runs against.
+pub struct TestContext {
+    // Needed to keep the temp dir alive until the context is dropped.
+    _temp: TempDir,
+    root: PathBuf,
+    fs_root: String,
+}
+
+impl TestContext {
+    /// Whether a fixture path is beneath the root that the store was created
+    /// for.
+    pub fn contains(&self, path: &str) -> bool {
+        path != self.fs_root && path.starts_with(&self.fs_root)
+    }
+
+    /// Converts a fixture path to the path within the store.
+    pub fn get_path(&self, path: &str) -> ObjectPath {
+        if !path.starts_with(&self.fs_root) {
+            panic!(
+                "Cannot get a path for {} with a root of {}",
+                path, self.fs_root
+            );
+        }
+
+        let mut target = &path[self.fs_root.len()..];
+        if target.starts_with('/') {
+            target = &target[1..]
+        }
+        ObjectPath::new(target).unwrap()
+    }
+
+    /// Returns the location on disk of a path within the store.
+    pub fn get_target(&self, path: &ObjectPath) -> PathBuf {
+        let mut target = self.root.join(&self.fs_root);
+        for part in path.parts() {
+            target.push(part);
+        }
+        target
+    }
+
+    /// Returns the directory on disk that the store should present.
+    pub fn get_fs_root(&self) -> PathBuf {
+        self.root.join(&self.fs_root)
+    }
+}
+
+/// Declares the files that the tests expect. The tests use a few differently
+/// named directories within this fixture.
+///
+/// Backends that have no real directories see `test1/dir1/maybedir` as a file
+/// rather than a directory.
+pub fn standard_fixture(backend: Backend) -> FixtureBuilder {
+    let mut fixture = FixtureBuilder::new()
+        .file_modified(
+            "test1/dir1/smallfile.txt",
+            "This is quite a short file.",
+            SMALL_FILE_MODIFIED(),
+        )
+        .file_modified(
+            "test1/dir1/largefile",
+            Content::Generated {
+                seed: 0,
+                length: 100 * MB,
+            },
+            LARGE_FILE_MODIFIED(),
+        )
+        .file(
+            "test1/dir1/mediumfile",
+            Content::Generated {
+                seed: 58,
+                length: 5 * MB,
+            },
+        );
+
+    fixture = if backend == Backend::File {
+        fixture
+            .file("test1/dir1/maybedir/foo", Content::Empty)
+            .file("test1/dir1/maybedir/bar", Content::Empty)
+            .file("test1/dir1/maybedir/baz", Content::Empty)
+            .file("test1/dir1/maybedir/foobar/foo", Content::Empty)
+            .file("test1/dir1/maybedir/foobar/bar", Content::Empty)
+    } else {
+        fixture.file("test1/dir1/maybedir", Content::Empty)
+    };
+
+    fixture
+        .file("test1/dir1/dir2/foo", Content::Empty)
+        .file("test1/dir1/dir2/bar", Content::Empty)
+        .file("test1/dir1/dir2/0foo", Content::Empty)
+        .file("test1/dir1/dir2/5diz", Content::Empty)
+        .file("test1/dir1/dir2/1bar", Content::Empty)
+        .file(
+            "test1/dir1/dir2/daz",
+            Content::Generated {
+                seed: 72,
+                length: 300,
+            },
+        )
+        .file("test1/dir1/dir2/hop", Content::Empty)
+        .file("test1/dir1/dir2/yu", Content::Empty)
+}
+
+/// Creates a filesystem used for testing from the
+/// [`standard_fixture`](fn.standard_fixture.html). `test_root` is the
+/// directory within the fixture that the store will be created for.
+pub fn prepare_test(backend: Backend, test_root: &str) -> TestResult<TestContext> {
+    let temp = tempdir().into_test_result()?;
+
+    let context = TestContext {
+        root: PathBuf::from(temp.path()),
+        _temp: temp,
+        fs_root: test_root.to_owned(),
+    };
+
+    standard_fixture(backend).create_in(&context.root)?;
+
+    Ok(context)
+}
+
+/// Generates a `#[test]` that runs a single test from the
+/// [conformance suite](testing/index.html). Included with the feature
+/// "testing".
+///
+/// `$setup` is an async function that takes the
+/// [`TestContext`](testing/struct.TestContext.html) and returns the store to
+/// test along with any context needed by the async `$cleanup` function.
+#[macro_export]
+macro_rules! make_test {
+    ($root:expr, $backend:expr, $pkg:ident, $name:ident, $setup:expr, $cleanup:expr) => {
+        #[test]
+        fn $name() {
+            $crate::testing::init_logging();
+            let result: $crate::testing::TestResult<()> = $crate::testing::run(async {
+                let test_context = $crate::testing::prepare_test($backend, $root)?;
+                let (fs, backend_context) = $setup(&test_context).await?;
+                $crate::testing::$pkg::$name(&fs, &test_context).await?;
+                $cleanup(backend_context).await?;
+                Ok(())
+            });
+
+            match result {
+                Ok(()) => (),
+                Err(error) => panic!(error.to_string()),
+            }
+        }
+    };
+}
+
+/// Generates a `#[test]` for every test in the
+/// [conformance suite](testing/index.html). Included with the feature
+/// "testing".
+///
+/// See [`make_test!`](macro.make_test.html) for the arguments.
+#[macro_export]
+macro_rules! build_tests {
+    ($root:expr, $backend:expr, $setup:expr, $cleanup:expr) => {
+        $crate::make_test!($root, $backend, read, test_list_objects, $setup, $cleanup);
+        $crate::make_test!(
+            $root,
+            $backend,
+            read,
+            test_list_objects_with_options,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            read,
+            test_list_objects_page,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!($root, $backend, read, test_list_directory, $setup, $cleanup);
+        $crate::make_test!($root, $backend, read, test_get_object, $setup, $cleanup);
+        $crate::make_test!(
+            $root,
+            $backend,
+            read,
+            test_get_file_stream,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            read,
+            test_get_file_stream_range,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!($root, $backend, read, test_open_file, $setup, $cleanup);
+        $crate::make_test!(
+            $root,
+            $backend,
+            read,
+            test_simultaneous_download,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!($root, $backend, read, test_read_ahead, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_copy_file, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_move_file, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_delete_object, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_delete_many, $setup, $cleanup);
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_create_directory,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!($root, $backend, write, test_metadata, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_content_type, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_write_mode, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_verified_read, $setup, $cleanup);
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_write_file_from_stream,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_write_file_from_source,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_write_returning,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!($root, $backend, write, test_progress, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_cancellation, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_file_writer, $setup, $cleanup);
+        $crate::make_test!($root, $backend, write, test_write_bytes, $setup, $cleanup);
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_local_transfer,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_move_onto_itself,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_move_write_modes,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!($root, $backend, write, test_move_metadata, $setup, $cleanup);
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_write_if_match,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            write,
+            test_racing_writes_if_match,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            settings,
+            test_strict_overwrites,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            settings,
+            test_cleanup_on_failure,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            settings,
+            test_directory_semantics,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            settings,
+            test_path_policy,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            settings,
+            test_concurrency_limiter,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!(
+            $root,
+            $backend,
+            settings,
+            test_copy_prefix_limiter,
+            $setup,
+            $cleanup
+        );
+        $crate::make_test!($root, $backend, events, test_events, $setup, $cleanup);
+        $crate::make_test!(
+            $root,
+            $backend,
+            events,
+            test_storage_events,
+            $setup,
+            $cleanup
+        );
+    };
+}
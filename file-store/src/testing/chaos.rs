@@ -0,0 +1,449 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! A backend that injects failures into another.
+//!
+//! [`ChaosBackend`](struct.ChaosBackend.html) wraps any
+//! [`StorageBackend`](../../trait.StorageBackend.html) and applies
+//! [faults](enum.Fault.html) to the operations that match its
+//! [rules](struct.Rule.html). It can fail the Nth request, cut off a download
+//! or upload part way through, slow operations down or silently truncate the
+//! data, which makes retry and resume logic testable against any backend.
+//!
+//! Transfers can also be slowed down with a fixed delay per chunk of data or a
+//! limited throughput to exercise timeouts, progress reporting and throttling.
+//! The delays are calculated from the size of each chunk rather than measured
+//! so they behave the same on every run.
+//!
+//! ```ignore
+//! use file_store::testing::chaos::{ChaosBackend, Fault, Rule, Trigger};
+//!
+//! let chaos = ChaosBackend::new(store);
+//! // The second download is disconnected after 100 bytes.
+//! chaos.inject(Rule::new(Trigger::Nth(2), Fault::Disconnect(100)).operation("get_file_stream"));
+//! ```
+use std::cmp::min;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::future::Future;
+use futures::stream::StreamExt;
+use tokio_timer::delay_for;
+
+use crate::backends::Backend;
+use crate::types::*;
+use crate::StorageBackend;
+
+/// A failure to inject.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Fault {
+    /// Fails the operation with an error of the given kind.
+    Error(StorageErrorKind),
+    /// Delays the operation.
+    Latency(Duration),
+    /// Fails the data being downloaded or uploaded with a
+    /// [`ConnectionClosed`](../../enum.StorageErrorKind.html#variant.ConnectionClosed)
+    /// error once the given number of bytes have passed.
+    Disconnect(u64),
+    /// Ends the data being downloaded or uploaded without an error once the
+    /// given number of bytes have passed.
+    Truncate(u64),
+    /// Delays every chunk of data downloaded or uploaded.
+    ChunkDelay(Duration),
+    /// Limits the data downloaded or uploaded to the given number of bytes
+    /// per second. Each chunk is delayed by the time it would take to
+    /// transfer at that rate so the timing doesn't depend on the system's
+    /// clock resolution or load.
+    Throughput(u64),
+}
+
+/// When a [rule](struct.Rule.html) applies, counted over the operations that
+/// match it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    /// Only the Nth matching operation, counting from 1.
+    Nth(usize),
+    /// Every Nth matching operation.
+    Every(usize),
+    /// Every matching operation from the Nth onwards.
+    From(usize),
+    /// Every matching operation.
+    Always,
+}
+
+impl Trigger {
+    fn fires(self, count: usize) -> bool {
+        match self {
+            Trigger::Nth(n) => count == n,
+            Trigger::Every(n) => n > 0 && count % n == 0,
+            Trigger::From(n) => count >= n,
+            Trigger::Always => true,
+        }
+    }
+}
+
+/// Applies a fault to some operations.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    operation: Option<&'static str>,
+    trigger: Trigger,
+    fault: Fault,
+    seen: usize,
+}
+
+impl Rule {
+    /// Creates a rule that applies `fault` to any operation when `trigger`
+    /// fires.
+    pub fn new(trigger: Trigger, fault: Fault) -> Rule {
+        Rule {
+            operation: None,
+            trigger,
+            fault,
+            seen: 0,
+        }
+    }
+
+    /// Only matches the named operation, the name of the
+    /// [`StorageBackend`](../../trait.StorageBackend.html) method, for
+    /// example "get_file_stream" or "write_file_from_stream".
+    pub fn operation(mut self, operation: &'static str) -> Rule {
+        self.operation = Some(operation);
+        self
+    }
+
+    fn check(&mut self, operation: &'static str) -> Option<Fault> {
+        match self.operation {
+            Some(op) if op != operation => return None,
+            _ => (),
+        }
+
+        self.seen += 1;
+        if self.trigger.fires(self.seen) {
+            Some(self.fault.clone())
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ChaosState {
+    rules: Vec<Rule>,
+    operations: usize,
+    injected: usize,
+}
+
+/// The faults applied to a single operation.
+#[derive(Clone, Debug, Default)]
+struct Faults {
+    error: Option<StorageErrorKind>,
+    latency: Duration,
+    cut_off: Option<(u64, bool)>,
+    chunk_delay: Duration,
+    throughput: Option<u64>,
+}
+
+impl Faults {
+    fn injected_error(&self) -> Option<StorageError> {
+        self.error
+            .clone()
+            .map(|kind| StorageError::new(kind, Some("Injected failure.")))
+    }
+
+    async fn before<T, E, F>(self, error: fn(StorageError) -> E, operation: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        if self.latency > Duration::from_secs(0) {
+            delay_for(self.latency).await;
+        }
+
+        if let Some(e) = self.injected_error() {
+            return Err(error(e));
+        }
+
+        operation.await
+    }
+
+    /// The time to wait before passing on a chunk of data.
+    fn pace(&self, length: usize) -> Duration {
+        let transfer = match self.throughput {
+            Some(rate) if rate > 0 => {
+                let nanos = length as u128 * 1_000_000_000 / u128::from(rate);
+                Duration::from_nanos(nanos as u64)
+            }
+            _ => Duration::from_secs(0),
+        };
+
+        self.chunk_delay + transfer
+    }
+
+    /// Slows down or cuts off a stream of data if requested.
+    fn stream(&self, stream: DataStream) -> DataStream {
+        if self.cut_off.is_none()
+            && self.chunk_delay == Duration::from_secs(0)
+            && self.throughput.is_none()
+        {
+            return stream;
+        }
+
+        let (remaining, fail) = match self.cut_off {
+            Some((limit, fail)) => (Some(limit), fail),
+            None => (None, false),
+        };
+
+        let faults = self.clone();
+        let state = (stream, remaining, false);
+        DataStream::from_stream(futures::stream::unfold(
+            state,
+            move |(mut stream, remaining, done)| {
+                let faults = faults.clone();
+                async move {
+                    if done {
+                        return None;
+                    }
+
+                    if remaining == Some(0) {
+                        if fail {
+                            let error = error::connection_closed(Some("Injected disconnection."));
+                            return Some((Err(error), (stream, remaining, true)));
+                        }
+                        return None;
+                    }
+
+                    match stream.next().await {
+                        Some(Ok(mut data)) => {
+                            let remaining = remaining.map(|r| {
+                                let length = min(data.len() as u64, r);
+                                data.truncate(length as usize);
+                                r - length
+                            });
+
+                            let pause = faults.pace(data.len());
+                            if pause > Duration::from_secs(0) {
+                                delay_for(pause).await;
+                            }
+                            Some((Ok(data), (stream, remaining, false)))
+                        }
+                        Some(Err(e)) => Some((Err(e), (stream, remaining, true))),
+                        None => None,
+                    }
+                }
+            },
+        ))
+    }
+}
+
+/// A [`StorageBackend`](../../trait.StorageBackend.html) that injects
+/// failures into the operations of another.
+///
+/// Clones share the same rules.
+#[derive(Debug)]
+pub struct ChaosBackend<B> {
+    inner: Arc<B>,
+    state: Arc<Mutex<ChaosState>>,
+}
+
+impl<B> Clone for ChaosBackend<B> {
+    fn clone(&self) -> ChaosBackend<B> {
+        ChaosBackend {
+            inner: self.inner.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<B> ChaosBackend<B>
+where
+    B: StorageBackend,
+{
+    /// Wraps a backend, initially without injecting any failures.
+    pub fn new(inner: B) -> ChaosBackend<B> {
+        ChaosBackend {
+            inner: Arc::new(inner),
+            state: Default::default(),
+        }
+    }
+
+    /// Gets the wrapped backend.
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    /// Adds a rule. Every rule that fires for an operation applies.
+    pub fn inject(&self, rule: Rule) {
+        if let Ok(mut state) = self.state.lock() {
+            state.rules.push(rule);
+        }
+    }
+
+    /// Removes all the rules.
+    pub fn clear(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.rules.clear();
+        }
+    }
+
+    /// Gets the number of operations started.
+    pub fn operations(&self) -> usize {
+        self.state.lock().map(|s| s.operations).unwrap_or_default()
+    }
+
+    /// Gets the number of operations that had faults applied.
+    pub fn injected(&self) -> usize {
+        self.state.lock().map(|s| s.injected).unwrap_or_default()
+    }
+
+    fn faults(&self, operation: &'static str) -> Faults {
+        let mut faults = Faults::default();
+        let mut state = match self.state.lock() {
+            Ok(s) => s,
+            Err(_) => return faults,
+        };
+
+        state.operations += 1;
+        let fired: Vec<Fault> = state
+            .rules
+            .iter_mut()
+            .filter_map(|rule| rule.check(operation))
+            .collect();
+        if !fired.is_empty() {
+            state.injected += 1;
+        }
+
+        for fault in fired {
+            match fault {
+                Fault::Error(kind) => faults.error = Some(kind),
+                Fault::Latency(latency) => faults.latency += latency,
+                Fault::Disconnect(bytes) => faults.cut_off = Some((bytes, true)),
+                Fault::Truncate(bytes) => faults.cut_off = Some((bytes, false)),
+                Fault::ChunkDelay(delay) => faults.chunk_delay += delay,
+                Fault::Throughput(rate) => {
+                    faults.throughput = Some(match faults.throughput {
+                        Some(existing) => min(existing, rate),
+                        None => rate,
+                    })
+                }
+            }
+        }
+
+        faults
+    }
+
+    fn storage<T, F>(&self, operation: &'static str, call: F) -> WrappedFuture<StorageResult<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&B) -> WrappedFuture<StorageResult<T>>,
+    {
+        let faults = self.faults(operation);
+        let future = call(&self.inner);
+        WrappedFuture::from_future(faults.before(|e| e, future))
+    }
+
+    fn transfer<F>(
+        &self,
+        operation: &'static str,
+        call: F,
+    ) -> WrappedFuture<Result<(), TransferError>>
+    where
+        F: FnOnce(&B, &Faults) -> WrappedFuture<Result<(), TransferError>>,
+    {
+        let faults = self.faults(operation);
+        let future = call(&self.inner, &faults);
+        WrappedFuture::from_future(faults.before(TransferError::TargetError, future))
+    }
+
+    fn download<F>(&self, operation: &'static str, call: F) -> DataStreamFuture
+    where
+        F: FnOnce(&B) -> DataStreamFuture,
+    {
+        let faults = self.faults(operation);
+        let future = call(&self.inner);
+        DataStreamFuture::from_future(async move {
+            let stream = faults.clone().before(|e| e, future).await?;
+            Ok(faults.stream(stream))
+        })
+    }
+}
+
+impl<B> StorageBackend for ChaosBackend<B>
+where
+    B: StorageBackend,
+{
+    fn backend_type(&self) -> Backend {
+        self.inner.backend_type()
+    }
+
+    fn list_objects(&self, prefix: ObjectPath) -> ObjectStreamFuture {
+        self.storage("list_objects", |b| b.list_objects(prefix))
+    }
+
+    fn list_objects_with_options(
+        &self,
+        prefix: ObjectPath,
+        options: ListOptions,
+    ) -> ObjectStreamFuture {
+        self.storage("list_objects_with_options", |b| {
+            b.list_objects_with_options(prefix, options)
+        })
+    }
+
+    fn lists_in_order(&self) -> bool {
+        self.inner.lists_in_order()
+    }
+
+    fn list_directory(&self, dir: ObjectPath) -> ObjectStreamFuture {
+        self.storage("list_directory", |b| b.list_directory(dir))
+    }
+
+    fn get_object(&self, path: ObjectPath) -> ObjectFuture {
+        self.storage("get_object", |b| b.get_object(path))
+    }
+
+    fn get_file_stream(&self, path: ObjectPath) -> DataStreamFuture {
+        self.download("get_file_stream", |b| b.get_file_stream(path))
+    }
+
+    fn get_file_stream_range(
+        &self,
+        path: ObjectPath,
+        offset: u64,
+        length: Option<u64>,
+    ) -> DataStreamFuture {
+        self.download("get_file_stream_range", |b| {
+            b.get_file_stream_range(path, offset, length)
+        })
+    }
+
+    fn copy_file(&self, source: ObjectPath, target: UploadInfo) -> CopyCompleteFuture {
+        self.transfer("copy_file", |b, _| b.copy_file(source, target))
+    }
+
+    fn move_file(&self, source: ObjectPath, target: UploadInfo) -> MoveCompleteFuture {
+        self.transfer("move_file", |b, _| b.move_file(source, target))
+    }
+
+    fn delete_object(&self, path: ObjectPath) -> OperationCompleteFuture {
+        self.storage("delete_object", |b| b.delete_object(path))
+    }
+
+    fn create_directory(&self, path: ObjectPath) -> OperationCompleteFuture {
+        self.storage("create_directory", |b| b.create_directory(path))
+    }
+
+    fn write_file_from_stream(&self, info: UploadInfo, stream: DataStream) -> WriteCompleteFuture {
+        self.transfer("write_file_from_stream", |b, faults| {
+            b.write_file_from_stream(info, faults.stream(stream))
+        })
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests for the events that a store records.
+use futures::stream::{Stream, StreamExt};
+
+use super::*;
+
+use crate::events::*;
+use crate::types::*;
+use crate::FileStore;
+
+async fn next_event<S>(events: &mut S) -> TestResult<S::Item>
+where
+    S: Stream + Unpin,
+{
+    match events.next().await {
+        Some(event) => Ok(event),
+        None => test_fail!("The event stream ended early."),
+    }
+}
+
+/// Checks that copies, moves, deletes and writes are recorded once they
+/// complete.
+pub async fn test_events(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let mut events = fs.events();
+
+    let path = context.get_path("test1/dir1/recorded.txt");
+    let target = context.get_path("test1/dir1/copied.txt");
+
+    fs.write_bytes(path.clone(), "Some data.").await?;
+    fs.copy_file(path.clone(), target.clone()).await?;
+    fs.delete_object(target.clone()).await?;
+    if fs.delete_object(target.clone()).await.is_ok() {
+        test_fail!("Should not have deleted {} twice.", target);
+    }
+
+    // Reads aren't recorded.
+    fs.get_object(path.clone()).await?;
+
+    fs.move_file(path.clone(), target.clone()).await?;
+
+    let event = next_event(&mut events).await?;
+    test_assert_eq!(event.operation, Operation::Write);
+    test_assert_eq!(event.backend, fs.backend_type());
+    test_assert_eq!(&event.path, &path);
+    test_assert_eq!(event.target, None);
+    test_assert_eq!(event.result, Ok(()));
+
+    let event = next_event(&mut events).await?;
+    test_assert_eq!(event.operation, Operation::Copy);
+    test_assert_eq!(&event.path, &path);
+    test_assert_eq!(event.target, Some(target.clone()));
+    test_assert_eq!(event.result, Ok(()));
+
+    let event = next_event(&mut events).await?;
+    test_assert_eq!(event.operation, Operation::Delete);
+    test_assert_eq!(&event.path, &target);
+    test_assert_eq!(event.result, Ok(()));
+
+    let event = next_event(&mut events).await?;
+    test_assert_eq!(event.operation, Operation::Delete);
+    test_assert_eq!(
+        event.result,
+        Err(StorageErrorKind::NotFound(target.clone())),
+        "Should have recorded the failure."
+    );
+
+    let event = next_event(&mut events).await?;
+    test_assert_eq!(event.operation, Operation::Move);
+    test_assert_eq!(&event.path, &path);
+    test_assert_eq!(event.target, Some(target));
+    test_assert_eq!(event.result, Ok(()));
+
+    Ok(())
+}
+
+/// Checks that every operation is reported as it starts and again when it
+/// finishes or fails.
+pub async fn test_storage_events(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let mut events = fs.subscribe_events();
+
+    let path = context.get_path("test1/dir1/recorded.txt");
+    let missing = context.get_path("test1/dir1/missing.txt");
+    fs.write_bytes(path.clone(), "Some data.").await?;
+    test_assert_eq!(fs.read_to_bytes(path.clone()).await?.len(), 10);
+    if fs.get_object(missing.clone()).await.is_ok() {
+        test_fail!("Should not have found {}.", missing);
+    }
+
+    let started = next_event(&mut events).await?;
+    test_assert_eq!(started.phase, EventPhase::Started);
+    test_assert_eq!(started.operation, "write_file");
+    test_assert_eq!(started.backend, fs.backend_type());
+    test_assert_eq!(&started.path, &path);
+    test_assert_eq!(started.duration, None);
+
+    let finished = next_event(&mut events).await?;
+    test_assert_eq!(finished.id, started.id);
+    test_assert_eq!(finished.phase, EventPhase::Finished);
+    test_assert!(finished.duration.is_some());
+    test_assert_eq!(
+        finished.bytes,
+        Some(10),
+        "Should have counted the data written."
+    );
+
+    let started = next_event(&mut events).await?;
+    test_assert_eq!(started.operation, "get_file_stream");
+    let finished = next_event(&mut events).await?;
+    test_assert_eq!(finished.id, started.id);
+    test_assert_eq!(finished.phase, EventPhase::Finished);
+    test_assert_eq!(
+        finished.bytes,
+        Some(10),
+        "Should have counted the data read."
+    );
+
+    let started = next_event(&mut events).await?;
+    test_assert_eq!(started.operation, "get_object");
+    test_assert_eq!(&started.path, &missing);
+    let failed = next_event(&mut events).await?;
+    test_assert_eq!(failed.id, started.id);
+    test_assert_eq!(
+        failed.phase,
+        EventPhase::Failed(StorageErrorKind::NotFound(missing))
+    );
+    test_assert_eq!(failed.bytes, None);
+
+    Ok(())
+}
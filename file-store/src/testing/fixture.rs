@@ -0,0 +1,209 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use filetime::{set_file_mtime, FileTime};
+use futures::stream::{empty, iter};
+
+use crate::types::*;
+use crate::FileStore;
+
+use super::utils::{stream_iterator, ContentIterator, MB};
+use super::{IntoTestResult, TestResult};
+
+/// The content of a file in a fixture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Content {
+    /// An empty file.
+    Empty,
+    /// The given bytes.
+    Bytes(Vec<u8>),
+    /// `length` bytes generated by a
+    /// [`ContentIterator`](struct.ContentIterator.html) with the given seed.
+    Generated {
+        /// The seed for the generator.
+        seed: u8,
+        /// The number of bytes to generate.
+        length: u64,
+    },
+}
+
+impl Content {
+    /// The length of the content in bytes.
+    pub fn len(&self) -> u64 {
+        match self {
+            Content::Empty => 0,
+            Content::Bytes(bytes) => bytes.len() as u64,
+            Content::Generated { length, .. } => *length,
+        }
+    }
+
+    /// Whether the content is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the bytes of the content.
+    pub fn bytes(&self) -> Box<dyn Iterator<Item = u8> + Send> {
+        match self {
+            Content::Empty => Box::new(std::iter::empty()),
+            Content::Bytes(bytes) => Box::new(bytes.clone().into_iter()),
+            Content::Generated { seed, length } => Box::new(ContentIterator::new(*seed, *length)),
+        }
+    }
+
+    fn into_stream(self) -> DataStream {
+        match self {
+            Content::Empty => DataStream::from_stream(empty()),
+            Content::Bytes(bytes) => DataStream::from_stream(iter(vec![Ok(Data::from(bytes))])),
+            Content::Generated { seed, length } => DataStream::from_stream(stream_iterator(
+                ContentIterator::new(seed, length),
+                MB as usize,
+            )),
+        }
+    }
+}
+
+impl From<&str> for Content {
+    fn from(text: &str) -> Content {
+        Content::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Content {
+    fn from(bytes: Vec<u8>) -> Content {
+        Content::Bytes(bytes)
+    }
+}
+
+/// A file declared in a [`FixtureBuilder`](struct.FixtureBuilder.html).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixtureFile {
+    path: String,
+    content: Content,
+    modified: Option<SystemTime>,
+}
+
+impl FixtureFile {
+    /// The path of the file, relative to wherever the fixture is created.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The content of the file.
+    pub fn content(&self) -> &Content {
+        &self.content
+    }
+
+    /// The modification time of the file if one was given.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+}
+
+/// Declares a tree of files that can be created in any
+/// [`FileStore`](../enum.FileStore.html) or in a local directory.
+///
+/// Directories are created as needed to hold the files.
+#[derive(Clone, Debug, Default)]
+pub struct FixtureBuilder {
+    files: Vec<FixtureFile>,
+}
+
+impl FixtureBuilder {
+    /// Creates an empty fixture.
+    pub fn new() -> FixtureBuilder {
+        Default::default()
+    }
+
+    /// Adds a file with the given content.
+    pub fn file<C: Into<Content>>(self, path: &str, content: C) -> FixtureBuilder {
+        self.add(path, content.into(), None)
+    }
+
+    /// Adds a file with the given content and modification time.
+    pub fn file_modified<C: Into<Content>>(
+        self,
+        path: &str,
+        content: C,
+        modified: SystemTime,
+    ) -> FixtureBuilder {
+        self.add(path, content.into(), Some(modified))
+    }
+
+    fn add(mut self, path: &str, content: Content, modified: Option<SystemTime>) -> FixtureBuilder {
+        self.files.push(FixtureFile {
+            path: path.to_owned(),
+            content,
+            modified,
+        });
+        self
+    }
+
+    /// The declared files.
+    pub fn files(&self) -> &[FixtureFile] {
+        &self.files
+    }
+
+    /// Creates the files beneath a local directory.
+    pub fn create_in(&self, dir: &Path) -> TestResult<()> {
+        for file in &self.files {
+            let mut target = dir.to_owned();
+            target.extend(file.path.split('/'));
+            if let Some(parent) = target.parent() {
+                create_dir_all(parent).into_test_result()?;
+            }
+
+            let mut writer = BufWriter::new(File::create(&target).into_test_result()?);
+            let mut buffer = Vec::with_capacity(64 * 1024);
+            for b in file.content.bytes() {
+                buffer.push(b);
+                if buffer.len() == buffer.capacity() {
+                    writer.write_all(&buffer).into_test_result()?;
+                    buffer.clear();
+                }
+            }
+            writer.write_all(&buffer).into_test_result()?;
+            writer.flush().into_test_result()?;
+
+            if let Some(modified) = file.modified {
+                set_file_mtime(&target, FileTime::from_system_time(modified)).into_test_result()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates the files beneath a prefix in a store. Modification times are
+    /// only set if the store's backend supports it.
+    pub async fn build(&self, store: &FileStore, prefix: &ObjectPath) -> TestResult<()> {
+        for file in &self.files {
+            let info = UploadInfo {
+                path: prefix.join(&ObjectPath::new(&file.path)?),
+                modified: file.modified,
+                ..Default::default()
+            };
+
+            store
+                .write_file_from_stream(info, file.content.clone().into_stream())
+                .await?;
+        }
+
+        Ok(())
+    }
+}
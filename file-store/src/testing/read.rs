@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! Tests for reading from a store.
 use std::fs::symlink_metadata;
 use std::future::Future;
 use std::iter::empty;
@@ -24,7 +25,8 @@ use futures::stream::{FuturesUnordered, StreamExt, TryStreamExt};
 use super::utils::*;
 use super::*;
 
-use file_store::*;
+use crate::types::*;
+use crate::FileStore;
 
 const MAX_TIME_DIFFERENCE: u64 = 1;
 
@@ -105,6 +107,7 @@ fn test_file_matches(target: &Path, object: Object) -> TestResult<()> {
     Ok(())
 }
 
+/// Checks that `list_objects` finds everything beneath a prefix.
 pub async fn test_list_objects(fs: &FileStore, context: &TestContext) -> TestResult<()> {
     async fn test_list<'a>(
         fs: &'a FileStore,
@@ -205,6 +208,135 @@ pub async fn test_list_objects(fs: &FileStore, context: &TestContext) -> TestRes
     Ok(())
 }
 
+/// Checks that listing options limit what `list_objects` returns.
+pub async fn test_list_objects_with_options(
+    fs: &FileStore,
+    context: &TestContext,
+) -> TestResult<()> {
+    async fn test_list<'a>(
+        fs: &'a FileStore,
+        context: &'a TestContext,
+        path: &'static str,
+        options: ListOptions,
+        files: Vec<&'static str>,
+    ) -> TestResult<()> {
+        let mut expected_paths: Vec<ObjectPath> =
+            files.iter().map(|path| context.get_path(path)).collect();
+
+        let mut results = fs
+            .list_objects_with_options(context.get_path(path), options)
+            .await?
+            .map_ok(|o| o.path())
+            .try_collect::<Vec<ObjectPath>>()
+            .await?;
+        results.sort();
+        expected_paths.sort();
+
+        test_assert_eq!(results, expected_paths, "Should have seen the right paths.");
+
+        Ok(())
+    }
+
+    let mut shallow = vec![
+        "test1/dir1/largefile",
+        "test1/dir1/mediumfile",
+        "test1/dir1/smallfile.txt",
+    ];
+
+    if fs.backend_type() != Backend::File {
+        shallow.push("test1/dir1/maybedir");
+    }
+
+    test_list(
+        fs,
+        context,
+        "test1/dir1/",
+        ListOptions::new().max_depth(1).include_directories(false),
+        shallow,
+    )
+    .await?;
+
+    test_list(
+        fs,
+        context,
+        "test1/dir1/dir2/",
+        ListOptions::new().start_after(context.get_path("test1/dir1/dir2/bar")),
+        vec![
+            "test1/dir1/dir2/daz",
+            "test1/dir1/dir2/foo",
+            "test1/dir1/dir2/hop",
+            "test1/dir1/dir2/yu",
+        ],
+    )
+    .await?;
+
+    let results = fs
+        .list_objects_with_options(
+            context.get_path("test1/dir1/dir2/"),
+            ListOptions::new().max_results(3),
+        )
+        .await?
+        .try_collect::<Vec<Object>>()
+        .await?;
+    test_assert_eq!(results.len(), 3, "Should have seen only 3 results.");
+
+    for object in results {
+        test_assert!(
+            object
+                .path()
+                .starts_with(&context.get_path("test1/dir1/dir2/")),
+            "Should only have seen objects beneath the prefix."
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that a listing can be paged through using continuation tokens.
+pub async fn test_list_objects_page(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let mut expected: Vec<ObjectPath> = vec![
+        "test1/dir1/dir2/0foo",
+        "test1/dir1/dir2/1bar",
+        "test1/dir1/dir2/5diz",
+        "test1/dir1/dir2/bar",
+        "test1/dir1/dir2/daz",
+        "test1/dir1/dir2/foo",
+        "test1/dir1/dir2/hop",
+        "test1/dir1/dir2/yu",
+    ]
+    .iter()
+    .map(|path| context.get_path(path))
+    .collect();
+    expected.sort();
+
+    let mut seen: Vec<ObjectPath> = Vec::new();
+    let mut sizes: Vec<usize> = Vec::new();
+    let mut token = ListToken::start();
+    loop {
+        let page = fs
+            .list_objects_page(context.get_path("test1/dir1/dir2/"), token, 3)
+            .await?;
+        sizes.push(page.objects.len());
+        seen.extend(page.objects.iter().map(|o| o.path()));
+
+        match page.next {
+            // Tokens should survive being persisted as strings.
+            Some(next) => token = next.to_string().parse()?,
+            None => break,
+        }
+    }
+
+    test_assert_eq!(
+        sizes,
+        vec![3, 3, 2],
+        "Should have seen the right page sizes."
+    );
+    test_assert_eq!(seen, expected, "Should have seen every object in order.");
+
+    Ok(())
+}
+
+/// Checks that `list_directory` finds only the direct children of a directory.
 pub async fn test_list_directory(fs: &FileStore, context: &TestContext) -> TestResult<()> {
     async fn test_list<'a>(
         fs: &'a FileStore,
@@ -281,6 +413,8 @@ pub async fn test_list_directory(fs: &FileStore, context: &TestContext) -> TestR
     Ok(())
 }
 
+/// Checks that `get_object` returns correct information or fails for missing
+/// objects.
 pub async fn test_get_object(fs: &FileStore, context: &TestContext) -> TestResult<()> {
     async fn test_pass(fs: &FileStore, context: &TestContext, path: &str) -> TestResult<()> {
         let path = context.get_path(path);
@@ -325,53 +459,61 @@ pub async fn test_get_object(fs: &FileStore, context: &TestContext) -> TestResul
     Ok(())
 }
 
+async fn test_stream_matches<I>(stream: DataStream, mut data: I) -> TestResult<()>
+where
+    I: Iterator<Item = u8>,
+{
+    let mut stream = Box::pin(stream);
+
+    let mut pos: usize = 0;
+    loop {
+        let buf = stream.next().await;
+        match buf {
+            Some(Ok(buffer)) => {
+                for x in 0..buffer.len() {
+                    match data.next() {
+                        Some(b) => test_assert_eq!(
+                            buffer[x],
+                            b,
+                            "Data should have matched at position {}.",
+                            pos
+                        ),
+                        None => test_fail!("Ran out of expected data as position {}.", pos),
+                    }
+                    pos += 1;
+                }
+            }
+            Some(Err(e)) => {
+                return Err(e.into());
+            }
+            None => {
+                test_assert_eq!(
+                    data.next(),
+                    None,
+                    "Expected data should have ended at position {}.",
+                    pos
+                );
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that `get_file_stream` returns the correct data.
 pub async fn test_get_file_stream(fs: &FileStore, context: &TestContext) -> TestResult<()> {
     async fn test_pass<I>(
         fs: &FileStore,
         context: &TestContext,
         path: &str,
-        mut data: I,
+        data: I,
     ) -> TestResult<()>
     where
         I: Iterator<Item = u8>,
     {
         let target = context.get_path(path);
-        let mut stream = Box::pin(fs.get_file_stream(target).await?);
-
-        let mut pos: usize = 0;
-        loop {
-            let buf = stream.next().await;
-            match buf {
-                Some(Ok(buffer)) => {
-                    for x in 0..buffer.len() {
-                        match data.next() {
-                            Some(b) => test_assert_eq!(
-                                buffer[x],
-                                b,
-                                "Data should have matched at position {}.",
-                                pos
-                            ),
-                            None => test_fail!("Ran out of expected data as position {}.", pos),
-                        }
-                        pos += 1;
-                    }
-                }
-                Some(Err(e)) => {
-                    return Err(e.into());
-                }
-                None => {
-                    test_assert_eq!(
-                        data.next(),
-                        None,
-                        "Expected data should have ended at position {}.",
-                        pos
-                    );
-                    break;
-                }
-            }
-        }
-
-        Ok(())
+        test_stream_matches(fs.get_file_stream(target).await?, data).await
     }
 
     async fn test_fail(fs: &FileStore, context: &TestContext, path: &str) -> TestResult<()> {
@@ -416,6 +558,80 @@ pub async fn test_get_file_stream(fs: &FileStore, context: &TestContext) -> Test
     Ok(())
 }
 
+/// Checks that `get_file_stream_range` returns the correct part of a file.
+pub async fn test_get_file_stream_range(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    async fn test_pass(
+        fs: &FileStore,
+        context: &TestContext,
+        offset: u64,
+        length: Option<u64>,
+        expected: usize,
+    ) -> TestResult<()> {
+        let target = context.get_path("test1/dir1/dir2/daz");
+        let stream = fs.get_file_stream_range(target, offset, length).await?;
+        let data = ContentIterator::new(72, 300)
+            .skip(offset as usize)
+            .take(expected);
+        test_stream_matches(stream, data).await
+    }
+
+    test_pass(fs, context, 0, None, 300).await?;
+    test_pass(fs, context, 0, Some(300), 300).await?;
+    test_pass(fs, context, 10, Some(50), 50).await?;
+    test_pass(fs, context, 250, None, 50).await?;
+    test_pass(fs, context, 290, Some(100), 10).await?;
+    test_pass(fs, context, 100, Some(0), 0).await?;
+    test_pass(fs, context, 300, None, 0).await?;
+    test_pass(fs, context, 400, Some(10), 0).await?;
+
+    let target = context.get_path("test1/dir1/dir2/gaz");
+    let result = fs.get_file_stream_range(target.clone(), 10, Some(10)).await;
+    test_assert!(result.is_err());
+    if let Err(e) = result {
+        test_assert_eq!(e.kind(), StorageErrorKind::NotFound(target));
+    }
+
+    Ok(())
+}
+
+/// Checks that an `OpenFile` reads the correct parts of a file.
+pub async fn test_open_file(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let file = fs
+        .open_file(context.get_path("test1/dir1/dir2/daz"))
+        .await?;
+    test_assert_eq!(file.len(), 300, "Should have seen the correct size.");
+
+    let expected: Vec<u8> = ContentIterator::new(72, 300).collect();
+    for &(offset, length, end) in &[
+        (0, 300, 300),
+        (0, 10, 10),
+        (120, 30, 150),
+        (290, 20, 300),
+        (300, 10, 300),
+        (500, 10, 300),
+    ] {
+        let data = file.read_at(offset as u64, length).await?;
+        let start = offset.min(300);
+        test_assert_eq!(
+            &data[..],
+            &expected[start..end],
+            "Should have read the correct data from {}.",
+            offset
+        );
+    }
+
+    for path in &["test1/dir1/dir2", "test1/dir1/dir2/gaz"] {
+        let target = context.get_path(path);
+        let result = fs.open_file(target.clone()).await;
+        test_assert!(result.is_err());
+        if let Err(e) = result {
+            test_assert_eq!(e.kind(), StorageErrorKind::NotFound(target));
+        }
+    }
+
+    Ok(())
+}
+
 struct Wrapper {
     inner: Pin<Box<dyn Future<Output = TestResult<()>> + Send + 'static>>,
 }
@@ -439,6 +655,7 @@ impl Future for Wrapper {
     }
 }
 
+/// Checks that many files can be read at the same time.
 pub async fn test_simultaneous_download(fs: &FileStore, context: &TestContext) -> TestResult<()> {
     async fn get_checker<I>(fs: FileStore, path: ObjectPath, mut data: I) -> TestResult<()>
     where
@@ -502,3 +719,28 @@ pub async fn test_simultaneous_download(fs: &FileStore, context: &TestContext) -
 
     Ok(())
 }
+
+/// Checks that reading ahead returns the same data.
+pub async fn test_read_ahead(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/mediumfile");
+    for chunks in 0..3 {
+        let options = StreamOptions::new().read_ahead(chunks);
+        test_assert_eq!(options.read_ahead_chunks(), chunks);
+
+        let stream = fs
+            .get_file_stream_with_options(path.clone(), options)
+            .await?;
+        test_stream_matches(stream, ContentIterator::new(58, 5 * MB)).await?;
+    }
+
+    let missing = context.get_path("test1/dir1/dir2/gaz");
+    let result = fs
+        .get_file_stream_with_options(missing.clone(), StreamOptions::new().read_ahead(2))
+        .await;
+    test_assert!(result.is_err());
+    if let Err(e) = result {
+        test_assert_eq!(e.kind(), StorageErrorKind::NotFound(missing));
+    }
+
+    Ok(())
+}
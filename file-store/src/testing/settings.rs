@@ -0,0 +1,393 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests for the settings that change how a store behaves.
+use std::fs::read;
+use std::future::Future;
+use std::task::Poll;
+
+use futures::channel::mpsc::unbounded;
+use futures::future::{poll_fn, FutureExt};
+use futures::stream::{iter, TryStreamExt};
+
+use super::utils::*;
+use super::*;
+
+use crate::backends::Backend;
+use crate::copy::CopyOptions;
+use crate::types::*;
+use crate::FileStore;
+
+/// Polls a future once, returning whether it is still pending.
+async fn is_pending<F>(future: &mut F) -> bool
+where
+    F: Future + Unpin,
+{
+    poll_fn(|cx| Poll::Ready(future.poll_unpin(cx).is_pending())).await
+}
+
+/// Checks that strict overwrites refuse to replace directories with files.
+pub async fn test_strict_overwrites(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    test_assert!(!fs.strict_overwrites(), "Should not be strict by default.");
+
+    let strict = fs.clone().with_strict_overwrites(true);
+    test_assert!(strict.strict_overwrites());
+    test_assert!(
+        !fs.strict_overwrites(),
+        "Should not have changed the original store."
+    );
+
+    // Files are still replaced.
+    let path = context.get_path("test1/dir1/smallfile.txt");
+    strict.write_bytes(path.clone(), "Some data.").await?;
+    test_assert_eq!(
+        &strict.read_to_bytes(path.clone()).await?[..],
+        b"Some data.",
+        "Should have replaced the file."
+    );
+
+    // Only backends with real directories have directories to protect.
+    if fs.backend_type() != Backend::File {
+        return Ok(());
+    }
+
+    let dir = context.get_path("test1/dir1/dir2");
+    match strict.write_bytes(dir.clone(), "Some data.").await {
+        Err(TransferError::TargetError(e)) => {
+            test_assert_eq!(e.kind(), StorageErrorKind::AlreadyExists(dir.clone()))
+        }
+        Err(e) => test_fail!("Unexpected error {:?}.", e),
+        Ok(()) => test_fail!("Should not have replaced the directory {}.", dir),
+    }
+
+    if strict.copy_file(path.clone(), dir.clone()).await.is_ok() {
+        test_fail!("Should not have copied over the directory {}.", dir);
+    }
+    if strict.move_file(path.clone(), dir.clone()).await.is_ok() {
+        test_fail!("Should not have moved over the directory {}.", dir);
+    }
+    test_assert!(
+        context.get_target(&dir).is_dir(),
+        "Should have left {} in place.",
+        dir
+    );
+    test_assert!(
+        context.get_target(&path).is_file(),
+        "Should have left {} in place.",
+        path
+    );
+
+    let info = UploadInfo {
+        path: dir.clone(),
+        replace_directory: true,
+        ..Default::default()
+    };
+    strict.write_bytes(info, "Some data.").await?;
+    test_assert!(
+        context.get_target(&dir).is_file(),
+        "Should have replaced the directory {} when asked to.",
+        dir
+    );
+
+    Ok(())
+}
+
+/// Checks that failed writes remove whatever they wrote when asked to.
+pub async fn test_cleanup_on_failure(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    fn broken() -> DataStream {
+        DataStream::from_stream(iter(vec![
+            Ok(Data::from("Some data.")),
+            Err(StorageError::new(
+                StorageErrorKind::InvalidData,
+                Some("Broken stream"),
+            )),
+        ]))
+    }
+
+    test_assert!(!fs.cleanup_on_failure(), "Should not clean up by default.");
+
+    let path = context.get_path("test1/dir1/cleaned");
+    let info = UploadInfo::from(path.clone()).cleanup_on_failure(true);
+    if fs.write_file_from_stream(info, broken()).await.is_ok() {
+        test_fail!("Should have failed to write from a broken stream.");
+    }
+    test_assert!(
+        !context.get_target(&path).exists(),
+        "Should have removed the partially written {}.",
+        path
+    );
+
+    let cleaning = fs.clone().with_cleanup_on_failure(true);
+    test_assert!(cleaning.cleanup_on_failure());
+    test_assert!(
+        !fs.cleanup_on_failure(),
+        "Should not have changed the original store."
+    );
+
+    let path = context.get_path("test1/dir1/default");
+    if cleaning
+        .write_file_from_stream(path.clone(), broken())
+        .await
+        .is_ok()
+    {
+        test_fail!("Should have failed to write from a broken stream.");
+    }
+    test_assert!(
+        !context.get_target(&path).exists(),
+        "Should have removed the partially written {}.",
+        path
+    );
+
+    // Network backends don't store anything until the data is complete.
+    if fs.backend_type() != Backend::File {
+        return Ok(());
+    }
+
+    let path = context.get_path("test1/dir1/partial");
+    if fs
+        .write_file_from_stream(path.clone(), broken())
+        .await
+        .is_ok()
+    {
+        test_fail!("Should have failed to write from a broken stream.");
+    }
+    test_assert_eq!(
+        read(context.get_target(&path)).map_err(TestError::from_error)?,
+        b"Some data.".to_vec(),
+        "Should have left the partial file behind."
+    );
+
+    let path = context.get_path("test1/dir1/kept");
+    let info = UploadInfo::from(path.clone()).cleanup_on_failure(false);
+    if cleaning
+        .write_file_from_stream(info, broken())
+        .await
+        .is_ok()
+    {
+        test_fail!("Should have failed to write from a broken stream.");
+    }
+    test_assert!(
+        context.get_target(&path).exists(),
+        "Should have left the partial file behind."
+    );
+
+    Ok(())
+}
+
+/// Checks that the directory semantics that don't depend on the backend list
+/// the same files for every backend.
+pub async fn test_directory_semantics(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    async fn list(
+        fs: &FileStore,
+        context: &TestContext,
+        prefix: &str,
+    ) -> TestResult<Vec<ObjectPath>> {
+        let mut paths = fs
+            .list_objects(context.get_path(prefix))
+            .await?
+            .map_ok(|o| o.path())
+            .try_collect::<Vec<ObjectPath>>()
+            .await?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    test_assert_eq!(fs.directory_semantics(), DirectorySemantics::Native);
+
+    // A file that shares a prefix with a directory.
+    let file = context.get_path("test1/dir1/dirfile");
+    fs.write_bytes(file.clone(), "Some data.").await?;
+
+    let mut dir2: Vec<ObjectPath> = vec![
+        "test1/dir1/dir2/0foo",
+        "test1/dir1/dir2/1bar",
+        "test1/dir1/dir2/5diz",
+        "test1/dir1/dir2/bar",
+        "test1/dir1/dir2/daz",
+        "test1/dir1/dir2/foo",
+        "test1/dir1/dir2/hop",
+        "test1/dir1/dir2/yu",
+    ]
+    .iter()
+    .map(|path| context.get_path(path))
+    .collect();
+    dir2.sort();
+
+    let mut prefixed = dir2.clone();
+    prefixed.push(file);
+    prefixed.sort();
+
+    let prefix = fs
+        .clone()
+        .with_directory_semantics(DirectorySemantics::Prefix);
+    test_assert_eq!(
+        list(&prefix, context, "test1/dir1/dir").await?,
+        prefixed,
+        "Should have listed every file starting with the prefix."
+    );
+    test_assert_eq!(list(&prefix, context, "test1/dir1/dir2/").await?, dir2);
+    test_assert!(list(&prefix, context, "test1/dir1/missing/")
+        .await?
+        .is_empty());
+
+    let strict = fs
+        .clone()
+        .with_directory_semantics(DirectorySemantics::StrictSlash);
+    test_assert_eq!(
+        list(&strict, context, "test1/dir1/dir2").await?,
+        dir2,
+        "Should have listed the prefix as a directory."
+    );
+    test_assert_eq!(list(&strict, context, "test1/dir1/dir2/").await?, dir2);
+    test_assert!(list(&strict, context, "test1/dir1/dir").await?.is_empty());
+    test_assert!(list(&strict, context, "test1/dir1/missing")
+        .await?
+        .is_empty());
+
+    // Clones keep the setting, the original is unaffected.
+    test_assert_eq!(
+        strict.clone().directory_semantics(),
+        DirectorySemantics::StrictSlash
+    );
+    test_assert_eq!(fs.directory_semantics(), DirectorySemantics::Native);
+
+    Ok(())
+}
+
+/// Checks that a custom path policy replaces the backend's own.
+pub async fn test_path_policy(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let store = fs
+        .clone()
+        .with_path_policy(PathPolicy::new().disallow_characters(&['*']));
+
+    let invalid = context.get_path("test1/dir1/small*file.txt");
+    match store.get_object(invalid.clone()).await {
+        Err(e) => test_assert_eq!(e.kind(), StorageErrorKind::InvalidPath(invalid.clone())),
+        Ok(_) => test_fail!("Should not have looked up {}.", invalid),
+    }
+    match store.delete_object(invalid.clone()).await {
+        Err(e) => test_assert_eq!(e.kind(), StorageErrorKind::InvalidPath(invalid.clone())),
+        Ok(()) => test_fail!("Should not have deleted {}.", invalid),
+    }
+    match store.write_bytes(invalid.clone(), "Some data.").await {
+        Err(TransferError::TargetError(e)) => {
+            test_assert_eq!(e.kind(), StorageErrorKind::InvalidPath(invalid.clone()))
+        }
+        Err(e) => test_fail!("Unexpected error {:?}.", e),
+        Ok(()) => test_fail!("Should not have written {}.", invalid),
+    }
+    test_assert!(
+        !context.get_target(&invalid).exists(),
+        "Should not have created {}.",
+        invalid
+    );
+
+    // Paths the policy allows still work.
+    let object = store
+        .get_object(context.get_path("test1/dir1/smallfile.txt"))
+        .await?;
+    test_assert_eq!(object.len(), 27);
+
+    Ok(())
+}
+
+/// Checks that stores sharing a concurrency limiter wait for each other's
+/// transfers but not for reads.
+pub async fn test_concurrency_limiter(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    test_assert!(fs.concurrency_limiter().is_none());
+
+    let limiter = ConcurrencyLimiter::new(1);
+    test_assert_eq!(limiter.limit(), 1);
+
+    let one = fs.clone().with_concurrency_limiter(limiter.clone());
+    let two = fs.clone().with_concurrency_limiter(limiter);
+    test_assert!(two.concurrency_limiter().is_some());
+    test_assert!(
+        fs.concurrency_limiter().is_none(),
+        "Should not have changed the original store."
+    );
+
+    // The first write holds the only permit while it waits for data.
+    let first_path = context.get_path("test1/dir1/first");
+    let second_path = context.get_path("test1/dir1/second");
+    let (sender, receiver) = unbounded::<StorageResult<Data>>();
+    let mut first = one.write_file_from_stream(first_path.clone(), receiver);
+    let mut second = two.write_bytes(second_path.clone(), "data");
+
+    test_assert!(
+        is_pending(&mut first).await,
+        "The first write should wait for data."
+    );
+    test_assert!(
+        is_pending(&mut second).await,
+        "The second write should wait for a permit."
+    );
+
+    sender
+        .unbounded_send(Ok(Data::from("first")))
+        .map_err(TestError::from_error)?;
+    drop(sender);
+    first.await?;
+    second.await?;
+
+    test_assert_eq!(&fs.read_to_bytes(first_path).await?[..], b"first");
+    test_assert_eq!(&fs.read_to_bytes(second_path.clone()).await?[..], b"data");
+
+    // Reads don't need a permit.
+    let (sender, receiver) = unbounded::<StorageResult<Data>>();
+    let mut blocker = one.write_file_from_stream(context.get_path("test1/dir1/blocked"), receiver);
+    test_assert!(
+        is_pending(&mut blocker).await,
+        "The write should wait for data."
+    );
+
+    test_assert_eq!(&two.read_to_bytes(second_path).await?[..], b"data");
+
+    drop(sender);
+    blocker.await?;
+
+    Ok(())
+}
+
+/// Checks that a prefix copy can be limited without changing the store.
+pub async fn test_copy_prefix_limiter(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let options = CopyOptions::new()
+        .concurrency(4)
+        .limiter(ConcurrencyLimiter::new(1));
+    let summary = fs
+        .copy_prefix(
+            context.get_path("test1/dir1/dir2"),
+            context.get_path("test1/dir1/copied"),
+            options,
+        )
+        .await?;
+
+    test_assert!(summary.is_success());
+    test_assert_eq!(summary.copied.len(), 8);
+    test_assert!(
+        fs.concurrency_limiter().is_none(),
+        "Should not have changed the store."
+    );
+
+    for name in &["foo", "bar", "0foo", "5diz", "1bar", "daz", "hop", "yu"] {
+        let path = context.get_path(&format!("test1/dir1/copied/{}", name));
+        test_assert!(
+            context.get_target(&path).is_file(),
+            "Should have copied {}.",
+            path
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [proptest](https://docs.rs/proptest) strategies for generating fixtures
+//! and changes to them.
+//!
+//! Generated paths only use lowercase ASCII letters so they are valid in every
+//! backend, and a generated fixture never holds a file at a path that another
+//! file uses as a directory.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+use super::{Content, FixtureBuilder, TestResult};
+use crate::types::*;
+use crate::FileStore;
+
+/// Generates file content up to a few kilobytes long.
+pub fn content() -> impl Strategy<Value = Content> {
+    prop_oneof![
+        Just(Content::Empty),
+        vec(any::<u8>(), 1..1024).prop_map(Content::Bytes),
+        (any::<u8>(), 1..4096u64).prop_map(|(seed, length)| Content::Generated { seed, length }),
+    ]
+}
+
+/// Generates a modification time in the past with whole second precision.
+pub fn modified() -> impl Strategy<Value = SystemTime> {
+    (1_000_000_000..1_500_000_000u64).prop_map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Generates a relative path with between one and `max_depth` parts.
+pub fn path(max_depth: usize) -> impl Strategy<Value = String> {
+    vec("[a-z]{1,6}", 1..=max_depth).prop_map(|parts| parts.join("/"))
+}
+
+fn clashes(a: &str, b: &str) -> bool {
+    a == b || a.starts_with(&format!("{}/", b)) || b.starts_with(&format!("{}/", a))
+}
+
+/// Generates a fixture of up to `max_files` files nested up to three
+/// directories deep.
+pub fn fixture(max_files: usize) -> impl Strategy<Value = FixtureBuilder> {
+    vec((path(3), content(), modified()), 0..=max_files).prop_map(|files| {
+        let mut paths: Vec<String> = Vec::new();
+        let mut fixture = FixtureBuilder::new();
+
+        for (path, content, modified) in files {
+            if !paths.iter().any(|p| clashes(p, &path)) {
+                fixture = fixture.file_modified(&path, content, modified);
+                paths.push(path);
+            }
+        }
+
+        fixture
+    })
+}
+
+/// A change to a store that was populated from a fixture.
+#[derive(Clone, Debug)]
+pub enum Mutation {
+    /// Replaces the content of one of the fixture's files.
+    Modify {
+        /// Selects the file.
+        index: Index,
+        /// The new content.
+        content: Content,
+    },
+    /// Deletes one of the fixture's files.
+    Delete {
+        /// Selects the file.
+        index: Index,
+    },
+    /// Creates a new file at the top level named `new-<name>`.
+    Create {
+        /// The name of the file.
+        name: String,
+        /// The content of the file.
+        content: Content,
+    },
+}
+
+impl Mutation {
+    /// Applies the change to a store. Files that are modified or created get
+    /// the current time as their modification time. Changes to the fixture's
+    /// files do nothing if the fixture is empty.
+    pub async fn apply(&self, store: &FileStore, fixture: &FixtureBuilder) -> TestResult<()> {
+        let files = fixture.files();
+
+        let (path, content) = match self {
+            Mutation::Modify { index, content } => {
+                if files.is_empty() {
+                    return Ok(());
+                }
+                (files[index.index(files.len())].path().to_owned(), content)
+            }
+            Mutation::Delete { index } => {
+                if files.is_empty() {
+                    return Ok(());
+                }
+
+                let path = ObjectPath::new(files[index.index(files.len())].path())?;
+                return match store.delete_object(path).await {
+                    Ok(()) => Ok(()),
+                    Err(e) => match e.kind() {
+                        // An earlier mutation may have deleted it already.
+                        StorageErrorKind::NotFound(_) => Ok(()),
+                        _ => Err(e.into()),
+                    },
+                };
+            }
+            Mutation::Create { name, content } => (format!("new-{}", name), content),
+        };
+
+        FixtureBuilder::new()
+            .file(&path, content.clone())
+            .build(store, &ObjectPath::empty())
+            .await
+    }
+}
+
+/// Generates up to `max` mutations.
+pub fn mutations(max: usize) -> impl Strategy<Value = Vec<Mutation>> {
+    vec(
+        prop_oneof![
+            (any::<Index>(), content())
+                .prop_map(|(index, content)| Mutation::Modify { index, content }),
+            any::<Index>().prop_map(|index| Mutation::Delete { index }),
+            ("[a-z]{1,6}", content())
+                .prop_map(|(name, content)| Mutation::Create { name, content }),
+        ],
+        0..=max,
+    )
+}
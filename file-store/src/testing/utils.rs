@@ -12,29 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::fs::File;
-use std::io::{BufWriter, Error, Write};
-use std::path::PathBuf;
+use std::io::Error;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use bytes::{BufMut, BytesMut};
-use futures::stream::Stream;
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::stream::{iter, Stream};
 
-use file_store::*;
-
-use super::{IntoTestResult, TestResult};
+use crate::types::*;
 
+/// One megabyte.
 pub const MB: u64 = 1024 * 1024;
 
 macro_rules! test_fail {
     ($message:expr) => {{
-        return Err(crate::runner::TestError::TestFailure(
+        return Err(crate::testing::TestError::TestFailure(
             format!("assertion failed at {}:{}: {}", file!(), line!(), $message)
         ));
     }};
     ($($info:tt)*) => {{
-        return Err(crate::runner::TestError::TestFailure(
+        return Err(crate::testing::TestError::TestFailure(
             format!("assertion failed at {}:{}: {}",
                 file!(), line!(), std::fmt::format(format_args!($($info)*)))
         ));
@@ -44,14 +41,14 @@ macro_rules! test_fail {
 macro_rules! test_assert {
     ($check:expr) => {{
         if !$check {
-            return Err(crate::runner::TestError::TestFailure(
+            return Err(crate::testing::TestError::TestFailure(
                 format!("assertion failed: `{}` at {}:{}", stringify!($check), file!(), line!()),
             ));
         }
     }};
     ($check:expr, $($info:tt)*) => {{
         if !$check {
-            return Err(crate::runner::TestError::TestFailure(
+            return Err(crate::testing::TestError::TestFailure(
                 format!("assertion failed: `{}` at {}:{}: {}",
                     stringify!($check), file!(), line!(), std::fmt::format(format_args!($($info)*)))
             ));
@@ -64,7 +61,7 @@ macro_rules! test_assert_eq {
         let found = $f;
         let expected = $e;
         if found != expected {
-            return Err(crate::runner::TestError::TestFailure(
+            return Err(crate::testing::TestError::TestFailure(
                 format!("assertion failed: `{} == {}` at {}:{}\n    found: `{:?}`\n expected: `{:?}`",
                     stringify!($f), stringify!($e), file!(), line!(), found, expected),
             ));
@@ -74,7 +71,7 @@ macro_rules! test_assert_eq {
         let found = $f;
         let expected = $e;
         if found != expected {
-            return Err(crate::runner::TestError::TestFailure(
+            return Err(crate::testing::TestError::TestFailure(
                 format!("assertion failed: `{} == {}` at {}:{}: {}\n    found: `{:?}`\n expected: `{:?}`",
                     stringify!($f), stringify!($e), file!(), line!(), std::fmt::format(format_args!($($info)*)), found, expected),
             ));
@@ -82,6 +79,20 @@ macro_rules! test_assert_eq {
     }};
 }
 
+/// Streams each of the given pieces of data as a separate chunk.
+pub fn data_stream<I>(pieces: I) -> DataStream
+where
+    I: IntoIterator,
+    I::Item: AsRef<[u8]>,
+{
+    let chunks: Vec<StorageResult<Data>> = pieces
+        .into_iter()
+        .map(|piece| Ok(Bytes::from(piece.as_ref())))
+        .collect();
+    DataStream::from_stream(iter(chunks))
+}
+
+/// A stream of data read from an iterator of bytes.
 pub struct IteratorStream<I>
 where
     I: Iterator<Item = u8>,
@@ -90,6 +101,7 @@ where
     buffer_size: usize,
 }
 
+/// Streams the bytes from an iterator in chunks of `buffer_size`.
 pub fn stream_iterator<I>(iterator: I, buffer_size: usize) -> IteratorStream<I>
 where
     I: Iterator<Item = u8>,
@@ -124,6 +136,7 @@ where
     }
 }
 
+/// Generates repeatable pseudo-random content.
 pub struct ContentIterator {
     seed: u8,
     value: u8,
@@ -132,6 +145,7 @@ pub struct ContentIterator {
 }
 
 impl ContentIterator {
+    /// Generates `length` bytes of content from `seed`.
     pub fn new(seed: u8, length: u64) -> ContentIterator {
         ContentIterator {
             seed,
@@ -160,26 +174,3 @@ impl Iterator for ContentIterator {
         Some(self.value)
     }
 }
-
-pub fn write_file<I>(dir: &PathBuf, name: &str, content: I) -> TestResult<PathBuf>
-where
-    I: IntoIterator<Item = u8>,
-{
-    let mut target = dir.clone();
-    target.push(name);
-
-    let file = File::create(&target).into_test_result()?;
-    let mut writer = BufWriter::new(file);
-
-    for b in content {
-        loop {
-            if writer.write(&[b]).into_test_result()? == 1 {
-                break;
-            }
-        }
-    }
-
-    writer.flush().into_test_result()?;
-
-    Ok(target)
-}
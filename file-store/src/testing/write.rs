@@ -0,0 +1,1195 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests for writing to a store.
+use std::collections::HashMap;
+use std::fs::{read_dir, symlink_metadata, File};
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use filetime::{set_file_mtime, FileTime};
+use futures::future::join_all;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use tempfile::tempdir;
+
+use super::utils::*;
+use super::*;
+
+use crate::backends::Backend;
+use crate::types::*;
+use crate::FileStore;
+
+fn test_file_matches<I>(target: &Path, info: UploadInfo, mut expected: I) -> TestResult<()>
+where
+    I: Iterator<Item = u8>,
+{
+    let meta = symlink_metadata(&target).map_err(TestError::from_error)?;
+
+    let mut found = BufReader::new(File::open(target).map_err(TestError::from_error)?).bytes();
+    let mut pos = 0;
+    loop {
+        match (found.next(), expected.next()) {
+            (Some(Err(e)), _) => {
+                return Err(TestError::from_error(e));
+            }
+            (Some(Ok(f)), Some(e)) => {
+                test_assert_eq!(
+                    f,
+                    e,
+                    "File content of {} at {} should have matched expected.",
+                    info.path,
+                    pos
+                );
+            }
+            (Some(_), None) => {
+                test_fail!("Found too many bytes in {}.", info.path);
+            }
+            (None, Some(_)) => {
+                test_fail!("Found too few bytes in {}.", info.path);
+            }
+            (None, None) => break,
+        }
+
+        pos += 1;
+    }
+
+    if let Some(time) = info.modified.as_ref() {
+        test_assert_eq!(
+            time,
+            &meta.modified().map_err(TestError::from_error)?,
+            "Should have seen the right modification time for {}.",
+            info.path
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that `copy_file` copies files and fails for missing sources.
+pub async fn test_copy_file(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    async fn test_pass(
+        fs: &FileStore,
+        context: &TestContext,
+        path: &str,
+        target: UploadInfo,
+        seed: u8,
+        length: u64,
+    ) -> TestResult<()> {
+        let remote_current = context.get_path(path);
+        let local_current = context.get_target(&remote_current);
+        let local_target = context.get_target(&target.path);
+
+        fs.copy_file(remote_current.clone(), target.clone()).await?;
+
+        let result = symlink_metadata(local_current.clone());
+        if let Ok(m) = result {
+            test_assert!(m.is_file(), "File {} should still exist.", remote_current);
+        } else {
+            test_fail!(
+                "Should not have seen an error getting metadata for the old file {}",
+                remote_current
+            );
+        }
+
+        test_file_matches(&local_target, target, ContentIterator::new(seed, length))?;
+
+        Ok(())
+    }
+
+    async fn test_fail(
+        fs: &FileStore,
+        context: &TestContext,
+        path: &str,
+        target: &str,
+    ) -> TestResult<()> {
+        let remote_current = context.get_path(path);
+        let remote_target = context.get_path(target);
+        let local_target = context.get_target(&remote_target);
+        let existing = symlink_metadata(local_target.clone()).ok().map(|m| m.len());
+
+        let result = fs
+            .copy_file(remote_current.clone(), remote_target.clone())
+            .await;
+
+        if let Err(e) = result {
+            if let TransferError::SourceError(s) = e {
+                test_assert_eq!(
+                    s.kind(),
+                    StorageErrorKind::NotFound(remote_current.clone()),
+                    "Should have been unable to find {}.",
+                    remote_current
+                );
+            } else {
+                test_fail!("Should have received a source error.");
+            }
+        } else {
+            test_fail!("Expected to fail to copy {}.", remote_current);
+        }
+
+        let result = symlink_metadata(local_target);
+        match (existing, result) {
+            (Some(len), Ok(m)) => {
+                test_assert_eq!(
+                    m.len(),
+                    len,
+                    "File {} should not have been changed.",
+                    remote_target
+                );
+            }
+            (Some(_), Err(_)) => {
+                test_fail!("File {} should still exist.", remote_target);
+            }
+            (None, Err(e)) => {
+                test_assert_eq!(
+                    e.kind(),
+                    ErrorKind::NotFound,
+                    "File {} should not exist.",
+                    remote_target
+                );
+            }
+            (None, Ok(_)) => {
+                test_fail!("File {} should not exist.", remote_target);
+            }
+        }
+
+        Ok(())
+    }
+
+    test_pass(
+        fs,
+        context,
+        "test1/dir1/mediumfile",
+        UploadInfo {
+            path: context.get_path("test1/dir1/testfile"),
+            modified: None,
+            ..Default::default()
+        },
+        58,
+        5 * MB,
+    )
+    .await?;
+    test_pass(
+        fs,
+        context,
+        "test1/dir1/largefile",
+        UploadInfo {
+            path: context.get_path("test1/dir1/dir2/hop"),
+            modified: None,
+            ..Default::default()
+        },
+        0,
+        100 * MB,
+    )
+    .await?;
+    test_pass(
+        fs,
+        context,
+        "test1/dir1/dir2/daz",
+        UploadInfo {
+            path: context.get_path("test1/dir1/bazza"),
+            modified: Some(UNIX_EPOCH + Duration::from_millis(1_703_257_714)),
+            ..Default::default()
+        },
+        72,
+        300,
+    )
+    .await?;
+
+    test_fail(fs, context, "test1/dir1/dir2/gaz", "test1/dir1/bazza").await?;
+    test_fail(fs, context, "test1/dir1/fooish", "test1/dir1/dir2/too").await?;
+
+    Ok(())
+}
+
+/// Checks that `move_file` moves files and fails for missing sources.
+pub async fn test_move_file(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    async fn test_pass(
+        fs: &FileStore,
+        context: &TestContext,
+        path: &str,
+        target: UploadInfo,
+        seed: u8,
+        length: u64,
+    ) -> TestResult<()> {
+        let remote_current = context.get_path(path);
+        let local_current = context.get_target(&remote_current);
+        let local_target = context.get_target(&target.path);
+
+        fs.move_file(remote_current.clone(), target.clone()).await?;
+
+        let result = symlink_metadata(local_current.clone());
+        if result.is_ok() {
+            test_fail!("File {} should no longer exist.", remote_current);
+        }
+
+        test_file_matches(&local_target, target, ContentIterator::new(seed, length))?;
+
+        Ok(())
+    }
+
+    async fn test_fail(
+        fs: &FileStore,
+        context: &TestContext,
+        path: &str,
+        target: &str,
+    ) -> TestResult<()> {
+        let remote_current = context.get_path(path);
+        let remote_target = context.get_path(target);
+        let local_target = context.get_target(&remote_target);
+
+        let result = fs
+            .move_file(remote_current.clone(), remote_target.clone())
+            .await;
+
+        if let Err(e) = result {
+            if let TransferError::SourceError(s) = e {
+                test_assert_eq!(
+                    s.kind(),
+                    StorageErrorKind::NotFound(remote_current.clone()),
+                    "Should have been unable to find {}.",
+                    remote_current
+                );
+            } else {
+                test_fail!("Should have received a source error.");
+            }
+        } else {
+            test_fail!("Expected to fail to copy {}.", remote_current);
+        }
+
+        let result = symlink_metadata(local_target);
+        if let Err(e) = result {
+            test_assert_eq!(
+                e.kind(),
+                ErrorKind::NotFound,
+                "File {} should not exist.",
+                remote_target
+            );
+        }
+
+        Ok(())
+    }
+
+    test_pass(
+        fs,
+        context,
+        "test1/dir1/mediumfile",
+        UploadInfo {
+            path: context.get_path("test1/dir1/testfile"),
+            modified: Some(UNIX_EPOCH + Duration::from_millis(1_703_257_714)),
+            ..Default::default()
+        },
+        58,
+        5 * MB,
+    )
+    .await?;
+    test_pass(
+        fs,
+        context,
+        "test1/dir1/largefile",
+        UploadInfo {
+            path: context.get_path("test1/dir1/dir2/hop"),
+            modified: None,
+            ..Default::default()
+        },
+        0,
+        100 * MB,
+    )
+    .await?;
+    test_pass(
+        fs,
+        context,
+        "test1/dir1/dir2/daz",
+        UploadInfo {
+            path: context.get_path("test1/dir1/bazza"),
+            modified: None,
+            ..Default::default()
+        },
+        72,
+        300,
+    )
+    .await?;
+
+    test_fail(fs, context, "test1/dir1/dir2/gaz", "test1/dir1/bazza").await?;
+    test_fail(fs, context, "test1/dir1/fooish", "test1/dir1/dir2/too").await?;
+
+    Ok(())
+}
+
+/// Checks that `delete_object` removes files.
+pub async fn test_delete_object(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    async fn test_pass(fs: &FileStore, context: &TestContext, path: &str) -> TestResult<()> {
+        let remote = context.get_path(path);
+        let target = context.get_target(&remote);
+
+        fs.delete_object(remote).await?;
+
+        match symlink_metadata(target.clone()) {
+            Ok(m) => {
+                test_assert!(m.is_file(), "Failed to delete {}", target.display());
+            }
+            Err(e) => {
+                test_assert_eq!(
+                    e.kind(),
+                    ErrorKind::NotFound,
+                    "Should have failed to find {}",
+                    target.display()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn test_fail(fs: &FileStore, context: &TestContext, path: &str) -> TestResult<()> {
+        let fspath = context.get_path(path);
+        let target = context.get_target(&fspath);
+
+        match fs.delete_object(fspath.clone()).await {
+            Ok(()) => test_fail!("Should have failed to delete {}", fspath),
+            Err(e) => test_assert_eq!(
+                e.kind(),
+                StorageErrorKind::NotFound(fspath.clone()),
+                "The file {} should have not been found.",
+                fspath
+            ),
+        }
+
+        if let Ok(m) = symlink_metadata(target) {
+            test_assert!(m.is_dir(), "Shouldn't have deleted {}.", fspath);
+        }
+
+        Ok(())
+    }
+
+    test_pass(fs, context, "test1/dir1/largefile").await?;
+    test_pass(fs, context, "test1/dir1/smallfile.txt").await?;
+    test_pass(fs, context, "test1/dir1/dir2/daz").await?;
+    test_pass(fs, context, "test1/dir1/maybedir").await?;
+
+    if fs.backend_type() == Backend::File {
+        test_pass(fs, context, "test1/dir1/dir2").await?;
+    } else {
+        test_fail(fs, context, "test1/dir1/dir2").await?;
+    }
+
+    test_fail(fs, context, "test1/dir1/biz").await?;
+
+    Ok(())
+}
+
+/// Checks that `write_file_from_stream` writes the correct data.
+pub async fn test_write_file_from_stream(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    async fn test_write(
+        fs: &FileStore,
+        context: &TestContext,
+        target: UploadInfo,
+        seed: u8,
+        length: u64,
+    ) -> TestResult<()> {
+        let local_target = context.get_target(&target.path);
+
+        fs.write_file_from_stream(
+            target.clone(),
+            stream_iterator(ContentIterator::new(seed, length), (length / 10) as usize),
+        )
+        .await?;
+
+        test_file_matches(&local_target, target, ContentIterator::new(seed, length))?;
+
+        Ok(())
+    }
+
+    test_write(
+        fs,
+        context,
+        UploadInfo {
+            path: context.get_path("test1/dir1/foobar"),
+            modified: Some(UNIX_EPOCH + Duration::from_millis(1_703_257_714)),
+            ..Default::default()
+        },
+        58,
+        300,
+    )
+    .await?;
+    test_write(
+        fs,
+        context,
+        UploadInfo {
+            path: context.get_path("test1/dir1/maybedir"),
+            modified: None,
+            ..Default::default()
+        },
+        27,
+        500,
+    )
+    .await?;
+    test_write(
+        fs,
+        context,
+        UploadInfo {
+            path: context.get_path("test1/dir1/dir2/daz"),
+            modified: None,
+            ..Default::default()
+        },
+        27,
+        100 * MB,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Checks that files can be written from upload sources.
+pub async fn test_write_file_from_source(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/sourced");
+    let data: Vec<u8> = ContentIterator::new(12, 300).collect();
+    fs.write_file_from_source(path.clone(), Data::from(data))
+        .await?;
+
+    test_file_matches(
+        &context.get_target(&path),
+        UploadInfo::from(path),
+        ContentIterator::new(12, 300),
+    )?;
+
+    let path = context.get_path("test1/dir1/rewound");
+    let source = RewindableStream::new(5000, || {
+        let stream = stream_iterator(ContentIterator::new(43, 5000), 700);
+        DataStreamFuture::from_value(Ok(DataStream::from_stream(
+            stream.map(|r| r.map_err(StorageError::from)),
+        )))
+    });
+    fs.write_file_from_source(path.clone(), source).await?;
+
+    test_file_matches(
+        &context.get_target(&path),
+        UploadInfo::from(path),
+        ContentIterator::new(43, 5000),
+    )?;
+
+    Ok(())
+}
+
+/// Checks that writes can return the object they wrote.
+pub async fn test_write_returning(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/returned");
+    let modified = UNIX_EPOCH + Duration::from_millis(1_703_257_714);
+    let info = UploadInfo {
+        path: path.clone(),
+        modified: Some(modified),
+        ..Default::default()
+    };
+
+    let object = fs
+        .write_file_from_stream_returning(info, stream_iterator(ContentIterator::new(31, 400), 100))
+        .await?;
+
+    test_assert_eq!(
+        object.path(),
+        path,
+        "Should have returned the written path."
+    );
+    test_assert_eq!(object.object_type(), ObjectType::File);
+    test_assert_eq!(object.len(), 400, "Should have returned the written size.");
+    test_assert_eq!(
+        object.modified(),
+        Some(modified),
+        "Should have returned the modification time."
+    );
+
+    Ok(())
+}
+
+/// Checks that progress is reported for uploads and downloads.
+pub async fn test_progress(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    fn last_transferred(events: &Mutex<Vec<ProgressEvent>>) -> Option<ProgressEvent> {
+        events
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|event| match event {
+                ProgressEvent::Transferred { .. } => true,
+                _ => false,
+            })
+            .cloned()
+    }
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let recorded = events.clone();
+    let listener = ProgressListener::new(move |event| recorded.lock().unwrap().push(event));
+
+    let path = context.get_path("test1/dir1/tracked");
+    let info = UploadInfo::from(path.clone()).progress(listener.clone());
+    fs.write_file_from_stream(info, stream_iterator(ContentIterator::new(5, 400), 100))
+        .await?;
+
+    test_assert_eq!(
+        last_transferred(&events),
+        Some(ProgressEvent::Transferred {
+            bytes: 400,
+            total: None
+        }),
+        "Should have reported all the data written."
+    );
+
+    events.lock().unwrap().clear();
+    let mut stream = fs
+        .get_file_stream_with_options(path, StreamOptions::new().progress(listener))
+        .await?;
+    while let Some(data) = stream.next().await {
+        data?;
+    }
+
+    test_assert_eq!(
+        last_transferred(&events),
+        Some(ProgressEvent::Transferred {
+            bytes: 400,
+            total: Some(400)
+        }),
+        "Should have reported all the data read."
+    );
+
+    Ok(())
+}
+
+/// Checks that cancelled writes fail and leave nothing behind.
+pub async fn test_cancellation(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let token = CancellationToken::new();
+    token.cancel();
+
+    let path = context.get_path("test1/dir1/cancelled");
+    let info = UploadInfo::from(path.clone()).cancellation(token);
+    let result = fs.write_bytes(info, "Never written.").await;
+    if result.is_ok() {
+        test_fail!("Should not have written after cancelling.");
+    }
+
+    // Network backends may see their request fail in other ways.
+    if fs.backend_type() == Backend::File {
+        test_assert_eq!(
+            result.map_err(StorageError::from).err().map(|e| e.kind()),
+            Some(StorageErrorKind::Cancelled)
+        );
+        test_assert!(
+            !context.get_target(&path).exists(),
+            "Should not have created the file."
+        );
+
+        // Cancel part way through the data.
+        let token = CancellationToken::new();
+        let trigger = token.clone();
+        let stream = stream_iterator(ContentIterator::new(9, 1000), 100).map(move |result| {
+            trigger.cancel();
+            result
+        });
+        let info = UploadInfo::from(path.clone()).cancellation(token);
+        if fs.write_file_from_stream(info, stream).await.is_ok() {
+            test_fail!("Should not have finished writing after cancelling.");
+        }
+        test_assert!(
+            !context.get_target(&path).exists(),
+            "Should have removed the partially written file."
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that a `FileWriter` writes the data sent to it.
+pub async fn test_file_writer(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    async fn test_write(
+        fs: &FileStore,
+        context: &TestContext,
+        target: UploadInfo,
+        seed: u8,
+        length: u64,
+    ) -> TestResult<()> {
+        let local_target = context.get_target(&target.path);
+
+        let content: Vec<u8> = ContentIterator::new(seed, length).collect();
+        let mut writer = fs.write_file(target.clone())?;
+        for chunk in content.chunks(64 * 1024) {
+            writer.send(Data::from(chunk)).await?;
+        }
+        writer.close().await?;
+
+        test_file_matches(&local_target, target, ContentIterator::new(seed, length))?;
+
+        Ok(())
+    }
+
+    test_write(
+        fs,
+        context,
+        UploadInfo {
+            path: context.get_path("test1/dir1/written"),
+            modified: Some(UNIX_EPOCH + Duration::from_millis(1_703_257_714)),
+            ..Default::default()
+        },
+        12,
+        300,
+    )
+    .await?;
+    test_write(
+        fs,
+        context,
+        UploadInfo {
+            path: context.get_path("test1/dir1/dir2/daz"),
+            modified: None,
+            ..Default::default()
+        },
+        91,
+        10 * MB,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Checks that small files can be written and read back in one go.
+pub async fn test_write_bytes(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/notes.txt");
+    fs.write_bytes(path.clone(), "Some short notes.").await?;
+
+    test_file_matches(
+        &context.get_target(&path),
+        UploadInfo {
+            path: path.clone(),
+            ..Default::default()
+        },
+        b"Some short notes.".iter().cloned(),
+    )?;
+
+    test_assert_eq!(
+        &fs.read_to_bytes(path.clone()).await?[..],
+        b"Some short notes.",
+        "Should have read the data written."
+    );
+    test_assert_eq!(
+        fs.read_to_string(path).await?,
+        "Some short notes.",
+        "Should have read the text written."
+    );
+
+    let path = context.get_path("test1/dir1/binary");
+    fs.write_bytes(path.clone(), vec![0xff, 0xfe, 0x00]).await?;
+    match fs.read_to_string(path).await {
+        Err(e) => test_assert_eq!(e.kind(), StorageErrorKind::InvalidData),
+        Ok(_) => test_fail!("Should not have read invalid UTF-8 as text."),
+    }
+
+    Ok(())
+}
+
+/// Checks that files can be transferred to and from the local disk.
+pub async fn test_local_transfer(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let temp = tempdir().map_err(TestError::from_error)?;
+
+    let path = context.get_path("test1/dir1/dir2/daz");
+    let object = fs.get_object(path.clone()).await?;
+    let local = temp.path().join("daz");
+    fs.download_to_path(path.clone(), &local).await?;
+    test_file_matches(
+        &local,
+        UploadInfo {
+            path,
+            modified: object.modified(),
+            ..Default::default()
+        },
+        ContentIterator::new(72, 300),
+    )?;
+
+    let modified = UNIX_EPOCH + Duration::from_millis(1_703_257_714);
+    set_file_mtime(&local, FileTime::from_system_time(modified)).map_err(TestError::from_error)?;
+    let target = context.get_path("test1/dir1/uploaded");
+    fs.upload_from_path(target.clone(), &local).await?;
+    test_file_matches(
+        &context.get_target(&target),
+        UploadInfo {
+            path: target,
+            modified: Some(modified),
+            ..Default::default()
+        },
+        ContentIterator::new(72, 300),
+    )?;
+
+    let missing = context.get_path("test1/dir1/dir2/gaz");
+    let local = temp.path().join("gaz");
+    match fs.download_to_path(missing.clone(), &local).await {
+        Err(TransferError::SourceError(e)) => {
+            test_assert_eq!(e.kind(), StorageErrorKind::NotFound(missing))
+        }
+        Err(e) => test_fail!("Unexpected error {:?}.", e),
+        Ok(()) => test_fail!("Should not have downloaded a missing file."),
+    }
+    test_assert!(!local.exists(), "Should not have created a local file.");
+
+    Ok(())
+}
+
+/// Checks that `delete_objects` and `delete_prefix` delete many files.
+pub async fn test_delete_many(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let deleted = context.get_path("test1/dir1/smallfile.txt");
+    let missing = context.get_path("test1/dir1/missing");
+    let summary = fs
+        .delete_objects(vec![deleted.clone(), missing.clone()])
+        .await;
+    test_assert_eq!(summary.deleted, vec![deleted.clone()]);
+    test_assert_eq!(summary.failed.len(), 1);
+    test_assert_eq!(&summary.failed[0].0, &missing);
+    test_assert_eq!(
+        summary.failed[0].1.kind(),
+        StorageErrorKind::NotFound(missing)
+    );
+    test_assert!(
+        !context.get_target(&deleted).exists(),
+        "Should have deleted {}.",
+        deleted
+    );
+
+    let summary = fs.delete_objects(vec!["/invalid"]).await;
+    test_assert!(!summary.is_success());
+    test_assert!(summary.failed.is_empty());
+    test_assert_eq!(summary.invalid.len(), 1);
+    test_assert_eq!(summary.invalid[0].0.as_str(), "/invalid");
+
+    let summary = fs
+        .delete_prefix(context.get_path("test1/dir1/dir2/"))
+        .await?;
+    test_assert!(summary.is_success());
+    test_assert_eq!(summary.deleted.len(), 8);
+    for name in &["foo", "bar", "0foo", "5diz", "1bar", "daz", "hop", "yu"] {
+        let path = context.get_path(&format!("test1/dir1/dir2/{}", name));
+        test_assert!(
+            !context.get_target(&path).is_file(),
+            "Should have deleted {}.",
+            path
+        );
+    }
+    test_assert!(
+        context
+            .get_target(&context.get_path("test1/dir1/mediumfile"))
+            .is_file(),
+        "Should not have deleted files outside the prefix."
+    );
+
+    Ok(())
+}
+
+/// Checks that directories can be created ahead of writing files into them.
+pub async fn test_create_directory(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let dir = context.get_path("test1/newdir/inner");
+    let target = context.get_target(&dir);
+
+    fs.create_directory(dir.clone()).await?;
+    // Creating a directory that already exists is fine.
+    fs.create_directory(dir.clone()).await?;
+
+    if fs.backend_type() == Backend::File {
+        match symlink_metadata(&target) {
+            Ok(m) => test_assert!(m.is_dir(), "Should have created {}.", target.display()),
+            Err(e) => test_fail!("Should have created {}: {}", target.display(), e),
+        }
+    } else {
+        test_assert!(
+            symlink_metadata(&target).is_err(),
+            "Should not have written a directory marker."
+        );
+    }
+
+    let mut path = dir;
+    path.push_part("file.txt");
+    fs.write_bytes(path.clone(), "Inside a new directory.")
+        .await?;
+    test_assert_eq!(
+        fs.read_to_string(path).await?,
+        "Inside a new directory.",
+        "Should have written a file in the new directory."
+    );
+
+    Ok(())
+}
+
+/// Checks that user metadata is stored with a file and read back.
+pub async fn test_metadata(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/described.txt");
+    let info = UploadInfo::from(path.clone())
+        .metadata("colour", "blue")
+        .metadata("owner", "Some One");
+    fs.write_bytes(info, "Some described data.").await?;
+
+    let mut expected = HashMap::new();
+    expected.insert(String::from("colour"), String::from("blue"));
+    expected.insert(String::from("owner"), String::from("Some One"));
+
+    let object = fs.get_object(path).await?;
+    test_assert_eq!(
+        object.metadata(),
+        Some(expected),
+        "Should have read back the metadata."
+    );
+
+    Ok(())
+}
+
+/// Checks that the content type given on upload is stored with the file.
+pub async fn test_content_type(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/readme");
+    let info = UploadInfo::from(path.clone()).content_type("text/markdown");
+    fs.write_bytes(info, "# Read me").await?;
+
+    let object = fs.get_object(path).await?;
+    test_assert_eq!(
+        object.content_type(),
+        Some(String::from("text/markdown")),
+        "Should have read back the content type."
+    );
+
+    Ok(())
+}
+
+/// Checks that writes respect the upload's write mode.
+pub async fn test_write_mode(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/guarded.txt");
+    fs.write_bytes(path.clone(), "Original data.").await?;
+
+    let info = UploadInfo::from(path.clone()).write_mode(WriteMode::FailIfExists);
+    match fs.write_bytes(info, "Replaced data.").await {
+        Err(e) => test_assert_eq!(
+            StorageError::from(e).kind(),
+            StorageErrorKind::AlreadyExists(path.clone())
+        ),
+        Ok(()) => test_fail!("Should not have replaced the existing file."),
+    }
+
+    let info = UploadInfo::from(path.clone()).write_mode(WriteMode::IgnoreIfExists);
+    fs.write_bytes(info, "Replaced data.").await?;
+    test_assert_eq!(
+        fs.read_to_string(path.clone()).await?,
+        "Original data.",
+        "Should have left the existing file alone."
+    );
+
+    let source = context.get_path("test1/dir1/source.txt");
+    fs.write_bytes(source.clone(), "Source data.").await?;
+    let info = UploadInfo::from(path.clone()).write_mode(WriteMode::FailIfExists);
+    if fs.copy_file(source.clone(), info).await.is_ok() {
+        test_fail!("Should not have copied over the existing file.");
+    }
+
+    let info = UploadInfo::from(path.clone()).write_mode(WriteMode::IgnoreIfExists);
+    fs.move_file(source.clone(), info).await?;
+    test_assert_eq!(
+        fs.read_to_string(path).await?,
+        "Original data.",
+        "Should have left the existing file alone."
+    );
+    test_assert_eq!(
+        fs.read_to_string(source).await?,
+        "Source data.",
+        "Should have left the source of a skipped move in place."
+    );
+
+    let path = context.get_path("test1/dir1/fresh.txt");
+    let info = UploadInfo::from(path.clone()).write_mode(WriteMode::FailIfExists);
+    fs.write_bytes(info, "Fresh data.").await?;
+    test_assert_eq!(
+        fs.read_to_string(path).await?,
+        "Fresh data.",
+        "Should have written the new file."
+    );
+
+    Ok(())
+}
+
+/// Checks that verified reads catch data that doesn't match its checksum.
+pub async fn test_verified_read(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    async fn read(fs: &FileStore, path: ObjectPath) -> StorageResult<Vec<u8>> {
+        let mut stream = fs
+            .get_file_stream_with_options(path, StreamOptions::new().verify(true))
+            .await?;
+        let mut result = Vec::new();
+        while let Some(data) = stream.next().await {
+            result.extend_from_slice(&data?);
+        }
+        Ok(result)
+    }
+
+    let path = context.get_path("test1/dir1/checked.txt");
+    fs.write_bytes(path.clone(), "Some checked data.").await?;
+
+    test_assert_eq!(
+        read(fs, path.clone()).await?,
+        b"Some checked data.".to_vec(),
+        "Should have read the data written."
+    );
+
+    // Only backends that store checksums can catch corruption.
+    if fs.get_object(path.clone()).await?.checksum().is_none() {
+        return Ok(());
+    }
+
+    if let Err(e) = std::fs::write(context.get_target(&path), "Some damaged data.") {
+        test_fail!("Failed to corrupt the file: {}", e);
+    }
+
+    match read(fs, path.clone()).await {
+        Ok(_) => test_fail!("Should have failed to verify the corrupted data."),
+        Err(e) => test_assert_eq!(
+            e.kind(),
+            StorageErrorKind::IntegrityError(path),
+            "Should have seen an integrity error."
+        ),
+    }
+
+    Ok(())
+}
+
+/// Checks that moving a file onto itself leaves it alone.
+pub async fn test_move_onto_itself(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/unmoved.txt");
+    let info = UploadInfo::from(path.clone()).metadata("colour", "blue");
+    fs.write_bytes(info, "Some data.").await?;
+
+    fs.move_file(path.clone(), path.clone()).await?;
+    test_assert_eq!(
+        fs.read_to_string(path.clone()).await?,
+        "Some data.",
+        "Should have left the file alone."
+    );
+
+    let mut expected = HashMap::new();
+    expected.insert(String::from("colour"), String::from("blue"));
+    test_assert_eq!(
+        fs.get_object(path.clone()).await?.metadata(),
+        Some(expected),
+        "Should have kept the metadata."
+    );
+
+    let info = UploadInfo::from(path.clone()).write_mode(WriteMode::FailIfExists);
+    match fs.move_file(path.clone(), info).await {
+        Err(TransferError::TargetError(e)) => {
+            test_assert_eq!(e.kind(), StorageErrorKind::AlreadyExists(path.clone()))
+        }
+        Err(e) => test_fail!("Unexpected error {:?}.", e),
+        Ok(()) => test_fail!("Should not have moved onto an existing file."),
+    }
+    test_assert_eq!(fs.read_to_string(path).await?, "Some data.");
+
+    Ok(())
+}
+
+/// Checks that moves only replace existing files in the overwrite mode.
+pub async fn test_move_write_modes(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let source = context.get_path("test1/dir1/dir2/daz");
+    let existing = context.get_path("test1/dir1/smallfile.txt");
+
+    let info = UploadInfo::from(existing.clone()).write_mode(WriteMode::FailIfExists);
+    match fs.move_file(source.clone(), info).await {
+        Err(TransferError::TargetError(e)) => {
+            test_assert_eq!(e.kind(), StorageErrorKind::AlreadyExists(existing.clone()))
+        }
+        Err(e) => test_fail!("Unexpected error {:?}.", e),
+        Ok(()) => test_fail!("Should not have replaced {}.", existing),
+    }
+    test_assert_eq!(
+        fs.read_to_string(existing.clone()).await?,
+        "This is quite a short file.",
+        "Should have left the existing file alone."
+    );
+    test_assert!(
+        context.get_target(&source).is_file(),
+        "Should have left {} in place.",
+        source
+    );
+
+    // A skipped move leaves the source where it is.
+    let info = UploadInfo::from(existing.clone()).write_mode(WriteMode::IgnoreIfExists);
+    fs.move_file(source.clone(), info).await?;
+    test_assert_eq!(
+        fs.read_to_string(existing).await?,
+        "This is quite a short file.",
+        "Should have skipped the move."
+    );
+    test_assert!(
+        context.get_target(&source).is_file(),
+        "Should have left {} in place.",
+        source
+    );
+
+    let target = context.get_path("test1/dir1/moved");
+    let info = UploadInfo::from(target.clone()).write_mode(WriteMode::FailIfExists);
+    fs.move_file(source.clone(), info).await?;
+    test_assert!(
+        !context.get_target(&source).exists(),
+        "Should have moved {}.",
+        source
+    );
+    test_file_matches(
+        &context.get_target(&target),
+        UploadInfo::from(target),
+        ContentIterator::new(72, 300),
+    )?;
+
+    Ok(())
+}
+
+/// Checks that a move stores the metadata given for the target rather than
+/// the source's.
+pub async fn test_move_metadata(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let source = context.get_path("test1/dir1/described.txt");
+    let info = UploadInfo::from(source.clone())
+        .metadata("colour", "blue")
+        .metadata("owner", "Some One")
+        .content_type("text/plain");
+    fs.write_bytes(info, "Some data.").await?;
+
+    let target = context.get_path("test1/dir1/redescribed.txt");
+    let info = UploadInfo::from(target.clone()).metadata("colour", "red");
+    fs.move_file(source, info).await?;
+
+    let mut expected = HashMap::new();
+    expected.insert(String::from("colour"), String::from("red"));
+
+    let object = fs.get_object(target).await?;
+    test_assert_eq!(
+        object.metadata(),
+        Some(expected),
+        "Should have replaced the metadata."
+    );
+
+    // Other backends may detect a content type.
+    if fs.backend_type() == Backend::File {
+        test_assert_eq!(
+            object.content_type(),
+            None,
+            "Should have replaced the content type."
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that conditional writes only replace files that haven't changed.
+pub async fn test_write_if_match(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/smallfile.txt");
+    let etag = match fs.get_object(path.clone()).await?.etag() {
+        Some(etag) => etag,
+        None => return Ok(()),
+    };
+
+    match fs
+        .write_file_if_match(path.clone(), &etag, data_stream(&["bb"]))
+        .await
+    {
+        Ok(()) => (),
+        Err(e) => {
+            // Backends that can't replace files conditionally say so.
+            let error = StorageError::from(e);
+            if error.kind() == StorageErrorKind::InvalidSettings {
+                return Ok(());
+            }
+            return Err(error.into());
+        }
+    }
+    test_assert_eq!(fs.read_to_string(path.clone()).await?, "bb");
+
+    match fs
+        .write_file_if_match(path.clone(), &etag, data_stream(&["ccc"]))
+        .await
+    {
+        Err(TransferError::TargetError(e)) => {
+            test_assert_eq!(e.kind(), StorageErrorKind::PreconditionFailed(path.clone()))
+        }
+        Err(e) => test_fail!("Unexpected error {:?}.", e),
+        Ok(()) => test_fail!("Should not have replaced a changed file."),
+    }
+    test_assert_eq!(
+        fs.read_to_string(path).await?,
+        "bb",
+        "Should have left the changed file alone."
+    );
+
+    let missing = context.get_path("test1/dir1/missing");
+    match fs
+        .write_file_if_match(missing.clone(), &etag, data_stream(&["ccc"]))
+        .await
+    {
+        Err(TransferError::TargetError(e)) => {
+            test_assert_eq!(
+                e.kind(),
+                StorageErrorKind::PreconditionFailed(missing.clone())
+            )
+        }
+        Err(e) => test_fail!("Unexpected error {:?}.", e),
+        Ok(()) => test_fail!("Should not have written a missing file."),
+    }
+    test_assert!(
+        !context.get_target(&missing).exists(),
+        "Should not have created {}.",
+        missing
+    );
+
+    Ok(())
+}
+
+/// Checks that of two racing conditional writes only one succeeds.
+pub async fn test_racing_writes_if_match(fs: &FileStore, context: &TestContext) -> TestResult<()> {
+    let path = context.get_path("test1/dir1/smallfile.txt");
+    let etag = match fs.get_object(path.clone()).await?.etag() {
+        Some(etag) => etag,
+        None => return Ok(()),
+    };
+
+    let results = join_all(vec![
+        fs.write_file_if_match(path.clone(), &etag, data_stream(&["bb"])),
+        fs.write_file_if_match(path.clone(), &etag, data_stream(&["ccc"])),
+    ])
+    .await;
+
+    let mut failures = 0;
+    for result in results {
+        match result {
+            Ok(()) => (),
+            Err(e) => match StorageError::from(e).kind() {
+                StorageErrorKind::InvalidSettings => return Ok(()),
+                StorageErrorKind::PreconditionFailed(p) => {
+                    test_assert_eq!(&p, &path);
+                    failures += 1;
+                }
+                k => test_fail!("Unexpected error kind {:?}.", k),
+            },
+        }
+    }
+    test_assert_eq!(failures, 1, "Exactly one write should have failed.");
+
+    let content = fs.read_to_string(path.clone()).await?;
+    test_assert!(
+        content == "bb" || content == "ccc",
+        "Should have seen one of the writes."
+    );
+
+    if fs.backend_type() == Backend::File {
+        if let Some(parent) = context.get_target(&path).parent() {
+            let partial = parent.join(".partial");
+            let remaining = read_dir(&partial).map_err(TestError::from_error)?.count();
+            test_assert_eq!(remaining, 0, "Should have removed the temporary files.");
+        }
+    }
+
+    Ok(())
+}
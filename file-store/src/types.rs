@@ -13,25 +13,47 @@
 // limitations under the License.
 
 //! The main types used in this crate.
+pub(crate) mod cancel;
+pub(crate) mod checksum;
+pub(crate) mod delete;
 pub(crate) mod error;
 pub(crate) mod future;
+pub(crate) mod glob;
+pub(crate) mod limiter;
+pub(crate) mod list;
 pub(crate) mod objects;
+pub(crate) mod open_file;
 pub(crate) mod path;
+pub(crate) mod progress;
+pub(crate) mod retry;
+pub(crate) mod source;
 pub(crate) mod stream;
+pub(crate) mod writer;
 
-use std::io;
-
-use bytes::buf::Buf;
-use bytes::{Bytes, IntoBuf};
-use futures::executor::{block_on_stream, BlockingStream};
-use futures::stream::Stream;
+use bytes::Bytes;
 
 use super::FileStore;
+pub use cancel::CancellationToken;
+pub use checksum::{Checksum, ChecksumAlgorithm};
+pub use delete::DeleteSummary;
 pub use error::{StorageError, StorageErrorKind, StorageResult, TransferError};
 pub use future::WrappedFuture;
-pub use objects::{Object, ObjectInfo, ObjectType, UploadInfo};
-pub use path::ObjectPath;
-pub use stream::WrappedStream;
+pub use glob::Glob;
+pub use limiter::ConcurrencyLimiter;
+pub use list::{ListOptions, ListPage, ListToken};
+pub use objects::{Object, ObjectInfo, ObjectType, UploadInfo, WriteMode};
+pub use open_file::OpenFile;
+pub use path::{DirectorySemantics, ObjectPath, PathPolicy};
+pub use progress::{ProgressEvent, ProgressListener};
+pub use retry::{
+    RetryPolicy, DEFAULT_INITIAL_DELAY, DEFAULT_MAX_ATTEMPTS, DEFAULT_MAX_DELAY,
+    DEFAULT_MAX_RETRY_AFTER,
+};
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+pub use source::PathSource;
+pub use source::{BufferedSource, RewindableStream, UploadSource};
+pub use stream::{StreamOptions, WrappedStream};
+pub use writer::FileWriter;
 
 /// The data type used for streaming data from and to files.
 pub type Data = Bytes;
@@ -52,51 +74,24 @@ pub type OperationCompleteFuture = WrappedFuture<StorageResult<()>>;
 pub type WriteCompleteFuture = WrappedFuture<Result<(), TransferError>>;
 /// A future that resolves to a [`DataStream`](type.DataStream.html).
 pub type DataStreamFuture = WrappedFuture<StorageResult<DataStream>>;
+/// A future that resolves to the [`Object`](enum.Object.html) a write operation
+/// created.
+pub type WrittenObjectFuture = WrappedFuture<Result<Object, TransferError>>;
+/// A future that resolves to [`Data`](type.Data.html).
+pub type DataFuture = WrappedFuture<StorageResult<Data>>;
+/// A future that resolves to a size in bytes.
+pub type SizeFuture = WrappedFuture<StorageResult<u64>>;
+/// A future that resolves to a `String`.
+pub type StringFuture = WrappedFuture<StorageResult<String>>;
+/// A future that resolves to an [`OpenFile`](struct.OpenFile.html).
+pub type OpenFileFuture = WrappedFuture<StorageResult<OpenFile>>;
 /// A future that resolves when the copy is complete.
 pub type CopyCompleteFuture = WrappedFuture<Result<(), TransferError>>;
 /// A future that resolves when the move is complete.
 pub type MoveCompleteFuture = WrappedFuture<Result<(), TransferError>>;
-
-pub(crate) struct BlockingStreamReader<S>
-where
-    S: Unpin + Stream,
-{
-    stream: BlockingStream<S>,
-    reader: Option<Box<dyn io::Read>>,
-}
-
-impl<S> BlockingStreamReader<S>
-where
-    S: Unpin + Stream,
-{
-    pub fn from_stream(stream: S) -> BlockingStreamReader<S> {
-        BlockingStreamReader {
-            stream: block_on_stream(stream),
-            reader: None,
-        }
-    }
-}
-
-impl<S, O, E> io::Read for BlockingStreamReader<S>
-where
-    S: Unpin + Stream<Item = Result<O, E>>,
-    O: IntoBuf,
-    <O as IntoBuf>::Buf: 'static,
-    E: Into<StorageError>,
-{
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        match self.reader {
-            Some(ref mut r) => r.read(buf),
-            None => match self.stream.next() {
-                Some(Ok(d)) => {
-                    let mut reader = d.into_buf().reader();
-                    let count = reader.read(buf)?;
-                    self.reader = Some(Box::new(reader));
-                    Ok(count)
-                }
-                Some(Err(e)) => Err(e.into().into()),
-                None => Ok(0),
-            },
-        }
-    }
-}
+/// A future that resolves once a batch of deletes is complete.
+pub type DeleteSummaryFuture = WrappedFuture<DeleteSummary>;
+/// A future that resolves to a [`ListPage`](struct.ListPage.html).
+pub type ListPageFuture = WrappedFuture<StorageResult<ListPage>>;
+/// A future that resolves to a list of [`ObjectPath`s](struct.ObjectPath.html).
+pub type PathListFuture = WrappedFuture<StorageResult<Vec<ObjectPath>>>;
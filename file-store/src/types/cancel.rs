@@ -0,0 +1,181 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Cancelling transfers that are in progress.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::future::TryFutureExt;
+use futures::stream::Stream;
+
+use super::stream::ResultStreamPoll;
+use super::*;
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+/// Cancels the transfers it is attached to.
+///
+/// Attach a token to a write, copy or move with
+/// [`UploadInfo::cancellation`](struct.UploadInfo.html#method.cancellation).
+/// Once [`cancel`](#method.cancel) is called the data being written fails with
+/// a [`Cancelled`](enum.StorageErrorKind.html#variant.Cancelled) error so the
+/// backend stops and cleans up whatever it partially wrote, cancelling B2
+/// large file uploads and removing partial local files. Copies and moves the
+/// backend performs itself are abandoned. Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+impl CancellationToken {
+    /// Creates a token that has not been cancelled.
+    pub fn new() -> CancellationToken {
+        Default::default()
+    }
+
+    /// Cancels the transfers using this token.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+
+        let wakers: Vec<Waker> = self.state.wakers.lock().unwrap().drain(..).collect();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Checks whether this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn poll_cancelled(&self, cx: &mut Context) -> Poll<()> {
+        if self.is_cancelled() {
+            return Poll::Ready(());
+        }
+
+        {
+            let mut wakers = self.state.wakers.lock().unwrap();
+            if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+
+        // The token may have been cancelled before the waker was registered.
+        if self.is_cancelled() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Fails a stream of data once this token is cancelled.
+    pub(crate) fn wrap_stream(&self, stream: DataStream) -> DataStream {
+        DataStream::from_stream(CancellableStream {
+            token: self.clone(),
+            inner: stream,
+            finished: false,
+        })
+    }
+
+    /// Fails the data read from an upload source once this token is
+    /// cancelled.
+    pub(crate) fn wrap_source(&self, source: Arc<dyn UploadSource>) -> Arc<dyn UploadSource> {
+        Arc::new(CancellableSource {
+            token: self.clone(),
+            inner: source,
+        })
+    }
+
+    /// Abandons an operation once this token is cancelled.
+    pub(crate) fn wrap_operation(&self, operation: WriteCompleteFuture) -> WriteCompleteFuture {
+        WriteCompleteFuture::from_future(CancellableOperation {
+            token: self.clone(),
+            inner: operation,
+        })
+    }
+}
+
+fn cancelled_error() -> StorageError {
+    error::cancelled(Some("The transfer was cancelled."))
+}
+
+struct CancellableStream {
+    token: CancellationToken,
+    inner: DataStream,
+    finished: bool,
+}
+
+impl Stream for CancellableStream {
+    type Item = StorageResult<Data>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> ResultStreamPoll<Data> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+
+        if self.token.poll_cancelled(cx).is_ready() {
+            self.finished = true;
+            return Poll::Ready(Some(Err(cancelled_error())));
+        }
+
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+struct CancellableSource {
+    token: CancellationToken,
+    inner: Arc<dyn UploadSource>,
+}
+
+impl UploadSource for CancellableSource {
+    fn size(&self) -> SizeFuture {
+        self.inner.size()
+    }
+
+    fn read_range(&self, offset: u64, length: u64) -> DataStreamFuture {
+        if self.token.is_cancelled() {
+            return DataStreamFuture::from_value(Err(cancelled_error()));
+        }
+
+        let token = self.token.clone();
+        DataStreamFuture::from_future(
+            self.inner
+                .read_range(offset, length)
+                .map_ok(move |stream| token.wrap_stream(stream)),
+        )
+    }
+}
+
+struct CancellableOperation {
+    token: CancellationToken,
+    inner: WriteCompleteFuture,
+}
+
+impl Future for CancellableOperation {
+    type Output = Result<(), TransferError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.token.poll_cancelled(cx).is_ready() {
+            return Poll::Ready(Err(TransferError::TargetError(cancelled_error())));
+        }
+
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checksums of object content.
+use std::fmt;
+#[cfg(feature = "sha-1")]
+use std::pin::Pin;
+#[cfg(feature = "sha-1")]
+use std::task::{Context, Poll};
+
+#[cfg(feature = "sha-1")]
+use futures::stream::Stream;
+#[cfg(feature = "sha-1")]
+use sha1::{Digest, Sha1};
+
+use super::*;
+
+/// The algorithm used to calculate a [`Checksum`](struct.Checksum.html).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    /// SHA-1, as used by B2.
+    Sha1,
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChecksumAlgorithm::Sha1 => f.pad("sha1"),
+        }
+    }
+}
+
+/// A checksum of an object's content as stored by the backend.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    /// The algorithm used.
+    pub algorithm: ChecksumAlgorithm,
+    /// The digest as lower case hex.
+    pub digest: String,
+}
+
+impl Checksum {
+    /// Creates a new checksum.
+    pub fn new(algorithm: ChecksumAlgorithm, digest: &str) -> Checksum {
+        Checksum {
+            algorithm,
+            digest: digest.to_lowercase(),
+        }
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.digest)
+    }
+}
+
+/// Checks a file's data against its checksum as it is read, failing once the
+/// end is reached if they don't match.
+pub(crate) fn verify_stream(
+    stream: DataStream,
+    path: ObjectPath,
+    checksum: Checksum,
+) -> StorageResult<DataStream> {
+    match checksum.algorithm {
+        #[cfg(feature = "sha-1")]
+        ChecksumAlgorithm::Sha1 => Ok(DataStream::from_stream(VerifyingStream {
+            stream,
+            hasher: Some(Sha1::new()),
+            path,
+            expected: checksum.digest,
+        })),
+        #[cfg(not(feature = "sha-1"))]
+        ChecksumAlgorithm::Sha1 => {
+            let _ = stream;
+            Err(error::invalid_settings(Some(&format!(
+                "Unable to verify {}, the sha-1 feature is required",
+                path
+            ))))
+        }
+    }
+}
+
+#[cfg(feature = "sha-1")]
+struct VerifyingStream {
+    stream: DataStream,
+    hasher: Option<Sha1>,
+    path: ObjectPath,
+    expected: String,
+}
+
+#[cfg(feature = "sha-1")]
+impl Stream for VerifyingStream {
+    type Item = StorageResult<Data>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.hasher.is_none() {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut self.stream).poll_next(cx) {
+            Poll::Ready(Some(Ok(data))) => {
+                if let Some(ref mut hasher) = self.hasher {
+                    hasher.input(&data);
+                }
+                Poll::Ready(Some(Ok(data)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                // The hash can never be completed.
+                self.hasher.take();
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                let hasher = match self.hasher.take() {
+                    Some(hasher) => hasher,
+                    None => return Poll::Ready(None),
+                };
+
+                let actual = format!("{:x}", hasher.result());
+                if actual == self.expected {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Err(error::integrity_error(
+                        self.path.clone(),
+                        Some(&format!("Expected {} but read {}", self.expected, actual)),
+                    ))))
+                }
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
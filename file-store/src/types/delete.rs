@@ -0,0 +1,36 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The results of deleting many objects at once.
+use super::*;
+
+/// The outcome of [deleting many objects](../trait.StorageBackend.html#method.delete_objects).
+#[derive(Debug, Default)]
+pub struct DeleteSummary {
+    /// The objects that were deleted.
+    pub deleted: Vec<ObjectPath>,
+    /// The objects that could not be deleted along with the error.
+    pub failed: Vec<(ObjectPath, StorageError)>,
+    /// The paths passed to
+    /// [`FileStore::delete_objects`](../enum.FileStore.html#method.delete_objects)
+    /// that could not be parsed, as given, along with the error.
+    pub invalid: Vec<(String, StorageError)>,
+}
+
+impl DeleteSummary {
+    /// Returns whether every object was deleted.
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.invalid.is_empty()
+    }
+}
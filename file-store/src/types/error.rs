@@ -32,6 +32,10 @@ pub enum StorageErrorKind {
     NotFound(ObjectPath),
     /// The object already exists.
     AlreadyExists(ObjectPath),
+    /// The object has changed since it was last read.
+    PreconditionFailed(ObjectPath),
+    /// The data read for the object did not match its checksum.
+    IntegrityError(ObjectPath),
     /// The operation was cancelled.
     Cancelled,
     /// The connection to storage failed.
@@ -51,6 +55,8 @@ pub enum StorageErrorKind {
     InvalidSettings,
     /// Some kind of limit on use use of the service has been reached.
     OverQuota,
+    /// The storage does not allow changes.
+    ReadOnly,
     /// An internal failure, please report a bug!
     InternalError,
     /// Any other type of error (normally will have an inner error).
@@ -79,6 +85,11 @@ impl StorageError {
         self.kind.clone()
     }
 
+    /// Returns any additional detail about the error.
+    pub fn detail(&self) -> Option<&str> {
+        self.detail.as_ref().map(String::as_str)
+    }
+
     // fn write<A, B>(&self, f: &mut fmt::Formatter, with_detail: A, without_detail: B) -> fmt::Result
     // where
     //     A: AsRef<str>,
@@ -118,6 +129,12 @@ impl fmt::Display for StorageError {
             StorageErrorKind::AlreadyExists(p) => {
                 self.default_write(f, format!("The path '{}' already exists", p))
             }
+            StorageErrorKind::PreconditionFailed(p) => {
+                self.default_write(f, format!("The path '{}' has changed", p))
+            }
+            StorageErrorKind::IntegrityError(p) => {
+                self.default_write(f, format!("The data for '{}' was corrupted", p))
+            }
             StorageErrorKind::InvalidData => self.default_write(f, "Invalid data"),
             StorageErrorKind::Cancelled => self.default_write(f, "The operation was cancelled"),
             StorageErrorKind::ConnectionFailed => {
@@ -136,6 +153,7 @@ impl fmt::Display for StorageError {
             StorageErrorKind::OverQuota => {
                 self.default_write(f, "A storage limit has been reached")
             }
+            StorageErrorKind::ReadOnly => self.default_write(f, "The storage is read-only"),
             StorageErrorKind::ServiceError => {
                 self.default_write(f, "The storage system encountered an error")
             }
@@ -150,6 +168,8 @@ impl From<StorageError> for io::Error {
             StorageErrorKind::InvalidPath(_) => io::ErrorKind::InvalidData,
             StorageErrorKind::NotFound(_) => io::ErrorKind::NotFound,
             StorageErrorKind::AlreadyExists(_) => io::ErrorKind::AlreadyExists,
+            StorageErrorKind::PreconditionFailed(_) => io::ErrorKind::Other,
+            StorageErrorKind::IntegrityError(_) => io::ErrorKind::InvalidData,
             StorageErrorKind::InvalidData => io::ErrorKind::InvalidData,
             StorageErrorKind::InvalidSettings => io::ErrorKind::InvalidInput,
             StorageErrorKind::Cancelled => io::ErrorKind::ConnectionAborted,
@@ -161,6 +181,7 @@ impl From<StorageError> for io::Error {
             StorageErrorKind::AccessExpired => io::ErrorKind::PermissionDenied,
             StorageErrorKind::ServiceError => io::ErrorKind::Other,
             StorageErrorKind::OverQuota => io::ErrorKind::Other,
+            StorageErrorKind::ReadOnly => io::ErrorKind::PermissionDenied,
         };
 
         io::Error::new(kind, error)
@@ -208,6 +229,15 @@ pub enum TransferError {
     TargetError(StorageError),
 }
 
+impl From<TransferError> for StorageError {
+    fn from(error: TransferError) -> StorageError {
+        match error {
+            TransferError::SourceError(e) => e,
+            TransferError::TargetError(e) => e,
+        }
+    }
+}
+
 pub fn parse_error(spec: &str, detail: Option<&str>) -> StorageError {
     StorageError::new(StorageErrorKind::ObjectPathParse(spec.to_owned()), detail)
 }
@@ -224,10 +254,22 @@ pub fn already_exists(path: ObjectPath, detail: Option<&str>) -> StorageError {
     StorageError::new(StorageErrorKind::AlreadyExists(path), detail)
 }
 
+pub fn precondition_failed(path: ObjectPath, detail: Option<&str>) -> StorageError {
+    StorageError::new(StorageErrorKind::PreconditionFailed(path), detail)
+}
+
+pub fn integrity_error(path: ObjectPath, detail: Option<&str>) -> StorageError {
+    StorageError::new(StorageErrorKind::IntegrityError(path), detail)
+}
+
 pub fn over_quota(detail: Option<&str>) -> StorageError {
     StorageError::new(StorageErrorKind::OverQuota, detail)
 }
 
+pub fn read_only(detail: Option<&str>) -> StorageError {
+    StorageError::new(StorageErrorKind::ReadOnly, detail)
+}
+
 pub fn access_denied(detail: Option<&str>) -> StorageError {
     StorageError::new(StorageErrorKind::AccessDenied, detail)
 }
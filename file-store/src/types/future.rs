@@ -36,7 +36,8 @@ impl<R> WrappedFuture<R>
 where
     R: Send + 'static,
 {
-    pub(crate) fn from_future<F>(base: F) -> WrappedFuture<F::Output>
+    /// Wraps the given future.
+    pub fn from_future<F>(base: F) -> WrappedFuture<F::Output>
     where
         F: Future + Send + 'static,
         F::Output: Send,
@@ -46,7 +47,8 @@ where
         }
     }
 
-    pub(crate) fn from_value(value: R) -> WrappedFuture<R> {
+    /// Creates a future that resolves immediately to the given value.
+    pub fn from_value(value: R) -> WrappedFuture<R> {
         WrappedFuture {
             base: Box::pin(ready(value)),
         }
@@ -0,0 +1,229 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Matching paths against glob patterns.
+use std::fmt;
+
+use super::*;
+
+/// A single character matcher within a path part.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    /// A character that must match exactly.
+    Literal(char),
+    /// `?`, matches any single character.
+    Any,
+    /// `*`, matches any number of characters.
+    Star,
+    /// `[...]`, matches any of the characters or ranges, or none of them if
+    /// negated.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(l) => *l == c,
+            Token::Any => true,
+            Token::Star => true,
+            Token::Class { negated, ranges } => {
+                ranges.iter().any(|(start, end)| *start <= c && c <= *end) != *negated
+            }
+        }
+    }
+}
+
+/// A part of the pattern between `/` characters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Part {
+    /// `**`, matches any number of path parts.
+    Recursive,
+    /// Matches a single path part.
+    Tokens(Vec<Token>),
+}
+
+fn parse_part(pattern: &str, part: &str) -> StorageResult<Part> {
+    if part == "**" {
+        return Ok(Part::Recursive);
+    }
+
+    let mut tokens = Vec::new();
+    let mut chars = part.chars();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '?' => Token::Any,
+            '*' => {
+                if tokens.last() == Some(&Token::Star) {
+                    continue;
+                }
+                Token::Star
+            }
+            '[' => {
+                let mut negated = false;
+                let mut class = Vec::new();
+                let mut closed = false;
+                for c in &mut chars {
+                    match c {
+                        '!' if class.is_empty() && !negated => negated = true,
+                        // A ']' straight after the opening is part of the class.
+                        ']' if !class.is_empty() => {
+                            closed = true;
+                            break;
+                        }
+                        c => class.push(c),
+                    }
+                }
+
+                if !closed {
+                    return Err(error::parse_error(
+                        pattern,
+                        Some("Character classes must end with a ']' character."),
+                    ));
+                }
+
+                let mut ranges = Vec::new();
+                let mut index = 0;
+                while index < class.len() {
+                    if index + 2 < class.len() && class[index + 1] == '-' {
+                        ranges.push((class[index], class[index + 2]));
+                        index += 3;
+                    } else {
+                        ranges.push((class[index], class[index]));
+                        index += 1;
+                    }
+                }
+
+                Token::Class { negated, ranges }
+            }
+            c => Token::Literal(c),
+        };
+        tokens.push(token);
+    }
+
+    Ok(Part::Tokens(tokens))
+}
+
+/// Matches a single path part against tokens.
+fn match_tokens(tokens: &[Token], text: &[char]) -> bool {
+    match tokens.split_first() {
+        None => text.is_empty(),
+        Some((Token::Star, rest)) => (0..=text.len()).any(|skip| match_tokens(rest, &text[skip..])),
+        Some((token, rest)) => match text.split_first() {
+            Some((c, remaining)) => token.matches(*c) && match_tokens(rest, remaining),
+            None => false,
+        },
+    }
+}
+
+fn match_parts(parts: &[Part], path: &[&str]) -> bool {
+    match parts.split_first() {
+        None => path.is_empty(),
+        Some((Part::Recursive, rest)) => {
+            (0..=path.len()).any(|skip| match_parts(rest, &path[skip..]))
+        }
+        Some((Part::Tokens(tokens), rest)) => match path.split_first() {
+            Some((part, remaining)) => {
+                let chars: Vec<char> = part.chars().collect();
+                match_tokens(tokens, &chars) && match_parts(rest, remaining)
+            }
+            None => false,
+        },
+    }
+}
+
+/// A glob pattern that matches [`ObjectPath`s](struct.ObjectPath.html).
+///
+/// Patterns are split into parts by the `/` character and each part must match
+/// a part of the path. Within a part `*` matches any number of characters, `?`
+/// matches a single character and `[...]` matches any of the enclosed
+/// characters or ranges like `a-z`, or any other character if the class starts
+/// with `!`. A part that is just `**` matches any number of path parts,
+/// including none. Matching is case sensitive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Glob {
+    pattern: String,
+    parts: Vec<Part>,
+}
+
+impl Glob {
+    /// Parses a glob pattern.
+    pub fn new(pattern: &str) -> StorageResult<Glob> {
+        if pattern.starts_with('/') {
+            return Err(error::parse_error(
+                pattern,
+                Some("Glob patterns cannot start with the '/' character."),
+            ));
+        }
+
+        let parts = pattern
+            .split('/')
+            .map(|part| parse_part(pattern, part))
+            .collect::<StorageResult<Vec<Part>>>()?;
+
+        Ok(Glob {
+            pattern: pattern.to_owned(),
+            parts,
+        })
+    }
+
+    /// Checks whether a path matches this pattern.
+    pub fn matches(&self, path: &ObjectPath) -> bool {
+        match_parts(&self.parts, &path.parts())
+    }
+
+    /// Gets the longest prefix that every matching path must start with.
+    ///
+    /// This is the literal text at the start of the pattern, it may end part
+    /// way through a path part.
+    pub fn prefix(&self) -> ObjectPath {
+        let mut prefix = String::new();
+        for (index, part) in self.parts.iter().enumerate() {
+            if index > 0 {
+                prefix.push('/');
+            }
+
+            let tokens = match part {
+                Part::Recursive => break,
+                Part::Tokens(tokens) => tokens,
+            };
+
+            let mut literal = true;
+            for token in tokens {
+                match token {
+                    Token::Literal(c) => prefix.push(*c),
+                    _ => {
+                        literal = false;
+                        break;
+                    }
+                }
+            }
+
+            if !literal {
+                break;
+            }
+        }
+
+        // The pattern never starts with '/' so this can never fail.
+        ObjectPath::new(prefix).unwrap_or_else(|_| ObjectPath::empty())
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(&self.pattern)
+    }
+}
@@ -0,0 +1,78 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Limits on the number of transfers running at once.
+use std::convert::Infallible;
+use std::future::Future;
+
+use super::*;
+use crate::utils::{Acquired, CloningPool};
+
+/// Bounds the number of transfers running at the same time.
+///
+/// A limiter can be shared by any number of [`FileStore`s](enum.FileStore.html)
+/// with [`FileStore::with_concurrency_limiter`](enum.FileStore.html#method.with_concurrency_limiter)
+/// and used for [prefix copies](copy/struct.CopyOptions.html#method.limiter)
+/// and [synchronisation](sync/struct.SyncOptions.html#method.limiter) to
+/// bound the transfers made by the whole process. Clones share the same
+/// limit.
+///
+/// Every write, copy and move holds a permit until it completes and waits
+/// for one to be free before starting. Reading a file does not hold a permit,
+/// a read streamed into a write is counted by the write. This means copying
+/// between two stores that share a limiter cannot deadlock waiting for a
+/// second permit.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimiter {
+    permits: CloningPool<()>,
+    limit: usize,
+}
+
+impl ConcurrencyLimiter {
+    /// Creates a limiter that allows `limit` transfers at once. At least one
+    /// transfer is always allowed.
+    pub fn new(limit: usize) -> ConcurrencyLimiter {
+        let limit = limit.max(1);
+
+        ConcurrencyLimiter {
+            permits: CloningPool::new((), Some(limit)),
+            limit,
+        }
+    }
+
+    /// Gets the number of transfers allowed at once.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Waits for a permit to start a transfer. The permit is returned when
+    /// dropped.
+    pub(crate) async fn acquire(&self) -> Acquired<(), (), Infallible> {
+        self.permits.acquire().await
+    }
+
+    /// Runs an operation once a permit is available, holding the permit until
+    /// the operation completes.
+    pub(crate) fn limit_operation<F>(&self, operation: F) -> WrappedFuture<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send,
+    {
+        let limiter = self.clone();
+        WrappedFuture::from_future(async move {
+            let _permit = limiter.acquire().await;
+            operation.await
+        })
+    }
+}
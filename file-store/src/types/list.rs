@@ -0,0 +1,197 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Options for controlling what a listing returns and paged listings.
+use std::fmt;
+use std::str::FromStr;
+
+use futures::future::{ready, TryFutureExt};
+use futures::stream::{StreamExt, TryStreamExt};
+
+use super::*;
+
+/// Options for listing objects, see
+/// [`FileStore::list_objects_with_options`](../enum.FileStore.html#method.list_objects_with_options).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListOptions {
+    max_results: Option<usize>,
+    start_after: Option<ObjectPath>,
+    exclude_directories: bool,
+    max_depth: Option<usize>,
+}
+
+impl ListOptions {
+    /// Creates the default options, which list exactly what
+    /// [`list_objects`](../enum.FileStore.html#method.list_objects) does.
+    pub fn new() -> ListOptions {
+        Default::default()
+    }
+
+    /// Stops the listing after `count` objects have been returned.
+    pub fn max_results(mut self, count: usize) -> ListOptions {
+        self.max_results = Some(count);
+        self
+    }
+
+    /// Only returns objects whose path sorts after `path`.
+    ///
+    /// Passing the path of the last object seen in a previous listing
+    /// continues where that listing left off for backends that list objects in
+    /// path order, such as B2.
+    pub fn start_after(mut self, path: ObjectPath) -> ListOptions {
+        self.start_after = Some(path);
+        self
+    }
+
+    /// Sets whether directory objects are included. They are by default.
+    pub fn include_directories(mut self, include: bool) -> ListOptions {
+        self.exclude_directories = !include;
+        self
+    }
+
+    /// Only returns objects at most `depth` levels beneath the directory
+    /// containing the prefix. A depth of 1 returns only the objects directly
+    /// within that directory.
+    pub fn max_depth(mut self, depth: usize) -> ListOptions {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Gets the maximum number of objects returned.
+    pub fn max_result_count(&self) -> Option<usize> {
+        self.max_results
+    }
+
+    /// Gets the path that returned objects must sort after.
+    pub fn start_after_path(&self) -> Option<&ObjectPath> {
+        self.start_after.as_ref()
+    }
+
+    /// Gets whether directory objects are included.
+    pub fn includes_directories(&self) -> bool {
+        !self.exclude_directories
+    }
+
+    /// Gets the maximum depth of returned objects.
+    pub fn max_depth_limit(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    /// Checks whether an object should be included, ignoring `max_results`.
+    pub(crate) fn matches(&self, prefix: &ObjectPath, object: &Object) -> bool {
+        if self.exclude_directories && object.object_type() == ObjectType::Directory {
+            return false;
+        }
+
+        let path = object.path();
+        if let Some(ref start) = self.start_after {
+            if path <= *start {
+                return false;
+            }
+        }
+
+        if let Some(max) = self.max_depth {
+            // The last part of the prefix is either empty or a partial name.
+            let base = prefix.parts().len().saturating_sub(1);
+            let mut parts = path.parts();
+            if parts.last() == Some(&"") {
+                parts.pop();
+            }
+
+            if parts.len().saturating_sub(base) > max {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Applies these options to a listing of `prefix`.
+    pub(crate) fn apply(
+        &self,
+        prefix: ObjectPath,
+        listing: ObjectStreamFuture,
+    ) -> ObjectStreamFuture {
+        let options = self.clone();
+        ObjectStreamFuture::from_future(listing.map_ok(move |stream| {
+            let limit = options.max_results;
+            let filtered = stream.try_filter(move |o| ready(options.matches(&prefix, o)));
+
+            match limit {
+                Some(count) => ObjectStream::from_stream(filtered.take(count)),
+                None => ObjectStream::from_stream(filtered),
+            }
+        }))
+    }
+}
+
+/// Marks where a [paged listing](../enum.FileStore.html#method.list_objects_page)
+/// should continue from.
+///
+/// Tokens can be persisted by converting them to a string and parsing it back
+/// later. The string form is opaque and should not be relied upon.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListToken {
+    after: Option<ObjectPath>,
+}
+
+impl ListToken {
+    /// Creates a token that starts a listing from the beginning.
+    pub fn start() -> ListToken {
+        Default::default()
+    }
+
+    pub(crate) fn after(path: ObjectPath) -> ListToken {
+        ListToken { after: Some(path) }
+    }
+
+    pub(crate) fn options(&self) -> ListOptions {
+        match self.after {
+            Some(ref path) => ListOptions::new().start_after(path.clone()),
+            None => ListOptions::new(),
+        }
+    }
+}
+
+impl fmt::Display for ListToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.after {
+            Some(ref path) => write!(f, "after:{}", path),
+            None => f.pad("start"),
+        }
+    }
+}
+
+impl FromStr for ListToken {
+    type Err = StorageError;
+
+    fn from_str(s: &str) -> Result<ListToken, StorageError> {
+        if s == "start" {
+            Ok(ListToken::start())
+        } else if s.starts_with("after:") {
+            Ok(ListToken::after(ObjectPath::new(&s[6..])?))
+        } else {
+            Err(error::invalid_data(Some("Invalid list token.")))
+        }
+    }
+}
+
+/// A single page of a listing.
+#[derive(Clone, Debug)]
+pub struct ListPage {
+    /// The objects in this page, in path order.
+    pub objects: Vec<Object>,
+    /// The token for the next page or `None` if this was the last page.
+    pub next: Option<ListToken>,
+}
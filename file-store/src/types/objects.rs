@@ -15,15 +15,18 @@
 //! Object types.
 
 use std::cmp::{Ordering, PartialOrd};
+use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
 use std::time::SystemTime;
 
-use enum_dispatch::enum_dispatch;
-
 use super::*;
+#[cfg(feature = "b2")]
 use crate::backends::b2::B2Object;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
 use crate::backends::file::FileObject;
+#[cfg(feature = "remote")]
+use crate::backends::remote::RemoteObject;
 
 /// An object's type. For most backends this will just be File.
 ///
@@ -91,7 +94,6 @@ impl fmt::Display for ObjectType {
     }
 }
 
-#[enum_dispatch(ObjectInfo)]
 /// An object of some kind that exists at a path in the storage system.
 ///
 /// Most backends only support File objects, and this crate only really supports
@@ -100,8 +102,90 @@ impl fmt::Display for ObjectType {
 #[allow(missing_docs)]
 #[derive(Clone, Debug)]
 pub enum Object {
+    #[cfg(feature = "b2")]
     B2(B2Object),
+    #[cfg(all(feature = "file", not(feature = "wasm")))]
     File(FileObject),
+    #[cfg(feature = "remote")]
+    Remote(RemoteObject),
+}
+
+/// Implements the conversions between `Object` and a backend's object type.
+macro_rules! object_variant {
+    ($variant:ident, $object:ty) => {
+        impl From<$object> for Object {
+            fn from(object: $object) -> Object {
+                Object::$variant(object)
+            }
+        }
+
+        impl TryFrom<Object> for $object {
+            type Error = &'static str;
+
+            fn try_from(object: Object) -> Result<$object, &'static str> {
+                match object {
+                    Object::$variant(o) => Ok(o),
+                    #[allow(unreachable_patterns)]
+                    _ => Err("Object is not of the requested type."),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(feature = "b2")]
+object_variant!(B2, B2Object);
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+object_variant!(File, FileObject);
+#[cfg(feature = "remote")]
+object_variant!(Remote, RemoteObject);
+
+/// Calls a method on whichever backend's object an `Object` contains.
+macro_rules! dispatch {
+    ($object:expr, $inner:ident => $call:expr) => {
+        match $object {
+            #[cfg(feature = "b2")]
+            Object::B2($inner) => $call,
+            #[cfg(all(feature = "file", not(feature = "wasm")))]
+            Object::File($inner) => $call,
+            #[cfg(feature = "remote")]
+            Object::Remote($inner) => $call,
+        }
+    };
+}
+
+impl ObjectInfo for Object {
+    fn path(&self) -> ObjectPath {
+        dispatch!(self, o => o.path())
+    }
+
+    fn len(&self) -> u64 {
+        dispatch!(self, o => o.len())
+    }
+
+    fn object_type(&self) -> ObjectType {
+        dispatch!(self, o => o.object_type())
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        dispatch!(self, o => o.modified())
+    }
+
+    fn etag(&self) -> Option<String> {
+        dispatch!(self, o => o.etag())
+    }
+
+    fn metadata(&self) -> Option<HashMap<String, String>> {
+        dispatch!(self, o => o.metadata())
+    }
+
+    fn content_type(&self) -> Option<String> {
+        dispatch!(self, o => o.content_type())
+    }
+
+    fn checksum(&self) -> Option<Checksum> {
+        dispatch!(self, o => o.checksum())
+    }
 }
 
 impl PartialEq for Object {
@@ -133,7 +217,6 @@ impl Ord for Object {
 ///
 /// Some of the information is optional because not all storage backends can get
 /// access to it.
-#[enum_dispatch]
 pub trait ObjectInfo {
     /// Gets the object's path.
     fn path(&self) -> ObjectPath;
@@ -152,6 +235,35 @@ pub trait ObjectInfo {
     /// Gets the last modification time for the object.
     fn modified(&self) -> Option<SystemTime>;
 
+    /// Gets a tag that changes whenever the object's content changes. Used
+    /// with [`write_file_if_match`](enum.FileStore.html#method.write_file_if_match).
+    fn etag(&self) -> Option<String> {
+        None
+    }
+
+    /// Gets the user metadata stored with the object.
+    ///
+    /// Returns `None` if the backend doesn't support metadata or didn't load
+    /// it, generally metadata is only available from
+    /// [`get_object`](trait.StorageBackend.html#tymethod.get_object) and not
+    /// from listings.
+    fn metadata(&self) -> Option<HashMap<String, String>> {
+        None
+    }
+
+    /// Gets the MIME type stored with the object.
+    fn content_type(&self) -> Option<String> {
+        None
+    }
+
+    /// Gets the checksum of the object's content stored by the backend.
+    ///
+    /// Reads can be [verified](struct.StreamOptions.html#method.verify)
+    /// against this.
+    fn checksum(&self) -> Option<Checksum> {
+        None
+    }
+
     /// Creates an [`UploadInfo`](struct.UploadInfo.html) for uploading this
     /// object to a new path.
     fn as_upload<P>(&self, path: P) -> StorageResult<UploadInfo>
@@ -167,6 +279,29 @@ pub trait ObjectInfo {
     }
 }
 
+/// What a write does when something already exists at the target path.
+///
+/// Backends apply this as part of the write where the storage allows it,
+/// otherwise they check the target first and a write racing with the check
+/// may be replaced. Each backend's documentation says which it does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Replaces whatever is there, the default.
+    Overwrite,
+    /// Fails with an [`AlreadyExists`](enum.StorageErrorKind.html#variant.AlreadyExists)
+    /// error.
+    FailIfExists,
+    /// Leaves the existing object alone and reports success without writing
+    /// anything. A move that is skipped leaves its source in place too.
+    IgnoreIfExists,
+}
+
+impl Default for WriteMode {
+    fn default() -> WriteMode {
+        WriteMode::Overwrite
+    }
+}
+
 /// Information used to upload a file.
 ///
 /// This allows attempting to set various properties of a file on upload. Not
@@ -177,12 +312,96 @@ pub trait ObjectInfo {
 /// have `Into` implementations for this object so you may not need to create
 /// one of these manually enless there are specific properties you wish to
 /// change.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default)]
 pub struct UploadInfo {
     /// The path to upload to.
     pub path: ObjectPath,
     /// Sets the last modified time for the file.
     pub modified: Option<SystemTime>,
+    /// Allows replacing a directory at the path even when the `FileStore` has
+    /// [strict overwrites](enum.FileStore.html#method.with_strict_overwrites)
+    /// enabled.
+    pub replace_directory: bool,
+    /// Whether to remove whatever was partially written if the upload fails.
+    /// When unset the `FileStore`'s
+    /// [default](enum.FileStore.html#method.with_cleanup_on_failure) is used.
+    pub cleanup_on_failure: Option<bool>,
+    /// A token identifying this upload. Backends that keep versions of files
+    /// skip an upload whose token matches the current version, so repeating
+    /// an upload, whether by a retry or by the caller, doesn't create
+    /// duplicate versions.
+    pub idempotency_key: Option<String>,
+    /// Arbitrary user metadata to store with the file. B2 stores these as file
+    /// info, which allows at most 10 entries including those used internally,
+    /// and the file backend as extended attributes where the platform supports
+    /// them.
+    pub metadata: HashMap<String, String>,
+    /// The MIME type of the file. When unset B2 detects the type from the
+    /// file's extension.
+    pub content_type: Option<String>,
+    /// What to do when something already exists at the path. Backends that
+    /// can create a file only if it is absent do so, otherwise the `FileStore`
+    /// checks for an existing object first.
+    pub write_mode: WriteMode,
+    /// Receives reports of the data written.
+    pub progress: Option<ProgressListener>,
+    /// Cancels the write. A cancelled write always cleans up after itself.
+    pub cancellation: Option<CancellationToken>,
+}
+
+impl UploadInfo {
+    /// Sets whether to remove whatever was partially written if the upload
+    /// fails.
+    pub fn cleanup_on_failure(mut self, cleanup: bool) -> UploadInfo {
+        self.cleanup_on_failure = Some(cleanup);
+        self
+    }
+
+    /// Sets a token identifying this upload. See
+    /// [`idempotency_key`](#structfield.idempotency_key).
+    pub fn idempotency_key(mut self, key: &str) -> UploadInfo {
+        self.idempotency_key = Some(key.to_owned());
+        self
+    }
+
+    /// Adds an entry to the user [`metadata`](#structfield.metadata).
+    pub fn metadata(mut self, key: &str, value: &str) -> UploadInfo {
+        self.metadata.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Sets the [`content_type`](#structfield.content_type).
+    pub fn content_type(mut self, content_type: &str) -> UploadInfo {
+        self.content_type = Some(content_type.to_owned());
+        self
+    }
+
+    /// Sets the [`write_mode`](#structfield.write_mode).
+    pub fn write_mode(mut self, mode: WriteMode) -> UploadInfo {
+        self.write_mode = mode;
+        self
+    }
+
+    /// Reports the progress of the upload to `listener`.
+    pub fn progress(mut self, listener: ProgressListener) -> UploadInfo {
+        self.progress = Some(listener);
+        self
+    }
+
+    /// Allows cancelling the upload with `token`.
+    pub fn cancellation(mut self, token: CancellationToken) -> UploadInfo {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Whether a failed upload should remove what it partially wrote.
+    pub(crate) fn cleans_up(&self) -> bool {
+        self.cleanup_on_failure.unwrap_or(false)
+            || self
+                .cancellation
+                .as_ref()
+                .map_or(false, CancellationToken::is_cancelled)
+    }
 }
 
 impl<I> From<I> for UploadInfo
@@ -193,6 +412,9 @@ where
         UploadInfo {
             path: info.path(),
             modified: info.modified(),
+            metadata: info.metadata().unwrap_or_default(),
+            content_type: info.content_type(),
+            ..Default::default()
         }
     }
 }
@@ -201,7 +423,7 @@ impl From<ObjectPath> for UploadInfo {
     fn from(path: ObjectPath) -> UploadInfo {
         UploadInfo {
             path,
-            modified: None,
+            ..Default::default()
         }
     }
 }
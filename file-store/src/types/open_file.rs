@@ -0,0 +1,110 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Random access to the content of a file.
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use bytes::BytesMut;
+use futures::future::ready;
+use futures::stream::TryStreamExt;
+
+use super::*;
+
+/// A file opened for reading at arbitrary offsets, returned by
+/// [`FileStore::open_file`](../enum.FileStore.html#method.open_file).
+///
+/// Each read fetches just the requested range from the backend, using a
+/// `Range` request for B2 and a seek for local files. The length and other
+/// information are those of the file when it was opened, changes made to the
+/// file afterwards are not reflected here.
+#[derive(Clone, Debug)]
+pub struct OpenFile {
+    store: FileStore,
+    object: Object,
+}
+
+impl OpenFile {
+    pub(crate) fn new(store: FileStore, object: Object) -> OpenFile {
+        OpenFile { store, object }
+    }
+
+    /// Gets the object that was opened.
+    pub fn object(&self) -> &Object {
+        &self.object
+    }
+
+    /// Reads up to `length` bytes starting at `offset`.
+    ///
+    /// Fewer bytes are returned if the read extends beyond the end of the
+    /// file, no bytes are returned if it starts beyond the end.
+    pub fn read_at(&self, offset: u64, length: u64) -> DataFuture {
+        let length = length.min(self.object.len().saturating_sub(offset));
+        if length == 0 {
+            return DataFuture::from_value(Ok(Data::new()));
+        }
+
+        let stream = self
+            .store
+            .get_file_stream_range(self.object.path(), offset, Some(length));
+
+        DataFuture::from_future(async move {
+            let buffer = stream
+                .await?
+                .try_fold(
+                    BytesMut::with_capacity(length as usize),
+                    |mut buffer, data| {
+                        buffer.extend_from_slice(&data);
+                        ready(Ok(buffer))
+                    },
+                )
+                .await?;
+            Ok(buffer.freeze())
+        })
+    }
+}
+
+impl ObjectInfo for OpenFile {
+    fn path(&self) -> ObjectPath {
+        self.object.path()
+    }
+
+    fn len(&self) -> u64 {
+        self.object.len()
+    }
+
+    fn object_type(&self) -> ObjectType {
+        self.object.object_type()
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.object.modified()
+    }
+
+    fn etag(&self) -> Option<String> {
+        self.object.etag()
+    }
+
+    fn metadata(&self) -> Option<HashMap<String, String>> {
+        self.object.metadata()
+    }
+
+    fn content_type(&self) -> Option<String> {
+        self.object.content_type()
+    }
+
+    fn checksum(&self) -> Option<Checksum> {
+        self.object.checksum()
+    }
+}
@@ -32,7 +32,7 @@ use super::error;
 /// Paths to objects must not start with a `/` character. For all methods other
 /// than [`list_objects`](enum.FileStore.html#method.list_objects) the path
 /// also must not end with a `/` character.
-#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ObjectPath {
     path: String,
 }
@@ -178,3 +178,172 @@ impl TryFrom<&str> for ObjectPath {
         ObjectPath::new(s)
     }
 }
+
+/// How a [`FileStore`](enum.FileStore.html) interprets the prefix passed to
+/// [`list_objects`](enum.FileStore.html#method.list_objects).
+///
+/// Backends differ in how they list objects. The file backend includes
+/// directories in its results and fails if the prefix's directory doesn't
+/// exist while B2 only has files and treats the prefix as a plain string, so
+/// listing `dir` includes `dir2/file` and a file named `dir`. The other
+/// settings give the same results for every backend.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DirectorySemantics {
+    /// Returns whatever the backend returns. This is the default.
+    Native,
+    /// Returns every file whose path starts with the prefix, compared as a
+    /// string. Directories are not included.
+    Prefix,
+    /// Treats the prefix as a directory whether or not it ends with a `/`, so
+    /// `dir` and `dir/` both return every file beneath `dir`. Directories are
+    /// not included.
+    StrictSlash,
+}
+
+impl Default for DirectorySemantics {
+    fn default() -> DirectorySemantics {
+        DirectorySemantics::Native
+    }
+}
+
+/// The rules a backend places on the paths it can store.
+///
+/// Every [`FileStore`](enum.FileStore.html) checks paths against its backend's
+/// policy before making any request so that paths the backend can't handle
+/// fail early with an [`InvalidPath`](enum.StorageErrorKind.html#variant.InvalidPath)
+/// error describing the problem. The policy starts out permissive and each
+/// builder method adds a rule.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PathPolicy {
+    max_length: Option<usize>,
+    max_part_length: Option<usize>,
+    container_parts: usize,
+    disallowed_characters: Vec<char>,
+    reject_control_characters: bool,
+    reject_empty_parts: bool,
+    reserved_parts: Vec<String>,
+}
+
+impl PathPolicy {
+    /// Creates a policy that accepts any path.
+    pub fn new() -> PathPolicy {
+        Default::default()
+    }
+
+    /// Limits the length in bytes of the object's name, which is the path
+    /// without any [container parts](#method.container_parts).
+    pub fn max_length(mut self, bytes: usize) -> PathPolicy {
+        self.max_length = Some(bytes);
+        self
+    }
+
+    /// Limits the length in bytes of each directory part.
+    pub fn max_part_length(mut self, bytes: usize) -> PathPolicy {
+        self.max_part_length = Some(bytes);
+        self
+    }
+
+    /// Sets how many leading directory parts name a container, like a bucket,
+    /// rather than being part of the object's name.
+    pub fn container_parts(mut self, parts: usize) -> PathPolicy {
+        self.container_parts = parts;
+        self
+    }
+
+    /// Rejects paths containing any of the given characters.
+    pub fn disallow_characters(mut self, characters: &[char]) -> PathPolicy {
+        self.disallowed_characters.extend_from_slice(characters);
+        self
+    }
+
+    /// Rejects paths containing control characters.
+    pub fn disallow_control_characters(mut self) -> PathPolicy {
+        self.reject_control_characters = true;
+        self
+    }
+
+    /// Rejects paths with empty directory parts, like `dir//file`. A trailing
+    /// `/` is still allowed.
+    pub fn disallow_empty_parts(mut self) -> PathPolicy {
+        self.reject_empty_parts = true;
+        self
+    }
+
+    /// Rejects paths that have the given directory part, like `..`.
+    pub fn reserve_part(mut self, part: &str) -> PathPolicy {
+        self.reserved_parts.push(part.to_owned());
+        self
+    }
+
+    /// Stops rejecting a directory part reserved by
+    /// [`reserve_part`](#method.reserve_part).
+    pub(crate) fn allow_part(mut self, part: &str) -> PathPolicy {
+        self.reserved_parts.retain(|r| r != part);
+        self
+    }
+
+    /// Checks a path against this policy.
+    pub fn validate(&self, path: &ObjectPath) -> Result<(), error::StorageError> {
+        let invalid = |detail: String| Err(error::invalid_path(path.clone(), Some(&detail)));
+
+        if let Some(c) = path
+            .path
+            .chars()
+            .find(|c| self.disallowed_characters.contains(c))
+        {
+            return invalid(format!("The character {:?} is not allowed", c));
+        }
+
+        if self.reject_control_characters {
+            if let Some(c) = path.path.chars().find(|c| c.is_control()) {
+                return invalid(format!("The control character {:?} is not allowed", c));
+            }
+        }
+
+        let parts = path.parts();
+        let last = parts.len().saturating_sub(1);
+        for (index, part) in parts.iter().enumerate() {
+            if part.is_empty() && self.reject_empty_parts && index != last {
+                return invalid(String::from("Paths cannot contain empty directory parts"));
+            }
+
+            if self.reserved_parts.iter().any(|r| r == part) {
+                return invalid(format!("'{}' cannot be used as a directory part", part));
+            }
+
+            if let Some(max) = self.max_part_length {
+                if part.len() > max {
+                    return invalid(format!(
+                        "Directory parts can be at most {} bytes long but '{}' is {}",
+                        max,
+                        part,
+                        part.len()
+                    ));
+                }
+            }
+        }
+
+        if let Some(max) = self.max_length {
+            let length = parts
+                .iter()
+                .skip(self.container_parts)
+                .enumerate()
+                .map(|(index, part)| {
+                    if index > 0 {
+                        part.len() + 1
+                    } else {
+                        part.len()
+                    }
+                })
+                .sum::<usize>();
+            if length > max {
+                return invalid(format!(
+                    "Names can be at most {} bytes long but this is {}",
+                    max, length
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
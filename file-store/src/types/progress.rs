@@ -0,0 +1,108 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Progress reporting for transfers.
+use std::fmt;
+use std::sync::Arc;
+
+use futures::channel::mpsc::unbounded;
+use futures::stream::StreamExt;
+
+use super::*;
+
+/// Something that happened during a transfer.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// More data has been transferred.
+    Transferred {
+        /// The number of bytes transferred so far.
+        bytes: u64,
+        /// The total number of bytes to transfer, if known.
+        total: Option<u64>,
+    },
+    /// A part of a large upload finished uploading. Only B2 uploads in parts.
+    PartComplete {
+        /// The number of the part, starting at 1.
+        part: usize,
+    },
+}
+
+/// Receives [`ProgressEvent`s](enum.ProgressEvent.html) for a transfer.
+///
+/// Attach one to an upload with
+/// [`UploadInfo::progress`](struct.UploadInfo.html#method.progress) or to a
+/// download with [`StreamOptions::progress`](struct.StreamOptions.html#method.progress).
+/// Clones report to the same place.
+#[derive(Clone)]
+pub struct ProgressListener {
+    callback: Arc<dyn Fn(ProgressEvent) + Send + Sync>,
+}
+
+impl ProgressListener {
+    /// Creates a listener that calls `callback` for every event. The callback
+    /// is called from whatever task is performing the transfer so it should
+    /// return quickly.
+    pub fn new<F>(callback: F) -> ProgressListener
+    where
+        F: Fn(ProgressEvent) + Send + Sync + 'static,
+    {
+        ProgressListener {
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// Creates a listener along with a stream of the events it receives. The
+    /// stream ends once every clone of the listener has been dropped.
+    pub fn stream() -> (ProgressListener, WrappedStream<ProgressEvent>) {
+        let (sender, receiver) = unbounded();
+        let listener = ProgressListener::new(move |event| {
+            let _ = sender.unbounded_send(event);
+        });
+
+        (listener, WrappedStream::from_stream(receiver))
+    }
+
+    /// Reports an event.
+    pub fn report(&self, event: ProgressEvent) {
+        (self.callback)(event)
+    }
+
+    /// Reports the data passing through a stream.
+    pub(crate) fn track(&self, stream: DataStream, total: Option<u64>) -> DataStream {
+        let listener = self.clone();
+        let mut bytes: u64 = 0;
+
+        DataStream::from_stream(stream.map(move |result| {
+            if let Ok(ref data) = result {
+                bytes += data.len() as u64;
+                listener.report(ProgressEvent::Transferred { bytes, total });
+            }
+            result
+        }))
+    }
+}
+
+impl fmt::Debug for ProgressListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProgressListener").finish()
+    }
+}
+
+/// Listeners are equal when they are clones of each other.
+impl PartialEq for ProgressListener {
+    fn eq(&self, other: &ProgressListener) -> bool {
+        Arc::ptr_eq(&self.callback, &other.callback)
+    }
+}
+
+impl Eq for ProgressListener {}
@@ -0,0 +1,236 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Controls how failed requests are retried.
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::*;
+
+/// The number of attempts made at a request by default.
+pub const DEFAULT_MAX_ATTEMPTS: usize = 5;
+
+/// The delay before the first retry by default.
+pub const DEFAULT_INITIAL_DELAY: Duration = Duration::from_millis(100);
+
+/// The longest delay between retries by default.
+pub const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// The longest a server can ask for a retry to be delayed by default.
+pub const DEFAULT_MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Decides how many times and how quickly a failed request is retried.
+///
+/// The delay before each retry doubles, starting from an initial delay, until
+/// it reaches a maximum. With jitter enabled the actual delay is a random
+/// amount between half and all of that so that many clients failing at the
+/// same time don't all retry at the same time.
+///
+/// When a server says how long to wait before retrying, like B2 does when
+/// rate limiting, that delay is used instead as long as it isn't longer than
+/// [`max_retry_after`](#method.max_retry_after).
+///
+/// Which errors are worth retrying is normally decided by the backend, for
+/// example B2 retries rate limiting and server errors while the file backend
+/// retries interrupted system calls. Use [`retry_on`](#method.retry_on) to
+/// decide yourself.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    initial_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    honor_retry_after: bool,
+    max_retry_after: Duration,
+    classifier: Option<Arc<dyn Fn(&StorageError) -> bool + Send + Sync>>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_delay: DEFAULT_INITIAL_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            jitter: true,
+            honor_retry_after: true,
+            max_retry_after: DEFAULT_MAX_RETRY_AFTER,
+            classifier: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates the default policy. A request is attempted up to
+    /// [`DEFAULT_MAX_ATTEMPTS`](constant.DEFAULT_MAX_ATTEMPTS.html) times with
+    /// jittered delays starting at
+    /// [`DEFAULT_INITIAL_DELAY`](constant.DEFAULT_INITIAL_DELAY.html) and
+    /// growing to no more than
+    /// [`DEFAULT_MAX_DELAY`](constant.DEFAULT_MAX_DELAY.html).
+    pub fn new() -> RetryPolicy {
+        Default::default()
+    }
+
+    /// Creates a policy that never retries.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new().max_attempts(1)
+    }
+
+    /// Sets the maximum number of attempts made at a request, including the
+    /// first. Values less than 1 are treated as 1.
+    pub fn max_attempts(mut self, attempts: usize) -> RetryPolicy {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry and the longest delay between
+    /// retries. Use `Duration::from_secs(0)` for both to retry immediately.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> RetryPolicy {
+        self.initial_delay = initial;
+        self.max_delay = max.max(initial);
+        self
+    }
+
+    /// Sets whether delays are randomised.
+    pub fn jitter(mut self, jitter: bool) -> RetryPolicy {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets whether delays requested by the server are used instead of the
+    /// backoff delay. Defaults to true.
+    pub fn honor_retry_after(mut self, honor: bool) -> RetryPolicy {
+        self.honor_retry_after = honor;
+        self
+    }
+
+    /// Sets the longest delay requested by the server that is waited for.
+    /// Longer requests fail the request instead of retrying it early and
+    /// being refused again. Defaults to
+    /// [`DEFAULT_MAX_RETRY_AFTER`](constant.DEFAULT_MAX_RETRY_AFTER.html).
+    pub fn max_retry_after(mut self, max: Duration) -> RetryPolicy {
+        self.max_retry_after = max;
+        self
+    }
+
+    /// Decides which errors are retried with the given function instead of
+    /// leaving it to the backend. Errors that can never succeed, like an
+    /// invalid request, are worth leaving out.
+    pub fn retry_on<F>(mut self, classifier: F) -> RetryPolicy
+    where
+        F: Fn(&StorageError) -> bool + Send + Sync + 'static,
+    {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+
+    /// Gets the maximum number of attempts made at a request.
+    pub fn attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Checks whether another attempt should be made after `attempts` attempts
+    /// failed, the last with `error`. `transient` is the backend's own opinion
+    /// of whether the error is worth retrying.
+    pub(crate) fn should_retry(
+        &self,
+        attempts: usize,
+        error: &StorageError,
+        transient: bool,
+        requested: Option<Duration>,
+    ) -> bool {
+        if attempts >= self.max_attempts {
+            return false;
+        }
+
+        match requested {
+            Some(delay) if self.honor_retry_after && delay > self.max_retry_after => return false,
+            _ => (),
+        }
+
+        match self.classifier {
+            Some(ref classifier) => classifier(error),
+            None => transient,
+        }
+    }
+
+    /// The delay before the next attempt after `attempts` attempts failed.
+    /// `requested` is the delay the server asked for, if any.
+    pub(crate) fn delay(&self, attempts: usize, requested: Option<Duration>) -> Duration {
+        if let Some(delay) = requested.filter(|_| self.honor_retry_after) {
+            return delay;
+        }
+
+        let shift = attempts.saturating_sub(1).min(31) as u32;
+        let delay = self
+            .initial_delay
+            .checked_mul(1 << shift)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if self.jitter {
+            let half = delay / 2;
+            half + half.mul_f64(random_fraction())
+        } else {
+            delay
+        }
+    }
+}
+
+/// A random number in the range [0, 1). Doesn't need to be good, just enough
+/// to spread out retries.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(time) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u32(time.subsec_nanos());
+    }
+
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("jitter", &self.jitter)
+            .field("honor_retry_after", &self.honor_retry_after)
+            .field("max_retry_after", &self.max_retry_after)
+            .field("custom_classifier", &self.classifier.is_some())
+            .finish()
+    }
+}
+
+/// Policies are equal when they have the same settings and either both leave
+/// classifying errors to the backend or share the same classifier.
+impl PartialEq for RetryPolicy {
+    fn eq(&self, other: &RetryPolicy) -> bool {
+        self.max_attempts == other.max_attempts
+            && self.initial_delay == other.initial_delay
+            && self.max_delay == other.max_delay
+            && self.jitter == other.jitter
+            && self.honor_retry_after == other.honor_retry_after
+            && self.max_retry_after == other.max_retry_after
+            && match (&self.classifier, &other.classifier) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (None, None) => true,
+                _ => false,
+            }
+    }
+}
+
+impl Eq for RetryPolicy {}
@@ -0,0 +1,282 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Sources of data for uploads that can be read more than once.
+//!
+//! A [`DataStream`](../type.DataStream.html) can only be read once, so a
+//! backend that wants to retry a failed request has to keep a copy of
+//! everything it sent. An [`UploadSource`](trait.UploadSource.html) can instead
+//! be asked for any range of its data as many times as needed.
+use std::fmt;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+use std::io::SeekFrom;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::{ready, TryFutureExt};
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+use futures::stream::TryStreamExt;
+use futures::stream::{once, Stream};
+
+use super::stream::RangeStream;
+use super::*;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+use crate::utils::ReaderStream;
+
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+const MB: usize = 1024 * 1024;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+const INITIAL_BUFFER_SIZE: usize = 8 * MB;
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+const MIN_BUFFER_SIZE: usize = MB;
+
+/// Data for an upload that can be read from any position, any number of times.
+///
+/// Pass one to [`FileStore::write_file_from_source`](../enum.FileStore.html#method.write_file_from_source).
+pub trait UploadSource: Send + Sync + 'static {
+    /// The total number of bytes in the source.
+    fn size(&self) -> SizeFuture;
+
+    /// Streams `length` bytes of the source starting at `offset`.
+    fn read_range(&self, offset: u64, length: u64) -> DataStreamFuture;
+}
+
+impl UploadSource for Data {
+    fn size(&self) -> SizeFuture {
+        SizeFuture::from_value(Ok(self.len() as u64))
+    }
+
+    fn read_range(&self, offset: u64, length: u64) -> DataStreamFuture {
+        let start = (offset as usize).min(self.len());
+        let end = start + (length as usize).min(self.len() - start);
+        let data = self.slice(start, end);
+
+        DataStreamFuture::from_value(Ok(DataStream::from_stream(once(ready(Ok(data))))))
+    }
+}
+
+/// An upload source made of chunks of data held in memory.
+///
+/// The chunks are reference counted, reading the source any number of times
+/// shares them rather than copying them.
+#[derive(Clone, Default)]
+pub struct BufferedSource {
+    chunks: Arc<Vec<Data>>,
+    size: u64,
+}
+
+impl BufferedSource {
+    /// Creates a source from chunks of data.
+    pub fn new(chunks: Vec<Data>) -> BufferedSource {
+        let size = chunks.iter().map(|chunk| chunk.len() as u64).sum();
+        BufferedSource {
+            chunks: Arc::new(chunks),
+            size,
+        }
+    }
+
+    /// The number of bytes in the source.
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    /// Checks whether the source contains no data.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Streams all of the data in the source.
+    pub fn stream(&self) -> DataStream {
+        self.range(0, self.size)
+    }
+
+    fn range(&self, offset: u64, length: u64) -> DataStream {
+        DataStream::from_stream(ChunkStream::new(self.chunks.clone(), offset, length))
+    }
+}
+
+impl From<Vec<Data>> for BufferedSource {
+    fn from(chunks: Vec<Data>) -> BufferedSource {
+        BufferedSource::new(chunks)
+    }
+}
+
+impl fmt::Debug for BufferedSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufferedSource")
+            .field("chunks", &self.chunks.len())
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl UploadSource for BufferedSource {
+    fn size(&self) -> SizeFuture {
+        SizeFuture::from_value(Ok(self.size))
+    }
+
+    fn read_range(&self, offset: u64, length: u64) -> DataStreamFuture {
+        DataStreamFuture::from_value(Ok(self.range(offset, length)))
+    }
+}
+
+/// Streams a range of the chunks of a `BufferedSource`. Whole chunks are
+/// returned as they are, only chunks at the ends of the range are sliced.
+struct ChunkStream {
+    chunks: Arc<Vec<Data>>,
+    index: usize,
+    skip: usize,
+    remaining: u64,
+}
+
+impl ChunkStream {
+    fn new(chunks: Arc<Vec<Data>>, offset: u64, length: u64) -> ChunkStream {
+        let mut index = 0;
+        let mut skip = offset;
+        while index < chunks.len() && skip >= chunks[index].len() as u64 {
+            skip -= chunks[index].len() as u64;
+            index += 1;
+        }
+
+        ChunkStream {
+            chunks,
+            index,
+            skip: skip as usize,
+            remaining: length,
+        }
+    }
+}
+
+impl Stream for ChunkStream {
+    type Item = StorageResult<Data>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.remaining == 0 || this.index >= this.chunks.len() {
+            return Poll::Ready(None);
+        }
+
+        let chunk = &this.chunks[this.index];
+        let available = (chunk.len() - this.skip) as u64;
+        let data = if this.skip == 0 && available <= this.remaining {
+            chunk.clone()
+        } else {
+            let end = this.skip + available.min(this.remaining) as usize;
+            chunk.slice(this.skip, end)
+        };
+
+        this.remaining -= data.len() as u64;
+        this.index += 1;
+        this.skip = 0;
+        Poll::Ready(Some(Ok(data)))
+    }
+}
+
+/// An upload source that reads from a file on the local disk. Included with the
+/// feature "file".
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+#[derive(Clone, Debug)]
+pub struct PathSource {
+    path: PathBuf,
+}
+
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+impl PathSource {
+    /// Creates a source for the file at `path`.
+    pub fn new<P: Into<PathBuf>>(path: P) -> PathSource {
+        PathSource { path: path.into() }
+    }
+}
+
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+impl UploadSource for PathSource {
+    fn size(&self) -> SizeFuture {
+        async fn size(path: PathBuf) -> StorageResult<u64> {
+            let metadata = tokio_fs::metadata(path.clone()).await?;
+            if !metadata.is_file() {
+                return Err(error::invalid_data(Some(&format!(
+                    "{} is not a file.",
+                    path.display()
+                ))));
+            }
+
+            Ok(metadata.len())
+        }
+
+        SizeFuture::from_future(size(self.path.clone()))
+    }
+
+    fn read_range(&self, offset: u64, length: u64) -> DataStreamFuture {
+        async fn read(path: PathBuf, offset: u64, length: u64) -> StorageResult<DataStream> {
+            let mut file = tokio_fs::File::open(path).await?;
+            if offset > 0 {
+                file.seek(SeekFrom::Start(offset)).await?;
+            }
+
+            let stream = DataStream::from_stream(
+                ReaderStream::<tokio_fs::File>::stream(
+                    file,
+                    INITIAL_BUFFER_SIZE.min(length as usize).max(1),
+                    MIN_BUFFER_SIZE,
+                )
+                .map_err(StorageError::from),
+            );
+            Ok(DataStream::from_stream(RangeStream::new(
+                stream,
+                0,
+                Some(length),
+            )))
+        }
+
+        DataStreamFuture::from_future(read(self.path.clone(), offset, length))
+    }
+}
+
+/// An upload source built from a function that opens a fresh stream of the
+/// same data each time it is called.
+///
+/// Reading a range reopens the stream and skips to the start of the range so
+/// prefer a source that can seek when one is available.
+pub struct RewindableStream<F> {
+    size: u64,
+    open: F,
+}
+
+impl<F> RewindableStream<F>
+where
+    F: Fn() -> DataStreamFuture + Send + Sync + 'static,
+{
+    /// Creates a source of `size` bytes read from the streams that `open`
+    /// returns.
+    pub fn new(size: u64, open: F) -> RewindableStream<F> {
+        RewindableStream { size, open }
+    }
+}
+
+impl<F> UploadSource for RewindableStream<F>
+where
+    F: Fn() -> DataStreamFuture + Send + Sync + 'static,
+{
+    fn size(&self) -> SizeFuture {
+        SizeFuture::from_value(Ok(self.size))
+    }
+
+    fn read_range(&self, offset: u64, length: u64) -> DataStreamFuture {
+        DataStreamFuture::from_future((self.open)().map_ok(move |stream| {
+            DataStream::from_stream(RangeStream::new(stream, offset, Some(length)))
+        }))
+    }
+}
@@ -13,12 +13,13 @@
 // limitations under the License.
 
 //! A module with some useful tools for working with streams.
+use std::collections::VecDeque;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
 use futures::stream::Stream;
 
-use super::StorageResult;
+use super::{Data, DataStream, ProgressListener, StorageResult};
 
 pub(crate) type StreamPoll<R> = Poll<Option<R>>;
 pub(crate) type ResultStreamPoll<R> = StreamPoll<StorageResult<R>>;
@@ -37,7 +38,8 @@ impl<R> WrappedStream<R>
 where
     R: Send + 'static,
 {
-    pub(crate) fn from_stream<S>(base: S) -> WrappedStream<S::Item>
+    /// Wraps the given stream.
+    pub fn from_stream<S>(base: S) -> WrappedStream<S::Item>
     where
         S: Stream + Send + 'static,
         S::Item: Send,
@@ -183,3 +185,181 @@ where
         result
     }
 }
+
+/// Options for reading a file's data, see
+/// [`FileStore::get_file_stream_with_options`](enum.FileStore.html#method.get_file_stream_with_options).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamOptions {
+    read_ahead: usize,
+    verify: bool,
+    progress: Option<ProgressListener>,
+}
+
+impl StreamOptions {
+    /// Creates the default options, which read the file exactly as
+    /// [`get_file_stream`](enum.FileStore.html#method.get_file_stream) does.
+    pub fn new() -> StreamOptions {
+        Default::default()
+    }
+
+    /// Buffers up to `chunks` chunks of data that have arrived from the
+    /// backend but not been consumed yet.
+    ///
+    /// Whenever the stream is polled it takes every chunk the backend has
+    /// ready, up to this limit, so the backend can carry on fetching while the
+    /// consumer works through the buffer.
+    pub fn read_ahead(mut self, chunks: usize) -> StreamOptions {
+        self.read_ahead = chunks;
+        self
+    }
+
+    /// Gets the number of chunks read ahead.
+    pub fn read_ahead_chunks(&self) -> usize {
+        self.read_ahead
+    }
+
+    /// Checks the data read against the object's
+    /// [checksum](trait.ObjectInfo.html#method.checksum), the stream fails
+    /// with an [`IntegrityError`](enum.StorageErrorKind.html#variant.IntegrityError)
+    /// once the end is reached if they don't match. Objects without a checksum
+    /// are read unverified.
+    pub fn verify(mut self, verify: bool) -> StreamOptions {
+        self.verify = verify;
+        self
+    }
+
+    /// Gets whether the data read is verified.
+    pub fn verifies(&self) -> bool {
+        self.verify
+    }
+
+    /// Reports the data read to `listener`. The object is looked up first so
+    /// the events include the file's size.
+    pub fn progress(mut self, listener: ProgressListener) -> StreamOptions {
+        self.progress = Some(listener);
+        self
+    }
+
+    /// Gets the listener that the data read is reported to.
+    pub fn progress_listener(&self) -> Option<&ProgressListener> {
+        self.progress.as_ref()
+    }
+
+    pub(crate) fn apply(&self, stream: DataStream, total: Option<u64>) -> DataStream {
+        let stream = match self.progress {
+            Some(ref listener) => listener.track(stream, total),
+            None => stream,
+        };
+
+        if self.read_ahead > 0 {
+            DataStream::from_stream(ReadAheadStream::new(stream, self.read_ahead))
+        } else {
+            stream
+        }
+    }
+}
+
+/// Buffers items from a stream ahead of them being requested.
+pub(crate) struct ReadAheadStream<S>
+where
+    S: Stream + Unpin,
+{
+    inner: S,
+    buffer: VecDeque<S::Item>,
+    limit: usize,
+    finished: bool,
+}
+
+impl<S> ReadAheadStream<S>
+where
+    S: Stream + Unpin,
+{
+    pub fn new(inner: S, limit: usize) -> ReadAheadStream<S> {
+        ReadAheadStream {
+            inner,
+            buffer: VecDeque::with_capacity(limit),
+            limit,
+            finished: false,
+        }
+    }
+}
+
+impl<S> Stream for ReadAheadStream<S>
+where
+    S: Stream + Unpin,
+    S::Item: Unpin,
+{
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> StreamPoll<S::Item> {
+        // Hold one more than the limit since one is about to be returned.
+        while !self.finished && self.buffer.len() <= self.limit {
+            match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(item)) => self.buffer.push_back(item),
+                Poll::Ready(None) => self.finished = true,
+                Poll::Pending => break,
+            }
+        }
+
+        match self.buffer.pop_front() {
+            Some(item) => Poll::Ready(Some(item)),
+            None if self.finished => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Skips the start of a stream of data and ends it after a given number of
+/// bytes.
+pub(crate) struct RangeStream {
+    inner: DataStream,
+    skip: u64,
+    remaining: Option<u64>,
+}
+
+impl RangeStream {
+    pub fn new(inner: DataStream, offset: u64, length: Option<u64>) -> RangeStream {
+        RangeStream {
+            inner,
+            skip: offset,
+            remaining: length,
+        }
+    }
+}
+
+impl Stream for RangeStream {
+    type Item = StorageResult<Data>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> ResultStreamPoll<Data> {
+        loop {
+            if self.remaining == Some(0) {
+                return Poll::Ready(None);
+            }
+
+            let mut data = match Pin::new(&mut self.inner).poll_next(cx) {
+                Poll::Ready(Some(Ok(data))) => data,
+                poll => return poll,
+            };
+
+            let len = data.len() as u64;
+            if self.skip >= len {
+                self.skip -= len;
+                continue;
+            }
+
+            if self.skip > 0 {
+                data = data.slice_from(self.skip as usize);
+                self.skip = 0;
+            }
+
+            if let Some(remaining) = self.remaining {
+                if (data.len() as u64) > remaining {
+                    data.truncate(remaining as usize);
+                }
+                self.remaining = Some(remaining - data.len() as u64);
+            }
+
+            return Poll::Ready(Some(Ok(data)));
+        }
+    }
+}
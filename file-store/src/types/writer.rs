@@ -0,0 +1,158 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writing to a file incrementally.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc::{channel, Sender};
+use futures::future::FutureExt;
+use futures::sink::Sink;
+use futures::stream::StreamExt;
+
+use super::*;
+
+/// The number of chunks buffered before writes wait for the upload.
+const WRITER_BUFFER: usize = 4;
+
+/// Writes data to a file as it is produced, returned by
+/// [`FileStore::write_file`](../enum.FileStore.html#method.write_file).
+///
+/// This is a `Sink` of [`Data`](type.Data.html) and, with the "tokio-io"
+/// feature, an `AsyncWrite`. The upload runs as the writer is used so there is
+/// no need to spawn anything. The file is only complete once the writer has
+/// been closed (or shut down) successfully, dropping it before then abandons
+/// the upload.
+pub struct FileWriter {
+    sender: Option<Sender<Data>>,
+    upload: Option<WriteCompleteFuture>,
+    failed: bool,
+}
+
+impl FileWriter {
+    pub(crate) fn new(store: &FileStore, info: UploadInfo) -> FileWriter {
+        let (sender, receiver) = channel(WRITER_BUFFER);
+        let upload = store.write_file_from_stream(info, receiver.map(Ok::<Data, StorageError>));
+
+        FileWriter {
+            sender: Some(sender),
+            upload: Some(upload),
+            failed: false,
+        }
+    }
+
+    /// Drives the upload, returning the result if it has finished.
+    fn poll_upload(&mut self, cx: &mut Context) -> Poll<StorageResult<()>> {
+        let result = match self.upload {
+            Some(ref mut upload) => match upload.poll_unpin(cx) {
+                Poll::Ready(result) => result,
+                Poll::Pending => return Poll::Pending,
+            },
+            None if self.failed => {
+                return Poll::Ready(Err(error::cancelled(Some("The upload failed."))))
+            }
+            None => return Poll::Ready(Ok(())),
+        };
+
+        self.upload = None;
+        self.failed = result.is_err();
+        Poll::Ready(result.map_err(StorageError::from))
+    }
+
+    /// Drives the upload while more data is expected. It should not finish
+    /// until the writer is closed so finishing early is always an error.
+    fn poll_running(&mut self, cx: &mut Context) -> StorageResult<()> {
+        if self.sender.is_none() {
+            return Err(error::invalid_data(Some("The writer has been closed.")));
+        }
+
+        match self.poll_upload(cx) {
+            Poll::Ready(Ok(())) => {
+                self.sender = None;
+                self.failed = true;
+                Err(error::internal_error(Some(
+                    "The upload finished before the writer was closed.",
+                )))
+            }
+            Poll::Ready(Err(e)) => {
+                self.sender = None;
+                Err(e)
+            }
+            Poll::Pending => Ok(()),
+        }
+    }
+}
+
+impl Sink<Data> for FileWriter {
+    type Error = StorageError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<StorageResult<()>> {
+        self.poll_running(cx)?;
+
+        match self.sender {
+            Some(ref mut sender) => sender
+                .poll_ready(cx)
+                .map_err(|_| error::connection_closed(Some("The upload stopped reading data."))),
+            None => Poll::Ready(Err(error::invalid_data(Some(
+                "The writer has been closed.",
+            )))),
+        }
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, data: Data) -> StorageResult<()> {
+        match self.sender {
+            Some(ref mut sender) => sender
+                .start_send(data)
+                .map_err(|_| error::connection_closed(Some("The upload stopped reading data."))),
+            None => Err(error::invalid_data(Some("The writer has been closed."))),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<StorageResult<()>> {
+        self.poll_running(cx)?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<StorageResult<()>> {
+        // Dropping the sender ends the stream that the upload is reading.
+        self.sender = None;
+        self.poll_upload(cx)
+    }
+}
+
+#[cfg(feature = "tokio-io")]
+impl tokio_io::AsyncWrite for FileWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match Sink::poll_ready(self.as_mut(), cx) {
+            Poll::Ready(Ok(())) => (),
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        Sink::start_send(self, Data::from(buf))?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+        Sink::poll_flush(self, cx).map_err(std::io::Error::from)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+        Sink::poll_close(self, cx).map_err(std::io::Error::from)
+    }
+}
@@ -0,0 +1,364 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers for writing files uploaded in HTTP requests. Included with the
+//! feature "upload".
+//!
+//! [`upload_body`](fn.upload_body.html) writes a raw request body to storage
+//! and [`upload_multipart`](fn.upload_multipart.html) writes a single file
+//! field from a `multipart/form-data` request body. Both accept any stream of
+//! buffers so a hyper `Body` can be passed directly:
+//!
+//! ```ignore
+//! let (parts, body) = request.into_parts();
+//! upload_body(&store, path.into(), &parts.headers, body, Some(MAX_SIZE)).await?;
+//! ```
+//!
+//! When a maximum size is given requests whose `Content-Length` is too large
+//! are rejected before anything is written and bodies that turn out to be too
+//! large fail with an [`OverQuota`](../enum.StorageErrorKind.html#variant.OverQuota)
+//! error as soon as the limit is passed.
+use bytes::{BytesMut, IntoBuf};
+use futures::future::ready;
+use futures::stream::{unfold, Stream, StreamExt};
+use http::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE};
+
+use crate::types::*;
+use crate::utils::into_data_stream;
+use crate::{FileStore, StorageBackend};
+
+/// The most header data allowed for a single part of a multipart body.
+const MAX_PART_HEADERS: usize = 8192;
+
+/// Checks the request's `Content-Length` header against a maximum size.
+///
+/// Returns the declared length if there is one.
+pub fn check_content_length(
+    headers: &HeaderMap,
+    max_size: Option<u64>,
+) -> StorageResult<Option<u64>> {
+    let length = match headers.get(CONTENT_LENGTH) {
+        Some(value) => match value.to_str().ok().and_then(|v| v.parse::<u64>().ok()) {
+            Some(l) => l,
+            None => return Err(error::invalid_data(Some("Invalid Content-Length header"))),
+        },
+        None => return Ok(None),
+    };
+
+    match max_size {
+        Some(max) if length > max => Err(error::over_quota(Some(&format!(
+            "Request body of {} bytes exceeds the limit of {} bytes",
+            length, max
+        )))),
+        _ => Ok(Some(length)),
+    }
+}
+
+/// Limits a stream of data to a maximum number of bytes.
+///
+/// Once the limit is exceeded the stream returns an
+/// [`OverQuota`](../enum.StorageErrorKind.html#variant.OverQuota) error and
+/// then ends.
+pub fn limit_size(stream: DataStream, max_size: u64) -> DataStream {
+    DataStream::from_stream(stream.scan(
+        (0, false),
+        move |state: &mut (u64, bool), result: StorageResult<Data>| {
+            if state.1 {
+                return ready(None);
+            }
+
+            let result = match result {
+                Ok(data) => {
+                    state.0 += data.len() as u64;
+                    if state.0 > max_size {
+                        state.1 = true;
+                        Err(error::over_quota(Some(&format!(
+                            "Request body exceeds the limit of {} bytes",
+                            max_size
+                        ))))
+                    } else {
+                        Ok(data)
+                    }
+                }
+                Err(e) => {
+                    state.1 = true;
+                    Err(e)
+                }
+            };
+
+            ready(Some(result))
+        },
+    ))
+}
+
+fn body_stream<S, I, E>(
+    headers: &HeaderMap,
+    body: S,
+    max_size: Option<u64>,
+) -> StorageResult<DataStream>
+where
+    S: Stream<Item = Result<I, E>> + Send + 'static,
+    I: IntoBuf + 'static,
+    E: Into<StorageError> + 'static,
+{
+    check_content_length(headers, max_size)?;
+
+    let stream = DataStream::from_stream(into_data_stream(body));
+    match max_size {
+        Some(max) => Ok(limit_size(stream, max)),
+        None => Ok(stream),
+    }
+}
+
+/// Writes a raw request body to a file.
+///
+/// `max_size` limits the size of the body.
+pub fn upload_body<S, I, E>(
+    store: &FileStore,
+    info: UploadInfo,
+    headers: &HeaderMap,
+    body: S,
+    max_size: Option<u64>,
+) -> WriteCompleteFuture
+where
+    S: Stream<Item = Result<I, E>> + Send + 'static,
+    I: IntoBuf + 'static,
+    E: Into<StorageError> + 'static,
+{
+    match body_stream(headers, body, max_size) {
+        Ok(stream) => StorageBackend::write_file_from_stream(store, info, stream),
+        Err(e) => WriteCompleteFuture::from_value(Err(TransferError::SourceError(e))),
+    }
+}
+
+/// Writes the file in the named field of a `multipart/form-data` request body
+/// to a file.
+///
+/// `max_size` limits the size of the entire body.
+pub fn upload_multipart<S, I, E>(
+    store: &FileStore,
+    info: UploadInfo,
+    field: &str,
+    headers: &HeaderMap,
+    body: S,
+    max_size: Option<u64>,
+) -> WriteCompleteFuture
+where
+    S: Stream<Item = Result<I, E>> + Send + 'static,
+    I: IntoBuf + 'static,
+    E: Into<StorageError> + 'static,
+{
+    let stream = match body_stream(headers, body, max_size)
+        .and_then(|stream| multipart_field(headers, stream, field))
+    {
+        Ok(s) => s,
+        Err(e) => return WriteCompleteFuture::from_value(Err(TransferError::SourceError(e))),
+    };
+
+    StorageBackend::write_file_from_stream(store, info, stream)
+}
+
+/// Extracts the boundary from a `multipart/form-data` content type.
+fn boundary(headers: &HeaderMap) -> StorageResult<String> {
+    let content_type = match headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+        Some(c) => c,
+        None => return Err(error::invalid_data(Some("Missing Content-Type header"))),
+    };
+
+    let mut params = content_type.split(';').map(str::trim);
+    match params.next() {
+        Some(mime) if mime.eq_ignore_ascii_case("multipart/form-data") => (),
+        _ => {
+            return Err(error::invalid_data(Some(
+                "Request body is not multipart/form-data",
+            )))
+        }
+    }
+
+    for param in params {
+        if param.len() > 9 && param[..9].eq_ignore_ascii_case("boundary=") {
+            return Ok(param[9..].trim_matches('"').to_owned());
+        }
+    }
+
+    Err(error::invalid_data(Some("Missing multipart boundary")))
+}
+
+/// Finds the name of a part from its headers.
+fn part_name(headers: &[u8]) -> Option<String> {
+    let headers = String::from_utf8_lossy(headers);
+    for line in headers.split("\r\n") {
+        let pos = match line.find(':') {
+            Some(p) => p,
+            None => continue,
+        };
+
+        if !line[..pos]
+            .trim()
+            .eq_ignore_ascii_case("content-disposition")
+        {
+            continue;
+        }
+
+        for param in line[pos + 1..].split(';').map(str::trim) {
+            if param.starts_with("name=") {
+                return Some(param[5..].trim_matches('"').to_owned());
+            }
+        }
+    }
+
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+enum Phase {
+    /// Looking for the next boundary.
+    Seeking,
+    /// Just after a boundary.
+    Boundary,
+    /// Reading a part's headers.
+    Headers,
+    /// Reading the requested field.
+    Field,
+    /// Finished.
+    Done,
+}
+
+struct MultipartState {
+    body: DataStream,
+    buffer: BytesMut,
+    delimiter: Vec<u8>,
+    field: String,
+    phase: Phase,
+}
+
+impl MultipartState {
+    fn fail(mut self, error: StorageError) -> Option<(StorageResult<Data>, MultipartState)> {
+        self.phase = Phase::Done;
+        Some((Err(error), self))
+    }
+}
+
+async fn next_chunk(mut state: MultipartState) -> Option<(StorageResult<Data>, MultipartState)> {
+    loop {
+        let delimiter_len = state.delimiter.len();
+
+        match state.phase {
+            Phase::Done => return None,
+            Phase::Seeking => match find(&state.buffer, &state.delimiter) {
+                Some(pos) => {
+                    state.buffer.advance(pos + delimiter_len);
+                    state.phase = Phase::Boundary;
+                    continue;
+                }
+                None => {
+                    let len = state.buffer.len();
+                    if len >= delimiter_len {
+                        state.buffer.advance(len + 1 - delimiter_len);
+                    }
+                }
+            },
+            Phase::Boundary => {
+                if state.buffer.len() >= 2 {
+                    if state.buffer.starts_with(b"--") {
+                        // The closing boundary.
+                        let message = format!("Missing multipart field '{}'", state.field);
+                        return state.fail(error::invalid_data(Some(&message)));
+                    }
+
+                    state.phase = Phase::Headers;
+                    continue;
+                }
+            }
+            Phase::Headers => match find(&state.buffer, b"\r\n\r\n") {
+                Some(pos) => {
+                    let headers = state.buffer.split_to(pos + 4);
+                    state.phase = match part_name(&headers) {
+                        Some(ref name) if name == &state.field => Phase::Field,
+                        _ => Phase::Seeking,
+                    };
+                    continue;
+                }
+                None => {
+                    if state.buffer.len() > MAX_PART_HEADERS {
+                        return state.fail(error::invalid_data(Some(
+                            "Multipart headers were too large",
+                        )));
+                    }
+                }
+            },
+            Phase::Field => match find(&state.buffer, &state.delimiter) {
+                Some(pos) => {
+                    let data = state.buffer.split_to(pos).freeze();
+                    state.phase = Phase::Done;
+                    if !data.is_empty() {
+                        return Some((Ok(data), state));
+                    }
+                    continue;
+                }
+                None => {
+                    let len = state.buffer.len();
+                    if len >= delimiter_len {
+                        let data = state.buffer.split_to(len + 1 - delimiter_len).freeze();
+                        return Some((Ok(data), state));
+                    }
+                }
+            },
+        }
+
+        match state.body.next().await {
+            Some(Ok(data)) => state.buffer.extend_from_slice(&data),
+            Some(Err(e)) => return state.fail(e),
+            None => {
+                return state.fail(error::invalid_data(Some(
+                    "Unexpected end of multipart body",
+                )))
+            }
+        }
+    }
+}
+
+/// Extracts the contents of the named field from a `multipart/form-data`
+/// request body.
+///
+/// Fails immediately if the request is not a multipart request. If the field
+/// is not present the stream ends with an
+/// [`InvalidData`](../enum.StorageErrorKind.html#variant.InvalidData) error.
+pub fn multipart_field(
+    headers: &HeaderMap,
+    body: DataStream,
+    field: &str,
+) -> StorageResult<DataStream> {
+    let boundary = boundary(headers)?;
+
+    // The body's leading boundary doesn't need to be preceded by a line break
+    // so start with one to treat all boundaries the same.
+    let mut buffer = BytesMut::new();
+    buffer.extend_from_slice(b"\r\n");
+
+    let state = MultipartState {
+        body,
+        buffer,
+        delimiter: format!("\r\n--{}", boundary).into_bytes(),
+        field: field.to_owned(),
+        phase: Phase::Seeking,
+    };
+
+    Ok(DataStream::from_stream(unfold(state, next_chunk)))
+}
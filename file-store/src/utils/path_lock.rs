@@ -0,0 +1,149 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+type Held<K> = Arc<Mutex<HashMap<K, Vec<Waker>>>>;
+
+fn held<K>(held: &Held<K>) -> MutexGuard<'_, HashMap<K, Vec<Waker>>> {
+    // The map is never left half updated so a panic elsewhere doesn't matter.
+    held.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Asynchronous locks on individual paths. Tasks locking different paths
+/// don't wait for each other.
+pub(crate) struct PathLocks<K> {
+    held: Held<K>,
+}
+
+/// Clones share the same locks.
+impl<K> Clone for PathLocks<K> {
+    fn clone(&self) -> PathLocks<K> {
+        PathLocks {
+            held: self.held.clone(),
+        }
+    }
+}
+
+impl<K> Default for PathLocks<K> {
+    fn default() -> PathLocks<K> {
+        PathLocks {
+            held: Default::default(),
+        }
+    }
+}
+
+impl<K> fmt::Debug for PathLocks<K>
+where
+    K: Eq + Hash + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(held(&self.held).keys()).finish()
+    }
+}
+
+impl<K> PathLocks<K>
+where
+    K: Clone + Eq + Hash + Ord + Unpin,
+{
+    /// Waits until nothing else holds the lock on `key` and takes it. The lock
+    /// is released when the returned guard is dropped.
+    pub(crate) fn lock(&self, key: K) -> PathLockFuture<K> {
+        PathLockFuture {
+            held: self.held.clone(),
+            key: Some(key),
+        }
+    }
+
+    /// Takes the locks on several keys. They are always taken in the same
+    /// order so two tasks locking overlapping keys can't deadlock.
+    pub(crate) async fn lock_all(&self, mut keys: Vec<K>) -> Vec<PathGuard<K>> {
+        keys.sort();
+        keys.dedup();
+
+        let mut guards = Vec::with_capacity(keys.len());
+        for key in keys {
+            guards.push(self.lock(key).await);
+        }
+        guards
+    }
+}
+
+/// Resolves to a [`PathGuard`](struct.PathGuard.html) once the lock is taken.
+pub(crate) struct PathLockFuture<K> {
+    held: Held<K>,
+    key: Option<K>,
+}
+
+impl<K> Future for PathLockFuture<K>
+where
+    K: Clone + Eq + Hash + Unpin,
+{
+    type Output = PathGuard<K>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<PathGuard<K>> {
+        let this = self.get_mut();
+        let key = this
+            .key
+            .take()
+            .expect("PathLockFuture polled after completion");
+
+        let mut locks = held(&this.held);
+        match locks.get_mut(&key) {
+            Some(waiting) => {
+                waiting.push(cx.waker().clone());
+                drop(locks);
+                this.key = Some(key);
+                Poll::Pending
+            }
+            None => {
+                locks.insert(key.clone(), Vec::new());
+                drop(locks);
+                Poll::Ready(PathGuard {
+                    held: this.held.clone(),
+                    key,
+                })
+            }
+        }
+    }
+}
+
+/// Holds the lock on a path until dropped.
+pub(crate) struct PathGuard<K>
+where
+    K: Eq + Hash,
+{
+    held: Held<K>,
+    key: K,
+}
+
+impl<K> Drop for PathGuard<K>
+where
+    K: Eq + Hash,
+{
+    fn drop(&mut self) {
+        let waiting = held(&self.held).remove(&self.key);
+
+        // Every waiting task tries again, one of them will get the lock.
+        for waker in waiting.into_iter().flatten() {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+
+use bytes::buf::Buf;
+use bytes::IntoBuf;
+use futures::executor::{block_on_stream, BlockingStream};
+use futures::stream::Stream;
+
+use crate::types::StorageError;
+
+/// Reads a stream of buffers synchronously.
+pub(crate) struct BlockingStreamReader<S>
+where
+    S: Unpin + Stream,
+{
+    stream: BlockingStream<S>,
+    reader: Option<Box<dyn io::Read>>,
+}
+
+impl<S> BlockingStreamReader<S>
+where
+    S: Unpin + Stream,
+{
+    pub fn from_stream(stream: S) -> BlockingStreamReader<S> {
+        BlockingStreamReader {
+            stream: block_on_stream(stream),
+            reader: None,
+        }
+    }
+}
+
+impl<S, O, E> io::Read for BlockingStreamReader<S>
+where
+    S: Unpin + Stream<Item = Result<O, E>>,
+    O: IntoBuf,
+    <O as IntoBuf>::Buf: 'static,
+    E: Into<StorageError>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.reader {
+            Some(ref mut r) => r.read(buf),
+            None => match self.stream.next() {
+                Some(Ok(d)) => {
+                    let mut reader = d.into_buf().reader();
+                    let count = reader.read(buf)?;
+                    self.reader = Some(Box::new(reader));
+                    Ok(count)
+                }
+                Some(Err(e)) => Err(e.into().into()),
+                None => Ok(0),
+            },
+        }
+    }
+}
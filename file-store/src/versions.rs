@@ -0,0 +1,102 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+//! Older versions of files.
+//!
+//! Some storage keeps the previous versions of a file when it is replaced or
+//! deleted. Backends that keep them implement
+//! [`VersionedStorage`](trait.VersionedStorage.html) which lists, reads and
+//! deletes individual versions. Of the current backends only B2 keeps versions, the
+//! [`FileStore`](../enum.FileStore.html) implementation fails for other
+//! backends.
+
+use std::time::SystemTime;
+
+use crate::types::*;
+use crate::FileStore;
+
+fn unsupported() -> StorageError {
+    error::invalid_settings(Some("This backend does not keep versions of files."))
+}
+
+/// A future that resolves to the versions of a file.
+pub type VersionListFuture = WrappedFuture<StorageResult<Vec<ObjectVersion>>>;
+
+/// A single version of a file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectVersion {
+    /// The backend's identifier for the version.
+    pub id: String,
+    /// The path of the file.
+    pub path: ObjectPath,
+    /// The size of this version.
+    pub len: u64,
+    /// The modification time recorded for this version.
+    pub modified: Option<SystemTime>,
+    /// When this version was created.
+    pub created: Option<SystemTime>,
+    /// Whether this version marks the file as deleted rather than holding
+    /// data. B2 calls these hidden versions.
+    pub deleted: bool,
+    /// Whether this is the current version of the file.
+    pub latest: bool,
+    /// The checksum of this version's content if known.
+    pub checksum: Option<Checksum>,
+}
+
+/// Storage that keeps older versions of files.
+pub trait VersionedStorage {
+    /// Lists every version of a file, newest first.
+    ///
+    /// Fails with a [`NotFound`](../enum.StorageErrorKind.html#variant.NotFound)
+    /// error if there are no versions.
+    fn list_versions(&self, path: ObjectPath) -> VersionListFuture;
+
+    /// Streams the content of one version of a file.
+    fn get_version_stream(&self, path: ObjectPath, version_id: &str) -> DataStreamFuture;
+
+    /// Permanently deletes one version of a file. Deleting the latest version
+    /// makes the version before it current.
+    fn delete_version(&self, path: ObjectPath, version_id: &str) -> OperationCompleteFuture;
+}
+
+impl VersionedStorage for FileStore {
+    fn list_versions(&self, path: ObjectPath) -> VersionListFuture {
+        match self {
+            #[cfg(feature = "b2")]
+            FileStore::B2(b) => b.list_versions(path),
+            #[allow(unreachable_patterns)]
+            _ => VersionListFuture::from_value(Err(unsupported())),
+        }
+    }
+
+    fn get_version_stream(&self, path: ObjectPath, version_id: &str) -> DataStreamFuture {
+        match self {
+            #[cfg(feature = "b2")]
+            FileStore::B2(b) => b.get_version_stream(path, version_id),
+            #[allow(unreachable_patterns)]
+            _ => DataStreamFuture::from_value(Err(unsupported())),
+        }
+    }
+
+    fn delete_version(&self, path: ObjectPath, version_id: &str) -> OperationCompleteFuture {
+        match self {
+            #[cfg(feature = "b2")]
+            FileStore::B2(b) => self
+                .object_cache()
+                .invalidate_after(vec![path.clone()], b.delete_version(path, version_id)),
+            #[allow(unreachable_patterns)]
+            _ => OperationCompleteFuture::from_value(Err(unsupported())),
+        }
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "archive", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::convert::TryInto;
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::{iter, once, TryStreamExt};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::archive::ArchiveFormat;
+use file_store::backends::file::FileBackend;
+use file_store::compression::{compress, Encoding};
+use file_store::*;
+
+async fn write(store: &FileStore, path: &str, data: &'static str) {
+    store
+        .write_file_from_stream(path, once(ready(Ok::<_, StorageError>(Bytes::from(data)))))
+        .await
+        .unwrap();
+}
+
+async fn build(store: &FileStore, prefix: &str, format: ArchiveFormat) -> Vec<u8> {
+    let chunks: Vec<Data> = store.archive(prefix, format).try_collect().await.unwrap();
+    chunks.concat()
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(data[pos..pos + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+fn field(data: &[u8]) -> &str {
+    let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    std::str::from_utf8(&data[..end]).unwrap()
+}
+
+#[test]
+fn test_tar() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        write(&store, "dir/a.txt", "Some data.").await;
+        write(&store, "dir/sub/b.txt", "Other data.").await;
+        write(&store, "other.txt", "Not included.").await;
+
+        let tar = build(&store, "dir", ArchiveFormat::Tar).await;
+        assert_eq!(tar.len(), 512 * 6);
+
+        let mut files = Vec::new();
+        let mut pos = 0;
+        while tar[pos] != 0 {
+            let header = &tar[pos..pos + 512];
+            assert_eq!(field(&header[257..263]), "ustar");
+
+            let checksum = u32::from_str_radix(field(&header[148..155]), 8).unwrap();
+            let sum: u32 = header
+                .iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    if i >= 148 && i < 156 {
+                        32
+                    } else {
+                        u32::from(*b)
+                    }
+                })
+                .sum();
+            assert_eq!(checksum, sum);
+
+            let name = field(&header[0..100]).to_owned();
+            let size = usize::from_str_radix(field(&header[124..136]), 8).unwrap();
+            let data = String::from_utf8(tar[pos + 512..pos + 512 + size].to_vec()).unwrap();
+            files.push((name, data));
+
+            pos += 512 + ((size + 511) / 512) * 512;
+        }
+
+        assert_eq!(
+            files,
+            vec![
+                ("a.txt".to_owned(), "Some data.".to_owned()),
+                ("sub/b.txt".to_owned(), "Other data.".to_owned()),
+            ]
+        );
+        assert!(tar[pos..].iter().all(|b| *b == 0));
+        assert_eq!(tar.len() - pos, 1024);
+    });
+}
+
+#[test]
+fn test_zip() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        write(&store, "dir/a.txt", "Some data.").await;
+        write(&store, "dir/sub/b.txt", "Other data.").await;
+        write(&store, "other.txt", "Not included.").await;
+
+        let zip = build(&store, "dir", ArchiveFormat::Zip).await;
+
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(&zip, end), 0x0605_4b50);
+        assert_eq!(u16_at(&zip, end + 10), 2);
+        let directory_size = u32_at(&zip, end + 12) as usize;
+        let directory = u32_at(&zip, end + 16) as usize;
+        assert_eq!(directory + directory_size, end);
+
+        let mut files = Vec::new();
+        let mut pos = directory;
+        while pos < end {
+            assert_eq!(u32_at(&zip, pos), 0x0201_4b50);
+            let crc = u32_at(&zip, pos + 16);
+            let size = u32_at(&zip, pos + 24) as usize;
+            let name_length = u16_at(&zip, pos + 28) as usize;
+            let name = String::from_utf8(zip[pos + 46..pos + 46 + name_length].to_vec()).unwrap();
+
+            let local = u32_at(&zip, pos + 42) as usize;
+            assert_eq!(u32_at(&zip, local), 0x0403_4b50);
+            let start = local + 30 + u16_at(&zip, local + 26) as usize;
+            let data = String::from_utf8(zip[start..start + size].to_vec()).unwrap();
+
+            // The data descriptor follows the data.
+            assert_eq!(u32_at(&zip, start + size), 0x0807_4b50);
+            assert_eq!(u32_at(&zip, start + size + 4), crc);
+
+            files.push((name, data, crc));
+            pos += 46 + name_length;
+        }
+
+        assert_eq!(
+            files,
+            vec![
+                ("a.txt".to_owned(), "Some data.".to_owned(), 0xbb5f_1e18),
+                (
+                    "sub/b.txt".to_owned(),
+                    "Other data.".to_owned(),
+                    0xce9e_e63e
+                ),
+            ]
+        );
+    });
+}
+
+#[test]
+fn test_missing_prefix() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+
+        let zip = build(&store, "", ArchiveFormat::Zip).await;
+        assert_eq!(zip.len(), 22);
+
+        let tar = build(&store, "", ArchiveFormat::Tar).await;
+        assert_eq!(tar, vec![0; 1024]);
+    });
+}
+
+#[test]
+fn test_dos_times() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_565_000_000);
+        store
+            .write_file_from_stream(
+                UploadInfo {
+                    path: ObjectPath::new("dir/a.txt").unwrap(),
+                    modified: Some(modified),
+                    ..Default::default()
+                },
+                once(ready(Ok::<_, StorageError>(Bytes::from("Some data.")))),
+            )
+            .await
+            .unwrap();
+
+        let zip = build(&store, "dir", ArchiveFormat::Zip).await;
+        store
+            .extract(once(ready(Ok::<_, StorageError>(Data::from(zip)))), "out")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_object("out/a.txt").await.unwrap().modified(),
+            Some(modified)
+        );
+    });
+}
+
+async fn test_round_trip(format: ArchiveFormat) {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+    let source = FileBackend::connect(source_dir.path()).await.unwrap();
+    let target = FileBackend::connect(target_dir.path()).await.unwrap();
+
+    write(&source, "dir/a.txt", "Some data.").await;
+    write(&source, "dir/sub/b.txt", "Other data.").await;
+    write(&source, "dir/c.txt", "").await;
+
+    let archive = build(&source, "dir", format).await;
+
+    // Feed the archive in small pieces to exercise the buffering.
+    let pieces: Vec<Result<Data, StorageError>> = archive
+        .chunks(7)
+        .map(|c| Ok(Data::from(c.to_vec())))
+        .collect();
+    let count = archive::Extractor::new(target.clone())
+        .concurrency(2)
+        .extract(iter(pieces), "restored")
+        .await
+        .unwrap();
+    assert_eq!(count, 3);
+
+    assert_eq!(
+        target.read_to_bytes("restored/a.txt").await.unwrap(),
+        "Some data."
+    );
+    assert_eq!(
+        target.read_to_bytes("restored/sub/b.txt").await.unwrap(),
+        "Other data."
+    );
+    assert_eq!(target.read_to_bytes("restored/c.txt").await.unwrap(), "");
+
+    let original = source
+        .get_object("dir/a.txt")
+        .await
+        .unwrap()
+        .modified()
+        .unwrap();
+    let extracted = target
+        .get_object("restored/a.txt")
+        .await
+        .unwrap()
+        .modified()
+        .unwrap();
+    let original = original.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let extracted = extracted.duration_since(UNIX_EPOCH).unwrap().as_secs();
+    // Zip archives only store times to the nearest two seconds.
+    assert!(original - extracted < 2);
+    assert!(extracted <= original);
+}
+
+#[test]
+fn test_extract_tar() {
+    Runtime::new()
+        .unwrap()
+        .block_on(test_round_trip(ArchiveFormat::Tar));
+}
+
+#[test]
+fn test_extract_zip() {
+    Runtime::new()
+        .unwrap()
+        .block_on(test_round_trip(ArchiveFormat::Zip));
+}
+
+#[test]
+fn test_extract_tar_gz() {
+    Runtime::new()
+        .unwrap()
+        .block_on(test_round_trip(ArchiveFormat::TarGz));
+}
+
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Adds a deflated file to a zip archive, with the sizes either in the local
+/// header or in a data descriptor.
+async fn put_deflated(
+    zip: &mut Vec<u8>,
+    name: &str,
+    data: &'static str,
+    crc: u32,
+    descriptor: bool,
+) {
+    let chunks: Vec<Data> = compress(
+        DataStream::from_stream(once(ready(Ok(Data::from(data))))),
+        Encoding::Deflate,
+    )
+    .try_collect()
+    .await
+    .unwrap();
+    let compressed = chunks.concat();
+
+    put_u32(zip, 0x0403_4b50);
+    put_u16(zip, 20);
+    put_u16(zip, if descriptor { 0x0808 } else { 0x0800 });
+    put_u16(zip, 8);
+    put_u16(zip, 0);
+    put_u16(zip, (1 << 5) | 1);
+    if descriptor {
+        put_u32(zip, 0);
+        put_u32(zip, 0);
+        put_u32(zip, 0);
+    } else {
+        put_u32(zip, crc);
+        put_u32(zip, compressed.len() as u32);
+        put_u32(zip, data.len() as u32);
+    }
+    put_u16(zip, name.len() as u16);
+    put_u16(zip, 0);
+    zip.extend_from_slice(name.as_bytes());
+    zip.extend_from_slice(&compressed);
+
+    if descriptor {
+        put_u32(zip, 0x0807_4b50);
+        put_u32(zip, crc);
+        put_u32(zip, compressed.len() as u32);
+        put_u32(zip, data.len() as u32);
+    }
+}
+
+fn put_end(zip: &mut Vec<u8>) {
+    put_u32(zip, 0x0605_4b50);
+    zip.extend_from_slice(&[0; 18]);
+}
+
+#[test]
+fn test_extract_deflated_zip() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+
+        let mut zip = Vec::new();
+        put_deflated(&mut zip, "a.txt", "Some data.", 0xbb5f_1e18, false).await;
+        put_deflated(&mut zip, "sub/b.txt", "Other data.", 0xce9e_e63e, true).await;
+        put_end(&mut zip);
+
+        let pieces: Vec<Result<Data, StorageError>> =
+            zip.chunks(5).map(|c| Ok(Data::from(c.to_vec()))).collect();
+        assert_eq!(store.extract(iter(pieces), "out").await.unwrap(), 2);
+        assert_eq!(
+            store.read_to_bytes("out/a.txt").await.unwrap(),
+            "Some data."
+        );
+        assert_eq!(
+            store.read_to_bytes("out/sub/b.txt").await.unwrap(),
+            "Other data."
+        );
+
+        // A file that fails its CRC check is not kept.
+        let mut zip = Vec::new();
+        put_deflated(&mut zip, "bad.txt", "Some data.", 0x1234_5678, true).await;
+        put_end(&mut zip);
+
+        let stream = once(ready(Ok::<_, StorageError>(Data::from(zip))));
+        match store.extract(stream, "out").await {
+            Err(TransferError::SourceError(e)) => {
+                assert_eq!(e.kind(), StorageErrorKind::InvalidData)
+            }
+            _ => panic!("Should have failed to extract a corrupt file."),
+        }
+        assert!(store.get_object("out/bad.txt").await.is_err());
+    });
+}
+
+#[test]
+fn test_extract_truncated() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        write(&store, "dir/a.txt", "Some data.").await;
+
+        let mut tar = build(&store, "dir", ArchiveFormat::Tar).await;
+        tar.truncate(520);
+
+        let stream = once(ready(Ok::<_, StorageError>(Data::from(tar))));
+        match store.extract(stream, "out").await {
+            Err(TransferError::SourceError(e)) => {
+                assert_eq!(e.kind(), StorageErrorKind::InvalidData)
+            }
+            _ => panic!("Should have failed to extract a truncated archive."),
+        }
+        assert!(store.get_object("out/a.txt").await.is_err());
+    });
+}
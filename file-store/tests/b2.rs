@@ -12,12 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![cfg(feature = "b2")]
+#![cfg(all(feature = "b2", feature = "hyper-client"))]
 
+#[macro_use]
 extern crate file_store;
 
-#[macro_use]
-mod runner;
 mod mocks;
 
 mod test1 {
@@ -28,7 +27,7 @@ mod test1 {
     use file_store::FileStore;
 
     use crate::mocks::b2_server::start_server;
-    use crate::runner::{TestContext, TestError, TestResult};
+    use file_store::testing::{TestContext, TestError, TestResult};
 
     async fn build_fs(context: &TestContext) -> TestResult<(FileStore, Sender<()>)> {
         let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
@@ -52,14 +51,16 @@ mod test1 {
 }
 
 mod retries {
+    use std::time::Duration;
+
     use futures::channel::oneshot::Sender;
 
     use file_store::backends::b2::B2Backend;
     use file_store::backends::Backend;
-    use file_store::FileStore;
+    use file_store::{FileStore, RetryPolicy};
 
     use crate::mocks::b2_server::start_server;
-    use crate::runner::{TestContext, TestError, TestResult};
+    use file_store::testing::{TestContext, TestError, TestResult};
 
     async fn build_fs(context: &TestContext) -> TestResult<(FileStore, Sender<()>)> {
         let (addr, sender) = start_server(context.get_fs_root(), 3)?;
@@ -68,6 +69,9 @@ mod retries {
             .host(&format!("http://{}", addr))
             .limit_small_file_size(20 * 1024 * 1024)
             .limit_requests(2)
+            .retry_policy(
+                RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(10)),
+            )
             .connect()
             .await?;
         Ok((fs, sender))
@@ -81,3 +85,1272 @@ mod retries {
 
     build_tests!("test1", Backend::B2, build_fs, cleanup);
 }
+
+mod incomplete_uploads {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures::stream::iter;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[test]
+    fn test_cleanup_incomplete_uploads() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("dir1")?)
+                .limit_small_file_size(500)
+                .connect()
+                .await?;
+
+            // A stream that fails part way through leaves the upload unfinished.
+            let stream = iter(vec![
+                Ok(Bytes::from(vec![5; 2000])),
+                Err(StorageError::new(
+                    StorageErrorKind::InvalidData,
+                    Some("Broken stream"),
+                )),
+            ]);
+            assert!(fs.write_file_from_stream("broken", stream).await.is_err());
+
+            let cancelled = fs
+                .cleanup_incomplete_uploads(Duration::from_secs(3600))
+                .await?;
+            assert!(cancelled.is_empty());
+
+            let cancelled = fs
+                .cleanup_incomplete_uploads(Duration::from_secs(0))
+                .await?;
+            assert_eq!(cancelled, vec![ObjectPath::new("broken")?]);
+
+            let cancelled = fs
+                .cleanup_incomplete_uploads(Duration::from_secs(0))
+                .await?;
+            assert!(cancelled.is_empty());
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+
+    #[test]
+    fn test_cancel_on_failure() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("dir1")?)
+                .limit_small_file_size(500)
+                .connect()
+                .await?
+                .with_cleanup_on_failure(true);
+
+            let stream = iter(vec![
+                Ok(Bytes::from(vec![5; 2000])),
+                Err(StorageError::new(
+                    StorageErrorKind::InvalidData,
+                    Some("Broken stream"),
+                )),
+            ]);
+            assert!(fs.write_file_from_stream("broken", stream).await.is_err());
+
+            let cancelled = fs
+                .cleanup_incomplete_uploads(Duration::from_secs(0))
+                .await?;
+            assert!(cancelled.is_empty());
+
+            // The upload can still opt out.
+            let stream = iter(vec![
+                Ok(Bytes::from(vec![5; 2000])),
+                Err(StorageError::new(
+                    StorageErrorKind::InvalidData,
+                    Some("Broken stream"),
+                )),
+            ]);
+            let info = UploadInfo::from(ObjectPath::new("broken")?).cleanup_on_failure(false);
+            assert!(fs.write_file_from_stream(info, stream).await.is_err());
+
+            let cancelled = fs
+                .cleanup_incomplete_uploads(Duration::from_secs(0))
+                .await?;
+            assert_eq!(cancelled, vec![ObjectPath::new("broken")?]);
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod parts_in_flight {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures::stream::iter;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::{start_counting_server, Fault, FaultInjector, RequestCounter};
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    const PARTS: &str = "/upload/part/";
+
+    #[test]
+    fn test_limit_parts_in_flight() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let faults = FaultInjector::new();
+            let requests = RequestCounter::new();
+            let (addr, sender) = start_counting_server(
+                context.get_fs_root(),
+                20000,
+                faults.clone(),
+                requests.clone(),
+            )?;
+
+            let expected: Vec<u8> = (0..12u8).flat_map(|i| vec![i; 400]).collect();
+            for limit in &[1, 3] {
+                let fs = B2Backend::builder("foo", "bar")
+                    .host(&format!("http://{}", addr))
+                    .prefix(ObjectPath::new("dir1")?)
+                    .limit_small_file_size(500)
+                    .limit_parts_in_flight(*limit)
+                    .connect()
+                    .await?;
+
+                // Slow part uploads down so that they overlap as much as the
+                // limit allows.
+                requests.watch(PARTS);
+                faults.inject_times(PARTS, Fault::Latency(Duration::from_millis(200)), 10);
+
+                // Enough data for five parts.
+                let chunks: Vec<StorageResult<Bytes>> =
+                    (0..12u8).map(|i| Ok(Bytes::from(vec![i; 400]))).collect();
+                fs.write_file_from_stream("parts", iter(chunks)).await?;
+                faults.clear();
+
+                let count = requests.get(PARTS);
+                assert_eq!(count.total, 5);
+                assert_eq!(count.active, 0);
+                assert_eq!(count.max_active, *limit);
+
+                assert_eq!(fs.read_to_bytes("parts").await?, expected);
+            }
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod resumable_uploads {
+    use futures::stream::TryStreamExt;
+
+    use file_store::backends::b2::{B2Backend, UploadSession};
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    /// A source that fails to read anything past a point, like a process
+    /// crashing part way through an upload.
+    struct Truncated {
+        data: Data,
+        limit: u64,
+    }
+
+    impl UploadSource for Truncated {
+        fn size(&self) -> SizeFuture {
+            self.data.size()
+        }
+
+        fn read_range(&self, offset: u64, length: u64) -> DataStreamFuture {
+            if offset + length > self.limit {
+                DataStreamFuture::from_value(Err(StorageError::new(
+                    StorageErrorKind::InvalidData,
+                    Some("Crashed"),
+                )))
+            } else {
+                self.data.read_range(offset, length)
+            }
+        }
+    }
+
+    #[test]
+    fn test_resume_upload() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("dir1")?)
+                .limit_parts_in_flight(1)
+                .connect()
+                .await?;
+
+            let expected: Vec<u8> = (0..35u8).flat_map(|i| vec![i; 100]).collect();
+            let data = Data::from(expected.clone());
+
+            let session = fs.begin_upload("resumed").await?;
+            assert_eq!(session.path(), "resumed");
+
+            let result = fs
+                .resume_upload(
+                    session.clone(),
+                    Truncated {
+                        data: data.clone(),
+                        limit: 2000,
+                    },
+                )
+                .await;
+            assert!(result.is_err());
+            assert!(fs.get_object("resumed").await.is_err());
+
+            // The session survives a round trip through storage.
+            let json = serde_json::to_string(&session).unwrap();
+            let session: UploadSession = serde_json::from_str(&json).unwrap();
+
+            fs.resume_upload(session, data).await?;
+
+            let written: Vec<Data> = fs.get_file_stream("resumed").await?.try_collect().await?;
+            assert_eq!(written.concat(), expected);
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod path_policy {
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    fn is_invalid<T>(result: StorageResult<T>) -> bool {
+        match result {
+            Err(e) => match e.kind() {
+                StorageErrorKind::InvalidPath(_) => true,
+                _ => false,
+            },
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_b2_path_policy() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("dir1")?)
+                .connect()
+                .await?;
+
+            assert!(is_invalid(fs.get_object("dir\\file").await));
+            assert!(is_invalid(fs.get_object("dir//file").await));
+            assert!(is_invalid(fs.get_object("dir/\u{7f}").await));
+            assert!(is_invalid(fs.get_object("a".repeat(1025).as_str()).await));
+            assert!(!is_invalid(fs.get_object("a".repeat(1024).as_str()).await));
+
+            // Without a prefix the bucket name doesn't count towards the limit.
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .connect()
+                .await?;
+            let path = format!("dir1/{}", "a".repeat(1024));
+            assert!(!is_invalid(fs.get_object(path.as_str()).await));
+            let path = format!("dir1/{}", "a".repeat(1025));
+            assert!(is_invalid(fs.get_object(path.as_str()).await));
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod idempotency {
+    use std::fs;
+
+    use bytes::Bytes;
+    use futures::future::ready;
+    use futures::stream::once;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[test]
+    fn test_idempotency_key() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("dir1")?)
+                .connect()
+                .await?;
+
+            let write = |data: &'static str, key: Option<&str>| {
+                let mut info = UploadInfo::from(ObjectPath::new("keyed").unwrap());
+                if let Some(key) = key {
+                    info = info.idempotency_key(key);
+                }
+                fs.write_file_from_stream(
+                    info,
+                    once(ready(Ok::<_, StorageError>(Bytes::from(data)))),
+                )
+            };
+            let target = context.get_fs_root().join("dir1").join("keyed");
+
+            write("first", Some("job-1")).await?;
+            assert_eq!(fs::read(&target).unwrap(), b"first");
+
+            // Repeating the upload with the same key does nothing.
+            write("second", Some("job-1")).await?;
+            assert_eq!(fs::read(&target).unwrap(), b"first");
+
+            write("third", Some("job-2")).await?;
+            assert_eq!(fs::read(&target).unwrap(), b"third");
+
+            write("fourth", None).await?;
+            assert_eq!(fs::read(&target).unwrap(), b"fourth");
+
+            write("fifth", Some("job-1")).await?;
+            assert_eq!(fs::read(&target).unwrap(), b"fifth");
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod faults {
+    use std::time::{Duration, Instant};
+
+    use futures::stream::TryStreamExt;
+    use http::StatusCode;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::{start_server_with_faults, Fault, FaultInjector};
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[test]
+    fn test_recovers_from_faults() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let faults = FaultInjector::new();
+            let (addr, sender) =
+                start_server_with_faults(context.get_fs_root(), 20000, faults.clone())?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("dir1")?)
+                .connect()
+                .await?;
+
+            let list = || async {
+                let objects: Vec<Object> = fs.list_objects("dir2").await?.try_collect().await?;
+                Ok::<_, StorageError>(objects.len())
+            };
+            let read = || async {
+                let data: Vec<Data> = fs
+                    .get_file_stream("smallfile.txt")
+                    .await?
+                    .try_collect()
+                    .await?;
+                Ok::<_, StorageError>(data.concat())
+            };
+
+            let expected = list().await?;
+
+            faults.inject_times(
+                "b2_list_file_names",
+                Fault::Error(StatusCode::SERVICE_UNAVAILABLE, "service_unavailable"),
+                2,
+            );
+            assert_eq!(list().await?, expected);
+
+            faults.inject(
+                "b2_list_file_names",
+                Fault::Error(StatusCode::TOO_MANY_REQUESTS, "too_many_requests"),
+            );
+            assert_eq!(list().await?, expected);
+
+            faults.inject("b2_list_file_names", Fault::Disconnect);
+            assert_eq!(list().await?, expected);
+
+            faults.inject("b2_list_file_names", Fault::ExpireAuth);
+            assert_eq!(list().await?, expected);
+
+            faults.inject(
+                "b2_list_file_names",
+                Fault::Latency(Duration::from_millis(200)),
+            );
+            assert_eq!(list().await?, expected);
+            assert_eq!(faults.pending(), 0);
+
+            faults.inject(
+                "/download/file/",
+                Fault::SlowBody(Duration::from_millis(20)),
+            );
+            assert_eq!(read().await?, b"This is quite a short file.".to_vec());
+
+            faults.inject("/download/file/", Fault::ExpireAuth);
+            assert_eq!(read().await?, b"This is quite a short file.".to_vec());
+            assert_eq!(faults.pending(), 0);
+
+            // Eventually the client gives up.
+            faults.inject_times(
+                "b2_list_file_names",
+                Fault::Error(StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+                10,
+            );
+            match list().await {
+                Ok(_) => panic!("Listing should have failed."),
+                Err(e) => match e.kind() {
+                    StorageErrorKind::ServiceError => (),
+                    k => panic!("Unexpected error kind {:?}", k),
+                },
+            }
+            faults.clear();
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+    async fn list(fs: &FileStore) -> StorageResult<usize> {
+        let objects: Vec<Object> = fs.list_objects("dir2").await?.try_collect().await?;
+        Ok(objects.len())
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let faults = FaultInjector::new();
+            let (addr, sender) =
+                start_server_with_faults(context.get_fs_root(), 20000, faults.clone())?;
+
+            let connect = |policy: RetryPolicy| {
+                B2Backend::builder("foo", "bar")
+                    .host(&format!("http://{}", addr))
+                    .prefix(ObjectPath::new("dir1").unwrap())
+                    .retry_policy(policy)
+                    .connect()
+            };
+            let fault = || Fault::Error(StatusCode::SERVICE_UNAVAILABLE, "service_unavailable");
+            let quick =
+                RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(5));
+
+            let fs = connect(quick.clone().max_attempts(3)).await?;
+            faults.inject_times("b2_list_file_names", fault(), 2);
+            assert!(list(&fs).await.is_ok());
+            assert_eq!(faults.pending(), 0);
+
+            faults.inject_times("b2_list_file_names", fault(), 3);
+            assert!(list(&fs).await.is_err());
+            assert_eq!(faults.pending(), 0);
+
+            let fs = connect(RetryPolicy::none()).await?;
+            faults.inject("b2_list_file_names", fault());
+            assert!(list(&fs).await.is_err());
+            assert_eq!(faults.pending(), 0);
+
+            // The classifier overrides the backend's choice of errors to retry.
+            let fs =
+                connect(quick.retry_on(|error| error.kind() != StorageErrorKind::ServiceError))
+                    .await?;
+            faults.inject("b2_list_file_names", fault());
+            assert!(list(&fs).await.is_err());
+            faults.clear();
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let faults = FaultInjector::new();
+            let (addr, sender) =
+                start_server_with_faults(context.get_fs_root(), 20000, faults.clone())?;
+
+            let connect = |policy: RetryPolicy| {
+                B2Backend::builder("foo", "bar")
+                    .host(&format!("http://{}", addr))
+                    .prefix(ObjectPath::new("dir1").unwrap())
+                    .retry_policy(policy)
+                    .connect()
+            };
+            let quick = RetryPolicy::new()
+                .backoff(Duration::from_millis(1), Duration::from_millis(5))
+                .max_attempts(2);
+
+            // The server's requested delay replaces the backoff delay.
+            let fs = connect(quick.clone()).await?;
+            faults.inject(
+                "b2_list_file_names",
+                Fault::RetryAfter(StatusCode::TOO_MANY_REQUESTS, "too_many_requests", 1),
+            );
+            let start = Instant::now();
+            assert!(list(&fs).await.is_ok());
+            assert!(start.elapsed() >= Duration::from_secs(1));
+            assert_eq!(faults.pending(), 0);
+
+            faults.inject(
+                "b2_list_file_names",
+                Fault::RetryAfter(StatusCode::SERVICE_UNAVAILABLE, "service_unavailable", 1),
+            );
+            let start = Instant::now();
+            assert!(list(&fs).await.is_ok());
+            assert!(start.elapsed() >= Duration::from_secs(1));
+
+            // Delays longer than the policy allows give up straight away.
+            let fs = connect(quick.clone().max_retry_after(Duration::from_millis(500))).await?;
+            faults.inject(
+                "b2_list_file_names",
+                Fault::RetryAfter(StatusCode::TOO_MANY_REQUESTS, "too_many_requests", 1),
+            );
+            let start = Instant::now();
+            assert!(list(&fs).await.is_err());
+            assert!(start.elapsed() < Duration::from_secs(1));
+
+            // Or the requested delay can be ignored.
+            let fs = connect(quick.honor_retry_after(false)).await?;
+            faults.inject(
+                "b2_list_file_names",
+                Fault::RetryAfter(StatusCode::TOO_MANY_REQUESTS, "too_many_requests", 1),
+            );
+            let start = Instant::now();
+            assert!(list(&fs).await.is_ok());
+            assert!(start.elapsed() < Duration::from_secs(1));
+            faults.clear();
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod middleware {
+    use std::sync::{Arc, Mutex};
+
+    use futures::stream::TryStreamExt;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::http_client::{HttpRequest, HttpResponse, Middleware};
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[derive(Clone, Debug, Default)]
+    struct Audit {
+        requests: Arc<Mutex<Vec<String>>>,
+        statuses: Arc<Mutex<Vec<u16>>>,
+    }
+
+    impl Middleware for Audit {
+        fn on_request(&self, request: &mut HttpRequest) -> StorageResult<()> {
+            let path = request.uri().path().to_owned();
+            if path.ends_with("b2_delete_file_version") {
+                return Err(StorageError::new(
+                    StorageErrorKind::Other,
+                    Some("Deletes are not allowed."),
+                ));
+            }
+
+            self.requests.lock().unwrap().push(path);
+            Ok(())
+        }
+
+        fn on_response(&self, response: &HttpResponse) {
+            self.statuses
+                .lock()
+                .unwrap()
+                .push(response.status().as_u16());
+        }
+    }
+
+    #[test]
+    fn test_b2_middleware() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let audit = Audit::default();
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .middleware(audit.clone())
+                .connect()
+                .await?;
+
+            let objects: Vec<Object> = fs.list_objects("").await?.try_collect().await?;
+            assert!(!objects.is_empty());
+
+            {
+                let requests = audit.requests.lock().unwrap();
+                assert_eq!(requests[0], "/b2api/v2/b2_authorize_account");
+                assert!(requests
+                    .iter()
+                    .any(|path| path.ends_with("b2_list_file_names")));
+                assert_eq!(audit.statuses.lock().unwrap().len(), requests.len());
+            }
+
+            assert!(fs.delete_object("test1/dir1/smallfile.txt").await.is_err());
+            assert!(fs.get_object("test1/dir1/smallfile.txt").await.is_ok());
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod soft_delete {
+    use std::sync::{Arc, Mutex};
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::http_client::{HttpRequest, Middleware};
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[derive(Clone, Debug, Default)]
+    struct Methods {
+        called: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Methods {
+        fn called(&self, method: &str) -> bool {
+            self.called
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|path| path.ends_with(method))
+        }
+    }
+
+    impl Middleware for Methods {
+        fn on_request(&self, request: &mut HttpRequest) -> StorageResult<()> {
+            self.called
+                .lock()
+                .unwrap()
+                .push(request.uri().path().to_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_b2_soft_delete() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let methods = Methods::default();
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .soft_delete(true)
+                .middleware(methods.clone())
+                .connect()
+                .await?;
+
+            fs.delete_object("test1/dir1/smallfile.txt").await?;
+            assert!(fs.get_object("test1/dir1/smallfile.txt").await.is_err());
+            assert!(methods.called("b2_hide_file"));
+            assert!(!methods.called("b2_delete_file_version"));
+
+            assert!(fs.delete_object("test1/dir1/smallfile.txt").await.is_err());
+            assert!(fs.hide_object("test1/dir1").await.is_err());
+
+            fs.hide_object("test1/dir1/mediumfile").await?;
+            assert!(fs.get_object("test1/dir1/mediumfile").await.is_err());
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod server_copy {
+    use std::sync::{Arc, Mutex};
+
+    use futures::stream::TryStreamExt;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::http_client::{HttpRequest, Middleware};
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[derive(Clone, Debug, Default)]
+    struct Requests {
+        paths: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Requests {
+        fn count(&self, pattern: &str) -> usize {
+            self.paths
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|path| path.contains(pattern))
+                .count()
+        }
+    }
+
+    impl Middleware for Requests {
+        fn on_request(&self, request: &mut HttpRequest) -> StorageResult<()> {
+            self.paths
+                .lock()
+                .unwrap()
+                .push(request.uri().path().to_owned());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_b2_server_copy() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let requests = Requests::default();
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .limit_small_file_size(2 * 1024 * 1024)
+                .middleware(requests.clone())
+                .connect()
+                .await?;
+
+            let read = |path: &'static str| {
+                let fs = fs.clone();
+                async move {
+                    let data: Vec<Data> = fs.get_file_stream(path).await?.try_collect().await?;
+                    Ok::<_, StorageError>(data.concat())
+                }
+            };
+
+            fs.copy_file("test1/dir1/smallfile.txt", "test1/dir1/copied.txt")
+                .await?;
+            assert_eq!(requests.count("b2_copy_file"), 1);
+
+            fs.move_file("test1/dir1/mediumfile", "test1/dir1/moved")
+                .await?;
+            assert_eq!(requests.count("b2_copy_part"), 3);
+            assert!(fs.get_object("test1/dir1/mediumfile").await.is_err());
+            assert_eq!(requests.count("/download/"), 0);
+
+            assert_eq!(
+                read("test1/dir1/copied.txt").await?,
+                read("test1/dir1/smallfile.txt").await?
+            );
+            let moved = fs.get_object("test1/dir1/moved").await?;
+            assert_eq!(moved.len(), 5 * 1024 * 1024);
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod file_ids {
+    use futures::stream::TryStreamExt;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[test]
+    fn test_b2_file_ids() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("test1")?)
+                .connect()
+                .await?;
+
+            let file_id = match fs.get_object("dir1/smallfile.txt").await? {
+                Object::B2(object) => object.file_id().unwrap().to_owned(),
+                _ => panic!("Expected a B2 object."),
+            };
+
+            let object = fs.get_object_by_id(&file_id).await?;
+            assert_eq!(object.path(), ObjectPath::new("dir1/smallfile.txt")?);
+            assert_eq!(object.object_type(), ObjectType::File);
+
+            let expected: Vec<Data> = fs
+                .get_file_stream("dir1/smallfile.txt")
+                .await?
+                .try_collect()
+                .await?;
+            let data: Vec<Data> = fs
+                .get_file_stream_by_id(&file_id)
+                .await?
+                .try_collect()
+                .await?;
+            assert_eq!(data.concat(), expected.concat());
+
+            let missing = fs.get_object_by_id("id_missing").await.err().unwrap();
+            assert_eq!(
+                missing.kind(),
+                StorageErrorKind::NotFound(ObjectPath::empty())
+            );
+
+            let narrow = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("test1/dir1/dir2")?)
+                .connect()
+                .await?;
+            assert!(narrow.get_object_by_id(&file_id).await.is_err());
+            assert!(narrow.get_file_stream_by_id(&file_id).await.is_err());
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod presigned {
+    use std::time::Duration;
+
+    use futures::stream::TryStreamExt;
+    use hyper::{Client, StatusCode, Uri};
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[test]
+    fn test_b2_presigned_urls() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("test1")?)
+                .connect()
+                .await?;
+
+            let url = fs
+                .presigned_url("dir1/smallfile.txt", Duration::from_secs(60))
+                .await?;
+            let uri: Uri = url.parse().unwrap();
+
+            let client = Client::new();
+            let response = client.get(uri.clone()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = response.into_body().try_concat().await.unwrap();
+            assert_eq!(&body[..], &b"This is quite a short file."[..]);
+
+            let forged: Uri = url.replace("smallfile.txt", "mediumfile").parse().unwrap();
+            let response = client.get(forged).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+            let authorization = fs
+                .get_download_authorization("dir1/dir2", Duration::from_secs(60))
+                .await?;
+            assert!(authorization
+                .url(&ObjectPath::new("dir1/dir2/daz")?)
+                .is_ok());
+            assert!(authorization
+                .url(&ObjectPath::new("dir1/smallfile.txt")?)
+                .is_err());
+
+            assert!(fs
+                .presigned_url("dir1/smallfile.txt", Duration::from_secs(8 * 24 * 60 * 60))
+                .await
+                .is_err());
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod versions {
+    use futures::stream::TryStreamExt;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::versions::VersionedStorage;
+    use file_store::*;
+
+    use crate::mocks::b2_server::start_server;
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    #[test]
+    fn test_b2_versions() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .connect()
+                .await?;
+
+            let path = ObjectPath::new("test1/dir1/smallfile.txt")?;
+            let versions = fs.list_versions(path.clone()).await?;
+            assert_eq!(versions.len(), 1);
+            let version = &versions[0];
+            assert_eq!(version.path, path);
+            assert!(version.latest);
+            assert!(!version.deleted);
+
+            let expected: Vec<Data> = fs
+                .get_file_stream(path.clone())
+                .await?
+                .try_collect()
+                .await?;
+            let data: Vec<Data> = fs
+                .get_version_stream(path.clone(), &version.id)
+                .await?
+                .try_collect()
+                .await?;
+            assert_eq!(data.concat(), expected.concat());
+            assert_eq!(data.concat().len() as u64, version.len);
+
+            let error = fs
+                .get_version_stream(path.clone(), "id_missing")
+                .await
+                .err()
+                .unwrap();
+            assert_eq!(error.kind(), StorageErrorKind::NotFound(path.clone()));
+
+            fs.delete_version(path.clone(), &version.id).await?;
+            assert!(fs.get_object(path).await.is_err());
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod shared_buffers {
+    use std::mem;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures::future::ready;
+    use futures::stream::{iter, once, StreamExt};
+    use http::StatusCode;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::http_client::{HttpRequest, Middleware, RequestBody};
+    use file_store::*;
+
+    use crate::mocks::b2_server::{start_server_with_faults, Fault, FaultInjector};
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    /// Records the address of every chunk of data sent in an upload.
+    #[derive(Clone, Debug, Default)]
+    struct ChunkRecorder {
+        chunks: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl ChunkRecorder {
+        fn take(&self) -> Vec<usize> {
+            mem::replace(&mut *self.chunks.lock().unwrap(), Vec::new())
+        }
+    }
+
+    impl Middleware for ChunkRecorder {
+        fn on_request(&self, request: &mut HttpRequest) -> StorageResult<()> {
+            if !request.uri().path().starts_with("/upload/") {
+                return Ok(());
+            }
+
+            let chunks = self.chunks.clone();
+            let body = mem::replace(request.body_mut(), RequestBody::Empty).into_stream();
+            *request.body_mut() =
+                RequestBody::Stream(DataStream::from_stream(body.inspect(move |chunk| {
+                    if let Ok(data) = chunk {
+                        chunks.lock().unwrap().push(data.as_ptr() as usize);
+                    }
+                })));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retries_share_buffers() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let faults = FaultInjector::new();
+            let (addr, sender) =
+                start_server_with_faults(context.get_fs_root(), 20000, faults.clone())?;
+
+            let recorder = ChunkRecorder::default();
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .limit_small_file_size(500)
+                .retry_policy(
+                    RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(10)),
+                )
+                .middleware(recorder.clone())
+                .connect()
+                .await?;
+
+            // A small file is sent from the caller's buffer on every attempt.
+            let data = Bytes::from(vec![3; 400]);
+            faults.inject(
+                "/upload/file/",
+                Fault::ErrorAfterBody(StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+            );
+            fs.write_bytes("test1/dir1/shared", data.clone()).await?;
+            assert_eq!(faults.pending(), 0);
+            let address = data.as_ptr() as usize;
+            assert_eq!(recorder.take(), vec![address, address]);
+            assert_eq!(fs.read_to_bytes("test1/dir1/shared").await?, data);
+
+            // As are the parts of a large file.
+            let chunks: Vec<Data> = (0..6u8).map(|i| Bytes::from(vec![i; 400])).collect();
+            let addresses: Vec<usize> = chunks.iter().map(|c| c.as_ptr() as usize).collect();
+            faults.inject(
+                "/upload/part/",
+                Fault::ErrorAfterBody(StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+            );
+            let stream = iter(chunks.clone()).map(Ok);
+            StorageBackend::write_file_from_stream(
+                &fs,
+                UploadInfo::from(ObjectPath::new("test1/dir1/parts")?),
+                DataStream::from_stream(stream),
+            )
+            .await?;
+            assert_eq!(faults.pending(), 0);
+            let sent = recorder.take();
+            assert!(sent.len() > addresses.len());
+            assert!(sent.iter().all(|address| addresses.contains(address)));
+            assert_eq!(fs.read_to_bytes("test1/dir1/parts").await?, chunks.concat());
+
+            // Chunks from other kinds of streams are copied.
+            let stream = once(ready(Ok::<_, StorageError>(data.clone())));
+            fs.write_file_from_stream("test1/dir1/copied", stream)
+                .await?;
+            assert_ne!(recorder.take(), vec![address]);
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
+
+mod lazy_listing {
+    use futures::stream::StreamExt;
+
+    use file_store::backends::b2::B2Backend;
+    use file_store::backends::Backend;
+    use file_store::*;
+
+    use crate::mocks::b2_server::{start_counting_server, FaultInjector, RequestCounter};
+    use file_store::testing::{prepare_test, run, TestError, TestResult};
+
+    const LIST: &str = "b2_list_file_versions";
+
+    #[test]
+    fn test_pages_requested_on_demand() {
+        let result: TestResult<()> = run(async {
+            let context = prepare_test(Backend::B2, "test1")?;
+            let requests = RequestCounter::new();
+            let (addr, sender) = start_counting_server(
+                context.get_fs_root(),
+                20000,
+                FaultInjector::new(),
+                requests.clone(),
+            )?;
+
+            let fs = B2Backend::builder("foo", "bar")
+                .host(&format!("http://{}", addr))
+                .prefix(ObjectPath::new("test1")?)
+                .limit_list_page_size(1)
+                .connect()
+                .await?;
+
+            for i in 0..5u8 {
+                fs.write_bytes(format!("paged/file{}", i).as_str(), vec![i; 10])
+                    .await?;
+            }
+
+            requests.watch(LIST);
+            let mut stream = fs.list_objects("paged").await?;
+            assert_eq!(requests.get(LIST).total, 0);
+
+            // Each file is only returned once the next file has been seen.
+            for i in 0..5usize {
+                let object = stream.next().await.unwrap()?;
+                assert_eq!(object.path(), ObjectPath::new(format!("paged/file{}", i))?);
+                assert_eq!(requests.get(LIST).total, (i + 2).min(5));
+            }
+
+            assert!(stream.next().await.is_none());
+            assert_eq!(requests.get(LIST).total, 5);
+
+            sender.send(()).map_err(|()| {
+                TestError::HarnessFailure(String::from(
+                    "Failed to send shutdown to mock b2 server.",
+                ))
+            })
+        });
+
+        if let Err(error) = result {
+            panic!(error.to_string());
+        }
+    }
+}
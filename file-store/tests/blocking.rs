@@ -0,0 +1,57 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", feature = "blocking", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs::create_dir_all;
+
+use tempfile::tempdir;
+
+use file_store::backends::file::FileBackend;
+use file_store::blocking::FileStoreSync;
+use file_store::*;
+
+#[test]
+fn test_blocking_round_trip() {
+    let temp = tempdir().unwrap();
+    create_dir_all(temp.path().join("dir")).unwrap();
+
+    let store = FileStoreSync::connect(FileBackend::connect(temp.path())).unwrap();
+
+    store
+        .put("dir/file.txt", &b"Some blocking data."[..])
+        .unwrap();
+    assert_eq!(
+        &store.get("dir/file.txt").unwrap()[..],
+        b"Some blocking data."
+    );
+
+    let objects = store.list("dir/").unwrap();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0].path(), ObjectPath::new("dir/file.txt").unwrap());
+    assert_eq!(objects[0].len(), 19);
+
+    store.delete("dir/file.txt").unwrap();
+    assert!(store.list("dir/").unwrap().is_empty());
+
+    match store.get("dir/file.txt") {
+        Ok(_) => panic!("Should have failed to read a deleted file."),
+        Err(e) => assert_eq!(
+            e.kind(),
+            StorageErrorKind::NotFound(ObjectPath::new("dir/file.txt").unwrap())
+        ),
+    }
+}
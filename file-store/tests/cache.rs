@@ -0,0 +1,148 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+use std::thread::sleep;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::once;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::cache::CacheSettings;
+use file_store::*;
+
+async fn write(store: &FileStore, path: &str, data: &'static str) {
+    store
+        .write_file_from_stream(path, once(ready(Ok::<_, StorageError>(Bytes::from(data)))))
+        .await
+        .unwrap();
+}
+
+#[test]
+fn test_object_cache() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        write(&store, "dir/a.txt", "Some data.").await;
+        write(&store, "dir/b.txt", "Other data.").await;
+
+        store.cache_objects(Some(CacheSettings::new(Duration::from_secs(60))));
+
+        assert_eq!(store.get_object("dir/a.txt").await.unwrap().len(), 10);
+
+        // Changes made elsewhere aren't seen, by this store or its clones.
+        fs::write(temp.path().join("dir").join("a.txt"), "Changed").unwrap();
+        assert_eq!(store.get_object("dir/a.txt").await.unwrap().len(), 10);
+        assert_eq!(
+            store.clone().get_object("dir/a.txt").await.unwrap().len(),
+            10
+        );
+
+        store.clear_object_cache();
+        assert_eq!(store.get_object("dir/a.txt").await.unwrap().len(), 7);
+
+        // Changes made through the store are.
+        write(&store, "dir/a.txt", "Some more data.").await;
+        assert_eq!(store.get_object("dir/a.txt").await.unwrap().len(), 15);
+
+        assert_eq!(store.get_object("dir/b.txt").await.unwrap().len(), 11);
+        store.delete_object("dir").await.unwrap();
+        assert!(store.get_object("dir/a.txt").await.is_err());
+        assert!(store.get_object("dir/b.txt").await.is_err());
+
+        // Disabling the cache always asks the backend.
+        write(&store, "c.txt", "Data.").await;
+        store.cache_objects(None);
+        assert_eq!(store.get_object("c.txt").await.unwrap().len(), 5);
+        fs::write(temp.path().join("c.txt"), "More data.").unwrap();
+        assert_eq!(store.get_object("c.txt").await.unwrap().len(), 10);
+    });
+}
+
+#[test]
+fn test_object_cache_limits() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        write(&store, "a.txt", "Some data.").await;
+        write(&store, "b.txt", "Other data.").await;
+
+        store.cache_objects(Some(
+            CacheSettings::new(Duration::from_millis(200)).capacity(1),
+        ));
+
+        assert_eq!(store.get_object("a.txt").await.unwrap().len(), 10);
+        fs::write(temp.path().join("a.txt"), "Changed").unwrap();
+        assert_eq!(store.get_object("a.txt").await.unwrap().len(), 10);
+
+        // Caching another object evicts the first.
+        assert_eq!(store.get_object("b.txt").await.unwrap().len(), 11);
+        assert_eq!(store.get_object("a.txt").await.unwrap().len(), 7);
+
+        // Entries expire.
+        fs::write(temp.path().join("a.txt"), "Changed again").unwrap();
+        sleep(Duration::from_millis(300));
+        assert_eq!(store.get_object("a.txt").await.unwrap().len(), 13);
+    });
+}
+
+#[test]
+fn test_not_found_cache() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+
+        // Missing paths aren't cached by default.
+        store.cache_objects(Some(CacheSettings::new(Duration::from_secs(60))));
+        assert!(store.get_object("a.txt").await.is_err());
+        fs::write(temp.path().join("a.txt"), "Some data.").unwrap();
+        assert_eq!(store.get_object("a.txt").await.unwrap().len(), 10);
+
+        store.cache_objects(Some(
+            CacheSettings::new(Duration::from_secs(60))
+                .not_found_ttl(Some(Duration::from_millis(200))),
+        ));
+
+        let path = ObjectPath::new("b.txt").unwrap();
+        let error = store.get_object(path.clone()).await.unwrap_err();
+        assert_eq!(error.kind(), StorageErrorKind::NotFound(path.clone()));
+
+        fs::write(temp.path().join("b.txt"), "Other data.").unwrap();
+        let error = store.get_object(path.clone()).await.unwrap_err();
+        assert_eq!(error.kind(), StorageErrorKind::NotFound(path.clone()));
+
+        // Missing entries expire sooner.
+        sleep(Duration::from_millis(300));
+        assert_eq!(store.get_object("b.txt").await.unwrap().len(), 11);
+
+        // Writing through the store forgets that the path was missing.
+        assert!(store.get_object("c.txt").await.is_err());
+        write(&store, "c.txt", "Data.").await;
+        assert_eq!(store.get_object("c.txt").await.unwrap().len(), 5);
+    });
+}
@@ -0,0 +1,152 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "cas", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use bytes::Bytes;
+use futures::stream::TryStreamExt;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::cas::*;
+use file_store::testing::data_stream;
+use file_store::*;
+
+/// Generates some random looking but repeatable data.
+fn data(seed: u32, length: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..length)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+async fn count_chunks(store: &FileStore) -> usize {
+    let objects: Vec<Object> = store
+        .list_objects("chunks/")
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    objects
+        .iter()
+        .filter(|o| o.object_type() == ObjectType::File)
+        .count()
+}
+
+fn chunk_store(store: &FileStore) -> ChunkStore {
+    ChunkStore::new(store.clone())
+        .chunk_sizes(1024, 4096, 16384)
+        .unwrap()
+}
+
+/// Inserts some data in the middle of the original.
+fn modify(original: &[u8]) -> Vec<u8> {
+    let mut modified = original[..100_000].to_vec();
+    modified.extend(data(2, 500));
+    modified.extend_from_slice(&original[100_000..]);
+    modified
+}
+
+async fn read(cas: &ChunkStore, path: &str) -> Vec<u8> {
+    let read: Vec<Bytes> = cas.read(path).await.unwrap().try_collect().await.unwrap();
+    read.concat()
+}
+
+#[test]
+fn test_deduplication() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let cas = chunk_store(&store);
+
+        let original = data(1, 200_000);
+        // Write in pieces that don't line up with chunk boundaries.
+        let summary = cas
+            .write("backup/1", data_stream(original.chunks(1000)))
+            .await
+            .unwrap();
+        assert_eq!(summary.manifest.size, 200_000);
+        assert_eq!(summary.new_bytes, 200_000);
+        assert_eq!(summary.new_chunks, summary.manifest.chunks.len());
+        assert_eq!(count_chunks(&store).await, summary.new_chunks);
+
+        let modified = modify(&original);
+        let summary = cas
+            .write("backup/2", data_stream(modified.chunks(1000)))
+            .await
+            .unwrap();
+        assert_eq!(summary.manifest.size, 200_500);
+        assert!(summary.new_chunks <= 4);
+        assert!(summary.new_bytes < 40_000);
+
+        assert_eq!(read(&cas, "backup/2").await, modified);
+
+        let mut paths = cas.list().await.unwrap();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                ObjectPath::new("backup/1").unwrap(),
+                ObjectPath::new("backup/2").unwrap()
+            ]
+        );
+    });
+}
+
+#[test]
+fn test_garbage_collection() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let cas = chunk_store(&store);
+
+        let original = data(1, 200_000);
+        cas.write("backup/1", data_stream(original.chunks(1000)))
+            .await
+            .unwrap();
+        let modified = modify(&original);
+        cas.write("backup/2", data_stream(modified.chunks(1000)))
+            .await
+            .unwrap();
+
+        assert_eq!(cas.collect_garbage().await.unwrap(), 0);
+
+        cas.delete("backup/2").await.unwrap();
+        let before = count_chunks(&store).await;
+        let removed = cas.collect_garbage().await.unwrap();
+        assert!(removed > 0);
+        assert_eq!(count_chunks(&store).await, before - removed);
+
+        assert_eq!(read(&cas, "backup/1").await, original);
+
+        match cas.read("backup/2").await {
+            Ok(_) => panic!("Should have failed to read a deleted file."),
+            Err(e) => assert_eq!(
+                e.kind(),
+                StorageErrorKind::NotFound(ObjectPath::new("backup/2").unwrap())
+            ),
+        }
+    });
+}
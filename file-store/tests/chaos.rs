@@ -0,0 +1,141 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::{iter, once, StreamExt};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::testing::chaos::{ChaosBackend, Fault, Rule, Trigger};
+use file_store::*;
+
+async fn read<B: StorageBackend>(store: &B, path: &ObjectPath) -> StorageResult<Vec<u8>> {
+    let mut stream = store.get_file_stream(path.clone()).await?;
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk?);
+    }
+    Ok(data)
+}
+
+#[test]
+fn test_chaos() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        fs::write(temp.path().join("a.txt"), "Some data.").unwrap();
+
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let chaos = ChaosBackend::new(store);
+        let path = ObjectPath::new("a.txt").unwrap();
+
+        // Only the second lookup fails.
+        chaos.inject(
+            Rule::new(
+                Trigger::Nth(2),
+                Fault::Error(StorageErrorKind::ServiceError),
+            )
+            .operation("get_object"),
+        );
+        assert!(chaos.get_object(path.clone()).await.is_ok());
+        assert_eq!(
+            chaos.get_object(path.clone()).await.unwrap_err().kind(),
+            StorageErrorKind::ServiceError
+        );
+        assert!(chaos.get_object(path.clone()).await.is_ok());
+        assert_eq!(chaos.operations(), 3);
+        assert_eq!(chaos.injected(), 1);
+        chaos.clear();
+
+        // Downloads can be cut off with or without an error.
+        chaos.inject(Rule::new(Trigger::Nth(1), Fault::Disconnect(4)));
+        assert_eq!(
+            read(&chaos, &path).await.unwrap_err().kind(),
+            StorageErrorKind::ConnectionClosed
+        );
+        chaos.inject(Rule::new(Trigger::Nth(1), Fault::Truncate(4)));
+        assert_eq!(read(&chaos, &path).await.unwrap(), b"Some");
+        assert_eq!(read(&chaos, &path).await.unwrap(), b"Some data.");
+        chaos.clear();
+
+        // Uploads too.
+        chaos.inject(Rule::new(Trigger::Always, Fault::Truncate(3)));
+        chaos
+            .write_file_from_stream(
+                ObjectPath::new("b.txt").unwrap().into(),
+                DataStream::from_stream(once(ready(Ok(Bytes::from("More data."))))),
+            )
+            .await
+            .unwrap();
+        assert_eq!(fs::read(temp.path().join("b.txt")).unwrap(), b"Mor");
+        chaos.clear();
+
+        chaos.inject(Rule::new(
+            Trigger::Always,
+            Fault::Latency(Duration::from_millis(50)),
+        ));
+        let start = Instant::now();
+        chaos.get_object(path.clone()).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    });
+}
+
+#[test]
+fn test_chaos_throttling() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        fs::write(temp.path().join("a.txt"), "Some data.").unwrap();
+
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let chaos = ChaosBackend::new(store);
+        let path = ObjectPath::new("a.txt").unwrap();
+
+        // 10 bytes at 100 bytes per second.
+        chaos.inject(Rule::new(Trigger::Always, Fault::Throughput(100)));
+        let start = Instant::now();
+        assert_eq!(read(&chaos, &path).await.unwrap(), b"Some data.");
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        chaos.clear();
+
+        chaos.inject(Rule::new(
+            Trigger::Always,
+            Fault::ChunkDelay(Duration::from_millis(50)),
+        ));
+        let start = Instant::now();
+        chaos
+            .write_file_from_stream(
+                ObjectPath::new("b.txt").unwrap().into(),
+                DataStream::from_stream(iter(vec![
+                    Ok(Bytes::from("More ")),
+                    Ok(Bytes::from("data.")),
+                ])),
+            )
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(fs::read(temp.path().join("b.txt")).unwrap(), b"More data.");
+    });
+}
@@ -0,0 +1,76 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "compression")]
+
+extern crate file_store;
+
+use futures::stream::TryStreamExt;
+use tokio::runtime::Runtime;
+
+use file_store::compression::*;
+use file_store::testing::data_stream;
+use file_store::*;
+
+async fn collect(stream: DataStream) -> StorageResult<Vec<u8>> {
+    let chunks: Vec<Data> = stream.try_collect().await?;
+    Ok(chunks.concat())
+}
+
+#[test]
+fn test_round_trip() {
+    let runtime = Runtime::new().unwrap();
+    let data = "Some compressible data. ".repeat(200).into_bytes();
+
+    for encoding in &[
+        Encoding::Gzip,
+        Encoding::Deflate,
+        Encoding::Zlib,
+        Encoding::Brotli,
+    ] {
+        let compressed = runtime
+            .block_on(collect(compress(data_stream(data.chunks(100)), *encoding)))
+            .unwrap();
+        assert!(compressed.len() < data.len());
+
+        let decompressed = runtime
+            .block_on(collect(decompress(
+                data_stream(compressed.chunks(100)),
+                *encoding,
+            )))
+            .unwrap();
+        assert_eq!(decompressed, data);
+    }
+}
+
+#[test]
+fn test_invalid_data() {
+    let runtime = Runtime::new().unwrap();
+
+    let stream = data_stream(&["Not compressed."]);
+    let error = runtime
+        .block_on(collect(decompress(stream, Encoding::Gzip)))
+        .unwrap_err();
+    assert_eq!(error.kind(), StorageErrorKind::InvalidData);
+}
+
+#[test]
+fn test_names() {
+    assert_eq!("gzip".parse::<Encoding>().unwrap(), Encoding::Gzip);
+    assert_eq!("BR".parse::<Encoding>().unwrap(), Encoding::Brotli);
+    assert!("lzma".parse::<Encoding>().is_err());
+    assert_eq!(Encoding::Brotli.to_string(), "br");
+    assert_eq!(Encoding::from_extension("gz"), Some(Encoding::Gzip));
+    assert_eq!(Encoding::from_extension("txt"), None);
+}
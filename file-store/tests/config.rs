@@ -0,0 +1,82 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "config")]
+
+extern crate file_store;
+
+use serde_json::{from_str, json};
+
+use file_store::config::*;
+
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+#[test]
+fn test_file_config() {
+    use tempfile::tempdir;
+    use tokio::runtime::Runtime;
+
+    use file_store::backends::Backend;
+    use file_store::FileStore;
+
+    let temp = tempdir().unwrap();
+    let config: StoreConfig = from_str(
+        &json!({
+            "type": "file",
+            "root": temp.path(),
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    assert_eq!(
+        config,
+        StoreConfig::File(FileConfig {
+            root: temp.path().to_owned(),
+        })
+    );
+
+    let runtime = Runtime::new().unwrap();
+    let store = runtime.block_on(FileStore::from_config(config)).unwrap();
+    assert_eq!(store.backend_type(), Backend::File);
+}
+
+#[cfg(feature = "b2")]
+#[test]
+fn test_b2_config() {
+    let config: StoreConfig = from_str(
+        r#"{
+            "type": "b2",
+            "key_id": "foo",
+            "key": "bar",
+            "prefix": "bucket/dir",
+            "max_requests": 5
+        }"#,
+    )
+    .unwrap();
+
+    match config {
+        StoreConfig::B2(b2) => {
+            assert_eq!(b2.key_id, "foo");
+            assert_eq!(b2.key, "bar");
+            assert_eq!(b2.prefix, Some(String::from("bucket/dir")));
+            assert_eq!(b2.max_requests, Some(5));
+            assert_eq!(b2.host, None);
+        }
+        #[allow(unreachable_patterns)]
+        _ => panic!("Expected a b2 config."),
+    }
+
+    assert!(from_str::<StoreConfig>(r#"{ "type": "b2", "key_id": "foo" }"#).is_err());
+    assert!(from_str::<StoreConfig>(r#"{ "type": "unknown" }"#).is_err());
+}
@@ -0,0 +1,95 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::copy::CopyOptions;
+use file_store::*;
+
+#[test]
+fn test_copy_prefix() {
+    let temp = tempdir().unwrap();
+    fs::create_dir_all(temp.path().join("source/dir")).unwrap();
+    fs::write(temp.path().join("source/a"), "aaa").unwrap();
+    fs::write(temp.path().join("source/dir/b"), "bb").unwrap();
+    fs::write(temp.path().join("source/dir/c"), "c").unwrap();
+    fs::write(temp.path().join("sourcefile"), "not included").unwrap();
+
+    let root = temp.path().to_owned();
+    let progress = Arc::new(AtomicUsize::new(0));
+    let counter = progress.clone();
+    Runtime::new().unwrap().block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        let options = CopyOptions::new().concurrency(2).on_progress(move |p| {
+            assert_eq!(p.total, 3);
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let summary = store
+            .copy_prefix("source", "target", options)
+            .await
+            .unwrap();
+
+        assert!(summary.is_success());
+        assert_eq!(summary.copied.len(), 3);
+        assert_eq!(summary.bytes, 6);
+    });
+
+    assert_eq!(progress.load(Ordering::SeqCst), 3);
+    assert_eq!(fs::read(temp.path().join("target/a")).unwrap(), b"aaa");
+    assert_eq!(fs::read(temp.path().join("target/dir/b")).unwrap(), b"bb");
+    assert_eq!(fs::read(temp.path().join("target/dir/c")).unwrap(), b"c");
+}
+
+#[test]
+fn test_copy_prefix_failures() {
+    let temp = tempdir().unwrap();
+    fs::create_dir_all(temp.path().join("source/dir")).unwrap();
+    fs::write(temp.path().join("source/a"), "aaa").unwrap();
+    fs::write(temp.path().join("source/dir/b"), "bb").unwrap();
+    fs::create_dir_all(temp.path().join("target")).unwrap();
+    // A file where the directory would need to be stops one copy.
+    fs::write(temp.path().join("target/dir"), "in the way").unwrap();
+
+    let root = temp.path().to_owned();
+    Runtime::new().unwrap().block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        let summary = store
+            .copy_prefix("source", "target", CopyOptions::new())
+            .await
+            .unwrap();
+
+        assert!(!summary.is_success());
+        assert_eq!(summary.copied, vec![ObjectPath::new("source/a").unwrap()]);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(
+            summary.failed[0].0,
+            ObjectPath::new("source/dir/b").unwrap()
+        );
+        assert_eq!(summary.bytes, 3);
+    });
+
+    assert_eq!(fs::read(temp.path().join("target/a")).unwrap(), b"aaa");
+}
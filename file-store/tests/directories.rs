@@ -0,0 +1,75 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+
+use futures::stream::TryStreamExt;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::*;
+
+#[test]
+fn test_native_missing_prefix() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        assert_eq!(store.directory_semantics(), DirectorySemantics::Native);
+
+        let missing: StorageResult<Vec<Object>> = store
+            .list_objects("missing/")
+            .await
+            .unwrap()
+            .try_collect()
+            .await;
+        assert!(missing.is_err());
+    });
+}
+
+#[test]
+fn test_strict_slash_root() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    fs::create_dir_all(temp.path().join("dir").join("sub")).unwrap();
+    fs::write(temp.path().join("dir").join("a"), "a").unwrap();
+    fs::write(temp.path().join("dir").join("sub").join("b"), "b").unwrap();
+    fs::write(temp.path().join("dirfile"), "d").unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path())
+            .await
+            .unwrap()
+            .with_directory_semantics(DirectorySemantics::StrictSlash);
+
+        // Listing the root includes files but not directories.
+        let mut paths: Vec<String> = store
+            .list_objects("")
+            .await
+            .unwrap()
+            .map_ok(|o| o.path().to_string())
+            .try_collect()
+            .await
+            .unwrap();
+        paths.sort();
+        assert_eq!(paths, vec!["dir/a", "dir/sub/b", "dirfile"]);
+    });
+}
@@ -0,0 +1,142 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "disk-cache", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::{once, StreamExt};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::disk_cache::CachingBackend;
+use file_store::*;
+
+async fn write<B: StorageBackend>(store: &B, path: &str, data: &'static str) {
+    store
+        .write_file_from_stream(
+            ObjectPath::new(path).unwrap(),
+            WrappedStream::from_stream(once(ready(Ok::<_, StorageError>(Bytes::from(data))))),
+        )
+        .await
+        .unwrap();
+}
+
+async fn read<B: StorageBackend>(store: &B, path: &str) -> String {
+    let mut stream = store
+        .get_file_stream(ObjectPath::new(path).unwrap())
+        .await
+        .unwrap();
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    String::from_utf8(data).unwrap()
+}
+
+#[test]
+fn test_cached_reads() {
+    let temp = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let backend = FileBackend::connect(temp.path()).await.unwrap();
+        let store = CachingBackend::new(backend, cache_dir.path()).max_size(25);
+        let a = ObjectPath::new("dir/a.txt").unwrap();
+
+        write(store.inner(), "dir/a.txt", "Some data.").await;
+
+        assert_eq!(read(&store, "dir/a.txt").await, "Some data.");
+        assert!(store.is_cached(&a));
+        assert_eq!(store.cached_size(), 10);
+
+        // Ranged reads are served from the cached copy.
+        let mut stream = store
+            .get_file_stream_range(a.clone(), 5, Some(3))
+            .await
+            .unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap(), Bytes::from("dat"));
+    });
+}
+
+#[test]
+fn test_invalidation() {
+    let temp = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let backend = FileBackend::connect(temp.path()).await.unwrap();
+        let store = CachingBackend::new(backend, cache_dir.path()).max_size(25);
+        let a = ObjectPath::new("dir/a.txt").unwrap();
+
+        write(store.inner(), "dir/a.txt", "Some data.").await;
+        assert_eq!(read(&store, "dir/a.txt").await, "Some data.");
+
+        // Changes made elsewhere are noticed.
+        fs::write(temp.path().join("dir").join("a.txt"), "Changed data").unwrap();
+        assert_eq!(read(&store, "dir/a.txt").await, "Changed data");
+        assert_eq!(store.cached_size(), 12);
+
+        // Changes made through the backend remove the cached copy.
+        write(&store, "dir/a.txt", "More data.").await;
+        assert!(!store.is_cached(&a));
+        assert_eq!(store.cached_size(), 0);
+        assert_eq!(read(&store, "dir/a.txt").await, "More data.");
+    });
+}
+
+#[test]
+fn test_eviction() {
+    let temp = tempdir().unwrap();
+    let cache_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let backend = FileBackend::connect(temp.path()).await.unwrap();
+        let store = CachingBackend::new(backend, cache_dir.path()).max_size(25);
+        let a = ObjectPath::new("dir/a.txt").unwrap();
+
+        write(store.inner(), "dir/a.txt", "Some data.").await;
+        let b = ObjectPath::new("dir/b.txt").unwrap();
+        let c = ObjectPath::new("c.txt").unwrap();
+        write(store.inner(), "dir/b.txt", "Other data.").await;
+        write(store.inner(), "c.txt", "Last data.").await;
+
+        // The least recently used files are evicted once the cache is full.
+        assert_eq!(read(&store, "dir/b.txt").await, "Other data.");
+        assert_eq!(read(&store, "dir/a.txt").await, "Some data.");
+        assert_eq!(read(&store, "c.txt").await, "Last data.");
+        assert!(store.is_cached(&a));
+        assert!(!store.is_cached(&b));
+        assert!(store.is_cached(&c));
+        assert_eq!(store.cached_size(), 20);
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 2);
+
+        // Deleting a directory removes everything cached beneath it.
+        store
+            .delete_object(ObjectPath::new("dir").unwrap())
+            .await
+            .unwrap();
+        assert!(!store.is_cached(&a));
+        assert_eq!(store.cached_size(), 10);
+        assert_eq!(fs::read_dir(cache_dir.path()).unwrap().count(), 1);
+    });
+}
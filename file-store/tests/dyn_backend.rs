@@ -0,0 +1,63 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::{once, TryStreamExt};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::*;
+
+#[test]
+fn test_dyn_backend() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let backend: Arc<dyn StorageBackend> = Arc::new(store);
+
+        let path = ObjectPath::new("file.txt").unwrap();
+        let data = DataStream::from_stream(once(ready(Ok(Bytes::from("Some data.")))));
+        backend
+            .write_file_from_stream(UploadInfo::from(path.clone()), data)
+            .await
+            .unwrap();
+
+        let objects: Vec<Object> = backend
+            .list_objects(ObjectPath::empty())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].path(), path);
+        assert_eq!(objects[0].len(), 10);
+
+        backend.delete_object(path.clone()).await.unwrap();
+        match backend.get_object(path.clone()).await {
+            Ok(_) => panic!("Should have failed to find a deleted file."),
+            Err(e) => assert_eq!(e.kind(), StorageErrorKind::NotFound(path)),
+        }
+    });
+}
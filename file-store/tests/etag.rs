@@ -0,0 +1,59 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+
+use futures::stream::TryStreamExt;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::*;
+
+#[test]
+fn test_temporary_files_hidden() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    // A file left behind by a write that crashed.
+    fs::create_dir_all(temp.path().join("dir").join(".partial")).unwrap();
+    fs::write(temp.path().join("dir").join("file"), "a").unwrap();
+    fs::write(
+        temp.path().join("dir").join(".partial").join("file.1-0"),
+        "b",
+    )
+    .unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        let mut paths: Vec<String> = store
+            .list_objects("")
+            .await
+            .unwrap()
+            .map_ok(|o| o.path().to_string())
+            .try_collect()
+            .await
+            .unwrap();
+        paths.sort();
+        assert_eq!(paths, vec!["dir", "dir/file"]);
+
+        assert!(store.write_bytes("dir/.partial/other", "c").await.is_err());
+    });
+}
@@ -0,0 +1,44 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use futures::stream::StreamExt;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::events::*;
+use file_store::*;
+
+#[test]
+fn test_events_end_with_store() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let mut events = store.events();
+
+        store.write_bytes("file.txt", "Some data.").await.unwrap();
+        let event = events.next().await.unwrap();
+        assert_eq!(event.operation, Operation::Write);
+
+        // Dropping the store closes the stream.
+        drop(store);
+        assert!(events.next().await.is_none());
+    });
+}
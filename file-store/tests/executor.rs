@@ -0,0 +1,94 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "executor", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use tempfile::tempdir;
+
+use file_store::backends::file::FileBackend;
+use file_store::executor::{run, spawn, spawn_blocking};
+use file_store::*;
+
+#[test]
+fn run_outside_runtime() -> Result<(), StorageError> {
+    let temp = tempdir()?;
+    fs::write(temp.path().join("test.txt"), "hello")?;
+
+    let store = run(FileBackend::connect(temp.path()))?;
+    let object = run(store.get_object("test.txt"))?;
+    assert_eq!(object.len(), 5);
+
+    Ok(())
+}
+
+#[test]
+fn spawn_outside_runtime() {
+    let (sender, receiver) = channel();
+    spawn(async move {
+        sender.send(5).unwrap();
+    });
+
+    assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(5));
+}
+
+#[test]
+fn spawn_blocking_result() -> Result<(), StorageError> {
+    assert_eq!(run(spawn_blocking(|| 2 + 3))?, 5);
+
+    let result = run(spawn_blocking(|| -> u32 { panic!("Expected panic.") }));
+    match result {
+        Err(e) => match e.kind() {
+            StorageErrorKind::Cancelled => (),
+            k => panic!("Unexpected error kind {:?}", k),
+        },
+        Ok(_) => panic!("Expected the panic to cancel the future."),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn spawn_blocking_reuses_threads() -> Result<(), StorageError> {
+    use std::collections::HashSet;
+    use std::thread;
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let threads = runtime.block_on(async {
+        let mut threads = HashSet::new();
+        for _ in 0..10 {
+            threads.insert(spawn_blocking(|| thread::current().id()).await?);
+        }
+        Ok::<_, StorageError>(threads)
+    })?;
+
+    // A new thread per call would give ten different threads.
+    assert!(threads.len() < 10);
+
+    let threads = run(async {
+        let mut threads = HashSet::new();
+        for _ in 0..10 {
+            threads.insert(spawn_blocking(|| thread::current().id()).await?);
+        }
+        Ok::<_, StorageError>(threads)
+    })?;
+    assert!(threads.len() < 10);
+
+    Ok(())
+}
@@ -12,17 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![cfg(feature = "file")]
-
-extern crate file_store;
+#![cfg(all(feature = "file", not(feature = "wasm")))]
 
 #[macro_use]
-mod runner;
+extern crate file_store;
 
 mod dir1 {
-    use crate::runner::{TestContext, TestResult};
     use file_store::backends::file::FileBackend;
     use file_store::backends::Backend;
+    use file_store::testing::{TestContext, TestResult};
     use file_store::FileStore;
 
     async fn build_fs(context: &TestContext) -> TestResult<(FileStore, ())> {
@@ -37,9 +35,9 @@ mod dir1 {
 }
 
 mod test1 {
-    use crate::runner::{TestContext, TestResult};
     use file_store::backends::file::FileBackend;
     use file_store::backends::Backend;
+    use file_store::testing::{TestContext, TestResult};
     use file_store::FileStore;
 
     async fn build_fs(context: &TestContext) -> TestResult<(FileStore, ())> {
@@ -0,0 +1,98 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::testing::{Content, ContentIterator, FixtureBuilder};
+use file_store::*;
+
+fn modified() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(1_500_000_000)
+}
+
+fn fixture() -> FixtureBuilder {
+    FixtureBuilder::new()
+        .file("top", "Some text.")
+        .file_modified("dir/empty", Content::Empty, modified())
+        .file(
+            "dir/sub/generated",
+            Content::Generated {
+                seed: 5,
+                length: 5000,
+            },
+        )
+}
+
+#[test]
+fn test_build_fixture() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let fixture = fixture();
+    assert_eq!(fixture.files().len(), 3);
+    assert_eq!(fixture.files()[2].content().len(), 5000);
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+        fixture
+            .build(&store, &ObjectPath::new("prefix").unwrap())
+            .await
+            .unwrap();
+
+        let prefix = root.join("prefix");
+        assert_eq!(fs::read(prefix.join("top")).unwrap(), b"Some text.");
+        assert_eq!(fs::read(prefix.join("dir").join("empty")).unwrap(), b"");
+        assert_eq!(
+            fs::metadata(prefix.join("dir").join("empty"))
+                .unwrap()
+                .modified()
+                .unwrap(),
+            modified()
+        );
+        assert_eq!(
+            fs::read(prefix.join("dir").join("sub").join("generated")).unwrap(),
+            ContentIterator::new(5, 5000).collect::<Vec<u8>>()
+        );
+    });
+}
+
+#[test]
+fn test_create_fixture() {
+    let temp = tempdir().unwrap();
+    let fixture = fixture();
+
+    // Creating the fixture locally gives the same files.
+    let local = temp.path().join("local");
+    fixture.create_in(&local).unwrap();
+    for file in fixture.files() {
+        let path = file
+            .path()
+            .split('/')
+            .fold(local.clone(), |p, part| p.join(part));
+        assert_eq!(
+            fs::read(path).unwrap(),
+            file.content().bytes().collect::<Vec<u8>>()
+        );
+    }
+}
@@ -0,0 +1,99 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate file_store;
+
+use file_store::*;
+
+fn matches(pattern: &str, path: &str) -> bool {
+    Glob::new(pattern)
+        .unwrap()
+        .matches(&ObjectPath::new(path).unwrap())
+}
+
+#[test]
+fn test_glob_matches() {
+    assert!(matches("a/b.txt", "a/b.txt"));
+    assert!(!matches("a/b.txt", "a/b.txt.bak"));
+    assert!(matches("a/*.txt", "a/b.txt"));
+    assert!(matches("a/*.txt", "a/.txt"));
+    assert!(!matches("a/*.txt", "a/c/b.txt"));
+    assert!(matches("a/?.txt", "a/b.txt"));
+    assert!(!matches("a/?.txt", "a/bc.txt"));
+    assert!(matches("a/[bc].txt", "a/c.txt"));
+    assert!(!matches("a/[bc].txt", "a/d.txt"));
+    assert!(matches("a/[!bc].txt", "a/d.txt"));
+    assert!(matches("a/[a-z]1", "a/q1"));
+    assert!(!matches("a/[a-z]1", "a/Q1"));
+    assert!(matches("a/[]]", "a/]"));
+    assert!(matches("a/[a-]", "a/-"));
+
+    assert!(matches("photos/**/*.jpg", "photos/a.jpg"));
+    assert!(matches("photos/**/*.jpg", "photos/2019/june/a.jpg"));
+    assert!(!matches("photos/**/*.jpg", "videos/a.jpg"));
+    assert!(!matches("photos/**/*.jpg", "photos/a.png"));
+    assert!(matches("**", "any/depth/at/all"));
+    assert!(matches("a/**", "a/b/c"));
+
+    assert!(Glob::new("a/[bc").is_err());
+    assert!(Glob::new("/a").is_err());
+}
+
+#[test]
+fn test_glob_prefix() {
+    let prefix = |pattern: &str| Glob::new(pattern).unwrap().prefix().to_string();
+
+    assert_eq!(prefix("a/b/*.txt"), "a/b/");
+    assert_eq!(prefix("a/b/c*.txt"), "a/b/c");
+    assert_eq!(prefix("photos/**/*.jpg"), "photos/");
+    assert_eq!(prefix("a/b.txt"), "a/b.txt");
+    assert_eq!(prefix("*.txt"), "");
+}
+
+#[cfg(all(feature = "file", not(feature = "wasm")))]
+#[test]
+fn test_list_glob() {
+    use std::fs;
+
+    use futures::stream::TryStreamExt;
+    use tempfile::tempdir;
+    use tokio::runtime::Runtime;
+
+    use file_store::backends::file::FileBackend;
+
+    let temp = tempdir().unwrap();
+    fs::create_dir_all(temp.path().join("photos/2019")).unwrap();
+    fs::create_dir_all(temp.path().join("videos")).unwrap();
+    fs::write(temp.path().join("photos/a.jpg"), "").unwrap();
+    fs::write(temp.path().join("photos/b.png"), "").unwrap();
+    fs::write(temp.path().join("photos/2019/c.jpg"), "").unwrap();
+    fs::write(temp.path().join("videos/d.jpg"), "").unwrap();
+
+    let root = temp.path().to_owned();
+    Runtime::new().unwrap().block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        let objects: Vec<Object> = store
+            .list_glob("photos/**/*.jpg")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let mut paths: Vec<String> = objects.iter().map(|o| o.path().to_string()).collect();
+        paths.sort();
+
+        assert_eq!(paths, vec!["photos/2019/c.jpg", "photos/a.jpg"]);
+    });
+}
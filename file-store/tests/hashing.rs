@@ -0,0 +1,162 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "hashing", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::{iter, once, TryStreamExt};
+use sha2::Sha256;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::hashing::*;
+use file_store::*;
+
+const DATA_HASH: &str = "a3d119683e015b0cbbcd2c24b2e531698bf552bd184c56a86b8e115871600a8d";
+
+#[test]
+fn test_to_hex() {
+    assert_eq!(to_hex(&[0x00, 0x0f, 0xa5, 0xff]), "000fa5ff");
+}
+
+#[test]
+fn test_hash_stream() {
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let chunks = vec![Ok(Bytes::from("Some ")), Ok(Bytes::from("data."))];
+        let (stream, hash) = hash_stream::<Sha256>(DataStream::from_stream(iter(chunks)));
+
+        let data: Vec<Data> = stream.try_collect().await.unwrap();
+        assert_eq!(data.concat(), b"Some data.");
+
+        let hash = hash.await.unwrap();
+        assert_eq!(to_hex(&hash), DATA_HASH);
+
+        // Dropping the stream early means no hash.
+        let (stream, hash) = hash_stream::<Sha256>(DataStream::from_stream(once(ready(Ok(
+            Bytes::from("Some data."),
+        )))));
+        drop(stream);
+        assert_eq!(hash.await.unwrap_err().kind(), StorageErrorKind::Cancelled);
+    });
+}
+
+#[test]
+fn test_verify_file() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let path = ObjectPath::new("file.txt").unwrap();
+
+        store
+            .write_file_from_stream(
+                path.clone(),
+                once(ready(Ok::<_, StorageError>(Bytes::from("Some data.")))),
+            )
+            .await
+            .unwrap();
+
+        let hash = digest_file::<Sha256>(&store, path.clone()).await.unwrap();
+        assert_eq!(to_hex(&hash), DATA_HASH);
+
+        verify_file::<Sha256>(&store, path.clone(), &hash)
+            .await
+            .unwrap();
+
+        let error = verify_file::<Sha256>(&store, path.clone(), &[0; 32])
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), StorageErrorKind::InvalidData);
+    });
+}
+
+#[test]
+fn test_checksum_tree() {
+    let first_dir = tempdir().unwrap();
+    let second_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let first = FileBackend::connect(first_dir.path()).await.unwrap();
+        let second = FileBackend::connect(second_dir.path()).await.unwrap();
+
+        let files = vec![
+            ("a.txt", "Some data."),
+            ("b.txt", "Other data."),
+            ("sub/c.txt", "More data."),
+        ];
+        for (path, data) in files {
+            first
+                .write_file_from_stream(
+                    format!("data/{}", path).as_str(),
+                    once(ready(Ok::<_, StorageError>(Bytes::from(data)))),
+                )
+                .await
+                .unwrap();
+            second
+                .write_file_from_stream(
+                    format!("backup/{}", path).as_str(),
+                    once(ready(Ok::<_, StorageError>(Bytes::from(data)))),
+                )
+                .await
+                .unwrap();
+        }
+
+        let data = ObjectPath::new("data").unwrap();
+        let backup = ObjectPath::new("backup").unwrap();
+
+        let tree = checksum_tree::<Sha256>(&first, data.clone()).await.unwrap();
+        let paths: Vec<&str> = tree.files.iter().map(|(p, _)| p.as_str()).collect();
+        assert_eq!(paths, vec!["a.txt", "b.txt", "sub/c.txt"]);
+        assert_eq!(to_hex(&tree.files[0].1), DATA_HASH);
+
+        let other = checksum_tree::<Sha256>(&second, backup.clone())
+            .await
+            .unwrap();
+        assert_eq!(tree, other);
+
+        // Changing a file changes the root.
+        second
+            .write_file_from_stream(
+                "backup/sub/c.txt",
+                once(ready(Ok::<_, StorageError>(Bytes::from("Changed.")))),
+            )
+            .await
+            .unwrap();
+        let other = checksum_tree::<Sha256>(&second, backup.clone())
+            .await
+            .unwrap();
+        assert_ne!(tree.root, other.root);
+
+        // As does moving one.
+        second.delete_object("backup/sub/c.txt").await.unwrap();
+        second
+            .write_file_from_stream(
+                "backup/c.txt",
+                once(ready(Ok::<_, StorageError>(Bytes::from("More data.")))),
+            )
+            .await
+            .unwrap();
+        let other = checksum_tree::<Sha256>(&second, backup).await.unwrap();
+        assert_ne!(tree.root, other.root);
+        assert_eq!(other.files.len(), 3);
+    });
+}
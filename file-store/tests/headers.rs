@@ -0,0 +1,78 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![cfg(feature = "http")]
+
+extern crate file_store;
+
+use std::sync::{Arc, Mutex};
+
+use futures::executor::block_on;
+use futures::stream::empty;
+use http::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+use http::{Request, Response};
+
+use file_store::http_client::*;
+use file_store::DataStream;
+
+#[derive(Clone, Debug, Default)]
+struct Capture {
+    headers: Arc<Mutex<Vec<HeaderMap>>>,
+}
+
+impl HttpClient for Capture {
+    fn request(&self, request: HttpRequest) -> HttpResponseFuture {
+        self.headers.lock().unwrap().push(request.headers().clone());
+        let response = Response::new(DataStream::from_stream(empty()));
+        HttpResponseFuture::from_value(Ok(response))
+    }
+}
+
+fn send(client: &HeadersClient, user_agent: Option<&str>) {
+    let mut builder = Request::builder();
+    builder.uri("https://example.com/");
+    if let Some(agent) = user_agent {
+        builder.header(USER_AGENT, agent);
+    }
+
+    block_on(client.request(builder.body(RequestBody::Empty).unwrap())).unwrap();
+}
+
+#[test]
+fn test_headers_client() {
+    let capture = Capture::default();
+    let trace = HeaderName::from_static("x-trace-id");
+    let client = HeadersClient::new(capture.clone())
+        .user_agent_suffix("my-app/1.0")
+        .header(trace.clone(), HeaderValue::from_static("abc"));
+
+    send(&client, Some("file-store/0.1"));
+    send(&client, None);
+
+    let headers = capture.headers.lock().unwrap();
+    assert_eq!(headers[0][USER_AGENT], "file-store/0.1 my-app/1.0");
+    assert_eq!(headers[0][&trace], "abc");
+    assert_eq!(headers[1][USER_AGENT], "my-app/1.0");
+    assert_eq!(headers[1][&trace], "abc");
+}
+
+#[test]
+fn test_invalid_user_agent_suffix() {
+    let client = HeadersClient::new(Capture::default()).user_agent_suffix("bad\nagent");
+    let request = Request::builder()
+        .uri("https://example.com/")
+        .body(RequestBody::Empty)
+        .unwrap();
+
+    assert!(block_on(client.request(request)).is_err());
+}
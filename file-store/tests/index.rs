@@ -0,0 +1,181 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "index", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::once;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::index::{MetadataIndex, Query};
+use file_store::*;
+
+async fn write(store: &FileStore, path: &str, data: &'static str, modified: u64) {
+    store
+        .write_file_from_stream(
+            UploadInfo {
+                path: ObjectPath::new(path).unwrap(),
+                modified: Some(UNIX_EPOCH + Duration::from_secs(modified)),
+                ..Default::default()
+            },
+            once(ready(Ok::<_, StorageError>(Bytes::from(data)))),
+        )
+        .await
+        .unwrap();
+}
+
+fn paths(index: &MetadataIndex, query: Query) -> Vec<String> {
+    index
+        .search(&query)
+        .unwrap()
+        .into_iter()
+        .map(|e| e.path.to_string())
+        .collect()
+}
+
+#[test]
+fn test_search() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        write(&store, "docs/a.txt", "Some data.", 1_000_000).await;
+        write(&store, "docs/b.md", "Other data here.", 2_000_000).await;
+        write(&store, "docs/sub/c.txt", "Small", 3_000_000).await;
+        write(&store, "docsx/d.txt", "Not in docs.", 4_000_000).await;
+
+        let database_dir = tempdir().unwrap();
+        let database = database_dir.path().join("index.sqlite");
+        let index = MetadataIndex::open(store.clone(), &database).unwrap();
+        assert_eq!(index.refresh("").await.unwrap(), 7);
+
+        let files = Query::new().object_type(ObjectType::File);
+        assert_eq!(
+            paths(&index, files.clone()),
+            vec!["docs/a.txt", "docs/b.md", "docs/sub/c.txt", "docsx/d.txt"]
+        );
+        assert_eq!(
+            paths(
+                &index,
+                files.clone().prefix(ObjectPath::new("docs").unwrap())
+            ),
+            vec!["docs/a.txt", "docs/b.md", "docs/sub/c.txt"]
+        );
+        assert_eq!(
+            paths(&index, Query::new().name("*.txt")),
+            vec!["docs/a.txt", "docs/sub/c.txt", "docsx/d.txt"]
+        );
+        assert_eq!(
+            paths(&index, files.clone().min_size(6).max_size(12)),
+            vec!["docs/a.txt", "docsx/d.txt"]
+        );
+        assert_eq!(
+            paths(
+                &index,
+                files
+                    .clone()
+                    .modified_after(UNIX_EPOCH + Duration::from_secs(2_000_000))
+                    .modified_before(UNIX_EPOCH + Duration::from_secs(4_000_000))
+            ),
+            vec!["docs/b.md", "docs/sub/c.txt"]
+        );
+        assert_eq!(paths(&index, files.clone().limit(1)), vec!["docs/a.txt"]);
+
+        let entry = index
+            .get(&ObjectPath::new("docs/b.md").unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.size, 16);
+        assert_eq!(
+            entry.modified,
+            Some(UNIX_EPOCH + Duration::from_secs(2_000_000))
+        );
+
+        // Custom metadata.
+        let a = ObjectPath::new("docs/a.txt").unwrap();
+        index.set_metadata(&a, "owner", "dave").unwrap();
+        index.set_metadata(&a, "reviewed", "yes").unwrap();
+        assert!(index
+            .set_metadata(&ObjectPath::new("missing").unwrap(), "owner", "dave")
+            .is_err());
+        assert_eq!(index.metadata(&a).unwrap().len(), 2);
+        assert_eq!(
+            paths(&index, Query::new().metadata("owner", Some("dave"))),
+            vec!["docs/a.txt"]
+        );
+        assert!(paths(&index, Query::new().metadata("owner", Some("bob"))).is_empty());
+        index.remove_metadata(&a, "reviewed").unwrap();
+        assert!(paths(&index, Query::new().metadata("reviewed", None)).is_empty());
+
+        // The index persists and refreshing keeps metadata for objects that
+        // still exist.
+        drop(index);
+        let index = MetadataIndex::open(store.clone(), &database).unwrap();
+        store.delete_object("docs/sub").await.unwrap();
+        assert_eq!(index.refresh("docs").await.unwrap(), 2);
+        assert_eq!(
+            paths(
+                &index,
+                Query::new().prefix(ObjectPath::new("docs").unwrap())
+            ),
+            vec!["docs/a.txt", "docs/b.md"]
+        );
+        assert_eq!(
+            paths(&index, Query::new().metadata("owner", None)),
+            vec!["docs/a.txt"]
+        );
+    });
+}
+
+#[test]
+fn test_watch() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let store = runtime.block_on(FileBackend::connect(temp.path())).unwrap();
+    let index = MetadataIndex::in_memory(store.clone()).unwrap();
+    runtime.spawn(index.watch());
+
+    let wait_for = |query: Query, expected: Vec<&str>| {
+        let start = SystemTime::now();
+        loop {
+            if paths(&index, query.clone()) == expected {
+                return;
+            }
+            assert!(start.elapsed().unwrap() < Duration::from_secs(5));
+            sleep(Duration::from_millis(10));
+        }
+    };
+
+    runtime.block_on(write(&store, "a.txt", "Some data.", 1_000_000));
+    wait_for(Query::new(), vec!["a.txt"]);
+
+    runtime.block_on(store.copy_file("a.txt", "b.txt")).unwrap();
+    wait_for(Query::new(), vec!["a.txt", "b.txt"]);
+
+    runtime.block_on(store.move_file("a.txt", "c.txt")).unwrap();
+    wait_for(Query::new(), vec!["b.txt", "c.txt"]);
+
+    runtime.block_on(store.delete_object("b.txt")).unwrap();
+    wait_for(Query::new(), vec!["c.txt"]);
+}
@@ -0,0 +1,161 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "lifecycle", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::lifecycle::{LifecyclePolicy, Rule};
+use file_store::testing::data_stream;
+use file_store::*;
+
+const DAY: u64 = 86400;
+
+async fn write(store: &FileStore, path: &str, day: u64) {
+    store
+        .write_file_from_stream(
+            UploadInfo {
+                path: ObjectPath::new(path).unwrap(),
+                modified: Some(UNIX_EPOCH + Duration::from_secs(day * DAY)),
+                ..Default::default()
+            },
+            data_stream(&["Some data."]),
+        )
+        .await
+        .unwrap();
+}
+
+fn path(path: &str) -> ObjectPath {
+    ObjectPath::new(path).unwrap()
+}
+
+fn days(days: u64) -> Duration {
+    Duration::from_secs(days * DAY)
+}
+
+fn now() -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(100 * DAY)
+}
+
+async fn populate(store: &FileStore, archive: &FileStore) -> LifecyclePolicy {
+    write(store, "logs/old.log", 10).await;
+    write(store, "logs/new.log", 95).await;
+    write(store, "reports/old.pdf", 10).await;
+    write(store, "reports/older.pdf", 5).await;
+    write(store, "media/old.png", 10).await;
+    write(store, "keep.txt", 1).await;
+
+    LifecyclePolicy::new(store.clone())
+        .rule(Rule::expire(path("logs"), days(30)))
+        .rule(Rule::transition(path("reports"), days(60), path("archive")))
+        .rule(Rule::transition(path("media"), days(60), path("media")).to_store(archive.clone()))
+        // Shouldn't touch files that earlier rules moved into archive.
+        .rule(Rule::expire(path("archive"), days(1)))
+}
+
+#[test]
+fn test_dry_run() {
+    let temp = tempdir().unwrap();
+    let archive_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let archive = FileBackend::connect(archive_dir.path()).await.unwrap();
+        let policy = populate(&store, &archive).await;
+
+        let report = policy.dry_run(true).run_at(now()).await.unwrap();
+        assert_eq!(report.expired, vec![path("logs/old.log")]);
+        assert_eq!(report.transitioned.len(), 3);
+        assert!(store.get_object("logs/old.log").await.is_ok());
+        assert!(store.get_object("reports/old.pdf").await.is_ok());
+        assert!(archive.get_object("media/media/old.png").await.is_err());
+    });
+}
+
+#[test]
+fn test_lifecycle() {
+    let temp = tempdir().unwrap();
+    let archive_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let archive = FileBackend::connect(archive_dir.path()).await.unwrap();
+        let policy = populate(&store, &archive).await;
+
+        let report = policy.run_at(now()).await.unwrap();
+        assert!(report.failed.is_empty());
+        assert_eq!(report.expired, vec![path("logs/old.log")]);
+
+        let mut transitioned = report.transitioned.clone();
+        transitioned.sort();
+        assert_eq!(
+            transitioned,
+            vec![
+                (path("media/old.png"), path("media/media/old.png")),
+                (path("reports/old.pdf"), path("archive/reports/old.pdf")),
+                (path("reports/older.pdf"), path("archive/reports/older.pdf")),
+            ]
+        );
+
+        assert!(store.get_object("logs/old.log").await.is_err());
+        assert!(store.get_object("logs/new.log").await.is_ok());
+        assert!(store.get_object("reports/old.pdf").await.is_err());
+        assert!(store.get_object("archive/reports/old.pdf").await.is_ok());
+        assert!(store.get_object("media/old.png").await.is_err());
+        assert_eq!(
+            archive
+                .get_object("media/media/old.png")
+                .await
+                .unwrap()
+                .modified(),
+            Some(UNIX_EPOCH + Duration::from_secs(10 * DAY))
+        );
+        assert!(store.get_object("keep.txt").await.is_ok());
+    });
+}
+
+#[test]
+fn test_later_run() {
+    let temp = tempdir().unwrap();
+    let archive_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let archive = FileBackend::connect(archive_dir.path()).await.unwrap();
+        let policy = populate(&store, &archive).await;
+
+        policy.run_at(now()).await.unwrap();
+
+        // A later run expires the archived files.
+        let mut report = policy.run_at(now()).await.unwrap();
+        report.expired.sort();
+        assert_eq!(
+            report.expired,
+            vec![
+                path("archive/reports/old.pdf"),
+                path("archive/reports/older.pdf")
+            ]
+        );
+        assert!(report.transitioned.is_empty());
+    });
+}
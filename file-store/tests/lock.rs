@@ -0,0 +1,208 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "lock", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+use std::time::Duration;
+
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+use tokio::timer::delay_for;
+
+use file_store::backends::file::FileBackend;
+use file_store::*;
+
+#[test]
+fn test_lock() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    fs::create_dir_all(temp.path().join("dir")).unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+        let ttl = Duration::from_secs(60);
+
+        let lease = store.lock("dir/tree", ttl).await.unwrap();
+        assert_eq!(lease.path().to_string(), "dir/tree");
+        assert_eq!(lease.lock_path().to_string(), "dir/.lock/tree");
+        assert!(lease.is_held());
+        assert!(root.join("dir").join(".lock").join("tree").is_file());
+
+        let error = store.lock("dir/tree", ttl).await.unwrap_err();
+        match error.kind() {
+            StorageErrorKind::AlreadyExists(p) => assert_eq!(p.to_string(), "dir/.lock/tree"),
+            k => panic!("Unexpected error kind {:?}", k),
+        }
+
+        // Other paths can still be locked.
+        let other = store.lock("dir/other", ttl).await.unwrap();
+        assert_ne!(other.holder(), lease.holder());
+
+        lease.release().await.unwrap();
+        assert!(!root.join("dir").join(".lock").join("tree").exists());
+
+        let lease = store.lock("dir/tree", ttl).await.unwrap();
+        drop(lease);
+        drop(other);
+        delay_for(Duration::from_millis(200)).await;
+        assert!(!root.join("dir").join(".lock").join("tree").exists());
+        assert!(!root.join("dir").join(".lock").join("other").exists());
+
+        let error = store.lock("", ttl).await.unwrap_err();
+        match error.kind() {
+            StorageErrorKind::InvalidPath(_) => (),
+            k => panic!("Unexpected error kind {:?}", k),
+        }
+    });
+}
+
+#[test]
+fn test_expired_lock() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    fs::create_dir_all(temp.path().join(".lock")).unwrap();
+    fs::write(
+        temp.path().join(".lock").join("file"),
+        r#"{"holder":"crashed","expires":1000}"#,
+    )
+    .unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+        let ttl = Duration::from_secs(60);
+
+        // Expired leases are not taken over.
+        let error = store.lock("file", ttl).await.unwrap_err();
+        match error.kind() {
+            StorageErrorKind::AlreadyExists(p) => assert_eq!(p.to_string(), ".lock/file"),
+            k => panic!("Unexpected error kind {:?}", k),
+        }
+
+        store.break_lock("file").await.unwrap();
+        assert!(!root.join(".lock").join("file").exists());
+
+        let lease = store.lock("file", ttl).await.unwrap();
+        assert!(lease.is_held());
+
+        let contents = fs::read_to_string(root.join(".lock").join("file")).unwrap();
+        assert!(contents.contains(lease.holder()));
+
+        lease.release().await.unwrap();
+    });
+}
+
+#[test]
+fn test_lock_directory_reserved() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+        let lease = store.lock("file", Duration::from_secs(60)).await.unwrap();
+
+        for path in &[".lock/file", "dir/.lock/file"] {
+            let error = store.get_object(*path).await.unwrap_err();
+            match error.kind() {
+                StorageErrorKind::InvalidPath(_) => (),
+                k => panic!("Unexpected error kind {:?}", k),
+            }
+
+            let error = store
+                .lock(*path, Duration::from_secs(60))
+                .await
+                .unwrap_err();
+            match error.kind() {
+                StorageErrorKind::InvalidPath(_) => (),
+                k => panic!("Unexpected error kind {:?}", k),
+            }
+        }
+
+        // Custom policies reserve it too.
+        let store = store.with_path_policy(PathPolicy::new());
+        let error = store.get_object(".lock/file").await.unwrap_err();
+        match error.kind() {
+            StorageErrorKind::InvalidPath(_) => (),
+            k => panic!("Unexpected error kind {:?}", k),
+        }
+
+        lease.release().await.unwrap();
+    });
+}
+
+#[test]
+fn test_renewal() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        let lease = store
+            .lock("file", Duration::from_millis(400))
+            .await
+            .unwrap();
+        let first_expiry = lease.expires();
+
+        delay_for(Duration::from_millis(1000)).await;
+        assert!(lease.is_held());
+        assert!(lease.expires() > first_expiry);
+
+        let error = store
+            .lock("file", Duration::from_millis(400))
+            .await
+            .unwrap_err();
+        match error.kind() {
+            StorageErrorKind::AlreadyExists(_) => (),
+            k => panic!("Unexpected error kind {:?}", k),
+        }
+
+        lease.release().await.unwrap();
+    });
+}
+
+#[test]
+fn test_renewal_after_takeover() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+        let ttl = Duration::from_millis(400);
+
+        let lease = store.lock("file", ttl).await.unwrap();
+        store.break_lock("file").await.unwrap();
+        let other = store.lock("file", ttl).await.unwrap();
+
+        // The first lease notices it lost the lock instead of taking it back.
+        delay_for(Duration::from_millis(1000)).await;
+        assert!(!lease.is_held());
+        assert!(other.is_held());
+        let contents = fs::read_to_string(root.join(".lock").join("file")).unwrap();
+        assert!(contents.contains(other.holder()));
+
+        lease.release().await.unwrap();
+        assert!(root.join(".lock").join("file").exists());
+        other.release().await.unwrap();
+    });
+}
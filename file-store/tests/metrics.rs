@@ -0,0 +1,139 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::metrics::*;
+
+type Key = (&'static str, Vec<(String, String)>);
+
+#[derive(Debug, Default)]
+struct Recorder {
+    counters: Mutex<HashMap<Key, u64>>,
+    histograms: Mutex<HashMap<Key, usize>>,
+}
+
+fn key(name: &'static str, labels: &[(&'static str, &str)]) -> Key {
+    let mut labels: Vec<(String, String)> = labels
+        .iter()
+        .map(|(name, value)| ((*name).to_owned(), (*value).to_owned()))
+        .collect();
+    labels.sort();
+    (name, labels)
+}
+
+impl Recorder {
+    fn counter(&self, name: &'static str, labels: &[(&'static str, &str)]) -> u64 {
+        let counters = self.counters.lock().unwrap();
+        counters
+            .get(&key(name, labels))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn histogram(&self, name: &'static str, labels: &[(&'static str, &str)]) -> usize {
+        let histograms = self.histograms.lock().unwrap();
+        histograms
+            .get(&key(name, labels))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+impl MetricsSink for Recorder {
+    fn increment_counter(&self, name: &'static str, value: u64, labels: &[(&'static str, &str)]) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(key(name, labels))
+            .or_default() += value;
+    }
+
+    fn record_histogram(&self, name: &'static str, _value: f64, labels: &[(&'static str, &str)]) {
+        *self
+            .histograms
+            .lock()
+            .unwrap()
+            .entry(key(name, labels))
+            .or_default() += 1;
+    }
+}
+
+#[test]
+fn test_metrics_sink() {
+    let temp = tempdir().unwrap();
+    let root = temp.path().to_owned();
+
+    Runtime::new().unwrap().block_on(async move {
+        let fs = FileBackend::connect(&root).await.unwrap();
+        let recorder = Arc::new(Recorder::default());
+        fs.clone().set_metrics_sink(recorder.clone());
+
+        fs.write_bytes("file", "Some data.").await.unwrap();
+        let data = fs.read_to_bytes("file").await.unwrap();
+        assert_eq!(data.len(), 10);
+        assert!(fs.get_object("missing").await.is_err());
+
+        let file = [("backend", "file")];
+        assert_eq!(recorder.counter(BYTES_UPLOADED, &file), 10);
+        assert_eq!(recorder.counter(BYTES_DOWNLOADED, &file), 10);
+
+        let write = [("backend", "file"), ("operation", "write_file")];
+        assert_eq!(
+            recorder.counter(OPERATIONS, &[write[0], write[1], ("result", "ok")]),
+            1
+        );
+        assert_eq!(recorder.histogram(OPERATION_DURATION, &write), 1);
+        assert_eq!(
+            recorder.counter(
+                OPERATIONS,
+                &[
+                    ("backend", "file"),
+                    ("operation", "get_object"),
+                    ("result", "error")
+                ]
+            ),
+            1
+        );
+    });
+}
+
+#[test]
+fn test_remove_metrics_sink() {
+    let temp = tempdir().unwrap();
+    let root = temp.path().to_owned();
+
+    Runtime::new().unwrap().block_on(async move {
+        let fs = FileBackend::connect(&root).await.unwrap();
+        let recorder = Arc::new(Recorder::default());
+        fs.clone().set_metrics_sink(recorder.clone());
+
+        let file = [("backend", "file")];
+        fs.write_bytes("file", "Some data.").await.unwrap();
+        assert_eq!(recorder.counter(BYTES_UPLOADED, &file), 10);
+
+        fs.remove_metrics_sink();
+        fs.write_bytes("file", "More data.").await.unwrap();
+        assert_eq!(recorder.counter(BYTES_UPLOADED, &file), 10);
+    });
+}
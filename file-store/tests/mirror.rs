@@ -0,0 +1,154 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+
+use futures::stream::StreamExt;
+use tempfile::{tempdir, TempDir};
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::mirror::MirrorBackend;
+use file_store::testing::data_stream;
+use file_store::*;
+
+async fn read(store: &MirrorBackend, path: &str) -> String {
+    let mut stream = store
+        .get_file_stream(ObjectPath::new(path).unwrap())
+        .await
+        .unwrap();
+    let mut data = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        data.extend_from_slice(&chunk.unwrap());
+    }
+    String::from_utf8(data).unwrap()
+}
+
+/// Builds a mirror over the directories holding a single file.
+async fn connect(temps: &[TempDir]) -> MirrorBackend {
+    let mut members = Vec::new();
+    for temp in temps {
+        members.push(FileBackend::connect(temp.path()).await.unwrap());
+    }
+    let mirror = MirrorBackend::new(members);
+
+    mirror
+        .write_file_from_stream(
+            ObjectPath::new("dir/a.txt").unwrap().into(),
+            data_stream(&["Some data."]),
+        )
+        .await
+        .unwrap();
+
+    mirror
+}
+
+#[test]
+fn test_mirror_writes() {
+    let temps = vec![tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap()];
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let mirror = connect(&temps).await;
+        for temp in &temps {
+            let data = fs::read(temp.path().join("dir").join("a.txt")).unwrap();
+            assert_eq!(data, b"Some data.");
+        }
+
+        mirror
+            .delete_object(ObjectPath::new("dir/a.txt").unwrap())
+            .await
+            .unwrap();
+        for temp in &temps {
+            assert!(!temp.path().join("dir").join("a.txt").exists());
+        }
+    });
+}
+
+#[test]
+fn test_mirror_reads() {
+    let temps = vec![tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap()];
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let mirror = connect(&temps).await;
+
+        // Reads fall back to other members.
+        fs::remove_file(temps[0].path().join("dir").join("a.txt")).unwrap();
+        assert_eq!(read(&mirror, "dir/a.txt").await, "Some data.");
+        assert_eq!(
+            mirror
+                .get_object(ObjectPath::new("dir/a.txt").unwrap())
+                .await
+                .unwrap()
+                .len(),
+            10
+        );
+    });
+}
+
+#[test]
+fn test_mirror_repair() {
+    let temps = vec![tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap()];
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let mirror = connect(&temps).await;
+        fs::remove_file(temps[0].path().join("dir").join("a.txt")).unwrap();
+        fs::write(temps[2].path().join("b.txt"), "Other data.").unwrap();
+
+        // Repairing copies the files back.
+        let summary = mirror.repair("").await.unwrap();
+        assert!(summary.is_success());
+        assert_eq!(summary.copied.len(), 3);
+        assert_eq!(summary.bytes, 32);
+        for temp in &temps {
+            assert!(temp.path().join("dir").join("a.txt").is_file());
+            assert!(temp.path().join("b.txt").is_file());
+        }
+        assert!(mirror.repair("").await.unwrap().copied.is_empty());
+    });
+}
+
+#[test]
+fn test_mirror_quorum() {
+    let temps = vec![tempdir().unwrap(), tempdir().unwrap(), tempdir().unwrap()];
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let mirror = connect(&temps).await;
+
+        // A change fails if any member fails, unless a quorum is set.
+        fs::create_dir_all(temps[1].path().join("blocked.txt").join("inner")).unwrap();
+        let blocked = ObjectPath::new("blocked.txt").unwrap();
+        assert!(mirror
+            .write_file_from_stream(blocked.clone().into(), data_stream(&["Data."]))
+            .await
+            .is_err());
+
+        let mirror = mirror.quorum(2);
+        mirror
+            .write_file_from_stream(blocked.into(), data_stream(&["Data."]))
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read(temps[2].path().join("blocked.txt")).unwrap(),
+            b"Data."
+        );
+    });
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![cfg(feature = "b2")]
+#![cfg(all(feature = "b2", feature = "hyper-client"))]
 
 use std::cmp::Ord;
 use std::cmp::Ordering;
@@ -25,27 +25,29 @@ use std::io::{BufWriter, Write};
 use std::net::SocketAddr;
 use std::net::TcpListener;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::{Duration, UNIX_EPOCH};
+use std::sync::{Arc, Mutex as SyncMutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use base64::encode;
 use filetime::{set_file_mtime, FileTime};
 use futures::channel::oneshot::{channel, Sender};
 use futures::future::FutureExt;
 use futures::lock::Mutex;
-use futures::stream::{iter, TryStreamExt};
+use futures::stream::{iter, StreamExt, TryStreamExt};
 use http::header;
-use http::header::{AsHeaderName, HeaderMap};
+use http::header::{AsHeaderName, HeaderMap, HeaderValue};
 use http::request::Parts;
 use http::StatusCode;
 use hyper::server::Server;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Chunk, Request, Response};
 use serde_json::{from_slice, to_string_pretty};
-use sha1::Sha1;
+use sha1::{Digest, Sha1};
 use tokio::spawn;
+use tokio::timer::delay_for;
 use uuid::Uuid;
 
+use file_store::hashing::to_hex;
 use storage_types::b2::v2::requests::*;
 use storage_types::b2::v2::responses::*;
 use storage_types::b2::v2::{
@@ -53,7 +55,7 @@ use storage_types::b2::v2::{
     B2_HEADER_FILE_INFO_PREFIX, B2_HEADER_FILE_NAME, B2_HEADER_PART_NUMBER, LAST_MODIFIED_KEY,
 };
 
-use crate::runner::TestResult;
+use file_store::testing::TestResult;
 
 const TEST_KEY_ID: &str = "foo";
 const TEST_KEY: &str = "bar";
@@ -239,6 +241,67 @@ where
     }
 }
 
+fn parse_range(header: &HeaderValue) -> Result<(u64, Option<u64>), B2Error> {
+    let invalid = || B2Error::invalid_parameters("Range header was invalid.");
+
+    let range = header.to_str().map_err(|_| invalid())?;
+    if !range.starts_with("bytes=") {
+        return Err(invalid());
+    }
+
+    let mut parts = range[6..].splitn(2, '-');
+    let start = match parts.next() {
+        Some(s) => s.parse::<u64>().map_err(|_| invalid())?,
+        None => return Err(invalid()),
+    };
+    let end = match parts.next() {
+        Some("") => None,
+        Some(s) => Some(s.parse::<u64>().map_err(|_| invalid())?),
+        None => return Err(invalid()),
+    };
+
+    match end {
+        Some(e) if e < start => Err(invalid()),
+        _ => Ok((start, end)),
+    }
+}
+
+/// Reads the data to copy from a source file id, applying a range if given.
+fn read_copy_source(file_id: &str, range: &Option<String>) -> Result<(PathBuf, Vec<u8>), B2Error> {
+    if !file_id.starts_with(FILE_ID_PREFIX) {
+        return Err(B2Error::invalid_parameters(format!(
+            "Invalid file id: {}",
+            file_id
+        )));
+    }
+
+    let path = PathBuf::from(&file_id[FILE_ID_PREFIX.len()..]);
+    match metadata(&path) {
+        Ok(ref meta) if meta.is_file() => (),
+        _ => return Err(B2Error::not_found(&path)),
+    }
+
+    let data = read(&path).into_path_err(&path)?;
+    let range = match range {
+        Some(range) => match HeaderValue::from_str(range) {
+            Ok(header) => parse_range(&header)?,
+            Err(_) => return Err(B2Error::invalid_parameters("Range was invalid.")),
+        },
+        None => return Ok((path, data)),
+    };
+
+    let (start, end) = range;
+    let end = match end {
+        Some(end) if end < data.len() as u64 => end as usize + 1,
+        _ => data.len(),
+    };
+    if start as usize > end {
+        return Err(B2Error::invalid_parameters("Range was beyond the file."));
+    }
+
+    Ok((path, data[start as usize..end].to_vec()))
+}
+
 macro_rules! api_response {
     ($body:expr) => {
         Ok(Response::builder()
@@ -424,26 +487,186 @@ impl Iterator for FileLister {
 struct LargeUpload {
     file_name: String,
     bucket_id: String,
+    started: Int,
     auth: HashSet<String>,
     parts: HashMap<usize, (Vec<Chunk>, String)>,
 }
 
 impl LargeUpload {
     fn new(file_name: &str, bucket_id: &str) -> LargeUpload {
+        let started = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_millis() as Int,
+            Err(_) => 0,
+        };
+
         LargeUpload {
             file_name: file_name.to_owned(),
             bucket_id: bucket_id.to_owned(),
+            started,
             auth: Default::default(),
             parts: Default::default(),
         }
     }
 }
 
+/// A failure to inject into a request.
+#[derive(Clone, Debug)]
+#[allow(dead_code)]
+pub enum Fault {
+    /// Responds with the given status and B2 error code.
+    Error(StatusCode, &'static str),
+    /// Responds with the given status and B2 error code along with a
+    /// `Retry-After` header asking for a delay in seconds.
+    RetryAfter(StatusCode, &'static str, u64),
+    /// Reads the whole request body and then responds with the given status
+    /// and B2 error code.
+    ErrorAfterBody(StatusCode, &'static str),
+    /// Closes the connection without responding.
+    Disconnect,
+    /// Waits before handling the request.
+    Latency(Duration),
+    /// Waits before sending each chunk of the response body.
+    SlowBody(Duration),
+    /// Expires the auth token used for the request.
+    ExpireAuth,
+}
+
+/// Injects faults into requests made to the mock server. Each fault is used
+/// for one request whose path contains the pattern, for API calls this is the
+/// method name, e.g. "b2_list_file_names". Uploads and downloads use
+/// "/upload/file/", "/upload/part/", "/download/file/" and
+/// "/download/b2api/v2/b2_download_file_by_id".
+#[derive(Clone, Default)]
+pub struct FaultInjector {
+    faults: Arc<SyncMutex<Vec<(String, Fault)>>>,
+}
+
+#[allow(dead_code)]
+impl FaultInjector {
+    pub fn new() -> FaultInjector {
+        Default::default()
+    }
+
+    /// Injects a fault into the next request matching the pattern.
+    pub fn inject(&self, pattern: &str, fault: Fault) {
+        self.inject_times(pattern, fault, 1);
+    }
+
+    /// Injects a fault into the next `count` requests matching the pattern.
+    pub fn inject_times(&self, pattern: &str, fault: Fault, count: usize) {
+        let mut faults = self.faults.lock().unwrap();
+        for _ in 0..count {
+            faults.push((pattern.to_owned(), fault.clone()));
+        }
+    }
+
+    /// The number of faults that have not been used yet.
+    pub fn pending(&self) -> usize {
+        self.faults.lock().unwrap().len()
+    }
+
+    /// Removes any unused faults.
+    pub fn clear(&self) {
+        self.faults.lock().unwrap().clear();
+    }
+
+    fn take(&self, path: &str) -> Option<Fault> {
+        let mut faults = self.faults.lock().unwrap();
+        let index = faults
+            .iter()
+            .position(|(pattern, _)| path.contains(pattern))?;
+        Some(faults.remove(index).1)
+    }
+}
+
+/// What a [`RequestCounter`] has seen of the requests matching a pattern.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RequestCount {
+    /// The number of requests made.
+    pub total: usize,
+    /// The number of requests being handled right now.
+    pub active: usize,
+    /// The most requests that were being handled at once.
+    pub max_active: usize,
+}
+
+/// Counts the requests made to the mock server whose path contains one of the
+/// watched patterns, which are matched in the same way as faults.
+#[derive(Clone, Default)]
+pub struct RequestCounter {
+    counts: Arc<SyncMutex<HashMap<String, RequestCount>>>,
+}
+
+#[allow(dead_code)]
+impl RequestCounter {
+    pub fn new() -> RequestCounter {
+        Default::default()
+    }
+
+    /// Starts counting the requests matching the pattern.
+    pub fn watch(&self, pattern: &str) {
+        self.counts
+            .lock()
+            .unwrap()
+            .insert(pattern.to_owned(), Default::default());
+    }
+
+    /// Gets the counts for a watched pattern.
+    pub fn get(&self, pattern: &str) -> RequestCount {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(pattern)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn start(&self, path: &str) -> ActiveRequest {
+        let mut counts = self.counts.lock().unwrap();
+        let mut patterns = Vec::new();
+        for (pattern, count) in counts.iter_mut() {
+            if path.contains(pattern.as_str()) {
+                count.total += 1;
+                count.active += 1;
+                count.max_active = count.max_active.max(count.active);
+                patterns.push(pattern.clone());
+            }
+        }
+
+        ActiveRequest {
+            counter: self.clone(),
+            patterns,
+        }
+    }
+}
+
+/// Marks a request as no longer active when dropped.
+struct ActiveRequest {
+    counter: RequestCounter,
+    patterns: Vec<String>,
+}
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        let mut counts = self.counter.counts.lock().unwrap();
+        for pattern in self.patterns.iter() {
+            if let Some(count) = counts.get_mut(pattern) {
+                count.active -= 1;
+            }
+        }
+    }
+}
+
 #[derive(Default)]
 struct B2ServerState {
     authorizations: HashMap<String, usize>,
     upload_authorizations: HashMap<String, String>,
+    // Maps tokens to the bucket id, file name prefix and expiry they allow.
+    download_authorizations: HashMap<String, (String, String, SystemTime)>,
     large_uploads: HashMap<String, LargeUpload>,
+    file_info: HashMap<String, UserFileInfo>,
+    content_types: HashMap<String, String>,
+    content_sha1s: HashMap<String, String>,
 }
 
 impl B2ServerState {
@@ -458,6 +681,8 @@ struct B2Server {
     root: PathBuf,
     auth_timeout: usize,
     state: Arc<Mutex<B2ServerState>>,
+    faults: FaultInjector,
+    requests: RequestCounter,
 }
 
 impl B2Server {
@@ -589,6 +814,29 @@ impl B2Server {
         api_response!(ListBucketsResponse { buckets })
     }
 
+    /// Adds the file info, content type and hash recorded at upload to listed
+    /// files.
+    async fn merge_file_info(&self, dir: &Path, files: &mut Vec<FileInfo>) {
+        let state = self.state.lock().await;
+        for file in files.iter_mut() {
+            let key = dir.join(&file.file_name).display().to_string();
+            if let Some(content_type) = state.content_types.get(&key) {
+                file.content_type = Some(content_type.clone());
+            }
+            if let Some(sha1) = state.content_sha1s.get(&key) {
+                file.content_sha1 = Some(sha1.clone());
+            }
+
+            if let Some(info) = state.file_info.get(&key) {
+                for (name, value) in info {
+                    file.file_info
+                        .entry(name.clone())
+                        .or_insert_with(|| value.clone());
+                }
+            }
+        }
+    }
+
     async fn b2_list_file_names(self, _head: Parts, body: ListFileNamesRequest) -> B2Result {
         if !body.bucket_id.starts_with(BUCKET_ID_PREFIX) {
             return Err(B2Error::invalid_bucket_id(&body.bucket_id));
@@ -625,6 +873,8 @@ impl B2Server {
             }
         }
 
+        self.merge_file_info(&dir, &mut response.files).await;
+
         api_response!(response)
     }
 
@@ -654,21 +904,321 @@ impl B2Server {
             next_file_id: None,
         };
 
+        // Pages are kept small to exercise paging, smaller pages can be asked for.
+        let count = body.max_file_count.map_or(DEFAULT_FILE_COUNT, |count| {
+            (count as usize).max(1).min(DEFAULT_FILE_COUNT)
+        });
+
         for result in lister {
             let info = result?;
 
-            if response.files.len() < DEFAULT_FILE_COUNT {
+            if response.files.len() < count {
                 response.files.push(info);
-            } else if response.files.len() == DEFAULT_FILE_COUNT {
+            } else if response.files.len() == count {
                 response.next_file_name = Some(info.file_name);
                 response.next_file_id = info.file_id;
                 break;
             }
         }
 
+        self.merge_file_info(&dir, &mut response.files).await;
+
         api_response!(response)
     }
 
+    async fn b2_get_download_authorization(
+        self,
+        _head: Parts,
+        body: GetDownloadAuthorizationRequest,
+    ) -> B2Result {
+        if !body.bucket_id.starts_with(BUCKET_ID_PREFIX) {
+            return Err(B2Error::invalid_bucket_id(&body.bucket_id));
+        }
+
+        if body.valid_duration_in_seconds < 1 || body.valid_duration_in_seconds > 604_800 {
+            return Err(B2Error::invalid_parameters("Invalid duration."));
+        }
+
+        let mut state = self.state.lock().await;
+        let token = format!("download_{}", state.download_authorizations.len());
+        state.download_authorizations.insert(
+            token.clone(),
+            (
+                body.bucket_id.clone(),
+                body.file_name_prefix.clone(),
+                SystemTime::now() + Duration::from_secs(body.valid_duration_in_seconds),
+            ),
+        );
+
+        api_response!(GetDownloadAuthorizationResponse {
+            bucket_id: body.bucket_id,
+            file_name_prefix: body.file_name_prefix,
+            authorization_token: token,
+        })
+    }
+
+    /// Serves a download authorized by a token in the query string rather than
+    /// the headers.
+    async fn b2_download_authorized(self, path: &str, query: &str) -> B2Result {
+        let unauthorized = || {
+            B2Error::new(
+                StatusCode::UNAUTHORIZED,
+                "unauthorized",
+                "The download authorization was invalid.",
+            )
+        };
+
+        let token = query
+            .split('&')
+            .filter_map(|pair| {
+                if pair.starts_with("Authorization=") {
+                    Some(&pair[14..])
+                } else {
+                    None
+                }
+            })
+            .next()
+            .ok_or_else(unauthorized)?;
+        let token = percent_decode(token).map_err(|_| unauthorized())?;
+
+        let target = match percent_decode(path) {
+            Ok(s) => s,
+            Err(_) => return Err(B2Error::invalid_parameters("File path was invalid utf-8.")),
+        };
+        let mut parts = target.splitn(2, '/');
+        let bucket = parts.next().unwrap_or("");
+        let file_name = parts.next().unwrap_or("");
+
+        {
+            let state = self.state.lock().await;
+            match state.download_authorizations.get(&token) {
+                Some((bucket_id, prefix, expires))
+                    if bucket_id == &format!("{}{}", BUCKET_ID_PREFIX, bucket)
+                        && file_name.starts_with(prefix.as_str())
+                        && SystemTime::now() < *expires => {}
+                _ => return Err(unauthorized()),
+            }
+        }
+
+        let mut file = self.root.clone();
+        file.push(target);
+        self.serve_file(file, None).await
+    }
+
+    async fn b2_get_file_info(self, _head: Parts, body: GetFileInfoRequest) -> B2Result {
+        if !body.file_id.starts_with(FILE_ID_PREFIX) {
+            return Err(B2Error::invalid_parameters(format!(
+                "Invalid file id: {}",
+                body.file_id
+            )));
+        }
+
+        let path = PathBuf::from(&body.file_id[FILE_ID_PREFIX.len()..]);
+        let meta = match metadata(&path) {
+            Ok(meta) if meta.is_file() => meta,
+            _ => return Err(B2Error::not_found(&path)),
+        };
+
+        let relative = match path.strip_prefix(&self.root) {
+            Ok(relative) => relative.to_owned(),
+            Err(_) => return Err(B2Error::not_found(&path)),
+        };
+        let mut parts = relative
+            .iter()
+            .map(|part| part.to_string_lossy().into_owned());
+        let bucket = match parts.next() {
+            Some(bucket) => bucket,
+            None => return Err(B2Error::not_found(&path)),
+        };
+        let file_name = parts.collect::<Vec<String>>().join("/");
+
+        let mut info = UserFileInfo::new();
+        if let Ok(time) = meta.modified() {
+            if let Ok(dur) = time.duration_since(UNIX_EPOCH) {
+                info.insert(LAST_MODIFIED_KEY.to_owned(), dur.as_millis().to_string());
+            }
+        }
+
+        let mut files = vec![FileInfo {
+            account_id: TEST_ACCOUNT_ID.to_owned(),
+            action: FileAction::Upload,
+            bucket_id: format!("{}{}", BUCKET_ID_PREFIX, bucket),
+            content_length: meta.len(),
+            content_sha1: None,
+            content_type: None,
+            file_id: Some(body.file_id),
+            file_info: info,
+            file_name,
+            upload_timestamp: 0,
+        }];
+
+        self.merge_file_info(&self.root.join(&bucket), &mut files)
+            .await;
+        api_response!(files.remove(0))
+    }
+
+    async fn b2_copy_file(self, _head: Parts, body: CopyFileRequest) -> B2Result {
+        let (source, data) = read_copy_source(&body.source_file_id, &body.range)?;
+
+        let bucket_id = match body.destination_bucket_id {
+            Some(id) => id,
+            None => {
+                return Err(B2Error::invalid_parameters(
+                    "The mock requires a destination bucket.",
+                ))
+            }
+        };
+        if !bucket_id.starts_with(BUCKET_ID_PREFIX) {
+            return Err(B2Error::invalid_bucket_id(&bucket_id));
+        }
+
+        let mut path = self.root.clone();
+        path.push(&bucket_id[BUCKET_ID_PREFIX.len()..]);
+        path.push(&body.file_name);
+
+        let mut state = self.state.lock().await;
+        let source_key = source.display().to_string();
+        let (content_type, user_info) = match body.metadata_directive.as_ref().map(String::as_str) {
+            Some("REPLACE") => {
+                let content_type = match body.content_type {
+                    Some(ref content_type) if content_type != "b2/x-auto" => content_type.clone(),
+                    _ => String::from("application/octet-stream"),
+                };
+                (
+                    content_type,
+                    body.file_info.unwrap_or_else(UserFileInfo::new),
+                )
+            }
+            _ => (
+                state
+                    .content_types
+                    .get(&source_key)
+                    .cloned()
+                    .unwrap_or_else(|| String::from("application/octet-stream")),
+                state
+                    .file_info
+                    .get(&source_key)
+                    .cloned()
+                    .unwrap_or_else(UserFileInfo::new),
+            ),
+        };
+
+        let mut writer = File::create(&path)?;
+        writer.write_all(&data)?;
+        drop(writer);
+
+        if let Some(time) = user_info
+            .get(LAST_MODIFIED_KEY)
+            .and_then(|t| t.parse::<u64>().ok())
+        {
+            let time = UNIX_EPOCH + Duration::from_millis(time);
+            if let Err(e) = set_file_mtime(&path, FileTime::from_system_time(time)) {
+                return Err(B2Error::server_error(format!(
+                    "Failed to set file modification time: {}.",
+                    e
+                )));
+            }
+        }
+
+        let mut hasher = Sha1::new();
+        hasher.input(&data);
+        let sha1 = to_hex(&hasher.result());
+
+        let key = path.display().to_string();
+        state.file_info.insert(key.clone(), user_info.clone());
+        state
+            .content_types
+            .insert(key.clone(), content_type.clone());
+        state.content_sha1s.insert(key, sha1.clone());
+
+        api_response!(CopyFileResponse {
+            account_id: TEST_ACCOUNT_ID.to_owned(),
+            action: FileAction::Upload,
+            bucket_id,
+            content_length: data.len() as Int,
+            content_sha1: Some(sha1),
+            content_type: Some(content_type),
+            file_id: Some(format!("{}{}", FILE_ID_PREFIX, path.display())),
+            file_info: user_info,
+            file_name: body.file_name,
+            upload_timestamp: 0,
+        })
+    }
+
+    async fn b2_copy_part(self, _head: Parts, body: CopyPartRequest) -> B2Result {
+        if body.part_number < 1 {
+            return Err(B2Error::invalid_parameters("Invalid part number."));
+        }
+
+        let (_, data) = read_copy_source(&body.source_file_id, &body.range)?;
+
+        let mut hasher = Sha1::new();
+        hasher.input(&data);
+        let sha1 = to_hex(&hasher.result());
+        let length = data.len() as Int;
+
+        let mut state = self.state.lock().await;
+        let upload = match state.large_uploads.get_mut(&body.large_file_id) {
+            Some(u) => u,
+            None => return Err(B2Error::invalid_parameters("Unknown file id.")),
+        };
+
+        upload
+            .parts
+            .insert(body.part_number - 1, (vec![data.into()], sha1.clone()));
+
+        api_response!(CopyPartResponse {
+            file_id: body.large_file_id,
+            part_number: body.part_number,
+            content_length: length,
+            content_sha1: sha1,
+            upload_timestamp: 0,
+        })
+    }
+
+    async fn b2_hide_file(self, _head: Parts, body: HideFileRequest) -> B2Result {
+        if !body.bucket_id.starts_with(BUCKET_ID_PREFIX) {
+            return Err(B2Error::invalid_bucket_id(&body.bucket_id));
+        }
+
+        let mut path = self.root.clone();
+        path.push(&body.bucket_id[BUCKET_ID_PREFIX.len()..]);
+        path.push(&body.file_name);
+
+        // The mock only keeps the latest version of a file so hiding it
+        // removes it.
+        match metadata(&path) {
+            Ok(ref meta) if meta.is_file() => (),
+            _ => {
+                return Err(B2Error::new(
+                    StatusCode::BAD_REQUEST,
+                    "file_not_present",
+                    format!("File not present: {}", body.file_name),
+                ));
+            }
+        }
+
+        remove_file(&path)?;
+        let key = path.display().to_string();
+        let mut state = self.state.lock().await;
+        state.file_info.remove(&key);
+        state.content_types.remove(&key);
+        state.content_sha1s.remove(&key);
+
+        api_response!(FileInfo {
+            account_id: TEST_ACCOUNT_ID.to_owned(),
+            action: FileAction::Hide,
+            bucket_id: body.bucket_id,
+            content_length: 0,
+            content_sha1: None,
+            content_type: None,
+            file_id: Some(format!("{}{}", FILE_ID_PREFIX, path.display())),
+            file_info: UserFileInfo::new(),
+            file_name: body.file_name,
+            upload_timestamp: 0,
+        })
+    }
+
     async fn b2_delete_file_version(
         self,
         _head: Parts,
@@ -695,6 +1245,10 @@ impl B2Server {
                 }
 
                 remove_file(path)?;
+                let mut state = self.state.lock().await;
+                state.file_info.remove(path);
+                state.content_types.remove(path);
+                state.content_sha1s.remove(path);
 
                 api_response!(DeleteFileVersionResponse {
                     file_id: body.file_id,
@@ -715,7 +1269,7 @@ impl B2Server {
         }
     }
 
-    async fn b2_download_file(self, path: &str) -> B2Result {
+    async fn b2_download_file(self, path: &str, range: Option<&HeaderValue>) -> B2Result {
         let path = match percent_decode(path) {
             Ok(s) => s,
             Err(_) => return Err(B2Error::invalid_parameters("File path was invalid utf-8.")),
@@ -723,13 +1277,67 @@ impl B2Server {
 
         let mut file = self.root.clone();
         file.push(path);
+        self.serve_file(file, range).await
+    }
 
+    async fn b2_download_file_by_id(self, query: &str, range: Option<&HeaderValue>) -> B2Result {
+        let file_id = query
+            .split('&')
+            .filter_map(|pair| {
+                if pair.starts_with("fileId=") {
+                    Some(&pair[7..])
+                } else {
+                    None
+                }
+            })
+            .next()
+            .ok_or_else(|| B2Error::invalid_parameters("Request had no file id."))?;
+
+        let file_id = match percent_decode(file_id) {
+            Ok(s) => s,
+            Err(_) => return Err(B2Error::invalid_parameters("File id was invalid utf-8.")),
+        };
+
+        if !file_id.starts_with(FILE_ID_PREFIX) {
+            return Err(B2Error::invalid_parameters(format!(
+                "Invalid file id: {}",
+                file_id
+            )));
+        }
+
+        let file = PathBuf::from(&file_id[FILE_ID_PREFIX.len()..]);
+        if !file.starts_with(&self.root) {
+            return Err(B2Error::not_found(&file));
+        }
+        self.serve_file(file, range).await
+    }
+
+    async fn serve_file(self, file: PathBuf, range: Option<&HeaderValue>) -> B2Result {
         let meta = metadata(&file).into_path_err(&file)?;
         if !meta.is_file() {
             return Err(B2Error::not_found(&file));
         }
 
-        let source = read(&file).into_path_err(file)?;
+        let mut source = read(&file).into_path_err(file)?;
+        let mut status = StatusCode::OK;
+        if let Some(range) = range {
+            let (start, end) = parse_range(range)?;
+            if start >= source.len() as u64 {
+                return Err(B2Error::new(
+                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    "range_not_satisfiable",
+                    "The range starts beyond the end of the file.",
+                ));
+            }
+
+            let end = match end {
+                Some(end) if end < source.len() as u64 => end as usize + 1,
+                _ => source.len(),
+            };
+            source = source[start as usize..end].to_vec();
+            status = StatusCode::PARTIAL_CONTENT;
+        }
+
         let mut len = source.len() / 5;
         if len == 0 {
             len = 1;
@@ -745,7 +1353,7 @@ impl B2Server {
             .collect();
 
         Ok(Response::builder()
-            .status(StatusCode::OK)
+            .status(status)
             .body(Body::wrap_stream(iter(blocks)))
             .expect("Failed to build response."))
     }
@@ -820,6 +1428,24 @@ impl B2Server {
             .and_then(|t| t.parse::<u64>().ok())
             .map(|d| UNIX_EPOCH + Duration::from_millis(d));
 
+        let info_prefix = B2_HEADER_FILE_INFO_PREFIX.to_lowercase();
+        let user_info: UserFileInfo = head
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.as_str();
+                if name.starts_with(&info_prefix) {
+                    value
+                        .to_str()
+                        .ok()
+                        .and_then(|v| percent_decode(v).ok())
+                        .map(|v| (name[info_prefix.len()..].to_owned(), v))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
         let mut path = self.root.clone();
         path.push(&bucket_id[BUCKET_ID_PREFIX.len()..]);
         path.push(&file);
@@ -832,7 +1458,7 @@ impl B2Server {
             match body.next().await {
                 Some(Ok(chunk)) => {
                     writer.write_all(&chunk)?;
-                    hasher.update(&chunk);
+                    hasher.input(&chunk);
                     length += chunk.len() as Int;
                 }
                 Some(Err(e)) => {
@@ -851,7 +1477,7 @@ impl B2Server {
             ));
         }
 
-        if expected_sha1 != hasher.hexdigest() {
+        if expected_sha1 != to_hex(&hasher.result()) {
             return Err(B2Error::invalid_parameters(
                 "Expected hash did not match data.",
             ));
@@ -866,15 +1492,36 @@ impl B2Server {
             }
         }
 
+        let content_type = match head
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+        {
+            // Real B2 guesses from the extension, the mock doesn't bother.
+            Some("b2/x-auto") | None => String::from("application/octet-stream"),
+            Some(content_type) => content_type.to_owned(),
+        };
+
+        let mut state = self.state.lock().await;
+        state
+            .file_info
+            .insert(path.display().to_string(), user_info.clone());
+        state
+            .content_types
+            .insert(path.display().to_string(), content_type.clone());
+        state
+            .content_sha1s
+            .insert(path.display().to_string(), expected_sha1.to_owned());
+
         api_response!(UploadFileResponse {
             account_id: TEST_ACCOUNT_ID.to_owned(),
             action: FileAction::Upload,
             bucket_id: bucket_id.to_owned(),
             content_length: length,
             content_sha1: Some(expected_sha1.to_owned()),
-            content_type: Some(String::from("application/octet-stream")),
+            content_type: Some(content_type),
             file_id: Some(format!("{}", path.display())),
-            file_info: Default::default(),
+            file_info: user_info,
             file_name: file.to_owned(),
             upload_timestamp: 0,
         })
@@ -969,7 +1616,7 @@ impl B2Server {
         loop {
             match body.next().await {
                 Some(Ok(chunk)) => {
-                    hasher.update(&chunk);
+                    hasher.input(&chunk);
                     length += chunk.len() as Int;
                     data.push(chunk);
                 }
@@ -989,7 +1636,7 @@ impl B2Server {
             ));
         }
 
-        if expected_sha1 != hasher.hexdigest() {
+        if expected_sha1 != to_hex(&hasher.result()) {
             return Err(B2Error::invalid_parameters(
                 "Expected hash did not match data.",
             ));
@@ -1038,7 +1685,7 @@ impl B2Server {
         };
         let mut writer = BufWriter::new(file);
 
-        let length = 0;
+        let mut length: Int = 0;
         for i in 0..upload.parts.len() {
             let (data, hash) = match upload.parts.remove(&i) {
                 Some(d) => d,
@@ -1051,6 +1698,7 @@ impl B2Server {
 
             for chunk in data {
                 writer.write_all(&chunk)?;
+                length += chunk.len() as Int;
             }
         }
 
@@ -1068,6 +1716,112 @@ impl B2Server {
         })
     }
 
+    async fn b2_list_unfinished_large_files(
+        self,
+        _head: Parts,
+        body: ListUnfinishedLargeFilesRequest,
+    ) -> B2Result {
+        if !body.bucket_id.starts_with(BUCKET_ID_PREFIX) {
+            return Err(B2Error::invalid_bucket_id(&body.bucket_id));
+        }
+
+        let prefix = body.name_prefix.unwrap_or_else(String::new);
+        let start = body.start_file_id.unwrap_or_else(String::new);
+        let count = body.max_file_count.unwrap_or(100) as usize;
+
+        let state = self.state.lock().await;
+        let mut ids: Vec<&String> = state
+            .large_uploads
+            .iter()
+            .filter(|(id, upload)| {
+                upload.bucket_id == body.bucket_id
+                    && upload.file_name.starts_with(&prefix)
+                    && id.as_str() >= start.as_str()
+            })
+            .map(|(id, _)| id)
+            .collect();
+        ids.sort();
+
+        let next_file_id = ids.get(count).map(|id| (*id).to_owned());
+        let files = ids
+            .iter()
+            .take(count)
+            .map(|id| {
+                let upload = &state.large_uploads[*id];
+                FileInfo {
+                    account_id: TEST_ACCOUNT_ID.to_owned(),
+                    action: FileAction::Start,
+                    bucket_id: upload.bucket_id.clone(),
+                    content_length: 0,
+                    content_sha1: None,
+                    content_type: None,
+                    file_id: Some((*id).to_owned()),
+                    file_info: Default::default(),
+                    file_name: upload.file_name.clone(),
+                    upload_timestamp: upload.started,
+                }
+            })
+            .collect();
+
+        api_response!(ListUnfinishedLargeFilesResponse {
+            files,
+            next_file_id,
+        })
+    }
+
+    async fn b2_list_parts(self, _head: Parts, body: ListPartsRequest) -> B2Result {
+        let start = body.start_part_number.unwrap_or(1).max(1);
+        let count = body.max_part_count.unwrap_or(100) as usize;
+
+        let state = self.state.lock().await;
+        let upload = match state.large_uploads.get(&body.file_id) {
+            Some(upload) => upload,
+            None => return Err(B2Error::invalid_parameters("Unknown file id.")),
+        };
+
+        let mut numbers: Vec<usize> = upload
+            .parts
+            .keys()
+            .map(|index| index + 1)
+            .filter(|number| *number >= start)
+            .collect();
+        numbers.sort();
+
+        let next_part_number = numbers.get(count).cloned();
+        let parts = numbers
+            .iter()
+            .take(count)
+            .map(|number| {
+                let (data, sha1) = &upload.parts[&(number - 1)];
+                PartInfo {
+                    file_id: body.file_id.clone(),
+                    part_number: *number,
+                    content_length: data.iter().map(|chunk| chunk.len() as Int).sum(),
+                    content_sha1: sha1.clone(),
+                    upload_timestamp: upload.started,
+                }
+            })
+            .collect();
+
+        api_response!(ListPartsResponse {
+            parts,
+            next_part_number,
+        })
+    }
+
+    async fn b2_cancel_large_file(self, _head: Parts, body: CancelLargeFileRequest) -> B2Result {
+        let mut state = self.state.lock().await;
+        match state.large_uploads.remove(&body.file_id) {
+            Some(upload) => api_response!(CancelLargeFileResponse {
+                file_id: body.file_id,
+                account_id: TEST_ACCOUNT_ID.to_owned(),
+                bucket_id: upload.bucket_id,
+                file_name: upload.file_name,
+            }),
+            None => Err(B2Error::invalid_parameters("Unknown file id.")),
+        }
+    }
+
     async fn check_auth(&self, auth: &str) -> Result<(), B2Error> {
         let mut state = self.state.lock().await;
         let count = match state.authorizations.get(auth) {
@@ -1095,17 +1849,92 @@ impl B2Server {
 
     async fn call_api(self, method: &str, head: Parts, data: Chunk) -> B2Result {
         api_method!(b2_list_buckets, self, method, head, data);
+        api_method!(b2_get_file_info, self, method, head, data);
+        api_method!(b2_get_download_authorization, self, method, head, data);
         api_method!(b2_list_file_names, self, method, head, data);
         api_method!(b2_list_file_versions, self, method, head, data);
         api_method!(b2_delete_file_version, self, method, head, data);
+        api_method!(b2_hide_file, self, method, head, data);
         api_method!(b2_get_upload_url, self, method, head, data);
         api_method!(b2_start_large_file, self, method, head, data);
         api_method!(b2_get_upload_part_url, self, method, head, data);
         api_method!(b2_finish_large_file, self, method, head, data);
+        api_method!(b2_list_unfinished_large_files, self, method, head, data);
+        api_method!(b2_list_parts, self, method, head, data);
+        api_method!(b2_cancel_large_file, self, method, head, data);
+        api_method!(b2_copy_file, self, method, head, data);
+        api_method!(b2_copy_part, self, method, head, data);
 
         Err(B2Error::invalid_parameters("Invalid API method requested."))
     }
 
+    async fn handle(self, request: Request<Body>) -> Result<Response<Body>, io::Error> {
+        let _active = self.requests.start(request.uri().path());
+        let fault = self.faults.take(request.uri().path());
+
+        match fault {
+            Some(Fault::Error(status, code)) => {
+                return Ok(B2Error::new(status, code, "Injected failure.").into())
+            }
+            Some(Fault::ErrorAfterBody(status, code)) => {
+                let _ = request.into_body().try_concat().await;
+                return Ok(B2Error::new(status, code, "Injected failure.").into());
+            }
+            Some(Fault::RetryAfter(status, code, seconds)) => {
+                let mut response: Response<Body> =
+                    B2Error::new(status, code, "Injected failure.").into();
+                response
+                    .headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+                return Ok(response);
+            }
+            Some(Fault::Disconnect) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "Injected disconnect.",
+                ))
+            }
+            Some(Fault::Latency(delay)) => delay_for(delay).await,
+            Some(Fault::ExpireAuth) => {
+                if let Some(auth) = request.headers().get(header::AUTHORIZATION) {
+                    if let Ok(auth) = auth.to_str() {
+                        let mut state = self.state.lock().await;
+                        if state.authorizations.contains_key(auth) {
+                            state
+                                .authorizations
+                                .insert(auth.to_owned(), self.auth_timeout);
+                        }
+                    }
+                }
+
+                return Ok(B2Error::new(
+                    StatusCode::UNAUTHORIZED,
+                    "expired_auth_token",
+                    "Auth token has expired.",
+                )
+                .into());
+            }
+            _ => (),
+        }
+
+        let response = match self.serve(request).await {
+            Ok(response) => response,
+            Err(e) => e.into(),
+        };
+
+        match fault {
+            Some(Fault::SlowBody(delay)) => {
+                let (head, body) = response.into_parts();
+                let body = body.then(move |chunk| async move {
+                    delay_for(delay).await;
+                    chunk
+                });
+                Ok(Response::from_parts(head, Body::wrap_stream(body)))
+            }
+            _ => Ok(response),
+        }
+    }
+
     async fn serve(self, request: Request<Body>) -> B2Result {
         let (head, body) = request.into_parts();
 
@@ -1116,6 +1945,15 @@ impl B2Server {
             }
         };
 
+        // Authorized downloads come from clients other than this crate.
+        if path.starts_with("/download/file/") {
+            if let Some(query) = head.uri.query() {
+                if query.contains("Authorization=") {
+                    return self.b2_download_authorized(&path[15..], query).await;
+                }
+            }
+        }
+
         match head.headers.get(header::USER_AGENT) {
             Some(ua) => {
                 let ua = ua.to_str().map_err(|e| {
@@ -1174,10 +2012,18 @@ impl B2Server {
                 B2Error::invalid_parameters(format!("Failed to receive entire body: {}", e))
             })?;
             self.call_api(method, head, data).await
+        } else if path.starts_with("/download/b2api/v2/b2_download_file_by_id") {
+            self.check_auth(&auth).await?;
+            self.b2_download_file_by_id(
+                head.uri.query().unwrap_or(""),
+                head.headers.get(header::RANGE),
+            )
+            .await
         } else if path.starts_with("/download/file/") {
             let target = &path[15..];
             self.check_auth(&auth).await?;
-            self.b2_download_file(target).await
+            self.b2_download_file(target, head.headers.get(header::RANGE))
+                .await
         } else if path.starts_with("/upload/file/") {
             if head.method != "POST" {
                 return Err(B2Error::method_not_allowed(
@@ -1268,6 +2114,26 @@ impl B2Server {
 }
 
 pub fn start_server(root: PathBuf, auth_timeout: usize) -> TestResult<(SocketAddr, Sender<()>)> {
+    start_server_with_faults(root, auth_timeout, FaultInjector::new())
+}
+
+/// Starts a server that injects faults into requests as the injector says.
+pub fn start_server_with_faults(
+    root: PathBuf,
+    auth_timeout: usize,
+    faults: FaultInjector,
+) -> TestResult<(SocketAddr, Sender<()>)> {
+    start_counting_server(root, auth_timeout, faults, RequestCounter::new())
+}
+
+/// Starts a server that injects faults into requests and counts the requests
+/// made to it.
+pub fn start_counting_server(
+    root: PathBuf,
+    auth_timeout: usize,
+    faults: FaultInjector,
+    requests: RequestCounter,
+) -> TestResult<(SocketAddr, Sender<()>)> {
     let (shutdown_sender, shutdown_receiver) = channel::<()>();
 
     let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
@@ -1281,6 +2147,8 @@ pub fn start_server(root: PathBuf, auth_timeout: usize) -> TestResult<(SocketAdd
         auth_timeout,
         state: Arc::new(Mutex::new(B2ServerState::new())),
         root,
+        faults,
+        requests,
     };
 
     let http_server = Server::from_tcp(listener)
@@ -1289,10 +2157,7 @@ pub fn start_server(root: PathBuf, auth_timeout: usize) -> TestResult<(SocketAdd
             let server = b2_server.clone();
             async {
                 Ok::<_, io::Error>(service_fn(move |request: Request<Body>| {
-                    server
-                        .clone()
-                        .serve(request)
-                        .map(|r| r.or_else(|e| Ok::<Response<Body>, io::Error>(e.into())))
+                    server.clone().handle(request)
                 }))
             }
         }));
@@ -12,5 +12,5 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#![cfg(feature = "b2")]
+#![cfg(all(feature = "b2", feature = "hyper-client"))]
 pub mod b2_server;
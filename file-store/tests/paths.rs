@@ -0,0 +1,106 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::*;
+
+fn assert_invalid<T>(result: StorageResult<T>, path: &str) {
+    match result {
+        Ok(_) => panic!("Expected '{}' to be invalid", path),
+        Err(e) => match e.kind() {
+            StorageErrorKind::InvalidPath(p) => assert_eq!(p.to_string(), path),
+            k => panic!("Unexpected error kind {:?}", k),
+        },
+    }
+}
+
+#[test]
+fn test_policy() {
+    let path = |s: &str| ObjectPath::new(s).unwrap();
+
+    let policy = PathPolicy::new();
+    assert_eq!(policy, PathPolicy::default());
+    assert!(policy.validate(&path("a//../\u{1}")).is_ok());
+
+    let policy = PathPolicy::new()
+        .max_length(10)
+        .max_part_length(5)
+        .container_parts(1)
+        .disallow_characters(&['*'])
+        .disallow_control_characters()
+        .disallow_empty_parts()
+        .reserve_part("..");
+
+    assert!(policy.validate(&path("")).is_ok());
+    assert!(policy.validate(&path("bucket/abcde/fghi")).is_ok());
+    assert!(policy.validate(&path("bucket/abcde/")).is_ok());
+    assert_invalid(
+        policy.validate(&path("bucket/abcde/fghij")),
+        "bucket/abcde/fghij",
+    );
+    assert_invalid(policy.validate(&path("bucket/abcdef")), "bucket/abcdef");
+    assert_invalid(policy.validate(&path("a/b*")), "a/b*");
+    assert_invalid(policy.validate(&path("a/b\n")), "a/b\n");
+    assert_invalid(policy.validate(&path("a//b")), "a//b");
+    assert_invalid(policy.validate(&path("a/../b")), "a/../b");
+}
+
+#[test]
+fn test_file_policy() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        assert_invalid(store.get_object("../escape").await, "../escape");
+        assert_invalid(store.get_object("dir/./file").await, "dir/./file");
+        assert_invalid(store.delete_object("dir//file").await, "dir//file");
+        assert_invalid(store.list_objects("dir\u{0}/").await, "dir\u{0}/");
+    });
+}
+
+#[test]
+fn test_file_name_length() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        let long = "a".repeat(256);
+        let result = store
+            .write_file_from_stream(
+                long.as_str(),
+                futures::stream::empty::<StorageResult<Vec<u8>>>(),
+            )
+            .await;
+        match result {
+            Err(TransferError::TargetError(e)) => match e.kind() {
+                StorageErrorKind::InvalidPath(p) => assert_eq!(p.to_string(), long),
+                k => panic!("Unexpected error kind {:?}", k),
+            },
+            r => panic!("Unexpected result {:?}", r),
+        }
+    });
+}
@@ -0,0 +1,294 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+
+use futures::future::join_all;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::quota::{QuotaBackend, QuotaLimits, Usage};
+use file_store::testing::data_stream;
+use file_store::*;
+
+fn path(path: &str) -> ObjectPath {
+    ObjectPath::new(path).unwrap()
+}
+
+fn assert_over_quota(error: TransferError) {
+    assert_eq!(
+        StorageError::from(error).kind(),
+        StorageErrorKind::OverQuota
+    );
+}
+
+#[test]
+fn test_quota() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        fs::create_dir_all(temp.path().join("a")).unwrap();
+        fs::write(temp.path().join("a").join("existing.txt"), "12345").unwrap();
+
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let quota = QuotaBackend::new(store)
+            .limit(path("a"), QuotaLimits::new().max_bytes(20).max_objects(3))
+            .limit(path("b"), QuotaLimits::new().max_objects(1));
+
+        // Existing files are counted.
+        assert_eq!(
+            quota.usage(path("a")).await.unwrap(),
+            Usage {
+                bytes: 5,
+                objects: 1
+            }
+        );
+
+        quota
+            .write_file_from_stream(path("a/one.txt").into(), data_stream(&["12345", "12345"]))
+            .await
+            .unwrap();
+        assert_eq!(quota.usage(path("a")).await.unwrap().bytes, 15);
+
+        // Passing the byte limit fails part way through the stream.
+        assert_over_quota(
+            quota
+                .write_file_from_stream(path("a/two.txt").into(), data_stream(&["12345", "12345"]))
+                .await
+                .unwrap_err(),
+        );
+        assert_eq!(
+            quota.usage(path("a")).await.unwrap(),
+            Usage {
+                bytes: 15,
+                objects: 2
+            }
+        );
+
+        // Replacing a file only counts the difference.
+        quota
+            .write_file_from_stream(path("a/one.txt").into(), data_stream(&["123456789012345"]))
+            .await
+            .unwrap();
+        assert_eq!(quota.usage(path("a")).await.unwrap().bytes, 20);
+
+        // Other prefixes have their own limits.
+        quota
+            .copy_file(path("a/existing.txt"), path("b/copy.txt").into())
+            .await
+            .unwrap();
+        assert_over_quota(
+            quota
+                .copy_file(path("a/existing.txt"), path("b/other.txt").into())
+                .await
+                .unwrap_err(),
+        );
+        assert!(!temp.path().join("b").join("other.txt").exists());
+
+        // Deleting frees space.
+        quota.delete_object(path("a/one.txt")).await.unwrap();
+        assert_eq!(
+            quota.usage(path("a")).await.unwrap(),
+            Usage {
+                bytes: 5,
+                objects: 1
+            }
+        );
+
+        quota
+            .move_file(path("b/copy.txt"), path("a/moved.txt").into())
+            .await
+            .unwrap();
+        assert_eq!(quota.usage(path("a")).await.unwrap().objects, 2);
+        assert_eq!(quota.usage(path("b")).await.unwrap().objects, 0);
+
+        quota.delete_object(path("a")).await.unwrap();
+        assert_eq!(quota.usage(path("a")).await.unwrap(), Usage::default());
+
+        // Unlimited paths are not tracked.
+        quota
+            .write_file_from_stream(
+                path("c.txt").into(),
+                data_stream(&["12345678901234567890123"]),
+            )
+            .await
+            .unwrap();
+    });
+}
+
+#[test]
+fn test_quota_ignore_if_exists() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        fs::create_dir_all(temp.path().join("a")).unwrap();
+        fs::write(temp.path().join("a").join("existing.txt"), "12345").unwrap();
+
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let quota = QuotaBackend::new(store).limit(path("a"), QuotaLimits::new().max_bytes(20));
+        let expected = Usage {
+            bytes: 5,
+            objects: 1,
+        };
+
+        // Writes that leave the existing file alone don't change the usage.
+        quota
+            .write_file_from_stream(
+                UploadInfo::from(path("a/existing.txt")).write_mode(WriteMode::IgnoreIfExists),
+                data_stream(&["1234567890"]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(quota.usage(path("a")).await.unwrap(), expected);
+
+        quota
+            .write_file_from_stream(path("a/source.txt").into(), data_stream(&["1234567"]))
+            .await
+            .unwrap();
+        quota
+            .copy_file(
+                path("a/source.txt"),
+                UploadInfo::from(path("a/existing.txt")).write_mode(WriteMode::IgnoreIfExists),
+            )
+            .await
+            .unwrap();
+        quota
+            .move_file(
+                path("a/source.txt"),
+                UploadInfo::from(path("a/existing.txt")).write_mode(WriteMode::IgnoreIfExists),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(temp.path().join("a").join("existing.txt")).unwrap(),
+            "12345"
+        );
+        assert_eq!(
+            quota.usage(path("a")).await.unwrap(),
+            Usage {
+                bytes: 12,
+                objects: 2
+            }
+        );
+        assert_eq!(
+            quota.recalculate(path("a")).await.unwrap(),
+            quota.usage(path("a")).await.unwrap()
+        );
+    });
+}
+
+#[test]
+fn test_quota_clones_share_limits() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let quota = QuotaBackend::new(store);
+        let clone = quota.clone();
+        let quota = quota.limit(path("a"), QuotaLimits::new().max_objects(1));
+
+        clone
+            .write_file_from_stream(path("a/one.txt").into(), data_stream(&["1"]))
+            .await
+            .unwrap();
+        assert_over_quota(
+            quota
+                .write_file_from_stream(path("a/two.txt").into(), data_stream(&["2"]))
+                .await
+                .unwrap_err(),
+        );
+        assert_eq!(quota.usage(path("a")).await.unwrap().objects, 1);
+    });
+}
+
+#[test]
+fn test_quota_concurrent_writes() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let quota = QuotaBackend::new(store).limit(path("a"), QuotaLimits::new().max_objects(2));
+
+        let writes = (0..4).map(|_| {
+            quota.write_file_from_stream(path("a/same.txt").into(), data_stream(&["12345"]))
+        });
+        for result in join_all(writes).await {
+            result.unwrap();
+        }
+
+        assert_eq!(
+            quota.usage(path("a")).await.unwrap(),
+            Usage {
+                bytes: 5,
+                objects: 1
+            }
+        );
+    });
+}
+
+#[test]
+fn test_quota_file_replaces_directory() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        fs::create_dir_all(temp.path().join("a").join("dir").join("sub")).unwrap();
+        fs::write(temp.path().join("a").join("dir").join("one.txt"), "12345").unwrap();
+        fs::write(
+            temp.path()
+                .join("a")
+                .join("dir")
+                .join("sub")
+                .join("two.txt"),
+            "1234567890",
+        )
+        .unwrap();
+
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let quota = QuotaBackend::new(store)
+            .limit(path("a"), QuotaLimits::new().max_bytes(20))
+            .limit(path("a/dir/sub"), QuotaLimits::new().max_objects(2));
+        assert_eq!(quota.usage(path("a/dir/sub")).await.unwrap().objects, 1);
+
+        // The replaced directory's files no longer count.
+        quota
+            .write_file_from_stream(path("a/dir").into(), data_stream(&["1234567"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            quota.usage(path("a")).await.unwrap(),
+            Usage {
+                bytes: 7,
+                objects: 1
+            }
+        );
+        assert_eq!(
+            quota.usage(path("a/dir/sub")).await.unwrap(),
+            Usage::default()
+        );
+        assert_eq!(
+            quota.recalculate(path("a")).await.unwrap(),
+            quota.usage(path("a")).await.unwrap()
+        );
+    });
+}
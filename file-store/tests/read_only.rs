@@ -0,0 +1,101 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::stream::StreamExt;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::read_only::ReadOnly;
+use file_store::testing::data_stream;
+use file_store::*;
+
+fn assert_read_only(error: StorageError) {
+    assert_eq!(error.kind(), StorageErrorKind::ReadOnly);
+}
+
+#[test]
+fn test_reads() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        fs::write(temp.path().join("a.txt"), "Some data.").unwrap();
+
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let backend: Arc<dyn StorageBackend> = Arc::new(ReadOnly::new(store));
+        let path = ObjectPath::new("a.txt").unwrap();
+
+        assert_eq!(backend.get_object(path.clone()).await.unwrap().len(), 10);
+        let mut data = backend.get_file_stream(path).await.unwrap();
+        assert_eq!(
+            data.next().await.unwrap().unwrap(),
+            Bytes::from("Some data.")
+        );
+    });
+}
+
+#[test]
+fn test_writes_refused() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        fs::write(temp.path().join("a.txt"), "Some data.").unwrap();
+
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let backend: Arc<dyn StorageBackend> = Arc::new(ReadOnly::new(store));
+        let path = ObjectPath::new("a.txt").unwrap();
+        let other = ObjectPath::new("b.txt").unwrap();
+
+        assert_read_only(
+            backend
+                .write_file_from_stream(other.clone().into(), data_stream(&["Data."]))
+                .await
+                .unwrap_err()
+                .into(),
+        );
+        assert_read_only(
+            backend
+                .copy_file(path.clone(), other.clone().into())
+                .await
+                .unwrap_err()
+                .into(),
+        );
+        assert_read_only(
+            backend
+                .move_file(path.clone(), other.clone().into())
+                .await
+                .unwrap_err()
+                .into(),
+        );
+        assert_read_only(backend.delete_object(path.clone()).await.unwrap_err());
+        assert_read_only(backend.create_directory(other.clone()).await.unwrap_err());
+
+        let summary = backend.delete_objects(vec![path.clone()]).await;
+        assert!(summary.deleted.is_empty());
+        assert_eq!(summary.failed.len(), 1);
+
+        assert_eq!(fs::read(temp.path().join("a.txt")).unwrap(), b"Some data.");
+        assert!(!temp.path().join("b.txt").exists());
+    });
+}
@@ -0,0 +1,104 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "recording", feature = "b2", feature = "hyper-client"))]
+
+extern crate file_store;
+
+mod mocks;
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::{once, TryStreamExt};
+use tempfile::tempdir;
+
+use file_store::backends::b2::B2Backend;
+use file_store::backends::Backend;
+use file_store::http_client::*;
+use file_store::testing::{prepare_test, run, TestError, TestResult};
+use file_store::*;
+
+use crate::mocks::b2_server::start_server;
+
+async fn exercise(fs: &FileStore) -> TestResult<(usize, Vec<u8>)> {
+    let objects: Vec<Object> = fs.list_objects("dir2").await?.try_collect().await?;
+
+    fs.write_file_from_stream(
+        "recorded",
+        once(ready(Ok::<_, StorageError>(Bytes::from("Some data.")))),
+    )
+    .await?;
+
+    let data: Vec<Data> = fs.get_file_stream("recorded").await?.try_collect().await?;
+    Ok((objects.len(), data.concat()))
+}
+
+#[test]
+fn test_record_and_replay() {
+    let result: TestResult<()> = run(async {
+        let context = prepare_test(Backend::B2, "test1")?;
+        let (addr, sender) = start_server(context.get_fs_root(), 20000)?;
+        let host = format!("http://{}", addr);
+
+        let recorder = RecordingClient::new(default_client(
+            &TlsSettings::new(),
+            None,
+            &PoolSettings::new(),
+        )?);
+        let fs = B2Backend::builder("foo", "bar")
+            .host(&host)
+            .prefix(ObjectPath::new("dir1")?)
+            .http_client(recorder.clone())
+            .connect()
+            .await?;
+        let recorded = exercise(&fs).await?;
+        assert_eq!(recorded.1, b"Some data.");
+
+        sender.send(()).map_err(|()| {
+            TestError::HarnessFailure(String::from("Failed to send shutdown to mock b2 server."))
+        })?;
+
+        let cassette = recorder.cassette();
+        assert!(!cassette.interactions().is_empty());
+
+        let temp = tempdir().map_err(TestError::from_error)?;
+        let file = temp.path().join("cassette.json");
+        cassette.save(&file)?;
+        let cassette = Cassette::load(&file)?;
+
+        // The server is gone so everything must come from the cassette.
+        let replay = ReplayClient::new(cassette);
+        let fs = B2Backend::builder("foo", "bar")
+            .host(&host)
+            .prefix(ObjectPath::new("dir1")?)
+            .http_client(replay.clone())
+            .connect()
+            .await?;
+        assert_eq!(exercise(&fs).await?, recorded);
+        assert_eq!(replay.remaining(), 0);
+
+        // Nothing is left to answer another request.
+        let error = fs.get_object("recorded").await.unwrap_err();
+        match error.kind() {
+            StorageErrorKind::Other => (),
+            k => panic!("Unexpected error kind {:?}", k),
+        }
+
+        Ok(())
+    });
+
+    if let Err(error) = result {
+        panic!(error.to_string());
+    }
+}
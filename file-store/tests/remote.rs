@@ -0,0 +1,171 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "remote", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::path::Path;
+
+use bytes::Bytes;
+use futures::future::FutureExt;
+use futures::stream::{iter, TryStreamExt};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::backends::remote::RemoteBackend;
+use file_store::backends::Backend;
+use file_store::http_client::{HttpClient, HttpRequest, HttpResponseFuture, RequestBody};
+use file_store::serve::grpc::GrpcHandler;
+use file_store::*;
+
+/// Sends requests straight to a handler.
+#[derive(Debug)]
+struct LoopbackClient {
+    handler: GrpcHandler,
+}
+
+impl HttpClient for LoopbackClient {
+    fn request(&self, request: HttpRequest) -> HttpResponseFuture {
+        let response = self.handler.handle(request.map(RequestBody::into_stream));
+        HttpResponseFuture::from_future(response.map(Ok))
+    }
+}
+
+/// Connects a remote store to a local one, returning both.
+async fn connect(root: &Path) -> (FileStore, FileStore) {
+    let local = FileBackend::connect(root).await.unwrap();
+    let client = LoopbackClient {
+        handler: GrpcHandler::new(local.clone()),
+    };
+
+    let store = RemoteBackend::builder("http://localhost")
+        .http_client(client)
+        .connect()
+        .await
+        .unwrap();
+    assert_eq!(store.backend_type(), Backend::Remote);
+
+    let data = iter(vec![
+        Ok::<_, StorageError>(Bytes::from("Some ")),
+        Ok(Bytes::from("data.")),
+    ]);
+    store
+        .write_file_from_stream("dir/file.txt", data)
+        .await
+        .unwrap();
+
+    (local, store)
+}
+
+#[test]
+fn test_remote_files() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let (local, store) = connect(temp.path()).await;
+
+        assert_eq!(
+            local.read_to_bytes("dir/file.txt").await.unwrap(),
+            "Some data."
+        );
+        assert_eq!(
+            store.read_to_bytes("dir/file.txt").await.unwrap(),
+            "Some data."
+        );
+
+        let object = store.get_object("dir/file.txt").await.unwrap();
+        assert_eq!(object.path(), ObjectPath::new("dir/file.txt").unwrap());
+        assert_eq!(object.len(), 10);
+        assert_eq!(object.object_type(), ObjectType::File);
+        assert_eq!(
+            object.modified(),
+            local.get_object("dir/file.txt").await.unwrap().modified()
+        );
+
+        store.copy_file("dir/file.txt", "copy.txt").await.unwrap();
+        store.move_file("copy.txt", "dir/moved.txt").await.unwrap();
+        assert_eq!(
+            local.read_to_bytes("dir/moved.txt").await.unwrap(),
+            "Some data."
+        );
+        assert!(local.get_object("copy.txt").await.is_err());
+    });
+}
+
+#[test]
+fn test_remote_listing() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let (_, store) = connect(temp.path()).await;
+        store
+            .copy_file("dir/file.txt", "dir/copy.txt")
+            .await
+            .unwrap();
+
+        let mut objects: Vec<Object> = store
+            .list_objects("dir/")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        objects.sort();
+        let paths: Vec<String> = objects.iter().map(|o| o.path().to_string()).collect();
+        assert_eq!(paths, vec!["dir/copy.txt", "dir/file.txt"]);
+
+        let objects: Vec<Object> = store
+            .list_directory("")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].object_type(), ObjectType::Directory);
+    });
+}
+
+#[test]
+fn test_remote_errors() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let (_, store) = connect(temp.path()).await;
+        store.delete_object("dir/file.txt").await.unwrap();
+
+        let path = ObjectPath::new("dir/file.txt").unwrap();
+        match store.get_object(path.clone()).await {
+            Ok(_) => panic!("Should have failed to find a deleted file."),
+            Err(e) => assert_eq!(e.kind(), StorageErrorKind::NotFound(path.clone())),
+        }
+
+        match store.get_file_stream(path.clone()).await {
+            Ok(_) => panic!("Should have failed to read a deleted file."),
+            Err(e) => assert_eq!(e.kind(), StorageErrorKind::NotFound(path.clone())),
+        }
+
+        match store.copy_file(path.clone(), "other.txt").await {
+            Err(TransferError::SourceError(e)) => {
+                assert_eq!(e.kind(), StorageErrorKind::NotFound(path))
+            }
+            _ => panic!("Should have failed to copy a deleted file."),
+        }
+    });
+}
@@ -0,0 +1,81 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+
+#[cfg(unix)]
+#[test]
+fn test_move_keeps_inode() {
+    use std::os::unix::fs::MetadataExt;
+
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    fs::create_dir_all(temp.path().join("dir")).unwrap();
+    fs::write(temp.path().join("source"), "Some data.").unwrap();
+    let inode = fs::metadata(temp.path().join("source")).unwrap().ino();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        store.move_file("source", "dir/moved").await.unwrap();
+        let moved = fs::metadata(root.join("dir").join("moved")).unwrap();
+        assert_eq!(moved.ino(), inode);
+    });
+}
+
+#[test]
+fn test_move_directory() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    fs::create_dir_all(temp.path().join("dir")).unwrap();
+    fs::write(temp.path().join("dir").join("existing"), "Old data.").unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        assert!(store.move_file("dir", "other").await.is_err());
+        assert!(root.join("dir").join("existing").is_file());
+        assert!(!root.join("other").exists());
+    });
+}
+
+#[test]
+fn test_move_onto_parent() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    fs::create_dir_all(temp.path().join("dir")).unwrap();
+    fs::write(temp.path().join("dir").join("source"), "Some data.").unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let store = FileBackend::connect(&root).await.unwrap();
+
+        // A directory containing the source can't be replaced.
+        assert!(store.move_file("dir/source", "dir").await.is_err());
+        assert!(root.join("dir").join("source").is_file());
+    });
+}
@@ -0,0 +1,164 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "responder", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use bytes::Bytes;
+use futures::stream::{iter, TryStreamExt};
+use http::header::*;
+use http::{Method, Response, StatusCode};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::responder::*;
+use file_store::*;
+
+async fn body(response: Response<DataStream>) -> Vec<u8> {
+    let chunks: Vec<Data> = response.into_body().try_collect().await.unwrap();
+    chunks.concat()
+}
+
+fn header(response: &Response<DataStream>, name: HeaderName) -> &str {
+    response.headers().get(name).unwrap().to_str().unwrap()
+}
+
+async fn write(store: &FileStore) -> ObjectPath {
+    let path = ObjectPath::new("dir/file.txt").unwrap();
+
+    // Split the data over a few chunks to exercise the range slicing.
+    let chunks = vec!["0123", "4567", "89"]
+        .into_iter()
+        .map(|s| Ok(Bytes::from(s)));
+    store
+        .write_file_from_stream(path.clone(), DataStream::from_stream(iter(chunks)))
+        .await
+        .unwrap();
+
+    path
+}
+
+#[test]
+fn test_serve_object() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let path = write(&store).await;
+
+        let headers = HeaderMap::new();
+        let response = serve_object(&store, path.clone(), &Method::GET, &headers)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, CONTENT_LENGTH), "10");
+        assert_eq!(header(&response, CONTENT_TYPE), "text/plain");
+        assert_eq!(header(&response, ACCEPT_RANGES), "bytes");
+        assert_eq!(body(response).await, b"0123456789");
+
+        let response = serve_object(&store, path.clone(), &Method::HEAD, &headers)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, CONTENT_LENGTH), "10");
+        assert_eq!(body(response).await, b"");
+
+        let result = serve_object(
+            &store,
+            ObjectPath::new("dir").unwrap(),
+            &Method::GET,
+            &headers,
+        )
+        .await;
+        match result {
+            Err(e) => assert_eq!(
+                e.kind(),
+                StorageErrorKind::NotFound(ObjectPath::new("dir").unwrap())
+            ),
+            Ok(r) => panic!("Unexpected response {:?}", r.status()),
+        }
+    });
+}
+
+#[test]
+fn test_serve_ranges() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let path = write(&store).await;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RANGE, HeaderValue::from_static("bytes=3-6"));
+        let response = serve_object(&store, path.clone(), &Method::GET, &headers)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header(&response, CONTENT_LENGTH), "4");
+        assert_eq!(header(&response, CONTENT_RANGE), "bytes 3-6/10");
+        assert_eq!(body(response).await, b"3456");
+
+        headers.insert(RANGE, HeaderValue::from_static("bytes=-3"));
+        let response = serve_object(&store, path.clone(), &Method::GET, &headers)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header(&response, CONTENT_RANGE), "bytes 7-9/10");
+        assert_eq!(body(response).await, b"789");
+
+        headers.insert(RANGE, HeaderValue::from_static("bytes=12-"));
+        let response = serve_object(&store, path.clone(), &Method::GET, &headers)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(header(&response, CONTENT_RANGE), "bytes */10");
+
+        // A stale If-Range means the whole file is sent.
+        headers.insert(RANGE, HeaderValue::from_static("bytes=3-6"));
+        headers.insert(IF_RANGE, HeaderValue::from_static("\"stale\""));
+        let response = serve_object(&store, path.clone(), &Method::GET, &headers)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, b"0123456789");
+    });
+}
+
+#[test]
+fn test_serve_not_modified() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let path = write(&store).await;
+
+        let response = serve_object(&store, path.clone(), &Method::GET, &HeaderMap::new())
+            .await
+            .unwrap();
+        let etag = header(&response, ETAG).to_owned();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, HeaderValue::from_str(&etag).unwrap());
+        let response = serve_object(&store, path, &Method::GET, &headers)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(body(response).await, b"");
+    });
+}
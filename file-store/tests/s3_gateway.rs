@@ -0,0 +1,177 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "s3-gateway", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use futures::stream::{iter, TryStreamExt};
+use http::{Request, Response, StatusCode};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::serve::s3::*;
+use file_store::*;
+
+fn request(
+    method: &str,
+    uri: &str,
+    body: &'static str,
+) -> Request<impl futures::Stream<Item = Result<Bytes, Infallible>>> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(iter(vec![Ok(Bytes::from(body))]))
+        .unwrap()
+}
+
+async fn body(response: Response<DataStream>) -> String {
+    let chunks: Vec<Data> = response.into_body().try_collect().await.unwrap();
+    String::from_utf8(chunks.concat()).unwrap()
+}
+
+async fn populate(handler: &S3Handler) {
+    for key in &["a.txt", "dir/b.txt", "dir/c.txt", "dir/sub/d.txt"] {
+        let response = handler
+            .handle(request("PUT", &format!("/bucket/{}", key), "Some data."))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key("ETag"));
+    }
+}
+
+#[test]
+fn test_buckets() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let handler = S3Handler::new(store, "bucket");
+
+        let response = handler.handle(request("HEAD", "/bucket", "")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = handler.handle(request("GET", "/other/file", "")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(body(response).await.contains("<Code>NoSuchBucket</Code>"));
+    });
+}
+
+#[test]
+fn test_objects() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let handler = S3Handler::new(store, "bucket");
+        populate(&handler).await;
+
+        let response = handler
+            .handle(request("GET", "/bucket/dir/b.txt", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, "Some data.");
+
+        let response = handler
+            .handle(request("HEAD", "/bucket/dir/b.txt", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("Content-Length").unwrap(), "10");
+
+        let response = handler.handle(request("GET", "/bucket/missing", "")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(body(response).await.contains("<Code>NoSuchKey</Code>"));
+
+        let response = handler
+            .handle(request("DELETE", "/bucket/dir/b.txt", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = handler
+            .handle(request("DELETE", "/bucket/dir/b.txt", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = handler
+            .handle(request("GET", "/bucket/dir/b.txt", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    });
+}
+
+#[test]
+fn test_listing() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let handler = S3Handler::new(store, "bucket");
+        populate(&handler).await;
+
+        let response = handler
+            .handle(request("GET", "/bucket?list-type=2&prefix=dir%2F", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let xml = body(response).await;
+        assert!(xml.contains("<KeyCount>3</KeyCount>"));
+        assert!(xml.contains("<Key>dir/b.txt</Key><Size>10</Size>"));
+        assert!(xml.contains("<Key>dir/sub/d.txt</Key>"));
+        assert!(!xml.contains("<Key>a.txt</Key>"));
+
+        let response = handler
+            .handle(request("GET", "/bucket?list-type=2&delimiter=%2F", ""))
+            .await;
+        let xml = body(response).await;
+        assert!(xml.contains("<KeyCount>2</KeyCount>"));
+        assert!(xml.contains("<Key>a.txt</Key>"));
+        assert!(xml.contains("<CommonPrefixes><Prefix>dir/</Prefix></CommonPrefixes>"));
+    });
+}
+
+#[test]
+fn test_listing_pages() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let handler = S3Handler::new(store, "bucket");
+        populate(&handler).await;
+
+        let response = handler
+            .handle(request("GET", "/bucket?list-type=2&max-keys=2", ""))
+            .await;
+        let xml = body(response).await;
+        assert!(xml.contains("<IsTruncated>true</IsTruncated>"));
+        assert!(xml.contains("<NextContinuationToken>dir/b.txt</NextContinuationToken>"));
+
+        let response = handler
+            .handle(request(
+                "GET",
+                "/bucket?list-type=2&max-keys=2&continuation-token=dir%2Fb.txt",
+                "",
+            ))
+            .await;
+        let xml = body(response).await;
+        assert!(xml.contains("<IsTruncated>false</IsTruncated>"));
+        assert!(xml.contains("<Key>dir/c.txt</Key>"));
+        assert!(xml.contains("<Key>dir/sub/d.txt</Key>"));
+    });
+}
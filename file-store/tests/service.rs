@@ -0,0 +1,80 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "tower", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use bytes::Bytes;
+use futures::future::{poll_fn, ready};
+use futures::stream::{once, TryStreamExt};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+use tower_service::Service;
+
+use file_store::backends::file::FileBackend;
+use file_store::service::*;
+use file_store::*;
+
+async fn send(
+    service: &mut StorageService,
+    request: StorageRequest,
+) -> StorageResult<StorageResponse> {
+    poll_fn(|cx| service.poll_ready(cx)).await?;
+    service.call(request).await
+}
+
+#[test]
+fn test_service() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let mut service = StorageService::new(store);
+        let path = ObjectPath::new("file.txt").unwrap();
+
+        let data = DataStream::from_stream(once(ready(Ok(Bytes::from("Some data.")))));
+        match send(
+            &mut service,
+            StorageRequest::WriteFileFromStream {
+                info: path.clone().into(),
+                data,
+            },
+        )
+        .await
+        {
+            Ok(StorageResponse::Complete) => (),
+            r => panic!("Unexpected response {:?}", r),
+        }
+
+        match send(&mut service, StorageRequest::GetFileStream(path.clone())).await {
+            Ok(StorageResponse::Data(stream)) => {
+                let chunks: Vec<Data> = stream.try_collect().await.unwrap();
+                assert_eq!(chunks.concat(), b"Some data.");
+            }
+            r => panic!("Unexpected response {:?}", r),
+        }
+
+        match send(&mut service, StorageRequest::DeleteObject(path.clone())).await {
+            Ok(StorageResponse::Complete) => (),
+            r => panic!("Unexpected response {:?}", r),
+        }
+
+        match send(&mut service, StorageRequest::GetObject(path.clone())).await {
+            Err(e) => assert_eq!(e.kind(), StorageErrorKind::NotFound(path)),
+            r => panic!("Unexpected response {:?}", r),
+        }
+    });
+}
@@ -0,0 +1,126 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+
+use futures::stream::TryStreamExt;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::shard::{HashRouter, ShardRouter, ShardedBackend};
+use file_store::testing::data_stream;
+use file_store::*;
+
+#[test]
+fn test_hash_router() {
+    let router = HashRouter;
+    let path = ObjectPath::new("dir/a.txt").unwrap();
+    assert_eq!(router.route(&path, 4), router.route(&path, 4));
+    assert!(router.route(&path, 4) < 4);
+    assert_eq!(router.route(&path, 1), 0);
+}
+
+#[test]
+fn test_sharded() {
+    let temps = vec![tempdir().unwrap(), tempdir().unwrap()];
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let mut shards = Vec::new();
+        for temp in &temps {
+            shards.push(FileBackend::connect(temp.path()).await.unwrap());
+        }
+
+        // Route files by the first letter of their name.
+        let store = ShardedBackend::new(shards).router(|path: &ObjectPath, _: usize| {
+            match path.parts().last() {
+                Some(name) if name.starts_with('a') => 0,
+                _ => 1,
+            }
+        });
+
+        for name in &["dir/a1.txt", "dir/b1.txt", "dir/a2.txt", "b2.txt"] {
+            store
+                .write_file_from_stream(
+                    ObjectPath::new(name).unwrap().into(),
+                    data_stream(&["Data."]),
+                )
+                .await
+                .unwrap();
+        }
+
+        assert!(temps[0].path().join("dir").join("a1.txt").is_file());
+        assert!(temps[1].path().join("dir").join("b1.txt").is_file());
+        assert!(!temps[1].path().join("dir").join("a1.txt").exists());
+
+        let b1 = ObjectPath::new("dir/b1.txt").unwrap();
+        assert_eq!(store.shard_index(&b1), 1);
+        assert_eq!(store.get_object(b1.clone()).await.unwrap().len(), 5);
+
+        // Listings merge every shard and only list directories once.
+        let objects: Vec<Object> = store
+            .list_objects(ObjectPath::empty())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let directories = objects
+            .iter()
+            .filter(|o| o.object_type() == ObjectType::Directory)
+            .count();
+        assert_eq!(directories, 1);
+        let mut files: Vec<String> = objects
+            .iter()
+            .filter(|o| o.object_type() == ObjectType::File)
+            .map(|o| o.path().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            vec!["b2.txt", "dir/a1.txt", "dir/a2.txt", "dir/b1.txt"]
+        );
+
+        let dir = store.get_object(ObjectPath::new("dir").unwrap()).await;
+        assert_eq!(dir.unwrap().object_type(), ObjectType::Directory);
+
+        // Moves between shards copy the data across.
+        store
+            .move_file(b1.clone(), ObjectPath::new("dir/a3.txt").unwrap().into())
+            .await
+            .unwrap();
+        assert!(!temps[1].path().join("dir").join("b1.txt").exists());
+        assert_eq!(
+            fs::read(temps[0].path().join("dir").join("a3.txt")).unwrap(),
+            b"Data."
+        );
+
+        // Deleting a directory deletes it from every shard.
+        store
+            .delete_object(ObjectPath::new("dir").unwrap())
+            .await
+            .unwrap();
+        assert!(!temps[0].path().join("dir").exists());
+        assert!(!temps[1].path().join("dir").exists());
+        assert!(store
+            .delete_object(ObjectPath::new("dir").unwrap())
+            .await
+            .is_err());
+    });
+}
@@ -0,0 +1,139 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "snapshot", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use bytes::Bytes;
+use futures::future::ready;
+use futures::stream::once;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::*;
+
+async fn write(store: &FileStore, path: &str, data: &'static str) {
+    store
+        .write_file_from_stream(path, once(ready(Ok::<_, StorageError>(Bytes::from(data)))))
+        .await
+        .unwrap();
+}
+
+#[test]
+fn test_snapshot() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let source = FileBackend::connect(source_dir.path()).await.unwrap();
+        let target = FileBackend::connect(target_dir.path()).await.unwrap();
+
+        write(&source, "data/a.txt", "Some data.").await;
+        write(&source, "data/sub/b.txt", "Other data.").await;
+        write(&source, "other/c.txt", "Not included.").await;
+
+        let manifest = source.snapshot("data").await.unwrap();
+        assert_eq!(manifest.prefix, "data");
+        assert_eq!(manifest.total_size(), 21);
+        let paths: Vec<&str> = manifest.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.txt", "sub/b.txt"]);
+        assert_eq!(
+            manifest.entries[0].sha256,
+            "a3d119683e015b0cbbcd2c24b2e531698bf552bd184c56a86b8e115871600a8d"
+        );
+
+        // Manifests survive serialization.
+        let json = serde_json::to_string(&manifest).unwrap();
+        let manifest: snapshot::SnapshotManifest = serde_json::from_str(&json).unwrap();
+
+        source.restore(&manifest, &target).await.unwrap();
+        assert_eq!(
+            target.read_to_bytes("data/a.txt").await.unwrap(),
+            "Some data."
+        );
+        assert_eq!(
+            target.read_to_bytes("data/sub/b.txt").await.unwrap(),
+            "Other data."
+        );
+        assert!(target.get_object("other/c.txt").await.is_err());
+        assert_eq!(
+            target.get_object("data/a.txt").await.unwrap().modified(),
+            source.get_object("data/a.txt").await.unwrap().modified()
+        );
+
+        // Changed files are detected.
+        write(&source, "data/a.txt", "New data.").await;
+        match source.restore(&manifest, &target).await {
+            Err(TransferError::SourceError(e)) => {
+                assert_eq!(e.kind(), StorageErrorKind::InvalidData)
+            }
+            _ => panic!("Should have failed to restore a changed file."),
+        }
+    });
+}
+
+#[test]
+fn test_verify() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let source = FileBackend::connect(source_dir.path()).await.unwrap();
+        let target = FileBackend::connect(target_dir.path()).await.unwrap();
+
+        write(&source, "data/a.txt", "Some data.").await;
+        write(&source, "data/b.txt", "Other data.").await;
+        write(&source, "data/sub/c.txt", "More data.").await;
+
+        let manifest = source.snapshot("data").await.unwrap();
+
+        let report = source.verify("data", &manifest).await.unwrap();
+        assert!(report.is_valid());
+        assert_eq!(report.verified, vec!["a.txt", "b.txt", "sub/c.txt"]);
+
+        // An empty store is missing everything.
+        let report = target.verify("data", &manifest).await.unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.missing, vec!["a.txt", "b.txt", "sub/c.txt"]);
+
+        // A copy can be verified at a different prefix.
+        write(&target, "backup/a.txt", "Some data.").await;
+        write(&target, "backup/b.txt", "Other dat!").await;
+        write(&target, "backup/sub/c.txt", "More data!").await;
+        write(&target, "backup/d.txt", "Extra data.").await;
+
+        let report = target.verify("backup", &manifest).await.unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.verified, vec!["a.txt"]);
+        assert!(report.missing.is_empty());
+        assert_eq!(report.unexpected, vec!["d.txt"]);
+
+        assert_eq!(report.corrupted.len(), 2);
+        assert_eq!(report.corrupted[0].path, "b.txt");
+        assert_eq!(report.corrupted[0].expected_size, 11);
+        assert_eq!(report.corrupted[0].actual_size, 10);
+        assert_eq!(report.corrupted[0].actual_sha256, None);
+        assert_eq!(report.corrupted[1].path, "sub/c.txt");
+        assert_eq!(report.corrupted[1].actual_size, 10);
+        assert!(report.corrupted[1].actual_sha256.is_some());
+        assert_ne!(
+            report.corrupted[1].actual_sha256.as_ref(),
+            Some(&report.corrupted[1].expected_sha256)
+        );
+    });
+}
@@ -0,0 +1,289 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "sync", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use filetime::{set_file_mtime, FileTime};
+use futures::stream::TryStreamExt;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::sync::*;
+use file_store::*;
+
+fn write(root: &Path, name: &str, data: &str, modified: SystemTime) {
+    let path = root.join(name);
+    fs::write(&path, data).unwrap();
+    set_file_mtime(&path, FileTime::from_system_time(modified)).unwrap();
+}
+
+#[test]
+fn test_mtimes_match() {
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let options = SyncOptions::new();
+    assert_eq!(options.tolerance(), DEFAULT_MTIME_TOLERANCE);
+
+    assert!(options.mtimes_match(Some(time), Some(time)));
+    assert!(options.mtimes_match(Some(time), Some(time + Duration::from_millis(1999))));
+    assert!(options.mtimes_match(Some(time + Duration::from_secs(2)), Some(time)));
+    assert!(!options.mtimes_match(Some(time), Some(time + Duration::from_secs(3))));
+    assert!(!options.mtimes_match(None, Some(time)));
+    assert!(!options.mtimes_match(None, None));
+
+    let options = options.mtime_tolerance(Duration::from_secs(0));
+    assert!(options.mtimes_match(Some(time), Some(time)));
+    assert!(!options.mtimes_match(Some(time), Some(time + Duration::from_millis(1))));
+}
+
+#[test]
+fn test_needs_update() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let later = time + Duration::from_secs(3600);
+
+    write(source_dir.path(), "same", "Some data.", time);
+    write(
+        target_dir.path(),
+        "same",
+        "Some data.",
+        time + Duration::from_secs(1),
+    );
+    write(source_dir.path(), "skewed", "Some data.", time);
+    write(target_dir.path(), "skewed", "Some data.", later);
+    write(source_dir.path(), "changed", "Some data.", time);
+    write(target_dir.path(), "changed", "Other data", later);
+    write(source_dir.path(), "resized", "Some data.", time);
+    write(target_dir.path(), "resized", "Less data", time);
+
+    runtime.block_on(async move {
+        let source = FileBackend::connect(source_dir.path()).await.unwrap();
+        let target = FileBackend::connect(target_dir.path()).await.unwrap();
+
+        let check = |name: &'static str, options: SyncOptions| {
+            let source = source.clone();
+            let target = target.clone();
+            async move {
+                let source_object = source.get_object(name).await.unwrap();
+                let target_object = target.get_object(name).await.unwrap();
+                needs_update(&source, &source_object, &target, &target_object, &options)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let times = SyncOptions::new();
+        assert!(!check("same", times.clone()).await);
+        assert!(check("skewed", times.clone()).await);
+        assert!(check("changed", times.clone()).await);
+        assert!(check("resized", times.clone()).await);
+
+        let checksums = SyncOptions::new().checksum_fallback(true);
+        assert!(!check("same", checksums.clone()).await);
+        assert!(!check("skewed", checksums.clone()).await);
+        assert!(check("changed", checksums.clone()).await);
+        assert!(check("resized", checksums.clone()).await);
+
+        let strict = SyncOptions::new().mtime_tolerance(Duration::from_secs(0));
+        assert!(check("same", strict).await);
+    });
+}
+
+#[test]
+fn test_sync() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let later = time + Duration::from_secs(3600);
+
+    fs::create_dir_all(source_dir.path().join("src")).unwrap();
+    fs::create_dir_all(target_dir.path().join("dst")).unwrap();
+    write(source_dir.path(), "src/new", "New data.", time);
+    write(source_dir.path(), "src/same", "Some data.", time);
+    write(target_dir.path(), "dst/same", "Some data.", time);
+    write(source_dir.path(), "src/changed", "Some data.", time);
+    write(target_dir.path(), "dst/changed", "Other data", later);
+    write(target_dir.path(), "dst/extra", "Extra data.", time);
+    write(target_dir.path(), "outside", "Not synced.", time);
+
+    let target_root = target_dir.path().to_owned();
+    runtime.block_on(async move {
+        let source = FileBackend::connect(source_dir.path()).await.unwrap();
+        let target = FileBackend::connect(&target_root).await.unwrap();
+
+        let report = sync(&source, "src", &target, "dst", SyncOptions::new())
+            .await
+            .unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.created, vec![ObjectPath::new("dst/new").unwrap()]);
+        assert_eq!(
+            report.updated,
+            vec![ObjectPath::new("dst/changed").unwrap()]
+        );
+        assert!(report.deleted.is_empty());
+        assert_eq!(report.unchanged, 1);
+        assert_eq!(report.bytes, 19);
+
+        let options = SyncOptions::new().delete_extraneous(true);
+        let report = sync(&source, "src", &target, "dst", options.clone())
+            .await
+            .unwrap();
+        assert_eq!(report.deleted, vec![ObjectPath::new("dst/extra").unwrap()]);
+        assert_eq!(report.changes(), 1);
+        assert_eq!(report.unchanged, 3);
+
+        let report = sync(&source, "src", &target, "dst", options).await.unwrap();
+        assert_eq!(report.changes(), 0);
+    });
+
+    let target = target_dir.path();
+    assert_eq!(fs::read(target.join("dst/new")).unwrap(), b"New data.");
+    assert_eq!(fs::read(target.join("dst/changed")).unwrap(), b"Some data.");
+    assert!(!target.join("dst/extra").exists());
+    assert!(target.join("outside").exists());
+}
+
+#[test]
+fn test_plan() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let later = time + Duration::from_secs(3600);
+
+    write(source_dir.path(), "new", "New data.", time);
+    write(source_dir.path(), "same", "Some data.", time);
+    write(target_dir.path(), "same", "Some data.", time);
+    write(source_dir.path(), "changed", "Some data.", time);
+    write(target_dir.path(), "changed", "Other", later);
+    write(target_dir.path(), "extra", "Extra data.", time);
+
+    let target_root = target_dir.path().to_owned();
+    runtime.block_on(async move {
+        let source = FileBackend::connect(source_dir.path()).await.unwrap();
+        let target = FileBackend::connect(&target_root).await.unwrap();
+
+        let options = SyncOptions::new().delete_extraneous(true);
+        let plan = plan(&source, "", &target, "", options).await.unwrap();
+
+        let mut actions: Vec<(String, ObjectPath, u64)> = plan
+            .actions
+            .iter()
+            .map(|a| {
+                let kind = match a {
+                    SyncAction::Create { .. } => "create",
+                    SyncAction::Update { .. } => "update",
+                    SyncAction::Delete { .. } => "delete",
+                };
+                (kind.to_owned(), a.path(), a.size())
+            })
+            .collect();
+        actions.sort();
+        assert_eq!(
+            actions,
+            vec![
+                ("create".to_owned(), ObjectPath::new("new").unwrap(), 9),
+                ("delete".to_owned(), ObjectPath::new("extra").unwrap(), 11),
+                ("update".to_owned(), ObjectPath::new("changed").unwrap(), 10),
+            ]
+        );
+        assert_eq!(plan.unchanged, 1);
+        assert_eq!(plan.bytes(), 19);
+
+        // Nothing changes until the plan is applied.
+        assert!(target.get_object("new").await.is_err());
+        assert!(target.get_object("extra").await.is_ok());
+
+        let report = apply(&source, &target, plan).await.unwrap();
+        assert!(report.is_success());
+        assert_eq!(report.changes(), 3);
+        assert!(target.get_object("new").await.is_ok());
+        assert!(target.get_object("extra").await.is_err());
+    });
+}
+
+#[test]
+fn test_diff() {
+    let source_dir = tempdir().unwrap();
+    let target_dir = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let time = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+    let later = time + Duration::from_secs(3600);
+
+    fs::create_dir_all(source_dir.path().join("a")).unwrap();
+    fs::create_dir_all(target_dir.path().join("b")).unwrap();
+    write(source_dir.path(), "a/new", "New data.", time);
+    write(source_dir.path(), "a/same", "Some data.", time);
+    write(target_dir.path(), "b/same", "Some data.", time);
+    write(source_dir.path(), "a/resized", "Some data.", time);
+    write(target_dir.path(), "b/resized", "Less data", time);
+    write(source_dir.path(), "a/touched", "Some data.", time);
+    write(target_dir.path(), "b/touched", "Some data.", later);
+    write(target_dir.path(), "b/extra", "Extra data.", time);
+
+    runtime.block_on(async move {
+        let source = FileBackend::connect(source_dir.path()).await.unwrap();
+        let target = FileBackend::connect(target_dir.path()).await.unwrap();
+
+        let entries: Vec<DiffEntry> = diff(&source, "a", &target, "b", SyncOptions::new())
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        let found: Vec<String> = entries
+            .iter()
+            .map(|e| match e {
+                DiffEntry::OnlyInSource(o) => format!("source {}", o.path()),
+                DiffEntry::OnlyInTarget(o) => format!("target {}", o.path()),
+                DiffEntry::Different {
+                    source,
+                    target,
+                    size,
+                    mtime,
+                } => format!(
+                    "different {} {} {} {}",
+                    source.path(),
+                    target.path(),
+                    size,
+                    mtime
+                ),
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            vec![
+                "target b/extra",
+                "source a/new",
+                "different a/resized b/resized true false",
+                "different a/touched b/touched false true",
+            ]
+        );
+    });
+}
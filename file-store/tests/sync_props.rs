@@ -0,0 +1,100 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "sync", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use futures::stream::TryStreamExt;
+use proptest::prelude::*;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::sync::*;
+use file_store::testing::strategies::{fixture, mutations};
+use file_store::*;
+
+async fn list_files(store: &FileStore) -> Vec<Object> {
+    let objects: Vec<Object> = store
+        .list_objects(ObjectPath::empty())
+        .await
+        .unwrap()
+        .try_collect()
+        .await
+        .unwrap();
+    objects
+        .into_iter()
+        .filter(|o| o.object_type() == ObjectType::File)
+        .collect()
+}
+
+/// Synchronises the whole of `source` to `target`, returning the number of
+/// changes made.
+async fn sync_all(source: &FileStore, target: &FileStore, options: &SyncOptions) -> usize {
+    let options = options.clone().delete_extraneous(true);
+    let report = sync(source, "", target, "", options).await.unwrap();
+    assert!(report.is_success());
+    report.changes()
+}
+
+async fn assert_converged(source: &FileStore, target: &FileStore, options: &SyncOptions) {
+    let mut source_files: Vec<ObjectPath> =
+        list_files(source).await.iter().map(|o| o.path()).collect();
+    let mut target_files: Vec<ObjectPath> =
+        list_files(target).await.iter().map(|o| o.path()).collect();
+    source_files.sort();
+    target_files.sort();
+    assert_eq!(source_files, target_files);
+
+    for path in source_files {
+        assert_eq!(
+            source.read_to_bytes(path.clone()).await.unwrap(),
+            target.read_to_bytes(path.clone()).await.unwrap(),
+            "{} should match",
+            path
+        );
+    }
+
+    assert_eq!(sync_all(source, target, options).await, 0);
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn test_sync_converges(fixture in fixture(8), mutations in mutations(6)) {
+        let source_dir = tempdir().unwrap();
+        let target_dir = tempdir().unwrap();
+        let runtime = Runtime::new().unwrap();
+
+        let source_root = source_dir.path().to_owned();
+        let target_root = target_dir.path().to_owned();
+        runtime.block_on(async move {
+            let source = FileBackend::connect(&source_root).await.unwrap();
+            let target = FileBackend::connect(&target_root).await.unwrap();
+            let options = SyncOptions::new();
+
+            fixture.build(&source, &ObjectPath::empty()).await.unwrap();
+            sync_all(&source, &target, &options).await;
+            assert_converged(&source, &target, &options).await;
+
+            for mutation in &mutations {
+                mutation.apply(&source, &fixture).await.unwrap();
+            }
+            sync_all(&source, &target, &options).await;
+            assert_converged(&source, &target, &options).await;
+        });
+    }
+}
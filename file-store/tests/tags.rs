@@ -0,0 +1,108 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "tags", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::tags::{SidecarTags, TaggingBackend, Tags};
+use file_store::testing::data_stream;
+use file_store::*;
+
+fn tags() -> Tags {
+    let mut tags = Tags::new();
+    tags.insert("owner".to_owned(), "dave".to_owned());
+    tags.insert("retention".to_owned(), "30d".to_owned());
+    tags
+}
+
+#[test]
+fn test_tags() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        store
+            .write_file_from_stream("dir/file.txt", data_stream(&["Some data."]))
+            .await
+            .unwrap();
+
+        assert!(store.get_tags("dir/file.txt").await.unwrap().is_empty());
+
+        store.set_tags("dir/file.txt", tags()).await.unwrap();
+        assert_eq!(store.get_tags("dir/file.txt").await.unwrap(), tags());
+        assert!(store.get_object(".tags/dir/file.txt.json").await.is_ok());
+
+        // Removing all tags removes the sidecar.
+        store.set_tags("dir/file.txt", Tags::new()).await.unwrap();
+        assert!(store.get_tags("dir/file.txt").await.unwrap().is_empty());
+        assert!(store.get_object(".tags/dir/file.txt.json").await.is_err());
+    });
+}
+
+#[test]
+fn test_tag_missing() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        store
+            .write_file_from_stream("dir/file.txt", data_stream(&["Some data."]))
+            .await
+            .unwrap();
+
+        match store.set_tags("dir/missing.txt", tags()).await {
+            Err(e) => assert_eq!(
+                e.kind(),
+                StorageErrorKind::NotFound(ObjectPath::new("dir/missing.txt").unwrap())
+            ),
+            Ok(()) => panic!("Should have failed to tag a missing file."),
+        }
+        assert!(store.get_tags("dir/missing.txt").await.is_err());
+    });
+}
+
+#[test]
+fn test_sidecar_prefix() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        store
+            .write_file_from_stream("dir/file.txt", data_stream(&["Some data."]))
+            .await
+            .unwrap();
+
+        let sidecar = SidecarTags::new(store.clone()).prefix(ObjectPath::new("meta").unwrap());
+        sidecar
+            .set_tags(ObjectPath::new("dir").unwrap(), tags())
+            .await
+            .unwrap();
+        assert!(store.get_object("meta/dir.json").await.is_ok());
+        assert_eq!(
+            sidecar
+                .get_tags(ObjectPath::new("dir").unwrap())
+                .await
+                .unwrap(),
+            tags()
+        );
+    });
+}
@@ -0,0 +1,40 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+#![cfg(feature = "http")]
+
+extern crate file_store;
+
+use file_store::http_client::TlsSettings;
+
+#[test]
+fn test_pinned_certificates() {
+    let tls = TlsSettings::new();
+    assert!(tls.pinned_certificates().is_empty());
+
+    let tls = tls.pin_certificate([1; 32]).pin_certificate([2; 32]);
+    assert_eq!(tls.pinned_certificates(), &[[1; 32], [2; 32]]);
+}
+
+#[cfg(all(feature = "tls-native", not(feature = "tls-rustls")))]
+#[test]
+fn test_native_pinning_unsupported() {
+    use file_store::http_client::HyperClient;
+    use file_store::StorageErrorKind;
+
+    let tls = TlsSettings::new().pin_certificate([0; 32]);
+    match HyperClient::with_tls(&tls) {
+        Ok(_) => panic!("Pinning should not be supported."),
+        Err(e) => assert_eq!(e.kind(), StorageErrorKind::InvalidSettings),
+    }
+}
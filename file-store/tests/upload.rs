@@ -0,0 +1,168 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "upload", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::convert::Infallible;
+
+use bytes::Bytes;
+use futures::stream::iter;
+use http::header::*;
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::upload::*;
+use file_store::*;
+
+/// Splits a body into small chunks to exercise boundaries that span chunks.
+fn chunked(body: &'static str) -> impl futures::Stream<Item = Result<Bytes, Infallible>> {
+    let chunks: Vec<Result<Bytes, Infallible>> = body
+        .as_bytes()
+        .chunks(5)
+        .map(|c| Ok(Bytes::from(c)))
+        .collect();
+    iter(chunks)
+}
+
+#[test]
+fn test_upload_body() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let path = ObjectPath::new("upload.txt").unwrap();
+
+        let headers = HeaderMap::new();
+        upload_body(
+            &store,
+            path.clone().into(),
+            &headers,
+            chunked("Some uploaded data."),
+            Some(100),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            store.read_to_bytes(path.clone()).await.unwrap(),
+            "Some uploaded data."
+        );
+
+        // Rejected before reading the body.
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_LENGTH, HeaderValue::from_static("1000"));
+        let error: StorageError = upload_body(
+            &store,
+            ObjectPath::new("large.txt").unwrap().into(),
+            &headers,
+            chunked("Small."),
+            Some(100),
+        )
+        .await
+        .unwrap_err()
+        .into();
+        assert_eq!(error.kind(), StorageErrorKind::OverQuota);
+        assert!(store
+            .get_object(ObjectPath::new("large.txt").unwrap())
+            .await
+            .is_err());
+
+        // Rejected while reading the body.
+        let headers = HeaderMap::new();
+        let error: StorageError = upload_body(
+            &store,
+            ObjectPath::new("large.txt").unwrap().into(),
+            &headers,
+            chunked("This body is longer than the limit."),
+            Some(10),
+        )
+        .await
+        .unwrap_err()
+        .into();
+        assert_eq!(error.kind(), StorageErrorKind::OverQuota);
+    });
+}
+
+#[test]
+fn test_upload_multipart() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    runtime.block_on(async move {
+        let store = FileBackend::connect(temp.path()).await.unwrap();
+        let path = ObjectPath::new("upload.txt").unwrap();
+
+        let body = "--XyZzY\r\n\
+                    Content-Disposition: form-data; name=\"title\"\r\n\
+                    \r\n\
+                    A title\r\n\
+                    --XyZzY\r\n\
+                    Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+                    Content-Type: text/plain\r\n\
+                    \r\n\
+                    The file's\r\ncontents.\r\n\
+                    --XyZzY--\r\n";
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("multipart/form-data; boundary=XyZzY"),
+        );
+
+        upload_multipart(
+            &store,
+            path.clone().into(),
+            "file",
+            &headers,
+            chunked(body),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            store.read_to_bytes(path.clone()).await.unwrap(),
+            "The file's\r\ncontents."
+        );
+
+        let error: StorageError = upload_multipart(
+            &store,
+            ObjectPath::new("missing.txt").unwrap().into(),
+            "missing",
+            &headers,
+            chunked(body),
+            None,
+        )
+        .await
+        .unwrap_err()
+        .into();
+        assert_eq!(error.kind(), StorageErrorKind::InvalidData);
+
+        let headers = HeaderMap::new();
+        let error: StorageError = upload_multipart(
+            &store,
+            ObjectPath::new("missing.txt").unwrap().into(),
+            "file",
+            &headers,
+            chunked(body),
+            None,
+        )
+        .await
+        .unwrap_err()
+        .into();
+        assert_eq!(error.kind(), StorageErrorKind::InvalidData);
+    });
+}
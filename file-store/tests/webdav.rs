@@ -0,0 +1,133 @@
+// Copyright 2019 Dave Townsend
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(all(feature = "webdav", feature = "file", not(feature = "wasm")))]
+
+extern crate file_store;
+
+use std::convert::Infallible;
+use std::path::Path;
+
+use bytes::Bytes;
+use futures::stream::{iter, TryStreamExt};
+use http::{Request, Response, StatusCode};
+use tempfile::tempdir;
+use tokio::runtime::Runtime;
+
+use file_store::backends::file::FileBackend;
+use file_store::serve::webdav::*;
+use file_store::*;
+
+fn request(
+    method: &str,
+    uri: &str,
+    body: &'static str,
+) -> Request<impl futures::Stream<Item = Result<Bytes, Infallible>>> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Depth", "1")
+        .body(iter(vec![Ok(Bytes::from(body))]))
+        .unwrap()
+}
+
+async fn body(response: Response<DataStream>) -> String {
+    let chunks: Vec<Data> = response.into_body().try_collect().await.unwrap();
+    String::from_utf8(chunks.concat()).unwrap()
+}
+
+async fn handler(root: &Path) -> WebDavHandler {
+    let store = FileBackend::connect(root).await.unwrap();
+    let handler = WebDavHandler::new(store).base_path("/dav/");
+
+    let response = handler
+        .handle(request("PUT", "/dav/dir/my%20file.txt", "Some data."))
+        .await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    handler
+}
+
+#[test]
+fn test_files() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let handler = handler(&root).await;
+
+        let response = handler.handle(request("OPTIONS", "/dav/", "")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("DAV").unwrap(), "1");
+
+        let response = handler
+            .handle(request("GET", "/dav/dir/my%20file.txt", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, "Some data.");
+
+        let response = handler
+            .handle(request("DELETE", "/dav/dir/my%20file.txt", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = handler
+            .handle(request("GET", "/dav/dir/my%20file.txt", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = handler.handle(request("GET", "/dav/../escape", "")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    });
+}
+
+#[test]
+fn test_propfind() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let handler = handler(&root).await;
+
+        let response = handler.handle(request("PROPFIND", "/dav/dir", "")).await;
+        assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+        let xml = body(response).await;
+        assert!(xml.contains("<D:href>/dav/dir/</D:href>"));
+        assert!(xml.contains("<D:href>/dav/dir/my%20file.txt</D:href>"));
+        assert!(xml.contains("<D:getcontentlength>10</D:getcontentlength>"));
+        assert!(xml.contains("<D:displayname>my file.txt</D:displayname>"));
+    });
+}
+
+#[test]
+fn test_mkcol() {
+    let temp = tempdir().unwrap();
+    let runtime = Runtime::new().unwrap();
+
+    let root = temp.path().to_owned();
+    runtime.block_on(async move {
+        let handler = handler(&root).await;
+
+        let response = handler.handle(request("MKCOL", "/dav/dir", "")).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        let response = handler.handle(request("MKCOL", "/dav/other", "")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = handler
+            .handle(request("MKCOL", "/dav/missing/other", ""))
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    });
+}
@@ -62,6 +62,21 @@ pub struct DeleteFileVersionRequest {
     pub file_id: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetDownloadAuthorizationRequest {
+    pub bucket_id: String,
+    pub file_name_prefix: String,
+    pub valid_duration_in_seconds: Int,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HideFileRequest {
+    pub bucket_id: String,
+    pub file_name: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetUploadUrlRequest {
@@ -77,6 +92,27 @@ pub struct StartLargeFileRequest {
     pub file_info: Option<UserFileInfo>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CopyFileRequest {
+    pub source_file_id: String,
+    pub destination_bucket_id: Option<String>,
+    pub file_name: String,
+    pub range: Option<String>,
+    pub metadata_directive: Option<String>,
+    pub content_type: Option<String>,
+    pub file_info: Option<UserFileInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CopyPartRequest {
+    pub source_file_id: String,
+    pub large_file_id: String,
+    pub part_number: usize,
+    pub range: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetUploadPartUrlRequest {
@@ -89,3 +125,26 @@ pub struct FinishLargeFileRequest {
     pub file_id: String,
     pub part_sha1_array: Vec<String>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListPartsRequest {
+    pub file_id: String,
+    pub start_part_number: Option<usize>,
+    pub max_part_count: Option<Int>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListUnfinishedLargeFilesRequest {
+    pub bucket_id: String,
+    pub name_prefix: Option<String>,
+    pub start_file_id: Option<String>,
+    pub max_file_count: Option<Int>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelLargeFileRequest {
+    pub file_id: String,
+}
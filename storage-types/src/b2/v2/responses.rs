@@ -133,6 +133,16 @@ pub struct DeleteFileVersionResponse {
     pub file_id: String,
 }
 
+pub type HideFileResponse = FileInfo;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetDownloadAuthorizationResponse {
+    pub bucket_id: String,
+    pub file_name_prefix: String,
+    pub authorization_token: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct GetUploadUrlResponse {
@@ -163,4 +173,33 @@ pub struct UploadPartResponse {
     pub upload_timestamp: Int,
 }
 
+pub type PartInfo = UploadPartResponse;
+
+pub type CopyFileResponse = FileInfo;
+
+pub type CopyPartResponse = UploadPartResponse;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListPartsResponse {
+    pub parts: Vec<PartInfo>,
+    pub next_part_number: Option<usize>,
+}
+
 pub type FinishLargeFileResponse = FileInfo;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListUnfinishedLargeFilesResponse {
+    pub files: Vec<FileInfo>,
+    pub next_file_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelLargeFileResponse {
+    pub file_id: String,
+    pub account_id: String,
+    pub bucket_id: String,
+    pub file_name: String,
+}